@@ -10,7 +10,7 @@ fn start_server(port: u16) -> tokio::task::JoinHandle<()> {
     };
     let num_dbs = config.databases;
     let config = Arc::new(RwLock::new(config));
-    let store = Arc::new(RwLock::new(cedis::store::DataStore::new(num_dbs)));
+    let store = Arc::new(cedis::store::DataStore::new(num_dbs));
     let pubsub = Arc::new(RwLock::new(cedis::pubsub::PubSubRegistry::new()));
     let aof = Arc::new(Mutex::new(cedis::persistence::aof::AofWriter::new()));
     let repl_state = Arc::new(RwLock::new(cedis::replication::ReplicationState::new()));
@@ -56,6 +56,29 @@ fn bench_set_get(conn: &mut redis::Connection, iterations: usize) -> (f64, f64)
     (set_ops, get_ops)
 }
 
+/// SET and GET throughput in MB/s for values of `size` bytes. Values this
+/// large travel from the request buffer to the keyspace and back out to the
+/// reply as the same shared bytes, so this mostly measures the socket.
+fn bench_large_set_get(conn: &mut redis::Connection, size: usize, iterations: usize) -> (f64, f64) {
+    let value = vec![b'v'; size];
+    let megabytes = (size * iterations) as f64 / (1024.0 * 1024.0);
+
+    let start = Instant::now();
+    for i in 0..iterations {
+        let _: () = conn.set(format!("large_key_{}", i % 16), &value).unwrap();
+    }
+    let set_mbps = megabytes / start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    for i in 0..iterations {
+        let got: Vec<u8> = conn.get(format!("large_key_{}", i % 16)).unwrap();
+        assert_eq!(got.len(), size);
+    }
+    let get_mbps = megabytes / start.elapsed().as_secs_f64();
+
+    (set_mbps, get_mbps)
+}
+
 fn bench_incr(conn: &mut redis::Connection, iterations: usize) -> f64 {
     let _: () = conn.set("bench_counter", "0").unwrap();
     let start = Instant::now();
@@ -128,6 +151,35 @@ fn bench_pipeline(conn: &mut redis::Connection, iterations: usize) -> f64 {
     iterations as f64 / elapsed.as_secs_f64()
 }
 
+/// Aggregate SET+GET throughput of `clients` connections running at once,
+/// each on its own keys. With the keyspace sharded, this should grow with
+/// the client count instead of flattening out on a single lock.
+fn bench_concurrent(port: u16, clients: usize, iterations: usize) -> f64 {
+    let mut conns: Vec<_> = (0..clients).map(|_| get_client(port)).collect();
+    let barrier = Arc::new(std::sync::Barrier::new(clients + 1));
+    let handles: Vec<_> = conns
+        .drain(..)
+        .enumerate()
+        .map(|(c, mut conn)| {
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                for i in 0..iterations {
+                    let key = format!("conc_{c}_{i}");
+                    let _: () = conn.set(&key, "value").unwrap();
+                    let _: String = conn.get(&key).unwrap();
+                }
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    (clients * iterations * 2) as f64 / start.elapsed().as_secs_f64()
+}
+
 #[tokio::main]
 async fn main() {
     let port = 17000;
@@ -161,6 +213,23 @@ async fn main() {
         let pipe_ops = bench_pipeline(&mut conn, iterations);
         println!("PIPE:   {pipe_ops:>10.0} ops/sec (100-cmd pipeline batches)");
 
+        println!("\n=== Large values ===\n");
+        for (size, label) in [(64 << 10, "64KB"), (1 << 20, "1MB"), (8 << 20, "8MB")] {
+            let runs = (iterations * 1024 / size).clamp(20, 1000);
+            let (set_mbps, get_mbps) = bench_large_set_get(&mut conn, size, runs);
+            println!("SET {label:>4}: {set_mbps:>8.0} MB/sec");
+            println!("GET {label:>4}: {get_mbps:>8.0} MB/sec");
+        }
+
+        println!(
+            "\n=== Concurrent SET+GET ({} operations per client) ===\n",
+            iterations / 5
+        );
+        for clients in [1, 2, 4, 8, 16] {
+            let ops = bench_concurrent(port, clients, iterations / 5);
+            println!("{clients:>2} clients: {ops:>10.0} ops/sec");
+        }
+
         println!("\n=== Done ===");
     })
     .await
@@ -0,0 +1,102 @@
+//! Heap usage tracking.
+//!
+//! The `cedis` binary installs [`TrackingAllocator`] as its global allocator,
+//! so `used_memory` and eviction see every live heap byte rather than an
+//! estimate. Embedders that keep their own allocator (the integration tests
+//! run servers in-process) get `None` from [`allocated`] and fall back to
+//! walking the dataset.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static STARTUP: AtomicUsize = AtomicUsize::new(0);
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The system allocator, counting the bytes it hands out.
+pub struct TrackingAllocator;
+
+impl TrackingAllocator {
+    #[inline]
+    fn added(size: usize) {
+        let now = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(now, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn removed(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+// SAFETY: every call is forwarded unchanged to `System`; we only count sizes.
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            Self::added(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            Self::added(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        Self::removed(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            Self::removed(layout.size());
+            Self::added(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Mark the tracking allocator as installed. Called once by the binary that
+/// registers it, before the server starts.
+pub fn enable() {
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Live heap bytes, or `None` when the tracking allocator is not installed.
+pub fn allocated() -> Option<usize> {
+    ACTIVE
+        .load(Ordering::Relaxed)
+        .then(|| ALLOCATED.load(Ordering::Relaxed))
+}
+
+/// Highest number of live heap bytes seen so far.
+pub fn peak() -> Option<usize> {
+    ACTIVE
+        .load(Ordering::Relaxed)
+        .then(|| PEAK.load(Ordering::Relaxed))
+}
+
+/// Remember the current heap usage as the baseline of an empty server.
+pub fn mark_startup() {
+    STARTUP.store(ALLOCATED.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+/// Heap bytes in use once the server had started, before any data arrived.
+pub fn startup() -> usize {
+    STARTUP.load(Ordering::Relaxed)
+}
+
+/// Resident set size of the process, where the platform reports it.
+pub fn rss() -> Option<usize> {
+    // statm: size resident shared text lib data dt, in 4 KiB pages
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4096)
+}
@@ -0,0 +1,207 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use cedis::persistence::aof::{self, AofError, AofReader};
+use cedis::persistence::rdb;
+
+/// Kind of file listed in a multi-part AOF manifest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileKind {
+    Base,
+    Incr,
+}
+
+/// Outcome of checking a single AOF file.
+enum CheckResult {
+    /// `items` counts commands for AOF files and keys for RDB base files.
+    Valid {
+        items: usize,
+        unit: &'static str,
+    },
+    Truncated {
+        offset: u64,
+        valid_len: u64,
+    },
+    Corrupted,
+}
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: cedis-check-aof [--fix] <file.aof|appendonlydir>");
+    ExitCode::from(2)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut fix = false;
+    let mut path: Option<String> = None;
+
+    for arg in &args {
+        match arg.as_str() {
+            "--fix" => fix = true,
+            "-h" | "--help" => return usage(),
+            other if path.is_none() && !other.starts_with("--") => path = Some(other.to_string()),
+            _ => return usage(),
+        }
+    }
+
+    let Some(path) = path else {
+        return usage();
+    };
+
+    let path = Path::new(&path);
+    let files = if path.is_dir() {
+        match read_manifest(path) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("Cannot read AOF manifest in {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        vec![(path.to_path_buf(), FileKind::Incr)]
+    };
+
+    let last = files.len().saturating_sub(1);
+    for (index, (file, kind)) in files.iter().enumerate() {
+        let result = match kind {
+            FileKind::Base if file.extension().is_some_and(|e| e == "rdb") => check_rdb(file),
+            _ => check_aof(file),
+        };
+        match result {
+            Ok(CheckResult::Valid { items, unit }) => {
+                println!("AOF {} is valid ({items} {unit})", file.display());
+            }
+            Ok(CheckResult::Truncated { offset, valid_len }) => {
+                let size = std::fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+                println!(
+                    "AOF analyzed: filename={}, size={size}, ok_up_to={valid_len}, diff={}",
+                    file.display(),
+                    size - valid_len
+                );
+                println!("Unexpected end of file at offset {offset}");
+                // Only the tail of the most recent file can be a half-written command
+                if index != last {
+                    println!(
+                        "AOF {} is not the last file and cannot be fixed",
+                        file.display()
+                    );
+                    return ExitCode::FAILURE;
+                }
+                if !fix {
+                    println!(
+                        "AOF {} is not valid. Use the --fix option to try fixing it.",
+                        file.display()
+                    );
+                    return ExitCode::FAILURE;
+                }
+                let file_str = file.to_string_lossy();
+                if let Err(e) = aof::truncate(&file_str, valid_len) {
+                    eprintln!("Failed to truncate AOF: {e}");
+                    return ExitCode::FAILURE;
+                }
+                println!("Successfully truncated AOF {}", file.display());
+            }
+            Ok(CheckResult::Corrupted) => {
+                println!(
+                    "AOF {} format error: corruption is not at the tail and cannot be fixed automatically",
+                    file.display()
+                );
+                return ExitCode::FAILURE;
+            }
+            Err(e) => {
+                eprintln!("Cannot check {}: {e}", file.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Scan an AOF file command by command.
+fn check_aof(path: &Path) -> io::Result<CheckResult> {
+    let file = std::fs::File::open(path)?;
+    let mut reader = AofReader::new(io::BufReader::new(file));
+    let mut commands = 0usize;
+
+    loop {
+        match reader.next_command() {
+            Ok(Some(_)) => commands += 1,
+            Ok(None) => {
+                return Ok(CheckResult::Valid {
+                    items: commands,
+                    unit: "commands",
+                });
+            }
+            Err(AofError::Truncated { offset, valid_len }) => {
+                return Ok(CheckResult::Truncated { offset, valid_len });
+            }
+            Err(e @ AofError::Corrupted { .. }) => {
+                println!("{e}");
+                return Ok(CheckResult::Corrupted);
+            }
+            Err(AofError::Io(e)) => return Err(e),
+        }
+    }
+}
+
+/// Validate an RDB base file referenced from a manifest.
+fn check_rdb(path: &Path) -> io::Result<CheckResult> {
+    let path_str = path.to_string_lossy();
+    match rdb::load(&path_str, 16) {
+        Ok(store) => Ok(CheckResult::Valid {
+            items: store
+                .try_lock_all()
+                .expect("a loaded store is not shared")
+                .databases()
+                .map(|db| db.dbsize())
+                .sum(),
+            unit: "keys",
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(e),
+        Err(e) => {
+            println!("RDB base {} is not valid: {e}", path.display());
+            Ok(CheckResult::Corrupted)
+        }
+    }
+}
+
+/// Read the manifest of a multi-part AOF directory.
+/// Lines look like `file appendonly.aof.1.base.rdb seq 1 type b`.
+/// Returns the base file followed by the incremental files in sequence order.
+fn read_manifest(dir: &Path) -> io::Result<Vec<(PathBuf, FileKind)>> {
+    let manifest = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .find(|p| p.extension().is_some_and(|e| e == "manifest"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no .manifest file"))?;
+
+    let contents = std::fs::read_to_string(&manifest)?;
+    let mut base = None;
+    let mut incrs = Vec::new();
+    for line in contents.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let field = |name: &str| {
+            tokens
+                .chunks(2)
+                .find(|pair| pair[0] == name)
+                .and_then(|pair| pair.get(1).copied())
+        };
+        let (Some(name), Some(kind)) = (field("file"), field("type")) else {
+            continue;
+        };
+        let seq: u64 = field("seq").and_then(|s| s.parse().ok()).unwrap_or(0);
+        match kind {
+            "b" => base = Some(dir.join(name)),
+            "i" => incrs.push((seq, dir.join(name))),
+            _ => {} // History files are pending deletion and not loaded
+        }
+    }
+    incrs.sort_by_key(|(seq, _)| *seq);
+
+    let mut files: Vec<(PathBuf, FileKind)> =
+        base.into_iter().map(|p| (p, FileKind::Base)).collect();
+    files.extend(incrs.into_iter().map(|(_, p)| (p, FileKind::Incr)));
+    Ok(files)
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::process::ExitCode;
+
+use cedis::persistence::rdb::{self, RdbChecksum};
+
+/// Reader wrapper that counts consumed bytes, used to locate errors.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: cedis-check-rdb <file.rdb> [--databases <n>]");
+    ExitCode::from(2)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut path: Option<String> = None;
+    let mut databases = 16usize;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--databases" if i + 1 < args.len() => {
+                match args[i + 1].parse() {
+                    Ok(n) => databases = n,
+                    Err(_) => return usage(),
+                }
+                i += 1;
+            }
+            "-h" | "--help" => return usage(),
+            arg if path.is_none() && !arg.starts_with("--") => path = Some(arg.to_string()),
+            _ => return usage(),
+        }
+        i += 1;
+    }
+
+    let Some(path) = path else {
+        return usage();
+    };
+
+    println!("[offset 0] Checking RDB file {path}");
+
+    let file = match std::fs::File::open(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("Cannot open {path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut reader = CountingReader {
+        inner: io::BufReader::new(file),
+        count: 0,
+    };
+
+    let (store, checksum) = match rdb::load_from_reader_checked(&mut reader, databases) {
+        Ok(result) => result,
+        Err(e) => {
+            println!("--- RDB ERROR DETECTED ---");
+            println!("[offset {}] {e}", reader.count);
+            println!("[additional info] While doing: read-object-value");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut keyspace = store.try_lock_all().expect("a loaded store is not shared");
+    let mut total_keys = 0usize;
+    for db in keyspace.databases() {
+        let db_index = db.index();
+        if db.dbsize() == 0 {
+            continue;
+        }
+        let mut histogram: BTreeMap<&'static str, usize> = BTreeMap::new();
+        for (_, entry) in db.iter() {
+            *histogram.entry(entry.value.type_name()).or_default() += 1;
+        }
+        let types: Vec<String> = histogram
+            .iter()
+            .map(|(name, count)| format!("{name}={count}"))
+            .collect();
+        println!(
+            "[info] db{db_index}: keys={},expires={} types: {}",
+            db.dbsize(),
+            db.expires_count(),
+            types.join(",")
+        );
+        total_keys += db.dbsize();
+    }
+
+    println!("[info] {total_keys} keys read");
+    match checksum {
+        RdbChecksum::Verified(crc) => {
+            println!("[offset {}] Checksum OK ({crc:016x})", reader.count)
+        }
+        RdbChecksum::Disabled => println!(
+            "[offset {}] RDB file was saved with checksum disabled: no check performed.",
+            reader.count
+        ),
+    }
+    println!("\\o/ RDB looks OK! \\o/");
+    ExitCode::SUCCESS
+}
@@ -14,19 +14,15 @@ async fn main() -> io::Result<()> {
     let mut i = 0;
     while i < args.len() {
         match args[i].as_str() {
-            "--host" | "-h" => {
-                if i + 1 < args.len() {
-                    host = args[i + 1].clone();
-                    i += 1;
-                }
+            "--host" | "-h" if i + 1 < args.len() => {
+                host = args[i + 1].clone();
+                i += 1;
             }
-            "--port" | "-p" => {
-                if i + 1 < args.len() {
-                    if let Ok(p) = args[i + 1].parse() {
-                        port = p;
-                    }
-                    i += 1;
+            "--port" | "-p" if i + 1 < args.len() => {
+                if let Ok(p) = args[i + 1].parse() {
+                    port = p;
                 }
+                i += 1;
             }
             _ => {}
         }
@@ -0,0 +1,223 @@
+use std::io::{self, BufRead, BufWriter, Write};
+use std::process::ExitCode;
+
+use cedis::persistence::aof;
+use cedis::persistence::export::{self, RecordFilter};
+use cedis::persistence::rdb::{self, RdbReader};
+use cedis::store::DataStore;
+use cedis::store::entry::Entry;
+
+const USAGE: &str = "\
+Usage:
+  cedis-rdb export [--format json|resp] [filters] [-o <out>] <dump.rdb>
+  cedis-rdb import [--format rdb|resp] [filters] [-o <out>] <records.jsonl|->
+
+Filters:
+  --db <n>          Only keys in database n
+  --key <pattern>   Only keys matching a glob pattern
+  --type <type>     Only keys of a type (string, list, set, zset, hash)
+
+Options:
+  --databases <n>   Number of databases (default 16)
+  -o <out>          Write to a file instead of stdout
+
+`export --format resp` emits commands suitable for `redis-cli --pipe`.";
+
+struct Options {
+    command: String,
+    format: Option<String>,
+    filter: RecordFilter,
+    databases: usize,
+    output: Option<String>,
+    input: String,
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let command = args.first()?.clone();
+    let mut format = None;
+    let mut filter = RecordFilter::default();
+    let mut databases = 16usize;
+    let mut output = None;
+    let mut input = None;
+
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1);
+        match args[i].as_str() {
+            "--format" => format = Some(value?.to_lowercase()),
+            "--db" => filter.db = Some(value?.parse().ok()?),
+            "--key" => filter.key_pattern = Some(value?.clone()),
+            "--type" => filter.type_name = Some(value?.to_lowercase()),
+            "--databases" => databases = value?.parse().ok()?,
+            "-o" | "--output" => output = Some(value?.clone()),
+            arg if input.is_none() && (arg == "-" || !arg.starts_with('-')) => {
+                input = Some(arg.to_string());
+                i += 1;
+                continue;
+            }
+            _ => return None,
+        }
+        i += 2;
+    }
+
+    Some(Options {
+        command,
+        format,
+        filter,
+        databases,
+        output,
+        input: input?,
+    })
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(opts) = parse_args(&args) else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let out: Box<dyn Write> = match &opts.output {
+        Some(path) => match std::fs::File::create(path) {
+            Ok(f) => Box::new(f),
+            Err(e) => {
+                eprintln!("Cannot create {path}: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut out = BufWriter::new(out);
+
+    let result = match (opts.command.as_str(), opts.format.as_deref()) {
+        ("export", None | Some("json")) => export(&opts, &mut out, Format::Json),
+        ("export", Some("resp")) => export(&opts, &mut out, Format::Resp),
+        ("import", None | Some("rdb")) => import(&opts, &mut out, Format::Rdb),
+        ("import", Some("resp")) => import(&opts, &mut out, Format::Resp),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match result.and_then(|n| out.flush().map(|_| n)) {
+        Ok(n) => {
+            eprintln!("{n} keys written");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    Resp,
+    Rdb,
+}
+
+/// Tracks the selected database so RESP output only emits SELECT on changes.
+struct RespEmitter {
+    current_db: Option<usize>,
+}
+
+impl RespEmitter {
+    fn emit(
+        &mut self,
+        out: &mut impl Write,
+        db: usize,
+        key: &str,
+        entry: &Entry,
+    ) -> io::Result<()> {
+        let commands = aof::entry_commands(key, entry);
+        if commands.is_empty() {
+            return Ok(());
+        }
+        if self.current_db != Some(db) {
+            out.write_all(&aof::select_command(db).serialize())?;
+            self.current_db = Some(db);
+        }
+        for cmd in commands {
+            out.write_all(&cmd.serialize())?;
+        }
+        Ok(())
+    }
+}
+
+/// Stream an RDB file key by key into JSON lines or RESP commands.
+fn export(opts: &Options, out: &mut impl Write, format: Format) -> io::Result<usize> {
+    let file = std::fs::File::open(&opts.input)?;
+    let mut reader = RdbReader::new(io::BufReader::new(file), opts.databases)?;
+    let mut resp = RespEmitter { current_db: None };
+    let mut written = 0usize;
+
+    while let Some(record) = reader.next_record()? {
+        if record.entry.is_expired() || !opts.filter.matches(record.db, &record.key, &record.entry)
+        {
+            continue;
+        }
+        match format {
+            Format::Json => {
+                if let Some(line) = export::record_to_json(record.db, &record.key, &record.entry) {
+                    writeln!(out, "{line}")?;
+                    written += 1;
+                }
+            }
+            _ => {
+                resp.emit(out, record.db, &record.key, &record.entry)?;
+                written += 1;
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// Read JSON lines and write them out as an RDB file or RESP commands.
+fn import(opts: &Options, out: &mut impl Write, format: Format) -> io::Result<usize> {
+    let input: Box<dyn BufRead> = if opts.input == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(io::BufReader::new(std::fs::File::open(&opts.input)?))
+    };
+
+    let store = DataStore::new(opts.databases);
+    let mut keyspace = store.try_lock_all().expect("a new store is not shared");
+    let mut resp = RespEmitter { current_db: None };
+    let mut written = 0usize;
+
+    for (line_no, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (db, key, entry) = export::record_from_json(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {e}", line_no + 1),
+            )
+        })?;
+        if db >= opts.databases {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: DB index {db} out of range", line_no + 1),
+            ));
+        }
+        if !opts.filter.matches(db, &key, &entry) {
+            continue;
+        }
+        if format == Format::Resp {
+            resp.emit(out, db, &key, &entry)?;
+        } else {
+            keyspace.db(db).set(key, entry);
+        }
+        written += 1;
+    }
+
+    if format == Format::Rdb {
+        rdb::save_to_writer(out, &mut keyspace)?;
+    }
+    Ok(written)
+}
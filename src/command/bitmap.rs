@@ -6,6 +6,7 @@ use crate::store::entry::Entry;
 use crate::types::RedisValue;
 use crate::types::bitmap::Bitmap;
 use crate::types::rstring::RedisString;
+use std::sync::atomic::Ordering;
 
 /// Helper: read bytes from a key that is either a String or nonexistent.
 /// Returns Ok(Some(bytes)) if it exists and is a string, Ok(None) if nonexistent,
@@ -48,11 +49,12 @@ pub async fn cmd_setbit(
         _ => return RespValue::error("ERR bit is not an integer or out of range"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let stats = &store.stats;
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     // Read existing bytes or create empty
-    let existing = match read_string_bytes(db, &key) {
+    let existing = match read_string_bytes(&mut db, &key) {
         Ok(bytes) => bytes,
         Err(e) => return e,
     };
@@ -77,7 +79,7 @@ pub async fn cmd_setbit(
 
     // Track dirty: increment when value changed, length changed, or key is new
     if is_dirty {
-        store.dirty += 1;
+        stats.dirty.fetch_add(1, Ordering::Relaxed);
     }
 
     RespValue::integer(if old { 1 } else { 0 })
@@ -101,10 +103,10 @@ pub async fn cmd_getbit(
         _ => return RespValue::error("ERR bit offset is not an integer or out of range"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let bytes = match read_string_bytes(db, &key) {
+    let bytes = match read_string_bytes(&mut db, &key) {
         Ok(Some(b)) => b,
         Ok(None) => return RespValue::integer(0),
         Err(e) => return e,
@@ -156,10 +158,10 @@ pub async fn cmd_bitcount(
         None
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let bytes = match read_string_bytes(db, &key) {
+    let bytes = match read_string_bytes(&mut db, &key) {
         Ok(Some(b)) => b,
         Ok(None) => return RespValue::integer(0),
         Err(e) => return e,
@@ -210,8 +212,10 @@ pub async fn cmd_bitop(args: &[RespValue], store: &SharedStore, client: &ClientS
         return RespValue::error("ERR syntax error");
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(client.db_index, args[1..].iter().filter_map(arg_to_string))
+        .await;
+    let mut db = store.db(client.db_index);
 
     // Read all source keys
     let mut bitmaps: Vec<Bitmap> = Vec::new();
@@ -220,7 +224,7 @@ pub async fn cmd_bitop(args: &[RespValue], store: &SharedStore, client: &ClientS
             Some(k) => k,
             None => return RespValue::error("ERR invalid key"),
         };
-        let bytes = match read_string_bytes(db, &key) {
+        let bytes = match read_string_bytes(&mut db, &key) {
             Ok(Some(b)) => b,
             Ok(None) => Vec::new(),
             Err(e) => return e,
@@ -320,10 +324,10 @@ pub async fn cmd_bitpos(
         None
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let bytes = match read_string_bytes(db, &key) {
+    let bytes = match read_string_bytes(&mut db, &key) {
         Ok(Some(b)) => b,
         Ok(None) => {
             // Empty key: looking for 1 returns -1, looking for 0 returns 0
@@ -693,11 +697,12 @@ pub async fn cmd_bitfield(
         }
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let stats = &store.stats;
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     // Read existing bytes
-    let mut data = match read_string_bytes(db, &key) {
+    let mut data = match read_string_bytes(&mut db, &key) {
         Ok(Some(b)) => b,
         Ok(None) => Vec::new(),
         Err(e) => return e,
@@ -841,7 +846,7 @@ pub async fn cmd_bitfield(
     }
 
     // Track dirty count
-    store.dirty += dirty;
+    stats.dirty.fetch_add(dirty, Ordering::Relaxed);
 
     RespValue::Array(Some(results))
 }
@@ -916,10 +921,10 @@ pub async fn cmd_bitfield_ro(
         }
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let data = match read_string_bytes(db, &key) {
+    let data = match read_string_bytes(&mut db, &key) {
         Ok(Some(b)) => b,
         Ok(None) => Vec::new(),
         Err(e) => return e,
@@ -2,33 +2,70 @@ use crate::command::{
     arg_to_bytes, arg_to_f64, arg_to_i64, arg_to_string, wrong_arg_count, wrong_type_error,
 };
 use crate::connection::ClientState;
+use crate::keywatcher::SharedKeyWatcher;
 use crate::resp::RespValue;
 use crate::store::SharedStore;
 use crate::store::entry::Entry;
 use crate::types::RedisValue;
-use crate::types::geo::{GeoSet, unit_to_meters};
+use crate::types::geo::{self, GeoResult, Shape, unit_to_meters};
+use crate::types::sorted_set::RedisSortedSet;
 
-fn get_or_create_geo<'a>(
+fn get_or_create_zset<'a>(
     db: &'a mut crate::store::Database,
     key: &str,
-) -> Result<&'a mut GeoSet, RespValue> {
+) -> Result<&'a mut RedisSortedSet, RespValue> {
     if !db.exists(key) {
-        db.set(key.to_string(), Entry::new(RedisValue::Geo(GeoSet::new())));
+        db.set(
+            key.to_string(),
+            Entry::new(RedisValue::SortedSet(RedisSortedSet::new())),
+        );
     }
     match db.get_mut(key) {
         Some(entry) => match &mut entry.value {
-            RedisValue::Geo(g) => Ok(g),
+            RedisValue::SortedSet(z) => Ok(z),
             _ => Err(wrong_type_error()),
         },
         None => unreachable!(),
     }
 }
 
+fn get_zset<'a>(
+    db: &'a mut crate::store::Database,
+    key: &str,
+) -> Result<Option<&'a RedisSortedSet>, RespValue> {
+    match db.get(key) {
+        Some(entry) => match &entry.value {
+            RedisValue::SortedSet(z) => Ok(Some(z)),
+            _ => Err(wrong_type_error()),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Coordinates are reported with 17 decimals, trailing zeros trimmed, like
+/// Redis does.
+fn coordinate_reply(value: f64) -> RespValue {
+    let text = format!("{value:.17}");
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    RespValue::bulk_string(text.as_bytes().to_vec())
+}
+
+fn parse_unit(arg: &RespValue) -> Result<f64, RespValue> {
+    arg_to_string(arg)
+        .and_then(|unit| unit_to_meters(&unit))
+        .ok_or_else(|| RespValue::error("ERR unsupported unit provided. please use M, KM, FT, MI"))
+}
+
+fn parse_float(arg: &RespValue) -> Result<f64, RespValue> {
+    arg_to_f64(arg).ok_or_else(|| RespValue::error("ERR value is not a valid float"))
+}
+
 /// GEOADD key [NX|XX] [CH] longitude latitude member [longitude latitude member ...]
 pub async fn cmd_geoadd(
     args: &[RespValue],
     store: &SharedStore,
     client: &ClientState,
+    key_watcher: &SharedKeyWatcher,
 ) -> RespValue {
     if args.len() < 4 {
         return wrong_arg_count("geoadd");
@@ -65,6 +102,9 @@ pub async fn cmd_geoadd(
             _ => break,
         }
     }
+    if nx && xx {
+        return RespValue::error("ERR XX and NX options at the same time are not compatible");
+    }
 
     // Remaining args are longitude latitude member triples
     let triples = &args[i..];
@@ -72,55 +112,64 @@ pub async fn cmd_geoadd(
         return wrong_arg_count("geoadd");
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-
-    let geo = match get_or_create_geo(db, &key) {
-        Ok(g) => g,
-        Err(e) => return e,
-    };
-
-    let mut added = 0i64;
-    let mut changed = 0i64;
-
+    // Validate every position before adding any (atomicity)
+    let mut points: Vec<(f64, Vec<u8>)> = Vec::with_capacity(triples.len() / 3);
     for triple in triples.chunks(3) {
-        let longitude = match arg_to_f64(&triple[0]) {
-            Some(v) => v,
-            None => return RespValue::error("ERR value is not a valid float"),
+        let longitude = match parse_float(&triple[0]) {
+            Ok(v) => v,
+            Err(e) => return e,
         };
-        let latitude = match arg_to_f64(&triple[1]) {
-            Some(v) => v,
-            None => return RespValue::error("ERR value is not a valid float"),
+        let latitude = match parse_float(&triple[1]) {
+            Ok(v) => v,
+            Err(e) => return e,
         };
+        if !geo::is_valid(longitude, latitude) {
+            return RespValue::error(format!(
+                "ERR invalid longitude,latitude pair {longitude:.6},{latitude:.6}"
+            ));
+        }
         let member = match arg_to_bytes(&triple[2]) {
             Some(m) => m.to_vec(),
             None => continue,
         };
+        points.push((geo::encode(longitude, latitude) as f64, member));
+    }
 
-        // Validate ranges
-        if !(-180.0..=180.0).contains(&longitude)
-            || !(-85.05112878..=85.05112878).contains(&latitude)
-        {
-            return RespValue::error("ERR invalid longitude,latitude pair");
-        }
-
-        let exists = geo.pos(&member).is_some();
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-        if nx && exists {
-            continue;
-        }
-        if xx && !exists {
-            continue;
-        }
+    // For XX mode on non-existing key, no-op
+    if xx && !db.exists(&key) {
+        return RespValue::integer(0);
+    }
+    let zset = match get_or_create_zset(&mut db, &key) {
+        Ok(z) => z,
+        Err(e) => return e,
+    };
 
-        let old_pos = geo.pos(&member);
-        let is_new = geo.add(member, longitude, latitude);
-        if is_new {
-            added += 1;
-        } else if old_pos != Some((longitude, latitude)) {
-            changed += 1;
+    let mut added = 0i64;
+    let mut changed = 0i64;
+    for (score, member) in points {
+        match zset.score(&member) {
+            Some(_) if nx => {}
+            None if xx => {}
+            Some(old) => {
+                if old != score {
+                    zset.add(member, score);
+                    changed += 1;
+                }
+            }
+            None => {
+                zset.add(member, score);
+                added += 1;
+            }
         }
     }
+    drop(store);
+    if added > 0 {
+        let mut watcher = key_watcher.write().await;
+        watcher.notify(&key);
+    }
 
     RespValue::integer(if ch { added + changed } else { added })
 }
@@ -147,34 +196,28 @@ pub async fn cmd_geodist(
         None => return RespValue::null_bulk_string(),
     };
 
-    let unit_factor = if args.len() == 4 {
-        let unit_str = match arg_to_string(&args[3]) {
-            Some(s) => s,
-            None => return RespValue::error("ERR unsupported unit provided"),
-        };
-        match unit_to_meters(&unit_str) {
-            Some(f) => f,
-            None => return RespValue::error("ERR unsupported unit provided"),
-        }
-    } else {
-        1.0 // default is meters
+    let unit_factor = match args.get(3).map(parse_unit) {
+        Some(Ok(f)) => f,
+        Some(Err(e)) => return e,
+        None => 1.0, // default is meters
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    match db.get(&key) {
-        Some(entry) => match &entry.value {
-            RedisValue::Geo(geo) => match geo.dist(member1, member2) {
-                Some(d) => {
-                    let converted = d / unit_factor;
-                    RespValue::bulk_string(format!("{:.4}", converted).into_bytes())
-                }
-                None => RespValue::null_bulk_string(),
-            },
-            _ => wrong_type_error(),
-        },
-        None => RespValue::null_bulk_string(),
+    let zset = match get_zset(&mut db, &key) {
+        Ok(Some(z)) => z,
+        Ok(None) => return RespValue::null_bulk_string(),
+        Err(e) => return e,
+    };
+    match (zset.score(member1), zset.score(member2)) {
+        (Some(score1), Some(score2)) => {
+            let (lon1, lat1) = geo::decode(score1);
+            let (lon2, lat2) = geo::decode(score2);
+            let d = geo::distance(lon1, lat1, lon2, lat2) / unit_factor;
+            RespValue::bulk_string(format!("{d:.4}").into_bytes())
+        }
+        _ => RespValue::null_bulk_string(),
     }
 }
 
@@ -187,260 +230,354 @@ pub async fn cmd_geopos(
     if args.len() < 2 {
         return wrong_arg_count("geopos");
     }
+    let nulls = || RespValue::array(args[1..].iter().map(|_| RespValue::null_array()).collect());
     let key = match arg_to_string(&args[0]) {
         Some(k) => k,
-        None => {
-            let nulls: Vec<RespValue> = args[1..].iter().map(|_| RespValue::null_array()).collect();
-            return RespValue::array(nulls);
-        }
+        None => return nulls(),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    match db.get(&key) {
-        Some(entry) => match &entry.value {
-            RedisValue::Geo(geo) => {
-                let results: Vec<RespValue> = args[1..]
-                    .iter()
-                    .map(|arg| {
-                        if let Some(member) = arg_to_bytes(arg) {
-                            match geo.pos(member) {
-                                Some((lon, lat)) => RespValue::array(vec![
-                                    RespValue::bulk_string(format!("{}", lon).into_bytes()),
-                                    RespValue::bulk_string(format!("{}", lat).into_bytes()),
-                                ]),
-                                None => RespValue::null_array(),
-                            }
-                        } else {
-                            RespValue::null_array()
-                        }
-                    })
-                    .collect();
-                RespValue::array(results)
+    let zset = match get_zset(&mut db, &key) {
+        Ok(Some(z)) => z,
+        Ok(None) => return nulls(),
+        Err(e) => return e,
+    };
+    let results: Vec<RespValue> = args[1..]
+        .iter()
+        .map(|arg| match arg_to_bytes(arg).and_then(|m| zset.score(m)) {
+            Some(score) => {
+                let (lon, lat) = geo::decode(score);
+                RespValue::array(vec![coordinate_reply(lon), coordinate_reply(lat)])
             }
-            _ => wrong_type_error(),
-        },
-        None => {
-            let nulls: Vec<RespValue> = args[1..].iter().map(|_| RespValue::null_array()).collect();
-            RespValue::array(nulls)
-        }
-    }
+            None => RespValue::null_array(),
+        })
+        .collect();
+    RespValue::array(results)
 }
 
-/// GEOSEARCH key FROMMEMBER member|FROMLONLAT lon lat BYRADIUS radius m|km|ft|mi|BYBOX width height m|km|ft|mi [ASC|DESC] [COUNT count]
-pub async fn cmd_geosearch(
+/// GEOHASH key member [member ...]
+pub async fn cmd_geohash(
     args: &[RespValue],
     store: &SharedStore,
     client: &ClientState,
 ) -> RespValue {
-    if args.len() < 4 {
-        return wrong_arg_count("geosearch");
+    if args.len() < 2 {
+        return wrong_arg_count("geohash");
     }
+    let nulls = || {
+        RespValue::array(
+            args[1..]
+                .iter()
+                .map(|_| RespValue::null_bulk_string())
+                .collect(),
+        )
+    };
     let key = match arg_to_string(&args[0]) {
         Some(k) => k,
-        None => return RespValue::array(vec![]),
+        None => return nulls(),
     };
 
-    let mut i = 1;
-    let mut center_lon: Option<f64> = None;
-    let mut center_lat: Option<f64> = None;
-    let mut from_member: Option<Vec<u8>> = None;
-    let mut by_radius: Option<(f64, f64)> = None; // (radius_m, unit_factor)
-    let mut by_box: Option<(f64, f64, f64)> = None; // (width_m, height_m, unit_factor)
-    let mut ascending = true;
-    let mut count: Option<usize> = None;
-    let mut withcoord = false;
-    let mut withdist = false;
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    while i < args.len() {
-        let opt = match arg_to_string(&args[i]) {
-            Some(s) => s.to_uppercase(),
-            None => {
-                i += 1;
-                continue;
-            }
-        };
-        match opt.as_str() {
-            "FROMMEMBER" => {
-                i += 1;
-                if i >= args.len() {
-                    return wrong_arg_count("geosearch");
-                }
-                from_member = arg_to_bytes(&args[i]).map(|b| b.to_vec());
-                i += 1;
-            }
-            "FROMLONLAT" => {
-                i += 1;
-                if i + 1 >= args.len() {
-                    return wrong_arg_count("geosearch");
-                }
-                center_lon = arg_to_f64(&args[i]);
-                center_lat = arg_to_f64(&args[i + 1]);
-                if center_lon.is_none() || center_lat.is_none() {
-                    return RespValue::error("ERR value is not a valid float");
-                }
-                i += 2;
+    let zset = match get_zset(&mut db, &key) {
+        Ok(Some(z)) => z,
+        Ok(None) => return nulls(),
+        Err(e) => return e,
+    };
+    let results: Vec<RespValue> = args[1..]
+        .iter()
+        .map(|arg| match arg_to_bytes(arg).and_then(|m| zset.score(m)) {
+            Some(score) => {
+                let (lon, lat) = geo::decode(score);
+                RespValue::bulk_string(geo::hash_string(lon, lat).into_bytes())
             }
-            "BYRADIUS" => {
-                i += 1;
-                if i + 1 >= args.len() {
-                    return wrong_arg_count("geosearch");
+            None => RespValue::null_bulk_string(),
+        })
+        .collect();
+    RespValue::array(results)
+}
+
+/// Where a search is centered.
+enum Origin {
+    Member(Vec<u8>),
+    LonLat(f64, f64),
+}
+
+/// Which command a search comes from, deciding the options it takes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SearchCommand {
+    Radius,
+    Search,
+    SearchStore,
+}
+
+/// A parsed GEOSEARCH, GEOSEARCHSTORE or GEORADIUS query.
+struct Search {
+    origin: Option<Origin>,
+    shape: Option<Shape>,
+    /// Meters per unit of reported distances.
+    unit: f64,
+    ascending: Option<bool>,
+    count: Option<usize>,
+    any: bool,
+    withcoord: bool,
+    withdist: bool,
+    withhash: bool,
+    storedist: bool,
+}
+
+impl Search {
+    fn new() -> Self {
+        Search {
+            origin: None,
+            shape: None,
+            unit: 1.0,
+            ascending: None,
+            count: None,
+            any: false,
+            withcoord: false,
+            withdist: false,
+            withhash: false,
+            storedist: false,
+        }
+    }
+
+    /// Parse the options following the key, or for GEORADIUS the radius.
+    fn parse_options(
+        &mut self,
+        args: &[RespValue],
+        command: SearchCommand,
+    ) -> Result<(), RespValue> {
+        let syntax_error = || RespValue::error("ERR syntax error");
+        let searching = command != SearchCommand::Radius;
+        let replying = command != SearchCommand::SearchStore;
+        let mut i = 0;
+        while i < args.len() {
+            let opt = arg_to_string(&args[i])
+                .ok_or_else(syntax_error)?
+                .to_uppercase();
+            let rest = &args[i + 1..];
+            i += 1;
+            match opt.as_str() {
+                "FROMMEMBER" if searching => {
+                    if self.origin.is_some() {
+                        return Err(RespValue::error(
+                            "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch",
+                        ));
+                    }
+                    let member = rest
+                        .first()
+                        .and_then(arg_to_bytes)
+                        .ok_or_else(syntax_error)?;
+                    self.origin = Some(Origin::Member(member.to_vec()));
+                    i += 1;
                 }
-                let radius = match arg_to_f64(&args[i]) {
-                    Some(r) => r,
-                    None => return RespValue::error("ERR value is not a valid float"),
-                };
-                i += 1;
-                let unit_str = match arg_to_string(&args[i]) {
-                    Some(s) => s,
-                    None => return RespValue::error("ERR unsupported unit provided"),
-                };
-                let factor = match unit_to_meters(&unit_str) {
-                    Some(f) => f,
-                    None => return RespValue::error("ERR unsupported unit provided"),
-                };
-                by_radius = Some((radius * factor, factor));
-                i += 1;
-            }
-            "BYBOX" => {
-                i += 1;
-                if i + 2 >= args.len() {
-                    return wrong_arg_count("geosearch");
+                "FROMLONLAT" if searching => {
+                    if self.origin.is_some() {
+                        return Err(RespValue::error(
+                            "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch",
+                        ));
+                    }
+                    let [lon, lat, ..] = rest else {
+                        return Err(syntax_error());
+                    };
+                    let (lon, lat) = (parse_float(lon)?, parse_float(lat)?);
+                    if !geo::is_valid(lon, lat) {
+                        return Err(RespValue::error(format!(
+                            "ERR invalid longitude,latitude pair {lon:.6},{lat:.6}"
+                        )));
+                    }
+                    self.origin = Some(Origin::LonLat(lon, lat));
+                    i += 2;
                 }
-                let width = match arg_to_f64(&args[i]) {
-                    Some(w) => w,
-                    None => return RespValue::error("ERR value is not a valid float"),
-                };
-                i += 1;
-                let height = match arg_to_f64(&args[i]) {
-                    Some(h) => h,
-                    None => return RespValue::error("ERR value is not a valid float"),
-                };
-                i += 1;
-                let unit_str = match arg_to_string(&args[i]) {
-                    Some(s) => s,
-                    None => return RespValue::error("ERR unsupported unit provided"),
-                };
-                let factor = match unit_to_meters(&unit_str) {
-                    Some(f) => f,
-                    None => return RespValue::error("ERR unsupported unit provided"),
-                };
-                by_box = Some((width * factor, height * factor, factor));
-                i += 1;
-            }
-            "ASC" => {
-                ascending = true;
-                i += 1;
-            }
-            "DESC" => {
-                ascending = false;
-                i += 1;
-            }
-            "COUNT" => {
-                i += 1;
-                if i >= args.len() {
-                    return wrong_arg_count("geosearch");
+                "BYRADIUS" | "BYBOX" if searching => {
+                    if self.shape.is_some() {
+                        return Err(RespValue::error(
+                            "ERR exactly one of BYRADIUS and BYBOX can be specified for geosearch",
+                        ));
+                    }
+                    let size_args = if opt == "BYRADIUS" { 1 } else { 2 };
+                    let (Some(sizes), Some(unit)) = (rest.get(..size_args), rest.get(size_args))
+                    else {
+                        return Err(syntax_error());
+                    };
+                    self.unit = parse_unit(unit)?;
+                    let sizes = sizes
+                        .iter()
+                        .map(|size| Ok(parse_float(size)? * self.unit))
+                        .collect::<Result<Vec<f64>, RespValue>>()?;
+                    self.shape = Some(match sizes[..] {
+                        [radius] if radius < 0.0 => {
+                            return Err(RespValue::error("ERR radius cannot be negative"));
+                        }
+                        [radius] => Shape::Radius(radius),
+                        [width, height] if width < 0.0 || height < 0.0 => {
+                            return Err(RespValue::error("ERR height or width cannot be negative"));
+                        }
+                        [width, height] => Shape::Box { width, height },
+                        _ => unreachable!(),
+                    });
+                    i += size_args + 1;
                 }
-                count = arg_to_i64(&args[i]).map(|n| n.max(0) as usize);
-                i += 1;
-                // Skip optional ANY flag
-                if i < args.len()
-                    && let Some(s) = arg_to_string(&args[i])
-                    && s.to_uppercase() == "ANY"
-                {
+                "ASC" => self.ascending = Some(true),
+                "DESC" => self.ascending = Some(false),
+                "COUNT" => {
+                    let count = rest.first().and_then(arg_to_i64).ok_or_else(|| {
+                        RespValue::error("ERR value is not an integer or out of range")
+                    })?;
+                    if count <= 0 {
+                        return Err(RespValue::error("ERR COUNT must be > 0"));
+                    }
+                    self.count = Some(count as usize);
                     i += 1;
+                    if rest
+                        .get(1)
+                        .and_then(arg_to_string)
+                        .is_some_and(|s| s.eq_ignore_ascii_case("ANY"))
+                    {
+                        self.any = true;
+                        i += 1;
+                    }
                 }
-            }
-            "WITHCOORD" => {
-                withcoord = true;
-                i += 1;
-            }
-            "WITHDIST" => {
-                withdist = true;
-                i += 1;
-            }
-            _ => {
-                i += 1;
+                "WITHCOORD" if replying => self.withcoord = true,
+                "WITHDIST" if replying => self.withdist = true,
+                "WITHHASH" if replying => self.withhash = true,
+                "STOREDIST" if command == SearchCommand::SearchStore => self.storedist = true,
+                // Storing GEORADIUS results isn't supported; they are
+                // returned instead
+                "STORE" | "STOREDIST" if command == SearchCommand::Radius => i += 1,
+                _ => return Err(syntax_error()),
             }
         }
-    }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+        if self.origin.is_none() {
+            return Err(RespValue::error(
+                "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch",
+            ));
+        }
+        if self.shape.is_none() {
+            return Err(RespValue::error(
+                "ERR exactly one of BYRADIUS and BYBOX can be specified for geosearch",
+            ));
+        }
+        if self.any && self.count.is_none() {
+            return Err(RespValue::error(
+                "ERR the ANY argument requires COUNT argument",
+            ));
+        }
+        Ok(())
+    }
 
-    // Resolve center point
-    let (cx, cy) = if let Some(member) = from_member {
-        match db.get(&key) {
-            Some(entry) => match &entry.value {
-                RedisValue::Geo(geo) => match geo.pos(&member) {
-                    Some((lon, lat)) => (lon, lat),
-                    None => return RespValue::array(vec![]),
-                },
-                _ => return wrong_type_error(),
+    /// Run the search over `zset`: the members inside the shape, in the
+    /// requested order and cut to COUNT.
+    fn run(&self, zset: &RedisSortedSet) -> Result<Vec<GeoResult>, RespValue> {
+        let center = match self.origin.as_ref().expect("parsed search has an origin") {
+            Origin::LonLat(lon, lat) => (*lon, *lat),
+            Origin::Member(member) => match zset.score(member) {
+                Some(score) => geo::decode(score),
+                None => {
+                    return Err(RespValue::error(
+                        "ERR could not decode requested zset member",
+                    ));
+                }
             },
-            None => return RespValue::array(vec![]),
+        };
+        let shape = self.shape.expect("parsed search has a shape");
+        // With ANY the first COUNT matches will do; otherwise all of them
+        // are needed to find the nearest
+        let limit = if self.any { self.count } else { None };
+        let mut results = geo::search(zset, center, &shape, limit);
+        // COUNT alone returns the nearest members
+        let ascending = match self.ascending {
+            None if self.count.is_some() && !self.any => Some(true),
+            order => order,
+        };
+        match ascending {
+            Some(true) => results.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+            Some(false) => results.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+            None => {}
         }
-    } else if let (Some(lon), Some(lat)) = (center_lon, center_lat) {
-        (lon, lat)
-    } else {
-        return RespValue::error("ERR exactly one of FROMMEMBER or FROMLONLAT must be provided");
-    };
+        if let Some(count) = self.count {
+            results.truncate(count);
+        }
+        Ok(results)
+    }
 
-    let results = match db.get(&key) {
-        Some(entry) => match &entry.value {
-            RedisValue::Geo(geo) => {
-                if let Some((radius_m, _factor)) = by_radius {
-                    geo.search_within_radius(cx, cy, radius_m, ascending, count)
-                } else if let Some((width_m, height_m, _factor)) = by_box {
-                    geo.search_within_box(cx, cy, width_m, height_m, ascending, count)
-                } else {
-                    return RespValue::error(
-                        "ERR exactly one of BYRADIUS or BYBOX must be provided",
-                    );
+    fn reply(&self, results: &[GeoResult]) -> RespValue {
+        let items = results
+            .iter()
+            .map(|r| {
+                let member = RespValue::bulk_string(r.member.clone());
+                if !(self.withcoord || self.withdist || self.withhash) {
+                    return member;
                 }
-            }
-            _ => return wrong_type_error(),
-        },
-        None => return RespValue::array(vec![]),
-    };
-
-    // Determine the unit factor for distance display
-    let display_factor = if let Some((_, factor)) = by_radius {
-        factor
-    } else if let Some((_, _, factor)) = by_box {
-        factor
-    } else {
-        1.0
-    };
-
-    let resp: Vec<RespValue> = results
-        .iter()
-        .map(|r| {
-            if withcoord || withdist {
-                let mut items = vec![RespValue::bulk_string(r.member.clone())];
-                if withdist {
+                let mut items = vec![member];
+                if self.withdist {
                     items.push(RespValue::bulk_string(
-                        format!("{:.4}", r.distance / display_factor).into_bytes(),
+                        format!("{:.4}", r.distance / self.unit).into_bytes(),
                     ));
                 }
-                if withcoord {
+                if self.withhash {
+                    items.push(RespValue::integer(r.score as i64));
+                }
+                if self.withcoord {
                     items.push(RespValue::array(vec![
-                        RespValue::bulk_string(format!("{}", r.longitude).into_bytes()),
-                        RespValue::bulk_string(format!("{}", r.latitude).into_bytes()),
+                        coordinate_reply(r.longitude),
+                        coordinate_reply(r.latitude),
                     ]));
                 }
                 RespValue::array(items)
-            } else {
-                RespValue::bulk_string(r.member.clone())
-            }
-        })
-        .collect();
+            })
+            .collect();
+        RespValue::array(items)
+    }
+}
+
+/// Run a parsed search on `key` and reply with the results.
+async fn search_reply(
+    key: &str,
+    search: &Search,
+    store: &SharedStore,
+    client: &ClientState,
+) -> RespValue {
+    let mut store = store.lock_keys(client.db_index, [key]).await;
+    let mut db = store.db(client.db_index);
 
-    RespValue::array(resp)
+    let zset = match get_zset(&mut db, key) {
+        Ok(Some(z)) => z,
+        Ok(None) => return RespValue::array(vec![]),
+        Err(e) => return e,
+    };
+    match search.run(zset) {
+        Ok(results) => search.reply(&results),
+        Err(e) => e,
+    }
 }
 
-/// GEORADIUS key longitude latitude radius m|km|ft|mi [WITHCOORD] [WITHDIST] [COUNT count] [ASC|DESC] [STORE key] [STOREDIST key]
+/// GEOSEARCH key FROMMEMBER member|FROMLONLAT lon lat BYRADIUS radius m|km|ft|mi|BYBOX width height m|km|ft|mi [ASC|DESC] [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]
+pub async fn cmd_geosearch(
+    args: &[RespValue],
+    store: &SharedStore,
+    client: &ClientState,
+) -> RespValue {
+    if args.len() < 4 {
+        return wrong_arg_count("geosearch");
+    }
+    let key = match arg_to_string(&args[0]) {
+        Some(k) => k,
+        None => return RespValue::array(vec![]),
+    };
+    let mut search = Search::new();
+    if let Err(e) = search.parse_options(&args[1..], SearchCommand::Search) {
+        return e;
+    }
+    search_reply(&key, &search, store, client).await
+}
+
+/// GEORADIUS key longitude latitude radius m|km|ft|mi [WITHCOORD] [WITHDIST] [WITHHASH] [COUNT count [ANY]] [ASC|DESC]
 pub async fn cmd_georadius(
     args: &[RespValue],
     store: &SharedStore,
@@ -453,123 +590,24 @@ pub async fn cmd_georadius(
         Some(k) => k,
         None => return RespValue::error("ERR invalid key"),
     };
-
-    // Check type first - return WRONGTYPE if key exists but is wrong type
-    {
-        let mut s = store.write().await;
-        let db = s.db(client.db_index);
-        if let Some(entry) = db.get(&key)
-            && !matches!(&entry.value, RedisValue::Geo(_))
-        {
-            return wrong_type_error();
-        }
-    }
-
-    let lon = match arg_to_f64(&args[1]) {
-        Some(v) => v,
-        None => return RespValue::error("ERR value is not a valid float"),
-    };
-    let lat = match arg_to_f64(&args[2]) {
-        Some(v) => v,
-        None => return RespValue::error("ERR value is not a valid float"),
-    };
-    let radius = match arg_to_f64(&args[3]) {
-        Some(v) => v,
-        None => return RespValue::error("ERR value is not a valid float"),
-    };
-    let unit_str = match arg_to_string(&args[4]) {
-        Some(s) => s,
-        None => return RespValue::error("ERR unsupported unit provided"),
+    let mut search = Search::new();
+    let (lon, lat) = match (parse_float(&args[1]), parse_float(&args[2])) {
+        (Ok(lon), Ok(lat)) => (lon, lat),
+        (Err(e), _) | (_, Err(e)) => return e,
     };
-    let factor = match unit_to_meters(&unit_str) {
-        Some(f) => f,
-        None => return RespValue::error("ERR unsupported unit provided"),
-    };
-
-    let mut withcoord = false;
-    let mut withdist = false;
-    let mut ascending = true;
-    let mut count: Option<usize> = None;
-    let mut i = 5;
-    while i < args.len() {
-        let opt = match arg_to_string(&args[i]) {
-            Some(s) => s.to_uppercase(),
-            None => {
-                i += 1;
-                continue;
-            }
-        };
-        match opt.as_str() {
-            "WITHCOORD" => {
-                withcoord = true;
-                i += 1;
-            }
-            "WITHDIST" => {
-                withdist = true;
-                i += 1;
-            }
-            "ASC" => {
-                ascending = true;
-                i += 1;
-            }
-            "DESC" => {
-                ascending = false;
-                i += 1;
-            }
-            "COUNT" => {
-                i += 1;
-                count = args.get(i).and_then(arg_to_i64).map(|n| n.max(0) as usize);
-                i += 1;
-            }
-            "STORE" | "STOREDIST" => {
-                i += 2;
-            } // skip store key
-            _ => {
-                i += 1;
-            }
-        }
+    if !geo::is_valid(lon, lat) {
+        return RespValue::error(format!(
+            "ERR invalid longitude,latitude pair {lon:.6},{lat:.6}"
+        ));
     }
-
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-
-    let results = match db.get(&key) {
-        Some(entry) => match &entry.value {
-            RedisValue::Geo(geo) => {
-                geo.search_within_radius(lon, lat, radius * factor, ascending, count)
-            }
-            _ => return wrong_type_error(),
-        },
-        None => return RespValue::array(vec![]),
-    };
-
-    let resp: Vec<RespValue> = results
-        .iter()
-        .map(|r| {
-            if withcoord || withdist {
-                let mut items = vec![RespValue::bulk_string(r.member.clone())];
-                if withdist {
-                    items.push(RespValue::bulk_string(
-                        format!("{:.4}", r.distance / factor).into_bytes(),
-                    ));
-                }
-                if withcoord {
-                    items.push(RespValue::array(vec![
-                        RespValue::bulk_string(format!("{}", r.longitude).into_bytes()),
-                        RespValue::bulk_string(format!("{}", r.latitude).into_bytes()),
-                    ]));
-                }
-                RespValue::array(items)
-            } else {
-                RespValue::bulk_string(r.member.clone())
-            }
-        })
-        .collect();
-
-    RespValue::array(resp)
+    search.origin = Some(Origin::LonLat(lon, lat));
+    if let Err(e) = radius_options(&mut search, &args[3..]) {
+        return e;
+    }
+    search_reply(&key, &search, store, client).await
 }
 
-/// GEORADIUSBYMEMBER key member radius m|km|ft|mi [WITHCOORD] [WITHDIST] [COUNT count] [ASC|DESC]
+/// GEORADIUSBYMEMBER key member radius m|km|ft|mi [WITHCOORD] [WITHDIST] [WITHHASH] [COUNT count [ANY]] [ASC|DESC]
 pub async fn cmd_georadiusbymember(
     args: &[RespValue],
     store: &SharedStore,
@@ -586,116 +624,32 @@ pub async fn cmd_georadiusbymember(
         Some(m) => m.to_vec(),
         None => return RespValue::error("ERR invalid member"),
     };
-    let radius = match arg_to_f64(&args[2]) {
-        Some(v) => v,
-        None => return RespValue::error("ERR value is not a valid float"),
-    };
-    let unit_str = match arg_to_string(&args[3]) {
-        Some(s) => s,
-        None => return RespValue::error("ERR unsupported unit provided"),
-    };
-    let factor = match unit_to_meters(&unit_str) {
-        Some(f) => f,
-        None => return RespValue::error("ERR unsupported unit provided"),
-    };
-
-    let mut withcoord = false;
-    let mut withdist = false;
-    let mut ascending = true;
-    let mut count: Option<usize> = None;
-    let mut i = 4;
-    while i < args.len() {
-        let opt = match arg_to_string(&args[i]) {
-            Some(s) => s.to_uppercase(),
-            None => {
-                i += 1;
-                continue;
-            }
-        };
-        match opt.as_str() {
-            "WITHCOORD" => {
-                withcoord = true;
-                i += 1;
-            }
-            "WITHDIST" => {
-                withdist = true;
-                i += 1;
-            }
-            "ASC" => {
-                ascending = true;
-                i += 1;
-            }
-            "DESC" => {
-                ascending = false;
-                i += 1;
-            }
-            "COUNT" => {
-                i += 1;
-                count = args.get(i).and_then(arg_to_i64).map(|n| n.max(0) as usize);
-                i += 1;
-            }
-            _ => {
-                i += 1;
-            }
-        }
+    let mut search = Search::new();
+    search.origin = Some(Origin::Member(member));
+    if let Err(e) = radius_options(&mut search, &args[2..]) {
+        return e;
     }
+    search_reply(&key, &search, store, client).await
+}
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-
-    // Resolve member position
-    let (lon, lat) = match db.get(&key) {
-        Some(entry) => match &entry.value {
-            RedisValue::Geo(geo) => match geo.pos(&member) {
-                Some(pos) => pos,
-                None => return RespValue::array(vec![]),
-            },
-            _ => return wrong_type_error(),
-        },
-        None => return RespValue::array(vec![]),
-    };
-
-    let results = match db.get(&key) {
-        Some(entry) => match &entry.value {
-            RedisValue::Geo(geo) => {
-                geo.search_within_radius(lon, lat, radius * factor, ascending, count)
-            }
-            _ => return wrong_type_error(),
-        },
-        None => return RespValue::array(vec![]),
-    };
-
-    let resp: Vec<RespValue> = results
-        .iter()
-        .map(|r| {
-            if withcoord || withdist {
-                let mut items = vec![RespValue::bulk_string(r.member.clone())];
-                if withdist {
-                    items.push(RespValue::bulk_string(
-                        format!("{:.4}", r.distance / factor).into_bytes(),
-                    ));
-                }
-                if withcoord {
-                    items.push(RespValue::array(vec![
-                        RespValue::bulk_string(format!("{}", r.longitude).into_bytes()),
-                        RespValue::bulk_string(format!("{}", r.latitude).into_bytes()),
-                    ]));
-                }
-                RespValue::array(items)
-            } else {
-                RespValue::bulk_string(r.member.clone())
-            }
-        })
-        .collect();
-
-    RespValue::array(resp)
+/// Parse the `radius unit [options]` tail of GEORADIUS and
+/// GEORADIUSBYMEMBER.
+fn radius_options(search: &mut Search, args: &[RespValue]) -> Result<(), RespValue> {
+    let radius = parse_float(&args[0])?;
+    if radius < 0.0 {
+        return Err(RespValue::error("ERR radius cannot be negative"));
+    }
+    search.unit = parse_unit(&args[1])?;
+    search.shape = Some(Shape::Radius(radius * search.unit));
+    search.parse_options(&args[2..], SearchCommand::Radius)
 }
 
-/// GEOSEARCHSTORE destination source [FROMMEMBER member|FROMLONLAT lon lat] [BYRADIUS radius m|km|ft|mi|BYBOX width height m|km|ft|mi] [ASC|DESC] [COUNT count]
+/// GEOSEARCHSTORE destination source FROMMEMBER member|FROMLONLAT lon lat BYRADIUS radius m|km|ft|mi|BYBOX width height m|km|ft|mi [ASC|DESC] [COUNT count [ANY]] [STOREDIST]
 pub async fn cmd_geosearchstore(
     args: &[RespValue],
     store: &SharedStore,
     client: &ClientState,
+    key_watcher: &SharedKeyWatcher,
 ) -> RespValue {
     if args.len() < 5 {
         return wrong_arg_count("geosearchstore");
@@ -704,167 +658,50 @@ pub async fn cmd_geosearchstore(
         Some(k) => k,
         None => return RespValue::error("ERR invalid key"),
     };
-
-    // Check if source key is wrong type first
     let source_key = match arg_to_string(&args[1]) {
         Some(k) => k,
         None => return RespValue::error("ERR invalid key"),
     };
-
-    {
-        let mut s = store.write().await;
-        let db = s.db(client.db_index);
-        if let Some(entry) = db.get(&source_key)
-            && !matches!(&entry.value, RedisValue::Geo(_))
-        {
-            return wrong_type_error();
-        }
+    let mut search = Search::new();
+    if let Err(e) = search.parse_options(&args[2..], SearchCommand::SearchStore) {
+        return e;
     }
 
-    // Reuse geosearch logic on the remaining args
-    let search_args: Vec<RespValue> = std::iter::once(args[1].clone())
-        .chain(args[2..].iter().cloned())
-        .collect();
+    let mut s = store
+        .lock_keys(client.db_index, [&source_key, &dest_key])
+        .await;
+    let mut db = s.db(client.db_index);
 
-    // Parse like geosearch but then store results
-    let search_result = cmd_geosearch(&search_args, store, client).await;
-
-    // Count results and store in destination
-    match search_result {
-        RespValue::Array(Some(ref items)) => {
-            let count = items.len() as i64;
-            // Create a new geo set at destination with the found members
-            let mut s = store.write().await;
-            let db = s.db(client.db_index);
-
-            // Get source geo data
-            let members_to_copy: Vec<(Vec<u8>, f64, f64)> = match db.get(&source_key) {
-                Some(entry) => match &entry.value {
-                    RedisValue::Geo(geo) => items
-                        .iter()
-                        .filter_map(|item| {
-                            let member_name = match item {
-                                RespValue::BulkString(Some(data)) => data.clone(),
-                                RespValue::Array(Some(inner)) => {
-                                    if let Some(RespValue::BulkString(Some(data))) = inner.first() {
-                                        data.clone()
-                                    } else {
-                                        return None;
-                                    }
-                                }
-                                _ => return None,
-                            };
-                            geo.pos(&member_name)
-                                .map(|(lon, lat)| (member_name, lon, lat))
-                        })
-                        .collect(),
-                    _ => return wrong_type_error(),
-                },
-                None => vec![],
-            };
-
-            let mut new_geo = GeoSet::new();
-            for (member, lon, lat) in &members_to_copy {
-                new_geo.add(member.clone(), *lon, *lat);
-            }
-            db.set(dest_key, Entry::new(RedisValue::Geo(new_geo)));
-
-            RespValue::integer(count)
-        }
-        _ => RespValue::integer(0),
-    }
-}
-
-/// GEOHASH key member [member ...] — return Geohash strings (stub returning empty strings)
-pub async fn cmd_geohash(
-    args: &[RespValue],
-    store: &SharedStore,
-    client: &ClientState,
-) -> RespValue {
-    if args.len() < 2 {
-        return wrong_arg_count("geohash");
-    }
-    let key = match arg_to_string(&args[0]) {
-        Some(k) => k,
-        None => return RespValue::array(vec![]),
-    };
-
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-
-    match db.get(&key) {
-        Some(entry) => match &entry.value {
-            RedisValue::Geo(geo) => {
-                let results: Vec<RespValue> = args[1..]
-                    .iter()
-                    .map(|arg| {
-                        if let Some(member) = arg_to_bytes(arg) {
-                            if let Some((lon, lat)) = geo.pos(member) {
-                                // Simple geohash encoding (11-char base32)
-                                let hash = encode_geohash(lon, lat, 11);
-                                RespValue::bulk_string(hash.into_bytes())
-                            } else {
-                                RespValue::null_bulk_string()
-                            }
-                        } else {
-                            RespValue::null_bulk_string()
-                        }
-                    })
-                    .collect();
-                RespValue::array(results)
-            }
-            _ => wrong_type_error(),
+    let results = match get_zset(&mut db, &source_key) {
+        Ok(Some(zset)) => match search.run(zset) {
+            Ok(results) => results,
+            Err(e) => return e,
         },
-        None => {
-            let nulls: Vec<RespValue> = args[1..]
-                .iter()
-                .map(|_| RespValue::null_bulk_string())
-                .collect();
-            RespValue::array(nulls)
-        }
-    }
-}
+        Ok(None) => vec![],
+        Err(e) => return e,
+    };
 
-fn encode_geohash(lon: f64, lat: f64, precision: usize) -> String {
-    const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
-    let mut min_lon = -180.0_f64;
-    let mut max_lon = 180.0_f64;
-    let mut min_lat = -90.0_f64;
-    let mut max_lat = 90.0_f64;
-    let mut hash = String::with_capacity(precision);
-    let mut bits = 0u8;
-    let mut bit_count = 0u8;
-    let mut even_bit = true;
-
-    while hash.len() < precision {
-        if even_bit {
-            let mid = (min_lon + max_lon) / 2.0;
-            if lon >= mid {
-                bits = (bits << 1) | 1;
-                min_lon = mid;
-            } else {
-                bits <<= 1;
-                max_lon = mid;
-            }
+    // Members keep their geohash scores, or with STOREDIST are scored by
+    // their distance
+    let mut zset = RedisSortedSet::new();
+    for r in &results {
+        let score = if search.storedist {
+            r.distance / search.unit
         } else {
-            let mid = (min_lat + max_lat) / 2.0;
-            if lat >= mid {
-                bits = (bits << 1) | 1;
-                min_lat = mid;
-            } else {
-                bits <<= 1;
-                max_lat = mid;
-            }
-        }
-        even_bit = !even_bit;
-        bit_count += 1;
-        if bit_count == 5 {
-            hash.push(BASE32[bits as usize] as char);
-            bits = 0;
-            bit_count = 0;
-        }
+            r.score
+        };
+        zset.add(r.member.clone(), score);
     }
-    hash
+    let count = zset.len() as i64;
+    if zset.is_empty() {
+        db.del(&dest_key);
+        return RespValue::integer(0);
+    }
+    db.set(dest_key.clone(), Entry::new(RedisValue::SortedSet(zset)));
+    drop(s);
+    let mut watcher = key_watcher.write().await;
+    watcher.notify(&dest_key);
+    RespValue::integer(count)
 }
 
 /// GEOMEMBERS key — return all members in the geo set (non-standard, utility command).
@@ -881,21 +718,16 @@ pub async fn cmd_geomembers(
         None => return RespValue::array(vec![]),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-
-    match db.get(&key) {
-        Some(entry) => match &entry.value {
-            RedisValue::Geo(geo) => {
-                let members = geo.all_members();
-                let resp: Vec<RespValue> = members
-                    .iter()
-                    .map(|(m, _, _)| RespValue::bulk_string(m.to_vec()))
-                    .collect();
-                RespValue::array(resp)
-            }
-            _ => wrong_type_error(),
-        },
-        None => RespValue::array(vec![]),
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
+
+    match get_zset(&mut db, &key) {
+        Ok(Some(zset)) => RespValue::array(
+            zset.iter()
+                .map(|(m, _)| RespValue::bulk_string(m.to_vec()))
+                .collect(),
+        ),
+        Ok(None) => RespValue::array(vec![]),
+        Err(e) => e,
     }
 }
@@ -1,5 +1,6 @@
 use crate::command::{
-    arg_to_bytes, arg_to_f64, arg_to_i64, arg_to_string, wrong_arg_count, wrong_type_error,
+    arg_to_bytes, arg_to_f64, arg_to_i64, arg_to_string, parse_cursor, parse_scan_count,
+    wrong_arg_count, wrong_type_error,
 };
 use crate::connection::ClientState;
 use crate::resp::RespValue;
@@ -34,10 +35,10 @@ pub async fn cmd_hset(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let hash = match get_or_create_hash(db, &key) {
+    let hash = match get_or_create_hash(&mut db, &key) {
         Ok(h) => h,
         Err(e) => return e,
     };
@@ -69,10 +70,10 @@ pub async fn cmd_hmset(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let hash = match get_or_create_hash(db, &key) {
+    let hash = match get_or_create_hash(&mut db, &key) {
         Ok(h) => h,
         Err(e) => return e,
     };
@@ -105,13 +106,13 @@ pub async fn cmd_hget(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::null_bulk_string(),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
             RedisValue::Hash(h) => match h.get(&field) {
-                Some(v) => RespValue::bulk_string(v.clone()),
+                Some(v) => RespValue::bulk_string(v.to_vec()),
                 None => RespValue::null_bulk_string(),
             },
             _ => wrong_type_error(),
@@ -129,8 +130,8 @@ pub async fn cmd_hdel(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let is_hash =
         matches!(db.get(&key), Some(entry) if matches!(&entry.value, RedisValue::Hash(_)));
@@ -182,7 +183,7 @@ pub async fn cmd_hexists(
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -203,7 +204,7 @@ pub async fn cmd_hlen(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -224,8 +225,8 @@ pub async fn cmd_hkeys(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::array(vec![]),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -252,8 +253,8 @@ pub async fn cmd_hvals(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::array(vec![]),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -261,7 +262,7 @@ pub async fn cmd_hvals(args: &[RespValue], store: &SharedStore, client: &ClientS
                 let vals: Vec<RespValue> = h
                     .values()
                     .into_iter()
-                    .map(|v| RespValue::bulk_string(v.clone()))
+                    .map(|v| RespValue::bulk_string(v.to_vec()))
                     .collect();
                 RespValue::array(vals)
             }
@@ -284,8 +285,8 @@ pub async fn cmd_hgetall(
         None => return RespValue::array(vec![]),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -293,7 +294,7 @@ pub async fn cmd_hgetall(
                 let mut result = Vec::new();
                 for (field, value) in h.entries() {
                     result.push(RespValue::bulk_string(field.as_bytes().to_vec()));
-                    result.push(RespValue::bulk_string(value.clone()));
+                    result.push(RespValue::bulk_string(value.to_vec()));
                 }
                 RespValue::array(result)
             }
@@ -318,7 +319,7 @@ pub async fn cmd_hmget(args: &[RespValue], store: &SharedStore, client: &ClientS
         }
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -328,7 +329,7 @@ pub async fn cmd_hmget(args: &[RespValue], store: &SharedStore, client: &ClientS
                 for arg in &args[1..] {
                     if let Some(field) = arg_to_string(arg) {
                         match h.get(&field) {
-                            Some(v) => results.push(RespValue::bulk_string(v.clone())),
+                            Some(v) => results.push(RespValue::bulk_string(v.to_vec())),
                             None => results.push(RespValue::null_bulk_string()),
                         }
                     } else {
@@ -370,10 +371,10 @@ pub async fn cmd_hincrby(
         None => return RespValue::error("ERR value is not an integer or out of range"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let hash = match get_or_create_hash(db, &key) {
+    let hash = match get_or_create_hash(&mut db, &key) {
         Ok(h) => h,
         Err(e) => return e,
     };
@@ -409,10 +410,10 @@ pub async fn cmd_hincrbyfloat(
         return RespValue::error("ERR value is NaN or Infinity");
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let hash = match get_or_create_hash(db, &key) {
+    let hash = match get_or_create_hash(&mut db, &key) {
         Ok(h) => h,
         Err(e) => return e,
     };
@@ -444,10 +445,10 @@ pub async fn cmd_hsetnx(
         None => return RespValue::error("ERR invalid value"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let hash = match get_or_create_hash(db, &key) {
+    let hash = match get_or_create_hash(&mut db, &key) {
         Ok(h) => h,
         Err(e) => return e,
     };
@@ -472,8 +473,8 @@ pub async fn cmd_hstrlen(
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -519,8 +520,8 @@ pub async fn cmd_hgetdel(
         );
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let is_hash =
         matches!(db.get(&key), Some(entry) if matches!(&entry.value, RedisValue::Hash(_)));
@@ -546,9 +547,8 @@ pub async fn cmd_hgetdel(
     {
         for arg in &args[3..] {
             if let Some(field) = arg_to_string(arg) {
-                match h.get(&field) {
-                    Some(v) => {
-                        let val = v.clone();
+                match h.get(&field).map(<[u8]>::to_vec) {
+                    Some(val) => {
                         h.del(&field);
                         results.push(RespValue::bulk_string(val));
                     }
@@ -584,8 +584,8 @@ pub async fn cmd_hrandfield(
         None => return RespValue::null_bulk_string(),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -624,7 +624,7 @@ pub async fn cmd_hrandfield(
                     let all_entries: Vec<(String, Vec<u8>)> = h
                         .entries()
                         .into_iter()
-                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .map(|(k, v)| (k.to_string(), v.to_vec()))
                         .collect();
 
                     if all_entries.is_empty() {
@@ -685,8 +685,13 @@ pub async fn cmd_hscan(args: &[RespValue], store: &SharedStore, client: &ClientS
         }
     };
 
+    let Some(cursor) = parse_cursor(&args[1]) else {
+        return RespValue::error("ERR invalid cursor");
+    };
+
     // Parse optional MATCH, COUNT, NOVALUES
     let mut pattern: Option<String> = None;
+    let mut count = 10;
     let mut novalues = false;
     let mut i = 2;
     while i < args.len() {
@@ -704,7 +709,11 @@ pub async fn cmd_hscan(args: &[RespValue], store: &SharedStore, client: &ClientS
             }
             "COUNT" => {
                 i += 1;
-            } // skip count value
+                count = match parse_scan_count(args.get(i)) {
+                    Ok(c) => c,
+                    Err(e) => return e,
+                };
+            }
             "NOVALUES" => {
                 novalues = true;
             }
@@ -713,26 +722,26 @@ pub async fn cmd_hscan(args: &[RespValue], store: &SharedStore, client: &ClientS
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
             RedisValue::Hash(h) => {
                 let mut result = Vec::new();
-                for (field, value) in h.entries() {
+                let next_cursor = h.scan(cursor, count, |field, value| {
                     if let Some(ref pat) = pattern
                         && !crate::glob::glob_match(pat, field)
                     {
-                        continue;
+                        return;
                     }
                     result.push(RespValue::bulk_string(field.as_bytes().to_vec()));
                     if !novalues {
-                        result.push(RespValue::bulk_string(value.clone()));
+                        result.push(RespValue::bulk_string(value.to_vec()));
                     }
-                }
+                });
                 RespValue::array(vec![
-                    RespValue::bulk_string(b"0".to_vec()),
+                    RespValue::bulk_string(next_cursor.to_string().into_bytes()),
                     RespValue::array(result),
                 ])
             }
@@ -1,32 +1,76 @@
-use crate::command::{arg_to_bytes, arg_to_string, wrong_arg_count};
+use crate::command::{arg_to_bytes, arg_to_string, wrong_arg_count, wrong_type_error};
 use crate::connection::ClientState;
 use crate::resp::RespValue;
 use crate::store::SharedStore;
 use crate::store::entry::Entry;
 use crate::types::RedisValue;
-use crate::types::hyperloglog::HyperLogLog;
+use crate::types::hyperloglog::{self, Encoding, HLL_REGISTERS, HyperLogLog};
+use crate::types::rstring::RedisString;
 
-/// Helper: get or create a HyperLogLog at the given key.
-/// Returns Err(RespValue) if the key holds the wrong type.
-fn get_or_create_hll<'a>(
-    db: &'a mut crate::store::Database,
+fn invalid_hll_error() -> RespValue {
+    RespValue::error("WRONGTYPE Key is not a valid HyperLogLog string value.")
+}
+
+fn corrupted_hll_error() -> RespValue {
+    RespValue::error("INVALIDOBJ Corrupted HLL object detected")
+}
+
+/// Helper: run `f` on the HLL string at `key` in place, creating an empty
+/// one first if `create`. Returns Ok(None) if the key doesn't exist and
+/// isn't created, Err(RespValue) if it holds anything but an HLL.
+fn with_hll_mut<T>(
+    db: &mut crate::store::Database,
     key: &str,
-) -> Result<&'a mut HyperLogLog, RespValue> {
-    if !db.exists(key) {
-        db.set(
-            key.to_string(),
-            Entry::new(RedisValue::HyperLogLog(HyperLogLog::new())),
-        );
+    create: bool,
+    f: impl FnOnce(&mut HyperLogLog) -> T,
+) -> Result<Option<T>, RespValue> {
+    if create && !db.exists(key) {
+        let empty = RedisString::new(HyperLogLog::new().into_bytes());
+        db.set(key.to_string(), Entry::new(RedisValue::String(empty)));
     }
-    match db.get_mut(key) {
+    let s = match db.get_mut(key) {
         Some(entry) => match &mut entry.value {
-            RedisValue::HyperLogLog(hll) => Ok(hll),
-            _ => Err(RespValue::error(
-                "WRONGTYPE Operation against a key holding the wrong kind of value",
-            )),
+            RedisValue::String(s) => s,
+            _ => return Err(wrong_type_error()),
         },
-        None => unreachable!(),
-    }
+        None => return Ok(None),
+    };
+    let mut hll =
+        HyperLogLog::from_bytes(std::mem::replace(s, RedisString::new(Vec::new())).into_bytes())
+            .map_err(|bytes| {
+                *s = RedisString::new(bytes);
+                invalid_hll_error()
+            })?;
+    let result = f(&mut hll);
+    *s = RedisString::new(hll.into_bytes());
+    Ok(Some(result))
+}
+
+/// Helper: a copy of the HLL at `key`, if any.
+fn read_hll(db: &mut crate::store::Database, key: &str) -> Result<Option<HyperLogLog>, RespValue> {
+    let bytes = match db.get(key) {
+        Some(entry) => match &entry.value {
+            RedisValue::String(s) => s.as_bytes().to_vec(),
+            _ => return Err(wrong_type_error()),
+        },
+        None => return Ok(None),
+    };
+    HyperLogLog::from_bytes(bytes)
+        .map(Some)
+        .map_err(|_| invalid_hll_error())
+}
+
+/// Helper: raise `max` to the registers of the HLL at `key`, if any.
+fn merge_key(
+    db: &mut crate::store::Database,
+    key: &str,
+    max: &mut [u8],
+) -> Result<Option<Encoding>, RespValue> {
+    let Some(hll) = read_hll(db, key)? else {
+        return Ok(None);
+    };
+    hll.merge_into(max).map_err(|_| corrupted_hll_error())?;
+    Ok(Some(hll.encoding()))
 }
 
 /// PFADD key element [element ...]
@@ -39,28 +83,17 @@ pub async fn cmd_pfadd(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-    let hll = match get_or_create_hll(db, &key) {
-        Ok(h) => h,
-        Err(e) => return e,
-    };
-
-    // If no elements provided, just ensure the key exists and return 0
-    if args.len() == 1 {
-        return RespValue::integer(0);
-    }
-
-    let mut changed = false;
-    for arg in &args[1..] {
-        if let Some(element) = arg_to_bytes(arg)
-            && hll.add(element)
-        {
-            changed = true;
-        }
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
+    // Creating the key counts as a change, even with no elements
+    let created = !db.exists(&key);
+    let elements = args[1..].iter().filter_map(arg_to_bytes);
+    match with_hll_mut(&mut db, &key, true, |hll| hll.add_all(elements)) {
+        Ok(Some(Ok(changed))) => RespValue::integer(i64::from(created || changed)),
+        Ok(Some(Err(_))) => corrupted_hll_error(),
+        Ok(None) => unreachable!(),
+        Err(e) => e,
     }
-
-    RespValue::integer(if changed { 1 } else { 0 })
 }
 
 /// PFCOUNT key [key ...]
@@ -73,49 +106,38 @@ pub async fn cmd_pfcount(
         return wrong_arg_count("pfcount");
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(client.db_index, args.iter().filter_map(arg_to_string))
+        .await;
+    let mut db = store.db(client.db_index);
 
     if args.len() == 1 {
-        // Single key: return its count
+        // Single key: count it, without touching its cached cardinality
         let key = match arg_to_string(&args[0]) {
             Some(k) => k,
             None => return RespValue::error("ERR invalid key"),
         };
-        match db.get(&key) {
-            Some(entry) => match &entry.value {
-                RedisValue::HyperLogLog(hll) => RespValue::integer(hll.count() as i64),
-                _ => RespValue::error(
-                    "WRONGTYPE Operation against a key holding the wrong kind of value",
-                ),
+        match read_hll(&mut db, &key) {
+            Ok(Some(hll)) => match hll.count() {
+                Ok(count) => RespValue::integer(count as i64),
+                Err(_) => corrupted_hll_error(),
             },
-            None => RespValue::integer(0),
+            Ok(None) => RespValue::integer(0),
+            Err(e) => e,
         }
     } else {
-        // Multiple keys: merge into a temporary HLL and count
-        let mut merged = HyperLogLog::new();
+        // Multiple keys: count the union of their registers
+        let mut max = vec![0u8; HLL_REGISTERS];
         for arg in args {
             let key = match arg_to_string(arg) {
                 Some(k) => k,
                 None => return RespValue::error("ERR invalid key"),
             };
-            match db.get(&key) {
-                Some(entry) => match &entry.value {
-                    RedisValue::HyperLogLog(hll) => {
-                        merged.merge(hll);
-                    }
-                    _ => {
-                        return RespValue::error(
-                            "WRONGTYPE Operation against a key holding the wrong kind of value",
-                        );
-                    }
-                },
-                None => {
-                    // Nonexistent key contributes nothing
-                }
+            if let Err(e) = merge_key(&mut db, &key, &mut max) {
+                return e;
             }
         }
-        RespValue::integer(merged.count() as i64)
+        RespValue::integer(hyperloglog::estimate(&max) as i64)
     }
 }
 
@@ -125,7 +147,7 @@ pub async fn cmd_pfmerge(
     store: &SharedStore,
     client: &ClientState,
 ) -> RespValue {
-    if args.len() < 2 {
+    if args.is_empty() {
         return wrong_arg_count("pfmerge");
     }
     let destkey = match arg_to_string(&args[0]) {
@@ -133,51 +155,88 @@ pub async fn cmd_pfmerge(
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(client.db_index, args.iter().filter_map(arg_to_string))
+        .await;
+    let mut db = store.db(client.db_index);
 
-    // First, collect all source HLLs into a temporary merged HLL
-    let mut merged = HyperLogLog::new();
-
-    // If destkey already exists as an HLL, include it in the merge
-    if let Some(entry) = db.get(&destkey) {
-        match &entry.value {
-            RedisValue::HyperLogLog(hll) => {
-                merged.merge(hll);
-            }
-            _ => {
-                return RespValue::error(
-                    "WRONGTYPE Operation against a key holding the wrong kind of value",
-                );
-            }
-        }
-    }
-
-    // Merge all source keys
-    for arg in &args[1..] {
+    // Take the max of every register over the sources, destkey included.
+    // The result is dense if any of them is, else sparse while it fits.
+    let mut max = vec![0u8; HLL_REGISTERS];
+    let mut encoding = Encoding::Sparse;
+    for arg in args {
         let key = match arg_to_string(arg) {
             Some(k) => k,
             None => return RespValue::error("ERR invalid key"),
         };
-        match db.get(&key) {
-            Some(entry) => match &entry.value {
-                RedisValue::HyperLogLog(hll) => {
-                    merged.merge(hll);
+        match merge_key(&mut db, &key, &mut max) {
+            Ok(Some(Encoding::Dense)) => encoding = Encoding::Dense,
+            Ok(_) => {}
+            Err(e) => return e,
+        }
+    }
+
+    let result = with_hll_mut(&mut db, &destkey, true, |hll| {
+        *hll = HyperLogLog::from_registers(&max, encoding);
+    });
+    match result {
+        Ok(_) => RespValue::ok(),
+        Err(e) => e,
+    }
+}
+
+/// PFDEBUG GETREG|DECODE|ENCODING|TODENSE key
+pub async fn cmd_pfdebug(
+    args: &[RespValue],
+    store: &SharedStore,
+    client: &ClientState,
+) -> RespValue {
+    if args.len() != 2 {
+        return wrong_arg_count("pfdebug");
+    }
+    let sub = arg_to_string(&args[0]).unwrap_or_default();
+    let key = match arg_to_string(&args[1]) {
+        Some(k) => k,
+        None => return RespValue::error("ERR invalid key"),
+    };
+
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
+
+    let result = with_hll_mut(&mut db, &key, false, |hll| {
+        match sub.to_uppercase().as_str() {
+            "GETREG" => {
+                // Like Redis, reading the registers converts to dense
+                if hll.to_dense().is_err() {
+                    return corrupted_hll_error();
                 }
-                _ => {
-                    return RespValue::error(
-                        "WRONGTYPE Operation against a key holding the wrong kind of value",
-                    );
+                match hll.registers() {
+                    Ok(registers) => RespValue::array(
+                        registers
+                            .into_iter()
+                            .map(|r| RespValue::integer(i64::from(r)))
+                            .collect(),
+                    ),
+                    Err(_) => corrupted_hll_error(),
                 }
-            },
-            None => {
-                // Nonexistent key contributes nothing
             }
+            "DECODE" => {
+                if hll.encoding() != Encoding::Sparse {
+                    return RespValue::error("ERR HLL encoding is not sparse");
+                }
+                RespValue::simple_string(hll.sparse_opcodes().join(" "))
+            }
+            "ENCODING" => RespValue::simple_string(hll.encoding().name()),
+            "TODENSE" => match hll.to_dense() {
+                Ok(converted) => RespValue::integer(i64::from(converted)),
+                Err(_) => corrupted_hll_error(),
+            },
+            _ => RespValue::error(format!("ERR Unknown PFDEBUG subcommand '{sub}'")),
         }
+    });
+    match result {
+        Ok(Some(reply)) => reply,
+        Ok(None) => RespValue::error("ERR The specified key does not exist"),
+        Err(e) => e,
     }
-
-    // Store the merged result at destkey
-    db.set(destkey, Entry::new(RedisValue::HyperLogLog(merged)));
-
-    RespValue::ok()
 }
@@ -1,22 +1,43 @@
-use crate::command::{arg_to_i64, arg_to_string, wrong_arg_count};
-use crate::config::SharedConfig;
+use crate::command::{arg_to_i64, arg_to_string, parse_cursor, parse_scan_count, wrong_arg_count};
 use crate::connection::ClientState;
 use crate::resp::RespValue;
-use crate::store::SharedStore;
 use crate::store::entry::{Entry, now_millis};
+use crate::store::{SharedStore, shard_id};
 
 pub async fn cmd_del(args: &[RespValue], store: &SharedStore, client: &ClientState) -> RespValue {
     if args.is_empty() {
         return wrong_arg_count("del");
     }
+    delete_keys(args, store, client, store.lazyfree.lazy_user_del()).await
+}
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+/// UNLINK: DEL that frees large values on the lazyfree thread.
+pub async fn cmd_unlink(
+    args: &[RespValue],
+    store: &SharedStore,
+    client: &ClientState,
+) -> RespValue {
+    if args.is_empty() {
+        return wrong_arg_count("unlink");
+    }
+    delete_keys(args, store, client, true).await
+}
+
+async fn delete_keys(
+    args: &[RespValue],
+    store: &SharedStore,
+    client: &ClientState,
+    lazy: bool,
+) -> RespValue {
+    let mut store = store
+        .lock_keys(client.db_index, args.iter().filter_map(arg_to_string))
+        .await;
+    let mut db = store.db(client.db_index);
     let mut count = 0i64;
 
     for arg in args {
         if let Some(key) = arg_to_string(arg)
-            && db.del(&key)
+            && db.delete(&key, lazy)
         {
             count += 1;
         }
@@ -34,7 +55,9 @@ pub async fn cmd_exists(
         return wrong_arg_count("exists");
     }
 
-    let mut store = store.write().await;
+    let store = store
+        .read_keys(client.db_index, args.iter().filter_map(arg_to_string))
+        .await;
     let db = store.db(client.db_index);
     let mut count = 0i64;
 
@@ -54,8 +77,10 @@ pub async fn cmd_touch(args: &[RespValue], store: &SharedStore, client: &ClientS
         return wrong_arg_count("touch");
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(client.db_index, args.iter().filter_map(arg_to_string))
+        .await;
+    let mut db = store.db(client.db_index);
     let mut count = 0i64;
 
     for arg in args {
@@ -171,8 +196,8 @@ pub async fn cmd_expire(
     }
 
     if seconds <= 0 && !gt {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
+        let mut store = store.lock_keys(client.db_index, [&key]).await;
+        let mut db = store.db(client.db_index);
         if !db.exists(&key) {
             return RespValue::integer(0);
         }
@@ -184,8 +209,8 @@ pub async fn cmd_expire(
     }
 
     let expires_at = now_millis() + (seconds as u64) * 1000;
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
     if !db.exists(&key) {
         return RespValue::integer(0);
     }
@@ -234,8 +259,8 @@ pub async fn cmd_pexpire(
     }
 
     if millis <= 0 && !gt {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
+        let mut store = store.lock_keys(client.db_index, [&key]).await;
+        let mut db = store.db(client.db_index);
         if !db.exists(&key) {
             return RespValue::integer(0);
         }
@@ -247,8 +272,8 @@ pub async fn cmd_pexpire(
     }
 
     let expires_at = now_millis() + millis as u64;
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
     if !db.exists(&key) {
         return RespValue::integer(0);
     }
@@ -286,8 +311,8 @@ pub async fn cmd_expireat(
 
     // Negative or past timestamp means delete the key immediately
     if timestamp < 0 {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
+        let mut store = store.lock_keys(client.db_index, [&key]).await;
+        let mut db = store.db(client.db_index);
         return RespValue::integer(if db.del(&key) { 1 } else { 0 });
     }
 
@@ -299,13 +324,13 @@ pub async fn cmd_expireat(
 
     if expires_at < now_millis() {
         // Past timestamp — delete the key
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
+        let mut store = store.lock_keys(client.db_index, [&key]).await;
+        let mut db = store.db(client.db_index);
         return RespValue::integer(if db.del(&key) { 1 } else { 0 });
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
     if !db.exists(&key) {
         return RespValue::integer(0);
     }
@@ -343,14 +368,14 @@ pub async fn cmd_pexpireat(
 
     // Negative or past timestamp means delete the key immediately
     if timestamp < 0 || (timestamp as u64) < now_millis() {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
+        let mut store = store.lock_keys(client.db_index, [&key]).await;
+        let mut db = store.db(client.db_index);
         return RespValue::integer(if db.del(&key) { 1 } else { 0 });
     }
 
     let ts = timestamp as u64;
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
     if !db.exists(&key) {
         return RespValue::integer(0);
     }
@@ -370,7 +395,7 @@ pub async fn cmd_ttl(args: &[RespValue], store: &SharedStore, client: &ClientSta
         None => return RespValue::integer(-2),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -388,7 +413,7 @@ pub async fn cmd_pttl(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::integer(-2),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -410,8 +435,8 @@ pub async fn cmd_expiretime(
         None => return RespValue::integer(-2),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match entry.expires_at {
@@ -435,8 +460,8 @@ pub async fn cmd_pexpiretime(
         None => return RespValue::integer(-2),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match entry.expires_at {
@@ -460,8 +485,8 @@ pub async fn cmd_persist(
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
     RespValue::integer(if db.persist(&key) { 1 } else { 0 })
 }
 
@@ -474,8 +499,8 @@ pub async fn cmd_type(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::SimpleString("none".to_string()),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.key_type(&key) {
         Some(t) => RespValue::SimpleString(t.to_string()),
@@ -500,8 +525,8 @@ pub async fn cmd_rename(
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&old, &new]).await;
+    let mut db = store.db(client.db_index);
 
     if db.rename(&old, &new) {
         RespValue::ok()
@@ -527,8 +552,8 @@ pub async fn cmd_renamenx(
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&old, &new]).await;
+    let mut db = store.db(client.db_index);
 
     if !db.exists(&old) {
         return RespValue::error("ERR no such key");
@@ -550,8 +575,8 @@ pub async fn cmd_keys(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::array(vec![]),
     };
 
-    let store = store.read().await;
-    let db = &store.databases[client.db_index];
+    let mut store = store.lock_db(client.db_index).await;
+    let db = store.db(client.db_index);
 
     let keys = db.keys(&pattern);
     let items: Vec<RespValue> = keys
@@ -565,9 +590,8 @@ pub async fn cmd_scan(args: &[RespValue], store: &SharedStore, client: &ClientSt
     if args.is_empty() {
         return wrong_arg_count("scan");
     }
-    let cursor = match arg_to_i64(&args[0]) {
-        Some(n) if n >= 0 => n as usize,
-        _ => return RespValue::error("ERR value is not an integer or out of range"),
+    let Some(cursor) = parse_cursor(&args[0]) else {
+        return RespValue::error("ERR invalid cursor");
     };
 
     let mut pattern = None;
@@ -589,9 +613,10 @@ pub async fn cmd_scan(args: &[RespValue], store: &SharedStore, client: &ClientSt
             }
             "COUNT" => {
                 i += 1;
-                if let Some(c) = arg_to_i64(args.get(i).unwrap_or(&RespValue::null_bulk_string())) {
-                    count = c as usize;
-                }
+                count = match parse_scan_count(args.get(i)) {
+                    Ok(c) => c,
+                    Err(e) => return e,
+                };
             }
             "TYPE" => {
                 i += 1;
@@ -602,8 +627,8 @@ pub async fn cmd_scan(args: &[RespValue], store: &SharedStore, client: &ClientSt
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_db(client.db_index).await;
+    let mut db = store.db(client.db_index);
 
     let (next_cursor, keys) =
         db.scan_with_type(cursor, pattern.as_deref(), count, type_filter.as_deref());
@@ -620,8 +645,8 @@ pub async fn cmd_scan(args: &[RespValue], store: &SharedStore, client: &ClientSt
 }
 
 pub async fn cmd_randomkey(store: &SharedStore, client: &ClientState) -> RespValue {
-    let store = store.read().await;
-    let db = &store.databases[client.db_index];
+    let mut store = store.lock_db(client.db_index).await;
+    let db = store.db(client.db_index);
 
     match db.random_key() {
         Some(key) => RespValue::bulk_string(key.into_bytes()),
@@ -632,7 +657,6 @@ pub async fn cmd_randomkey(store: &SharedStore, client: &ClientState) -> RespVal
 pub async fn cmd_object(
     args: &[RespValue],
     store: &SharedStore,
-    config: &SharedConfig,
     client: &ClientState,
 ) -> RespValue {
     if args.is_empty() {
@@ -654,9 +678,8 @@ pub async fn cmd_object(
                 None => return RespValue::null_bulk_string(),
             };
 
-            let cfg = config.read().await;
-            let mut store = store.write().await;
-            let db = store.db(client.db_index);
+            let mut store = store.lock_keys(client.db_index, [&key]).await;
+            let mut db = store.db(client.db_index);
 
             match db.get(&key) {
                 Some(entry) => {
@@ -670,69 +693,11 @@ pub async fn cmd_object(
                                 "raw"
                             }
                         }
-                        crate::types::RedisValue::List(l) => {
-                            if cfg.list_max_listpack_size > 0 {
-                                // Positive: max number of entries per listpack node
-                                if l.len() <= cfg.list_max_listpack_size as usize {
-                                    "listpack"
-                                } else {
-                                    "quicklist"
-                                }
-                            } else {
-                                // Negative: byte-size limit per node
-                                // -1=4KB, -2=8KB, -3=16KB, -4=32KB, -5=64KB
-                                let max_bytes = match cfg.list_max_listpack_size {
-                                    -1 => 4096,
-                                    -2 => 8192,
-                                    -3 => 16384,
-                                    -4 => 32768,
-                                    -5 => 65536,
-                                    _ => 8192, // default to 8KB
-                                };
-                                // Estimate serialized size: ~(11 + avg_entry_size) per entry
-                                let est_size: usize = l.iter().map(|e| e.len() + 11).sum();
-                                if est_size <= max_bytes {
-                                    "listpack"
-                                } else {
-                                    "quicklist"
-                                }
-                            }
-                        }
-                        crate::types::RedisValue::Hash(h) => {
-                            let max_entries = cfg.hash_max_listpack_entries as usize;
-                            let max_value = cfg.hash_max_listpack_value as usize;
-                            if h.len() <= max_entries && !h.has_long_entry(max_value) {
-                                "listpack"
-                            } else {
-                                "hashtable"
-                            }
-                        }
-                        crate::types::RedisValue::Set(s) => {
-                            let max_intset = cfg.set_max_intset_entries as usize;
-                            let max_listpack = cfg.set_max_listpack_entries as usize;
-                            let max_listpack_value = cfg.set_max_listpack_value as usize;
-                            if s.is_all_integers() && s.len() <= max_intset {
-                                "intset"
-                            } else if s.len() <= max_listpack
-                                && !s.has_long_entry(max_listpack_value)
-                            {
-                                "listpack"
-                            } else {
-                                "hashtable"
-                            }
-                        }
-                        crate::types::RedisValue::SortedSet(z) => {
-                            let max_entries = cfg.zset_max_listpack_entries as usize;
-                            let max_value = cfg.zset_max_listpack_value as usize;
-                            if z.len() <= max_entries && !z.has_long_entry(max_value) {
-                                "listpack"
-                            } else {
-                                "skiplist"
-                            }
-                        }
+                        crate::types::RedisValue::List(l) => l.encoding(),
+                        crate::types::RedisValue::Hash(h) => h.encoding(),
+                        crate::types::RedisValue::Set(s) => s.encoding(),
+                        crate::types::RedisValue::SortedSet(z) => z.encoding(),
                         crate::types::RedisValue::Stream(_) => "stream",
-                        crate::types::RedisValue::HyperLogLog(_) => "raw",
-                        crate::types::RedisValue::Geo(_) => "skiplist",
                     };
                     RespValue::bulk_string(encoding.as_bytes().to_vec())
                 }
@@ -747,8 +712,8 @@ pub async fn cmd_object(
                 Some(k) => k,
                 None => return RespValue::null_bulk_string(),
             };
-            let mut store = store.write().await;
-            let db = store.db(client.db_index);
+            let mut store = store.lock_keys(client.db_index, [&key]).await;
+            let mut db = store.db(client.db_index);
             if db.exists(&key) {
                 RespValue::integer(1)
             } else {
@@ -763,8 +728,8 @@ pub async fn cmd_object(
                 Some(k) => k,
                 None => return RespValue::null_bulk_string(),
             };
-            let store = store.read().await;
-            let db = &store.databases[client.db_index];
+            let mut store = store.lock_keys(client.db_index, [&key]).await;
+            let db = store.db(client.db_index);
             match db.get_entry(&key) {
                 Some(entry) => RespValue::integer(entry.idle_seconds() as i64),
                 None => RespValue::error("ERR no such key"),
@@ -778,8 +743,8 @@ pub async fn cmd_object(
                 Some(k) => k,
                 None => return RespValue::null_bulk_string(),
             };
-            let mut store = store.write().await;
-            let db = store.db(client.db_index);
+            let mut store = store.lock_keys(client.db_index, [&key]).await;
+            let mut db = store.db(client.db_index);
             if db.exists(&key) {
                 RespValue::integer(0)
             } else {
@@ -846,8 +811,8 @@ pub async fn cmd_dump(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::null_bulk_string(),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
     match db.get(&key) {
         Some(entry) => {
             // Format: [type_byte] [data...] [version_u16_le] [crc64_u64_le]
@@ -861,7 +826,7 @@ pub async fn cmd_dump(args: &[RespValue], store: &SharedStore, client: &ClientSt
                     data.push(1u8); // type: list
                     dump_encode_len(&mut data, l.len());
                     for item in l.iter() {
-                        dump_encode_bytes(&mut data, item);
+                        dump_encode_bytes(&mut data, &item);
                     }
                 }
                 crate::types::RedisValue::Set(s) => {
@@ -869,7 +834,7 @@ pub async fn cmd_dump(args: &[RespValue], store: &SharedStore, client: &ClientSt
                     let members = s.members();
                     dump_encode_len(&mut data, members.len());
                     for m in members {
-                        dump_encode_bytes(&mut data, m);
+                        dump_encode_bytes(&mut data, &m);
                     }
                 }
                 crate::types::RedisValue::SortedSet(z) => {
@@ -941,8 +906,8 @@ pub async fn cmd_restore(
         i += 1;
     }
 
-    let mut store_w = store.write().await;
-    let db = store_w.db(client.db_index);
+    let mut store_w = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store_w.db(client.db_index);
 
     if db.exists(&key) && !replace {
         return RespValue::error("BUSYKEY Target key name already exists.");
@@ -1096,33 +1061,30 @@ pub async fn cmd_sort(args: &[RespValue], store: &SharedStore, client: &ClientSt
                     return RespValue::error("ERR syntax error");
                 }
             }
-            "BY" => {
-                if i + 1 < args.len() {
-                    by_pattern = arg_to_string(&args[i + 1]);
-                    i += 1;
-                }
+            "BY" if i + 1 < args.len() => {
+                by_pattern = arg_to_string(&args[i + 1]);
+                i += 1;
             }
-            "GET" => {
-                if i + 1 < args.len() {
-                    if let Some(pat) = arg_to_string(&args[i + 1]) {
-                        get_patterns.push(pat);
-                    }
-                    i += 1;
+            "GET" if i + 1 < args.len() => {
+                if let Some(pat) = arg_to_string(&args[i + 1]) {
+                    get_patterns.push(pat);
                 }
+                i += 1;
             }
             _ => {}
         }
         i += 1;
     }
 
-    let mut store_lock = store.write().await;
-    let db = store_lock.db(client.db_index);
+    // BY and GET patterns can name any key in the database
+    let mut store_lock = store.lock_db(client.db_index).await;
+    let mut db = store_lock.db(client.db_index);
 
     // Get the elements to sort
     let mut elements: Vec<Vec<u8>> = match db.get(&key) {
         Some(entry) => match &entry.value {
-            crate::types::RedisValue::List(list) => list.iter().cloned().collect(),
-            crate::types::RedisValue::Set(set) => set.members().into_iter().cloned().collect(),
+            crate::types::RedisValue::List(list) => list.iter().map(|v| v.into_owned()).collect(),
+            crate::types::RedisValue::Set(set) => set.iter().map(|m| m.into_owned()).collect(),
             crate::types::RedisValue::SortedSet(zset) => {
                 zset.iter().map(|(m, _)| m.to_vec()).collect()
             }
@@ -1133,7 +1095,7 @@ pub async fn cmd_sort(args: &[RespValue], store: &SharedStore, client: &ClientSt
 
     // Helper: resolve a pattern like "weight_*" by replacing * with the element value
     let resolve_pattern =
-        |db: &mut crate::store::Database, pattern: &str, element: &[u8]| -> Option<Vec<u8>> {
+        |db: &mut crate::store::Database<'_>, pattern: &str, element: &[u8]| -> Option<Vec<u8>> {
             if pattern == "#" {
                 return Some(element.to_vec());
             }
@@ -1170,14 +1132,14 @@ pub async fn cmd_sort(args: &[RespValue], store: &SharedStore, client: &ClientSt
             // Sort by external key values
             if alpha {
                 elements.sort_by(|a, b| {
-                    let a_val = resolve_pattern(db, by_pat, a).unwrap_or_default();
-                    let b_val = resolve_pattern(db, by_pat, b).unwrap_or_default();
+                    let a_val = resolve_pattern(&mut db, by_pat, a).unwrap_or_default();
+                    let b_val = resolve_pattern(&mut db, by_pat, b).unwrap_or_default();
                     a_val.cmp(&b_val)
                 });
             } else {
                 elements.sort_by(|a, b| {
-                    let a_val = resolve_pattern(db, by_pat, a).unwrap_or_default();
-                    let b_val = resolve_pattern(db, by_pat, b).unwrap_or_default();
+                    let a_val = resolve_pattern(&mut db, by_pat, a).unwrap_or_default();
+                    let b_val = resolve_pattern(&mut db, by_pat, b).unwrap_or_default();
                     let a_num: f64 = String::from_utf8_lossy(&a_val).parse().unwrap_or(0.0);
                     let b_num: f64 = String::from_utf8_lossy(&b_val).parse().unwrap_or(0.0);
                     a_num
@@ -1222,7 +1184,7 @@ pub async fn cmd_sort(args: &[RespValue], store: &SharedStore, client: &ClientSt
             let mut items = Vec::new();
             for elem in &elements {
                 for pat in &get_patterns {
-                    items.push(resolve_pattern(db, pat, elem).unwrap_or_default());
+                    items.push(resolve_pattern(&mut db, pat, elem).unwrap_or_default());
                 }
             }
             items
@@ -1239,7 +1201,7 @@ pub async fn cmd_sort(args: &[RespValue], store: &SharedStore, client: &ClientSt
         let mut resp_items = Vec::new();
         for elem in &elements {
             for pat in &get_patterns {
-                match resolve_pattern(db, pat, elem) {
+                match resolve_pattern(&mut db, pat, elem) {
                     Some(val) => resp_items.push(RespValue::bulk_string(val)),
                     None => resp_items.push(RespValue::null_bulk_string()),
                 }
@@ -1300,24 +1262,26 @@ pub async fn cmd_copy(args: &[RespValue], store: &SharedStore, client: &ClientSt
 
     let dest_db_index = dest_db.unwrap_or(client.db_index);
 
-    let mut store = store.write().await;
-
     // Validate destination db index
-    if dest_db_index >= store.databases.len() {
+    if dest_db_index >= store.num_databases() {
         return RespValue::error("ERR DB index is out of range");
     }
 
-    // Read the source entry from the source db
-    let src_db = &mut store.databases[client.db_index];
+    let mut store = store
+        .lock_shards(vec![
+            shard_id(client.db_index, &source),
+            shard_id(dest_db_index, &destination),
+        ])
+        .await;
 
     // Check if source exists (with lazy expiration)
-    let source_entry = match src_db.get(&source) {
+    let source_entry = match store.db(client.db_index).get(&source) {
         Some(entry) => entry.clone(),
         None => return RespValue::integer(0),
     };
 
     // Now operate on the destination db
-    let dst_db = &mut store.databases[dest_db_index];
+    let mut dst_db = store.db(dest_db_index);
 
     // Check if destination exists
     if dst_db.exists(&destination) && !replace {
@@ -1347,24 +1311,28 @@ pub async fn cmd_move(args: &[RespValue], store: &SharedStore, client: &ClientSt
         return RespValue::error("ERR source and destination objects are the same");
     }
 
-    let mut store = store.write().await;
-
-    if dest_db_index >= store.databases.len() {
+    if dest_db_index >= store.num_databases() {
         return RespValue::error("ERR index out of range");
     }
 
-    let src_db = &mut store.databases[client.db_index];
-    let source_entry = match src_db.get(&key) {
+    let mut store = store
+        .lock_shards(vec![
+            shard_id(client.db_index, &key),
+            shard_id(dest_db_index, &key),
+        ])
+        .await;
+
+    let source_entry = match store.db(client.db_index).get(&key) {
         Some(entry) => entry.clone(),
         None => return RespValue::integer(0),
     };
 
-    let dst_db = &mut store.databases[dest_db_index];
+    let mut dst_db = store.db(dest_db_index);
     if dst_db.exists(&key) {
         return RespValue::integer(0);
     }
 
     dst_db.set(key.clone(), source_entry);
-    store.databases[client.db_index].del(&key);
+    store.db(client.db_index).del(&key);
     RespValue::integer(1)
 }
@@ -60,8 +60,8 @@ pub async fn cmd_lpushx(
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     // Only push if the key already exists and is a list
     match db.get_mut(&key) {
@@ -98,8 +98,8 @@ pub async fn cmd_rpushx(
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -135,10 +135,10 @@ pub async fn cmd_lpush(
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let list = match get_or_create_list(db, &key) {
+    let list = match get_or_create_list(&mut db, &key) {
         Ok(l) => l,
         Err(e) => return e,
     };
@@ -173,10 +173,10 @@ pub async fn cmd_rpush(
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let list = match get_or_create_list(db, &key) {
+    let list = match get_or_create_list(&mut db, &key) {
         Ok(l) => l,
         Err(e) => return e,
     };
@@ -215,8 +215,8 @@ pub async fn cmd_lpop(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -271,8 +271,8 @@ pub async fn cmd_rpop(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -318,7 +318,7 @@ pub async fn cmd_llen(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -351,7 +351,7 @@ pub async fn cmd_lrange(
         None => return RespValue::error("ERR value is not an integer or out of range"),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -387,7 +387,7 @@ pub async fn cmd_lindex(
         None => return RespValue::error("ERR value is not an integer or out of range"),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -419,8 +419,8 @@ pub async fn cmd_lset(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::error("ERR invalid value"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -462,8 +462,8 @@ pub async fn cmd_linsert(
         None => return RespValue::error("ERR invalid value"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -501,8 +501,8 @@ pub async fn cmd_lrem(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -530,8 +530,8 @@ pub async fn cmd_ltrim(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::error("ERR value is not an integer or out of range"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -562,8 +562,8 @@ pub async fn cmd_rpoplpush(
         None => return RespValue::null_bulk_string(),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&src, &dst]).await;
+    let mut db = store.db(client.db_index);
 
     // Pop from source
     let value = match db.get_mut(&src) {
@@ -580,7 +580,7 @@ pub async fn cmd_rpoplpush(
     };
 
     // Push to destination
-    let list = match get_or_create_list(db, &dst) {
+    let list = match get_or_create_list(&mut db, &dst) {
         Ok(l) => l,
         Err(e) => return e,
     };
@@ -618,8 +618,8 @@ pub async fn cmd_lmove(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::error("ERR syntax error"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&src, &dst]).await;
+    let mut db = store.db(client.db_index);
 
     let value = match db.get_mut(&src) {
         Some(entry) => match &mut entry.value {
@@ -638,7 +638,7 @@ pub async fn cmd_lmove(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::null_bulk_string(),
     };
 
-    let list = match get_or_create_list(db, &dst) {
+    let list = match get_or_create_list(&mut db, &dst) {
         Ok(l) => l,
         Err(e) => return e,
     };
@@ -692,8 +692,8 @@ pub async fn cmd_lmpop(args: &[RespValue], store: &SharedStore, client: &ClientS
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, &keys).await;
+    let mut db = store.db(client.db_index);
 
     for key in &keys {
         let list_len = match db.get(key) {
@@ -767,13 +767,18 @@ pub async fn cmd_blpop(
 
     // Try immediate pop first
     {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
-        if let Some(result) = try_lpop_from_keys(db, &keys) {
+        let mut store = store.lock_keys(client.db_index, &keys).await;
+        let mut db = store.db(client.db_index);
+        if let Some(result) = try_lpop_from_keys(&mut db, &keys) {
             return result;
         }
     }
 
+    // EXEC doesn't block: time out right away
+    if client.deny_blocking {
+        return RespValue::null_array();
+    }
+
     // Register a single shared Notify for all keys
     let notify = {
         let mut watcher = key_watcher.write().await;
@@ -797,9 +802,9 @@ pub async fn cmd_blpop(
     }
 
     // Try to pop after being notified
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-    try_lpop_from_keys(db, &keys).unwrap_or_else(RespValue::null_array)
+    let mut store = store.lock_keys(client.db_index, &keys).await;
+    let mut db = store.db(client.db_index);
+    try_lpop_from_keys(&mut db, &keys).unwrap_or_else(RespValue::null_array)
 }
 
 pub async fn cmd_brpop(
@@ -825,13 +830,18 @@ pub async fn cmd_brpop(
 
     // Try immediate pop first
     {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
-        if let Some(result) = try_rpop_from_keys(db, &keys) {
+        let mut store = store.lock_keys(client.db_index, &keys).await;
+        let mut db = store.db(client.db_index);
+        if let Some(result) = try_rpop_from_keys(&mut db, &keys) {
             return result;
         }
     }
 
+    // EXEC doesn't block: time out right away
+    if client.deny_blocking {
+        return RespValue::null_array();
+    }
+
     // Register a single shared Notify for all keys
     let notify = {
         let mut watcher = key_watcher.write().await;
@@ -852,13 +862,13 @@ pub async fn cmd_brpop(
         return RespValue::null_array();
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-    try_rpop_from_keys(db, &keys).unwrap_or_else(RespValue::null_array)
+    let mut store = store.lock_keys(client.db_index, &keys).await;
+    let mut db = store.db(client.db_index);
+    try_rpop_from_keys(&mut db, &keys).unwrap_or_else(RespValue::null_array)
 }
 
 /// Try to LPOP from the first non-empty list key. Returns None if all empty.
-fn try_lpop_from_keys(db: &mut crate::store::Database, keys: &[String]) -> Option<RespValue> {
+fn try_lpop_from_keys(db: &mut crate::store::Database<'_>, keys: &[String]) -> Option<RespValue> {
     for key in keys {
         match db.get_mut(key) {
             Some(entry) => match &mut entry.value {
@@ -882,7 +892,7 @@ fn try_lpop_from_keys(db: &mut crate::store::Database, keys: &[String]) -> Optio
 }
 
 /// Try to RPOP from the first non-empty list key. Returns None if all empty.
-fn try_rpop_from_keys(db: &mut crate::store::Database, keys: &[String]) -> Option<RespValue> {
+fn try_rpop_from_keys(db: &mut crate::store::Database<'_>, keys: &[String]) -> Option<RespValue> {
     for key in keys {
         match db.get_mut(key) {
             Some(entry) => match &mut entry.value {
@@ -963,8 +973,8 @@ pub async fn cmd_lpos(args: &[RespValue], store: &SharedStore, client: &ClientSt
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -1036,14 +1046,19 @@ pub async fn cmd_blmove(
 
     // Try immediate move first
     {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
-        if let Some(result) = try_lmove(db, &src, &dst, &wherefrom, &whereto) {
+        let mut store = store.lock_keys(client.db_index, [&src, &dst]).await;
+        let mut db = store.db(client.db_index);
+        if let Some(result) = try_lmove(&mut db, &src, &dst, &wherefrom, &whereto) {
             return result;
         }
     }
 
     let keys = vec![src.clone()];
+    // EXEC doesn't block: time out right away
+    if client.deny_blocking {
+        return RespValue::null_bulk_string();
+    }
+
     let notify = {
         let mut watcher = key_watcher.write().await;
         watcher.register_many(&keys)
@@ -1063,9 +1078,9 @@ pub async fn cmd_blmove(
         return RespValue::null_bulk_string();
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-    try_lmove(db, &src, &dst, &wherefrom, &whereto).unwrap_or_else(RespValue::null_bulk_string)
+    let mut store = store.lock_keys(client.db_index, &keys).await;
+    let mut db = store.db(client.db_index);
+    try_lmove(&mut db, &src, &dst, &wherefrom, &whereto).unwrap_or_else(RespValue::null_bulk_string)
 }
 
 pub async fn cmd_brpoplpush(
@@ -1094,14 +1109,19 @@ pub async fn cmd_brpoplpush(
 
     // Try immediate move first
     {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
-        if let Some(result) = try_lmove(db, &src, &dst, "RIGHT", "LEFT") {
+        let mut store = store.lock_keys(client.db_index, [&src, &dst]).await;
+        let mut db = store.db(client.db_index);
+        if let Some(result) = try_lmove(&mut db, &src, &dst, "RIGHT", "LEFT") {
             return result;
         }
     }
 
     let keys = vec![src.clone()];
+    // EXEC doesn't block: time out right away
+    if client.deny_blocking {
+        return RespValue::null_bulk_string();
+    }
+
     let notify = {
         let mut watcher = key_watcher.write().await;
         watcher.register_many(&keys)
@@ -1121,9 +1141,9 @@ pub async fn cmd_brpoplpush(
         return RespValue::null_bulk_string();
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-    try_lmove(db, &src, &dst, "RIGHT", "LEFT").unwrap_or_else(RespValue::null_bulk_string)
+    let mut store = store.lock_keys(client.db_index, &keys).await;
+    let mut db = store.db(client.db_index);
+    try_lmove(&mut db, &src, &dst, "RIGHT", "LEFT").unwrap_or_else(RespValue::null_bulk_string)
 }
 
 pub async fn cmd_blmpop(
@@ -1180,13 +1200,18 @@ pub async fn cmd_blmpop(
 
     // Try immediate pop first
     {
-        let mut store_w = store.write().await;
-        let db = store_w.db(client.db_index);
-        if let Some(result) = try_lmpop(db, &keys, &direction, count) {
+        let mut store_w = store.lock_keys(client.db_index, &keys).await;
+        let mut db = store_w.db(client.db_index);
+        if let Some(result) = try_lmpop(&mut db, &keys, &direction, count) {
             return result;
         }
     }
 
+    // EXEC doesn't block: time out right away
+    if client.deny_blocking {
+        return RespValue::null_array();
+    }
+
     let notify = {
         let mut watcher = key_watcher.write().await;
         watcher.register_many(&keys)
@@ -1206,14 +1231,14 @@ pub async fn cmd_blmpop(
         return RespValue::null_array();
     }
 
-    let mut store_w = store.write().await;
-    let db = store_w.db(client.db_index);
-    try_lmpop(db, &keys, &direction, count).unwrap_or_else(RespValue::null_array)
+    let mut store_w = store.lock_keys(client.db_index, &keys).await;
+    let mut db = store_w.db(client.db_index);
+    try_lmpop(&mut db, &keys, &direction, count).unwrap_or_else(RespValue::null_array)
 }
 
 /// Try to LMOVE from src to dst. Returns None if source is empty/missing.
 fn try_lmove(
-    db: &mut crate::store::Database,
+    db: &mut crate::store::Database<'_>,
     src: &str,
     dst: &str,
     wherefrom: &str,
@@ -1261,7 +1286,7 @@ fn try_lmove(
 
 /// Try to LMPOP from the first non-empty list key. Returns None if all empty.
 fn try_lmpop(
-    db: &mut crate::store::Database,
+    db: &mut crate::store::Database<'_>,
     keys: &[String],
     direction: &str,
     count: usize,
@@ -16,12 +16,14 @@ pub mod transaction;
 use crate::config::SharedConfig;
 use crate::connection::ClientState;
 use crate::keywatcher::SharedKeyWatcher;
+use crate::latency::SharedLatencyMonitor;
 use crate::pubsub::SharedPubSub;
 use crate::replication::SharedReplicationState;
 use crate::resp::RespValue;
 use crate::scripting::ScriptCache;
 use crate::slowlog::{SharedLastSaveTime, SharedSlowLog};
 use crate::store::SharedStore;
+use bytes::Bytes;
 use tokio::sync::mpsc;
 
 /// Dispatch a parsed command to the appropriate handler.
@@ -39,6 +41,7 @@ pub async fn dispatch(
     repl_state: &SharedReplicationState,
     last_save_time: &SharedLastSaveTime,
     slowlog: &SharedSlowLog,
+    latency: &SharedLatencyMonitor,
 ) -> RespValue {
     // If in MULTI mode and this isn't EXEC/DISCARD/MULTI/WATCH/UNWATCH, queue the command
     if client.in_multi && !matches!(cmd_name, "EXEC" | "DISCARD" | "MULTI" | "WATCH" | "UNWATCH") {
@@ -70,13 +73,15 @@ pub async fn dispatch(
         "SELECT" => server_cmd::cmd_select(args, client, config).await,
         "AUTH" => server_cmd::cmd_auth(args, client, config).await,
         "DBSIZE" => server_cmd::cmd_dbsize(store, client).await,
-        "FLUSHDB" => server_cmd::cmd_flushdb(store, client).await,
-        "FLUSHALL" => server_cmd::cmd_flushall(store).await,
+        "FLUSHDB" => server_cmd::cmd_flushdb(args, store, client).await,
+        "FLUSHALL" => server_cmd::cmd_flushall(args, store).await,
         "SWAPDB" => server_cmd::cmd_swapdb(args, store, config).await,
 
         // Server
-        "INFO" => server_cmd::cmd_info(args, store, config, repl_state, last_save_time).await,
-        "CONFIG" => server_cmd::cmd_config(args, config, store).await,
+        "INFO" => {
+            server_cmd::cmd_info(args, store, config, repl_state, last_save_time, latency).await
+        }
+        "CONFIG" => server_cmd::cmd_config(args, config, store, latency).await,
         "TIME" => server_cmd::cmd_time(),
         "COMMAND" => server_cmd::cmd_command(args),
         "CLIENT" => server_cmd::cmd_client(args, client),
@@ -109,7 +114,7 @@ pub async fn dispatch(
 
         // Keys
         "DEL" => key::cmd_del(args, store, client).await,
-        "UNLINK" => key::cmd_del(args, store, client).await,
+        "UNLINK" => key::cmd_unlink(args, store, client).await,
         "EXISTS" => key::cmd_exists(args, store, client).await,
         "EXPIRE" => key::cmd_expire(args, store, client).await,
         "PEXPIRE" => key::cmd_pexpire(args, store, client).await,
@@ -126,7 +131,7 @@ pub async fn dispatch(
         "KEYS" => key::cmd_keys(args, store, client).await,
         "SCAN" => key::cmd_scan(args, store, client).await,
         "RANDOMKEY" => key::cmd_randomkey(store, client).await,
-        "OBJECT" => key::cmd_object(args, store, config, client).await,
+        "OBJECT" => key::cmd_object(args, store, client).await,
         "DUMP" => key::cmd_dump(args, store, client).await,
         "RESTORE" => key::cmd_restore(args, store, client).await,
         "SORT" | "SORT_RO" => key::cmd_sort(args, store, client).await,
@@ -263,13 +268,13 @@ pub async fn dispatch(
         "PFMERGE" => hyperloglog::cmd_pfmerge(args, store, client).await,
 
         // Geo
-        "GEOADD" => geo::cmd_geoadd(args, store, client).await,
+        "GEOADD" => geo::cmd_geoadd(args, store, client, key_watcher).await,
         "GEODIST" => geo::cmd_geodist(args, store, client).await,
         "GEOPOS" => geo::cmd_geopos(args, store, client).await,
         "GEOSEARCH" => geo::cmd_geosearch(args, store, client).await,
         "GEORADIUS" => geo::cmd_georadius(args, store, client).await,
         "GEORADIUSBYMEMBER" => geo::cmd_georadiusbymember(args, store, client).await,
-        "GEOSEARCHSTORE" => geo::cmd_geosearchstore(args, store, client).await,
+        "GEOSEARCHSTORE" => geo::cmd_geosearchstore(args, store, client, key_watcher).await,
         "GEOHASH" => geo::cmd_geohash(args, store, client).await,
         "GEOMEMBERS" => geo::cmd_geomembers(args, store, client).await,
 
@@ -287,6 +292,7 @@ pub async fn dispatch(
                 repl_state,
                 last_save_time,
                 slowlog,
+                latency,
             )
             .await
         }
@@ -303,9 +309,9 @@ pub async fn dispatch(
         "PUBSUB" => pubsub::cmd_pubsub(args, pubsub).await,
 
         // Persistence
-        "SAVE" => server_cmd::cmd_save(store, config, last_save_time).await,
-        "BGSAVE" => server_cmd::cmd_bgsave(store, config, last_save_time).await,
-        "BGREWRITEAOF" => server_cmd::cmd_bgrewriteaof(store, config).await,
+        "SAVE" => server_cmd::cmd_save(store, config, last_save_time, latency).await,
+        "BGSAVE" => server_cmd::cmd_bgsave(store, config, last_save_time, latency).await,
+        "BGREWRITEAOF" => server_cmd::cmd_bgrewriteaof(store, config, latency).await,
         "LASTSAVE" => server_cmd::cmd_lastsave(last_save_time),
 
         // Scripting
@@ -340,22 +346,6 @@ pub async fn dispatch(
         // Stubs for compatibility
         "FUNCTION" => RespValue::ok(),
         "HELLO" => server_cmd::cmd_hello(args),
-        "WAIT" => {
-            // WAIT numreplicas timeout
-            if args.len() != 2 {
-                return wrong_arg_count("wait");
-            }
-            match arg_to_i64(&args[0]) {
-                Some(n) if n >= 0 => {}
-                _ => return RespValue::error("ERR value is not an integer or out of range"),
-            }
-            match arg_to_i64(&args[1]) {
-                Some(t) if t >= 0 => {}
-                _ => return RespValue::error("ERR timeout is not an integer or out of range"),
-            }
-            // No replication support: 0 replicas acknowledged
-            RespValue::integer(0)
-        }
         "FCALL" | "FCALL_RO" => {
             RespValue::error("ERR No matching script. Please use FUNCTION LOAD.")
         }
@@ -372,8 +362,8 @@ pub async fn dispatch(
                 Some(k) => k,
                 None => return RespValue::null_bulk_string(),
             };
-            let store_r = store.read().await;
-            let db = &store_r.databases[client.db_index];
+            let mut store_r = store.lock_keys(client.db_index, [&key]).await;
+            let db = store_r.db(client.db_index);
             match db.get_entry(&key) {
                 Some(entry) => {
                     // Type check: DIGEST only works on strings
@@ -399,8 +389,8 @@ pub async fn dispatch(
                 None => return RespValue::integer(0),
             };
 
-            let mut store_w = store.write().await;
-            let db = store_w.db(client.db_index);
+            let mut store_w = store.lock_keys(client.db_index, [&key]).await;
+            let mut db = store_w.db(client.db_index);
 
             // Unconditional delete (no condition args)
             if args.len() == 1 {
@@ -462,83 +452,12 @@ pub async fn dispatch(
             }
         }
         "PFSELFTEST" => RespValue::ok(),
-        "PFDEBUG" => {
-            // PFDEBUG encoding key -> return "sparse" or "dense"
-            if args
-                .first()
-                .and_then(arg_to_string)
-                .map(|s| s.to_uppercase())
-                .as_deref()
-                == Some("ENCODING")
-            {
-                RespValue::bulk_string(b"sparse".to_vec())
-            } else {
-                RespValue::ok()
-            }
-        }
+        "PFDEBUG" => hyperloglog::cmd_pfdebug(args, store, client).await,
         "SUBSTR" => string::cmd_getrange(args, store, client).await,
-        "MEMORY" => {
-            let sub = args
-                .first()
-                .and_then(|a| a.to_string_lossy())
-                .map(|s| s.to_uppercase())
-                .unwrap_or_default();
-            match sub.as_str() {
-                "USAGE" => {
-                    if let Some(key_val) = args.get(1).and_then(|a| a.to_string_lossy()) {
-                        let store_r = store.read().await;
-                        let db = &store_r.databases[client.db_index];
-                        match db.get_entry(&key_val) {
-                            Some(entry) => {
-                                // Match Redis's memory accounting:
-                                // dictEntry (24) + key SDS header+data (key_len+1) + robj (16)
-                                let key_overhead = 24 + key_val.len() + 1 + 16;
-                                let value_size = match &entry.value {
-                                    crate::types::RedisValue::String(s) => {
-                                        if s.as_i64().is_some() {
-                                            0 // Integer stored in robj->ptr
-                                        } else if s.len() <= 44 {
-                                            s.len() + 1 // Embstr: data embedded in robj allocation
-                                        } else {
-                                            8 + s.len() + 1 // Raw: SDS header + data + null
-                                        }
-                                    }
-                                    crate::types::RedisValue::List(l) => {
-                                        let elem_bytes: usize =
-                                            l.iter().map(|v| v.len() + 11).sum();
-                                        128 + elem_bytes
-                                    }
-                                    crate::types::RedisValue::Hash(h) => {
-                                        let field_bytes: usize =
-                                            h.iter().map(|(k, v)| k.len() + v.len() + 24).sum();
-                                        64 + field_bytes
-                                    }
-                                    crate::types::RedisValue::Set(s) => {
-                                        let mem: usize = s.iter().map(|m| m.len() + 16).sum();
-                                        64 + mem
-                                    }
-                                    crate::types::RedisValue::SortedSet(z) => {
-                                        let mem: usize = z.iter().map(|(m, _)| m.len() + 40).sum();
-                                        128 + mem
-                                    }
-                                    _ => 64,
-                                };
-                                RespValue::integer((key_overhead + value_size) as i64)
-                            }
-                            None => RespValue::null_bulk_string(),
-                        }
-                    } else {
-                        RespValue::error("ERR wrong number of arguments for 'memory|usage' command")
-                    }
-                }
-                "DOCTOR" | "MALLOC-STATS" | "PURGE" | "STATS" | "HELP" => RespValue::ok(),
-                _ => RespValue::error("ERR unknown MEMORY subcommand"),
-            }
-        }
+        "MEMORY" => server_cmd::cmd_memory(args, store, config, repl_state, client).await,
         "SLOWLOG" => server_cmd::cmd_slowlog(args, slowlog, config).await,
-        "LATENCY" => RespValue::array(vec![]),
+        "LATENCY" => server_cmd::cmd_latency(args, latency, config).await,
         "CLUSTER" => RespValue::error("ERR This instance has cluster support disabled"),
-        "WAITAOF" => RespValue::array(vec![RespValue::integer(0), RespValue::integer(0)]),
         "ACL" => {
             let sub = args
                 .first()
@@ -569,30 +488,15 @@ pub async fn dispatch(
                 _ => RespValue::ok(),
             }
         }
-        "REPLCONF" => crate::replication::master::handle_replconf(args),
-        "REPLICAOF" | "SLAVEOF" => {
-            // REPLICAOF host port | REPLICAOF NO ONE
-            if args.len() != 2 {
-                return wrong_arg_count("replicaof");
-            }
-            let host = match arg_to_string(&args[0]) {
-                Some(h) => h,
-                None => return RespValue::error("ERR invalid host"),
-            };
-            let port_str = match arg_to_string(&args[1]) {
-                Some(p) => p,
-                None => return RespValue::error("ERR invalid port"),
-            };
-
-            if host.eq_ignore_ascii_case("NO") && port_str.eq_ignore_ascii_case("ONE") {
-                // Promote to master - just return OK for now
-                // Full implementation would cancel sync loop and generate new replid
-                RespValue::ok()
-            } else {
-                // Set as replica - just return OK
-                // The actual sync loop is started at server startup time via config
-                RespValue::ok()
-            }
+        "REPLCONF" => crate::replication::master::handle_replconf(args, client),
+        "ROLE" => server_cmd::cmd_role(repl_state).await,
+        "WAIT" => server_cmd::cmd_wait(args, client, repl_state).await,
+        "WAITAOF" => server_cmd::cmd_waitaof(args, client, config, repl_state).await,
+        "REPLICAOF" | "SLAVEOF" | "FAILOVER" => {
+            // REPLICAOF and FAILOVER are handled in server::process_command, which owns the
+            // handles needed to start the sync task. Reaching here means it was
+            // queued in a transaction.
+            RespValue::error("ERR Command not allowed inside a transaction")
         }
         "SYNC" | "PSYNC" => {
             // PSYNC is handled at the connection level in handle_connection.
@@ -616,6 +520,49 @@ pub async fn dispatch(
     }
 }
 
+/// Account an executed call in INFO commandstats and errorstats. Unknown
+/// commands only count towards their error reply. Arity and unknown
+/// subcommand errors go through the reject path, since Redis refuses those
+/// before running the command.
+pub fn record_call(
+    latency: &SharedLatencyMonitor,
+    cmd_name: &str,
+    elapsed: std::time::Duration,
+    response: &RespValue,
+) {
+    let error = match response {
+        RespValue::Error(e) if is_rejection(e) => {
+            reject_call(latency, cmd_name, e);
+            return;
+        }
+        RespValue::Error(e) => Some(e.as_str()),
+        _ => None,
+    };
+    let mut lm = latency.lock().unwrap();
+    if is_known_command(cmd_name) {
+        lm.record_command(&cmd_name.to_lowercase(), elapsed, error);
+    } else if let Some(error) = error {
+        lm.record_error(error);
+    }
+}
+
+/// Account a call refused before execution and return its error reply.
+pub fn reject_call(latency: &SharedLatencyMonitor, cmd_name: &str, error: &str) -> RespValue {
+    let mut lm = latency.lock().unwrap();
+    if is_known_command(cmd_name) {
+        lm.record_rejected(&cmd_name.to_lowercase(), error);
+    } else {
+        lm.record_error(error);
+    }
+    RespValue::error(error)
+}
+
+/// Errors Redis raises while looking the command up rather than running it.
+fn is_rejection(error: &str) -> bool {
+    error.starts_with("ERR wrong number of arguments")
+        || error.starts_with("ERR unknown subcommand")
+}
+
 /// Check if a command name is known (for MULTI queueing error detection).
 fn is_known_command(cmd: &str) -> bool {
     matches!(
@@ -841,6 +788,8 @@ fn is_known_command(cmd: &str) -> bool {
             | "ACL"
             | "REPLICAOF"
             | "SLAVEOF"
+            | "FAILOVER"
+            | "ROLE"
             | "SYNC"
             | "PSYNC"
             | "REPLCONF"
@@ -850,6 +799,71 @@ fn is_known_command(cmd: &str) -> bool {
     )
 }
 
+/// Keys `cmd` touches in the client's current database, so EXEC can lock
+/// their shards up front. `None` means the command needs the whole keyspace:
+/// it works on entire databases, switches database, or its keys can't be
+/// told from the arguments.
+pub fn command_keys(cmd: &str, args: &[RespValue]) -> Option<Vec<String>> {
+    let all = |args: &[RespValue]| args.iter().map(arg_to_string).collect::<Option<Vec<_>>>();
+    // `numkeys key [key ...]` starting at args[at]
+    let numkeys = |at: usize| {
+        let n = usize::try_from(arg_to_i64(args.get(at)?)?).ok()?;
+        all(args.get(at + 1..at + 1 + n)?)
+    };
+    match cmd {
+        "PING" | "ECHO" | "TIME" | "PUBLISH" | "LASTSAVE" => Some(vec![]),
+
+        "GET" | "SET" | "GETSET" | "APPEND" | "STRLEN" | "INCR" | "DECR" | "INCRBY" | "DECRBY"
+        | "INCRBYFLOAT" | "SETNX" | "SETEX" | "PSETEX" | "GETRANGE" | "SETRANGE" | "GETDEL"
+        | "GETEX" | "SUBSTR" | "DIGEST" | "DELEX" | "EXPIRE" | "PEXPIRE" | "EXPIREAT"
+        | "PEXPIREAT" | "TTL" | "PTTL" | "EXPIRETIME" | "PEXPIRETIME" | "PERSIST" | "TYPE"
+        | "DUMP" | "RESTORE" | "LPUSH" | "RPUSH" | "LPUSHX" | "RPUSHX" | "LPOP" | "RPOP"
+        | "LLEN" | "LRANGE" | "LINDEX" | "LSET" | "LINSERT" | "LREM" | "LTRIM" | "LPOS"
+        | "HSET" | "HGET" | "HDEL" | "HEXISTS" | "HLEN" | "HKEYS" | "HVALS" | "HGETALL"
+        | "HMSET" | "HMGET" | "HINCRBY" | "HINCRBYFLOAT" | "HSETNX" | "HRANDFIELD" | "HSCAN"
+        | "HSTRLEN" | "HGETDEL" | "SADD" | "SREM" | "SISMEMBER" | "SMISMEMBER" | "SMEMBERS"
+        | "SCARD" | "SPOP" | "SRANDMEMBER" | "SSCAN" | "ZADD" | "ZREM" | "ZSCORE" | "ZRANK"
+        | "ZREVRANK" | "ZCARD" | "ZCOUNT" | "ZRANGE" | "ZREVRANGE" | "ZRANGEBYSCORE"
+        | "ZREVRANGEBYSCORE" | "ZRANGEBYLEX" | "ZREVRANGEBYLEX" | "ZINCRBY" | "ZRANDMEMBER"
+        | "ZSCAN" | "ZPOPMIN" | "ZPOPMAX" | "ZMSCORE" | "ZLEXCOUNT" | "ZREMRANGEBYSCORE"
+        | "ZREMRANGEBYLEX" | "ZREMRANGEBYRANK" | "XADD" | "XLEN" | "XRANGE" | "XREVRANGE"
+        | "XTRIM" | "XACK" | "XCLAIM" | "XAUTOCLAIM" | "XPENDING" | "XDEL" | "SETBIT"
+        | "GETBIT" | "BITCOUNT" | "BITPOS" | "BITFIELD" | "BITFIELD_RO" | "PFADD" | "GEOADD"
+        | "GEODIST" | "GEOPOS" | "GEOSEARCH" | "GEOHASH" | "GEOMEMBERS" => all(args.get(..1)?),
+
+        "OBJECT" | "XGROUP" | "XINFO" | "PFDEBUG" => all(args.get(1..2)?),
+        "RENAME" | "RENAMENX" | "RPOPLPUSH" | "BRPOPLPUSH" | "LMOVE" | "BLMOVE" | "SMOVE"
+        | "LCS" | "GEOSEARCHSTORE" => all(args.get(..2)?),
+        "DEL" | "UNLINK" | "EXISTS" | "TOUCH" | "MGET" | "SUNION" | "SINTER" | "SDIFF"
+        | "SUNIONSTORE" | "SINTERSTORE" | "SDIFFSTORE" | "PFCOUNT" | "PFMERGE" => all(args),
+        "BLPOP" | "BRPOP" | "BZPOPMIN" | "BZPOPMAX" => all(args.split_last()?.1),
+        "BITOP" => all(args.get(1..)?),
+        "MSET" | "MSETNX" => all(&args.iter().step_by(2).cloned().collect::<Vec<_>>()),
+        "MSETEX" => {
+            let n = usize::try_from(arg_to_i64(args.first()?)?).ok()?;
+            let pairs = args.get(1..1 + 2 * n)?;
+            all(&pairs.iter().step_by(2).cloned().collect::<Vec<_>>())
+        }
+        "LMPOP" | "SINTERCARD" | "ZINTERCARD" | "ZMPOP" | "ZUNION" | "ZINTER" | "ZDIFF" => {
+            numkeys(0)
+        }
+        "BLMPOP" | "BZMPOP" => numkeys(1),
+        "ZUNIONSTORE" | "ZINTERSTORE" | "ZDIFFSTORE" => {
+            let mut keys = numkeys(1)?;
+            keys.push(arg_to_string(args.first()?)?);
+            Some(keys)
+        }
+        "XREAD" | "XREADGROUP" => {
+            let streams = args.iter().position(|a| {
+                arg_to_string(a).is_some_and(|a| a.eq_ignore_ascii_case("STREAMS"))
+            })?;
+            let rest = &args[streams + 1..];
+            all(&rest[..rest.len() / 2])
+        }
+        _ => None,
+    }
+}
+
 /// Compute a 64-bit digest hash for the DIGEST command and IFDEQ/IFDNE conditions.
 /// Returns a 16-character lowercase hex string.
 pub fn digest_hash(data: &[u8]) -> String {
@@ -870,6 +884,14 @@ pub fn arg_to_bytes(arg: &RespValue) -> Option<&[u8]> {
     arg.as_str()
 }
 
+/// Extract a bulk string argument as shared bytes, to keep without copying.
+pub fn arg_to_shared_bytes(arg: &RespValue) -> Option<Bytes> {
+    match arg {
+        RespValue::BulkString(Some(data)) => Some(data.clone()),
+        _ => arg.as_str().map(Bytes::copy_from_slice),
+    }
+}
+
 /// Extract a UTF-8 string from a RespValue argument.
 pub fn arg_to_string(arg: &RespValue) -> Option<String> {
     arg.to_string_lossy()
@@ -881,6 +903,22 @@ pub fn arg_to_i64(arg: &RespValue) -> Option<i64> {
     s.parse().ok()
 }
 
+/// Extract a SCAN-family cursor, an unsigned 64-bit integer.
+pub fn parse_cursor(arg: &RespValue) -> Option<u64> {
+    arg.to_string_lossy()?.parse().ok()
+}
+
+/// Parse the value of a SCAN-family COUNT option, which must be positive.
+pub fn parse_scan_count(arg: Option<&RespValue>) -> Result<usize, RespValue> {
+    match arg.and_then(arg_to_i64) {
+        Some(n) if n > 0 => Ok(n as usize),
+        Some(_) => Err(RespValue::error("ERR syntax error")),
+        None => Err(RespValue::error(
+            "ERR value is not an integer or out of range",
+        )),
+    }
+}
+
 /// Extract an f64 from a RespValue argument.
 /// Supports standard floats, inf/-inf, and C-style hex floats (0x1.0p+0).
 pub fn arg_to_f64(arg: &RespValue) -> Option<f64> {
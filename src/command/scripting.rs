@@ -54,8 +54,9 @@ pub async fn cmd_eval(
         .filter_map(arg_to_string)
         .collect();
 
-    // Acquire write lock and run script synchronously
-    let mut store_guard = store.write().await;
+    // Scripts may use keys they don't declare, so they run with the whole
+    // keyspace locked
+    let mut store_guard = store.lock_all().await;
     scripting::eval_script(&script, &keys, &argv, &mut store_guard, client.db_index)
 }
 
@@ -106,8 +107,9 @@ pub async fn cmd_evalsha(
         .filter_map(arg_to_string)
         .collect();
 
-    // Acquire write lock and run script synchronously
-    let mut store_guard = store.write().await;
+    // Scripts may use keys they don't declare, so they run with the whole
+    // keyspace locked
+    let mut store_guard = store.lock_all().await;
     scripting::eval_script(&script, &keys, &argv, &mut store_guard, client.db_index)
 }
 
@@ -1,11 +1,12 @@
 use crate::command::{arg_to_i64, arg_to_string, wrong_arg_count};
 use crate::config::SharedConfig;
 use crate::connection::ClientState;
+use crate::latency::{self, SharedLatencyMonitor};
 use crate::persistence;
 use crate::replication::{ReplicationRole, SharedReplicationState};
 use crate::resp::RespValue;
 use crate::slowlog::{SharedLastSaveTime, SharedSlowLog};
-use crate::store::SharedStore;
+use crate::store::{DataStore, SharedStore};
 use std::sync::atomic::Ordering;
 
 pub fn cmd_ping(args: &[RespValue]) -> RespValue {
@@ -110,20 +111,46 @@ pub async fn cmd_auth(
 }
 
 pub async fn cmd_dbsize(store: &SharedStore, client: &ClientState) -> RespValue {
-    let mut store = store.write().await;
+    let mut store = store.lock_db(client.db_index).await;
     let db = store.db(client.db_index);
     RespValue::integer(db.dbsize() as i64)
 }
 
-pub async fn cmd_flushdb(store: &SharedStore, client: &ClientState) -> RespValue {
-    let mut store = store.write().await;
-    store.db(client.db_index).flush();
+/// Parse the optional ASYNC|SYNC mode of FLUSHDB/FLUSHALL. Returns whether
+/// the data is freed on the lazyfree thread.
+fn parse_flush_mode(args: &[RespValue]) -> Result<bool, RespValue> {
+    match args {
+        [] => Ok(false),
+        [mode] => match arg_to_string(mode).map(|m| m.to_uppercase()).as_deref() {
+            Some("ASYNC") => Ok(true),
+            Some("SYNC") => Ok(false),
+            _ => Err(RespValue::error("ERR syntax error")),
+        },
+        _ => Err(RespValue::error("ERR syntax error")),
+    }
+}
+
+pub async fn cmd_flushdb(
+    args: &[RespValue],
+    store: &SharedStore,
+    client: &ClientState,
+) -> RespValue {
+    let lazy = match parse_flush_mode(args) {
+        Ok(lazy) => lazy,
+        Err(e) => return e,
+    };
+    let mut store = store.lock_db(client.db_index).await;
+    store.db(client.db_index).flush(lazy);
     RespValue::ok()
 }
 
-pub async fn cmd_flushall(store: &SharedStore) -> RespValue {
-    let mut store = store.write().await;
-    store.flush_all();
+pub async fn cmd_flushall(args: &[RespValue], store: &SharedStore) -> RespValue {
+    let lazy = match parse_flush_mode(args) {
+        Ok(lazy) => lazy,
+        Err(e) => return e,
+    };
+    let mut store = store.lock_all().await;
+    store.flush_all(lazy);
     RespValue::ok()
 }
 
@@ -150,7 +177,7 @@ pub async fn cmd_swapdb(
     }
     drop(cfg);
 
-    let mut store = store.write().await;
+    let mut store = store.lock_dbs(&[a, b]).await;
     if store.swap_db(a, b) {
         RespValue::ok()
     } else {
@@ -164,9 +191,10 @@ pub async fn cmd_info(
     config: &SharedConfig,
     repl_state: &SharedReplicationState,
     last_save_time: &SharedLastSaveTime,
+    latency: &SharedLatencyMonitor,
 ) -> RespValue {
     let cfg = config.read().await;
-    let mut store = store.write().await;
+    let stats = &store.stats;
 
     // Determine which section(s) to include
     let section_filter: Option<String> = args
@@ -183,9 +211,13 @@ pub async fn cmd_info(
             || section_filter.as_deref() == Some(name)
             || section_filter.as_deref() == Some("default")
     };
+    // Per-command sections are only listed when asked for, as in Redis
+    let show_extended = |name: &str| -> bool {
+        matches!(section_filter.as_deref(), Some("all" | "everything"))
+            || section_filter.as_deref() == Some(name)
+    };
 
     let mut info = String::new();
-    let used_mem = store.estimated_memory();
 
     if show_section("server") {
         info.push_str("# Server\r\n");
@@ -216,12 +248,18 @@ pub async fn cmd_info(
 
     if show_section("clients") {
         info.push_str("# Clients\r\n");
-        info.push_str("connected_clients:1\r\n");
+        info.push_str(&format!(
+            "connected_clients:{}\r\n",
+            stats.connected_clients.load(Ordering::Relaxed)
+        ));
         info.push_str("cluster_connections:0\r\n");
         info.push_str("maxclients:10000\r\n");
         info.push_str("client_recent_max_input_buffer:0\r\n");
         info.push_str("client_recent_max_output_buffer:0\r\n");
-        info.push_str("total_clients_connected_including_replicas:1\r\n");
+        info.push_str(&format!(
+            "total_clients_connected_including_replicas:{}\r\n",
+            stats.connected_clients.load(Ordering::Relaxed)
+        ));
         info.push_str("blocked_clients:0\r\n");
         info.push_str("tracking_clients:0\r\n");
         info.push_str("clients_in_timeout_table:0\r\n");
@@ -230,30 +268,32 @@ pub async fn cmd_info(
 
     if show_section("memory") {
         info.push_str("# Memory\r\n");
-        info.push_str(&format!("used_memory:{}\r\n", used_mem));
-        let human = if used_mem < 1024 {
-            format!("{used_mem}B")
-        } else if used_mem < 1024 * 1024 {
-            format!("{:.2}K", used_mem as f64 / 1024.0)
-        } else if used_mem < 1024 * 1024 * 1024 {
-            format!("{:.2}M", used_mem as f64 / (1024.0 * 1024.0))
-        } else {
-            format!("{:.2}G", used_mem as f64 / (1024.0 * 1024.0 * 1024.0))
-        };
-        info.push_str(&format!("used_memory_human:{human}\r\n"));
-        info.push_str(&format!("used_memory_rss:{}\r\n", used_mem));
-        info.push_str(&format!("used_memory_rss_human:{human}\r\n"));
-        info.push_str(&format!("used_memory_peak:{}\r\n", used_mem));
-        info.push_str(&format!("used_memory_peak_human:{human}\r\n"));
-        info.push_str("used_memory_peak_perc:100.00%\r\n");
-        info.push_str("used_memory_overhead:0\r\n");
-        info.push_str("used_memory_startup:0\r\n");
-        info.push_str("used_memory_dataset:0\r\n");
-        info.push_str("used_memory_dataset_perc:0.00%\r\n");
-        info.push_str("allocator_allocated:0\r\n");
-        info.push_str("allocator_active:0\r\n");
-        info.push_str("allocator_resident:0\r\n");
-        info.push_str(&format!("total_system_memory:{}\r\n", used_mem));
+        let report = MemoryReport::collect(store, repl_state).await;
+        let human = bytes_to_human;
+        info.push_str(&format!("used_memory:{}\r\n", report.used));
+        info.push_str(&format!("used_memory_human:{}\r\n", human(report.used)));
+        info.push_str(&format!("used_memory_rss:{}\r\n", report.rss));
+        info.push_str(&format!("used_memory_rss_human:{}\r\n", human(report.rss)));
+        info.push_str(&format!("used_memory_peak:{}\r\n", report.peak));
+        info.push_str(&format!(
+            "used_memory_peak_human:{}\r\n",
+            human(report.peak)
+        ));
+        info.push_str(&format!(
+            "used_memory_peak_perc:{:.2}%\r\n",
+            report.peak_percentage()
+        ));
+        info.push_str(&format!("used_memory_overhead:{}\r\n", report.overhead()));
+        info.push_str(&format!("used_memory_startup:{}\r\n", report.startup));
+        info.push_str(&format!("used_memory_dataset:{}\r\n", report.dataset));
+        info.push_str(&format!(
+            "used_memory_dataset_perc:{:.2}%\r\n",
+            report.dataset_percentage()
+        ));
+        info.push_str(&format!("allocator_allocated:{}\r\n", report.used));
+        info.push_str(&format!("allocator_active:{}\r\n", report.used));
+        info.push_str(&format!("allocator_resident:{}\r\n", report.rss));
+        info.push_str("total_system_memory:0\r\n");
         info.push_str("total_system_memory_human:0B\r\n");
         info.push_str("used_memory_lua:0\r\n");
         info.push_str("used_memory_scripts:0\r\n");
@@ -270,27 +310,48 @@ pub async fn cmd_info(
         info.push_str(&format!("maxmemory_policy:{}\r\n", cfg.maxmemory_policy));
         info.push_str("allocator_frag_ratio:1.0\r\n");
         info.push_str("allocator_frag_bytes:0\r\n");
-        info.push_str("allocator_rss_ratio:1.0\r\n");
-        info.push_str("allocator_rss_bytes:0\r\n");
+        info.push_str(&format!(
+            "allocator_rss_ratio:{:.2}\r\n",
+            report.fragmentation()
+        ));
+        info.push_str(&format!(
+            "allocator_rss_bytes:{}\r\n",
+            report.rss as i64 - report.used as i64
+        ));
         info.push_str("rss_overhead_ratio:1.0\r\n");
         info.push_str("rss_overhead_bytes:0\r\n");
-        info.push_str("mem_fragmentation_ratio:1.0\r\n");
-        info.push_str("mem_fragmentation_bytes:0\r\n");
+        info.push_str(&format!(
+            "mem_fragmentation_ratio:{:.2}\r\n",
+            report.fragmentation()
+        ));
+        info.push_str(&format!(
+            "mem_fragmentation_bytes:{}\r\n",
+            report.rss as i64 - report.used as i64
+        ));
         info.push_str("mem_not_counted_for_evict:0\r\n");
-        info.push_str("mem_replication_backlog:0\r\n");
+        info.push_str(&format!("mem_replication_backlog:{}\r\n", report.backlog));
         info.push_str("mem_clients_slaves:0\r\n");
         info.push_str("mem_clients_normal:0\r\n");
         info.push_str("mem_aof_buffer:0\r\n");
         info.push_str("mem_allocator:libc\r\n");
         info.push_str("active_defrag_running:0\r\n");
-        info.push_str("lazyfree_pending_objects:0\r\n");
-        info.push_str("lazyfreed_objects:0\r\n");
+        info.push_str(&format!(
+            "lazyfree_pending_objects:{}\r\n",
+            store.lazyfree.pending_objects()
+        ));
+        info.push_str(&format!(
+            "lazyfreed_objects:{}\r\n",
+            store.lazyfree.lazyfreed_objects()
+        ));
         info.push_str("\r\n");
     }
 
     if show_section("stats") {
         info.push_str("# Stats\r\n");
-        info.push_str("total_connections_received:0\r\n");
+        info.push_str(&format!(
+            "total_connections_received:{}\r\n",
+            stats.total_connections_received.load(Ordering::Relaxed)
+        ));
         info.push_str("total_commands_processed:0\r\n");
         info.push_str("instantaneous_ops_per_sec:0\r\n");
         info.push_str("total_net_input_bytes:0\r\n");
@@ -298,17 +359,37 @@ pub async fn cmd_info(
         info.push_str("instantaneous_input_kbps:0.00\r\n");
         info.push_str("instantaneous_output_kbps:0.00\r\n");
         info.push_str("rejected_connections:0\r\n");
-        info.push_str("sync_full:0\r\n");
-        info.push_str("sync_partial_ok:0\r\n");
-        info.push_str("sync_partial_err:0\r\n");
-        store.drain_lazy_expired();
-        info.push_str(&format!("expired_keys:{}\r\n", store.expired_keys));
-        info.push_str("expired_stale_perc:0.00\r\n");
-        info.push_str("expired_time_cap_reached_count:0\r\n");
-        info.push_str("expire_cycle_cpu_milliseconds:0\r\n");
-        info.push_str("evicted_keys:0\r\n");
+        {
+            let rs = repl_state.read().await;
+            info.push_str(&format!("sync_full:{}\r\n", rs.sync_full));
+            info.push_str(&format!("sync_partial_ok:{}\r\n", rs.sync_partial_ok));
+            info.push_str(&format!("sync_partial_err:{}\r\n", rs.sync_partial_err));
+        }
+        info.push_str(&format!(
+            "expired_keys:{}\r\n",
+            stats.expired_keys.load(Ordering::Relaxed)
+        ));
+        info.push_str(&format!(
+            "expired_stale_perc:{:.2}\r\n",
+            f64::from_bits(stats.expired_stale_perc.load(Ordering::Relaxed)) * 100.0
+        ));
+        info.push_str(&format!(
+            "expired_time_cap_reached_count:{}\r\n",
+            stats.expired_time_cap_reached_count.load(Ordering::Relaxed)
+        ));
+        info.push_str(&format!(
+            "expire_cycle_cpu_milliseconds:{}\r\n",
+            stats.expire_cycle_time_used.load(Ordering::Relaxed) / 1000
+        ));
+        info.push_str(&format!(
+            "evicted_keys:{}\r\n",
+            stats.evicted_keys.load(Ordering::Relaxed)
+        ));
         info.push_str("evicted_clients:0\r\n");
-        info.push_str("total_keys_evicted:0\r\n");
+        info.push_str(&format!(
+            "total_keys_evicted:{}\r\n",
+            stats.evicted_keys.load(Ordering::Relaxed)
+        ));
         info.push_str("keyspace_hits:0\r\n");
         info.push_str("keyspace_misses:0\r\n");
         info.push_str("pubsub_channels:0\r\n");
@@ -325,7 +406,10 @@ pub async fn cmd_info(
         info.push_str("tracking_total_items:0\r\n");
         info.push_str("tracking_total_prefixes:0\r\n");
         info.push_str("unexpected_error_replies:0\r\n");
-        info.push_str("total_error_replies:0\r\n");
+        info.push_str(&format!(
+            "total_error_replies:{}\r\n",
+            latency.lock().unwrap().total_error_replies()
+        ));
         info.push_str("dump_payload_sanitizations:0\r\n");
         info.push_str("total_reads_processed:0\r\n");
         info.push_str("total_writes_processed:0\r\n");
@@ -347,7 +431,10 @@ pub async fn cmd_info(
         info.push_str("current_save_keys_processed:0\r\n");
         info.push_str("current_save_keys_total:0\r\n");
         let last_save = last_save_time.load(Ordering::Relaxed);
-        info.push_str(&format!("rdb_changes_since_last_save:{}\r\n", store.dirty));
+        info.push_str(&format!(
+            "rdb_changes_since_last_save:{}\r\n",
+            stats.dirty.load(Ordering::Relaxed)
+        ));
         info.push_str("rdb_bgsave_in_progress:0\r\n");
         info.push_str(&format!("rdb_last_save_time:{}\r\n", last_save));
         info.push_str("rdb_last_bgsave_status:ok\r\n");
@@ -390,15 +477,34 @@ pub async fn cmd_info(
                 "master_sync_in_progress:{}\r\n",
                 if rs.master_sync_in_progress { 1 } else { 0 }
             ));
+            info.push_str(&format!("slave_repl_offset:{}\r\n", rs.master_repl_offset));
+            info.push_str(&format!(
+                "slave_priority:{}\r\n",
+                config.read().await.replica_priority
+            ));
         }
         info.push_str(&format!("connected_slaves:{}\r\n", rs.connected_slaves()));
         for (i, replica) in rs.replicas.iter().enumerate() {
             info.push_str(&format!(
                 "slave{}:ip={},port={},state={},offset={},lag={}\r\n",
-                i, replica.addr, replica.port, replica.state, replica.offset, replica.lag
+                i,
+                replica.addr,
+                replica.port,
+                replica.state,
+                replica.offset,
+                replica.lag()
             ));
         }
-        info.push_str("master_failover_state:no-failover\r\n");
+        {
+            let cfg = config.read().await;
+            if cfg.min_replicas_to_write > 0 && cfg.min_replicas_max_lag > 0 {
+                info.push_str(&format!(
+                    "min_slaves_good_slaves:{}\r\n",
+                    rs.good_replicas(cfg.min_replicas_max_lag)
+                ));
+            }
+        }
+        info.push_str(&format!("master_failover_state:{}\r\n", rs.failover_state));
         info.push_str(&format!("master_replid:{}\r\n", rs.master_replid));
         info.push_str(&format!("master_replid2:{}\r\n", rs.master_replid2));
         info.push_str(&format!("master_repl_offset:{}\r\n", rs.master_repl_offset));
@@ -434,8 +540,45 @@ pub async fn cmd_info(
         info.push_str("\r\n");
     }
 
+    if show_extended("commandstats") {
+        info.push_str("# Commandstats\r\n");
+        let lm = latency.lock().unwrap();
+        let mut commands: Vec<_> = lm.commands().collect();
+        commands.sort_by(|a, b| a.0.cmp(b.0));
+        for (cmd, stats) in commands {
+            let usec_per_call = if stats.calls > 0 {
+                stats.usec as f64 / stats.calls as f64
+            } else {
+                0.0
+            };
+            info.push_str(&format!(
+                "cmdstat_{cmd}:calls={},usec={},usec_per_call={usec_per_call:.2},rejected_calls={},failed_calls={}\r\n",
+                stats.calls, stats.usec, stats.rejected_calls, stats.failed_calls
+            ));
+        }
+        info.push_str("\r\n");
+    }
+
     if show_section("errorstats") {
         info.push_str("# Errorstats\r\n");
+        for (prefix, count) in latency.lock().unwrap().errors() {
+            info.push_str(&format!("errorstat_{prefix}:count={count}\r\n"));
+        }
+        info.push_str("\r\n");
+    }
+
+    if show_extended("latencystats") {
+        info.push_str("# Latencystats\r\n");
+        let lm = latency.lock().unwrap();
+        let mut commands: Vec<_> = lm.commands().filter(|(_, s)| s.calls > 0).collect();
+        commands.sort_by(|a, b| a.0.cmp(b.0));
+        for (cmd, stats) in commands {
+            let [p50, p99, p999] =
+                [50.0, 99.0, 99.9].map(|p| stats.percentile(p).unwrap_or_default() as f64);
+            info.push_str(&format!(
+                "latency_percentiles_usec_{cmd}:p50={p50:.3},p99={p99:.3},p99.9={p999:.3}\r\n"
+            ));
+        }
         info.push_str("\r\n");
     }
 
@@ -447,10 +590,9 @@ pub async fn cmd_info(
 
     if show_section("keyspace") {
         info.push_str("# Keyspace\r\n");
-        for (i, db) in store.databases.iter().enumerate() {
-            let size = db.dbsize();
+        for i in 0..store.num_databases() {
+            let (size, expires) = store.db_key_counts(i);
             if size > 0 {
-                let expires = db.expires_count();
                 info.push_str(&format!(
                     "db{i}:keys={size},expires={expires},avg_ttl=0\r\n"
                 ));
@@ -465,6 +607,7 @@ pub async fn cmd_config(
     args: &[RespValue],
     config: &SharedConfig,
     store: &SharedStore,
+    latency: &SharedLatencyMonitor,
 ) -> RespValue {
     if args.is_empty() {
         return wrong_arg_count("config");
@@ -489,16 +632,23 @@ pub async fn cmd_config(
             let params = [
                 "bind",
                 "port",
+                "metrics-port",
                 "databases",
                 "requirepass",
                 "timeout",
                 "tcp-keepalive",
                 "hz",
+                "active-expire-effort",
+                "lazyfree-lazy-eviction",
+                "lazyfree-lazy-expire",
+                "lazyfree-lazy-server-del",
+                "lazyfree-lazy-user-del",
                 "loglevel",
                 "dbfilename",
                 "dir",
                 "appendonly",
                 "appendfsync",
+                "aof-load-truncated",
                 "maxmemory",
                 "maxmemory-policy",
                 "save",
@@ -513,11 +663,23 @@ pub async fn cmd_config(
                 "set-max-listpack-value",
                 "slowlog-log-slower-than",
                 "slowlog-max-len",
+                "latency-monitor-threshold",
                 "list-compress-depth",
                 "zset-max-listpack-entries",
                 "zset-max-ziplist-entries",
                 "zset-max-listpack-value",
                 "zset-max-ziplist-value",
+                "hll-sparse-max-bytes",
+                "replica-serve-stale-data",
+                "masterauth",
+                "masteruser",
+                "replica-priority",
+                "repl-diskless-sync",
+                "repl-diskless-load",
+                "min-replicas-to-write",
+                "min-slaves-to-write",
+                "min-replicas-max-lag",
+                "min-slaves-max-lag",
             ];
 
             let mut result = Vec::new();
@@ -566,15 +728,23 @@ pub async fn cmd_config(
                     return RespValue::error(format!("ERR {e}"));
                 }
             }
+            store.lazyfree.configure(&cfg);
+            crate::types::encoding::configure(&cfg);
             RespValue::ok()
         }
         "RESETSTAT" => {
-            let mut store = store.write().await;
-            store.expired_keys = 0;
-            store.expired_keys_active = 0;
-            for db in &mut store.databases {
-                db.lazy_expired_count = 0;
-            }
+            let stats = &store.stats;
+            stats.expired_keys.store(0, Ordering::Relaxed);
+            stats.expired_keys_active.store(0, Ordering::Relaxed);
+            stats.expired_stale_perc.store(0, Ordering::Relaxed);
+            stats
+                .expired_time_cap_reached_count
+                .store(0, Ordering::Relaxed);
+            stats.expire_cycle_time_used.store(0, Ordering::Relaxed);
+            stats.evicted_keys.store(0, Ordering::Relaxed);
+            stats.total_connections_received.store(0, Ordering::Relaxed);
+            store.lazyfree.reset_stats();
+            latency.lock().unwrap().reset_stats();
             RespValue::ok()
         }
         _ => RespValue::error(format!(
@@ -1125,25 +1295,14 @@ pub async fn cmd_debug(
             let path = format!("{}/{}", cfg.dir, cfg.dbfilename);
             drop(cfg);
             {
-                let store_r = store.read().await;
-                if let Err(e) = persistence::rdb::save(&store_r, &path) {
+                let mut keyspace = store.lock_all().await;
+                if let Err(e) = persistence::rdb::save(&mut keyspace, &path) {
                     return RespValue::error(format!("ERR {e}"));
                 }
             }
-            {
-                let mut store_w = store.write().await;
-                let num_dbs = store_w.databases.len();
-                match persistence::rdb::load(&path, num_dbs) {
-                    Ok(loaded) => {
-                        // Replace database contents
-                        for (i, db) in loaded.databases.into_iter().enumerate() {
-                            if i < store_w.databases.len() {
-                                store_w.databases[i] = db;
-                            }
-                        }
-                    }
-                    Err(e) => return RespValue::error(format!("ERR {e}")),
-                }
+            match persistence::rdb::load(&path, store.num_databases()) {
+                Ok(loaded) => store.replace(loaded).await,
+                Err(e) => return RespValue::error(format!("ERR {e}")),
             }
             RespValue::ok()
         }
@@ -1160,8 +1319,8 @@ pub async fn cmd_debug(
                 Some(k) => k,
                 None => return RespValue::error("ERR no such key"),
             };
-            let mut store_w = store.write().await;
-            let db = store_w.db(client.db_index);
+            let mut store_w = store.lock_keys(client.db_index, [&key]).await;
+            let mut db = store_w.db(client.db_index);
             match db.get(&key) {
                 Some(entry) => {
                     let type_name = entry.value.type_name();
@@ -1175,51 +1334,10 @@ pub async fn cmd_debug(
                                 "raw"
                             }
                         }
-                        crate::types::RedisValue::List(l) => {
-                            let cfg = config.read().await;
-                            if cfg.list_max_listpack_size > 0 {
-                                if l.len() <= cfg.list_max_listpack_size as usize {
-                                    "listpack"
-                                } else {
-                                    "quicklist"
-                                }
-                            } else {
-                                "quicklist"
-                            }
-                        }
-                        crate::types::RedisValue::Hash(h) => {
-                            let cfg = config.read().await;
-                            if h.len() <= cfg.hash_max_listpack_entries as usize
-                                && !h.has_long_entry(cfg.hash_max_listpack_value as usize)
-                            {
-                                "listpack"
-                            } else {
-                                "hashtable"
-                            }
-                        }
-                        crate::types::RedisValue::Set(s) => {
-                            let cfg = config.read().await;
-                            if s.is_all_integers() && s.len() <= cfg.set_max_intset_entries as usize
-                            {
-                                "intset"
-                            } else if s.len() <= cfg.set_max_listpack_entries as usize
-                                && !s.has_long_entry(cfg.set_max_listpack_value as usize)
-                            {
-                                "listpack"
-                            } else {
-                                "hashtable"
-                            }
-                        }
-                        crate::types::RedisValue::SortedSet(z) => {
-                            let cfg = config.read().await;
-                            if z.len() <= cfg.zset_max_listpack_entries as usize
-                                && !z.has_long_entry(cfg.zset_max_listpack_value as usize)
-                            {
-                                "listpack"
-                            } else {
-                                "skiplist"
-                            }
-                        }
+                        crate::types::RedisValue::List(l) => l.encoding(),
+                        crate::types::RedisValue::Hash(h) => h.encoding(),
+                        crate::types::RedisValue::Set(s) => s.encoding(),
+                        crate::types::RedisValue::SortedSet(z) => z.encoding(),
                         _ => "raw",
                     };
                     let info = format!(
@@ -1277,13 +1395,17 @@ pub async fn cmd_save(
     store: &SharedStore,
     config: &SharedConfig,
     last_save_time: &SharedLastSaveTime,
+    latency: &SharedLatencyMonitor,
 ) -> RespValue {
-    let store = store.read().await;
+    let mut store = store.lock_all().await;
     let cfg = config.read().await;
     let path = format!("{}/{}", cfg.dir, cfg.dbfilename);
     drop(cfg);
 
-    match persistence::rdb::save(&store, &path) {
+    let start = std::time::Instant::now();
+    let saved = persistence::rdb::save(&mut store, &path);
+    latency::monitor(latency, config, latency::RDB_SAVE, start.elapsed()).await;
+    match saved {
         Ok(()) => {
             let now = std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
@@ -1300,16 +1422,21 @@ pub async fn cmd_bgsave(
     store: &SharedStore,
     config: &SharedConfig,
     last_save_time: &SharedLastSaveTime,
+    latency: &SharedLatencyMonitor,
 ) -> RespValue {
     let store = store.clone();
     let config = config.clone();
     let last_save_time = last_save_time.clone();
+    let latency = latency.clone();
     tokio::spawn(async move {
-        let store = store.read().await;
+        let mut store = store.lock_all().await;
         let cfg = config.read().await;
         let path = format!("{}/{}", cfg.dir, cfg.dbfilename);
         drop(cfg);
-        if let Err(e) = persistence::rdb::save(&store, &path) {
+        let start = std::time::Instant::now();
+        let saved = persistence::rdb::save(&mut store, &path);
+        latency::monitor(&latency, &config, latency::RDB_SAVE, start.elapsed()).await;
+        if let Err(e) = saved {
             tracing::warn!("Background save failed: {e}");
         } else {
             let now = std::time::SystemTime::now()
@@ -1323,15 +1450,23 @@ pub async fn cmd_bgsave(
     RespValue::SimpleString("Background saving started".to_string())
 }
 
-pub async fn cmd_bgrewriteaof(store: &SharedStore, config: &SharedConfig) -> RespValue {
+pub async fn cmd_bgrewriteaof(
+    store: &SharedStore,
+    config: &SharedConfig,
+    latency: &SharedLatencyMonitor,
+) -> RespValue {
     let store = store.clone();
     let config = config.clone();
+    let latency = latency.clone();
     tokio::spawn(async move {
-        let store = store.read().await;
+        let mut store = store.lock_all().await;
         let cfg = config.read().await;
         let path = format!("{}/appendonly.aof", cfg.dir);
         drop(cfg);
-        if let Err(e) = persistence::aof::rewrite(&store, &path) {
+        let start = std::time::Instant::now();
+        let rewritten = persistence::aof::rewrite(&mut store, &path);
+        latency::monitor(&latency, &config, latency::AOF_REWRITE, start.elapsed()).await;
+        if let Err(e) = rewritten {
             tracing::warn!("Background AOF rewrite failed: {e}");
         } else {
             tracing::info!("Background AOF rewrite completed");
@@ -1457,3 +1592,594 @@ pub async fn cmd_slowlog(
         )),
     }
 }
+
+pub async fn cmd_latency(
+    args: &[RespValue],
+    latency: &SharedLatencyMonitor,
+    config: &SharedConfig,
+) -> RespValue {
+    if args.is_empty() {
+        return wrong_arg_count("latency");
+    }
+
+    let subcmd = match arg_to_string(&args[0]) {
+        Some(s) => s.to_uppercase(),
+        None => return RespValue::error("ERR invalid subcommand"),
+    };
+    let names: Vec<String> = args[1..].iter().filter_map(arg_to_string).collect();
+    let bulk = |s: &str| RespValue::bulk_string(s.as_bytes().to_vec());
+
+    match (subcmd.as_str(), names.len()) {
+        ("LATEST", 0) => {
+            let lm = latency.lock().unwrap();
+            RespValue::array(
+                lm.events()
+                    .filter_map(|(event, series)| {
+                        let last = series.samples.back()?;
+                        Some(RespValue::array(vec![
+                            bulk(event),
+                            RespValue::integer(last.time as i64),
+                            RespValue::integer(last.latency_ms as i64),
+                            RespValue::integer(series.max_ms as i64),
+                        ]))
+                    })
+                    .collect(),
+            )
+        }
+        ("HISTORY", 1) => {
+            let lm = latency.lock().unwrap();
+            let samples = lm
+                .series(&names[0])
+                .map(|series| {
+                    series
+                        .samples
+                        .iter()
+                        .map(|s| {
+                            RespValue::array(vec![
+                                RespValue::integer(s.time as i64),
+                                RespValue::integer(s.latency_ms as i64),
+                            ])
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            RespValue::array(samples)
+        }
+        ("RESET", _) => RespValue::integer(latency.lock().unwrap().reset(&names) as i64),
+        ("GRAPH", 1) => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            match latency.lock().unwrap().graph(&names[0], now) {
+                Some(graph) => bulk(&graph),
+                None => RespValue::error(format!(
+                    "ERR No samples available for event '{}'",
+                    names[0]
+                )),
+            }
+        }
+        ("DOCTOR", 0) => {
+            let threshold = config.read().await.latency_monitor_threshold;
+            bulk(&latency.lock().unwrap().doctor(threshold))
+        }
+        ("HISTOGRAM", _) => {
+            let lm = latency.lock().unwrap();
+            let mut histograms: Vec<_> = if names.is_empty() {
+                lm.commands()
+                    .filter(|(_, h)| h.calls > 0)
+                    .map(|(cmd, h)| (cmd.clone(), h))
+                    .collect()
+            } else {
+                names
+                    .iter()
+                    .map(|n| n.to_lowercase())
+                    .filter_map(|cmd| lm.command(&cmd).map(|h| (cmd, h)))
+                    .filter(|(_, h)| h.calls > 0)
+                    .collect()
+            };
+            histograms.sort_by(|a, b| a.0.cmp(&b.0));
+            histograms.dedup_by(|a, b| a.0 == b.0);
+            let mut reply = Vec::with_capacity(histograms.len() * 2);
+            for (cmd, histogram) in histograms {
+                let buckets = histogram
+                    .cumulative()
+                    .into_iter()
+                    .flat_map(|(bound, count)| {
+                        [
+                            RespValue::integer(bound as i64),
+                            RespValue::integer(count as i64),
+                        ]
+                    })
+                    .collect();
+                reply.push(bulk(&cmd));
+                reply.push(RespValue::array(vec![
+                    bulk("calls"),
+                    RespValue::integer(histogram.calls as i64),
+                    bulk("histogram_usec"),
+                    RespValue::array(buckets),
+                ]));
+            }
+            RespValue::array(reply)
+        }
+        ("HELP", 0) => RespValue::array(
+            [
+                "LATENCY <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "DOCTOR",
+                "    Return a human readable latency analysis report.",
+                "GRAPH <event>",
+                "    Return an ASCII latency graph for the <event> class.",
+                "HISTORY <event>",
+                "    Return time-latency samples for the <event> class.",
+                "LATEST",
+                "    Return the latest latency samples for all events.",
+                "RESET [<event> ...]",
+                "    Reset latency data of one or more <event> classes.",
+                "    (default: reset all data for all event classes)",
+                "HISTOGRAM [<command> ...]",
+                "    Return a cumulative distribution of latencies in the format of a histogram for the specified command names.",
+                "    If no commands are specified then all histograms are replied.",
+            ]
+            .iter()
+            .map(|line| bulk(line))
+            .collect(),
+        ),
+        _ => RespValue::error(format!(
+            "ERR Unknown subcommand or wrong number of arguments for LATENCY {subcmd}"
+        )),
+    }
+}
+
+/// ROLE
+/// Master: ["master", offset, [[ip, port, offset], ...]].
+/// Replica: ["slave", host, port, link state, offset].
+pub async fn cmd_role(repl_state: &SharedReplicationState) -> RespValue {
+    let state = repl_state.read().await;
+    let bulk = |s: &str| RespValue::bulk_string(s.as_bytes().to_vec());
+    match state.role {
+        ReplicationRole::Master => {
+            let replicas = state
+                .replicas
+                .iter()
+                .filter(|r| r.state == crate::replication::ReplicaState::Online)
+                .map(|r| {
+                    RespValue::array(vec![
+                        bulk(&r.addr),
+                        bulk(&r.port.to_string()),
+                        bulk(&r.offset.to_string()),
+                    ])
+                })
+                .collect();
+            RespValue::array(vec![
+                bulk("master"),
+                RespValue::integer(state.master_repl_offset),
+                RespValue::array(replicas),
+            ])
+        }
+        ReplicationRole::Replica => {
+            let link = if state.master_sync_in_progress {
+                "sync"
+            } else if state.master_link_status == "up" {
+                "connected"
+            } else {
+                "connecting"
+            };
+            RespValue::array(vec![
+                bulk("slave"),
+                bulk(state.master_host.as_deref().unwrap_or_default()),
+                RespValue::integer(state.master_port.unwrap_or_default() as i64),
+                bulk(link),
+                RespValue::integer(state.master_repl_offset),
+            ])
+        }
+    }
+}
+
+/// WAIT numreplicas timeout
+/// Blocks until `numreplicas` replicas acknowledged this client's last write.
+pub async fn cmd_wait(
+    args: &[RespValue],
+    client: &ClientState,
+    repl_state: &SharedReplicationState,
+) -> RespValue {
+    if args.len() != 2 {
+        return wrong_arg_count("wait");
+    }
+    let numreplicas = match arg_to_i64(&args[0]) {
+        Some(n) if n >= 0 => n as usize,
+        _ => return RespValue::error("ERR value is not an integer or out of range"),
+    };
+    let timeout = match arg_to_i64(&args[1]) {
+        Some(t) if t >= 0 => t as u64,
+        _ => return RespValue::error("ERR timeout is not an integer or out of range"),
+    };
+
+    let woff = client.woff;
+    {
+        let mut state = repl_state.write().await;
+        if state.role == ReplicationRole::Replica {
+            return RespValue::error(
+                "ERR WAIT cannot be used with replica instances. Please also note that writes to replicas are just local and are not propagated.",
+            );
+        }
+        // EXEC doesn't block, and the queued writes aren't propagated yet
+        if client.deny_blocking {
+            let acked = state.replicas_acked(state.master_repl_offset);
+            return RespValue::integer(acked as i64);
+        }
+        if state.replicas_acked(woff) >= numreplicas {
+            return RespValue::integer(state.replicas_acked(woff) as i64);
+        }
+        // No replica is online to acknowledge anything
+        if !state.request_acks() {
+            return RespValue::integer(0);
+        }
+    }
+
+    crate::replication::wait_for_acks(repl_state, timeout, |state| {
+        state.replicas_acked(woff) >= numreplicas
+    })
+    .await;
+
+    let state = repl_state.read().await;
+    RespValue::integer(state.replicas_acked(woff) as i64)
+}
+
+/// WAITAOF numlocal numreplicas timeout
+/// Blocks until this client's last write is fsynced to the local AOF (if numlocal > 0)
+/// and to the AOF of `numreplicas` replicas. Replies with [local, replicas].
+pub async fn cmd_waitaof(
+    args: &[RespValue],
+    client: &ClientState,
+    config: &SharedConfig,
+    repl_state: &SharedReplicationState,
+) -> RespValue {
+    if args.len() != 3 {
+        return wrong_arg_count("waitaof");
+    }
+    let numlocal = match arg_to_i64(&args[0]) {
+        Some(n) if n >= 0 => n as usize,
+        _ => return RespValue::error("ERR value is out of range, must be positive"),
+    };
+    let numreplicas = match arg_to_i64(&args[1]) {
+        Some(n) if n >= 0 => n as usize,
+        _ => return RespValue::error("ERR value is out of range, must be positive"),
+    };
+    let timeout = match arg_to_i64(&args[2]) {
+        Some(t) if t >= 0 => t as u64,
+        _ => return RespValue::error("ERR timeout is not an integer or out of range"),
+    };
+    if numlocal > 0 {
+        let cfg = config.read().await;
+        if !cfg.appendonly {
+            return RespValue::error(
+                "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.",
+            );
+        }
+        // The OS decides when to flush, so a local fsync would never happen
+        if cfg.appendfsync == "no" {
+            return RespValue::error(
+                "ERR WAITAOF cannot be used when numlocal is set but appendfsync is no.",
+            );
+        }
+    }
+
+    let woff = client.woff;
+    let reply = |state: &crate::replication::ReplicationState| {
+        let local = (state.aof_fsynced_offset >= woff) as i64;
+        let replicas = state.replicas_fsynced(woff);
+        (local, replicas)
+    };
+    let satisfied = move |(local, replicas): (i64, usize)| {
+        local as usize >= numlocal.min(1) && replicas >= numreplicas
+    };
+
+    {
+        let mut state = repl_state.write().await;
+        if state.role == ReplicationRole::Replica {
+            return RespValue::error("ERR WAITAOF cannot be used with replica instances.");
+        }
+        // EXEC doesn't block, and the queued writes aren't propagated yet
+        if client.deny_blocking {
+            let woff = state.master_repl_offset;
+            return RespValue::array(vec![
+                RespValue::integer((state.aof_fsynced_offset >= woff) as i64),
+                RespValue::integer(state.replicas_fsynced(woff) as i64),
+            ]);
+        }
+        let (local, replicas) = reply(&state);
+        // Without an online replica the replica count can't grow
+        if !satisfied((local, replicas)) && numreplicas > 0 && !state.request_acks() {
+            return RespValue::array(vec![
+                RespValue::integer(local),
+                RespValue::integer(replicas as i64),
+            ]);
+        }
+    }
+
+    crate::replication::wait_for_acks(repl_state, timeout, |state| satisfied(reply(state))).await;
+
+    let (local, replicas) = reply(&*repl_state.read().await);
+    RespValue::array(vec![
+        RespValue::integer(local),
+        RespValue::integer(replicas as i64),
+    ])
+}
+
+/// Memory figures shared by INFO memory, MEMORY STATS and MEMORY DOCTOR.
+struct MemoryReport {
+    used: usize,
+    peak: usize,
+    rss: usize,
+    startup: usize,
+    dataset: usize,
+    backlog: usize,
+    keys: usize,
+    /// `(db index, keyspace table bytes)` of every non-empty database.
+    db_overhead: Vec<(usize, usize)>,
+}
+
+impl MemoryReport {
+    /// Gather the figures without locking the keyspace: used memory comes
+    /// from the allocator, the table overheads from what each shard published
+    /// when last unlocked, and the dataset is whatever is left, as in Redis.
+    async fn collect(store: &DataStore, repl_state: &SharedReplicationState) -> Self {
+        let used = store.used_memory();
+        let backlog = repl_state
+            .read()
+            .await
+            .backlog
+            .as_ref()
+            .map_or(0, |bl| bl.capacity());
+        let startup = crate::allocator::startup().min(used);
+        let mut keys = 0;
+        let mut db_overhead = Vec::new();
+        for db in 0..store.num_databases() {
+            let (size, _) = store.db_key_counts(db);
+            keys += size;
+            if size > 0 {
+                db_overhead.push((db, store.db_overhead(db)));
+            }
+        }
+        let tables: usize = db_overhead.iter().map(|(_, bytes)| bytes).sum();
+        MemoryReport {
+            used,
+            peak: crate::allocator::peak().unwrap_or(used).max(used),
+            rss: crate::allocator::rss().unwrap_or(used),
+            startup,
+            dataset: used.saturating_sub(startup + backlog + tables),
+            backlog,
+            keys,
+            db_overhead,
+        }
+    }
+
+    fn overhead(&self) -> usize {
+        self.used.saturating_sub(self.dataset)
+    }
+
+    fn percent_of(part: usize, whole: usize) -> f64 {
+        if whole == 0 {
+            0.0
+        } else {
+            part as f64 * 100.0 / whole as f64
+        }
+    }
+
+    fn dataset_percentage(&self) -> f64 {
+        Self::percent_of(self.dataset, self.used.saturating_sub(self.startup))
+    }
+
+    fn peak_percentage(&self) -> f64 {
+        Self::percent_of(self.used, self.peak)
+    }
+
+    fn fragmentation(&self) -> f64 {
+        if self.used == 0 {
+            1.0
+        } else {
+            self.rss as f64 / self.used as f64
+        }
+    }
+}
+
+fn bytes_to_human(n: usize) -> String {
+    if n < 1024 {
+        format!("{n}B")
+    } else if n < 1024 * 1024 {
+        format!("{:.2}K", n as f64 / 1024.0)
+    } else if n < 1024 * 1024 * 1024 {
+        format!("{:.2}M", n as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.2}G", n as f64 / (1024.0 * 1024.0 * 1024.0))
+    }
+}
+
+/// MEMORY USAGE key [SAMPLES count] | STATS | DOCTOR | MALLOC-STATS | PURGE | HELP
+pub async fn cmd_memory(
+    args: &[RespValue],
+    store: &SharedStore,
+    config: &SharedConfig,
+    repl_state: &SharedReplicationState,
+    client: &ClientState,
+) -> RespValue {
+    let sub = args
+        .first()
+        .and_then(|a| a.to_string_lossy())
+        .map(|s| s.to_uppercase())
+        .unwrap_or_default();
+    let bulk = |s: &str| RespValue::bulk_string(s.as_bytes().to_vec());
+    match (sub.as_str(), args.len()) {
+        ("USAGE", 2 | 4) => {
+            let Some(key) = arg_to_string(&args[1]) else {
+                return RespValue::null_bulk_string();
+            };
+            let mut samples = 5;
+            if args.len() == 4 {
+                if !arg_to_string(&args[2]).is_some_and(|s| s.eq_ignore_ascii_case("SAMPLES")) {
+                    return RespValue::error("ERR syntax error");
+                }
+                match arg_to_i64(&args[3]) {
+                    Some(n) if n >= 0 => samples = n as usize,
+                    _ => {
+                        return RespValue::error("ERR value is out of range, must be positive");
+                    }
+                }
+            }
+            let mut store = store.lock_keys(client.db_index, [&key]).await;
+            let mut db = store.db(client.db_index);
+            if !db.exists(&key) {
+                return RespValue::null_bulk_string();
+            }
+            match db.key_memory_usage(&key, samples) {
+                Some(bytes) => RespValue::integer(bytes as i64),
+                None => RespValue::null_bulk_string(),
+            }
+        }
+        ("STATS", 1) => {
+            let report = MemoryReport::collect(store, repl_state).await;
+            let int = |n: usize| RespValue::integer(n as i64);
+            let float = |f: f64| bulk(&format!("{f:.2}"));
+            let mut reply = vec![
+                bulk("peak.allocated"),
+                int(report.peak),
+                bulk("total.allocated"),
+                int(report.used),
+                bulk("startup.allocated"),
+                int(report.startup),
+                bulk("replication.backlog"),
+                int(report.backlog),
+                bulk("clients.slaves"),
+                int(0),
+                bulk("clients.normal"),
+                int(0),
+                bulk("aof.buffer"),
+                int(0),
+                bulk("lua.caches"),
+                int(0),
+            ];
+            for (i, overhead) in &report.db_overhead {
+                reply.push(bulk(&format!("db.{i}")));
+                reply.push(RespValue::array(vec![
+                    bulk("overhead.hashtable.main"),
+                    int(*overhead),
+                    bulk("overhead.hashtable.expires"),
+                    int(0),
+                ]));
+            }
+            reply.extend([
+                bulk("overhead.total"),
+                int(report.overhead()),
+                bulk("keys.count"),
+                int(report.keys),
+                bulk("keys.bytes-per-key"),
+                int(report.used.saturating_sub(report.startup) / report.keys.max(1)),
+                bulk("dataset.bytes"),
+                int(report.dataset),
+                bulk("dataset.percentage"),
+                float(report.dataset_percentage()),
+                bulk("peak.percentage"),
+                float(report.peak_percentage()),
+                bulk("allocator.allocated"),
+                int(report.used),
+                bulk("allocator.resident"),
+                int(report.rss),
+                bulk("fragmentation"),
+                float(report.fragmentation()),
+                bulk("fragmentation.bytes"),
+                RespValue::integer(report.rss as i64 - report.used as i64),
+            ]);
+            RespValue::array(reply)
+        }
+        ("DOCTOR", 1) => {
+            let report = MemoryReport::collect(store, repl_state).await;
+            let (maxmemory, policy) = {
+                let cfg = config.read().await;
+                (cfg.maxmemory as usize, cfg.maxmemory_policy.clone())
+            };
+            bulk(&memory_doctor(&report, maxmemory, &policy))
+        }
+        ("MALLOC-STATS", 1) => bulk("Stats not supported for the current allocator"),
+        ("PURGE", 1) => RespValue::ok(),
+        ("HELP", 1) => RespValue::array(
+            [
+                "MEMORY <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "DOCTOR",
+                "    Return memory problems reports.",
+                "MALLOC-STATS",
+                "    Return internal statistics report from the memory allocator.",
+                "PURGE",
+                "    Attempt to purge dirty pages for reclamation by the allocator.",
+                "STATS",
+                "    Return information about the memory usage of the server.",
+                "USAGE <key> [SAMPLES <count>]",
+                "    Return memory in bytes used by <key> and its value. Nested values are",
+                "    sampled up to <count> times (default: 5, 0 means sample all).",
+                "HELP",
+                "    Print this help.",
+            ]
+            .iter()
+            .map(|line| RespValue::SimpleString(line.to_string()))
+            .collect(),
+        ),
+        ("USAGE", _) => {
+            RespValue::error("ERR wrong number of arguments for 'memory|usage' command")
+        }
+        _ => RespValue::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{sub}'. Try MEMORY HELP."
+        )),
+    }
+}
+
+/// Below this much memory there is too little data to diagnose anything.
+const MEMORY_DOCTOR_MIN_USED: usize = 5 * 1024 * 1024;
+
+fn memory_doctor(report: &MemoryReport, maxmemory: usize, policy: &str) -> String {
+    if report.used < MEMORY_DOCTOR_MIN_USED {
+        return "Hi Sam, this instance is empty or is using very little memory, my issues \
+                detector can't be used in these conditions. Please, leave for your mission on \
+                Earth and fill it with some data. The new Sam and I will be back to our \
+                programming as soon as I finished rebooting.\n"
+            .to_string();
+    }
+
+    let mut issues = Vec::new();
+    if report.peak > report.used * 3 / 2 {
+        issues.push(
+            "Peak memory: In the past this instance used more than 150% the memory that is \
+             currently using. The allocator is normally not able to release memory after a \
+             peak, so you can expect to see a big fragmentation ratio. This is harmless: the \
+             memory will be reused as soon as you fill the instance with more data.",
+        );
+    }
+    if report.fragmentation() > 1.4 && report.rss > 10 * 1024 * 1024 {
+        issues.push(
+            "High fragmentation: This instance has a memory fragmentation greater than 1.4 \
+             (this means that the Resident Set Size of the process is much larger than the \
+             sum of the logical allocations Cedis performed). This is usually due to a past \
+             memory peak or to allocation patterns that leave holes in the heap.",
+        );
+    }
+    if maxmemory > 0 && report.used > maxmemory / 10 * 9 && policy == "noeviction" {
+        issues.push(
+            "Close to maxmemory: This instance uses more than 90% of maxmemory and \
+             maxmemory-policy is noeviction, so writes will soon start failing. Raise \
+             maxmemory or pick an eviction policy.",
+        );
+    }
+
+    if issues.is_empty() {
+        return "Hi Sam, I can't find any memory issue in your instance. I can only account \
+                for what occurs on this base.\n"
+            .to_string();
+    }
+    let mut out =
+        String::from("Sam, I detected a few issues in this Cedis instance memory implants:\n\n");
+    for issue in issues {
+        out.push_str(" * ");
+        out.push_str(issue);
+        out.push_str("\n\n");
+    }
+    out.push_str("I'm here to keep you safe, Sam. I want to help you.\n");
+    out
+}
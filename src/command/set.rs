@@ -1,4 +1,7 @@
-use crate::command::{arg_to_bytes, arg_to_i64, arg_to_string, wrong_arg_count, wrong_type_error};
+use crate::command::{
+    arg_to_bytes, arg_to_i64, arg_to_string, parse_cursor, parse_scan_count, wrong_arg_count,
+    wrong_type_error,
+};
 use crate::connection::ClientState;
 use crate::resp::RespValue;
 use crate::store::SharedStore;
@@ -35,9 +38,9 @@ pub async fn cmd_sadd(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
-    let set = match get_or_create_set(db, &key) {
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
+    let set = match get_or_create_set(&mut db, &key) {
         Ok(s) => s,
         Err(e) => return e,
     };
@@ -62,8 +65,8 @@ pub async fn cmd_srem(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     // Check type first
     let is_set = matches!(db.get(&key), Some(entry) if matches!(&entry.value, RedisValue::Set(_)));
@@ -115,7 +118,7 @@ pub async fn cmd_sismember(
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -143,8 +146,8 @@ pub async fn cmd_smismember(
         }
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -183,7 +186,7 @@ pub async fn cmd_smembers(
         None => return RespValue::array(vec![]),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -192,7 +195,7 @@ pub async fn cmd_smembers(
                 let members: Vec<RespValue> = set
                     .members()
                     .into_iter()
-                    .map(|m| RespValue::bulk_string(m.clone()))
+                    .map(|m| RespValue::bulk_string(m.to_vec()))
                     .collect();
                 RespValue::array(members)
             }
@@ -211,7 +214,7 @@ pub async fn cmd_scard(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -241,8 +244,8 @@ pub async fn cmd_spop(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     // Check type first
     match db.get(&key) {
@@ -308,15 +311,15 @@ pub async fn cmd_srandmember(
         None => return RespValue::null_bulk_string(),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
             RedisValue::Set(set) => {
                 if args.len() == 1 {
                     match set.random_member() {
-                        Some(m) => RespValue::bulk_string(m.clone()),
+                        Some(m) => RespValue::bulk_string(m.to_vec()),
                         None => RespValue::null_bulk_string(),
                     }
                 } else {
@@ -358,7 +361,7 @@ fn collect_sets(
         if let Some(key) = arg_to_string(arg) {
             match db.get(&key) {
                 Some(entry) => match &entry.value {
-                    RedisValue::Set(s) => sets.push(s.iter().cloned().collect()),
+                    RedisValue::Set(s) => sets.push(s.iter().map(|m| m.into_owned()).collect()),
                     _ => return Err(wrong_type_error()),
                 },
                 None => sets.push(HashSet::new()),
@@ -376,10 +379,12 @@ pub async fn cmd_sunion(
     if args.is_empty() {
         return wrong_arg_count("sunion");
     }
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(client.db_index, args.iter().filter_map(arg_to_string))
+        .await;
+    let mut db = store.db(client.db_index);
 
-    let sets = match collect_sets(db, args) {
+    let sets = match collect_sets(&mut db, args) {
         Ok(s) => s,
         Err(e) => return e,
     };
@@ -401,10 +406,12 @@ pub async fn cmd_sinter(
     if args.is_empty() {
         return wrong_arg_count("sinter");
     }
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(client.db_index, args.iter().filter_map(arg_to_string))
+        .await;
+    let mut db = store.db(client.db_index);
 
-    let sets = match collect_sets(db, args) {
+    let sets = match collect_sets(&mut db, args) {
         Ok(s) => s,
         Err(e) => return e,
     };
@@ -426,10 +433,12 @@ pub async fn cmd_sdiff(args: &[RespValue], store: &SharedStore, client: &ClientS
     if args.is_empty() {
         return wrong_arg_count("sdiff");
     }
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(client.db_index, args.iter().filter_map(arg_to_string))
+        .await;
+    let mut db = store.db(client.db_index);
 
-    let sets = match collect_sets(db, args) {
+    let sets = match collect_sets(&mut db, args) {
         Ok(s) => s,
         Err(e) => return e,
     };
@@ -461,10 +470,12 @@ async fn set_store_op(
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store_lock = store.write().await;
-    let db = store_lock.db(client.db_index);
+    let mut store_lock = store
+        .lock_keys(client.db_index, args.iter().filter_map(arg_to_string))
+        .await;
+    let mut db = store_lock.db(client.db_index);
 
-    let sets = match collect_sets(db, &args[1..]) {
+    let sets = match collect_sets(&mut db, &args[1..]) {
         Ok(s) => s,
         Err(e) => return e,
     };
@@ -538,8 +549,8 @@ pub async fn cmd_smove(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&src, &dst]).await;
+    let mut db = store.db(client.db_index);
 
     // Check source type
     match db.get(&src) {
@@ -580,7 +591,7 @@ pub async fn cmd_smove(args: &[RespValue], store: &SharedStore, client: &ClientS
     }
 
     // Add to destination
-    let dest_set = match get_or_create_set(db, &dst) {
+    let dest_set = match get_or_create_set(&mut db, &dst) {
         Ok(s) => s,
         Err(e) => return e,
     };
@@ -603,8 +614,13 @@ pub async fn cmd_sscan(args: &[RespValue], store: &SharedStore, client: &ClientS
         }
     };
 
+    let Some(cursor) = parse_cursor(&args[1]) else {
+        return RespValue::error("ERR invalid cursor");
+    };
+
     // Parse optional MATCH, COUNT
     let mut pattern: Option<String> = None;
+    let mut count = 10;
     let mut i = 2;
     while i < args.len() {
         let opt = match arg_to_string(&args[i]) {
@@ -621,33 +637,33 @@ pub async fn cmd_sscan(args: &[RespValue], store: &SharedStore, client: &ClientS
             }
             "COUNT" => {
                 i += 1;
-            } // skip count value
+                count = match parse_scan_count(args.get(i)) {
+                    Ok(c) => c,
+                    Err(e) => return e,
+                };
+            }
             _ => {}
         }
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
             RedisValue::Set(set) => {
-                let members: Vec<RespValue> = set
-                    .members()
-                    .into_iter()
-                    .filter(|m| {
-                        if let Some(ref pat) = pattern {
-                            let s = String::from_utf8_lossy(m);
-                            crate::glob::glob_match(pat, &s)
-                        } else {
-                            true
-                        }
-                    })
-                    .map(|m| RespValue::bulk_string(m.clone()))
-                    .collect();
+                let mut members = Vec::new();
+                let next_cursor = set.scan(cursor, count, |m| {
+                    if let Some(ref pat) = pattern
+                        && !crate::glob::glob_match(pat, &String::from_utf8_lossy(m))
+                    {
+                        return;
+                    }
+                    members.push(RespValue::bulk_string(m.to_vec()));
+                });
                 RespValue::array(vec![
-                    RespValue::bulk_string(b"0".to_vec()),
+                    RespValue::bulk_string(next_cursor.to_string().into_bytes()),
                     RespValue::array(members),
                 ])
             }
@@ -711,10 +727,11 @@ pub async fn cmd_sintercard(
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let keys = args[1..key_end].iter().filter_map(arg_to_string);
+    let mut store = store.lock_keys(client.db_index, keys).await;
+    let mut db = store.db(client.db_index);
 
-    let sets = match collect_sets(db, &args[1..1 + numkeys]) {
+    let sets = match collect_sets(&mut db, &args[1..1 + numkeys]) {
         Ok(s) => s,
         Err(e) => return e,
     };
@@ -1,5 +1,6 @@
 use crate::command::{
-    arg_to_bytes, arg_to_f64, arg_to_i64, arg_to_string, wrong_arg_count, wrong_type_error,
+    arg_to_bytes, arg_to_f64, arg_to_i64, arg_to_string, parse_cursor, parse_scan_count,
+    wrong_arg_count, wrong_type_error,
 };
 use crate::connection::ClientState;
 use crate::keywatcher::SharedKeyWatcher;
@@ -103,8 +104,8 @@ pub async fn cmd_zadd(
         return RespValue::error("ERR syntax error");
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     if incr {
         // INCR mode: single score-member pair, return the new score
@@ -150,7 +151,7 @@ pub async fn cmd_zadd(
             }
         }
 
-        let zset = match get_or_create_zset(db, &key) {
+        let zset = match get_or_create_zset(&mut db, &key) {
             Ok(z) => z,
             Err(e) => return e,
         };
@@ -190,7 +191,7 @@ pub async fn cmd_zadd(
             return wrong_type_error();
         }
 
-        let zset = match get_or_create_zset(db, &key) {
+        let zset = match get_or_create_zset(&mut db, &key) {
             Ok(z) => z,
             Err(e) => return e,
         };
@@ -251,8 +252,8 @@ pub async fn cmd_zrem(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     // Check type first
     let is_zset =
@@ -305,7 +306,7 @@ pub async fn cmd_zscore(
         None => return RespValue::null_bulk_string(),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -341,8 +342,8 @@ pub async fn cmd_zrank(args: &[RespValue], store: &SharedStore, client: &ClientS
         false
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -403,8 +404,8 @@ pub async fn cmd_zrevrank(
         false
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -449,7 +450,7 @@ pub async fn cmd_zcard(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -482,8 +483,8 @@ pub async fn cmd_zcount(
         None => return RespValue::error("ERR min or max is not a float"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -575,17 +576,15 @@ pub async fn cmd_zrange(
             "BYLEX" => bylex = true,
             "REV" => rev = true,
             "WITHSCORES" => withscores = true,
-            "LIMIT" => {
-                if i + 2 < args.len() {
-                    let off = arg_to_i64(&args[i + 1]).unwrap_or(0);
-                    let cnt = arg_to_i64(&args[i + 2]).unwrap_or(-1);
-                    if off < 0 {
-                        return RespValue::array(vec![]);
-                    }
-                    limit_offset = off as usize;
-                    limit_count = Some(if cnt < 0 { usize::MAX } else { cnt as usize });
-                    i += 2;
+            "LIMIT" if i + 2 < args.len() => {
+                let off = arg_to_i64(&args[i + 1]).unwrap_or(0);
+                let cnt = arg_to_i64(&args[i + 2]).unwrap_or(-1);
+                if off < 0 {
+                    return RespValue::array(vec![]);
                 }
+                limit_offset = off as usize;
+                limit_count = Some(if cnt < 0 { usize::MAX } else { cnt as usize });
+                i += 2;
             }
             _ => {}
         }
@@ -599,8 +598,8 @@ pub async fn cmd_zrange(
         // LIMIT is optional; if not specified, return all matches
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     if byscore {
         // Score-based range
@@ -749,8 +748,8 @@ pub async fn cmd_zrevrange(
             .map(|s| s.to_uppercase() == "WITHSCORES")
             .unwrap_or(false);
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -819,20 +818,18 @@ pub async fn cmd_zrangebyscore(
             .unwrap_or_default();
         match opt.as_str() {
             "WITHSCORES" => withscores = true,
-            "LIMIT" => {
-                if i + 2 < args.len() {
-                    offset = arg_to_i64(&args[i + 1]).unwrap_or(0) as usize;
-                    count = arg_to_i64(&args[i + 2]).unwrap_or(-1) as usize;
-                    i += 2;
-                }
+            "LIMIT" if i + 2 < args.len() => {
+                offset = arg_to_i64(&args[i + 1]).unwrap_or(0) as usize;
+                count = arg_to_i64(&args[i + 2]).unwrap_or(-1) as usize;
+                i += 2;
             }
             _ => {}
         }
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -896,20 +893,18 @@ pub async fn cmd_zrevrangebyscore(
             .unwrap_or_default();
         match opt.as_str() {
             "WITHSCORES" => withscores = true,
-            "LIMIT" => {
-                if i + 2 < args.len() {
-                    offset = arg_to_i64(&args[i + 1]).unwrap_or(0) as usize;
-                    count = arg_to_i64(&args[i + 2]).unwrap_or(-1) as usize;
-                    i += 2;
-                }
+            "LIMIT" if i + 2 < args.len() => {
+                offset = arg_to_i64(&args[i + 1]).unwrap_or(0) as usize;
+                count = arg_to_i64(&args[i + 2]).unwrap_or(-1) as usize;
+                i += 2;
             }
             _ => {}
         }
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -983,8 +978,8 @@ pub async fn cmd_zrangebylex(
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -1047,8 +1042,8 @@ pub async fn cmd_zrevrangebylex(
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -1134,8 +1129,8 @@ pub async fn cmd_zremrangebyscore(
         None => return RespValue::error("ERR min or max is not a float"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let members_to_remove: Vec<Vec<u8>> = match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -1203,8 +1198,8 @@ pub async fn cmd_zremrangebylex(
         None => return RespValue::error("ERR min or max not valid string range item"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let members_to_remove: Vec<Vec<u8>> = match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -1258,8 +1253,8 @@ pub async fn cmd_zremrangebyrank(
         None => return RespValue::error("ERR value is not an integer or out of range"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let members_to_remove: Vec<Vec<u8>> = match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -1313,10 +1308,10 @@ pub async fn cmd_zincrby(
         None => return RespValue::error("ERR invalid member"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let zset = match get_or_create_zset(db, &key) {
+    let zset = match get_or_create_zset(&mut db, &key) {
         Ok(z) => z,
         Err(e) => return e,
     };
@@ -1465,8 +1460,12 @@ async fn zset_combine_op(
         i += 1;
     }
 
-    let mut store_lock = store.write().await;
-    let db = store_lock.db(client.db_index);
+    let keys = args[arg_offset + 1..arg_offset + 1 + numkeys]
+        .iter()
+        .filter_map(arg_to_string)
+        .chain(dest.clone());
+    let mut store_lock = store.lock_keys(client.db_index, keys).await;
+    let mut db = store_lock.db(client.db_index);
 
     // Collect all sorted sets (also handle regular sets)
     let mut zsets: Vec<Vec<(Vec<u8>, f64)>> = Vec::new();
@@ -1654,8 +1653,13 @@ pub async fn cmd_zintercard(
         }
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(
+            client.db_index,
+            args[1..1 + numkeys].iter().filter_map(arg_to_string),
+        )
+        .await;
+    let mut db = store.db(client.db_index);
 
     // Collect all sorted sets
     let mut zsets: Vec<Vec<Vec<u8>>> = Vec::new();
@@ -1760,8 +1764,13 @@ pub async fn cmd_zmpop(args: &[RespValue], store: &SharedStore, client: &ClientS
         }
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(
+            client.db_index,
+            args[1..1 + numkeys].iter().filter_map(arg_to_string),
+        )
+        .await;
+    let mut db = store.db(client.db_index);
 
     // Find first non-empty sorted set
     for j in 0..numkeys {
@@ -1836,8 +1845,8 @@ pub async fn cmd_zrandmember(
         None => return RespValue::null_bulk_string(),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -1934,19 +1943,63 @@ pub async fn cmd_zscan(args: &[RespValue], store: &SharedStore, client: &ClientS
         }
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let Some(cursor) = parse_cursor(&args[1]) else {
+        return RespValue::error("ERR invalid cursor");
+    };
+
+    // Parse optional MATCH, COUNT, NOSCORES
+    let mut pattern: Option<String> = None;
+    let mut count = 10;
+    let mut noscores = false;
+    let mut i = 2;
+    while i < args.len() {
+        let opt = match arg_to_string(&args[i]) {
+            Some(s) => s.to_uppercase(),
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        match opt.as_str() {
+            "MATCH" => {
+                i += 1;
+                pattern = args.get(i).and_then(arg_to_string);
+            }
+            "COUNT" => {
+                i += 1;
+                count = match parse_scan_count(args.get(i)) {
+                    Ok(c) => c,
+                    Err(e) => return e,
+                };
+            }
+            "NOSCORES" => {
+                noscores = true;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
             RedisValue::SortedSet(zset) => {
                 let mut result = Vec::new();
-                for (member, score) in zset.iter() {
+                let next_cursor = zset.scan(cursor, count, |member, score| {
+                    if let Some(ref pat) = pattern
+                        && !crate::glob::glob_match(pat, &String::from_utf8_lossy(member))
+                    {
+                        return;
+                    }
                     result.push(RespValue::bulk_string(member.to_vec()));
-                    result.push(RespValue::bulk_string(format!("{score}").into_bytes()));
-                }
+                    if !noscores {
+                        result.push(RespValue::bulk_string(format!("{score}").into_bytes()));
+                    }
+                });
                 RespValue::array(vec![
-                    RespValue::bulk_string(b"0".to_vec()),
+                    RespValue::bulk_string(next_cursor.to_string().into_bytes()),
                     RespValue::array(result),
                 ])
             }
@@ -1981,8 +2034,8 @@ pub async fn cmd_zpopmin(
         1
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let is_zset = matches!(db.get(&key), Some(e) if matches!(&e.value, RedisValue::SortedSet(_)));
     if !is_zset {
@@ -2040,8 +2093,8 @@ pub async fn cmd_zpopmax(
         1
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let is_zset = matches!(db.get(&key), Some(e) if matches!(&e.value, RedisValue::SortedSet(_)));
     if !is_zset {
@@ -2096,8 +2149,8 @@ pub async fn cmd_zmscore(
         }
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -2151,8 +2204,8 @@ pub async fn cmd_zlexcount(
         None => return RespValue::error("ERR min or max not valid string range item"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -2230,13 +2283,18 @@ pub async fn cmd_bzpopmin(
 
     // Try immediate pop
     {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
-        if let Some(result) = try_zpop_from_keys(db, &keys, true) {
+        let mut store = store.lock_keys(client.db_index, &keys).await;
+        let mut db = store.db(client.db_index);
+        if let Some(result) = try_zpop_from_keys(&mut db, &keys, true) {
             return result;
         }
     }
 
+    // EXEC doesn't block: time out right away
+    if client.deny_blocking {
+        return RespValue::null_array();
+    }
+
     let timeout_dur = if timeout == 0.0 {
         Duration::from_secs(365 * 24 * 3600)
     } else {
@@ -2259,9 +2317,9 @@ pub async fn cmd_bzpopmin(
     }
 
     if notified {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
-        if let Some(result) = try_zpop_from_keys(db, &keys, true) {
+        let mut store = store.lock_keys(client.db_index, &keys).await;
+        let mut db = store.db(client.db_index);
+        if let Some(result) = try_zpop_from_keys(&mut db, &keys, true) {
             return result;
         }
     }
@@ -2294,13 +2352,18 @@ pub async fn cmd_bzpopmax(
 
     // Try immediate pop
     {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
-        if let Some(result) = try_zpop_from_keys(db, &keys, false) {
+        let mut store = store.lock_keys(client.db_index, &keys).await;
+        let mut db = store.db(client.db_index);
+        if let Some(result) = try_zpop_from_keys(&mut db, &keys, false) {
             return result;
         }
     }
 
+    // EXEC doesn't block: time out right away
+    if client.deny_blocking {
+        return RespValue::null_array();
+    }
+
     let timeout_dur = if timeout == 0.0 {
         Duration::from_secs(365 * 24 * 3600)
     } else {
@@ -2323,9 +2386,9 @@ pub async fn cmd_bzpopmax(
     }
 
     if notified {
-        let mut store = store.write().await;
-        let db = store.db(client.db_index);
-        if let Some(result) = try_zpop_from_keys(db, &keys, false) {
+        let mut store = store.lock_keys(client.db_index, &keys).await;
+        let mut db = store.db(client.db_index);
+        if let Some(result) = try_zpop_from_keys(&mut db, &keys, false) {
             return result;
         }
     }
@@ -138,10 +138,10 @@ pub async fn cmd_xadd(
         fields.push((field, value));
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
-    let stream = match get_or_create_stream(db, &key) {
+    let stream = match get_or_create_stream(&mut db, &key) {
         Ok(s) => s,
         Err(e) => return e,
     };
@@ -179,8 +179,8 @@ pub async fn cmd_xlen(args: &[RespValue], store: &SharedStore, client: &ClientSt
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -241,8 +241,8 @@ pub async fn cmd_xrange(
         }
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -315,8 +315,8 @@ pub async fn cmd_xrevrange(
         }
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -400,8 +400,8 @@ pub async fn cmd_xread(args: &[RespValue], store: &SharedStore, client: &ClientS
         }
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, &keys).await;
+    let mut db = store.db(client.db_index);
 
     let mut results = Vec::with_capacity(half);
     let mut any_results = false;
@@ -450,7 +450,7 @@ pub async fn cmd_xread(args: &[RespValue], store: &SharedStore, client: &ClientS
                         .collect();
 
                     results.push(RespValue::array(vec![
-                        RespValue::bulk_string(key.as_bytes()),
+                        RespValue::bulk_string(key.clone()),
                         RespValue::array(resp_entries),
                     ]));
                 }
@@ -459,7 +459,7 @@ pub async fn cmd_xread(args: &[RespValue], store: &SharedStore, client: &ClientS
             None => {
                 // Key doesn't exist — return empty array for this stream
                 results.push(RespValue::array(vec![
-                    RespValue::bulk_string(key.as_bytes()),
+                    RespValue::bulk_string(key.clone()),
                     RespValue::array(vec![]),
                 ]));
             }
@@ -496,8 +496,8 @@ pub async fn cmd_xdel(args: &[RespValue], store: &SharedStore, client: &ClientSt
         }
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -532,8 +532,8 @@ pub async fn cmd_xinfo(args: &[RespValue], store: &SharedStore, client: &ClientS
                 None => return RespValue::error("ERR invalid key"),
             };
 
-            let mut store = store.write().await;
-            let db = store.db(client.db_index);
+            let mut store = store.lock_keys(client.db_index, [&key]).await;
+            let mut db = store.db(client.db_index);
 
             match db.get(&key) {
                 Some(entry) => match &entry.value {
@@ -574,8 +574,8 @@ pub async fn cmd_xinfo(args: &[RespValue], store: &SharedStore, client: &ClientS
                 None => return RespValue::error("ERR invalid key"),
             };
 
-            let mut store = store.write().await;
-            let db = store.db(client.db_index);
+            let mut store = store.lock_keys(client.db_index, [&key]).await;
+            let mut db = store.db(client.db_index);
 
             match db.get(&key) {
                 Some(entry) => match &entry.value {
@@ -637,8 +637,8 @@ pub async fn cmd_xinfo(args: &[RespValue], store: &SharedStore, client: &ClientS
                 None => return RespValue::error("ERR invalid group name"),
             };
 
-            let mut store = store.write().await;
-            let db = store.db(client.db_index);
+            let mut store = store.lock_keys(client.db_index, [&key]).await;
+            let mut db = store.db(client.db_index);
 
             match db.get(&key) {
                 Some(entry) => match &entry.value {
@@ -724,8 +724,8 @@ pub async fn cmd_xtrim(args: &[RespValue], store: &SharedStore, client: &ClientS
         _ => return RespValue::error("ERR value is not an integer or out of range"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -794,8 +794,8 @@ async fn cmd_xgroup_create(
             .map(|s| s.eq_ignore_ascii_case("MKSTREAM"))
             .unwrap_or(false);
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     // Check if key exists
     if !db.exists(&key) {
@@ -859,8 +859,8 @@ async fn cmd_xgroup_destroy(
         None => return RespValue::error("ERR invalid group name"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -900,8 +900,8 @@ async fn cmd_xgroup_createconsumer(
         None => return RespValue::error("ERR invalid consumer name"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -941,8 +941,8 @@ async fn cmd_xgroup_delconsumer(
         None => return RespValue::error("ERR invalid consumer name"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -982,8 +982,8 @@ async fn cmd_xgroup_setid(
         None => return RespValue::error("ERR invalid stream ID"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -1144,8 +1144,11 @@ pub async fn cmd_xreadgroup(
     }
 
     // If BLOCK specified and using ">" for at least one key, block and wait
+    // EXEC doesn't block: time out right away
     let uses_new = ids.iter().any(|id| id == ">");
-    if let Some(timeout_ms) = block {
+    if let Some(timeout_ms) = block
+        && !client.deny_blocking
+    {
         if uses_new {
             let notify = {
                 let mut watcher = key_watcher.write().await;
@@ -1195,8 +1198,8 @@ async fn xreadgroup_attempt(
     count: Option<usize>,
     noack: bool,
 ) -> Result<Option<RespValue>, RespValue> {
-    let mut store_guard = store.write().await;
-    let db = store_guard.db(client.db_index);
+    let mut store_guard = store.lock_keys(client.db_index, keys).await;
+    let mut db = store_guard.db(client.db_index);
 
     let mut results = Vec::with_capacity(keys.len());
     let mut any_results = false;
@@ -1229,7 +1232,7 @@ async fn xreadgroup_attempt(
                                 .collect();
 
                             results.push(RespValue::array(vec![
-                                RespValue::bulk_string(key.as_bytes()),
+                                RespValue::bulk_string(key.clone()),
                                 RespValue::array(resp_entries),
                             ]));
                         }
@@ -1285,8 +1288,8 @@ pub async fn cmd_xack(args: &[RespValue], store: &SharedStore, client: &ClientSt
         }
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -1399,8 +1402,8 @@ pub async fn cmd_xclaim(
         return wrong_arg_count("xclaim");
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -1514,8 +1517,8 @@ pub async fn cmd_xautoclaim(
         idx += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -1585,8 +1588,8 @@ pub async fn cmd_xpending(
         None => return RespValue::error("ERR invalid group name"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -1,5 +1,6 @@
 use crate::command::{
-    arg_to_bytes, arg_to_f64, arg_to_i64, arg_to_string, wrong_arg_count, wrong_type_error,
+    arg_to_bytes, arg_to_f64, arg_to_i64, arg_to_shared_bytes, arg_to_string, wrong_arg_count,
+    wrong_type_error,
 };
 use crate::connection::ClientState;
 use crate::resp::RespValue;
@@ -17,11 +18,11 @@ pub async fn cmd_get(args: &[RespValue], store: &SharedStore, client: &ClientSta
         None => return RespValue::null_bulk_string(),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
     match db.get(&key) {
         Some(entry) => match &entry.value {
-            RedisValue::String(s) => RespValue::bulk_string(s.as_bytes().to_vec()),
+            RedisValue::String(s) => RespValue::bulk_string(s.to_bytes()),
             _ => wrong_type_error(),
         },
         None => RespValue::null_bulk_string(),
@@ -36,8 +37,8 @@ pub async fn cmd_set(args: &[RespValue], store: &SharedStore, client: &ClientSta
         Some(k) => k,
         None => return RespValue::error("ERR invalid key"),
     };
-    let value = match arg_to_bytes(&args[1]) {
-        Some(v) => v.to_vec(),
+    let value = match arg_to_shared_bytes(&args[1]) {
+        Some(v) => v,
         None => return RespValue::error("ERR invalid value"),
     };
 
@@ -141,14 +142,14 @@ pub async fn cmd_set(args: &[RespValue], store: &SharedStore, client: &ClientSta
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     // GET option: return old value
     let old_value = if get {
         match db.get(&key) {
             Some(entry) => match &entry.value {
-                RedisValue::String(s) => Some(RespValue::bulk_string(s.as_bytes().to_vec())),
+                RedisValue::String(s) => Some(RespValue::bulk_string(s.to_bytes())),
                 _ => return wrong_type_error(),
             },
             None => Some(RespValue::null_bulk_string()),
@@ -254,17 +255,17 @@ pub async fn cmd_getset(
         Some(k) => k,
         None => return RespValue::error("ERR invalid key"),
     };
-    let value = match arg_to_bytes(&args[1]) {
-        Some(v) => v.to_vec(),
+    let value = match arg_to_shared_bytes(&args[1]) {
+        Some(v) => v,
         None => return RespValue::error("ERR invalid value"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let old = match db.get(&key) {
         Some(entry) => match &entry.value {
-            RedisValue::String(s) => RespValue::bulk_string(s.as_bytes().to_vec()),
+            RedisValue::String(s) => RespValue::bulk_string(s.to_bytes()),
             _ => return wrong_type_error(),
         },
         None => RespValue::null_bulk_string(),
@@ -279,7 +280,9 @@ pub async fn cmd_mget(args: &[RespValue], store: &SharedStore, client: &ClientSt
         return wrong_arg_count("mget");
     }
 
-    let mut store = store.write().await;
+    let store = store
+        .read_keys(client.db_index, args.iter().filter_map(arg_to_string))
+        .await;
     let db = store.db(client.db_index);
     let mut results = Vec::with_capacity(args.len());
 
@@ -294,7 +297,7 @@ pub async fn cmd_mget(args: &[RespValue], store: &SharedStore, client: &ClientSt
         match db.get(&key) {
             Some(entry) => match &entry.value {
                 RedisValue::String(s) => {
-                    results.push(RespValue::bulk_string(s.as_bytes().to_vec()));
+                    results.push(RespValue::bulk_string(s.to_bytes()));
                 }
                 _ => results.push(RespValue::null_bulk_string()),
             },
@@ -310,16 +313,21 @@ pub async fn cmd_mset(args: &[RespValue], store: &SharedStore, client: &ClientSt
         return wrong_arg_count("mset");
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(
+            client.db_index,
+            args.iter().step_by(2).filter_map(arg_to_string),
+        )
+        .await;
+    let mut db = store.db(client.db_index);
 
     for pair in args.chunks(2) {
         let key = match arg_to_string(&pair[0]) {
             Some(k) => k,
             None => continue,
         };
-        let value = match arg_to_bytes(&pair[1]) {
-            Some(v) => v.to_vec(),
+        let value = match arg_to_shared_bytes(&pair[1]) {
+            Some(v) => v,
             None => continue,
         };
         db.set(key, Entry::new(RedisValue::String(RedisString::new(value))));
@@ -337,8 +345,13 @@ pub async fn cmd_msetnx(
         return wrong_arg_count("msetnx");
     }
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store
+        .lock_keys(
+            client.db_index,
+            args.iter().step_by(2).filter_map(arg_to_string),
+        )
+        .await;
+    let mut db = store.db(client.db_index);
 
     // Check if any key exists
     for pair in args.chunks(2) {
@@ -357,8 +370,8 @@ pub async fn cmd_msetnx(
             Some(k) => k,
             None => continue,
         };
-        let value = match arg_to_bytes(&pair[1]) {
-            Some(v) => v.to_vec(),
+        let value = match arg_to_shared_bytes(&pair[1]) {
+            Some(v) => v,
             None => continue,
         };
         db.set(key, Entry::new(RedisValue::String(RedisString::new(value))));
@@ -379,13 +392,13 @@ pub async fn cmd_append(
         Some(k) => k,
         None => return RespValue::error("ERR invalid key"),
     };
-    let value = match arg_to_bytes(&args[1]) {
-        Some(v) => v.to_vec(),
+    let value = match arg_to_shared_bytes(&args[1]) {
+        Some(v) => v,
         None => return RespValue::error("ERR invalid value"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -416,7 +429,7 @@ pub async fn cmd_strlen(
         None => return RespValue::integer(0),
     };
 
-    let mut store = store.write().await;
+    let store = store.read_keys(client.db_index, [&key]).await;
     let db = store.db(client.db_index);
 
     match db.get(&key) {
@@ -442,8 +455,8 @@ async fn incr_decr(
         None => return RespValue::error("ERR invalid key"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -524,8 +537,8 @@ pub async fn cmd_incrbyfloat(
         None => return RespValue::error("ERR value is not a valid float"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -553,13 +566,13 @@ pub async fn cmd_setnx(args: &[RespValue], store: &SharedStore, client: &ClientS
         Some(k) => k,
         None => return RespValue::error("ERR invalid key"),
     };
-    let value = match arg_to_bytes(&args[1]) {
-        Some(v) => v.to_vec(),
+    let value = match arg_to_shared_bytes(&args[1]) {
+        Some(v) => v,
         None => return RespValue::error("ERR invalid value"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     if db.exists(&key) {
         RespValue::integer(0)
@@ -581,13 +594,13 @@ pub async fn cmd_setex(args: &[RespValue], store: &SharedStore, client: &ClientS
         Some(n) if n > 0 => n as u64,
         _ => return RespValue::error("ERR invalid expire time in 'setex' command"),
     };
-    let value = match arg_to_bytes(&args[2]) {
-        Some(v) => v.to_vec(),
+    let value = match arg_to_shared_bytes(&args[2]) {
+        Some(v) => v,
         None => return RespValue::error("ERR invalid value"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let entry = Entry::with_expiry(
         RedisValue::String(RedisString::new(value)),
@@ -613,13 +626,13 @@ pub async fn cmd_psetex(
         Some(n) if n > 0 => n as u64,
         _ => return RespValue::error("ERR invalid expire time in 'psetex' command"),
     };
-    let value = match arg_to_bytes(&args[2]) {
-        Some(v) => v.to_vec(),
+    let value = match arg_to_shared_bytes(&args[2]) {
+        Some(v) => v,
         None => return RespValue::error("ERR invalid value"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let entry = Entry::with_expiry(
         RedisValue::String(RedisString::new(value)),
@@ -650,8 +663,8 @@ pub async fn cmd_getrange(
         None => return RespValue::error("ERR value is not an integer or out of range"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get(&key) {
         Some(entry) => match &entry.value {
@@ -681,13 +694,13 @@ pub async fn cmd_setrange(
         Some(n) if n >= 0 => n as usize,
         _ => return RespValue::error("ERR value is not an integer or out of range"),
     };
-    let value = match arg_to_bytes(&args[2]) {
-        Some(v) => v.to_vec(),
+    let value = match arg_to_shared_bytes(&args[2]) {
+        Some(v) => v,
         None => return RespValue::error("ERR invalid value"),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     match db.get_mut(&key) {
         Some(entry) => match &mut entry.value {
@@ -727,12 +740,12 @@ pub async fn cmd_getdel(
         None => return RespValue::null_bulk_string(),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let result = match db.get(&key) {
         Some(entry) => match &entry.value {
-            RedisValue::String(s) => RespValue::bulk_string(s.as_bytes().to_vec()),
+            RedisValue::String(s) => RespValue::bulk_string(s.to_bytes()),
             _ => return wrong_type_error(),
         },
         None => return RespValue::null_bulk_string(),
@@ -752,12 +765,12 @@ pub async fn cmd_getex(args: &[RespValue], store: &SharedStore, client: &ClientS
         None => return RespValue::null_bulk_string(),
     };
 
-    let mut store = store.write().await;
-    let db = store.db(client.db_index);
+    let mut store = store.lock_keys(client.db_index, [&key]).await;
+    let mut db = store.db(client.db_index);
 
     let result = match db.get(&key) {
         Some(entry) => match &entry.value {
-            RedisValue::String(s) => RespValue::bulk_string(s.as_bytes().to_vec()),
+            RedisValue::String(s) => RespValue::bulk_string(s.to_bytes()),
             _ => return wrong_type_error(),
         },
         None => return RespValue::null_bulk_string(),
@@ -934,8 +947,10 @@ pub async fn cmd_msetex(
     }
 
     let now = now_millis();
-    let mut store = store.write().await;
-    let db = &mut store.databases[client.db_index];
+    let mut store = store
+        .lock_keys(client.db_index, pairs.iter().map(|(key, _)| key))
+        .await;
+    let mut db = store.db(client.db_index);
 
     if nx {
         for (key, _) in &pairs {
@@ -1013,8 +1028,8 @@ pub async fn cmd_lcs(args: &[RespValue], store: &SharedStore, client: &ClientSta
         i += 1;
     }
 
-    let mut store = store.write().await;
-    let db = &mut store.databases[client.db_index];
+    let mut store = store.lock_keys(client.db_index, [&key1, &key2]).await;
+    let mut db = store.db(client.db_index);
     let a = db
         .get(&key1)
         .and_then(|e| match &e.value {
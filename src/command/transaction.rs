@@ -1,12 +1,13 @@
 use crate::config::SharedConfig;
 use crate::connection::ClientState;
 use crate::keywatcher::SharedKeyWatcher;
+use crate::latency::SharedLatencyMonitor;
 use crate::pubsub::SharedPubSub;
 use crate::replication::SharedReplicationState;
 use crate::resp::RespValue;
 use crate::scripting::ScriptCache;
 use crate::slowlog::{SharedLastSaveTime, SharedSlowLog};
-use crate::store::SharedStore;
+use crate::store::{SharedStore, shard_id};
 use tokio::sync::mpsc;
 
 pub fn cmd_multi(client: &mut ClientState) -> RespValue {
@@ -31,6 +32,7 @@ pub fn cmd_exec<'a>(
     repl_state: &'a SharedReplicationState,
     last_save_time: &'a SharedLastSaveTime,
     slowlog: &'a SharedSlowLog,
+    latency: &'a SharedLatencyMonitor,
 ) -> std::pin::Pin<Box<dyn std::future::Future<Output = RespValue> + Send + 'a>> {
     Box::pin(async move {
         if !client.in_multi {
@@ -48,35 +50,33 @@ pub fn cmd_exec<'a>(
             return RespValue::error("EXECABORT Transaction discarded because of previous errors.");
         }
 
+        // Lock every shard the transaction touches, watched keys included, so
+        // the WATCH check and the queued commands see one consistent state.
+        let mut guard = match transaction_shards(client) {
+            Some(ids) => store.lock_shards(ids).await,
+            None => store.lock_all().await,
+        };
+
         // Check WATCH: compare key state (alive/dead + version).
         // A key's "state" is whether it logically exists (not expired) and its version.
         // If the state at WATCH time matches the state at EXEC time, no conflict.
-        if !client.watched_keys.is_empty() {
-            let store_guard = store.read().await;
-            for (db_index, key, saved_version, _saved_global, alive_at_watch) in
-                &client.watched_keys
-            {
-                let db = &store_guard.databases[*db_index];
-                let alive_now = db.key_alive(key);
-                let dirty = if *alive_at_watch && alive_now {
-                    // Key was alive at WATCH and is still alive: check version
-                    db.key_version(key) != *saved_version
-                } else if !*alive_at_watch && !alive_now {
-                    // Key was dead at WATCH and is still dead: no change
-                    false
-                } else {
-                    // Key's existence status changed (alive→dead or dead→alive)
-                    true
-                };
-                if dirty {
-                    drop(store_guard);
-                    client.multi_queue.clear();
-                    client.watched_keys.clear();
-                    client.watch_dirty = false;
-                    return RespValue::null_array();
-                }
+        for (db_index, key, saved_version, alive_at_watch) in &client.watched_keys {
+            let db = guard.db(*db_index);
+            let alive_now = db.key_alive(key);
+            let dirty = if *alive_at_watch && alive_now {
+                // Key was alive at WATCH and is still alive: check version
+                db.key_version(key) != *saved_version
+            } else if !*alive_at_watch && !alive_now {
+                // Key was dead at WATCH and is still dead: no change
+                false
+            } else {
+                // Key's existence status changed (alive→dead or dead→alive)
+                true
+            };
+            if dirty {
+                client.watch_dirty = true;
+                break;
             }
-            drop(store_guard);
         }
 
         if client.watch_dirty {
@@ -90,30 +90,54 @@ pub fn cmd_exec<'a>(
         client.watched_keys.clear();
         client.watch_dirty = false;
 
-        let mut results = Vec::with_capacity(queue.len());
-        for (cmd_name, args) in queue {
-            let result = crate::command::dispatch(
-                &cmd_name,
-                &args,
-                store,
-                config,
-                client,
-                pubsub,
-                pubsub_tx,
-                key_watcher,
-                script_cache,
-                repl_state,
-                last_save_time,
-                slowlog,
-            )
-            .await;
-            results.push(result);
-        }
+        client.deny_blocking = true;
+        let results = crate::store::hold(guard, async {
+            let mut results = Vec::with_capacity(queue.len());
+            for (cmd_name, args) in queue {
+                let started = std::time::Instant::now();
+                let result = crate::command::dispatch(
+                    &cmd_name,
+                    &args,
+                    store,
+                    config,
+                    client,
+                    pubsub,
+                    pubsub_tx,
+                    key_watcher,
+                    script_cache,
+                    repl_state,
+                    last_save_time,
+                    slowlog,
+                    latency,
+                )
+                .await;
+                crate::command::record_call(latency, &cmd_name, started.elapsed(), &result);
+                results.push(result);
+            }
+            results
+        })
+        .await;
+        client.deny_blocking = false;
 
         RespValue::array(results)
     })
 }
 
+/// Shards holding the watched keys and every key the queued commands touch,
+/// or `None` when some command needs the whole keyspace.
+fn transaction_shards(client: &ClientState) -> Option<Vec<usize>> {
+    let mut ids: Vec<usize> = client
+        .watched_keys
+        .iter()
+        .map(|(db, key, ..)| shard_id(*db, key))
+        .collect();
+    for (cmd_name, args) in &client.multi_queue {
+        let keys = crate::command::command_keys(cmd_name, args)?;
+        ids.extend(keys.iter().map(|key| shard_id(client.db_index, key)));
+    }
+    Some(ids)
+}
+
 pub fn cmd_discard(client: &mut ClientState) -> RespValue {
     if !client.in_multi {
         return RespValue::error("ERR DISCARD without MULTI");
@@ -138,18 +162,17 @@ pub async fn cmd_watch(
         return RespValue::error("ERR WATCH inside MULTI is not allowed");
     }
 
-    let store_guard = store.read().await;
-    let db = &store_guard.databases[client.db_index];
-    let global_ver = db.global_version();
-
-    for arg in args {
-        if let Some(key) = crate::command::arg_to_string(arg) {
-            let alive = db.key_alive(&key);
-            let ver = db.key_version(&key);
-            client
-                .watched_keys
-                .push((client.db_index, key, ver, global_ver, alive));
-        }
+    let keys: Vec<String> = args
+        .iter()
+        .filter_map(crate::command::arg_to_string)
+        .collect();
+    let mut store = store.lock_keys(client.db_index, &keys).await;
+    let db = store.db(client.db_index);
+
+    for key in keys {
+        let alive = db.key_alive(&key);
+        let ver = db.key_version(&key);
+        client.watched_keys.push((client.db_index, key, ver, alive));
     }
 
     RespValue::ok()
@@ -16,6 +16,8 @@ pub struct Config {
     pub dir: String,
     pub appendonly: bool,
     pub appendfsync: String,
+    /// Load an AOF whose last command is cut short, truncating the partial tail.
+    pub aof_load_truncated: bool,
     pub save_rules: Vec<(u64, u64)>,
     // Memory
     pub maxmemory: u64,
@@ -30,17 +32,48 @@ pub struct Config {
     pub list_compress_depth: i64,
     pub zset_max_listpack_entries: u64,
     pub zset_max_listpack_value: u64,
+    /// Largest sparse HyperLogLog, header included, before it turns dense.
+    pub hll_sparse_max_bytes: u64,
     // Slowlog
     /// Slowlog threshold in microseconds. Commands slower than this are logged. -1 to disable.
     pub slowlog_log_slower_than: i64,
     /// Maximum number of slowlog entries to keep.
     pub slowlog_max_len: usize,
+    /// Record latency events at or above this many milliseconds. 0 disables.
+    pub latency_monitor_threshold: u64,
+    /// Free values in the background when they are evicted, expired,
+    /// deleted as a side effect of a command, or deleted with DEL.
+    pub lazyfree_lazy_eviction: bool,
+    pub lazyfree_lazy_expire: bool,
+    pub lazyfree_lazy_server_del: bool,
+    pub lazyfree_lazy_user_del: bool,
+    /// How hard active expiration works to reclaim expired keys, 1-10.
+    pub active_expire_effort: u64,
     // Debug flags
     pub active_expire_enabled: bool,
     // Replication
     pub replicaof: Option<(String, u16)>,
     pub replica_read_only: bool,
+    /// Keep answering queries while the link to the master is down.
+    pub replica_serve_stale_data: bool,
+    /// Password (and ACL user) a replica authenticates with to its master.
+    pub masterauth: Option<String>,
+    pub masteruser: Option<String>,
+    /// Sentinels promote replicas with a lower priority first; 0 means never.
+    pub replica_priority: u64,
     pub repl_backlog_size: usize,
+    /// Stream the RDB for a full resync straight to the socket instead of via a temp file.
+    pub repl_diskless_sync: bool,
+    /// How a replica loads the master's RDB: "disabled" (via a temp file),
+    /// "on-empty-db" or "swapdb" (parse straight from the socket).
+    pub repl_diskless_load: String,
+    /// Refuse writes unless this many replicas are connected with acceptable lag. 0 disables.
+    pub min_replicas_to_write: usize,
+    /// Maximum seconds since a replica's last ACK for it to count towards min-replicas-to-write.
+    pub min_replicas_max_lag: u64,
+    // Monitoring
+    /// Serve Prometheus metrics over HTTP on this port. 0 disables.
+    pub metrics_port: u16,
 }
 
 impl Default for Config {
@@ -58,6 +91,7 @@ impl Default for Config {
             dir: ".".to_string(),
             appendonly: false,
             appendfsync: "everysec".to_string(),
+            aof_load_truncated: true,
             save_rules: vec![(900, 1), (300, 10), (60, 10000)],
             maxmemory: 0,
             maxmemory_policy: "noeviction".to_string(),
@@ -70,12 +104,28 @@ impl Default for Config {
             list_compress_depth: 0,
             zset_max_listpack_entries: 128,
             zset_max_listpack_value: 64,
+            hll_sparse_max_bytes: 3000,
             slowlog_log_slower_than: 10_000, // 10ms in microseconds (Redis default)
             slowlog_max_len: 128,
+            latency_monitor_threshold: 0,
+            lazyfree_lazy_eviction: false,
+            lazyfree_lazy_expire: false,
+            lazyfree_lazy_server_del: false,
+            lazyfree_lazy_user_del: false,
+            active_expire_effort: 1,
             active_expire_enabled: true,
             replicaof: None,
             replica_read_only: true,
+            replica_serve_stale_data: true,
+            masterauth: None,
+            masteruser: None,
+            replica_priority: 100,
             repl_backlog_size: 1_048_576, // 1MB
+            repl_diskless_sync: true,
+            repl_diskless_load: "disabled".to_string(),
+            min_replicas_to_write: 0,
+            min_replicas_max_lag: 10,
+            metrics_port: 0,
         }
     }
 }
@@ -86,96 +136,148 @@ impl Config {
         let mut i = 0;
         while i < args.len() {
             match args[i].as_str() {
-                "--port" => {
-                    if i + 1 < args.len() {
-                        if let Ok(p) = args[i + 1].parse() {
-                            config.port = p;
-                        }
-                        i += 1;
+                "--port" if i + 1 < args.len() => {
+                    if let Ok(p) = args[i + 1].parse() {
+                        config.port = p;
                     }
+                    i += 1;
                 }
-                "--bind" => {
-                    if i + 1 < args.len() {
-                        config.bind = args[i + 1].clone();
-                        i += 1;
+                "--metrics-port" if i + 1 < args.len() => {
+                    if let Ok(p) = args[i + 1].parse() {
+                        config.metrics_port = p;
                     }
+                    i += 1;
+                }
+                "--bind" if i + 1 < args.len() => {
+                    config.bind = args[i + 1].clone();
+                    i += 1;
+                }
+                "--requirepass" if i + 1 < args.len() => {
+                    config.requirepass = Some(args[i + 1].clone());
+                    i += 1;
+                }
+                "--dbfilename" if i + 1 < args.len() => {
+                    config.dbfilename = args[i + 1].clone();
+                    i += 1;
+                }
+                "--dir" if i + 1 < args.len() => {
+                    config.dir = args[i + 1].clone();
+                    i += 1;
                 }
-                "--requirepass" => {
-                    if i + 1 < args.len() {
-                        config.requirepass = Some(args[i + 1].clone());
-                        i += 1;
+                "--appendonly" if i + 1 < args.len() => {
+                    config.appendonly = args[i + 1] == "yes";
+                    i += 1;
+                }
+                "--aof-load-truncated" if i + 1 < args.len() => {
+                    config.aof_load_truncated = args[i + 1] == "yes";
+                    i += 1;
+                }
+                "--databases" if i + 1 < args.len() => {
+                    if let Ok(d) = args[i + 1].parse() {
+                        config.databases = d;
                     }
+                    i += 1;
                 }
-                "--dbfilename" => {
-                    if i + 1 < args.len() {
-                        config.dbfilename = args[i + 1].clone();
-                        i += 1;
+                "--timeout" if i + 1 < args.len() => {
+                    if let Ok(t) = args[i + 1].parse() {
+                        config.timeout = t;
                     }
+                    i += 1;
                 }
-                "--dir" => {
-                    if i + 1 < args.len() {
-                        config.dir = args[i + 1].clone();
-                        i += 1;
+                "--loglevel" if i + 1 < args.len() => {
+                    config.loglevel = args[i + 1].clone();
+                    i += 1;
+                }
+                "--hz" if i + 1 < args.len() => {
+                    if let Ok(h) = args[i + 1].parse() {
+                        config.hz = h;
                     }
+                    i += 1;
+                }
+                "--lazyfree-lazy-eviction" if i + 1 < args.len() => {
+                    config.lazyfree_lazy_eviction = args[i + 1] == "yes";
+                    i += 1;
                 }
-                "--appendonly" => {
-                    if i + 1 < args.len() {
-                        config.appendonly = args[i + 1] == "yes";
-                        i += 1;
+                "--lazyfree-lazy-expire" if i + 1 < args.len() => {
+                    config.lazyfree_lazy_expire = args[i + 1] == "yes";
+                    i += 1;
+                }
+                "--lazyfree-lazy-server-del" if i + 1 < args.len() => {
+                    config.lazyfree_lazy_server_del = args[i + 1] == "yes";
+                    i += 1;
+                }
+                "--lazyfree-lazy-user-del" if i + 1 < args.len() => {
+                    config.lazyfree_lazy_user_del = args[i + 1] == "yes";
+                    i += 1;
+                }
+                "--active-expire-effort" if i + 1 < args.len() => {
+                    if let Ok(effort @ 1..=10) = args[i + 1].parse() {
+                        config.active_expire_effort = effort;
                     }
+                    i += 1;
                 }
-                "--databases" => {
-                    if i + 1 < args.len() {
-                        if let Ok(d) = args[i + 1].parse() {
-                            config.databases = d;
-                        }
-                        i += 1;
+                "--latency-monitor-threshold" if i + 1 < args.len() => {
+                    if let Ok(ms) = args[i + 1].parse() {
+                        config.latency_monitor_threshold = ms;
                     }
+                    i += 1;
                 }
-                "--timeout" => {
-                    if i + 1 < args.len() {
-                        if let Ok(t) = args[i + 1].parse() {
-                            config.timeout = t;
+                "--replicaof" | "--slaveof" if i + 2 < args.len() => {
+                    let host = args[i + 1].clone();
+                    if let Ok(port) = args[i + 2].parse::<u16>() {
+                        if host.eq_ignore_ascii_case("no")
+                            && args[i + 2].eq_ignore_ascii_case("one")
+                        {
+                            config.replicaof = None;
+                        } else {
+                            config.replicaof = Some((host, port));
                         }
-                        i += 1;
                     }
+                    i += 2;
                 }
-                "--loglevel" => {
-                    if i + 1 < args.len() {
-                        config.loglevel = args[i + 1].clone();
-                        i += 1;
+                "--repl-backlog-size" if i + 1 < args.len() => {
+                    if let Ok(s) = args[i + 1].parse() {
+                        config.repl_backlog_size = s;
                     }
+                    i += 1;
                 }
-                "--hz" => {
-                    if i + 1 < args.len() {
-                        if let Ok(h) = args[i + 1].parse() {
-                            config.hz = h;
-                        }
-                        i += 1;
+                "--replica-serve-stale-data" | "--slave-serve-stale-data" if i + 1 < args.len() => {
+                    config.replica_serve_stale_data = args[i + 1] == "yes";
+                    i += 1;
+                }
+                "--masterauth" if i + 1 < args.len() => {
+                    config.masterauth = Some(args[i + 1].clone());
+                    i += 1;
+                }
+                "--masteruser" if i + 1 < args.len() => {
+                    config.masteruser = Some(args[i + 1].clone());
+                    i += 1;
+                }
+                "--replica-priority" | "--slave-priority" if i + 1 < args.len() => {
+                    if let Ok(n) = args[i + 1].parse() {
+                        config.replica_priority = n;
                     }
+                    i += 1;
                 }
-                "--replicaof" | "--slaveof" => {
-                    if i + 2 < args.len() {
-                        let host = args[i + 1].clone();
-                        if let Ok(port) = args[i + 2].parse::<u16>() {
-                            if host.eq_ignore_ascii_case("no")
-                                && args[i + 2].eq_ignore_ascii_case("one")
-                            {
-                                config.replicaof = None;
-                            } else {
-                                config.replicaof = Some((host, port));
-                            }
-                        }
-                        i += 2;
+                "--repl-diskless-sync" if i + 1 < args.len() => {
+                    config.repl_diskless_sync = args[i + 1] == "yes";
+                    i += 1;
+                }
+                "--repl-diskless-load" if i + 1 < args.len() => {
+                    config.repl_diskless_load = args[i + 1].to_lowercase();
+                    i += 1;
+                }
+                "--min-replicas-to-write" | "--min-slaves-to-write" if i + 1 < args.len() => {
+                    if let Ok(n) = args[i + 1].parse() {
+                        config.min_replicas_to_write = n;
                     }
+                    i += 1;
                 }
-                "--repl-backlog-size" => {
-                    if i + 1 < args.len() {
-                        if let Ok(s) = args[i + 1].parse() {
-                            config.repl_backlog_size = s;
-                        }
-                        i += 1;
+                "--min-replicas-max-lag" | "--min-slaves-max-lag" if i + 1 < args.len() => {
+                    if let Ok(n) = args[i + 1].parse() {
+                        config.min_replicas_max_lag = n;
                     }
+                    i += 1;
                 }
                 _ => {}
             }
@@ -188,16 +290,53 @@ impl Config {
         match key.to_lowercase().as_str() {
             "bind" => Some(self.bind.clone()),
             "port" => Some(self.port.to_string()),
+            "metrics-port" => Some(self.metrics_port.to_string()),
             "databases" => Some(self.databases.to_string()),
             "requirepass" => self.requirepass.clone().or(Some(String::new())),
             "timeout" => Some(self.timeout.to_string()),
             "tcp-keepalive" => Some(self.tcp_keepalive.to_string()),
             "hz" => Some(self.hz.to_string()),
+            "active-expire-effort" => Some(self.active_expire_effort.to_string()),
+            "lazyfree-lazy-eviction" => Some(
+                if self.lazyfree_lazy_eviction {
+                    "yes"
+                } else {
+                    "no"
+                }
+                .to_string(),
+            ),
+            "lazyfree-lazy-expire" => Some(
+                if self.lazyfree_lazy_expire {
+                    "yes"
+                } else {
+                    "no"
+                }
+                .to_string(),
+            ),
+            "lazyfree-lazy-server-del" => Some(
+                if self.lazyfree_lazy_server_del {
+                    "yes"
+                } else {
+                    "no"
+                }
+                .to_string(),
+            ),
+            "lazyfree-lazy-user-del" => Some(
+                if self.lazyfree_lazy_user_del {
+                    "yes"
+                } else {
+                    "no"
+                }
+                .to_string(),
+            ),
             "loglevel" => Some(self.loglevel.clone()),
             "dbfilename" => Some(self.dbfilename.clone()),
             "dir" => Some(self.dir.clone()),
             "appendonly" => Some(if self.appendonly { "yes" } else { "no" }.to_string()),
             "appendfsync" => Some(self.appendfsync.clone()),
+            "aof-load-truncated" => {
+                Some(if self.aof_load_truncated { "yes" } else { "no" }.to_string())
+            }
             "maxmemory" => Some(self.maxmemory.to_string()),
             "maxmemory-policy" => Some(self.maxmemory_policy.clone()),
             "list-max-ziplist-size" | "list-max-listpack-size" => {
@@ -219,6 +358,7 @@ impl Config {
             "zset-max-ziplist-value" | "zset-max-listpack-value" => {
                 Some(self.zset_max_listpack_value.to_string())
             }
+            "hll-sparse-max-bytes" => Some(self.hll_sparse_max_bytes.to_string()),
             "save" => {
                 let s: Vec<String> = self
                     .save_rules
@@ -229,10 +369,32 @@ impl Config {
             }
             "slowlog-log-slower-than" => Some(self.slowlog_log_slower_than.to_string()),
             "slowlog-max-len" => Some(self.slowlog_max_len.to_string()),
+            "latency-monitor-threshold" => Some(self.latency_monitor_threshold.to_string()),
             "repl-backlog-size" => Some(self.repl_backlog_size.to_string()),
+            "replica-serve-stale-data" | "slave-serve-stale-data" => Some(
+                if self.replica_serve_stale_data {
+                    "yes"
+                } else {
+                    "no"
+                }
+                .to_string(),
+            ),
+            "masterauth" => self.masterauth.clone().or(Some(String::new())),
+            "masteruser" => self.masteruser.clone().or(Some(String::new())),
+            "replica-priority" | "slave-priority" => Some(self.replica_priority.to_string()),
+            "repl-diskless-sync" => {
+                Some(if self.repl_diskless_sync { "yes" } else { "no" }.to_string())
+            }
+            "repl-diskless-load" => Some(self.repl_diskless_load.clone()),
             "replica-read-only" | "slave-read-only" => {
                 Some(if self.replica_read_only { "yes" } else { "no" }.to_string())
             }
+            "min-replicas-to-write" | "min-slaves-to-write" => {
+                Some(self.min_replicas_to_write.to_string())
+            }
+            "min-replicas-max-lag" | "min-slaves-max-lag" => {
+                Some(self.min_replicas_max_lag.to_string())
+            }
             _ => None,
         }
     }
@@ -243,6 +405,29 @@ impl Config {
                 self.hz = value.parse().map_err(|_| "Invalid hz value".to_string())?;
                 Ok(())
             }
+            "lazyfree-lazy-eviction" => {
+                self.lazyfree_lazy_eviction = value == "yes";
+                Ok(())
+            }
+            "lazyfree-lazy-expire" => {
+                self.lazyfree_lazy_expire = value == "yes";
+                Ok(())
+            }
+            "lazyfree-lazy-server-del" => {
+                self.lazyfree_lazy_server_del = value == "yes";
+                Ok(())
+            }
+            "lazyfree-lazy-user-del" => {
+                self.lazyfree_lazy_user_del = value == "yes";
+                Ok(())
+            }
+            "active-expire-effort" => {
+                self.active_expire_effort = match value.parse() {
+                    Ok(effort @ 1..=10) => effort,
+                    _ => return Err("argument must be between 1 and 10 inclusive".to_string()),
+                };
+                Ok(())
+            }
             "timeout" => {
                 self.timeout = value
                     .parse()
@@ -271,6 +456,10 @@ impl Config {
                 self.appendfsync = value.to_string();
                 Ok(())
             }
+            "aof-load-truncated" => {
+                self.aof_load_truncated = value == "yes";
+                Ok(())
+            }
             "requirepass" => {
                 self.requirepass = if value.is_empty() {
                     None
@@ -324,6 +513,11 @@ impl Config {
                     value.parse().map_err(|_| "Invalid value".to_string())?;
                 Ok(())
             }
+            "hll-sparse-max-bytes" => {
+                self.hll_sparse_max_bytes =
+                    value.parse().map_err(|_| "Invalid value".to_string())?;
+                Ok(())
+            }
             "slowlog-log-slower-than" => {
                 self.slowlog_log_slower_than =
                     value.parse().map_err(|_| "Invalid value".to_string())?;
@@ -333,6 +527,48 @@ impl Config {
                 self.slowlog_max_len = value.parse().map_err(|_| "Invalid value".to_string())?;
                 Ok(())
             }
+            "latency-monitor-threshold" => {
+                self.latency_monitor_threshold =
+                    value.parse().map_err(|_| "Invalid value".to_string())?;
+                Ok(())
+            }
+            "replica-serve-stale-data" | "slave-serve-stale-data" => {
+                self.replica_serve_stale_data = value == "yes";
+                Ok(())
+            }
+            "masterauth" => {
+                self.masterauth = (!value.is_empty()).then(|| value.to_string());
+                Ok(())
+            }
+            "masteruser" => {
+                self.masteruser = (!value.is_empty()).then(|| value.to_string());
+                Ok(())
+            }
+            "replica-priority" | "slave-priority" => {
+                self.replica_priority = value.parse().map_err(|_| "Invalid value".to_string())?;
+                Ok(())
+            }
+            "repl-diskless-sync" => {
+                self.repl_diskless_sync = value == "yes";
+                Ok(())
+            }
+            "repl-diskless-load" => match value.to_lowercase().as_str() {
+                v @ ("disabled" | "on-empty-db" | "swapdb") => {
+                    self.repl_diskless_load = v.to_string();
+                    Ok(())
+                }
+                _ => Err("Invalid repl-diskless-load value".to_string()),
+            },
+            "min-replicas-to-write" | "min-slaves-to-write" => {
+                self.min_replicas_to_write =
+                    value.parse().map_err(|_| "Invalid value".to_string())?;
+                Ok(())
+            }
+            "min-replicas-max-lag" | "min-slaves-max-lag" => {
+                self.min_replicas_max_lag =
+                    value.parse().map_err(|_| "Invalid value".to_string())?;
+                Ok(())
+            }
             _ => {
                 // Accept unknown parameters silently for compatibility
                 Ok(())
@@ -24,10 +24,13 @@ pub struct ClientState {
     // Transaction state
     pub in_multi: bool,
     pub multi_queue: Vec<(String, Vec<RespValue>)>,
-    /// (db_index, key, version_at_watch_time, global_version_at_watch_time, alive_at_watch_time)
-    pub watched_keys: Vec<(usize, String, u64, u64, bool)>,
+    /// (db_index, key, version_at_watch_time, alive_at_watch_time)
+    pub watched_keys: Vec<(usize, String, u64, bool)>,
     pub watch_dirty: bool,
     pub multi_error: bool,
+    /// Set while EXEC runs the queue: blocking commands reply as if they had
+    /// timed out instead of waiting with the transaction's shards locked
+    pub deny_blocking: bool,
 
     // Pub/Sub state — number of active subscriptions (channels + patterns)
     pub subscriptions: usize,
@@ -37,6 +40,12 @@ pub struct ClientState {
 
     // Replication: true if this client is processing replicated commands from master
     pub is_replication_client: bool,
+
+    /// Replication offset right after this client's last write, used by WAIT/WAITAOF
+    pub woff: i64,
+
+    /// Port announced by a replica with `REPLCONF listening-port`
+    pub replica_listening_port: u16,
 }
 
 impl Default for ClientState {
@@ -58,9 +67,12 @@ impl ClientState {
             watched_keys: Vec::new(),
             watch_dirty: false,
             multi_error: false,
+            deny_blocking: false,
             subscriptions: 0,
             in_monitor: false,
             is_replication_client: false,
+            woff: 0,
+            replica_listening_port: 0,
         }
     }
 
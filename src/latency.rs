@@ -0,0 +1,614 @@
+use crate::config::SharedConfig;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Samples kept per event.
+const LATENCY_TS_LEN: usize = 160;
+
+/// Linear steps per power of two in a command histogram.
+const SUB_BUCKET_BITS: u64 = 4;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// Longer calls are counted as taking this long (~12.7 days).
+const MAX_TRACKED_USEC: u64 = 1 << 40;
+
+const HISTOGRAM_BUCKETS: usize = (SUB_BUCKETS * (40 - SUB_BUCKET_BITS + 1)) as usize;
+
+/// Distinct error prefixes tracked by INFO errorstats; replies with
+/// further new prefixes are only counted in `total_error_replies`.
+const MAX_ERROR_TYPES: usize = 128;
+
+/// Events sampled by the latency monitor.
+pub const COMMAND: &str = "command";
+pub const FAST_COMMAND: &str = "fast-command";
+pub const EXPIRE_CYCLE: &str = "expire-cycle";
+pub const EVICTION_CYCLE: &str = "eviction-cycle";
+pub const AOF_FSYNC: &str = "aof-fsync";
+pub const AOF_REWRITE: &str = "aof-rewrite";
+pub const RDB_SAVE: &str = "rdb-save";
+
+/// One latency spike: when it happened (unix seconds) and how long it took.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencySample {
+    pub time: u64,
+    pub latency_ms: u64,
+}
+
+/// The most recent spikes of one event, at most one per second.
+#[derive(Debug, Default)]
+pub struct LatencySeries {
+    pub samples: VecDeque<LatencySample>,
+    /// Highest latency ever recorded for the event.
+    pub max_ms: u64,
+}
+
+impl LatencySeries {
+    fn add(&mut self, time: u64, latency_ms: u64) {
+        self.max_ms = self.max_ms.max(latency_ms);
+        // Spikes within the same second are merged, keeping the worst
+        if let Some(last) = self.samples.back_mut()
+            && last.time == time
+        {
+            last.latency_ms = last.latency_ms.max(latency_ms);
+            return;
+        }
+        if self.samples.len() == LATENCY_TS_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(LatencySample { time, latency_ms });
+    }
+}
+
+/// Call counters and latency distribution of one command, as reported by
+/// INFO commandstats/latencystats and LATENCY HISTOGRAM.
+#[derive(Debug)]
+pub struct CommandStats {
+    pub calls: u64,
+    /// Total microseconds spent executing the command.
+    pub usec: u64,
+    /// Calls refused before execution (NOAUTH, READONLY, ...).
+    pub rejected_calls: u64,
+    /// Calls that executed but replied with an error.
+    pub failed_calls: u64,
+    /// Calls per duration, each power of two split into `SUB_BUCKETS`
+    /// linear steps so percentiles stay within ~6% of the real value.
+    buckets: Vec<u64>,
+}
+
+impl Default for CommandStats {
+    fn default() -> Self {
+        CommandStats {
+            calls: 0,
+            usec: 0,
+            rejected_calls: 0,
+            failed_calls: 0,
+            buckets: vec![0; HISTOGRAM_BUCKETS],
+        }
+    }
+}
+
+impl CommandStats {
+    /// Bucket of a call that took `micros`. Buckets cover `(lo, hi]` so
+    /// every one falls within a single power-of-two range.
+    fn bucket(micros: u64) -> usize {
+        let w = micros.saturating_sub(1).min(MAX_TRACKED_USEC - 1);
+        if w < SUB_BUCKETS {
+            return w as usize;
+        }
+        let exp = (u64::BITS - 1 - w.leading_zeros()) as u64;
+        let shift = exp - SUB_BUCKET_BITS;
+        (SUB_BUCKETS + shift * SUB_BUCKETS + ((w >> shift) - SUB_BUCKETS)) as usize
+    }
+
+    /// Largest duration, in microseconds, that lands in `bucket`.
+    fn bucket_upper(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < SUB_BUCKETS {
+            return bucket + 1;
+        }
+        let shift = (bucket - SUB_BUCKETS) / SUB_BUCKETS;
+        let sub = (bucket - SUB_BUCKETS) % SUB_BUCKETS;
+        (SUB_BUCKETS + sub + 1) << shift
+    }
+
+    fn record(&mut self, micros: u64) {
+        self.buckets[Self::bucket(micros)] += 1;
+        self.calls += 1;
+        self.usec += micros;
+    }
+
+    /// `(bucket upper bound in us, calls up to that bound)` for every
+    /// power-of-two bucket that received calls, in increasing order.
+    pub fn cumulative(&self) -> Vec<(u64, u64)> {
+        let mut out: Vec<(u64, u64)> = Vec::new();
+        let mut total = 0;
+        for (i, n) in self.buckets.iter().enumerate().filter(|(_, n)| **n > 0) {
+            total += n;
+            let bound = 1u64 << (u64::BITS - (Self::bucket_upper(i) - 1).leading_zeros());
+            match out.last_mut() {
+                Some(last) if last.0 == bound => last.1 = total,
+                _ => out.push((bound, total)),
+            }
+        }
+        out
+    }
+
+    /// Duration in microseconds that `percentile`% of the calls stayed
+    /// within, or `None` before the first call.
+    pub fn percentile(&self, percentile: f64) -> Option<u64> {
+        if self.calls == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0 * self.calls as f64).ceil() as u64).max(1);
+        let mut total = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            total += n;
+            if total >= rank {
+                return Some(Self::bucket_upper(i));
+            }
+        }
+        None
+    }
+}
+
+/// Latency spikes per event, per-command call statistics and error reply
+/// counts by prefix.
+#[derive(Debug, Default)]
+pub struct LatencyMonitor {
+    events: BTreeMap<String, LatencySeries>,
+    commands: HashMap<String, CommandStats>,
+    errors: BTreeMap<String, u64>,
+    total_error_replies: u64,
+}
+
+impl LatencyMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a spike for `event` at the current time.
+    pub fn add_sample(&mut self, event: &str, latency_ms: u64) {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.add_sample_at(event, now, latency_ms);
+    }
+
+    pub fn add_sample_at(&mut self, event: &str, time: u64, latency_ms: u64) {
+        self.events
+            .entry(event.to_string())
+            .or_default()
+            .add(time, latency_ms);
+    }
+
+    fn command_mut(&mut self, cmd: &str) -> &mut CommandStats {
+        if !self.commands.contains_key(cmd) {
+            self.commands
+                .insert(cmd.to_string(), CommandStats::default());
+        }
+        self.commands.get_mut(cmd).expect("inserted above")
+    }
+
+    /// Record an executed call of `cmd` (lowercase) and the error it
+    /// replied with, if any.
+    pub fn record_command(&mut self, cmd: &str, elapsed: Duration, error: Option<&str>) {
+        let stats = self.command_mut(cmd);
+        stats.record(elapsed.as_micros() as u64);
+        if let Some(error) = error {
+            stats.failed_calls += 1;
+            self.record_error(error);
+        }
+    }
+
+    /// Record a call of `cmd` (lowercase) refused before execution.
+    pub fn record_rejected(&mut self, cmd: &str, error: &str) {
+        self.command_mut(cmd).rejected_calls += 1;
+        self.record_error(error);
+    }
+
+    /// Count an error reply under its prefix, e.g. `ERR` or `WRONGTYPE`.
+    pub fn record_error(&mut self, error: &str) {
+        self.total_error_replies += 1;
+        let prefix = error.split(' ').next().unwrap_or_default();
+        if let Some(count) = self.errors.get_mut(prefix) {
+            *count += 1;
+        } else if self.errors.len() < MAX_ERROR_TYPES {
+            self.errors.insert(prefix.to_string(), 1);
+        }
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = (&String, &LatencySeries)> {
+        self.events.iter()
+    }
+
+    pub fn series(&self, event: &str) -> Option<&LatencySeries> {
+        self.events.get(event)
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = (&String, &CommandStats)> {
+        self.commands.iter()
+    }
+
+    pub fn command(&self, cmd: &str) -> Option<&CommandStats> {
+        self.commands.get(cmd)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = (&String, &u64)> {
+        self.errors.iter()
+    }
+
+    pub fn total_error_replies(&self) -> u64 {
+        self.total_error_replies
+    }
+
+    /// Forget command and error statistics, for CONFIG RESETSTAT.
+    pub fn reset_stats(&mut self) {
+        self.commands.clear();
+        self.errors.clear();
+        self.total_error_replies = 0;
+    }
+
+    /// Forget the given events, or all of them. Returns how many were reset.
+    pub fn reset(&mut self, events: &[String]) -> usize {
+        if events.is_empty() {
+            let n = self.events.len();
+            self.events.clear();
+            return n;
+        }
+        events
+            .iter()
+            .filter(|e| self.events.remove(e.as_str()).is_some())
+            .count()
+    }
+
+    /// ASCII graph of an event's spikes, oldest first, as LATENCY GRAPH.
+    pub fn graph(&self, event: &str, now: u64) -> Option<String> {
+        let series = self.events.get(event)?;
+        let samples: Vec<_> = series
+            .samples
+            .iter()
+            .skip(series.samples.len().saturating_sub(80))
+            .collect();
+        let high = samples.iter().map(|s| s.latency_ms).max().unwrap_or(0);
+        let low = samples.iter().map(|s| s.latency_ms).min().unwrap_or(0);
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{event} - high {high} ms, low {low} ms (all time high {} ms)",
+            series.max_ms
+        );
+        out.push_str(&"-".repeat(80));
+        out.push('\n');
+
+        // Four rows of bars, each row drawn as '_' (half) or '|' (full)
+        const ROWS: u64 = 4;
+        let levels: Vec<u64> = samples
+            .iter()
+            .map(|s| {
+                if high == low {
+                    ROWS * 2
+                } else {
+                    1 + (s.latency_ms - low) * (ROWS * 2 - 1) / (high - low)
+                }
+            })
+            .collect();
+        for row in (0..ROWS).rev() {
+            let line: String = levels
+                .iter()
+                .map(|&level| match level.saturating_sub(row * 2) {
+                    0 => ' ',
+                    1 => '_',
+                    _ => '|',
+                })
+                .collect();
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out.push('\n');
+
+        // How long ago each sample was, printed vertically under its bar
+        let labels: Vec<Vec<char>> = samples
+            .iter()
+            .map(|s| {
+                let age = now.saturating_sub(s.time);
+                let label = match age {
+                    0..60 => format!("{age}s"),
+                    60..3600 => format!("{}m", age / 60),
+                    3600..86400 => format!("{}h", age / 3600),
+                    _ => format!("{}d", age / 86400),
+                };
+                label.chars().collect()
+            })
+            .collect();
+        let height = labels.iter().map(Vec::len).max().unwrap_or(0);
+        for row in 0..height {
+            let line: String = labels
+                .iter()
+                .map(|l| l.get(row).copied().unwrap_or(' '))
+                .collect();
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        Some(out)
+    }
+
+    /// Human-readable analysis of the recorded spikes, as LATENCY DOCTOR.
+    pub fn doctor(&self, threshold_ms: u64) -> String {
+        if threshold_ms == 0 && self.events.is_empty() {
+            return "I'm sorry, Dave, I can't do that. Latency monitoring is disabled in this \
+                    instance. You may use \"CONFIG SET latency-monitor-threshold <milliseconds>.\" \
+                    in order to enable it.\n"
+                .to_string();
+        }
+        if self.events.is_empty() {
+            return "Dave, no latency spike was observed during the lifetime of this instance, \
+                    not in the slightest bit. I honestly think you ought to sleep a bit more.\n"
+                .to_string();
+        }
+
+        let mut out = String::from(
+            "Dave, I have observed latency spikes in this instance. \
+             You don't mind talking about it, do you Dave?\n\n",
+        );
+        for (i, (event, series)) in self.events.iter().enumerate() {
+            let n = series.samples.len() as u64;
+            let avg = series.samples.iter().map(|s| s.latency_ms).sum::<u64>() / n.max(1);
+            let deviation = series
+                .samples
+                .iter()
+                .map(|s| s.latency_ms.abs_diff(avg))
+                .sum::<u64>()
+                / n.max(1);
+            let period = match (series.samples.front(), series.samples.back()) {
+                (Some(first), Some(last)) if n > 1 => (last.time - first.time) / (n - 1),
+                _ => 0,
+            };
+            let _ = writeln!(
+                out,
+                "{}. {event}: {n} latency spikes (average {avg}ms, mean deviation {deviation}ms, \
+                 period {period} sec). Worst all time event {}ms.",
+                i + 1,
+                series.max_ms
+            );
+        }
+
+        out.push_str("\nI have a few advices for you:\n\n");
+        for event in self.events.keys() {
+            let advice = match event.as_str() {
+                COMMAND => {
+                    "- Check your SLOWLOG for slow commands. Prefer O(1) and O(log N) \
+                     commands, and break large KEYS, SMEMBERS or range queries into \
+                     SCAN-style iterations."
+                }
+                FAST_COMMAND => {
+                    "- Even O(1) commands were slow, which points at the system rather \
+                     than the workload: check for CPU starvation, swapping or a \
+                     noisy neighbour."
+                }
+                EXPIRE_CYCLE => {
+                    "- Many keys expire at the same time. Add some randomness to the \
+                     expire times you set."
+                }
+                EVICTION_CYCLE => {
+                    "- Evicting keys is slow. Raise maxmemory, or write less data \
+                     faster than it can be evicted."
+                }
+                AOF_FSYNC => {
+                    "- fsync of the append only file is slow. Use a faster disk, or \
+                     appendfsync everysec / no if you can lose some data on a crash."
+                }
+                AOF_REWRITE => {
+                    "- Rewriting the append only file is slow. Rewrite less often or \
+                     keep the dataset smaller."
+                }
+                RDB_SAVE => {
+                    "- Saving the RDB snapshot is slow. Use a faster disk or relax \
+                     the save rules."
+                }
+                _ => continue,
+            };
+            out.push_str(advice);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Locked for every command, so a plain mutex that is never held across
+/// an await point.
+pub type SharedLatencyMonitor = Arc<Mutex<LatencyMonitor>>;
+
+/// Record `elapsed` as a spike of `event` if it reaches
+/// `latency-monitor-threshold`.
+pub async fn monitor(
+    latency: &SharedLatencyMonitor,
+    config: &SharedConfig,
+    event: &str,
+    elapsed: Duration,
+) {
+    let threshold = config.read().await.latency_monitor_threshold;
+    let ms = elapsed.as_millis() as u64;
+    if threshold > 0 && ms >= threshold {
+        latency.lock().unwrap().add_sample(event, ms);
+    }
+}
+
+/// Commands that run in constant or logarithmic time, sampled as
+/// `fast-command` rather than `command`.
+pub fn is_fast_command(cmd: &str) -> bool {
+    matches!(
+        cmd,
+        "GET"
+            | "SETNX"
+            | "STRLEN"
+            | "INCR"
+            | "DECR"
+            | "INCRBY"
+            | "DECRBY"
+            | "INCRBYFLOAT"
+            | "GETBIT"
+            | "SETBIT"
+            | "EXISTS"
+            | "TYPE"
+            | "TTL"
+            | "PTTL"
+            | "EXPIRE"
+            | "PEXPIRE"
+            | "EXPIREAT"
+            | "PEXPIREAT"
+            | "PERSIST"
+            | "LPUSH"
+            | "RPUSH"
+            | "LPOP"
+            | "RPOP"
+            | "LLEN"
+            | "HGET"
+            | "HSET"
+            | "HSETNX"
+            | "HEXISTS"
+            | "HLEN"
+            | "HSTRLEN"
+            | "SADD"
+            | "SREM"
+            | "SISMEMBER"
+            | "SCARD"
+            | "ZADD"
+            | "ZREM"
+            | "ZSCORE"
+            | "ZCARD"
+            | "ZRANK"
+            | "ZREVRANK"
+            | "PFADD"
+            | "PING"
+            | "ECHO"
+            | "SELECT"
+            | "DBSIZE"
+            | "TIME"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_merge_within_a_second() {
+        let mut lm = LatencyMonitor::new();
+        lm.add_sample_at(COMMAND, 100, 20);
+        lm.add_sample_at(COMMAND, 100, 50);
+        lm.add_sample_at(COMMAND, 100, 30);
+        lm.add_sample_at(COMMAND, 101, 10);
+        let series = lm.series(COMMAND).unwrap();
+        assert_eq!(
+            series.samples.iter().copied().collect::<Vec<_>>(),
+            vec![
+                LatencySample {
+                    time: 100,
+                    latency_ms: 50
+                },
+                LatencySample {
+                    time: 101,
+                    latency_ms: 10
+                },
+            ]
+        );
+        assert_eq!(series.max_ms, 50);
+
+        for t in 0..LATENCY_TS_LEN as u64 * 2 {
+            lm.add_sample_at(RDB_SAVE, t, 1);
+        }
+        assert_eq!(lm.series(RDB_SAVE).unwrap().samples.len(), LATENCY_TS_LEN);
+
+        assert_eq!(lm.reset(&["nosuch".to_string(), COMMAND.to_string()]), 1);
+        assert_eq!(lm.reset(&[]), 1);
+        assert!(lm.events().next().is_none());
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut lm = LatencyMonitor::new();
+        for micros in [1, 2, 3, 4, 5, 1000] {
+            lm.record_command("get", Duration::from_micros(micros), None);
+        }
+        let h = lm.command("get").unwrap();
+        assert_eq!(h.calls, 6);
+        assert_eq!(h.usec, 1015);
+        assert_eq!(
+            h.cumulative(),
+            vec![(1, 1), (2, 2), (4, 4), (8, 5), (1024, 6)]
+        );
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut stats = CommandStats::default();
+        assert_eq!(stats.percentile(50.0), None);
+        for micros in 1..=1000 {
+            stats.record(micros);
+        }
+        assert_eq!(stats.percentile(50.0), Some(512));
+        let p99 = stats.percentile(99.0).unwrap();
+        assert!((990..=1056).contains(&p99), "p99 = {p99}");
+        assert_eq!(stats.percentile(100.0), Some(1024));
+
+        for micros in [0, 1, 16, 17, 1 << 39, u64::MAX] {
+            let bucket = CommandStats::bucket(micros);
+            assert!(bucket < HISTOGRAM_BUCKETS);
+            assert!(CommandStats::bucket_upper(bucket) >= micros.clamp(1, MAX_TRACKED_USEC));
+        }
+    }
+
+    #[test]
+    fn test_command_and_error_stats() {
+        let mut lm = LatencyMonitor::new();
+        lm.record_command("set", Duration::from_micros(10), None);
+        lm.record_command(
+            "incr",
+            Duration::from_micros(5),
+            Some("ERR value is not an integer"),
+        );
+        lm.record_command(
+            "lpush",
+            Duration::from_micros(5),
+            Some("WRONGTYPE Operation"),
+        );
+        lm.record_rejected(
+            "set",
+            "READONLY You can't write against a read only replica.",
+        );
+        lm.record_error("ERR unknown command 'foo'");
+
+        let set = lm.command("set").unwrap();
+        assert_eq!((set.calls, set.rejected_calls, set.failed_calls), (1, 1, 0));
+        assert_eq!(lm.command("incr").unwrap().failed_calls, 1);
+        let errors: Vec<_> = lm.errors().map(|(e, n)| (e.as_str(), *n)).collect();
+        assert_eq!(errors, vec![("ERR", 2), ("READONLY", 1), ("WRONGTYPE", 1)]);
+        assert_eq!(lm.total_error_replies(), 4);
+
+        lm.reset_stats();
+        assert!(lm.commands().next().is_none());
+        assert!(lm.errors().next().is_none());
+        assert_eq!(lm.total_error_replies(), 0);
+    }
+
+    #[test]
+    fn test_graph_and_doctor() {
+        let mut lm = LatencyMonitor::new();
+        assert!(lm.doctor(0).contains("disabled"));
+        assert!(lm.doctor(10).contains("no latency spike"));
+        lm.add_sample_at(COMMAND, 100, 10);
+        lm.add_sample_at(COMMAND, 102, 40);
+        let graph = lm.graph(COMMAND, 105).unwrap();
+        assert!(graph.starts_with("command - high 40 ms, low 10 ms (all time high 40 ms)"));
+        assert!(graph.contains(" |\n"));
+        assert!(graph.contains("53\nss\n"));
+        assert!(lm.graph(EXPIRE_CYCLE, 105).is_none());
+        let report = lm.doctor(10);
+        assert!(report.contains("1. command: 2 latency spikes (average 25ms"));
+        assert!(report.contains("SLOWLOG"));
+    }
+}
@@ -0,0 +1,158 @@
+//! Background freeing of large values (lazyfree).
+//!
+//! Dropping an aggregate frees its elements one by one, so deleting a value
+//! with millions of them stalls the command doing it, with its shard locked.
+//! [`LazyFree`] hands such values to a dedicated thread instead, like Redis's
+//! lazyfree background job. Values with few allocations are cheaper to drop
+//! in place than to send over, so they still are.
+
+use crate::config::Config;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, OnceLock};
+
+/// Values that take more allocations than this to free are dropped on the
+/// lazyfree thread.
+pub const LAZYFREE_THRESHOLD: usize = 64;
+
+type Job = Box<dyn Send>;
+
+#[derive(Debug, Default)]
+struct Counters {
+    /// Objects sent to the lazyfree thread and not freed yet.
+    pending: AtomicU64,
+    /// Objects the lazyfree thread has freed.
+    freed: AtomicU64,
+}
+
+/// The lazyfree thread, started on first use, and the `lazyfree-lazy-*`
+/// settings. Shared by every shard of a store.
+#[derive(Debug, Default)]
+pub struct LazyFree {
+    sender: OnceLock<Sender<(Job, u64)>>,
+    counters: Arc<Counters>,
+    lazy_eviction: AtomicBool,
+    lazy_expire: AtomicBool,
+    lazy_server_del: AtomicBool,
+    lazy_user_del: AtomicBool,
+}
+
+impl LazyFree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the `lazyfree-lazy-*` options from `config`.
+    pub fn configure(&self, config: &Config) {
+        self.lazy_eviction
+            .store(config.lazyfree_lazy_eviction, Ordering::Relaxed);
+        self.lazy_expire
+            .store(config.lazyfree_lazy_expire, Ordering::Relaxed);
+        self.lazy_server_del
+            .store(config.lazyfree_lazy_server_del, Ordering::Relaxed);
+        self.lazy_user_del
+            .store(config.lazyfree_lazy_user_del, Ordering::Relaxed);
+    }
+
+    /// Whether keys evicted for maxmemory are freed lazily.
+    pub fn lazy_eviction(&self) -> bool {
+        self.lazy_eviction.load(Ordering::Relaxed)
+    }
+
+    /// Whether expired keys are freed lazily.
+    pub fn lazy_expire(&self) -> bool {
+        self.lazy_expire.load(Ordering::Relaxed)
+    }
+
+    /// Whether values deleted or overwritten as a side effect of a command
+    /// (SET over an existing key, RENAME onto one, ...) are freed lazily.
+    pub fn lazy_server_del(&self) -> bool {
+        self.lazy_server_del.load(Ordering::Relaxed)
+    }
+
+    /// Whether DEL behaves like UNLINK.
+    pub fn lazy_user_del(&self) -> bool {
+        self.lazy_user_del.load(Ordering::Relaxed)
+    }
+
+    /// Drop `value` on the lazyfree thread if freeing it takes more than
+    /// [`LAZYFREE_THRESHOLD`] allocations, right here otherwise.
+    pub fn free<T: Send + 'static>(&self, value: T, effort: usize) {
+        if effort > LAZYFREE_THRESHOLD {
+            self.send(Box::new(value), 1);
+        }
+    }
+
+    /// Drop a whole keyspace holding `keys` keys on the lazyfree thread, for
+    /// FLUSHDB/FLUSHALL ASYNC.
+    pub fn free_keys<T: Send + 'static>(&self, keyspace: T, keys: usize) {
+        if keys > 0 {
+            self.send(Box::new(keyspace), keys as u64);
+        }
+    }
+
+    fn send(&self, job: Job, objects: u64) {
+        self.counters.pending.fetch_add(objects, Ordering::Relaxed);
+        let sender = self.sender.get_or_init(|| {
+            let (tx, rx) = mpsc::channel::<(Job, u64)>();
+            let counters = self.counters.clone();
+            std::thread::Builder::new()
+                .name("lazyfree".to_string())
+                .spawn(move || {
+                    for (job, objects) in rx {
+                        drop(job);
+                        counters.pending.fetch_sub(objects, Ordering::Relaxed);
+                        counters.freed.fetch_add(objects, Ordering::Relaxed);
+                    }
+                })
+                .expect("failed to spawn the lazyfree thread");
+            tx
+        });
+        // The thread only stops once the sender is dropped with `self`
+        let _ = sender.send((job, objects));
+    }
+
+    /// Objects waiting to be freed by the lazyfree thread.
+    pub fn pending_objects(&self) -> u64 {
+        self.counters.pending.load(Ordering::Relaxed)
+    }
+
+    /// Objects freed by the lazyfree thread so far.
+    pub fn lazyfreed_objects(&self) -> u64 {
+        self.counters.freed.load(Ordering::Relaxed)
+    }
+
+    /// Reset the freed objects counter (CONFIG RESETSTAT).
+    pub fn reset_stats(&self) {
+        self.counters.freed.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn wait_for_freed(lazyfree: &LazyFree, objects: u64) {
+        let start = Instant::now();
+        while lazyfree.lazyfreed_objects() < objects {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_frees_large_values_in_background() {
+        let lazyfree = LazyFree::new();
+        lazyfree.free(vec![0u8; 10], 1);
+        assert!(lazyfree.sender.get().is_none());
+
+        lazyfree.free(vec![vec![0u8]; 1000], 1000);
+        lazyfree.free_keys(vec![1, 2, 3], 3);
+        wait_for_freed(&lazyfree, 4);
+        assert_eq!(lazyfree.pending_objects(), 0);
+
+        lazyfree.reset_stats();
+        assert_eq!(lazyfree.lazyfreed_objects(), 0);
+    }
+}
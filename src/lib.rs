@@ -7,17 +7,22 @@
 //! transactions, pub/sub, persistence, and a configurable server — all without
 //! using any existing Redis or RESP libraries.
 
+pub mod allocator;
 pub mod command;
 pub mod config;
 pub mod connection;
 pub mod error;
 pub mod glob;
 pub mod keywatcher;
+pub mod latency;
+pub mod lazyfree;
+pub mod metrics;
 pub mod persistence;
 pub mod pubsub;
 pub mod replication;
 pub mod resp;
 pub mod scripting;
+pub mod sentinel;
 pub mod server;
 pub mod slowlog;
 pub mod store;
@@ -1,16 +1,23 @@
+use cedis::allocator::{self, TrackingAllocator};
 use cedis::config::Config;
-use cedis::persistence::aof::{AofWriter, FsyncPolicy};
+use cedis::persistence::aof::{AofError, AofWriter, FsyncPolicy};
 use cedis::persistence::rdb;
 use cedis::pubsub::PubSubRegistry;
 use cedis::replication::ReplicationState;
+use cedis::sentinel::{self, SentinelConfig};
 use cedis::server;
 use cedis::store::DataStore;
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
-use tracing::info;
+use tracing::{error, info};
+
+#[global_allocator]
+static GLOBAL: TrackingAllocator = TrackingAllocator;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+    allocator::enable();
+
     // Initialize tracing
     tracing_subscriber::fmt()
         .with_env_filter(
@@ -21,13 +28,20 @@ async fn main() -> std::io::Result<()> {
 
     // Parse command line args
     let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--sentinel") {
+        let config = SentinelConfig::from_args(&args)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        return sentinel::run_sentinel(config).await;
+    }
     let config = Config::from_args(&args);
+    allocator::mark_startup();
 
     let num_dbs = config.databases;
     let rdb_path = format!("{}/{}", config.dir, config.dbfilename);
     let aof_enabled = config.appendonly;
     let aof_path = format!("{}/appendonly.aof", config.dir);
     let aof_policy = FsyncPolicy::from_str(&config.appendfsync);
+    let aof_load_truncated = config.aof_load_truncated;
 
     // Try to load RDB on startup
     let store = if std::path::Path::new(&rdb_path).exists() {
@@ -46,19 +60,30 @@ async fn main() -> std::io::Result<()> {
         DataStore::new(num_dbs)
     };
 
-    let mut store = store;
-
     // Try to replay AOF if it exists (AOF takes precedence over RDB)
     if aof_enabled && std::path::Path::new(&aof_path).exists() {
         info!("Replaying AOF from {aof_path}...");
-        match cedis::persistence::aof::replay(&aof_path, &mut store, num_dbs) {
+        let mut keyspace = store.try_lock_all().expect("the store is not shared yet");
+        match cedis::persistence::aof::replay(&aof_path, &mut keyspace, num_dbs, aof_load_truncated)
+        {
             Ok(count) => info!("AOF replayed {count} commands"),
-            Err(e) => tracing::warn!("Failed to replay AOF: {e}"),
+            Err(e @ AofError::Truncated { .. }) => {
+                error!("{e}");
+                error!(
+                    "Refusing to start: set aof-load-truncated to yes to load the AOF anyway, \
+                     or repair it with cedis-check-aof --fix"
+                );
+                std::process::exit(1);
+            }
+            Err(e) => {
+                error!("Failed to replay AOF: {e}");
+                std::process::exit(1);
+            }
         }
     }
 
     let config = Arc::new(RwLock::new(config));
-    let store = Arc::new(RwLock::new(store));
+    let store = Arc::new(store);
     let pubsub = Arc::new(RwLock::new(PubSubRegistry::new()));
     let repl_state = Arc::new(RwLock::new(ReplicationState::new()));
 
@@ -0,0 +1,409 @@
+//! Prometheus metrics endpoint.
+//!
+//! With `metrics-port` set, the server answers `GET /metrics` over plain HTTP
+//! with the text exposition format. Every figure is read from the same state
+//! INFO reports, and metric names follow redis_exporter so existing
+//! dashboards keep working.
+
+use crate::config::SharedConfig;
+use crate::latency::SharedLatencyMonitor;
+use crate::replication::{ReplicationRole, SharedReplicationState};
+use crate::slowlog::{SharedLastSaveTime, SharedSlowLog};
+use crate::store::SharedStore;
+use std::fmt::{Display, Write};
+use std::sync::atomic::Ordering;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::debug;
+
+/// Largest request head we are willing to read.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Handles to the server state the metrics are rendered from.
+#[derive(Clone)]
+pub struct MetricsSources {
+    pub store: SharedStore,
+    pub config: SharedConfig,
+    pub repl_state: SharedReplicationState,
+    pub last_save_time: SharedLastSaveTime,
+    pub slowlog: SharedSlowLog,
+    pub latency: SharedLatencyMonitor,
+}
+
+/// Accept HTTP connections on `listener` until the task is dropped.
+pub async fn serve(listener: TcpListener, sources: MetricsSources) {
+    loop {
+        let Ok((stream, peer_addr)) = listener.accept().await else {
+            continue;
+        };
+        let sources = sources.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(stream, &sources).await {
+                debug!("Metrics connection error from {peer_addr}: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_request(mut stream: TcpStream, sources: &MetricsSources) -> std::io::Result<()> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 || buf.len() + n > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&buf);
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", render(sources).await),
+        ("GET", _) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Metric families in the text exposition format.
+#[derive(Default)]
+struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {name} {help}");
+        let _ = writeln!(self.out, "# TYPE {name} {kind}");
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            self.out.push('{');
+            for (i, (label, value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                let _ = write!(self.out, "{label}=\"{}\"", escape_label(value));
+            }
+            self.out.push('}');
+        }
+        let _ = writeln!(self.out, " {value}");
+    }
+
+    /// A family with a single unlabelled sample.
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl Display) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render all metrics.
+pub async fn render(sources: &MetricsSources) -> String {
+    let mut m = Exposition::default();
+
+    let (maxmemory, aof_enabled) = {
+        let cfg = sources.config.read().await;
+        (cfg.maxmemory, cfg.appendonly)
+    };
+
+    {
+        let rs = sources.repl_state.read().await;
+        let role = match rs.role {
+            ReplicationRole::Master => "master",
+            ReplicationRole::Replica => "slave",
+        };
+        m.family(
+            "redis_instance_info",
+            "gauge",
+            "Information about the server.",
+        );
+        m.sample(
+            "redis_instance_info",
+            &[
+                ("role", role),
+                ("redis_version", "7.0.0"),
+                ("cedis_version", env!("CARGO_PKG_VERSION")),
+            ],
+            1,
+        );
+        m.single(
+            "redis_master_repl_offset",
+            "gauge",
+            "Replication offset of this server.",
+            rs.master_repl_offset,
+        );
+        if rs.role == ReplicationRole::Replica {
+            m.single(
+                "redis_master_link_up",
+                "gauge",
+                "Whether the link to the master is up.",
+                u8::from(rs.master_link_status == "up"),
+            );
+        }
+        m.single(
+            "redis_connected_slaves",
+            "gauge",
+            "Number of connected replicas.",
+            rs.connected_slaves(),
+        );
+        m.family(
+            "redis_connected_slave_offset_bytes",
+            "gauge",
+            "Replication offset acknowledged by each replica.",
+        );
+        for replica in &rs.replicas {
+            let port = replica.port.to_string();
+            let state = replica.state.to_string();
+            m.sample(
+                "redis_connected_slave_offset_bytes",
+                &[
+                    ("slave_ip", &replica.addr),
+                    ("slave_port", &port),
+                    ("slave_state", &state),
+                ],
+                replica.offset,
+            );
+        }
+        m.family(
+            "redis_connected_slave_lag_seconds",
+            "gauge",
+            "Seconds since each replica last acknowledged the stream.",
+        );
+        for replica in &rs.replicas {
+            let port = replica.port.to_string();
+            let state = replica.state.to_string();
+            m.sample(
+                "redis_connected_slave_lag_seconds",
+                &[
+                    ("slave_ip", &replica.addr),
+                    ("slave_port", &port),
+                    ("slave_state", &state),
+                ],
+                replica.lag(),
+            );
+        }
+    }
+
+    {
+        // Read from the shards' published gauges, without locking the keyspace
+        let store = &sources.store;
+        let stats = &store.stats;
+        m.single(
+            "redis_memory_used_bytes",
+            "gauge",
+            "Memory in use, as in INFO used_memory.",
+            store.used_memory(),
+        );
+        if let Some(peak) = crate::allocator::peak() {
+            m.single(
+                "redis_memory_used_peak_bytes",
+                "gauge",
+                "Highest memory in use since startup.",
+                peak,
+            );
+        }
+        if let Some(rss) = crate::allocator::rss() {
+            m.single(
+                "redis_memory_used_rss_bytes",
+                "gauge",
+                "Resident set size of the process.",
+                rss,
+            );
+        }
+        m.single(
+            "redis_memory_max_bytes",
+            "gauge",
+            "Configured maxmemory, 0 when unlimited.",
+            maxmemory,
+        );
+        let counts: Vec<_> = (0..store.num_databases())
+            .map(|i| {
+                let (keys, expires) = store.db_key_counts(i);
+                (format!("db{i}"), keys, expires)
+            })
+            .collect();
+        m.family("redis_db_keys", "gauge", "Number of keys per database.");
+        for (db, keys, _) in &counts {
+            m.sample("redis_db_keys", &[("db", db)], keys);
+        }
+        m.family(
+            "redis_db_keys_expiring",
+            "gauge",
+            "Number of keys with a TTL per database.",
+        );
+        for (db, _, expiring) in &counts {
+            m.sample("redis_db_keys_expiring", &[("db", db)], expiring);
+        }
+        m.single(
+            "redis_expired_keys_total",
+            "counter",
+            "Keys removed because their TTL passed.",
+            stats.expired_keys.load(Ordering::Relaxed),
+        );
+        m.single(
+            "redis_evicted_keys_total",
+            "counter",
+            "Keys removed to stay under maxmemory.",
+            stats.evicted_keys.load(Ordering::Relaxed),
+        );
+        m.single(
+            "redis_connected_clients",
+            "gauge",
+            "Number of client connections.",
+            stats.connected_clients.load(Ordering::Relaxed),
+        );
+        m.single(
+            "redis_connections_received_total",
+            "counter",
+            "Client connections accepted.",
+            stats.total_connections_received.load(Ordering::Relaxed),
+        );
+        m.single(
+            "redis_rdb_changes_since_last_save",
+            "gauge",
+            "Writes since the last RDB save.",
+            stats.dirty.load(Ordering::Relaxed),
+        );
+    }
+
+    m.single(
+        "redis_rdb_last_save_timestamp_seconds",
+        "gauge",
+        "Unix time of the last successful RDB save.",
+        sources.last_save_time.load(Ordering::Relaxed),
+    );
+    m.single(
+        "redis_aof_enabled",
+        "gauge",
+        "Whether the append only file is enabled.",
+        u8::from(aof_enabled),
+    );
+    m.single(
+        "redis_slowlog_length",
+        "gauge",
+        "Number of entries in the slow log.",
+        sources.slowlog.lock().await.len(),
+    );
+
+    let lm = sources.latency.lock().unwrap();
+    let mut commands: Vec<_> = lm.commands().collect();
+    commands.sort_by(|a, b| a.0.cmp(b.0));
+    m.family("redis_commands_total", "counter", "Calls per command.");
+    for (cmd, stats) in &commands {
+        m.sample("redis_commands_total", &[("cmd", cmd)], stats.calls);
+    }
+    m.family(
+        "redis_commands_duration_seconds_total",
+        "counter",
+        "Time spent executing each command.",
+    );
+    for (cmd, stats) in &commands {
+        m.sample(
+            "redis_commands_duration_seconds_total",
+            &[("cmd", cmd)],
+            stats.usec as f64 / 1_000_000.0,
+        );
+    }
+    m.family(
+        "redis_commands_rejected_calls_total",
+        "counter",
+        "Calls per command refused before execution.",
+    );
+    for (cmd, stats) in &commands {
+        m.sample(
+            "redis_commands_rejected_calls_total",
+            &[("cmd", cmd)],
+            stats.rejected_calls,
+        );
+    }
+    m.family(
+        "redis_commands_failed_calls_total",
+        "counter",
+        "Calls per command that replied with an error.",
+    );
+    for (cmd, stats) in &commands {
+        m.sample(
+            "redis_commands_failed_calls_total",
+            &[("cmd", cmd)],
+            stats.failed_calls,
+        );
+    }
+    m.family(
+        "redis_latency_percentiles_usec",
+        "summary",
+        "Command latency percentiles in microseconds.",
+    );
+    for (cmd, stats) in commands.iter().filter(|(_, s)| s.calls > 0) {
+        for (quantile, percentile) in [("0.5", 50.0), ("0.99", 99.0), ("0.999", 99.9)] {
+            m.sample(
+                "redis_latency_percentiles_usec",
+                &[("cmd", cmd), ("quantile", quantile)],
+                stats.percentile(percentile).unwrap_or_default(),
+            );
+        }
+        m.sample(
+            "redis_latency_percentiles_usec_sum",
+            &[("cmd", cmd)],
+            stats.usec,
+        );
+        m.sample(
+            "redis_latency_percentiles_usec_count",
+            &[("cmd", cmd)],
+            stats.calls,
+        );
+    }
+    m.family(
+        "redis_errors_total",
+        "counter",
+        "Error replies by error prefix.",
+    );
+    for (prefix, count) in lm.errors() {
+        m.sample("redis_errors_total", &[("err", prefix)], count);
+    }
+
+    m.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exposition_format() {
+        let mut m = Exposition::default();
+        m.single("redis_up", "gauge", "Whether the server is up.", 1);
+        m.family("redis_db_keys", "gauge", "Keys per database.");
+        m.sample("redis_db_keys", &[("db", "db0"), ("note", "a\"b\\c\nd")], 3);
+        assert_eq!(
+            m.out,
+            "# HELP redis_up Whether the server is up.\n\
+             # TYPE redis_up gauge\n\
+             redis_up 1\n\
+             # HELP redis_db_keys Keys per database.\n\
+             # TYPE redis_db_keys gauge\n\
+             redis_db_keys{db=\"db0\",note=\"a\\\"b\\\\c\\nd\"} 3\n"
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use crate::resp::RespValue;
-use crate::store::DataStore;
+use crate::store::KeyspaceGuard;
+use crate::store::entry::Entry;
 use crate::types::RedisValue;
+use crate::types::rstring::RedisString;
 use std::io::{self, BufRead, Read, Write};
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -9,6 +11,10 @@ use tokio::sync::Mutex;
 pub struct AofWriter {
     file: Option<std::fs::File>,
     fsync_policy: FsyncPolicy,
+    /// Replication offset covered by the commands written so far.
+    written_reploff: i64,
+    /// Replication offset covered by the last fsync.
+    fsynced_reploff: i64,
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -40,6 +46,8 @@ impl AofWriter {
         AofWriter {
             file: None,
             fsync_policy: FsyncPolicy::Everysec,
+            written_reploff: 0,
+            fsynced_reploff: 0,
         }
     }
 
@@ -71,12 +79,40 @@ impl AofWriter {
         file.write_all(&resp.serialize())?;
 
         if self.fsync_policy == FsyncPolicy::Always {
-            file.flush()?;
+            file.sync_data()?;
         }
 
         Ok(())
     }
 
+    /// Note that the commands logged so far reach replication offset `offset`.
+    /// With `appendfsync always` they are already on disk.
+    pub fn advance_reploff(&mut self, offset: i64) {
+        self.written_reploff = self.written_reploff.max(offset);
+        if self.fsync_policy == FsyncPolicy::Always {
+            self.fsynced_reploff = self.written_reploff;
+        }
+    }
+
+    /// Replication offset known to be durable in the AOF.
+    pub fn fsynced_reploff(&self) -> i64 {
+        self.fsynced_reploff
+    }
+
+    /// Fsync the file and return the replication offset now durable.
+    /// `appendfsync no` leaves flushing to the OS, so nothing is ever known
+    /// to be durable and the offset stays where it is.
+    pub fn fsync(&mut self) -> io::Result<i64> {
+        if self.fsync_policy == FsyncPolicy::No {
+            return Ok(self.fsynced_reploff);
+        }
+        if let Some(f) = &mut self.file {
+            f.sync_data()?;
+        }
+        self.fsynced_reploff = self.written_reploff;
+        Ok(self.fsynced_reploff)
+    }
+
     /// Flush the file to disk.
     pub fn flush(&mut self) -> io::Result<()> {
         if let Some(f) = &mut self.file {
@@ -96,29 +132,216 @@ impl AofWriter {
     }
 }
 
+/// Errors that can occur while reading an AOF file.
+#[derive(Debug, thiserror::Error)]
+pub enum AofError {
+    /// The file ends in the middle of a command (e.g. after a crash during a write).
+    /// `valid_len` is the length of the prefix made of complete commands.
+    #[error("Unexpected end of file reading the append only file at offset {offset}")]
+    Truncated { offset: u64, valid_len: u64 },
+
+    /// The file contains malformed data that is not just a short tail.
+    #[error("Bad file format reading the append only file at offset {offset}: {reason}")]
+    Corrupted { offset: u64, reason: String },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Sequential reader over the commands stored in an AOF file.
+/// Tracks the byte offset of each command so errors can be located precisely.
+pub struct AofReader<R> {
+    reader: R,
+    /// Bytes consumed so far.
+    pos: u64,
+    /// Offset just past the last complete command.
+    valid_len: u64,
+}
+
+impl<R: BufRead> AofReader<R> {
+    pub fn new(reader: R) -> Self {
+        AofReader {
+            reader,
+            pos: 0,
+            valid_len: 0,
+        }
+    }
+
+    /// Offset just past the last complete command that was read.
+    pub fn valid_len(&self) -> u64 {
+        self.valid_len
+    }
+
+    /// Read the next command as a list of arguments (command name first).
+    /// Returns `Ok(None)` at a clean end of file.
+    pub fn next_command(&mut self) -> Result<Option<Vec<RespValue>>, AofError> {
+        let start = self.pos;
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let value = match self.read_value() {
+            Ok(v) => v,
+            Err(ReadError::Eof) => {
+                return Err(AofError::Truncated {
+                    offset: start,
+                    valid_len: self.valid_len,
+                });
+            }
+            Err(ReadError::Format(reason)) => {
+                return Err(AofError::Corrupted {
+                    offset: start,
+                    reason,
+                });
+            }
+            Err(ReadError::Io(e)) => return Err(AofError::Io(e)),
+        };
+
+        match value {
+            RespValue::Array(Some(items)) if !items.is_empty() => {
+                self.valid_len = self.pos;
+                Ok(Some(items))
+            }
+            _ => Err(AofError::Corrupted {
+                offset: start,
+                reason: "expected a non-empty command array".to_string(),
+            }),
+        }
+    }
+
+    /// Read one CRLF-terminated line, without the terminator.
+    fn read_line(&mut self) -> Result<String, ReadError> {
+        let mut line = Vec::new();
+        let n = self.reader.read_until(b'\n', &mut line)?;
+        self.pos += n as u64;
+        if !line.ends_with(b"\r\n") {
+            // A line without its terminator can only be a short read at the tail
+            return if line.ends_with(b"\n") {
+                Err(ReadError::Format("line not terminated by CRLF".to_string()))
+            } else {
+                Err(ReadError::Eof)
+            };
+        }
+        line.truncate(line.len() - 2);
+        String::from_utf8(line).map_err(|_| ReadError::Format("invalid header line".to_string()))
+    }
+
+    fn read_value(&mut self) -> Result<RespValue, ReadError> {
+        let line = self.read_line()?;
+        if line.is_empty() {
+            return Err(ReadError::Format("empty line".to_string()));
+        }
+
+        let first = line.as_bytes()[0];
+        let rest = &line[1..];
+
+        match first {
+            b'$' => {
+                let len: i64 = rest
+                    .parse()
+                    .map_err(|_| ReadError::Format(format!("bad bulk length '{rest}'")))?;
+                if len == -1 {
+                    return Ok(RespValue::null_bulk_string());
+                }
+                if len < 0 || len > RedisString::MAX_SIZE as i64 {
+                    return Err(ReadError::Format(format!("bad bulk length '{rest}'")));
+                }
+                let len = len as usize;
+                // Grow the buffer as the data arrives rather than trusting the
+                // header, so a corrupt length can't force a huge allocation
+                let mut buf = Vec::new();
+                (&mut self.reader)
+                    .take(len as u64 + 2) // +2 for \r\n
+                    .read_to_end(&mut buf)?;
+                self.pos += buf.len() as u64;
+                if buf.len() < len + 2 {
+                    return Err(ReadError::Eof);
+                }
+                if &buf[len..] != b"\r\n" {
+                    return Err(ReadError::Format(
+                        "bulk string not terminated by CRLF".to_string(),
+                    ));
+                }
+                buf.truncate(len);
+                Ok(RespValue::BulkString(Some(buf.into())))
+            }
+            b'*' => {
+                let count: i64 = rest
+                    .parse()
+                    .map_err(|_| ReadError::Format(format!("bad array length '{rest}'")))?;
+                if count < 0 {
+                    return Err(ReadError::Format(format!("bad array length '{rest}'")));
+                }
+                let mut items = Vec::with_capacity((count as usize).min(1024));
+                for _ in 0..count {
+                    if self.reader.fill_buf()?.is_empty() {
+                        return Err(ReadError::Eof);
+                    }
+                    items.push(self.read_value()?);
+                }
+                Ok(RespValue::Array(Some(items)))
+            }
+            _ => Err(ReadError::Format(format!(
+                "unexpected byte '{}'",
+                first.escape_ascii()
+            ))),
+        }
+    }
+}
+
+/// Internal error of the low-level RESP reader.
+enum ReadError {
+    Eof,
+    Format(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            ReadError::Eof
+        } else {
+            ReadError::Io(e)
+        }
+    }
+}
+
 /// Replay an AOF file to restore state.
-pub fn replay(path: &str, store: &mut DataStore, num_databases: usize) -> io::Result<usize> {
+///
+/// A file that ends in the middle of a command is truncated back to the last
+/// complete command when `load_truncated` is set (`aof-load-truncated yes`),
+/// otherwise loading fails. Malformed data anywhere else always fails.
+pub fn replay(
+    path: &str,
+    store: &mut KeyspaceGuard,
+    num_databases: usize,
+    load_truncated: bool,
+) -> Result<usize, AofError> {
     let file = match std::fs::File::open(path) {
         Ok(f) => f,
         Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
-        Err(e) => return Err(e),
+        Err(e) => return Err(e.into()),
     };
 
-    let mut reader = io::BufReader::new(file);
+    let mut reader = AofReader::new(io::BufReader::new(file));
     let mut cmd_count = 0usize;
     let mut current_db = 0usize;
 
     loop {
-        // Read one RESP value
-        let value = match read_resp_value(&mut reader) {
-            Ok(Some(v)) => v,
+        let items = match reader.next_command() {
+            Ok(Some(items)) => items,
             Ok(None) => break,
-            Err(_) => break, // Truncated AOF, stop here
-        };
-
-        let items = match value {
-            RespValue::Array(Some(items)) if !items.is_empty() => items,
-            _ => continue,
+            Err(AofError::Truncated { offset, valid_len }) if load_truncated => {
+                tracing::warn!(
+                    "!!! Warning: short read while loading the AOF file {path} at offset {offset} !!!"
+                );
+                truncate(path, valid_len)?;
+                tracing::warn!(
+                    "AOF loaded anyway because aof-load-truncated is enabled, truncated to {valid_len} bytes"
+                );
+                break;
+            }
+            Err(e) => return Err(e),
         };
 
         let cmd_name = match items[0].to_string_lossy() {
@@ -136,106 +359,30 @@ pub fn replay(path: &str, store: &mut DataStore, num_databases: usize) -> io::Re
     Ok(cmd_count)
 }
 
+/// Truncate an AOF file to `len` bytes, dropping a partially written tail.
+pub fn truncate(path: &str, len: u64) -> io::Result<()> {
+    let file = std::fs::OpenOptions::new().write(true).open(path)?;
+    file.set_len(len)?;
+    file.sync_all()
+}
+
 /// Rewrite the AOF by scanning the current store state.
-pub fn rewrite(store: &DataStore, path: &str) -> io::Result<()> {
+pub fn rewrite(store: &mut KeyspaceGuard, path: &str) -> io::Result<()> {
     let tmp_path = format!("{path}.tmp");
     let mut file = std::fs::File::create(&tmp_path)?;
 
-    for (db_index, db) in store.databases.iter().enumerate() {
+    for db in store.databases() {
+        let db_index = db.index();
         let entries: Vec<_> = db.iter().collect();
         if entries.is_empty() {
             continue;
         }
 
         // SELECT db
-        let select_cmd = RespValue::array(vec![
-            RespValue::bulk_string(b"SELECT".to_vec()),
-            RespValue::bulk_string(db_index.to_string().into_bytes()),
-        ]);
-        file.write_all(&select_cmd.serialize())?;
+        file.write_all(&select_command(db_index).serialize())?;
 
         for (key, entry) in &entries {
-            match &entry.value {
-                RedisValue::String(s) => {
-                    let cmd = RespValue::array(vec![
-                        RespValue::bulk_string(b"SET".to_vec()),
-                        RespValue::bulk_string(key.as_bytes().to_vec()),
-                        RespValue::bulk_string(s.as_bytes().to_vec()),
-                    ]);
-                    file.write_all(&cmd.serialize())?;
-                }
-                RedisValue::List(list) => {
-                    let items: Vec<_> = list.iter().collect();
-                    if !items.is_empty() {
-                        let mut cmd_parts = vec![
-                            RespValue::bulk_string(b"RPUSH".to_vec()),
-                            RespValue::bulk_string(key.as_bytes().to_vec()),
-                        ];
-                        for item in items {
-                            cmd_parts.push(RespValue::bulk_string(item.to_vec()));
-                        }
-                        file.write_all(&RespValue::array(cmd_parts).serialize())?;
-                    }
-                }
-                RedisValue::Hash(hash) => {
-                    let fields: Vec<_> = hash.iter().collect();
-                    if !fields.is_empty() {
-                        let mut cmd_parts = vec![
-                            RespValue::bulk_string(b"HSET".to_vec()),
-                            RespValue::bulk_string(key.as_bytes().to_vec()),
-                        ];
-                        for (field, value) in fields {
-                            cmd_parts.push(RespValue::bulk_string(field.as_bytes().to_vec()));
-                            cmd_parts.push(RespValue::bulk_string(value.to_vec()));
-                        }
-                        file.write_all(&RespValue::array(cmd_parts).serialize())?;
-                    }
-                }
-                RedisValue::Set(set) => {
-                    let members = set.members();
-                    if !members.is_empty() {
-                        let mut cmd_parts = vec![
-                            RespValue::bulk_string(b"SADD".to_vec()),
-                            RespValue::bulk_string(key.as_bytes().to_vec()),
-                        ];
-                        for member in members {
-                            cmd_parts.push(RespValue::bulk_string(member.to_vec()));
-                        }
-                        file.write_all(&RespValue::array(cmd_parts).serialize())?;
-                    }
-                }
-                RedisValue::SortedSet(zset) => {
-                    let items: Vec<_> = zset.iter().collect();
-                    if !items.is_empty() {
-                        let mut cmd_parts = vec![
-                            RespValue::bulk_string(b"ZADD".to_vec()),
-                            RespValue::bulk_string(key.as_bytes().to_vec()),
-                        ];
-                        for (member, score) in items {
-                            cmd_parts.push(RespValue::bulk_string(score.to_string().into_bytes()));
-                            cmd_parts.push(RespValue::bulk_string(member.to_vec()));
-                        }
-                        file.write_all(&RespValue::array(cmd_parts).serialize())?;
-                    }
-                }
-                RedisValue::Stream(_) => {
-                    // Stream AOF serialization not yet implemented; skip
-                }
-                RedisValue::HyperLogLog(_) => {
-                    // HyperLogLog AOF serialization not yet implemented; skip
-                }
-                RedisValue::Geo(_) => {
-                    // Geo AOF serialization not yet implemented; skip
-                }
-            }
-
-            // Expiry
-            if let Some(exp) = entry.expires_at {
-                let cmd = RespValue::array(vec![
-                    RespValue::bulk_string(b"PEXPIREAT".to_vec()),
-                    RespValue::bulk_string(key.as_bytes().to_vec()),
-                    RespValue::bulk_string(exp.to_string().into_bytes()),
-                ]);
+            for cmd in entry_commands(key, entry) {
                 file.write_all(&cmd.serialize())?;
             }
         }
@@ -247,9 +394,90 @@ pub fn rewrite(store: &DataStore, path: &str) -> io::Result<()> {
     Ok(())
 }
 
+/// Build the `SELECT <db>` command.
+pub fn select_command(db_index: usize) -> RespValue {
+    RespValue::array(vec![
+        RespValue::bulk_string(b"SELECT".to_vec()),
+        RespValue::bulk_string(db_index.to_string().into_bytes()),
+    ])
+}
+
+/// Build the commands that recreate a key, followed by `PEXPIREAT` if it has an expiry.
+/// Types without a command representation yet produce no commands.
+pub fn entry_commands(key: &str, entry: &Entry) -> Vec<RespValue> {
+    let mut commands = Vec::with_capacity(2);
+    let with_key = |name: &[u8]| {
+        vec![
+            RespValue::bulk_string(name.to_vec()),
+            RespValue::bulk_string(key.as_bytes().to_vec()),
+        ]
+    };
+
+    match &entry.value {
+        RedisValue::String(s) => {
+            let mut cmd_parts = with_key(b"SET");
+            cmd_parts.push(RespValue::bulk_string(s.to_bytes()));
+            commands.push(RespValue::array(cmd_parts));
+        }
+        RedisValue::List(list) => {
+            if !list.is_empty() {
+                let mut cmd_parts = with_key(b"RPUSH");
+                for item in list.iter() {
+                    cmd_parts.push(RespValue::bulk_string(item.to_vec()));
+                }
+                commands.push(RespValue::array(cmd_parts));
+            }
+        }
+        RedisValue::Hash(hash) => {
+            if !hash.is_empty() {
+                let mut cmd_parts = with_key(b"HSET");
+                for (field, value) in hash.iter() {
+                    cmd_parts.push(RespValue::bulk_string(field.as_bytes().to_vec()));
+                    cmd_parts.push(RespValue::bulk_string(value.to_vec()));
+                }
+                commands.push(RespValue::array(cmd_parts));
+            }
+        }
+        RedisValue::Set(set) => {
+            let members = set.members();
+            if !members.is_empty() {
+                let mut cmd_parts = with_key(b"SADD");
+                for member in members {
+                    cmd_parts.push(RespValue::bulk_string(member.to_vec()));
+                }
+                commands.push(RespValue::array(cmd_parts));
+            }
+        }
+        RedisValue::SortedSet(zset) => {
+            if !zset.is_empty() {
+                let mut cmd_parts = with_key(b"ZADD");
+                for (member, score) in zset.iter() {
+                    cmd_parts.push(RespValue::bulk_string(score.to_string().into_bytes()));
+                    cmd_parts.push(RespValue::bulk_string(member.to_vec()));
+                }
+                commands.push(RespValue::array(cmd_parts));
+            }
+        }
+        RedisValue::Stream(_) => {
+            // Stream AOF serialization not yet implemented; skip
+        }
+    }
+
+    // Expiry
+    if !commands.is_empty()
+        && let Some(exp) = entry.expires_at
+    {
+        let mut cmd_parts = with_key(b"PEXPIREAT");
+        cmd_parts.push(RespValue::bulk_string(exp.to_string().into_bytes()));
+        commands.push(RespValue::array(cmd_parts));
+    }
+
+    commands
+}
+
 /// Apply a single command to the store (for AOF replay).
 fn apply_command(
-    store: &mut DataStore,
+    store: &mut KeyspaceGuard,
     cmd: &str,
     args: &[RespValue],
     current_db: &mut usize,
@@ -268,10 +496,9 @@ fn apply_command(
         }
         "SET" => {
             if let (Some(key), Some(val)) = (arg_str(0), arg_bytes(1)) {
-                let db = store.db(*current_db);
-                let entry = crate::store::entry::Entry::new(RedisValue::String(
-                    crate::types::rstring::RedisString::new(val),
-                ));
+                let mut db = store.db(*current_db);
+                let entry =
+                    crate::store::entry::Entry::new(RedisValue::String(RedisString::new(val)));
                 db.set(key, entry);
             }
         }
@@ -284,7 +511,7 @@ fn apply_command(
         }
         "RPUSH" | "LPUSH" => {
             if let Some(key) = arg_str(0) {
-                let db = store.db(*current_db);
+                let mut db = store.db(*current_db);
                 if db.get(&key).is_none() {
                     db.set(
                         key.clone(),
@@ -310,7 +537,7 @@ fn apply_command(
         }
         "HSET" => {
             if let Some(key) = arg_str(0) {
-                let db = store.db(*current_db);
+                let mut db = store.db(*current_db);
                 if db.get(&key).is_none() {
                     db.set(
                         key.clone(),
@@ -335,7 +562,7 @@ fn apply_command(
         }
         "SADD" => {
             if let Some(key) = arg_str(0) {
-                let db = store.db(*current_db);
+                let mut db = store.db(*current_db);
                 if db.get(&key).is_none() {
                     db.set(
                         key.clone(),
@@ -357,7 +584,7 @@ fn apply_command(
         }
         "ZADD" => {
             if let Some(key) = arg_str(0) {
-                let db = store.db(*current_db);
+                let mut db = store.db(*current_db);
                 if db.get(&key).is_none() {
                     db.set(
                         key.clone(),
@@ -400,71 +627,113 @@ fn apply_command(
     }
 }
 
-/// Read a single RESP value from a buffered reader.
-fn read_resp_value(reader: &mut io::BufReader<std::fs::File>) -> io::Result<Option<RespValue>> {
-    let mut line = String::new();
-    let n = reader.read_line(&mut line)?;
-    if n == 0 {
-        return Ok(None);
+pub type SharedAofWriter = Arc<Mutex<AofWriter>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::DataStore;
+
+    fn temp_aof(name: &str, contents: &[u8]) -> String {
+        let path = std::env::temp_dir()
+            .join(format!("cedis-aof-{}-{name}.aof", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(&path, contents).unwrap();
+        path
     }
-    let line = line.trim_end_matches("\r\n").trim_end_matches('\n');
 
-    if line.is_empty() {
-        return Ok(None);
+    const SET_A: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+    const SET_B: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n";
+
+    #[test]
+    fn test_replay_truncated_tail_is_repaired() {
+        let mut contents = SET_A.to_vec();
+        contents.extend_from_slice(&SET_B[..SET_B.len() - 5]);
+        let path = temp_aof("truncated-yes", &contents);
+
+        let store = DataStore::new(16);
+        let mut store = store.try_lock_all().unwrap();
+        let count = replay(&path, &mut store, 16, true).unwrap();
+        assert_eq!(count, 1);
+        assert!(store.db(0).exists("a"));
+        assert_eq!(std::fs::read(&path).unwrap(), SET_A);
+        std::fs::remove_file(&path).unwrap();
     }
 
-    let first = line.as_bytes()[0];
-    let rest = &line[1..];
-
-    match first {
-        b'+' => Ok(Some(RespValue::SimpleString(rest.to_string()))),
-        b'-' => Ok(Some(RespValue::Error(rest.to_string()))),
-        b':' => {
-            let n: i64 = rest
-                .parse()
-                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Bad integer"))?;
-            Ok(Some(RespValue::Integer(n)))
-        }
-        b'$' => {
-            let len: i64 = rest
-                .parse()
-                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Bad bulk len"))?;
-            if len == -1 {
-                return Ok(Some(RespValue::null_bulk_string()));
-            }
-            let len = len as usize;
-            let mut buf = vec![0u8; len + 2]; // +2 for \r\n
-            reader.read_exact(&mut buf)?;
-            buf.truncate(len);
-            Ok(Some(RespValue::BulkString(Some(buf))))
-        }
-        b'*' => {
-            let count: i64 = rest
-                .parse()
-                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Bad array len"))?;
-            if count == -1 {
-                return Ok(Some(RespValue::null_array()));
-            }
-            let count = count as usize;
-            let mut items = Vec::with_capacity(count);
-            for _ in 0..count {
-                match read_resp_value(reader)? {
-                    Some(v) => items.push(v),
-                    None => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::UnexpectedEof,
-                            "Truncated array",
-                        ));
-                    }
-                }
+    #[test]
+    fn test_replay_truncated_tail_refused() {
+        let mut contents = SET_A.to_vec();
+        contents.extend_from_slice(b"*3\r\n$3\r\nSE");
+        let path = temp_aof("truncated-no", &contents);
+
+        let store = DataStore::new(16);
+        let mut store = store.try_lock_all().unwrap();
+        match replay(&path, &mut store, 16, false) {
+            Err(AofError::Truncated { offset, valid_len }) => {
+                assert_eq!(offset, SET_A.len() as u64);
+                assert_eq!(valid_len, SET_A.len() as u64);
             }
-            Ok(Some(RespValue::Array(Some(items))))
+            other => panic!("expected truncation error, got {other:?}"),
         }
-        _ => Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!("Unknown RESP byte: {first}"),
-        )),
+        // The file is left untouched
+        assert_eq!(std::fs::read(&path).unwrap(), contents);
+        std::fs::remove_file(&path).unwrap();
     }
-}
 
-pub type SharedAofWriter = Arc<Mutex<AofWriter>>;
+    #[test]
+    fn test_replay_corruption_reports_offset() {
+        let mut contents = SET_A.to_vec();
+        contents.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$x\r\nb\r\n$1\r\n2\r\n");
+        contents.extend_from_slice(SET_B);
+        let path = temp_aof("corrupted", &contents);
+
+        let store = DataStore::new(16);
+        let mut store = store.try_lock_all().unwrap();
+        match replay(&path, &mut store, 16, true) {
+            Err(AofError::Corrupted { offset, .. }) => assert_eq!(offset, SET_A.len() as u64),
+            other => panic!("expected corruption error, got {other:?}"),
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), contents);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_huge_bulk_length_is_corruption() {
+        let mut contents = SET_A.to_vec();
+        contents.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$999999999999\r\nb\r\n");
+        let path = temp_aof("huge-bulk", &contents);
+
+        let store = DataStore::new(16);
+        let mut store = store.try_lock_all().unwrap();
+        match replay(&path, &mut store, 16, true) {
+            Err(AofError::Corrupted { offset, .. }) => assert_eq!(offset, SET_A.len() as u64),
+            other => panic!("expected corruption error, got {other:?}"),
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_fsync_tracks_replication_offset() {
+        let path = temp_aof("reploff", b"");
+        let mut writer = AofWriter::new();
+        writer.open(&path, FsyncPolicy::Everysec).unwrap();
+        writer.log_command("SET", &[]).unwrap();
+        writer.advance_reploff(42);
+        assert_eq!(writer.fsynced_reploff(), 0);
+        assert_eq!(writer.fsync().unwrap(), 42);
+
+        // appendfsync always is durable as soon as the command is logged
+        let mut writer = AofWriter::new();
+        writer.open(&path, FsyncPolicy::Always).unwrap();
+        writer.advance_reploff(7);
+        assert_eq!(writer.fsynced_reploff(), 7);
+
+        // appendfsync no never claims anything reached the disk
+        let mut writer = AofWriter::new();
+        writer.open(&path, FsyncPolicy::No).unwrap();
+        writer.advance_reploff(9);
+        assert_eq!(writer.fsync().unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,532 @@
+//! Conversion of RDB records to and from newline-delimited JSON.
+//!
+//! Each line is one key:
+//!
+//! ```text
+//! {"db":0,"key":"user:1","type":"hash","ttl":3600000,"value":{"name":"ada"}}
+//! ```
+//!
+//! `ttl` is the remaining time to live in milliseconds (`null` when the key has
+//! no expiry). Values are shaped by type: a string for `string`, an array of
+//! strings for `list` and `set`, an object for `hash`, and an array of
+//! `[member, score]` pairs for `zset`. Binary data that is not valid UTF-8 is
+//! written as `{"hex":"..."}` in place of a string.
+
+use crate::glob::glob_match;
+use crate::store::entry::{Entry, now_millis};
+use crate::types::RedisValue;
+use crate::types::hash::RedisHash;
+use crate::types::list::RedisList;
+use crate::types::rstring::RedisString;
+use crate::types::set::RedisSet;
+use crate::types::sorted_set::RedisSortedSet;
+
+/// Selects which keys are exported or imported.
+#[derive(Debug, Default, Clone)]
+pub struct RecordFilter {
+    pub db: Option<usize>,
+    pub key_pattern: Option<String>,
+    pub type_name: Option<String>,
+}
+
+impl RecordFilter {
+    pub fn matches(&self, db: usize, key: &str, entry: &Entry) -> bool {
+        self.db.is_none_or(|d| d == db)
+            && self
+                .key_pattern
+                .as_deref()
+                .is_none_or(|p| glob_match(p, key))
+            && self
+                .type_name
+                .as_deref()
+                .is_none_or(|t| entry.value.type_name().eq_ignore_ascii_case(t))
+    }
+}
+
+/// A parsed JSON value. Object members keep their input order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    fn get(&self, name: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members.iter().find(|(k, _)| k == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Serialize compactly onto `out`.
+    pub fn write(&self, out: &mut String) {
+        match self {
+            JsonValue::Null => out.push_str("null"),
+            JsonValue::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            JsonValue::Number(n) => {
+                if n.is_finite() {
+                    out.push_str(&n.to_string());
+                } else {
+                    // JSON has no infinity; zset scores use strings instead
+                    write_json_string(out, if *n > 0.0 { "inf" } else { "-inf" });
+                }
+            }
+            JsonValue::String(s) => write_json_string(out, s),
+            JsonValue::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write(out);
+                }
+                out.push(']');
+            }
+            JsonValue::Object(members) => {
+                out.push('{');
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(out, k);
+                    out.push(':');
+                    v.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Parse a complete JSON document.
+pub fn parse_json(input: &str) -> Result<JsonValue, String> {
+    let mut parser = JsonParser {
+        bytes: input.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.pos != parser.bytes.len() {
+        return Err(format!("trailing characters at offset {}", parser.pos));
+    }
+    Ok(value)
+}
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!(
+                "expected '{}' at offset {}",
+                byte as char, self.pos
+            ))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => self.parse_string().map(JsonValue::String),
+            Some(b't') => self.parse_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.parse_literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.parse_literal("null", JsonValue::Null),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            _ => Err(format!("unexpected input at offset {}", self.pos)),
+        }
+    }
+
+    fn parse_literal(&mut self, word: &str, value: JsonValue) -> Result<JsonValue, String> {
+        if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(format!("unexpected input at offset {}", self.pos))
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, String> {
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(JsonValue::Number)
+            .ok_or_else(|| format!("invalid number at offset {start}"))
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while self
+                .bytes
+                .get(self.pos)
+                .is_some_and(|&b| b != b'"' && b != b'\\')
+            {
+                self.pos += 1;
+            }
+            out.push_str(
+                std::str::from_utf8(&self.bytes[start..self.pos])
+                    .map_err(|_| format!("invalid UTF-8 at offset {start}"))?,
+            );
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    let escape = self.bytes.get(self.pos + 1).copied();
+                    self.pos += 2;
+                    match escape {
+                        Some(b'"') => out.push('"'),
+                        Some(b'\\') => out.push('\\'),
+                        Some(b'/') => out.push('/'),
+                        Some(b'b') => out.push('\u{8}'),
+                        Some(b'f') => out.push('\u{c}'),
+                        Some(b'n') => out.push('\n'),
+                        Some(b'r') => out.push('\r'),
+                        Some(b't') => out.push('\t'),
+                        Some(b'u') => out.push(self.parse_unicode_escape()?),
+                        _ => return Err(format!("invalid escape at offset {}", self.pos - 2)),
+                    }
+                }
+                _ => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    /// Parse the hex digits of a `\u` escape, combining surrogate pairs.
+    fn parse_unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.parse_hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.bytes[self.pos..].starts_with(b"\\u") {
+                return Err(format!("unpaired surrogate at offset {}", self.pos));
+            }
+            self.pos += 2;
+            let low = self.parse_hex4()?;
+            0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| format!("invalid code point at offset {}", self.pos))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| format!("invalid \\u escape at offset {}", self.pos))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(JsonValue::Array(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                _ => return Err(format!("expected ',' or ']' at offset {}", self.pos)),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, String> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(JsonValue::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(b':')?;
+            members.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(members));
+                }
+                _ => return Err(format!("expected ',' or '}}' at offset {}", self.pos)),
+            }
+        }
+    }
+}
+
+/// Encode binary data, falling back to `{"hex": ...}` for non-UTF-8 bytes.
+fn bytes_to_json(data: &[u8]) -> JsonValue {
+    match std::str::from_utf8(data) {
+        Ok(s) => JsonValue::String(s.to_string()),
+        Err(_) => {
+            let hex: String = data.iter().map(|b| format!("{b:02x}")).collect();
+            JsonValue::Object(vec![("hex".to_string(), JsonValue::String(hex))])
+        }
+    }
+}
+
+fn json_to_bytes(value: &JsonValue) -> Result<Vec<u8>, String> {
+    match value {
+        JsonValue::String(s) => Ok(s.as_bytes().to_vec()),
+        JsonValue::Number(n) => Ok(n.to_string().into_bytes()),
+        JsonValue::Object(_) => {
+            let hex = match value.get("hex") {
+                Some(JsonValue::String(h)) if h.len() % 2 == 0 => h,
+                _ => return Err("expected {\"hex\": \"...\"}".to_string()),
+            };
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<_, _>>()
+                .map_err(|_| "invalid hex string".to_string())
+        }
+        _ => Err("expected a string value".to_string()),
+    }
+}
+
+/// Encode one key as a JSON line (without the trailing newline).
+/// Returns `None` for types that RDB files do not carry yet.
+pub fn record_to_json(db: usize, key: &str, entry: &Entry) -> Option<String> {
+    let value = match &entry.value {
+        RedisValue::String(s) => bytes_to_json(s.as_bytes()),
+        RedisValue::List(l) => JsonValue::Array(l.iter().map(|v| bytes_to_json(&v)).collect()),
+        RedisValue::Set(s) => JsonValue::Array(s.iter().map(|m| bytes_to_json(&m)).collect()),
+        RedisValue::Hash(h) => JsonValue::Object(
+            h.iter()
+                .map(|(f, v)| (f.to_string(), bytes_to_json(v)))
+                .collect(),
+        ),
+        RedisValue::SortedSet(z) => JsonValue::Array(
+            z.iter()
+                .map(|(m, score)| {
+                    JsonValue::Array(vec![bytes_to_json(m), JsonValue::Number(score)])
+                })
+                .collect(),
+        ),
+        RedisValue::Stream(_) => return None,
+    };
+
+    let ttl = match entry.ttl_millis() {
+        -1 => JsonValue::Null,
+        ms => JsonValue::Number(ms.max(0) as f64),
+    };
+
+    let record = JsonValue::Object(vec![
+        ("db".to_string(), JsonValue::Number(db as f64)),
+        ("key".to_string(), JsonValue::String(key.to_string())),
+        (
+            "type".to_string(),
+            JsonValue::String(entry.value.type_name().to_string()),
+        ),
+        ("ttl".to_string(), ttl),
+        ("value".to_string(), value),
+    ]);
+    let mut out = String::new();
+    record.write(&mut out);
+    Some(out)
+}
+
+/// Decode one JSON line into `(db, key, entry)`.
+/// A `ttl` is converted to an absolute expiry relative to the current time.
+pub fn record_from_json(line: &str) -> Result<(usize, String, Entry), String> {
+    let record = parse_json(line)?;
+
+    let db = match record.get("db") {
+        None => 0,
+        Some(JsonValue::Number(n)) if *n >= 0.0 && n.fract() == 0.0 => *n as usize,
+        Some(_) => return Err("'db' must be a non-negative integer".to_string()),
+    };
+    let key = match record.get("key") {
+        Some(JsonValue::String(k)) => k.clone(),
+        _ => return Err("missing 'key'".to_string()),
+    };
+    let type_name = match record.get("type") {
+        Some(JsonValue::String(t)) => t.as_str(),
+        _ => return Err("missing 'type'".to_string()),
+    };
+    let value = record.get("value").ok_or("missing 'value'")?;
+    let items = || match value {
+        JsonValue::Array(items) => Ok(items),
+        _ => Err(format!("'value' of a {type_name} must be an array")),
+    };
+
+    let value = match type_name {
+        "string" => RedisValue::String(RedisString::new(json_to_bytes(value)?)),
+        "list" => {
+            let mut list = RedisList::new();
+            for item in items()? {
+                list.rpush(json_to_bytes(item)?);
+            }
+            RedisValue::List(list)
+        }
+        "set" => {
+            let mut set = RedisSet::new();
+            for item in items()? {
+                set.add(json_to_bytes(item)?);
+            }
+            RedisValue::Set(set)
+        }
+        "hash" => {
+            let JsonValue::Object(members) = value else {
+                return Err("'value' of a hash must be an object".to_string());
+            };
+            let mut hash = RedisHash::new();
+            for (field, v) in members {
+                hash.set(field.clone(), json_to_bytes(v)?);
+            }
+            RedisValue::Hash(hash)
+        }
+        "zset" => {
+            let mut zset = RedisSortedSet::new();
+            for item in items()? {
+                let (member, score) = match item {
+                    JsonValue::Array(pair) if pair.len() == 2 => (&pair[0], &pair[1]),
+                    _ => return Err("zset entries must be [member, score] pairs".to_string()),
+                };
+                let score = match score {
+                    JsonValue::Number(n) => *n,
+                    JsonValue::String(s) => match s.as_str() {
+                        "inf" | "+inf" => f64::INFINITY,
+                        "-inf" => f64::NEG_INFINITY,
+                        _ => return Err(format!("invalid zset score '{s}'")),
+                    },
+                    _ => return Err("invalid zset score".to_string()),
+                };
+                zset.add(json_to_bytes(member)?, score);
+            }
+            RedisValue::SortedSet(zset)
+        }
+        other => return Err(format!("unsupported type '{other}'")),
+    };
+
+    let mut entry = Entry::new(value);
+    match record.get("ttl") {
+        None | Some(JsonValue::Null) => {}
+        Some(JsonValue::Number(ms)) if *ms >= 0.0 => {
+            entry.expires_at = Some(now_millis() + *ms as u64);
+        }
+        Some(_) => return Err("'ttl' must be null or a non-negative number".to_string()),
+    }
+    Ok((db, key, entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_roundtrip_all_types() {
+        let mut hash = RedisHash::new();
+        hash.set("name".to_string(), b"ada \"lovelace\"".to_vec());
+        let mut zset = RedisSortedSet::new();
+        zset.add(b"alice".to_vec(), 1.5);
+        zset.add(b"bob".to_vec(), f64::INFINITY);
+        let mut list = RedisList::new();
+        list.rpush(vec![0xff, 0x00, 0x10]);
+        list.rpush(b"line\nbreak".to_vec());
+
+        for value in [
+            RedisValue::Hash(hash),
+            RedisValue::SortedSet(zset),
+            RedisValue::List(list),
+        ] {
+            let entry = Entry::new(value);
+            let line = record_to_json(3, "k", &entry).unwrap();
+            let (db, key, decoded) = record_from_json(&line).unwrap();
+            assert_eq!((db, key.as_str()), (3, "k"));
+            assert_eq!(record_to_json(3, "k", &decoded).unwrap(), line);
+        }
+    }
+
+    #[test]
+    fn test_json_ttl_and_filter() {
+        let entry = Entry::with_expiry(
+            RedisValue::String(RedisString::new(b"v".to_vec())),
+            now_millis() + 60_000,
+        );
+        let line = record_to_json(0, "session:1", &entry).unwrap();
+        let (_, _, decoded) = record_from_json(&line).unwrap();
+        let ttl = decoded.ttl_millis();
+        assert!(ttl > 50_000 && ttl <= 60_000, "ttl was {ttl}");
+
+        let filter = RecordFilter {
+            db: Some(0),
+            key_pattern: Some("session:*".to_string()),
+            type_name: Some("string".to_string()),
+        };
+        assert!(filter.matches(0, "session:1", &decoded));
+        assert!(!filter.matches(1, "session:1", &decoded));
+        assert!(!filter.matches(0, "user:1", &decoded));
+    }
+
+    #[test]
+    fn test_parse_json_errors() {
+        assert!(parse_json("{\"a\":1").is_err());
+        assert!(parse_json("[1,]").is_err());
+        assert!(parse_json("\"\\ud83d\\ude00\"").is_ok());
+        assert!(record_from_json("{\"key\":\"k\",\"type\":\"list\",\"value\":\"x\"}").is_err());
+    }
+}
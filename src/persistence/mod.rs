@@ -1,2 +1,4 @@
 pub mod aof;
+pub mod crc64;
+pub mod export;
 pub mod rdb;
@@ -1,9 +1,11 @@
-use crate::store::DataStore;
+use crate::persistence::crc64::{Crc64Reader, Crc64Writer};
 use crate::store::entry::Entry;
+use crate::store::{DataStore, KeyspaceGuard};
 use crate::types::RedisValue;
 use std::io::{self, Read, Write};
 
 // RDB opcodes
+const RDB_OPCODE_AUX: u8 = 0xFA;
 const RDB_OPCODE_EXPIRETIME_MS: u8 = 0xFC;
 const RDB_OPCODE_SELECTDB: u8 = 0xFE;
 const RDB_OPCODE_EOF: u8 = 0xFF;
@@ -20,7 +22,7 @@ const RDB_MAGIC: &[u8] = b"REDIS";
 const RDB_VERSION: &[u8] = b"0011";
 
 /// Write the data store to an RDB file (atomic via temp file + rename).
-pub fn save(store: &DataStore, path: &str) -> io::Result<()> {
+pub fn save(store: &mut KeyspaceGuard, path: &str) -> io::Result<()> {
     let tmp_path = format!("{path}.tmp");
     let mut file = std::fs::File::create(&tmp_path)?;
     save_to_writer(&mut file, store)?;
@@ -31,12 +33,16 @@ pub fn save(store: &DataStore, path: &str) -> io::Result<()> {
 }
 
 /// Write the data store to any writer in RDB format.
-pub fn save_to_writer(w: &mut impl Write, store: &DataStore) -> io::Result<()> {
+pub fn save_to_writer(w: &mut impl Write, store: &mut KeyspaceGuard) -> io::Result<()> {
+    let mut w = Crc64Writer::new(w);
+    let w = &mut w;
+
     // Magic + version
     w.write_all(RDB_MAGIC)?;
     w.write_all(RDB_VERSION)?;
 
-    for (db_index, db) in store.databases.iter().enumerate() {
+    for db in store.databases() {
+        let db_index = db.index();
         let entries: Vec<_> = db.iter().collect();
         if entries.is_empty() {
             continue;
@@ -73,10 +79,9 @@ pub fn save_to_writer(w: &mut impl Write, store: &DataStore) -> io::Result<()> {
                 RedisValue::List(list) => {
                     w.write_all(&[RDB_TYPE_LIST])?;
                     write_string(w, key.as_bytes())?;
-                    let items: Vec<_> = list.iter().collect();
-                    write_length(w, items.len() as u64)?;
-                    for item in items {
-                        write_string(w, item)?;
+                    write_length(w, list.len() as u64)?;
+                    for item in list.iter() {
+                        write_string(w, &item)?;
                     }
                 }
                 RedisValue::Set(set) => {
@@ -85,7 +90,7 @@ pub fn save_to_writer(w: &mut impl Write, store: &DataStore) -> io::Result<()> {
                     let members = set.members();
                     write_length(w, members.len() as u64)?;
                     for member in members {
-                        write_string(w, member)?;
+                        write_string(w, &member)?;
                     }
                 }
                 RedisValue::SortedSet(zset) => {
@@ -111,24 +116,19 @@ pub fn save_to_writer(w: &mut impl Write, store: &DataStore) -> io::Result<()> {
                 RedisValue::Stream(_) => {
                     // Stream RDB serialization not yet implemented; skip
                 }
-                RedisValue::HyperLogLog(_) => {
-                    // HyperLogLog RDB serialization not yet implemented; skip
-                }
-                RedisValue::Geo(_) => {
-                    // Geo RDB serialization not yet implemented; skip
-                }
             }
         }
     }
 
-    // EOF + 8 byte checksum (0 for now)
+    // EOF + 8 byte CRC64 of everything before it
     w.write_all(&[RDB_OPCODE_EOF])?;
-    w.write_all(&[0u8; 8])?;
+    let crc = w.crc();
+    w.inner_mut().write_all(&crc.to_le_bytes())?;
     Ok(())
 }
 
 /// Serialize the data store to an in-memory RDB byte vector.
-pub fn save_to_bytes(store: &DataStore) -> io::Result<Vec<u8>> {
+pub fn save_to_bytes(store: &mut KeyspaceGuard) -> io::Result<Vec<u8>> {
     let mut buf = Vec::with_capacity(4096);
     save_to_writer(&mut buf, store)?;
     Ok(buf)
@@ -140,75 +140,165 @@ pub fn load(path: &str, num_databases: usize) -> io::Result<DataStore> {
     load_from_reader(&mut file, num_databases)
 }
 
+/// Checksum status of a successfully loaded RDB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RdbChecksum {
+    /// The trailing CRC64 matched the file contents.
+    Verified(u64),
+    /// The file was written with checksums disabled (all-zero trailer).
+    Disabled,
+}
+
 /// Load an RDB from any reader into a data store.
 pub fn load_from_reader(r: &mut impl Read, num_databases: usize) -> io::Result<DataStore> {
-    let mut store = DataStore::new(num_databases);
+    load_from_reader_checked(r, num_databases).map(|(store, _)| store)
+}
 
-    // Read magic
-    let mut magic = [0u8; 5];
-    r.read_exact(&mut magic)?;
-    if magic != *RDB_MAGIC {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Invalid RDB magic",
-        ));
+/// Load an RDB from any reader, also reporting how its checksum was verified.
+/// A checksum mismatch or a file that ends before the EOF opcode is an error.
+pub fn load_from_reader_checked(
+    r: &mut impl Read,
+    num_databases: usize,
+) -> io::Result<(DataStore, RdbChecksum)> {
+    let mut reader = RdbReader::new(r, num_databases)?;
+    let store = DataStore::new(num_databases);
+    let mut keyspace = store.try_lock_all().expect("a new store is not shared");
+    while let Some(record) = reader.next_record()? {
+        keyspace.db(record.db).set(record.key, record.entry);
     }
+    drop(keyspace);
+    let checksum = reader
+        .checksum()
+        .expect("checksum is known once all records are read");
+    Ok((store, checksum))
+}
 
-    // Read version
-    let mut version = [0u8; 4];
-    r.read_exact(&mut version)?;
+/// A single key read from an RDB file.
+#[derive(Debug)]
+pub struct RdbRecord {
+    pub db: usize,
+    pub key: String,
+    pub entry: Entry,
+}
 
-    let mut current_db = 0usize;
-    let mut next_expiry: Option<u64> = None;
+/// Streaming RDB reader that yields one key at a time, so a snapshot can be
+/// inspected without materializing the whole data store.
+pub struct RdbReader<R> {
+    r: Crc64Reader<R>,
+    num_databases: usize,
+    current_db: usize,
+    checksum: Option<RdbChecksum>,
+}
+
+impl<R: Read> RdbReader<R> {
+    /// Validate the RDB header and prepare to read records.
+    pub fn new(r: R, num_databases: usize) -> io::Result<Self> {
+        let mut r = Crc64Reader::new(r);
 
-    loop {
-        let mut byte = [0u8; 1];
-        if r.read_exact(&mut byte).is_err() {
-            break;
+        // Read magic
+        let mut magic = [0u8; 5];
+        r.read_exact(&mut magic)?;
+        if magic != *RDB_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid RDB magic",
+            ));
         }
 
-        match byte[0] {
-            RDB_OPCODE_EOF => break,
-            RDB_OPCODE_SELECTDB => {
-                current_db = read_length(r)? as usize;
-                if current_db >= num_databases {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "DB index out of range",
-                    ));
-                }
-            }
-            RDB_OPCODE_RESIZEDB => {
-                let _db_size = read_length(r)?;
-                let _expires_size = read_length(r)?;
-            }
-            RDB_OPCODE_EXPIRETIME_MS => {
-                let mut buf = [0u8; 8];
-                r.read_exact(&mut buf)?;
-                next_expiry = Some(u64::from_le_bytes(buf));
-            }
-            0xFD => {
-                // EXPIRETIME in seconds
-                let mut buf = [0u8; 4];
-                r.read_exact(&mut buf)?;
-                next_expiry = Some(u32::from_le_bytes(buf) as u64 * 1000);
-            }
-            type_byte => {
-                let key = read_string_as_string(r)?;
-                let value = read_value(r, type_byte)?;
+        // Read version
+        let mut version = [0u8; 4];
+        r.read_exact(&mut version)?;
+
+        Ok(RdbReader {
+            r,
+            num_databases,
+            current_db: 0,
+            checksum: None,
+        })
+    }
+
+    /// Checksum status, available once `next_record` has returned `None`.
+    pub fn checksum(&self) -> Option<RdbChecksum> {
+        self.checksum
+    }
 
-                let mut entry = Entry::new(value);
-                if let Some(exp) = next_expiry.take() {
-                    entry.expires_at = Some(exp);
+    /// Read the next key, or `None` after the EOF opcode and checksum.
+    pub fn next_record(&mut self) -> io::Result<Option<RdbRecord>> {
+        if self.checksum.is_some() {
+            return Ok(None);
+        }
+        let r = &mut self.r;
+        let mut next_expiry: Option<u64> = None;
+
+        loop {
+            let mut byte = [0u8; 1];
+            r.read_exact(&mut byte)?;
+
+            match byte[0] {
+                RDB_OPCODE_EOF => break,
+                RDB_OPCODE_AUX => {
+                    // Auxiliary metadata (redis-ver, ctime, ...) is not needed
+                    let _key = read_string(r)?;
+                    let _value = read_string(r)?;
+                }
+                RDB_OPCODE_SELECTDB => {
+                    self.current_db = read_length(r)? as usize;
+                    if self.current_db >= self.num_databases {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "DB index out of range",
+                        ));
+                    }
+                }
+                RDB_OPCODE_RESIZEDB => {
+                    let _db_size = read_length(r)?;
+                    let _expires_size = read_length(r)?;
+                }
+                RDB_OPCODE_EXPIRETIME_MS => {
+                    let mut buf = [0u8; 8];
+                    r.read_exact(&mut buf)?;
+                    next_expiry = Some(u64::from_le_bytes(buf));
                 }
+                0xFD => {
+                    // EXPIRETIME in seconds
+                    let mut buf = [0u8; 4];
+                    r.read_exact(&mut buf)?;
+                    next_expiry = Some(u32::from_le_bytes(buf) as u64 * 1000);
+                }
+                type_byte => {
+                    let key = read_string_as_string(r)?;
+                    let value = read_value(r, type_byte)?;
 
-                let db = store.db(current_db);
-                db.set(key, entry);
+                    let mut entry = Entry::new(value);
+                    entry.expires_at = next_expiry;
+                    return Ok(Some(RdbRecord {
+                        db: self.current_db,
+                        key,
+                        entry,
+                    }));
+                }
             }
         }
-    }
 
-    Ok(store)
+        let computed = r.crc();
+        let mut buf = [0u8; 8];
+        r.inner_mut().read_exact(&mut buf)?;
+        let expected = u64::from_le_bytes(buf);
+        let checksum = if expected == 0 {
+            RdbChecksum::Disabled
+        } else if expected == computed {
+            RdbChecksum::Verified(computed)
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "RDB checksum mismatch: expected {expected:016x}, computed {computed:016x}"
+                ),
+            ));
+        };
+        self.checksum = Some(checksum);
+        Ok(None)
+    }
 }
 
 // --- Encoding helpers ---
@@ -402,3 +492,43 @@ fn read_value(r: &mut impl Read, type_byte: u8) -> io::Result<RedisValue> {
         )),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::rstring::RedisString;
+
+    fn sample_rdb() -> Vec<u8> {
+        let store = DataStore::new(16);
+        let mut keyspace = store.try_lock_all().unwrap();
+        keyspace.db(0).set(
+            "greeting".to_string(),
+            Entry::new(RedisValue::String(RedisString::new(b"hello".to_vec()))),
+        );
+        save_to_bytes(&mut keyspace).unwrap()
+    }
+
+    #[test]
+    fn test_checksum_roundtrip() {
+        let data = sample_rdb();
+        let (store, checksum) = load_from_reader_checked(&mut data.as_slice(), 16).unwrap();
+        assert!(matches!(checksum, RdbChecksum::Verified(_)));
+        assert!(store.try_lock_all().unwrap().db(0).exists("greeting"));
+    }
+
+    #[test]
+    fn test_checksum_mismatch_detected() {
+        let mut data = sample_rdb();
+        let pos = data.windows(5).position(|w| w == b"hello").unwrap();
+        data[pos] = b'j';
+        let err = load_from_reader(&mut data.as_slice(), 16).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+    }
+
+    #[test]
+    fn test_truncated_rdb_is_error() {
+        let data = sample_rdb();
+        let truncated = &data[..data.len() - 12];
+        assert!(load_from_reader(&mut &truncated[..], 16).is_err());
+    }
+}
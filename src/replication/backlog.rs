@@ -16,13 +16,18 @@ pub struct ReplicationBacklog {
 
 impl ReplicationBacklog {
     pub fn new(capacity: usize) -> Self {
+        Self::with_offset(capacity, 0)
+    }
+
+    /// Create an empty backlog whose history starts at replication offset `offset`.
+    pub fn with_offset(capacity: usize, offset: i64) -> Self {
         ReplicationBacklog {
             buf: vec![0u8; capacity],
             capacity,
             write_pos: 0,
             total_written: 0,
-            start_offset: 0,
-            end_offset: 0,
+            start_offset: offset,
+            end_offset: offset,
         }
     }
 
@@ -45,9 +50,6 @@ impl ReplicationBacklog {
 
     /// Check if a given replication offset is still available in the backlog.
     pub fn is_valid_offset(&self, offset: i64) -> bool {
-        if self.total_written == 0 {
-            return false;
-        }
         offset >= self.start_offset && offset <= self.end_offset
     }
 
@@ -142,4 +144,16 @@ mod tests {
         let read = bl.read_from(2).unwrap();
         assert_eq!(read, b"345678abcd");
     }
+
+    #[test]
+    fn test_backlog_with_offset() {
+        let mut bl = ReplicationBacklog::with_offset(100, 1000);
+        // A replica that is fully caught up can continue with nothing to send
+        assert_eq!(bl.read_from(1000).unwrap(), b"");
+        assert!(!bl.is_valid_offset(999));
+
+        bl.append(b"abc", 1003);
+        assert_eq!(bl.first_byte_offset(), 1000);
+        assert_eq!(bl.read_from(1001).unwrap(), b"bc");
+    }
 }
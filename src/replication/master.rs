@@ -1,8 +1,14 @@
+use crate::config::SharedConfig;
+use crate::connection::ClientState;
 use crate::persistence::rdb;
-use crate::replication::{ReplicaInfo, ReplicaState, SharedReplicationState};
+use crate::replication::transfer;
+use crate::replication::{
+    ReplicaInfo, ReplicaState, ReplicationRole, ReplicationState, SharedReplicationState,
+};
 use crate::resp::{RespParser, RespValue};
-use crate::store::SharedStore;
+use crate::store::{KeyspaceGuard, SharedStore};
 use bytes::BytesMut;
+use std::io;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
@@ -12,119 +18,250 @@ static NEXT_REPLICA_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::Atomic
 
 /// Handle the PSYNC handshake and full/partial sync with a replica.
 /// This takes over the TCP connection after a PSYNC command is received.
+/// `PSYNC <replid> <offset> FAILOVER` comes from our master handing over to
+/// us: we promote ourselves first, then serve it as our replica.
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_psync(
     mut stream: TcpStream,
     replid: String,
     offset: i64,
+    failover: bool,
+    listening_port: u16,
     store: &SharedStore,
+    config: &SharedConfig,
     repl_state: &SharedReplicationState,
-    repl_backlog_size: usize,
 ) {
-    let (replid_match, master_replid, master_offset) = {
-        let state = repl_state.read().await;
-        let matches = replid == state.master_replid || replid == "?";
-        (
-            matches,
-            state.master_replid.clone(),
-            state.master_repl_offset,
-        )
-    };
+    let peer_addr = stream
+        .peer_addr()
+        .map(|a| a.ip().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
 
-    // Try partial resync
-    if replid != "?" && replid_match && offset > 0 {
-        let partial_data = {
-            let state = repl_state.read().await;
-            state.backlog.as_ref().and_then(|bl| bl.read_from(offset))
-        };
+    if failover {
+        let mut state = repl_state.write().await;
+        if state.role != ReplicationRole::Replica || replid != state.master_replid {
+            drop(state);
+            let _ = stream
+                .write_all(b"-ERR PSYNC FAILOVER replid must match my replid.\r\n")
+                .await;
+            return;
+        }
+        state.become_master();
+        info!("FAILOVER requested by our master, MASTER MODE enabled");
+        drop(state);
+        config.write().await.replicaof = None;
+    }
 
-        if let Some(data) = partial_data {
-            // Partial resync possible
-            let continue_resp = format!("+CONTINUE {master_replid}\r\n");
-            if stream.write_all(continue_resp.as_bytes()).await.is_err() {
-                return;
+    // Try partial resync, against either our current ID or replid2.
+    // The replica is registered under the same lock that reads the backlog,
+    // so no write can fall between the backlog data and the live stream.
+    let (partial, master_replid) = {
+        let mut state = repl_state.write().await;
+        let partial = if replid != "?" && offset > 0 {
+            state.partial_resync_data(&replid, offset).map(|data| {
+                (
+                    data,
+                    register_replica(
+                        &mut state,
+                        &peer_addr,
+                        listening_port,
+                        offset,
+                        ReplicaState::Online,
+                    ),
+                )
+            })
+        } else {
+            None
+        };
+        if partial.is_some() {
+            state.sync_partial_ok += 1;
+        } else {
+            if replid != "?" {
+                state.sync_partial_err += 1;
             }
+            state.sync_full += 1;
+        }
+        (partial, state.master_replid.clone())
+    };
 
-            // Send missing data
-            if !data.is_empty() && stream.write_all(&data).await.is_err() {
-                return;
-            }
+    if let Some((data, (replica_id, rx))) = partial {
+        let continue_resp = format!("+CONTINUE {master_replid}\r\n");
+        if stream.write_all(continue_resp.as_bytes()).await.is_err() {
+            unregister_replica(repl_state, replica_id).await;
+            return;
+        }
 
-            info!("Partial resync with replica at offset {offset}");
-            register_and_stream(stream, repl_state, master_offset).await;
+        // Send missing data
+        if !data.is_empty() && stream.write_all(&data).await.is_err() {
+            unregister_replica(repl_state, replica_id).await;
             return;
         }
-    }
 
-    // Full resync
-    let fullresync_resp = format!("+FULLRESYNC {master_replid} {master_offset}\r\n");
-    if stream.write_all(fullresync_resp.as_bytes()).await.is_err() {
+        info!("Partial resync with replica at offset {offset}");
+        stream_to_replica(stream, repl_state, replica_id, rx, peer_addr).await;
         return;
     }
 
-    // Generate RDB in memory
-    let rdb_data = {
-        let store_guard = store.read().await;
-        match rdb::save_to_bytes(&store_guard) {
-            Ok(data) => data,
-            Err(e) => {
-                warn!("Failed to generate RDB for replication: {e}");
-                return;
-            }
-        }
+    // Full resync. The replica is registered under the same locks that pin
+    // the snapshot, so writes made once they are released queue up on its
+    // channel and are replayed once the RDB has been sent.
+    let (repl_backlog_size, diskless, dir) = {
+        let cfg = config.read().await;
+        (
+            cfg.repl_backlog_size,
+            cfg.repl_diskless_sync,
+            cfg.dir.clone(),
+        )
+    };
+    let snapshot = store.lock_all().await;
+    let (replica_id, rx, master_replid, master_offset) = {
+        let mut state = repl_state.write().await;
+        state.ensure_backlog(repl_backlog_size);
+        let offset = state.master_repl_offset;
+        let (id, rx) = register_replica(
+            &mut state,
+            &peer_addr,
+            listening_port,
+            offset,
+            ReplicaState::WaitBgsave,
+        );
+        (id, rx, state.master_replid.clone(), offset)
     };
 
-    // Send RDB as bulk string: $<len>\r\n<rdb_data>
-    let header = format!("${}\r\n", rdb_data.len());
-    if stream.write_all(header.as_bytes()).await.is_err() {
-        return;
-    }
-    if stream.write_all(&rdb_data).await.is_err() {
-        return;
+    let fullresync_resp = format!("+FULLRESYNC {master_replid} {master_offset}\r\n");
+    let sent = match stream.write_all(fullresync_resp.as_bytes()).await {
+        Ok(()) if diskless => {
+            send_rdb_diskless(&mut stream, snapshot, repl_state, replica_id).await
+        }
+        Ok(()) => send_rdb_from_disk(&mut stream, snapshot, &dir, repl_state, replica_id).await,
+        Err(e) => Err(e),
+    };
+    match sent {
+        Ok(len) => info!("Full resync with replica, sent {len} bytes RDB"),
+        Err(e) => {
+            warn!("Failed to send RDB to replica {peer_addr}: {e}");
+            unregister_replica(repl_state, replica_id).await;
+            return;
+        }
     }
 
-    info!(
-        "Full resync with replica, sent {} bytes RDB",
-        rdb_data.len()
-    );
+    set_replica_state(repl_state, replica_id, ReplicaState::Online).await;
+    stream_to_replica(stream, repl_state, replica_id, rx, peer_addr).await;
+}
 
-    // Ensure backlog exists
-    {
-        let mut state = repl_state.write().await;
-        state.ensure_backlog(repl_backlog_size);
-    }
+/// Stream the snapshot straight to the socket as `$EOF:<mark>\r\n<rdb><mark>`.
+/// Writers only wait while the snapshot is serialized, not for the replica
+/// to receive it; what they write meanwhile queues on the replica's channel.
+async fn send_rdb_diskless(
+    stream: &mut TcpStream,
+    snapshot: KeyspaceGuard,
+    repl_state: &SharedReplicationState,
+    replica_id: u64,
+) -> io::Result<usize> {
+    let mark = transfer::eof_mark();
+    stream
+        .write_all(format!("$EOF:{mark}\r\n").as_bytes())
+        .await?;
+    set_replica_state(repl_state, replica_id, ReplicaState::SendBulk).await;
 
-    register_and_stream(stream, repl_state, master_offset).await;
+    let (mut chunks, writer) = transfer::spawn_rdb_writer(snapshot);
+    let mut sent = 0;
+    while let Some(chunk) = chunks.recv().await {
+        stream.write_all(&chunk).await?;
+        sent += chunk.len();
+    }
+    writer.await.map_err(io::Error::other)??;
+    stream.write_all(mark.as_bytes()).await?;
+    Ok(sent)
 }
 
-/// Register this replica and enter the streaming loop.
-async fn register_and_stream(
-    mut stream: TcpStream,
+/// Save the snapshot to a temporary RDB file, then send it as a bulk string.
+/// The store is unlocked as soon as the file is written.
+async fn send_rdb_from_disk(
+    stream: &mut TcpStream,
+    snapshot: KeyspaceGuard,
+    dir: &str,
     repl_state: &SharedReplicationState,
-    initial_offset: i64,
-) {
-    let (tx, mut rx) = mpsc::unbounded_channel::<bytes::Bytes>();
+    replica_id: u64,
+) -> io::Result<usize> {
+    let path = format!("{dir}/temp-repl-{}-{replica_id}.rdb", std::process::id());
+    let save_path = path.clone();
+    let saved = tokio::task::spawn_blocking(move || {
+        let mut snapshot = snapshot;
+        rdb::save(&mut snapshot, &save_path)
+    })
+    .await
+    .map_err(io::Error::other)
+    .and_then(|r| r);
 
-    let replica_id = NEXT_REPLICA_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    let peer_addr = stream
-        .peer_addr()
-        .map(|a| a.to_string())
-        .unwrap_or_else(|_| "unknown".to_string());
+    let result = match saved {
+        Ok(()) => {
+            set_replica_state(repl_state, replica_id, ReplicaState::SendBulk).await;
+            send_file(stream, &path).await
+        }
+        Err(e) => Err(e),
+    };
+    let _ = tokio::fs::remove_file(&path).await;
+    result
+}
 
-    {
-        let mut state = repl_state.write().await;
-        state.replicas.push(ReplicaInfo {
-            id: replica_id,
-            addr: peer_addr.clone(),
-            port: 0,
-            state: ReplicaState::Online,
-            offset: initial_offset,
-            lag: 0,
-            tx,
-        });
+async fn send_file(stream: &mut TcpStream, path: &str) -> io::Result<usize> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    stream.write_all(format!("${len}\r\n").as_bytes()).await?;
+    tokio::io::copy(&mut file, stream).await?;
+    Ok(len as usize)
+}
+
+async fn set_replica_state(
+    repl_state: &SharedReplicationState,
+    replica_id: u64,
+    replica_state: ReplicaState,
+) {
+    let mut state = repl_state.write().await;
+    if let Some(r) = state.replicas.iter_mut().find(|r| r.id == replica_id) {
+        r.state = replica_state;
     }
+}
 
+/// Add a replica to the registry. Writes propagated from now on are queued
+/// on the returned channel. A replica that still needs an RDB starts out
+/// waiting for it and is marked online once the transfer is done.
+fn register_replica(
+    state: &mut ReplicationState,
+    peer_addr: &str,
+    listening_port: u16,
+    initial_offset: i64,
+    replica_state: ReplicaState,
+) -> (u64, mpsc::UnboundedReceiver<bytes::Bytes>) {
+    let (tx, rx) = mpsc::unbounded_channel::<bytes::Bytes>();
+    let replica_id = NEXT_REPLICA_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    state.replicas.push(ReplicaInfo {
+        id: replica_id,
+        addr: peer_addr.to_string(),
+        port: listening_port,
+        state: replica_state,
+        offset: initial_offset,
+        aof_offset: -1,
+        last_ack: std::time::Instant::now(),
+        tx,
+    });
     debug!("Replica {peer_addr} registered (id={replica_id})");
+    (replica_id, rx)
+}
 
+async fn unregister_replica(repl_state: &SharedReplicationState, replica_id: u64) {
+    let mut state = repl_state.write().await;
+    state.replicas.retain(|r| r.id != replica_id);
+}
+
+/// Forward the replication stream to a registered replica and read its ACKs.
+async fn stream_to_replica(
+    mut stream: TcpStream,
+    repl_state: &SharedReplicationState,
+    replica_id: u64,
+    mut rx: mpsc::UnboundedReceiver<bytes::Bytes>,
+    peer_addr: String,
+) {
     let mut buf = BytesMut::with_capacity(256);
 
     loop {
@@ -145,23 +282,11 @@ async fn register_and_stream(
                 match result {
                     Ok(0) => break, // Disconnected
                     Ok(_) => {
-                        // Parse any REPLCONF ACK responses
+                        // Parse any REPLCONF ACK <offset> [FACK <aofoffset>] responses
                         while let Ok(Some(value)) = RespParser::parse(&mut buf) {
-                            if let RespValue::Array(Some(ref items)) = value
-                                && items.len() >= 3
-                            {
-                                let cmd = items[0].to_string_lossy().unwrap_or_default().to_uppercase();
-                                let sub = items[1].to_string_lossy().unwrap_or_default().to_uppercase();
-                                if cmd == "REPLCONF" && sub == "ACK"
-                                    && let Some(offset_str) = items[2].to_string_lossy()
-                                    && let Ok(offset) = offset_str.parse::<i64>()
-                                {
-                                    let mut state = repl_state.write().await;
-                                    if let Some(r) = state.replicas.iter_mut().find(|r| r.id == replica_id) {
-                                        r.offset = offset;
-                                        r.lag = 0;
-                                    }
-                                }
+                            if let Some((offset, aof_offset)) = parse_ack(&value) {
+                                let mut state = repl_state.write().await;
+                                state.record_ack(replica_id, offset, aof_offset);
                             }
                         }
                     }
@@ -172,15 +297,29 @@ async fn register_and_stream(
     }
 
     // Cleanup: remove replica
-    {
-        let mut state = repl_state.write().await;
-        state.replicas.retain(|r| r.id != replica_id);
-    }
+    unregister_replica(repl_state, replica_id).await;
     debug!("Replica {peer_addr} disconnected (id={replica_id})");
 }
 
+/// Parse `REPLCONF ACK <offset> [FACK <aofoffset>]` sent by a replica.
+fn parse_ack(value: &RespValue) -> Option<(i64, Option<i64>)> {
+    let RespValue::Array(Some(items)) = value else {
+        return None;
+    };
+    let arg = |i: usize| items.get(i).and_then(|v| v.to_string_lossy());
+    if !arg(0)?.eq_ignore_ascii_case("REPLCONF") || !arg(1)?.eq_ignore_ascii_case("ACK") {
+        return None;
+    }
+    let offset = arg(2)?.parse().ok()?;
+    let aof_offset = match arg(3) {
+        Some(opt) if opt.eq_ignore_ascii_case("FACK") => arg(4).and_then(|s| s.parse().ok()),
+        _ => None,
+    };
+    Some((offset, aof_offset))
+}
+
 /// Handle REPLCONF command from a replica during handshake.
-pub fn handle_replconf(args: &[RespValue]) -> RespValue {
+pub fn handle_replconf(args: &[RespValue], client: &mut ClientState) -> RespValue {
     // Accept all REPLCONF subcommands during handshake
     if args.is_empty() {
         return RespValue::error("ERR wrong number of arguments for 'replconf' command");
@@ -189,7 +328,14 @@ pub fn handle_replconf(args: &[RespValue]) -> RespValue {
     let sub = args[0].to_string_lossy().unwrap_or_default().to_uppercase();
 
     match sub.as_str() {
-        "LISTENING-PORT" | "CAPA" | "GETACK" => RespValue::ok(),
+        "LISTENING-PORT" => match args.get(1).and_then(|a| a.to_string_lossy()?.parse().ok()) {
+            Some(port) => {
+                client.replica_listening_port = port;
+                RespValue::ok()
+            }
+            None => RespValue::error("ERR value is not an integer or out of range"),
+        },
+        "CAPA" | "GETACK" => RespValue::ok(),
         "ACK" => {
             // ACK is handled in the streaming loop, but during normal command
             // dispatch we just return OK
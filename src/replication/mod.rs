@@ -1,9 +1,12 @@
 pub mod backlog;
 pub mod master;
 pub mod replica;
+pub mod transfer;
 
+use crate::resp::RespValue;
 use std::sync::Arc;
-use tokio::sync::{RwLock, mpsc};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, RwLock, mpsc};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ReplicationRole {
@@ -30,18 +33,51 @@ impl std::fmt::Display for ReplicaState {
     }
 }
 
+/// Progress of a FAILOVER on this master.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailoverState {
+    NoFailover,
+    /// Writes are paused until a replica catches up with our offset.
+    WaitingForSync,
+    /// We are demoting ourselves with a `PSYNC ... FAILOVER` to the target.
+    FailoverInProgress,
+}
+
+impl std::fmt::Display for FailoverState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailoverState::NoFailover => write!(f, "no-failover"),
+            FailoverState::WaitingForSync => write!(f, "waiting-for-sync"),
+            FailoverState::FailoverInProgress => write!(f, "failover-in-progress"),
+        }
+    }
+}
+
 /// Info about a connected replica (from master's perspective).
 #[derive(Debug)]
 pub struct ReplicaInfo {
     pub id: u64,
+    /// IP address the replica connected from.
     pub addr: String,
+    /// Port the replica listens on, from `REPLCONF listening-port` (0 if unknown).
     pub port: u16,
     pub state: ReplicaState,
     pub offset: i64,
-    pub lag: u64,
+    /// Replication offset the replica has fsynced to its AOF, or -1 if it has no AOF.
+    pub aof_offset: i64,
+    /// When the replica last sent `REPLCONF ACK`.
+    pub last_ack: Instant,
     pub tx: mpsc::UnboundedSender<bytes::Bytes>,
 }
 
+impl ReplicaInfo {
+    /// Seconds since the replica's last ACK. Replicas ACK every second, so a
+    /// healthy replica has a lag of 0 or 1.
+    pub fn lag(&self) -> u64 {
+        self.last_ack.elapsed().as_secs()
+    }
+}
+
 /// The replication state shared across the server.
 #[derive(Debug)]
 pub struct ReplicationState {
@@ -52,6 +88,24 @@ pub struct ReplicationState {
     pub second_repl_offset: i64,
     pub replicas: Vec<ReplicaInfo>,
     pub backlog: Option<backlog::ReplicationBacklog>,
+    /// Replication offset covered by the last local AOF fsync.
+    pub aof_fsynced_offset: i64,
+    /// Woken whenever a replica ACK arrives or the local AOF is fsynced.
+    pub ack_notify: Arc<Notify>,
+    /// Full resyncs served to replicas.
+    pub sync_full: u64,
+    /// Partial resyncs accepted.
+    pub sync_partial_ok: u64,
+    /// Partial resyncs requested but refused (answered with a full resync).
+    pub sync_partial_err: u64,
+    pub failover_state: FailoverState,
+    /// Cancelled by `FAILOVER ABORT` while waiting for the target to sync.
+    pub failover_abort: Option<tokio_util::sync::CancellationToken>,
+    /// Held shared by client writes from before they run until they are
+    /// propagated, and exclusively by FAILOVER to pause them.
+    pub write_gate: Arc<tokio::sync::RwLock<()>>,
+    /// FAILOVER's exclusive hold on `write_gate`, released when it ends.
+    pub failover_pause: Option<tokio::sync::OwnedRwLockWriteGuard<()>>,
     // Replica-specific
     pub master_host: Option<String>,
     pub master_port: Option<u16>,
@@ -76,6 +130,15 @@ impl ReplicationState {
             second_repl_offset: -1,
             replicas: Vec::new(),
             backlog: None,
+            aof_fsynced_offset: 0,
+            ack_notify: Arc::new(Notify::new()),
+            sync_full: 0,
+            sync_partial_ok: 0,
+            sync_partial_err: 0,
+            failover_state: FailoverState::NoFailover,
+            failover_abort: None,
+            write_gate: Arc::new(tokio::sync::RwLock::new(())),
+            failover_pause: None,
             master_host: None,
             master_port: None,
             master_link_status: "up".to_string(),
@@ -85,10 +148,94 @@ impl ReplicationState {
     }
 
     /// Ensure the replication backlog exists with the given size.
+    /// A new backlog starts at the current offset.
     pub fn ensure_backlog(&mut self, size: usize) {
         if self.backlog.is_none() {
-            self.backlog = Some(backlog::ReplicationBacklog::new(size));
+            self.reset_backlog(size);
+        }
+    }
+
+    /// Discard the backlog history, e.g. after a full resync replaced the dataset.
+    pub fn reset_backlog(&mut self, size: usize) {
+        self.backlog = Some(backlog::ReplicationBacklog::with_offset(
+            size,
+            self.master_repl_offset,
+        ));
+    }
+
+    /// Start a new replication history, keeping the old ID as replid2 so that
+    /// replicas of the previous master can still partially resync with us.
+    pub fn shift_replid(&mut self) {
+        self.master_replid2 = std::mem::replace(&mut self.master_replid, generate_replid());
+        self.second_repl_offset = self.master_repl_offset + 1;
+    }
+
+    /// Stop following our master and become a master with a new history.
+    /// Replicas of our old master can still continue with us via replid2.
+    pub fn become_master(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel.cancel();
+        }
+        self.role = ReplicationRole::Master;
+        self.master_host = None;
+        self.master_port = None;
+        self.master_sync_in_progress = false;
+        self.shift_replid();
+    }
+
+    /// An online replica that has acknowledged our whole stream and can take
+    /// over in a FAILOVER, restricted to `target` (ip, port) if given.
+    pub fn failover_candidate(&self, target: Option<(&str, u16)>) -> Option<(String, u16)> {
+        self.replicas
+            .iter()
+            .filter(|r| r.state == ReplicaState::Online && r.port != 0)
+            .filter(|r| target.is_none_or(|(ip, port)| r.addr == ip && r.port == port))
+            .find(|r| r.offset >= self.master_repl_offset)
+            .map(|r| (r.addr.clone(), r.port))
+    }
+
+    /// Finish a FAILOVER and let paused writers continue.
+    pub fn end_failover(&mut self) {
+        self.failover_state = FailoverState::NoFailover;
+        self.failover_abort = None;
+        self.failover_pause = None;
+        self.ack_notify.notify_waiters();
+    }
+
+    /// Undo a FAILOVER demotion whose handshake failed or was aborted, going
+    /// back to serving as master. Returns false if no demotion was underway.
+    pub fn abort_failover(&mut self) -> bool {
+        if self.failover_state != FailoverState::FailoverInProgress {
+            return false;
+        }
+        if let Some(cancel) = self.cancel.take() {
+            cancel.cancel();
+        }
+        self.role = ReplicationRole::Master;
+        self.master_host = None;
+        self.master_port = None;
+        self.master_link_status = "up".to_string();
+        self.master_sync_in_progress = false;
+        self.end_failover();
+        true
+    }
+
+    /// Forget replid2, once our history no longer extends the old one.
+    pub fn clear_replid2(&mut self) {
+        self.master_replid2 = "0".repeat(40);
+        self.second_repl_offset = -1;
+    }
+
+    /// Return the backlog data a replica is missing if it can continue from
+    /// `offset` in history `replid`. Either our current ID or, up to the point
+    /// where we switched histories, replid2 is accepted.
+    pub fn partial_resync_data(&self, replid: &str, offset: i64) -> Option<Vec<u8>> {
+        let same_history = replid == self.master_replid
+            || (replid == self.master_replid2 && offset < self.second_repl_offset);
+        if !same_history {
+            return None;
         }
+        self.backlog.as_ref()?.read_from(offset)
     }
 
     /// Append data to the replication backlog and increment offset.
@@ -99,12 +246,78 @@ impl ReplicationState {
         }
     }
 
-    /// Send replication data to all connected replicas.
+    /// Send replication data to all connected replicas. Replicas still
+    /// receiving their RDB queue it until the transfer completes.
     /// Removes replicas where the send channel is closed.
     pub fn propagate_to_replicas(&mut self, data: &[u8]) {
         let data = bytes::Bytes::copy_from_slice(data);
+        self.replicas.retain(|r| r.tx.send(data.clone()).is_ok());
+    }
+
+    /// Ask every replica to send `REPLCONF ACK` right away.
+    /// The request travels in the replication stream, so it advances the offset.
+    /// Returns false without sending it when no replica is online to answer.
+    pub fn request_acks(&mut self) -> bool {
+        if !self
+            .replicas
+            .iter()
+            .any(|r| r.state == ReplicaState::Online)
+        {
+            return false;
+        }
+        let getack = RespValue::array(vec![
+            RespValue::bulk_string(b"REPLCONF".to_vec()),
+            RespValue::bulk_string(b"GETACK".to_vec()),
+            RespValue::bulk_string(b"*".to_vec()),
+        ])
+        .serialize();
+        self.feed_backlog(&getack);
+        self.propagate_to_replicas(&getack);
+        true
+    }
+
+    /// Record a replica's acknowledged offsets and wake any WAIT/WAITAOF callers.
+    pub fn record_ack(&mut self, replica_id: u64, offset: i64, aof_offset: Option<i64>) {
+        if let Some(r) = self.replicas.iter_mut().find(|r| r.id == replica_id) {
+            r.offset = offset;
+            if let Some(aof_offset) = aof_offset {
+                r.aof_offset = aof_offset;
+            }
+            r.last_ack = Instant::now();
+        }
+        self.ack_notify.notify_waiters();
+    }
+
+    /// Record that the local AOF is fsynced up to `offset`.
+    pub fn record_aof_fsync(&mut self, offset: i64) {
+        if offset > self.aof_fsynced_offset {
+            self.aof_fsynced_offset = offset;
+            self.ack_notify.notify_waiters();
+        }
+    }
+
+    /// Online replicas that acknowledged at least `offset`.
+    pub fn replicas_acked(&self, offset: i64) -> usize {
+        self.replicas
+            .iter()
+            .filter(|r| r.state == ReplicaState::Online && r.offset >= offset)
+            .count()
+    }
+
+    /// Online replicas that fsynced at least `offset` to their AOF.
+    pub fn replicas_fsynced(&self, offset: i64) -> usize {
+        self.replicas
+            .iter()
+            .filter(|r| r.state == ReplicaState::Online && r.aof_offset >= offset)
+            .count()
+    }
+
+    /// Online replicas whose last ACK is at most `max_lag` seconds old.
+    pub fn good_replicas(&self, max_lag: u64) -> usize {
         self.replicas
-            .retain(|r| r.state == ReplicaState::Online && r.tx.send(data.clone()).is_ok());
+            .iter()
+            .filter(|r| r.state == ReplicaState::Online && r.lag() <= max_lag)
+            .count()
     }
 
     /// Count of connected replicas in Online state.
@@ -116,8 +329,41 @@ impl ReplicationState {
     }
 }
 
+/// Block until `done` holds or `timeout_ms` elapses (0 waits forever).
+/// `done` is re-checked every time an ACK or local fsync is recorded, and
+/// when a FAILOVER ends.
+pub async fn wait_for_acks<F>(repl_state: &SharedReplicationState, timeout_ms: u64, done: F)
+where
+    F: Fn(&ReplicationState) -> bool,
+{
+    let deadline =
+        (timeout_ms > 0).then(|| tokio::time::Instant::now() + Duration::from_millis(timeout_ms));
+    let notify = repl_state.read().await.ack_notify.clone();
+
+    loop {
+        // Register interest before checking, so a wakeup in between is not lost
+        let notified = notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        if done(&*repl_state.read().await) {
+            return;
+        }
+
+        match deadline {
+            Some(deadline) => {
+                tokio::select! {
+                    _ = &mut notified => {}
+                    _ = tokio::time::sleep_until(deadline) => return,
+                }
+            }
+            None => notified.await,
+        }
+    }
+}
+
 /// Generate a 40-character hex replication ID.
-fn generate_replid() -> String {
+pub fn generate_replid() -> String {
     use rand::Rng;
     let mut rng = rand::thread_rng();
     let bytes: Vec<u8> = (0..20).map(|_| rng.r#gen()).collect();
@@ -2,17 +2,19 @@ use crate::command;
 use crate::config::SharedConfig;
 use crate::connection::ClientState;
 use crate::keywatcher::SharedKeyWatcher;
+use crate::persistence::aof::SharedAofWriter;
 use crate::persistence::rdb;
 use crate::pubsub::SharedPubSub;
-use crate::replication::SharedReplicationState;
+use crate::replication::{FailoverState, SharedReplicationState, transfer};
 use crate::resp::{RespParser, RespValue};
 use crate::scripting::ScriptCache;
-use crate::store::SharedStore;
-use bytes::BytesMut;
+use crate::store::{DataStore, SharedStore};
+use bytes::{Bytes, BytesMut};
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 
@@ -25,6 +27,7 @@ pub async fn replica_sync_loop(
     port: u16,
     store: SharedStore,
     config: SharedConfig,
+    aof: SharedAofWriter,
     repl_state: SharedReplicationState,
     pubsub: SharedPubSub,
     key_watcher: SharedKeyWatcher,
@@ -32,6 +35,7 @@ pub async fn replica_sync_loop(
     cancel: CancellationToken,
     last_save_time: crate::slowlog::SharedLastSaveTime,
     slowlog: crate::slowlog::SharedSlowLog,
+    latency: crate::latency::SharedLatencyMonitor,
 ) {
     let mut retry_delay = Duration::from_secs(1);
     let max_retry_delay = Duration::from_secs(30);
@@ -66,6 +70,7 @@ pub async fn replica_sync_loop(
                     stream,
                     &store,
                     &config,
+                    &aof,
                     &repl_state,
                     &pubsub,
                     &key_watcher,
@@ -73,6 +78,7 @@ pub async fn replica_sync_loop(
                     &cancel,
                     &last_save_time,
                     &slowlog,
+                    &latency,
                 )
                 .await
                 {
@@ -89,6 +95,13 @@ pub async fn replica_sync_loop(
             }
         }
 
+        // The FAILOVER target did not take over: keep serving as master
+        if repl_state.write().await.abort_failover() {
+            config.write().await.replicaof = None;
+            warn!("FAILOVER handshake with {addr} failed, resuming as master");
+            return;
+        }
+
         if cancel.is_cancelled() {
             return;
         }
@@ -107,6 +120,7 @@ async fn run_sync(
     mut stream: TcpStream,
     store: &SharedStore,
     config: &SharedConfig,
+    aof: &SharedAofWriter,
     repl_state: &SharedReplicationState,
     pubsub: &SharedPubSub,
     key_watcher: &SharedKeyWatcher,
@@ -114,6 +128,7 @@ async fn run_sync(
     cancel: &CancellationToken,
     last_save_time: &crate::slowlog::SharedLastSaveTime,
     slowlog: &crate::slowlog::SharedSlowLog,
+    latency: &crate::latency::SharedLatencyMonitor,
 ) -> Result<(), String> {
     let mut buf = BytesMut::with_capacity(8192);
 
@@ -126,6 +141,25 @@ async fn run_sync(
         .map_err(|e| format!("PING response failed: {e}"))?;
     debug!("Master PING response: {resp:?}");
 
+    // Authenticate before anything a protected master would refuse
+    let (masteruser, masterauth) = {
+        let cfg = config.read().await;
+        (cfg.masteruser.clone(), cfg.masterauth.clone())
+    };
+    if let Some(pass) = masterauth {
+        let mut auth = vec!["AUTH"];
+        if let Some(user) = &masteruser {
+            auth.push(user);
+        }
+        auth.push(&pass);
+        send_command(&mut stream, &auth)
+            .await
+            .map_err(|e| format!("AUTH failed: {e}"))?;
+        if let RespValue::Error(e) = read_response(&mut stream, &mut buf, cancel).await? {
+            return Err(format!("Unable to AUTH to MASTER: {e}"));
+        }
+    }
+
     // Step 2: REPLCONF listening-port
     let port = {
         let cfg = config.read().await;
@@ -147,9 +181,13 @@ async fn run_sync(
         .map_err(|e| format!("REPLCONF capa response failed: {e}"))?;
 
     // Step 4: PSYNC
-    let (replid, offset) = {
+    let (replid, offset, failover) = {
         let state = repl_state.read().await;
-        (state.master_replid.clone(), state.master_repl_offset)
+        (
+            state.master_replid.clone(),
+            state.master_repl_offset,
+            state.failover_state == FailoverState::FailoverInProgress,
+        )
     };
     // First sync: PSYNC ? -1
     let psync_replid = if offset == 0 { "?" } else { &replid };
@@ -158,7 +196,12 @@ async fn run_sync(
     } else {
         offset.to_string()
     };
-    send_command(&mut stream, &["PSYNC", psync_replid, &psync_offset])
+    // A master handing over in a FAILOVER asks the target to promote itself
+    let mut psync = vec!["PSYNC", psync_replid, &psync_offset];
+    if failover {
+        psync.push("FAILOVER");
+    }
+    send_command(&mut stream, &psync)
         .await
         .map_err(|e| format!("PSYNC failed: {e}"))?;
 
@@ -167,18 +210,27 @@ async fn run_sync(
         .await
         .map_err(|e| format!("PSYNC response failed: {e}"))?;
 
+    if failover && (psync_resp.starts_with("+FULLRESYNC") || psync_resp.starts_with("+CONTINUE")) {
+        repl_state.write().await.end_failover();
+        info!("FAILOVER complete, now a replica");
+    }
+
     if psync_resp.starts_with("+FULLRESYNC") {
         let parts: Vec<&str> = psync_resp.splitn(3, ' ').collect();
         if parts.len() >= 3 {
             let new_replid = parts[1].to_string();
             let new_offset: i64 = parts[2].trim().parse().unwrap_or(0);
 
-            {
-                let mut state = repl_state.write().await;
-                state.master_replid = new_replid;
-                state.master_repl_offset = new_offset;
-                state.master_sync_in_progress = true;
-            }
+            let backlog_size = config.read().await.repl_backlog_size;
+            let mut state = repl_state.write().await;
+            // The dataset is replaced, so our old history no longer applies
+            state.master_replid = new_replid;
+            state.master_repl_offset = new_offset;
+            state.clear_replid2();
+            state.reset_backlog(backlog_size);
+            // Our sub-replicas hold the old dataset: make them resync from us
+            state.replicas.clear();
+            state.master_sync_in_progress = true;
         }
 
         // Receive RDB bulk transfer
@@ -193,12 +245,20 @@ async fn run_sync(
         info!("Full resync complete, entering streaming mode");
     } else if psync_resp.starts_with("+CONTINUE") {
         let parts: Vec<&str> = psync_resp.splitn(2, ' ').collect();
-        if parts.len() >= 2 {
-            let new_replid = parts[1].trim().to_string();
-            let mut state = repl_state.write().await;
-            state.master_replid = new_replid;
-            state.master_link_status = "up".to_string();
+        let backlog_size = config.read().await.repl_backlog_size;
+        let mut state = repl_state.write().await;
+        if let Some(new_replid) = parts.get(1).map(|id| id.trim().to_string())
+            && new_replid != state.master_replid
+        {
+            // The master was promoted and started a new history: keep ours as
+            // replid2 so our own replicas can still continue from it
+            state.master_replid2 = std::mem::replace(&mut state.master_replid, new_replid);
+            state.second_repl_offset = state.master_repl_offset + 1;
+            // Reconnecting lets our sub-replicas learn the new ID via replid2
+            state.replicas.clear();
         }
+        state.ensure_backlog(backlog_size);
+        state.master_link_status = "up".to_string();
         info!("Partial resync, entering streaming mode");
     } else {
         return Err(format!("Unexpected PSYNC response: {psync_resp}"));
@@ -209,6 +269,11 @@ async fn run_sync(
 
     let mut ack_interval = tokio::time::interval(Duration::from_secs(1));
 
+    // One client for the whole stream, so SELECT carries over to later commands
+    let mut client = ClientState::new();
+    client.authenticated = true;
+    client.is_replication_client = true;
+
     loop {
         tokio::select! {
             _ = cancel.cancelled() => {
@@ -218,12 +283,23 @@ async fn run_sync(
                 match result {
                     Ok(0) => return Err("Master disconnected".to_string()),
                     Ok(_) => {
-                        // Parse and apply commands
+                        // Parse and apply commands. `raw` keeps the exact bytes
+                        // received, which are proxied to our own backlog and replicas.
+                        let raw = buf.clone().freeze();
+                        let mut consumed = 0;
                         while let Ok(Some(value)) = RespParser::parse(&mut buf) {
+                            let end = raw.len() - buf.len();
+                            let cmd_raw = raw.slice(consumed..end);
+                            consumed = end;
+
+                            let getack = is_getack(&value);
                             apply_command(
                                 value,
+                                &cmd_raw,
+                                &mut client,
                                 store,
                                 config,
+                                aof,
                                 pubsub,
                                 &pubsub_tx,
                                 key_watcher,
@@ -231,37 +307,68 @@ async fn run_sync(
                                 repl_state,
                                 last_save_time,
                                 slowlog,
+                                latency,
                             ).await;
+                            // The master is blocked in WAIT/WAITAOF: answer right away
+                            if getack {
+                                send_ack(&mut stream, aof, repl_state).await?;
+                            }
                         }
                     }
                     Err(e) => return Err(format!("Read error: {e}")),
                 }
             }
             _ = ack_interval.tick() => {
-                // Send REPLCONF ACK
-                let offset = {
-                    let state = repl_state.read().await;
-                    state.master_repl_offset
-                };
-                let ack_cmd = format!(
-                    "*3\r\n$8\r\nREPLCONF\r\n$3\r\nACK\r\n${}\r\n{}\r\n",
-                    offset.to_string().len(),
-                    offset
-                );
-                if stream.write_all(ack_cmd.as_bytes()).await.is_err() {
-                    return Err("Failed to send ACK".to_string());
-                }
+                send_ack(&mut stream, aof, repl_state).await?;
             }
         }
     }
 }
 
+/// Whether a replicated command is `REPLCONF GETACK`.
+fn is_getack(value: &RespValue) -> bool {
+    matches!(
+        value,
+        RespValue::Array(Some(items)) if items.len() >= 2
+            && items[0].to_string_lossy().is_some_and(|s| s.eq_ignore_ascii_case("REPLCONF"))
+            && items[1].to_string_lossy().is_some_and(|s| s.eq_ignore_ascii_case("GETACK"))
+    )
+}
+
+/// Send `REPLCONF ACK <offset> FACK <aofoffset>` to the master.
+/// The FACK offset is -1 when AOF is disabled, so WAITAOF never counts this replica.
+async fn send_ack(
+    stream: &mut TcpStream,
+    aof: &SharedAofWriter,
+    repl_state: &SharedReplicationState,
+) -> Result<(), String> {
+    let aof_active = aof.lock().await.is_active();
+    let (offset, aof_offset) = {
+        let state = repl_state.read().await;
+        let aof_offset = if aof_active {
+            state.aof_fsynced_offset
+        } else {
+            -1
+        };
+        (state.master_repl_offset.to_string(), aof_offset.to_string())
+    };
+    send_command(stream, &["REPLCONF", "ACK", &offset, "FACK", &aof_offset])
+        .await
+        .map_err(|_| "Failed to send ACK".to_string())
+}
+
 /// Apply a replicated command to the local store.
+/// `raw` is the command exactly as the master sent it: it advances our offset
+/// and is forwarded unchanged to our backlog and sub-replicas, so the whole
+/// replication tree shares one replid and offset space.
 #[allow(clippy::too_many_arguments)]
 async fn apply_command(
     value: RespValue,
+    raw: &[u8],
+    client: &mut ClientState,
     store: &SharedStore,
     config: &SharedConfig,
+    aof: &SharedAofWriter,
     pubsub: &SharedPubSub,
     pubsub_tx: &mpsc::UnboundedSender<RespValue>,
     key_watcher: &SharedKeyWatcher,
@@ -269,7 +376,17 @@ async fn apply_command(
     repl_state: &SharedReplicationState,
     last_save_time: &crate::slowlog::SharedLastSaveTime,
     slowlog: &crate::slowlog::SharedSlowLog,
+    latency: &crate::latency::SharedLatencyMonitor,
 ) {
+    let offset = {
+        let backlog_size = config.read().await.repl_backlog_size;
+        let mut state = repl_state.write().await;
+        state.ensure_backlog(backlog_size);
+        state.feed_backlog(raw);
+        state.propagate_to_replicas(raw);
+        state.master_repl_offset
+    };
+
     let items = match &value {
         RespValue::Array(Some(items)) if !items.is_empty() => items,
         _ => return,
@@ -280,39 +397,19 @@ async fn apply_command(
         None => return,
     };
 
-    // Skip PING commands from master (keepalive)
+    // PING is only a keepalive, but still counts towards the offset
     if cmd_name == "PING" {
         return;
     }
 
-    // Track offset: add the serialized size of this command
-    let cmd_bytes = value.serialize();
-    {
-        let mut state = repl_state.write().await;
-        state.master_repl_offset += cmd_bytes.len() as i64;
-    }
-
     let args = &items[1..];
 
-    // Use a temporary client state for replicated commands
-    let mut client = ClientState::new();
-    client.authenticated = true;
-    client.is_replication_client = true;
-
-    // Handle SELECT specially to track db_index
-    if cmd_name == "SELECT"
-        && let Some(db_str) = args.first().and_then(|a| a.to_string_lossy())
-        && let Ok(db_idx) = db_str.parse::<usize>()
-    {
-        client.db_index = db_idx;
-    }
-
     let _response = command::dispatch(
         &cmd_name,
         args,
         store,
         config,
-        &mut client,
+        client,
         pubsub,
         pubsub_tx,
         key_watcher,
@@ -320,11 +417,99 @@ async fn apply_command(
         repl_state,
         last_save_time,
         slowlog,
+        latency,
     )
     .await;
+
+    // Persist the replicated write so WAITAOF can count this replica
+    if cmd_name != "REPLCONF" {
+        let fsynced = {
+            let mut aof = aof.lock().await;
+            if !aof.is_active() {
+                return;
+            }
+            let _ = aof.log_command(&cmd_name, args);
+            aof.advance_reploff(offset);
+            aof.fsynced_reploff()
+        };
+        repl_state.write().await.record_aof_fsync(fsynced);
+    }
+}
+
+/// Where an incoming RDB goes while it is received.
+enum RdbSink {
+    /// Spooled to a temporary file, loaded once complete.
+    Disk { file: tokio::fs::File, path: String },
+    /// Parsed on a blocking thread as it arrives (`repl-diskless-load`).
+    Socket {
+        tx: mpsc::Sender<Bytes>,
+        loader: JoinHandle<std::io::Result<DataStore>>,
+    },
+}
+
+impl RdbSink {
+    async fn write(&mut self, chunk: Bytes) -> Result<(), String> {
+        match self {
+            RdbSink::Disk { file, .. } => file
+                .write_all(&chunk)
+                .await
+                .map_err(|e| format!("Failed to write RDB to disk: {e}")),
+            RdbSink::Socket { tx, loader } => {
+                if tx.send(chunk).await.is_ok() {
+                    return Ok(());
+                }
+                // The parser hung up early, so it has already failed
+                match loader.await {
+                    Ok(Err(e)) => Err(format!("Failed to load RDB: {e}")),
+                    _ => Err("RDB parser stopped unexpectedly".to_string()),
+                }
+            }
+        }
+    }
+
+    async fn finish(self, num_dbs: usize) -> Result<DataStore, String> {
+        let loaded = match self {
+            RdbSink::Disk { mut file, path } => {
+                let load_path = path.clone();
+                let loaded = match file.flush().await {
+                    Ok(()) => {
+                        drop(file);
+                        tokio::task::spawn_blocking(move || rdb::load(&load_path, num_dbs)).await
+                    }
+                    Err(e) => Ok(Err(e)),
+                };
+                let _ = tokio::fs::remove_file(&path).await;
+                loaded
+            }
+            RdbSink::Socket { tx, loader } => {
+                drop(tx);
+                loader.await
+            }
+        };
+        match loaded {
+            Ok(Ok(store)) => Ok(store),
+            Ok(Err(e)) => Err(format!("Failed to load RDB: {e}")),
+            Err(e) => Err(format!("RDB loader panicked: {e}")),
+        }
+    }
+
+    async fn discard(self) {
+        match self {
+            RdbSink::Disk { file, path } => {
+                drop(file);
+                let _ = tokio::fs::remove_file(&path).await;
+            }
+            RdbSink::Socket { tx, loader } => {
+                drop(tx);
+                let _ = loader.await;
+            }
+        }
+    }
 }
 
-/// Receive an RDB bulk transfer from master.
+/// Receive the RDB of a full resync and replace the store with it.
+/// The master frames it either as `$<len>\r\n<rdb>` or, for diskless sync,
+/// as `$EOF:<mark>\r\n<rdb><mark>`.
 async fn receive_rdb(
     stream: &mut TcpStream,
     buf: &mut BytesMut,
@@ -332,64 +517,130 @@ async fn receive_rdb(
     config: &SharedConfig,
     cancel: &CancellationToken,
 ) -> Result<(), String> {
-    // Read the bulk string header: $<len>\r\n
     let header = read_line(stream, buf, cancel)
         .await
         .map_err(|e| format!("RDB header read failed: {e}"))?;
+    let framing = match header.strip_prefix('$') {
+        Some(mark) if mark.starts_with("EOF:") && mark.len() == 4 + transfer::EOF_MARK_LEN => {
+            RdbFraming::EofMark(mark.as_bytes()[4..].to_vec())
+        }
+        Some(len) => RdbFraming::Length(
+            len.trim()
+                .parse()
+                .map_err(|_| "Invalid RDB length".to_string())?,
+        ),
+        None => return Err(format!("Expected bulk string for RDB, got: {header}")),
+    };
 
-    if !header.starts_with('$') {
-        return Err(format!("Expected bulk string for RDB, got: {header}"));
-    }
+    let (num_dbs, load_mode, dir) = {
+        let cfg = config.read().await;
+        (
+            cfg.databases,
+            cfg.repl_diskless_load.clone(),
+            cfg.dir.clone(),
+        )
+    };
+    let diskless = match load_mode.as_str() {
+        "swapdb" => true,
+        "on-empty-db" => store
+            .lock_all()
+            .await
+            .databases()
+            .all(|db| db.dbsize() == 0),
+        _ => false,
+    };
+    let mut sink = if diskless {
+        let (tx, loader) = transfer::spawn_rdb_loader(num_dbs);
+        RdbSink::Socket { tx, loader }
+    } else {
+        static NEXT_TEMP_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+        let temp_id = NEXT_TEMP_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path = format!("{dir}/temp-sync-{}-{temp_id}.rdb", std::process::id());
+        let file = tokio::fs::File::create(&path)
+            .await
+            .map_err(|e| format!("Failed to create {path}: {e}"))?;
+        RdbSink::Disk { file, path }
+    };
 
-    let rdb_len: usize = header[1..]
-        .trim()
-        .parse()
-        .map_err(|_| "Invalid RDB length".to_string())?;
-
-    // Read the RDB data
-    let mut rdb_data = vec![0u8; rdb_len];
-    let mut read = 0;
-
-    // First consume any data already in buf
-    let from_buf = std::cmp::min(buf.len(), rdb_len);
-    if from_buf > 0 {
-        rdb_data[..from_buf].copy_from_slice(&buf[..from_buf]);
-        buf.advance(from_buf);
-        read = from_buf;
-    }
+    let received = match copy_rdb(stream, buf, framing, &mut sink, cancel).await {
+        Ok(n) => n,
+        Err(e) => {
+            sink.discard().await;
+            return Err(e);
+        }
+    };
+    let new_store = sink.finish(num_dbs).await?;
 
-    while read < rdb_len {
-        tokio::select! {
-            result = stream.read(&mut rdb_data[read..]) => {
-                match result {
-                    Ok(0) => return Err("Master disconnected during RDB transfer".to_string()),
-                    Ok(n) => read += n,
-                    Err(e) => return Err(format!("RDB read error: {e}")),
+    // Replace the store contents. Until now the old dataset kept serving reads.
+    store.replace(new_store).await;
+
+    info!(
+        "Loaded RDB from master ({received} bytes, {})",
+        if diskless { "diskless" } else { "via disk" }
+    );
+    Ok(())
+}
+
+enum RdbFraming {
+    Length(usize),
+    EofMark(Vec<u8>),
+}
+
+/// Move the RDB payload from the socket into `sink`, leaving any bytes that
+/// follow it (the start of the command stream) in `buf`.
+async fn copy_rdb(
+    stream: &mut TcpStream,
+    buf: &mut BytesMut,
+    framing: RdbFraming,
+    sink: &mut RdbSink,
+    cancel: &CancellationToken,
+) -> Result<usize, String> {
+    let mut received = 0;
+    match framing {
+        RdbFraming::Length(len) => {
+            while received < len {
+                if buf.is_empty() {
+                    read_rdb_data(stream, buf, cancel).await?;
                 }
-            }
-            _ = cancel.cancelled() => {
-                return Err("Cancelled during RDB transfer".to_string());
+                let n = (len - received).min(buf.len());
+                sink.write(buf.split_to(n).freeze()).await?;
+                received += n;
             }
         }
+        RdbFraming::EofMark(mark) => loop {
+            if let Some(pos) = transfer::find_mark(buf, &mark) {
+                if pos > 0 {
+                    sink.write(buf.split_to(pos).freeze()).await?;
+                }
+                buf.advance(mark.len());
+                return Ok(received + pos);
+            }
+            // Hold back a tail that could be the start of the mark
+            let safe = buf.len().saturating_sub(mark.len() - 1);
+            if safe > 0 {
+                sink.write(buf.split_to(safe).freeze()).await?;
+                received += safe;
+            }
+            read_rdb_data(stream, buf, cancel).await?;
+        },
     }
+    Ok(received)
+}
 
-    // Load the RDB data
-    let num_dbs = {
-        let cfg = config.read().await;
-        cfg.databases
-    };
-
-    let new_store = rdb::load_from_reader(&mut rdb_data.as_slice(), num_dbs)
-        .map_err(|e| format!("Failed to load RDB: {e}"))?;
-
-    // Replace the store contents
-    {
-        let mut store_guard = store.write().await;
-        *store_guard = new_store;
+async fn read_rdb_data(
+    stream: &mut TcpStream,
+    buf: &mut BytesMut,
+    cancel: &CancellationToken,
+) -> Result<(), String> {
+    buf.reserve(64 * 1024);
+    tokio::select! {
+        result = stream.read_buf(buf) => match result {
+            Ok(0) => Err("Master disconnected during RDB transfer".to_string()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("RDB read error: {e}")),
+        },
+        _ = cancel.cancelled() => Err("Cancelled during RDB transfer".to_string()),
     }
-
-    info!("Loaded RDB from master ({rdb_len} bytes)");
-    Ok(())
 }
 
 /// Send a command as a RESP array.
@@ -0,0 +1,182 @@
+//! Streaming RDB transfer for full resyncs.
+//!
+//! The RDB serializer and parser are synchronous, so they run on a blocking
+//! thread and exchange chunks with the async socket code over a channel.
+//! The loader reads through a bounded channel and never holds the whole RDB.
+//! The writer never waits on the socket: it runs at full speed so the
+//! keyspace is unlocked as soon as it is serialized, and chunks a slow
+//! replica hasn't taken yet stay buffered, much like the temporary file of
+//! a disk-based sync.
+
+use crate::persistence::rdb;
+use crate::store::{DataStore, KeyspaceGuard};
+use bytes::{Buf, Bytes};
+use std::io::{self, Read, Write};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Length of the random marker that terminates a `$EOF:<mark>` transfer.
+pub const EOF_MARK_LEN: usize = 40;
+
+/// Size of the chunks handed between the blocking thread and the socket.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks in flight before the loader's producer waits for it.
+const CHANNEL_DEPTH: usize = 16;
+
+/// Generate the marker for a diskless transfer. The RDB length is unknown
+/// up front, so the master sends `$EOF:<mark>\r\n`, the RDB, then the mark.
+pub fn eof_mark() -> String {
+    super::generate_replid()
+}
+
+/// Position of `mark` in `haystack`, if present.
+pub fn find_mark(haystack: &[u8], mark: &[u8]) -> Option<usize> {
+    haystack.windows(mark.len()).position(|w| w == mark)
+}
+
+/// Serialize a snapshot of the store on a blocking thread. The RDB arrives
+/// in chunks on the returned receiver; dropping it aborts the serialization.
+/// The keyspace is unlocked once the last chunk is produced, however long
+/// the receiver takes to drain them.
+pub fn spawn_rdb_writer(
+    mut store: KeyspaceGuard,
+) -> (mpsc::UnboundedReceiver<Bytes>, JoinHandle<io::Result<()>>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let handle = tokio::task::spawn_blocking(move || {
+        let mut w = ChunkWriter::new(tx);
+        rdb::save_to_writer(&mut w, &mut store)?;
+        w.flush()
+    });
+    (rx, handle)
+}
+
+/// Parse an RDB on a blocking thread from chunks sent on the returned sender.
+/// Dropping the sender marks the end of the data.
+pub fn spawn_rdb_loader(
+    num_databases: usize,
+) -> (mpsc::Sender<Bytes>, JoinHandle<io::Result<DataStore>>) {
+    let (tx, rx) = mpsc::channel(CHANNEL_DEPTH);
+    let handle = tokio::task::spawn_blocking(move || {
+        rdb::load_from_reader(&mut ChunkReader::new(rx), num_databases)
+    });
+    (tx, handle)
+}
+
+/// `Write` adapter that batches bytes into chunks sent over a channel.
+pub struct ChunkWriter {
+    tx: mpsc::UnboundedSender<Bytes>,
+    buf: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub fn new(tx: mpsc::UnboundedSender<Bytes>) -> Self {
+        ChunkWriter {
+            tx,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        self.tx
+            .send(Bytes::from(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "RDB transfer aborted"))
+    }
+}
+
+/// `Read` adapter over chunks received from a channel. A closed channel
+/// reads as end of file. Must be used from a blocking thread.
+pub struct ChunkReader {
+    rx: mpsc::Receiver<Bytes>,
+    chunk: Bytes,
+}
+
+impl ChunkReader {
+    pub fn new(rx: mpsc::Receiver<Bytes>) -> Self {
+        ChunkReader {
+            rx,
+            chunk: Bytes::new(),
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => self.chunk = chunk,
+                None => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.chunk.len());
+        out[..n].copy_from_slice(&self.chunk[..n]);
+        self.chunk.advance(n);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::entry::Entry;
+    use crate::types::RedisValue;
+    use crate::types::rstring::RedisString;
+
+    #[test]
+    fn test_find_mark() {
+        assert_eq!(find_mark(b"abcMARKdef", b"MARK"), Some(3));
+        assert_eq!(find_mark(b"abcMAR", b"MARK"), None);
+        assert_eq!(find_mark(b"MA", b"MARK"), None);
+    }
+
+    #[test]
+    fn test_chunk_round_trip() {
+        let store = DataStore::new(16);
+        let mut store = store.try_lock_all().unwrap();
+        for i in 0..5000 {
+            store.db(i % 3).set(
+                format!("key:{i}"),
+                Entry::new(RedisValue::String(RedisString::new(vec![b'x'; 64]))),
+            );
+        }
+        let expected = rdb::save_to_bytes(&mut store).unwrap();
+
+        // The writer finishes, and drops the snapshot, before anything is read
+        let (tx, mut chunks) = mpsc::unbounded_channel();
+        let mut w = ChunkWriter::new(tx);
+        rdb::save_to_writer(&mut w, &mut store).unwrap();
+        w.flush().unwrap();
+        drop((w, store));
+
+        // Then through a small bounded channel to a reader on its own thread
+        let (tx, rx) = mpsc::channel(2);
+        let reader = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            ChunkReader::new(rx).read_to_end(&mut received).unwrap();
+            received
+        });
+        while let Ok(chunk) = chunks.try_recv() {
+            tx.blocking_send(chunk).unwrap();
+        }
+        drop(tx);
+        let received = reader.join().unwrap();
+
+        assert_eq!(received, expected);
+        let loaded = rdb::load_from_reader(&mut received.as_slice(), 16).unwrap();
+        assert_eq!(loaded.try_lock_all().unwrap().db(1).dbsize(), 1667);
+    }
+}
@@ -1,4 +1,4 @@
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use std::io;
 
 /// A RESP2 value.
@@ -11,7 +11,7 @@ pub enum RespValue {
     /// :1000\r\n
     Integer(i64),
     /// $6\r\nfoobar\r\n  or  $-1\r\n (null)
-    BulkString(Option<Vec<u8>>),
+    BulkString(Option<Bytes>),
     /// *2\r\n...  or  *-1\r\n (null)
     Array(Option<Vec<RespValue>>),
 }
@@ -33,7 +33,7 @@ impl RespValue {
         RespValue::Integer(n)
     }
 
-    pub fn bulk_string(data: impl Into<Vec<u8>>) -> Self {
+    pub fn bulk_string(data: impl Into<Bytes>) -> Self {
         RespValue::BulkString(Some(data.into()))
     }
 
@@ -98,6 +98,41 @@ impl RespValue {
         }
     }
 
+    /// Serialize this value as chunks to write out in order. Bulk strings
+    /// of at least [`BIG_ARG`] bytes are chunks of their own, shared with
+    /// the value rather than copied.
+    pub fn to_chunks(&self) -> Vec<Bytes> {
+        let mut buf = Vec::new();
+        let mut chunks = Vec::new();
+        self.write_chunks(&mut buf, &mut chunks);
+        if !buf.is_empty() {
+            chunks.push(Bytes::from(buf));
+        }
+        chunks
+    }
+
+    fn write_chunks(&self, buf: &mut Vec<u8>, chunks: &mut Vec<Bytes>) {
+        match self {
+            RespValue::BulkString(Some(data)) if data.len() >= BIG_ARG => {
+                buf.push(b'$');
+                buf.extend_from_slice(data.len().to_string().as_bytes());
+                buf.extend_from_slice(b"\r\n");
+                chunks.push(Bytes::from(std::mem::take(buf)));
+                chunks.push(data.clone());
+                buf.extend_from_slice(b"\r\n");
+            }
+            RespValue::Array(Some(items)) => {
+                buf.push(b'*');
+                buf.extend_from_slice(items.len().to_string().as_bytes());
+                buf.extend_from_slice(b"\r\n");
+                for item in items {
+                    item.write_chunks(buf, chunks);
+                }
+            }
+            other => other.write_to(buf),
+        }
+    }
+
     /// Try to interpret this value as a string (for command parsing).
     pub fn as_str(&self) -> Option<&[u8]> {
         match self {
@@ -119,8 +154,19 @@ impl RespValue {
 /// Handles partial reads — call `parse()` repeatedly as data arrives.
 /// Returns `Ok(Some(value))` when a complete value is parsed,
 /// `Ok(None)` when more data is needed.
+///
+/// A complete value is first scanned in place, then split off the buffer.
+/// Bulk strings of at least [`BIG_ARG`] bytes are handed out as slices of
+/// the read buffer, so large payloads are never copied; smaller ones are
+/// copied out so they don't keep the whole buffer alive once stored. Big
+/// ones are copied too when they fill less than half of the buffer's
+/// allocation, so a stored value never pins more than twice its size.
 pub struct RespParser;
 
+/// Bulk strings at least this long are sliced out of the read buffer
+/// instead of copied, like Redis's `PROTO_MBULK_BIG_ARG`.
+pub const BIG_ARG: usize = 32 * 1024;
+
 impl RespParser {
     /// Try to parse a complete RESP value from the buffer.
     /// On success, consumes the parsed bytes from `buf` and returns the value.
@@ -132,22 +178,122 @@ impl RespParser {
 
         // Check if this is an inline command (doesn't start with a RESP type byte)
         match buf[0] {
-            b'+' | b'-' | b':' | b'$' | b'*' => Self::parse_value(buf),
+            b'+' | b'-' | b':' | b'$' | b'*' => {
+                let len = match Self::scan_value(buf, 0)? {
+                    Some(end) => end,
+                    None => return Ok(None),
+                };
+                // The allocation from the frame on, which slices would pin
+                let capacity = buf.capacity();
+                let frame = buf.split_to(len).freeze();
+                let mut pos = 0;
+                let mut sliced = 0;
+                let mut value = Self::read_value(&frame, &mut pos, &mut sliced)?;
+                if sliced > 0 && sliced * 2 < capacity {
+                    // A buffer grown by earlier reads or a deep pipeline
+                    copy_big_args(&mut value);
+                } else if sliced > 0 {
+                    // Values now own the old allocation; read on in a new one
+                    // so it is freed with them
+                    *buf = BytesMut::from(&buf[..]);
+                }
+                Ok(Some(value))
+            }
             _ => Self::parse_inline(buf),
         }
     }
 
-    fn parse_value(buf: &mut BytesMut) -> Result<Option<RespValue>, RespError> {
-        if buf.is_empty() {
+    /// Check that a complete value starts at `pos`, returning where it
+    /// ends, or `Ok(None)` if more data is needed. Consumes nothing.
+    fn scan_value(buf: &[u8], pos: usize) -> Result<Option<usize>, RespError> {
+        let Some(&kind) = buf.get(pos) else {
             return Ok(None);
+        };
+        let crlf = match find_crlf_from(buf, pos + 1) {
+            Some(crlf) => crlf,
+            None => return Ok(None),
+        };
+        let line = &buf[pos + 1..crlf];
+        let next = crlf + 2;
+
+        match kind {
+            b'+' | b'-' => Ok(Some(next)),
+            b':' => {
+                parse_integer_line(line)?;
+                Ok(Some(next))
+            }
+            b'$' => match parse_bulk_len(line)? {
+                None => Ok(Some(next)),
+                Some(len) => {
+                    let end = next + len;
+                    if buf.len() < end + 2 {
+                        return Ok(None);
+                    }
+                    if &buf[end..end + 2] != b"\r\n" {
+                        return Err(RespError::InvalidData(
+                            "Missing trailing CRLF after bulk string".into(),
+                        ));
+                    }
+                    Ok(Some(end + 2))
+                }
+            },
+            b'*' => {
+                let mut end = next;
+                for _ in 0..parse_array_len(line)?.unwrap_or(0) {
+                    end = match Self::scan_value(buf, end)? {
+                        Some(end) => end,
+                        None => return Ok(None),
+                    };
+                }
+                Ok(Some(end))
+            }
+            other => Err(RespError::InvalidByte(other)),
         }
+    }
 
-        match buf[0] {
-            b'+' => Self::parse_simple_string(buf),
-            b'-' => Self::parse_error(buf),
-            b':' => Self::parse_integer(buf),
-            b'$' => Self::parse_bulk_string(buf),
-            b'*' => Self::parse_array(buf),
+    /// Read the value at `pos` out of a frame [`scan_value`](Self::scan_value)
+    /// has checked, advancing `pos` past it. Adds the length of every bulk
+    /// string sharing the frame to `sliced`.
+    fn read_value(
+        frame: &Bytes,
+        pos: &mut usize,
+        sliced: &mut usize,
+    ) -> Result<RespValue, RespError> {
+        let kind = frame[*pos];
+        let crlf = find_crlf_from(frame, *pos + 1)
+            .ok_or_else(|| RespError::InvalidData("truncated frame".into()))?;
+        let line = &frame[*pos + 1..crlf];
+        *pos = crlf + 2;
+
+        match kind {
+            b'+' => Ok(RespValue::SimpleString(
+                String::from_utf8_lossy(line).into_owned(),
+            )),
+            b'-' => Ok(RespValue::Error(String::from_utf8_lossy(line).into_owned())),
+            b':' => Ok(RespValue::Integer(parse_integer_line(line)?)),
+            b'$' => match parse_bulk_len(line)? {
+                None => Ok(RespValue::BulkString(None)),
+                Some(len) => {
+                    let data = if len >= BIG_ARG {
+                        *sliced += len;
+                        frame.slice(*pos..*pos + len)
+                    } else {
+                        Bytes::copy_from_slice(&frame[*pos..*pos + len])
+                    };
+                    *pos += len + 2;
+                    Ok(RespValue::BulkString(Some(data)))
+                }
+            },
+            b'*' => match parse_array_len(line)? {
+                None => Ok(RespValue::Array(None)),
+                Some(len) => {
+                    let mut items = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        items.push(Self::read_value(frame, pos, sliced)?);
+                    }
+                    Ok(RespValue::Array(Some(items)))
+                }
+            },
             other => Err(RespError::InvalidByte(other)),
         }
     }
@@ -181,139 +327,46 @@ impl RespParser {
 
         let items: Vec<RespValue> = parts
             .into_iter()
-            .map(|s| RespValue::BulkString(Some(s.into_bytes())))
+            .map(|s| RespValue::BulkString(Some(Bytes::from(s))))
             .collect();
 
         Ok(Some(RespValue::Array(Some(items))))
     }
+}
 
-    fn parse_simple_string(buf: &mut BytesMut) -> Result<Option<RespValue>, RespError> {
-        if let Some(end) = find_crlf_from(buf, 1) {
-            let s = String::from_utf8_lossy(&buf[1..end]).to_string();
-            buf.advance(end + 2);
-            Ok(Some(RespValue::SimpleString(s)))
-        } else {
-            Ok(None)
-        }
-    }
-
-    fn parse_error(buf: &mut BytesMut) -> Result<Option<RespValue>, RespError> {
-        if let Some(end) = find_crlf_from(buf, 1) {
-            let s = String::from_utf8_lossy(&buf[1..end]).to_string();
-            buf.advance(end + 2);
-            Ok(Some(RespValue::Error(s)))
-        } else {
-            Ok(None)
-        }
-    }
-
-    fn parse_integer(buf: &mut BytesMut) -> Result<Option<RespValue>, RespError> {
-        if let Some(end) = find_crlf_from(buf, 1) {
-            let s = std::str::from_utf8(&buf[1..end])
-                .map_err(|_| RespError::InvalidData("Invalid integer encoding".into()))?;
-            let n: i64 = s
-                .parse()
-                .map_err(|_| RespError::InvalidData(format!("Invalid integer: {s}")))?;
-            buf.advance(end + 2);
-            Ok(Some(RespValue::Integer(n)))
-        } else {
-            Ok(None)
-        }
-    }
-
-    fn parse_bulk_string(buf: &mut BytesMut) -> Result<Option<RespValue>, RespError> {
-        let crlf = match find_crlf_from(buf, 1) {
-            Some(pos) => pos,
-            None => return Ok(None),
-        };
-
-        let len_str = std::str::from_utf8(&buf[1..crlf])
-            .map_err(|_| RespError::InvalidData("invalid bulk length".into()))?;
-        let len: i64 = len_str
-            .parse()
-            .map_err(|_| RespError::InvalidData("invalid bulk length".into()))?;
-
-        if len == -1 {
-            buf.advance(crlf + 2);
-            return Ok(Some(RespValue::BulkString(None)));
-        }
-
-        if len < -1 {
-            return Err(RespError::InvalidData("invalid bulk length".into()));
-        }
-
-        if len > 512 * 1024 * 1024 {
-            // 512MB max bulk string length
-            return Err(RespError::InvalidData("invalid bulk length".into()));
-        }
-        let len = len as usize;
-        let total_needed = crlf + 2 + len + 2; // header + data + trailing \r\n
-
-        if buf.len() < total_needed {
-            return Ok(None);
-        }
-
-        let data = buf[crlf + 2..crlf + 2 + len].to_vec();
-
-        // Verify trailing \r\n
-        if buf[crlf + 2 + len] != b'\r' || buf[crlf + 2 + len + 1] != b'\n' {
-            return Err(RespError::InvalidData(
-                "Missing trailing CRLF after bulk string".into(),
-            ));
-        }
+fn parse_integer_line(line: &[u8]) -> Result<i64, RespError> {
+    let s = std::str::from_utf8(line)
+        .map_err(|_| RespError::InvalidData("Invalid integer encoding".into()))?;
+    s.parse()
+        .map_err(|_| RespError::InvalidData(format!("Invalid integer: {s}")))
+}
 
-        buf.advance(total_needed);
-        Ok(Some(RespValue::BulkString(Some(data))))
+/// Length of a bulk string from its header line, `None` for the null bulk
+/// string.
+fn parse_bulk_len(line: &[u8]) -> Result<Option<usize>, RespError> {
+    let len: i64 = std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| RespError::InvalidData("invalid bulk length".into()))?;
+    match len {
+        -1 => Ok(None),
+        // 512MB max bulk string length
+        0..=0x2000_0000 => Ok(Some(len as usize)),
+        _ => Err(RespError::InvalidData("invalid bulk length".into())),
     }
+}
 
-    fn parse_array(buf: &mut BytesMut) -> Result<Option<RespValue>, RespError> {
-        let crlf = match find_crlf_from(buf, 1) {
-            Some(pos) => pos,
-            None => return Ok(None),
-        };
-
-        let len_str = std::str::from_utf8(&buf[1..crlf])
-            .map_err(|_| RespError::InvalidData("Invalid array length encoding".into()))?;
-        let len: i64 = len_str
-            .parse()
-            .map_err(|_| RespError::InvalidData("invalid multibulk length".into()))?;
-
-        if len == -1 {
-            buf.advance(crlf + 2);
-            return Ok(Some(RespValue::Array(None)));
-        }
-
-        if len < -1 {
-            // Negative multibulk length other than -1: treat as null array
-            buf.advance(crlf + 2);
-            return Ok(Some(RespValue::Array(None)));
-        }
-
-        if len > 1024 * 1024 {
-            return Err(RespError::InvalidData("invalid multibulk length".into()));
-        }
-
-        let len = len as usize;
-
-        // We need to try parsing all elements.
-        // Save the current position so we can restore if we don't have enough data.
-        let saved = buf.clone();
-        buf.advance(crlf + 2);
-
-        let mut items = Vec::with_capacity(len);
-        for _ in 0..len {
-            match Self::parse_value(buf) {
-                Ok(Some(val)) => items.push(val),
-                Ok(None) => {
-                    // Not enough data — restore buffer
-                    *buf = saved;
-                    return Ok(None);
-                }
-                Err(e) => return Err(e),
-            }
-        }
-
-        Ok(Some(RespValue::Array(Some(items))))
+/// Length of an array from its header line, `None` for the null array.
+fn parse_array_len(line: &[u8]) -> Result<Option<usize>, RespError> {
+    let len: i64 = std::str::from_utf8(line)
+        .map_err(|_| RespError::InvalidData("Invalid array length encoding".into()))?
+        .parse()
+        .map_err(|_| RespError::InvalidData("invalid multibulk length".into()))?;
+    match len {
+        // Negative multibulk lengths are all treated as a null array
+        ..0 => Ok(None),
+        0..=0x10_0000 => Ok(Some(len as usize)),
+        _ => Err(RespError::InvalidData("invalid multibulk length".into())),
     }
 }
 
@@ -322,6 +375,17 @@ fn find_crlf(buf: &[u8]) -> Option<usize> {
     find_crlf_from(buf, 0)
 }
 
+/// Replace the big bulk strings sliced out of a read buffer with copies.
+fn copy_big_args(value: &mut RespValue) {
+    match value {
+        RespValue::BulkString(Some(data)) if data.len() >= BIG_ARG => {
+            *data = Bytes::copy_from_slice(data);
+        }
+        RespValue::Array(Some(items)) => items.iter_mut().for_each(copy_big_args),
+        _ => {}
+    }
+}
+
 /// Find \r\n starting from the given position.
 fn find_crlf_from(buf: &[u8], start: usize) -> Option<usize> {
     if buf.len() < start + 2 {
@@ -444,7 +508,10 @@ mod tests {
     fn test_parse_bulk_string() {
         let mut buf = BytesMut::from("$6\r\nfoobar\r\n");
         let result = RespParser::parse(&mut buf).unwrap().unwrap();
-        assert_eq!(result, RespValue::BulkString(Some(b"foobar".to_vec())));
+        assert_eq!(
+            result,
+            RespValue::BulkString(Some(Bytes::from_static(b"foobar")))
+        );
     }
 
     #[test]
@@ -458,7 +525,7 @@ mod tests {
     fn test_parse_empty_bulk_string() {
         let mut buf = BytesMut::from("$0\r\n\r\n");
         let result = RespParser::parse(&mut buf).unwrap().unwrap();
-        assert_eq!(result, RespValue::BulkString(Some(vec![])));
+        assert_eq!(result, RespValue::BulkString(Some(Bytes::new())));
     }
 
     #[test]
@@ -468,8 +535,8 @@ mod tests {
         assert_eq!(
             result,
             RespValue::Array(Some(vec![
-                RespValue::BulkString(Some(b"foo".to_vec())),
-                RespValue::BulkString(Some(b"bar".to_vec())),
+                RespValue::BulkString(Some(Bytes::from_static(b"foo"))),
+                RespValue::BulkString(Some(Bytes::from_static(b"bar"))),
             ]))
         );
     }
@@ -516,7 +583,9 @@ mod tests {
         let result = RespParser::parse(&mut buf).unwrap().unwrap();
         assert_eq!(
             result,
-            RespValue::Array(Some(vec![RespValue::BulkString(Some(b"PING".to_vec()))]))
+            RespValue::Array(Some(vec![RespValue::BulkString(Some(Bytes::from_static(
+                b"PING"
+            )))]))
         );
     }
 
@@ -527,9 +596,9 @@ mod tests {
         assert_eq!(
             result,
             RespValue::Array(Some(vec![
-                RespValue::BulkString(Some(b"SET".to_vec())),
-                RespValue::BulkString(Some(b"key".to_vec())),
-                RespValue::BulkString(Some(b"value".to_vec())),
+                RespValue::BulkString(Some(Bytes::from_static(b"SET"))),
+                RespValue::BulkString(Some(Bytes::from_static(b"key"))),
+                RespValue::BulkString(Some(Bytes::from_static(b"value"))),
             ]))
         );
     }
@@ -554,7 +623,7 @@ mod tests {
 
     #[test]
     fn test_serialize_bulk_string() {
-        let val = RespValue::BulkString(Some(b"hello".to_vec()));
+        let val = RespValue::BulkString(Some(Bytes::from_static(b"hello")));
         assert_eq!(val.serialize(), b"$5\r\nhello\r\n");
     }
 
@@ -567,7 +636,7 @@ mod tests {
     #[test]
     fn test_serialize_array() {
         let val = RespValue::Array(Some(vec![
-            RespValue::BulkString(Some(b"foo".to_vec())),
+            RespValue::BulkString(Some(Bytes::from_static(b"foo"))),
             RespValue::Integer(42),
         ]));
         assert_eq!(val.serialize(), b"*2\r\n$3\r\nfoo\r\n:42\r\n");
@@ -589,6 +658,69 @@ mod tests {
         assert!(buf.is_empty());
     }
 
+    #[test]
+    fn test_parse_partial_array() {
+        let mut buf = BytesMut::from("*2\r\n$3\r\nfoo\r\n$3\r\nba");
+        assert!(RespParser::parse(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), 19);
+        buf.extend_from_slice(b"r\r\n");
+        assert!(RespParser::parse(&mut buf).unwrap().is_some());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_big_bulk_string_is_not_copied() {
+        let payload = vec![b'x'; BIG_ARG];
+        let mut frame = format!("*1\r\n${}\r\n", payload.len()).into_bytes();
+        frame.extend_from_slice(&payload);
+        frame.extend_from_slice(b"\r\n");
+        let mut buf = BytesMut::from(&frame[..]);
+        let start = buf.as_ptr() as usize;
+        let end = start + buf.len();
+
+        let value = RespParser::parse(&mut buf).unwrap().unwrap();
+        let RespValue::Array(Some(items)) = &value else {
+            panic!("expected an array");
+        };
+        let RespValue::BulkString(Some(data)) = &items[0] else {
+            panic!("expected a bulk string");
+        };
+        assert_eq!(data, &payload[..]);
+        // A slice of the read buffer, not a copy
+        assert!((start..end).contains(&(data.as_ptr() as usize)));
+
+        // And written back out without being copied either
+        let reply = RespValue::BulkString(Some(data.clone()));
+        let chunks = reply.to_chunks();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].as_ptr(), data.as_ptr());
+        assert_eq!(chunks.concat(), reply.serialize());
+    }
+
+    #[test]
+    fn test_big_bulk_string_in_a_mostly_empty_buffer_is_copied() {
+        let payload = vec![b'x'; BIG_ARG];
+        let mut buf = BytesMut::with_capacity(BIG_ARG * 8);
+        buf.extend_from_slice(format!("*1\r\n${}\r\n", payload.len()).as_bytes());
+        buf.extend_from_slice(&payload);
+        buf.extend_from_slice(b"\r\n");
+        let start = buf.as_ptr() as usize;
+        let end = start + buf.capacity();
+
+        let value = RespParser::parse(&mut buf).unwrap().unwrap();
+        let RespValue::Array(Some(items)) = &value else {
+            panic!("expected an array");
+        };
+        let RespValue::BulkString(Some(data)) = &items[0] else {
+            panic!("expected a bulk string");
+        };
+        assert_eq!(data, &payload[..]);
+        // A slice would pin eight times its size
+        assert!(!(start..end).contains(&(data.as_ptr() as usize)));
+        // The buffer is read on in place
+        assert_eq!(buf.as_ptr() as usize, start + payload.len() + 14);
+    }
+
     #[test]
     fn test_split_inline_quoted() {
         let parts = split_inline_command(r#"SET key "hello world""#).unwrap();
@@ -9,7 +9,7 @@ use std::sync::{Arc, Mutex};
 use mlua::prelude::*;
 
 use crate::resp::RespValue;
-use crate::store::DataStore;
+use crate::store::KeyspaceGuard;
 
 /// Compute the SHA1 hex digest of a script.
 pub fn sha1_hex(script: &str) -> String {
@@ -153,12 +153,13 @@ fn lua_to_resp(val: LuaValue) -> RespValue {
 /// Execute a Redis command synchronously against the data store.
 ///
 /// This is called from within the Lua VM for `redis.call()` / `redis.pcall()`.
-/// The caller must already hold a write lock on the store (passed via raw pointer).
+/// The caller must already hold a lock on the whole keyspace (passed via raw
+/// pointer).
 ///
 /// We only support a curated set of simple commands here. The store pointer is
 /// valid because the Lua execution lives entirely within the scope of the
 /// async function that holds the write guard.
-fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]) -> RespValue {
+fn execute_redis_command(store: &mut KeyspaceGuard, db_index: usize, args: &[String]) -> RespValue {
     if args.is_empty() {
         return RespValue::error("ERR wrong number of arguments for 'redis.call'");
     }
@@ -172,12 +173,10 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'get' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
-                    crate::types::RedisValue::String(s) => {
-                        RespValue::bulk_string(s.as_bytes().to_vec())
-                    }
+                    crate::types::RedisValue::String(s) => RespValue::bulk_string(s.to_bytes()),
                     _ => RespValue::error(
                         "WRONGTYPE Operation against a key holding the wrong kind of value",
                     ),
@@ -192,7 +191,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             }
             let key = cmd_args[0].to_string();
             let value = cmd_args[1].as_bytes().to_vec();
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             let entry = crate::store::entry::Entry::new(crate::types::RedisValue::String(
                 crate::types::rstring::RedisString::new(value),
             ));
@@ -205,7 +204,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
                 return RespValue::error("ERR wrong number of arguments for 'setnx' command");
             }
             let key = cmd_args[0];
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             if db.exists(key) {
                 RespValue::integer(0)
             } else {
@@ -221,13 +220,13 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.is_empty() {
                 return RespValue::error("ERR wrong number of arguments for 'mget' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             let mut results = Vec::with_capacity(cmd_args.len());
             for key in &cmd_args {
                 match db.get(key) {
                     Some(entry) => match &entry.value {
                         crate::types::RedisValue::String(s) => {
-                            results.push(RespValue::bulk_string(s.as_bytes().to_vec()));
+                            results.push(RespValue::bulk_string(s.to_bytes()));
                         }
                         _ => results.push(RespValue::null_bulk_string()),
                     },
@@ -241,7 +240,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() < 2 || !cmd_args.len().is_multiple_of(2) {
                 return RespValue::error("ERR wrong number of arguments for 'mset' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             for pair in cmd_args.chunks(2) {
                 let entry = crate::store::entry::Entry::new(crate::types::RedisValue::String(
                     crate::types::rstring::RedisString::new(pair[1].as_bytes().to_vec()),
@@ -257,7 +256,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             }
             let key = cmd_args[0];
             let data = cmd_args[1].as_bytes();
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             if !db.exists(key) {
                 let entry = crate::store::entry::Entry::new(crate::types::RedisValue::String(
                     crate::types::rstring::RedisString::new(data.to_vec()),
@@ -283,7 +282,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'strlen' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::String(s) => RespValue::integer(s.len() as i64),
@@ -321,10 +320,11 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.is_empty() {
                 return RespValue::error("ERR wrong number of arguments for 'del' command");
             }
-            let db = store.db(db_index);
+            let lazy = cmd == "UNLINK" || store.lazyfree().lazy_user_del();
+            let mut db = store.db(db_index);
             let mut count = 0i64;
             for key in &cmd_args {
-                if db.del(key) {
+                if db.delete(key, lazy) {
                     count += 1;
                 }
             }
@@ -335,7 +335,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.is_empty() {
                 return RespValue::error("ERR wrong number of arguments for 'exists' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             let mut count = 0i64;
             for key in &cmd_args {
                 if db.exists(key) {
@@ -349,7 +349,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'type' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             let type_name = db.key_type(cmd_args[0]).unwrap_or("none");
             RespValue::SimpleString(type_name.to_string())
         }
@@ -358,7 +358,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'ttl' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => RespValue::integer(entry.ttl_seconds()),
                 None => RespValue::integer(-2),
@@ -369,7 +369,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'pttl' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => RespValue::integer(entry.ttl_millis()),
                 None => RespValue::integer(-2),
@@ -383,11 +383,11 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             match cmd_args[1].parse::<i64>() {
                 Ok(seconds) => {
                     if seconds <= 0 {
-                        let db = store.db(db_index);
+                        let mut db = store.db(db_index);
                         return RespValue::integer(if db.del(cmd_args[0]) { 1 } else { 0 });
                     }
                     let expires_at = crate::store::entry::now_millis() + (seconds as u64) * 1000;
-                    let db = store.db(db_index);
+                    let mut db = store.db(db_index);
                     RespValue::integer(if db.set_expiry(cmd_args[0], expires_at) {
                         1
                     } else {
@@ -402,7 +402,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'persist' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             RespValue::integer(if db.persist(cmd_args[0]) { 1 } else { 0 })
         }
 
@@ -410,7 +410,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 2 {
                 return RespValue::error("ERR wrong number of arguments for 'rename' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             if db.rename(cmd_args[0], cmd_args[1]) {
                 RespValue::ok()
             } else {
@@ -434,9 +434,9 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
                 return RespValue::error("ERR wrong number of arguments for 'hset' command");
             }
             let key = cmd_args[0];
-            let db = store.db(db_index);
-            ensure_hash(db, key);
-            let hash = match get_hash_mut(db, key) {
+            let mut db = store.db(db_index);
+            ensure_hash(&mut db, key);
+            let hash = match get_hash_mut(&mut db, key) {
                 Ok(h) => h,
                 Err(e) => return e,
             };
@@ -453,11 +453,11 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 2 {
                 return RespValue::error("ERR wrong number of arguments for 'hget' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::Hash(h) => match h.get(cmd_args[1]) {
-                        Some(v) => RespValue::bulk_string(v.clone()),
+                        Some(v) => RespValue::bulk_string(v.to_vec()),
                         None => RespValue::null_bulk_string(),
                     },
                     _ => RespValue::error(
@@ -473,7 +473,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
                 return RespValue::error("ERR wrong number of arguments for 'hdel' command");
             }
             let key = cmd_args[0];
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get_mut(key) {
                 Some(entry) => match &mut entry.value {
                     crate::types::RedisValue::Hash(h) => {
@@ -497,7 +497,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 2 {
                 return RespValue::error("ERR wrong number of arguments for 'hexists' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::Hash(h) => {
@@ -515,7 +515,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'hlen' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::Hash(h) => RespValue::integer(h.len() as i64),
@@ -531,14 +531,14 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'hgetall' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::Hash(h) => {
                         let mut items = Vec::new();
                         for (field, value) in h.iter() {
                             items.push(RespValue::bulk_string(field.as_bytes().to_vec()));
-                            items.push(RespValue::bulk_string(value.clone()));
+                            items.push(RespValue::bulk_string(value.to_vec()));
                         }
                         RespValue::array(items)
                     }
@@ -554,14 +554,14 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() < 2 {
                 return RespValue::error("ERR wrong number of arguments for 'hmget' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::Hash(h) => {
                         let mut results = Vec::new();
                         for field in &cmd_args[1..] {
                             match h.get(field) {
-                                Some(v) => results.push(RespValue::bulk_string(v.clone())),
+                                Some(v) => results.push(RespValue::bulk_string(v.to_vec())),
                                 None => results.push(RespValue::null_bulk_string()),
                             }
                         }
@@ -582,7 +582,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'hkeys' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::Hash(h) => {
@@ -604,13 +604,13 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'hvals' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::Hash(h) => {
                         let items: Vec<RespValue> = h
                             .iter()
-                            .map(|(_, v)| RespValue::bulk_string(v.clone()))
+                            .map(|(_, v)| RespValue::bulk_string(v.to_vec()))
                             .collect();
                         RespValue::array(items)
                     }
@@ -632,7 +632,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'llen' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::List(l) => RespValue::integer(l.len() as i64),
@@ -656,7 +656,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
                 Ok(n) => n,
                 Err(_) => return RespValue::error("ERR value is not an integer or out of range"),
             };
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::List(l) => {
@@ -683,7 +683,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
                 Ok(n) => n,
                 Err(_) => return RespValue::error("ERR value is not an integer or out of range"),
             };
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::List(l) => match l.lindex(index) {
@@ -704,9 +704,9 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
                 return RespValue::error("ERR wrong number of arguments for 'sadd' command");
             }
             let key = cmd_args[0];
-            let db = store.db(db_index);
-            ensure_set(db, key);
-            let set = match get_set_mut(db, key) {
+            let mut db = store.db(db_index);
+            ensure_set(&mut db, key);
+            let set = match get_set_mut(&mut db, key) {
                 Ok(s) => s,
                 Err(e) => return e,
             };
@@ -724,7 +724,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
                 return RespValue::error("ERR wrong number of arguments for 'srem' command");
             }
             let key = cmd_args[0];
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get_mut(key) {
                 Some(entry) => match &mut entry.value {
                     crate::types::RedisValue::Set(s) => {
@@ -748,7 +748,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 2 {
                 return RespValue::error("ERR wrong number of arguments for 'sismember' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::Set(s) => {
@@ -770,14 +770,14 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'smembers' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::Set(s) => {
                         let items: Vec<RespValue> = s
                             .members()
                             .into_iter()
-                            .map(|m| RespValue::bulk_string(m.clone()))
+                            .map(|m| RespValue::bulk_string(m.to_vec()))
                             .collect();
                         RespValue::array(items)
                     }
@@ -793,7 +793,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'scard' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::Set(s) => RespValue::integer(s.len() as i64),
@@ -812,9 +812,9 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
                 return RespValue::error("ERR wrong number of arguments for 'zadd' command");
             }
             let key = cmd_args[0];
-            let db = store.db(db_index);
-            ensure_sorted_set(db, key);
-            let zset = match get_sorted_set_mut(db, key) {
+            let mut db = store.db(db_index);
+            ensure_sorted_set(&mut db, key);
+            let zset = match get_sorted_set_mut(&mut db, key) {
                 Ok(z) => z,
                 Err(e) => return e,
             };
@@ -835,7 +835,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 2 {
                 return RespValue::error("ERR wrong number of arguments for 'zscore' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::SortedSet(z) => match z.score(cmd_args[1].as_bytes())
@@ -855,7 +855,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 1 {
                 return RespValue::error("ERR wrong number of arguments for 'zcard' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::SortedSet(z) => RespValue::integer(z.len() as i64),
@@ -872,7 +872,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
                 return RespValue::error("ERR wrong number of arguments for 'zrem' command");
             }
             let key = cmd_args[0];
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get_mut(key) {
                 Some(entry) => match &mut entry.value {
                     crate::types::RedisValue::SortedSet(z) => {
@@ -896,7 +896,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.len() != 2 {
                 return RespValue::error("ERR wrong number of arguments for 'zrank' command");
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             match db.get(cmd_args[0]) {
                 Some(entry) => match &entry.value {
                     crate::types::RedisValue::SortedSet(z) => {
@@ -935,8 +935,13 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
         }
 
         "FLUSHDB" => {
-            let db = store.db(db_index);
-            db.flush();
+            let lazy = match cmd_args.first().map(|m| m.to_uppercase()).as_deref() {
+                None | Some("SYNC") => false,
+                Some("ASYNC") => true,
+                Some(_) => return RespValue::error("ERR syntax error"),
+            };
+            let mut db = store.db(db_index);
+            db.flush(lazy);
             RespValue::ok()
         }
 
@@ -945,7 +950,9 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             if cmd_args.is_empty() {
                 return RespValue::error("ERR wrong number of arguments for 'scan' command");
             }
-            let cursor: usize = cmd_args[0].parse().unwrap_or(0);
+            let Ok(cursor) = cmd_args[0].parse::<u64>() else {
+                return RespValue::error("ERR invalid cursor");
+            };
             let mut pattern = None;
             let mut count = 10usize;
             let mut type_filter = None;
@@ -974,7 +981,7 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
                 }
                 i += 1;
             }
-            let db = store.db(db_index);
+            let mut db = store.db(db_index);
             let (next_cursor, keys) =
                 db.scan_with_type(cursor, pattern.as_deref(), count, type_filter.as_deref());
             let key_values: Vec<RespValue> = keys
@@ -987,6 +994,9 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
             ])
         }
 
+        // A script can't wait for replicas while it holds the keyspace
+        "WAIT" | "WAITAOF" => RespValue::error("ERR This Redis command is not allowed from script"),
+
         _ => RespValue::error(format!(
             "ERR unknown or unsupported command '{}' called from Lua script",
             cmd
@@ -998,8 +1008,8 @@ fn execute_redis_command(store: &mut DataStore, db_index: usize, args: &[String]
 // Helper functions for the script command executor
 // ---------------------------------------------------------------------------
 
-fn script_incr(store: &mut DataStore, db_index: usize, key: &str, delta: i64) -> RespValue {
-    let db = store.db(db_index);
+fn script_incr(store: &mut KeyspaceGuard, db_index: usize, key: &str, delta: i64) -> RespValue {
+    let mut db = store.db(db_index);
     if !db.exists(key) {
         let entry = crate::store::entry::Entry::new(crate::types::RedisValue::String(
             crate::types::rstring::RedisString::from_str("0"),
@@ -1021,7 +1031,7 @@ fn script_incr(store: &mut DataStore, db_index: usize, key: &str, delta: i64) ->
 }
 
 fn script_list_push(
-    store: &mut DataStore,
+    store: &mut KeyspaceGuard,
     db_index: usize,
     cmd_args: &[&str],
     left: bool,
@@ -1033,9 +1043,9 @@ fn script_list_push(
         ));
     }
     let key = cmd_args[0];
-    let db = store.db(db_index);
-    ensure_list(db, key);
-    let list = match get_list_mut(db, key) {
+    let mut db = store.db(db_index);
+    ensure_list(&mut db, key);
+    let list = match get_list_mut(&mut db, key) {
         Ok(l) => l,
         Err(e) => return e,
     };
@@ -1050,7 +1060,7 @@ fn script_list_push(
 }
 
 fn script_list_pop(
-    store: &mut DataStore,
+    store: &mut KeyspaceGuard,
     db_index: usize,
     cmd_args: &[&str],
     left: bool,
@@ -1061,7 +1071,7 @@ fn script_list_pop(
             "ERR wrong number of arguments for '{name}' command"
         ));
     }
-    let db = store.db(db_index);
+    let mut db = store.db(db_index);
     match db.get_mut(cmd_args[0]) {
         Some(entry) => match &mut entry.value {
             crate::types::RedisValue::List(l) => {
@@ -1079,7 +1089,7 @@ fn script_list_pop(
     }
 }
 
-fn ensure_list(db: &mut crate::store::Database, key: &str) {
+fn ensure_list(db: &mut crate::store::Database<'_>, key: &str) {
     if !db.exists(key) {
         let list = crate::types::list::RedisList::new();
         db.set(
@@ -1090,7 +1100,7 @@ fn ensure_list(db: &mut crate::store::Database, key: &str) {
 }
 
 fn get_list_mut<'a>(
-    db: &'a mut crate::store::Database,
+    db: &'a mut crate::store::Database<'_>,
     key: &str,
 ) -> Result<&'a mut crate::types::list::RedisList, RespValue> {
     match db.get_mut(key) {
@@ -1104,7 +1114,7 @@ fn get_list_mut<'a>(
     }
 }
 
-fn ensure_hash(db: &mut crate::store::Database, key: &str) {
+fn ensure_hash(db: &mut crate::store::Database<'_>, key: &str) {
     if !db.exists(key) {
         let hash = crate::types::hash::RedisHash::new();
         db.set(
@@ -1115,7 +1125,7 @@ fn ensure_hash(db: &mut crate::store::Database, key: &str) {
 }
 
 fn get_hash_mut<'a>(
-    db: &'a mut crate::store::Database,
+    db: &'a mut crate::store::Database<'_>,
     key: &str,
 ) -> Result<&'a mut crate::types::hash::RedisHash, RespValue> {
     match db.get_mut(key) {
@@ -1129,7 +1139,7 @@ fn get_hash_mut<'a>(
     }
 }
 
-fn ensure_set(db: &mut crate::store::Database, key: &str) {
+fn ensure_set(db: &mut crate::store::Database<'_>, key: &str) {
     if !db.exists(key) {
         let set = crate::types::set::RedisSet::new();
         db.set(
@@ -1140,7 +1150,7 @@ fn ensure_set(db: &mut crate::store::Database, key: &str) {
 }
 
 fn get_set_mut<'a>(
-    db: &'a mut crate::store::Database,
+    db: &'a mut crate::store::Database<'_>,
     key: &str,
 ) -> Result<&'a mut crate::types::set::RedisSet, RespValue> {
     match db.get_mut(key) {
@@ -1154,7 +1164,7 @@ fn get_set_mut<'a>(
     }
 }
 
-fn ensure_sorted_set(db: &mut crate::store::Database, key: &str) {
+fn ensure_sorted_set(db: &mut crate::store::Database<'_>, key: &str) {
     if !db.exists(key) {
         let zset = crate::types::sorted_set::RedisSortedSet::new();
         db.set(
@@ -1165,7 +1175,7 @@ fn ensure_sorted_set(db: &mut crate::store::Database, key: &str) {
 }
 
 fn get_sorted_set_mut<'a>(
-    db: &'a mut crate::store::Database,
+    db: &'a mut crate::store::Database<'_>,
     key: &str,
 ) -> Result<&'a mut crate::types::sorted_set::RedisSortedSet, RespValue> {
     match db.get_mut(key) {
@@ -1185,7 +1195,7 @@ fn get_sorted_set_mut<'a>(
 
 /// Evaluate a Lua script with the given KEYS and ARGV arrays.
 ///
-/// `store` must be a mutable reference to the DataStore (the caller already
+/// `store` must be a mutable reference to the KeyspaceGuard (the caller already
 /// holds the write lock).  This function creates a short-lived Lua VM,
 /// registers the `redis.call()` / `redis.pcall()` globals, then runs the
 /// script and converts the return value back to `RespValue`.
@@ -1193,17 +1203,17 @@ pub fn eval_script(
     script: &str,
     keys: &[String],
     argv: &[String],
-    store: &mut DataStore,
+    store: &mut KeyspaceGuard,
     db_index: usize,
 ) -> RespValue {
     let lua = Lua::new();
 
-    // We pass a raw pointer to the DataStore into the Lua closures.
+    // We pass a raw pointer to the KeyspaceGuard into the Lua closures.
     // SAFETY: the pointer is valid for the entire duration of `lua.load(...).call()`,
-    // because this function holds `&mut DataStore` (and hence the write guard)
+    // because this function holds `&mut KeyspaceGuard` (and hence the write guard)
     // for the entire call. The Lua VM is created and destroyed within this
     // function, so the pointer cannot escape.
-    let store_ptr = store as *mut DataStore;
+    let store_ptr = store as *mut KeyspaceGuard;
 
     // Set up KEYS and ARGV globals
     if let Err(e) = setup_globals(&lua, keys, argv, store_ptr, db_index) {
@@ -1231,7 +1241,7 @@ fn setup_globals(
     lua: &Lua,
     keys: &[String],
     argv: &[String],
-    store_ptr: *mut DataStore,
+    store_ptr: *mut KeyspaceGuard,
     db_index: usize,
 ) -> LuaResult<()> {
     // KEYS table (1-indexed)
@@ -1252,7 +1262,7 @@ fn setup_globals(
     let call_store_ptr = store_ptr as usize;
     let call_db = db_index;
     let redis_call = lua.create_function(move |lua, args: LuaMultiValue| {
-        let store: &mut DataStore = unsafe { &mut *(call_store_ptr as *mut DataStore) };
+        let store: &mut KeyspaceGuard = unsafe { &mut *(call_store_ptr as *mut KeyspaceGuard) };
         let str_args = lua_args_to_strings(&args)?;
         let result = execute_redis_command(store, call_db, &str_args);
         // redis.call() raises on errors
@@ -1266,7 +1276,7 @@ fn setup_globals(
     let pcall_store_ptr = store_ptr as usize;
     let pcall_db = db_index;
     let redis_pcall = lua.create_function(move |lua, args: LuaMultiValue| {
-        let store: &mut DataStore = unsafe { &mut *(pcall_store_ptr as *mut DataStore) };
+        let store: &mut KeyspaceGuard = unsafe { &mut *(pcall_store_ptr as *mut KeyspaceGuard) };
         let str_args = lua_args_to_strings(&args)?;
         let result = execute_redis_command(store, pcall_db, &str_args);
         // redis.pcall() never raises — errors are returned as tables
@@ -0,0 +1,410 @@
+//! Client connections to a sentinel: the SENTINEL command family, a small
+//! subset of the regular commands, and pub/sub for sentinel events.
+
+use super::monitor::{force_failover, process_hello, vote_leader};
+use super::{Addr, HELLO_CHANNEL, MonitoredMaster, SentinelState, SharedSentinel};
+use crate::glob::glob_match;
+use crate::resp::{RespParser, RespValue};
+use bytes::BytesMut;
+use std::collections::BTreeSet;
+use std::io;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Channels and patterns a client is subscribed to.
+#[derive(Default)]
+struct Subscriptions {
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+}
+
+impl Subscriptions {
+    fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+}
+
+fn bulk(s: &str) -> RespValue {
+    RespValue::bulk_string(s.as_bytes().to_vec())
+}
+
+/// Serve one client until it disconnects or sends QUIT.
+pub async fn handle_connection(mut stream: TcpStream, state: SharedSentinel) -> io::Result<()> {
+    let mut buf = BytesMut::with_capacity(4096);
+    let mut events = state.lock().await.events.subscribe();
+    let mut subs = Subscriptions::default();
+    loop {
+        loop {
+            let value = match RespParser::parse(&mut buf) {
+                Ok(Some(value)) => value,
+                Ok(None) => break,
+                Err(e) => {
+                    let reply = RespValue::error(format!("ERR Protocol error: {e}"));
+                    stream.write_all(&reply.serialize()).await?;
+                    return Ok(());
+                }
+            };
+            let Some(args) = command_args(&value) else {
+                continue;
+            };
+            if args[0].eq_ignore_ascii_case("QUIT") {
+                stream.write_all(&RespValue::ok().serialize()).await?;
+                return Ok(());
+            }
+            let mut out = Vec::new();
+            for reply in execute(&args, &state, &mut subs).await {
+                reply.write_to(&mut out);
+            }
+            stream.write_all(&out).await?;
+        }
+
+        tokio::select! {
+            n = stream.read_buf(&mut buf) => {
+                if n? == 0 {
+                    return Ok(());
+                }
+            }
+            event = events.recv(), if subs.count() > 0 => match event {
+                Ok((channel, message)) => {
+                    let mut out = Vec::new();
+                    if subs.channels.contains(&channel) {
+                        RespValue::array(vec![bulk("message"), bulk(&channel), bulk(&message)])
+                            .write_to(&mut out);
+                    }
+                    for pattern in subs.patterns.iter().filter(|p| glob_match(p, &channel)) {
+                        RespValue::array(vec![
+                            bulk("pmessage"),
+                            bulk(pattern),
+                            bulk(&channel),
+                            bulk(&message),
+                        ])
+                        .write_to(&mut out);
+                    }
+                    stream.write_all(&out).await?;
+                }
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+fn command_args(value: &RespValue) -> Option<Vec<String>> {
+    match value {
+        RespValue::Array(Some(items)) if !items.is_empty() => {
+            items.iter().map(|v| v.to_string_lossy()).collect()
+        }
+        _ => None,
+    }
+}
+
+/// Run one command. Subscription commands reply once per channel, so
+/// every command returns a list of replies.
+async fn execute(
+    args: &[String],
+    state: &SharedSentinel,
+    subs: &mut Subscriptions,
+) -> Vec<RespValue> {
+    let cmd = args[0].to_uppercase();
+    let wrong_args = || {
+        vec![RespValue::error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            args[0].to_lowercase()
+        ))]
+    };
+    match cmd.as_str() {
+        "SUBSCRIBE" | "PSUBSCRIBE" | "UNSUBSCRIBE" | "PUNSUBSCRIBE" => {
+            if args.len() < 2 && !cmd.contains("UNSUB") {
+                return wrong_args();
+            }
+            subscription(&cmd, &args[1..], subs)
+        }
+        _ if subs.count() > 0 && cmd != "PING" => vec![RespValue::error(format!(
+            "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+            args[0].to_lowercase()
+        ))],
+        "PING" => vec![match args.get(1) {
+            Some(message) => bulk(message),
+            None => RespValue::simple_string("PONG"),
+        }],
+        "ECHO" if args.len() == 2 => vec![bulk(&args[1])],
+        "AUTH" | "CLIENT" | "HELLO" => vec![RespValue::ok()],
+        "INFO" => vec![bulk(&info(&*state.lock().await))],
+        "ROLE" => {
+            let st = state.lock().await;
+            vec![RespValue::array(vec![
+                bulk("sentinel"),
+                RespValue::array(st.masters.keys().map(|n| bulk(n)).collect()),
+            ])]
+        }
+        "PUBLISH" if args.len() == 3 => {
+            if args[1] != HELLO_CHANNEL {
+                return vec![RespValue::error(
+                    "ERR Only HELLO messages are accepted by Sentinel instances.",
+                )];
+            }
+            process_hello(&mut *state.lock().await, &args[2]);
+            vec![RespValue::integer(1)]
+        }
+        "SENTINEL" if args.len() >= 2 => vec![sentinel_command(&args[1..], state).await],
+        "ECHO" | "PUBLISH" | "SENTINEL" => wrong_args(),
+        _ => vec![RespValue::error(format!(
+            "ERR unknown command '{}', with args beginning with: {}",
+            args[0],
+            args[1..]
+                .iter()
+                .map(|a| format!("'{a}' "))
+                .collect::<String>()
+        ))],
+    }
+}
+
+fn subscription(cmd: &str, names: &[String], subs: &mut Subscriptions) -> Vec<RespValue> {
+    let pattern = cmd.starts_with('P');
+    let set = if pattern {
+        &mut subs.patterns
+    } else {
+        &mut subs.channels
+    };
+    let kind = cmd.to_lowercase();
+    let mut replies = Vec::new();
+    if cmd.contains("UNSUB") {
+        let names: Vec<String> = if names.is_empty() {
+            set.iter().cloned().collect()
+        } else {
+            names.to_vec()
+        };
+        for name in names {
+            set.remove(&name);
+            replies.push((name, kind.clone()));
+        }
+    } else {
+        for name in names {
+            set.insert(name.clone());
+            replies.push((name.clone(), kind.clone()));
+        }
+    }
+    let count = subs.count();
+    replies
+        .into_iter()
+        .map(|(name, kind)| {
+            RespValue::array(vec![
+                bulk(&kind),
+                bulk(&name),
+                RespValue::integer(count as i64),
+            ])
+        })
+        .collect()
+}
+
+fn info(st: &SentinelState) -> String {
+    let mut out = String::new();
+    out.push_str("# Server\r\n");
+    out.push_str("redis_mode:sentinel\r\n");
+    out.push_str(&format!("run_id:{}\r\n", st.myid));
+    out.push_str(&format!("tcp_port:{}\r\n", st.addr.port));
+    out.push_str("\r\n# Sentinel\r\n");
+    out.push_str(&format!("sentinel_masters:{}\r\n", st.masters.len()));
+    out.push_str("sentinel_tilt:0\r\n");
+    out.push_str("sentinel_running_scripts:0\r\n");
+    out.push_str("sentinel_scripts_queue_length:0\r\n");
+    for (i, m) in st.masters.values().enumerate() {
+        let status = if m.odown {
+            "odown"
+        } else if m.sdown {
+            "sdown"
+        } else {
+            "ok"
+        };
+        out.push_str(&format!(
+            "master{i}:name={},status={status},address={},slaves={},sentinels={}\r\n",
+            m.name,
+            m.addr,
+            m.replicas.len(),
+            m.sentinels.len() + 1
+        ));
+    }
+    out
+}
+
+/// Field/value pairs as a flat array, the shape Redis uses for
+/// `SENTINEL MASTERS`, `REPLICAS` and `SENTINELS`.
+fn fields(pairs: Vec<(&str, String)>) -> RespValue {
+    RespValue::array(
+        pairs
+            .into_iter()
+            .flat_map(|(k, v)| [bulk(k), bulk(&v)])
+            .collect(),
+    )
+}
+
+fn millis_since(t: Instant) -> String {
+    t.elapsed().as_millis().to_string()
+}
+
+fn master_fields(st: &SentinelState, m: &MonitoredMaster) -> RespValue {
+    let mut pairs = vec![
+        ("name", m.name.clone()),
+        ("ip", m.addr.ip.clone()),
+        ("port", m.addr.port.to_string()),
+        ("flags", m.flags()),
+        ("last-ok-ping-reply", millis_since(m.link.last_avail)),
+        ("num-slaves", m.replicas.len().to_string()),
+        ("num-other-sentinels", m.sentinels.len().to_string()),
+        ("quorum", m.quorum.to_string()),
+        (
+            "down-after-milliseconds",
+            m.down_after.as_millis().to_string(),
+        ),
+        (
+            "failover-timeout",
+            m.failover_timeout.as_millis().to_string(),
+        ),
+        ("config-epoch", m.config_epoch.to_string()),
+        ("current-epoch", st.current_epoch.to_string()),
+    ];
+    if let Some(f) = &m.failover {
+        pairs.push(("failover-state", f.stage.to_string()));
+    }
+    fields(pairs)
+}
+
+fn replica_fields(m: &MonitoredMaster) -> Vec<RespValue> {
+    m.replicas
+        .values()
+        .map(|r| {
+            let mut flags = vec![if r.role_master { "master" } else { "slave" }];
+            if r.sdown {
+                flags.push("s_down");
+            }
+            let (master_host, master_port) = match &r.master_addr {
+                Some(a) => (a.ip.clone(), a.port.to_string()),
+                None => ("?".to_string(), "0".to_string()),
+            };
+            fields(vec![
+                ("name", r.addr.to_string()),
+                ("ip", r.addr.ip.clone()),
+                ("port", r.addr.port.to_string()),
+                ("flags", flags.join(",")),
+                ("last-ok-ping-reply", millis_since(r.link.last_avail)),
+                (
+                    "master-link-status",
+                    if r.master_link_up { "ok" } else { "err" }.to_string(),
+                ),
+                ("master-host", master_host),
+                ("master-port", master_port),
+                ("slave-priority", r.priority.to_string()),
+                ("slave-repl-offset", r.offset.to_string()),
+            ])
+        })
+        .collect()
+}
+
+fn sentinel_fields(m: &MonitoredMaster) -> Vec<RespValue> {
+    m.sentinels
+        .values()
+        .map(|p| {
+            fields(vec![
+                ("name", p.addr.to_string()),
+                ("ip", p.addr.ip.clone()),
+                ("port", p.addr.port.to_string()),
+                ("runid", p.runid.clone()),
+                ("flags", "sentinel".to_string()),
+                ("last-hello-message", millis_since(p.last_hello_received)),
+                ("voted-leader", p.leader.clone().unwrap_or("?".to_string())),
+                ("voted-leader-epoch", p.leader_epoch.to_string()),
+            ])
+        })
+        .collect()
+}
+
+async fn sentinel_command(args: &[String], state: &SharedSentinel) -> RespValue {
+    let sub = args[0].to_uppercase();
+    let mut st = state.lock().await;
+    let no_such_master = || RespValue::error("ERR No such master with that name");
+    match (sub.as_str(), args.len()) {
+        ("MASTERS", 1) => {
+            RespValue::array(st.masters.values().map(|m| master_fields(&st, m)).collect())
+        }
+        ("MASTER", 2) => match st.masters.get(&args[1]) {
+            Some(m) => master_fields(&st, m),
+            None => no_such_master(),
+        },
+        ("REPLICAS" | "SLAVES", 2) => match st.masters.get(&args[1]) {
+            Some(m) => RespValue::array(replica_fields(m)),
+            None => no_such_master(),
+        },
+        ("SENTINELS", 2) => match st.masters.get(&args[1]) {
+            Some(m) => RespValue::array(sentinel_fields(m)),
+            None => no_such_master(),
+        },
+        ("GET-MASTER-ADDR-BY-NAME", 2) => match st.masters.get(&args[1]) {
+            Some(m) => RespValue::array(vec![bulk(&m.addr.ip), bulk(&m.addr.port.to_string())]),
+            None => RespValue::null_array(),
+        },
+        ("IS-MASTER-DOWN-BY-ADDR", 5) => {
+            let (Ok(port), Ok(epoch)) = (args[2].parse::<u16>(), args[3].parse::<u64>()) else {
+                return RespValue::error("ERR value is not an integer or out of range");
+            };
+            let addr = Addr::new(args[1].clone(), port);
+            let Some(name) = st
+                .masters
+                .values()
+                .find(|m| m.addr == addr)
+                .map(|m| m.name.clone())
+            else {
+                return RespValue::array(vec![
+                    RespValue::integer(0),
+                    bulk("*"),
+                    RespValue::integer(0),
+                ]);
+            };
+            let down = st.masters[&name].sdown;
+            let (leader, leader_epoch) = if args[4] == "*" {
+                (None, 0)
+            } else {
+                vote_leader(&mut st, &name, &args[4], epoch)
+            };
+            RespValue::array(vec![
+                RespValue::integer(down as i64),
+                bulk(leader.as_deref().unwrap_or("*")),
+                RespValue::integer(leader_epoch as i64),
+            ])
+        }
+        ("FAILOVER", 2) => match force_failover(&mut st, &args[1]) {
+            Ok(()) => RespValue::ok(),
+            Err(e) => RespValue::error(e),
+        },
+        ("MYID", 1) => bulk(&st.myid),
+        ("CKQUORUM", 2) => match st.masters.get(&args[1]) {
+            Some(m) => {
+                let usable = 1 + m
+                    .sentinels
+                    .values()
+                    .filter(|p| p.last_hello_received.elapsed().as_secs() < 30)
+                    .count();
+                let voters = m.sentinels.len() + 1;
+                if usable < m.quorum {
+                    RespValue::error(format!(
+                        "NOQUORUM {usable} usable Sentinels. Not enough available Sentinels to reach the specified quorum for this master"
+                    ))
+                } else if usable < voters / 2 + 1 {
+                    RespValue::error(format!(
+                        "NOQUORUM {usable} usable Sentinels. Not enough available Sentinels to reach the majority and authorize a failover"
+                    ))
+                } else {
+                    RespValue::simple_string(format!(
+                        "OK {usable} usable Sentinels. Quorum and failover authorization can be reached"
+                    ))
+                }
+            }
+            None => no_such_master(),
+        },
+        _ => RespValue::error(format!(
+            "ERR Unknown sentinel subcommand or wrong number of arguments for '{}'",
+            args[0]
+        )),
+    }
+}
@@ -0,0 +1,65 @@
+//! Minimal RESP client used by sentinels to talk to monitored instances and
+//! to each other.
+
+use crate::resp::{RespParser, RespValue};
+use bytes::BytesMut;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A connection to a Redis-compatible server.
+pub struct Link {
+    stream: TcpStream,
+    buf: BytesMut,
+}
+
+impl Link {
+    pub async fn connect(addr: &str) -> io::Result<Self> {
+        Ok(Link {
+            stream: TcpStream::connect(addr).await?,
+            buf: BytesMut::with_capacity(4096),
+        })
+    }
+
+    /// Send a command without waiting for the reply.
+    pub async fn send(&mut self, args: &[&str]) -> io::Result<()> {
+        let cmd = RespValue::array(
+            args.iter()
+                .map(|a| RespValue::bulk_string(a.as_bytes().to_vec()))
+                .collect(),
+        );
+        self.stream.write_all(&cmd.serialize()).await
+    }
+
+    /// Read the next value sent by the server.
+    pub async fn read_value(&mut self) -> io::Result<RespValue> {
+        loop {
+            match RespParser::parse(&mut self.buf) {
+                Ok(Some(value)) => return Ok(value),
+                Ok(None) => {}
+                Err(e) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+                }
+            }
+            if self.stream.read_buf(&mut self.buf).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    pub async fn command(&mut self, args: &[&str]) -> io::Result<RespValue> {
+        self.send(args).await?;
+        self.read_value().await
+    }
+}
+
+/// Connect, run one command and return its reply, all within `timeout`.
+pub async fn query(addr: &str, args: &[&str], timeout: Duration) -> io::Result<RespValue> {
+    tokio::time::timeout(timeout, async {
+        let mut link = Link::connect(addr).await?;
+        link.command(args).await
+    })
+    .await
+    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+}
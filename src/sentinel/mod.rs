@@ -0,0 +1,458 @@
+//! Sentinel mode: monitor masters, agree with other sentinels that a master
+//! is down, elect a leader and promote one of its replicas.
+//!
+//! Started with `cedis --sentinel [sentinel.conf]`. The config file uses the
+//! Redis `sentinel.conf` directives, and any `--name args...` on the command
+//! line is applied as an extra config line, e.g.
+//! `--sentinel monitor mymaster 127.0.0.1 6379 2`.
+//!
+//! Sentinels discover replicas from the master's `INFO replication`, and each
+//! other through hello messages published on `__sentinel__:hello`. State is
+//! kept in memory only; the config file is never rewritten.
+
+pub mod command;
+pub mod link;
+pub mod monitor;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::{Mutex, broadcast};
+use tracing::info;
+
+/// Pub/sub channel sentinels announce themselves and their config on.
+pub const HELLO_CHANNEL: &str = "__sentinel__:hello";
+
+/// Address of a monitored instance or another sentinel.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Addr {
+    pub ip: String,
+    pub port: u16,
+}
+
+impl Addr {
+    pub fn new(ip: impl Into<String>, port: u16) -> Self {
+        Addr {
+            ip: ip.into(),
+            port,
+        }
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.ip, self.port)
+    }
+}
+
+/// A `sentinel monitor` entry and its per-master options.
+#[derive(Debug, Clone)]
+pub struct MasterConfig {
+    pub name: String,
+    pub addr: Addr,
+    pub quorum: usize,
+    pub down_after: Duration,
+    pub failover_timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct SentinelConfig {
+    pub bind: String,
+    pub port: u16,
+    pub announce_ip: Option<String>,
+    pub announce_port: Option<u16>,
+    pub myid: Option<String>,
+    pub masters: Vec<MasterConfig>,
+}
+
+impl Default for SentinelConfig {
+    fn default() -> Self {
+        SentinelConfig {
+            bind: "127.0.0.1".to_string(),
+            port: 26379,
+            announce_ip: None,
+            announce_port: None,
+            myid: None,
+            masters: Vec::new(),
+        }
+    }
+}
+
+impl SentinelConfig {
+    /// Build the config from an optional config file (the first positional
+    /// argument) followed by `--name args...` overrides.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut config = SentinelConfig::default();
+        let mut lines: Vec<Vec<String>> = Vec::new();
+        for arg in args {
+            if let Some(name) = arg.strip_prefix("--") {
+                lines.push(vec![name.to_string()]);
+            } else if let Some(line) = lines.last_mut() {
+                line.push(arg.clone());
+            } else {
+                let text = std::fs::read_to_string(arg)
+                    .map_err(|e| format!("Cannot read config file {arg}: {e}"))?;
+                for line in text.lines() {
+                    let words: Vec<String> = line.split_whitespace().map(String::from).collect();
+                    if !words.is_empty() && !words[0].starts_with('#') {
+                        config.apply(&words)?;
+                    }
+                }
+            }
+        }
+        for line in lines {
+            // A bare --sentinel only selects sentinel mode
+            if line.len() > 1 || line[0] != "sentinel" {
+                config.apply(&line)?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Apply one config line. Directives that only matter to a data server
+    /// (dir, logfile, ...) are ignored.
+    fn apply(&mut self, words: &[String]) -> Result<(), String> {
+        let arg = |i: usize| {
+            words
+                .get(i)
+                .map(String::as_str)
+                .ok_or_else(|| format!("Missing argument in '{}'", words.join(" ")))
+        };
+        let number = |i: usize| -> Result<u64, String> {
+            arg(i)?
+                .parse()
+                .map_err(|_| format!("Invalid number in '{}'", words.join(" ")))
+        };
+        match words[0].to_lowercase().as_str() {
+            "port" => self.port = number(1)? as u16,
+            "bind" => self.bind = arg(1)?.to_string(),
+            "sentinel" => match arg(1)?.to_lowercase().as_str() {
+                "monitor" => self.masters.push(MasterConfig {
+                    name: arg(2)?.to_string(),
+                    addr: Addr::new(arg(3)?, number(4)? as u16),
+                    quorum: (number(5)? as usize).max(1),
+                    down_after: Duration::from_secs(30),
+                    failover_timeout: Duration::from_secs(180),
+                }),
+                "down-after-milliseconds" => {
+                    self.master_mut(arg(2)?)?.down_after = Duration::from_millis(number(3)?);
+                }
+                "failover-timeout" => {
+                    self.master_mut(arg(2)?)?.failover_timeout = Duration::from_millis(number(3)?);
+                }
+                // Replicas are always reconfigured all at once
+                "parallel-syncs" => {
+                    self.master_mut(arg(2)?)?;
+                }
+                "announce-ip" => self.announce_ip = Some(arg(2)?.to_string()),
+                "announce-port" => self.announce_port = Some(number(2)? as u16),
+                "myid" => self.myid = Some(arg(2)?.to_string()),
+                other => return Err(format!("Unsupported sentinel directive '{other}'")),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn master_mut(&mut self, name: &str) -> Result<&mut MasterConfig, String> {
+        self.masters
+            .iter_mut()
+            .find(|m| m.name == name)
+            .ok_or_else(|| format!("No such master with specified name: {name}"))
+    }
+}
+
+/// Ping and INFO bookkeeping for one instance.
+#[derive(Debug)]
+pub struct InstanceLink {
+    /// Last time the instance answered a PING (starts as the time we learned of it).
+    pub last_avail: Instant,
+    pub last_ping: Option<Instant>,
+    /// When the oldest PING still waiting for a valid reply was sent.
+    pub act_ping: Option<Instant>,
+    pub last_info_sent: Option<Instant>,
+    /// Last time the instance answered INFO.
+    pub last_info: Option<Instant>,
+    pub last_hello: Option<Instant>,
+    pub ping_pending: bool,
+    pub info_pending: bool,
+}
+
+impl InstanceLink {
+    /// Down once a PING has gone unanswered for longer than `down_after`.
+    pub fn is_down(&self, down_after: Duration, now: Instant) -> bool {
+        self.act_ping
+            .is_some_and(|t| now.duration_since(t) > down_after)
+    }
+
+    fn new() -> Self {
+        InstanceLink {
+            last_avail: Instant::now(),
+            last_ping: None,
+            act_ping: None,
+            last_info_sent: None,
+            last_info: None,
+            last_hello: None,
+            ping_pending: false,
+            info_pending: false,
+        }
+    }
+}
+
+/// A replica of a monitored master, as reported by INFO.
+#[derive(Debug)]
+pub struct ReplicaInstance {
+    pub addr: Addr,
+    pub link: InstanceLink,
+    pub sdown: bool,
+    /// The replica reports itself as a master.
+    pub role_master: bool,
+    /// The master it says it replicates from.
+    pub master_addr: Option<Addr>,
+    pub master_link_up: bool,
+    pub offset: i64,
+    pub priority: u64,
+    /// Since when the replica reports its current role and master.
+    pub role_since: Instant,
+}
+
+impl ReplicaInstance {
+    fn new(addr: Addr) -> Self {
+        ReplicaInstance {
+            addr,
+            link: InstanceLink::new(),
+            sdown: false,
+            role_master: false,
+            master_addr: None,
+            master_link_up: false,
+            offset: 0,
+            priority: 100,
+            role_since: Instant::now(),
+        }
+    }
+}
+
+/// Another sentinel monitoring the same master.
+#[derive(Debug)]
+pub struct PeerSentinel {
+    pub runid: String,
+    pub addr: Addr,
+    pub last_hello_received: Instant,
+    pub last_hello_sent: Option<Instant>,
+    /// Its last answer to `is-master-down-by-addr`, and when it arrived.
+    pub master_down: bool,
+    pub master_down_reply: Option<Instant>,
+    pub ask_pending: bool,
+    pub last_ask: Option<Instant>,
+    /// The leader it voted for, and in which epoch.
+    pub leader: Option<String>,
+    pub leader_epoch: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailoverStage {
+    /// Waiting to be elected leader for the failover epoch.
+    WaitStart,
+    SelectReplica,
+    /// `REPLICAOF NO ONE` sent, waiting for the replica to report role master.
+    WaitPromotion,
+    ReconfReplicas,
+}
+
+impl fmt::Display for FailoverStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailoverStage::WaitStart => write!(f, "wait_start"),
+            FailoverStage::SelectReplica => write!(f, "select_slave"),
+            FailoverStage::WaitPromotion => write!(f, "wait_promotion"),
+            FailoverStage::ReconfReplicas => write!(f, "reconf_slaves"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Failover {
+    pub epoch: u64,
+    pub stage: FailoverStage,
+    pub stage_since: Instant,
+    /// Started by `SENTINEL FAILOVER`: no agreement or election needed.
+    pub forced: bool,
+    pub promoted: Option<Addr>,
+}
+
+/// A monitored master with everything learned about it.
+#[derive(Debug)]
+pub struct MonitoredMaster {
+    pub name: String,
+    pub addr: Addr,
+    pub quorum: usize,
+    pub down_after: Duration,
+    pub failover_timeout: Duration,
+    pub config_epoch: u64,
+    pub link: InstanceLink,
+    pub sdown: bool,
+    pub odown: bool,
+    pub replicas: BTreeMap<Addr, ReplicaInstance>,
+    pub sentinels: BTreeMap<String, PeerSentinel>,
+    /// Our vote in the leader election, and its epoch.
+    pub leader: Option<String>,
+    pub leader_epoch: u64,
+    pub failover: Option<Failover>,
+    /// No failover attempt is started before this time.
+    pub failover_not_before: Option<Instant>,
+}
+
+impl MonitoredMaster {
+    fn new(config: &MasterConfig) -> Self {
+        MonitoredMaster {
+            name: config.name.clone(),
+            addr: config.addr.clone(),
+            quorum: config.quorum,
+            down_after: config.down_after,
+            failover_timeout: config.failover_timeout,
+            config_epoch: 0,
+            link: InstanceLink::new(),
+            sdown: false,
+            odown: false,
+            replicas: BTreeMap::new(),
+            sentinels: BTreeMap::new(),
+            leader: None,
+            leader_epoch: 0,
+            failover: None,
+            failover_not_before: None,
+        }
+    }
+
+    /// Flags as shown by `SENTINEL MASTERS`.
+    pub fn flags(&self) -> String {
+        let mut flags = vec!["master"];
+        if self.sdown {
+            flags.push("s_down");
+        }
+        if self.odown {
+            flags.push("o_down");
+        }
+        if self.failover.is_some() {
+            flags.push("failover_in_progress");
+        }
+        flags.join(",")
+    }
+}
+
+/// Everything a sentinel process knows.
+#[derive(Debug)]
+pub struct SentinelState {
+    pub myid: String,
+    pub current_epoch: u64,
+    /// Address we announce in hello messages.
+    pub addr: Addr,
+    pub masters: BTreeMap<String, MonitoredMaster>,
+    /// Events (`+sdown`, `+switch-master`, ...) published to subscribed clients.
+    pub events: broadcast::Sender<(String, String)>,
+}
+
+impl SentinelState {
+    pub fn new(config: &SentinelConfig) -> Self {
+        let ip = config.announce_ip.clone().unwrap_or_else(|| {
+            if config.bind == "0.0.0.0" {
+                "127.0.0.1".to_string()
+            } else {
+                config.bind.clone()
+            }
+        });
+        SentinelState {
+            myid: config
+                .myid
+                .clone()
+                .unwrap_or_else(crate::replication::generate_replid),
+            current_epoch: 0,
+            addr: Addr::new(ip, config.announce_port.unwrap_or(config.port)),
+            masters: config
+                .masters
+                .iter()
+                .map(|m| (m.name.clone(), MonitoredMaster::new(m)))
+                .collect(),
+            events: broadcast::channel(1024).0,
+        }
+    }
+
+    /// Log an event and publish it on the channel of the same name.
+    pub fn event(&self, kind: &str, message: String) {
+        info!("{kind} {message}");
+        let _ = self.events.send((kind.to_string(), message));
+    }
+}
+
+pub type SharedSentinel = Arc<Mutex<SentinelState>>;
+
+/// Run a sentinel: serve clients and monitor every configured master.
+pub async fn run_sentinel(config: SentinelConfig) -> std::io::Result<()> {
+    let addr = format!("{}:{}", config.bind, config.port);
+    let listener = TcpListener::bind(&addr).await?;
+    let state: SharedSentinel = Arc::new(Mutex::new(SentinelState::new(&config)));
+    {
+        let st = state.lock().await;
+        info!("Sentinel ID is {}, listening on {addr}", st.myid);
+        for master in st.masters.values() {
+            st.event(
+                "+monitor",
+                format!(
+                    "master {} {} {} quorum {}",
+                    master.name, master.addr.ip, master.addr.port, master.quorum
+                ),
+            );
+        }
+    }
+
+    for master in &config.masters {
+        tokio::spawn(monitor::monitor_master(state.clone(), master.name.clone()));
+    }
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = command::handle_connection(stream, state).await {
+                tracing::debug!("Sentinel client error: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_config_from_args() {
+        let config = SentinelConfig::from_args(&args(
+            "--sentinel --port 26380 \
+             --sentinel monitor mymaster 127.0.0.1 6379 2 \
+             --sentinel down-after-milliseconds mymaster 1000 \
+             --sentinel failover-timeout mymaster 5000",
+        ))
+        .unwrap();
+        assert_eq!(config.port, 26380);
+        assert_eq!(config.masters.len(), 1);
+        let m = &config.masters[0];
+        assert_eq!(m.addr, Addr::new("127.0.0.1", 6379));
+        assert_eq!(m.quorum, 2);
+        assert_eq!(m.down_after, Duration::from_millis(1000));
+        assert_eq!(m.failover_timeout, Duration::from_millis(5000));
+    }
+
+    #[test]
+    fn test_config_requires_known_master() {
+        let err = SentinelConfig::from_args(&args(
+            "--sentinel --sentinel down-after-milliseconds other 1000",
+        ))
+        .unwrap_err();
+        assert!(err.contains("No such master"));
+    }
+}
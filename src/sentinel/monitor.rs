@@ -0,0 +1,874 @@
+//! Per-master monitoring loop.
+//!
+//! Every tick plans the requests that are due (PING, INFO, hello, asking
+//! peers whether the master is down) and advances down detection and the
+//! failover state machine while holding the state lock. The requests run in
+//! their own tasks and fold their replies back into the state.
+
+use super::link::{Link, query};
+use super::{
+    Addr, Failover, FailoverStage, HELLO_CHANNEL, MonitoredMaster, PeerSentinel, ReplicaInstance,
+    SentinelState, SharedSentinel,
+};
+use crate::resp::RespValue;
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const TICK: Duration = Duration::from_millis(100);
+const PING_PERIOD: Duration = Duration::from_secs(1);
+const HELLO_PERIOD: Duration = Duration::from_secs(2);
+const ASK_PERIOD: Duration = Duration::from_secs(1);
+const INFO_PERIOD: Duration = Duration::from_secs(10);
+/// INFO period while the master is down or a failover is running.
+const INFO_PERIOD_FAST: Duration = Duration::from_secs(1);
+/// Timeout for a single request to an instance or a peer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// Random delay before starting a failover, so sentinels that see the
+/// master fail at the same time rarely split the vote.
+const MAX_DESYNC_MS: u64 = 1000;
+/// A replica reporting the wrong master for this long is reconfigured.
+const RECONF_GRACE: Duration = Duration::from_secs(4);
+const MAX_ELECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Who a request was sent to.
+#[derive(Debug, Clone)]
+enum Target {
+    Master,
+    Replica(Addr),
+}
+
+#[derive(Debug)]
+enum Action {
+    Ping(Target, Addr),
+    Info(Target, Addr),
+    Hello(Addr, String),
+    AskMasterDown {
+        runid: String,
+        to: Addr,
+        master: Addr,
+        epoch: u64,
+        req_runid: String,
+    },
+    /// `REPLICAOF <master>`, or `REPLICAOF NO ONE` without one.
+    Replicaof(Addr, Option<Addr>),
+}
+
+/// Monitor one master until the process exits.
+pub async fn monitor_master(state: SharedSentinel, name: String) {
+    tokio::spawn(subscribe_hellos(state.clone(), name.clone()));
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        let actions = {
+            let mut st = state.lock().await;
+            tick(&mut st, &name)
+        };
+        for action in actions {
+            tokio::spawn(run_action(state.clone(), name.clone(), action));
+        }
+    }
+}
+
+fn info_period(m: &MonitoredMaster) -> Duration {
+    if m.sdown || m.failover.is_some() {
+        INFO_PERIOD_FAST
+    } else {
+        INFO_PERIOD
+    }
+}
+
+fn due(last: Option<Instant>, period: Duration, now: Instant) -> bool {
+    last.is_none_or(|t| now.duration_since(t) >= period)
+}
+
+/// `slave <ip:port> <ip> <port> @ <master> <ip> <port>`, as in Redis events.
+fn describe_replica(m: &MonitoredMaster, addr: &Addr) -> String {
+    format!(
+        "slave {addr} {} {} @ {} {} {}",
+        addr.ip, addr.port, m.name, m.addr.ip, m.addr.port
+    )
+}
+
+fn describe_master(m: &MonitoredMaster) -> String {
+    format!("master {} {} {}", m.name, m.addr.ip, m.addr.port)
+}
+
+/// The hello message announcing us and our view of `m`.
+fn hello_message(st: &SentinelState, m: &MonitoredMaster) -> String {
+    format!(
+        "{},{},{},{},{},{},{},{}",
+        st.addr.ip,
+        st.addr.port,
+        st.myid,
+        st.current_epoch,
+        m.name,
+        m.addr.ip,
+        m.addr.port,
+        m.config_epoch
+    )
+}
+
+fn tick(st: &mut SentinelState, name: &str) -> Vec<Action> {
+    let now = Instant::now();
+    let mut actions = Vec::new();
+    let hello = match st.masters.get(name) {
+        Some(m) => hello_message(st, m),
+        None => return actions,
+    };
+    let myid = st.myid.clone();
+    let current_epoch = st.current_epoch;
+    let Some(m) = st.masters.get_mut(name) else {
+        return actions;
+    };
+    let info_every = info_period(m);
+
+    // Periodic requests
+    let ping_every = PING_PERIOD.min(m.down_after);
+    if !m.link.ping_pending && due(m.link.last_ping, ping_every, now) {
+        m.link.ping_pending = true;
+        m.link.last_ping = Some(now);
+        m.link.act_ping.get_or_insert(now);
+        actions.push(Action::Ping(Target::Master, m.addr.clone()));
+    }
+    if !m.link.info_pending && due(m.link.last_info_sent, info_every, now) {
+        m.link.info_pending = true;
+        m.link.last_info_sent = Some(now);
+        actions.push(Action::Info(Target::Master, m.addr.clone()));
+    }
+    if due(m.link.last_hello, HELLO_PERIOD, now) {
+        m.link.last_hello = Some(now);
+        actions.push(Action::Hello(m.addr.clone(), hello.clone()));
+    }
+    for replica in m.replicas.values_mut() {
+        let target = || Target::Replica(replica.addr.clone());
+        if !replica.link.ping_pending && due(replica.link.last_ping, ping_every, now) {
+            replica.link.ping_pending = true;
+            replica.link.last_ping = Some(now);
+            replica.link.act_ping.get_or_insert(now);
+            actions.push(Action::Ping(target(), replica.addr.clone()));
+        }
+        if !replica.link.info_pending && due(replica.link.last_info_sent, info_every, now) {
+            replica.link.info_pending = true;
+            replica.link.last_info_sent = Some(now);
+            actions.push(Action::Info(target(), replica.addr.clone()));
+        }
+        if !replica.sdown && due(replica.link.last_hello, HELLO_PERIOD, now) {
+            replica.link.last_hello = Some(now);
+            actions.push(Action::Hello(replica.addr.clone(), hello.clone()));
+        }
+    }
+    for peer in m.sentinels.values_mut() {
+        if due(peer.last_hello_sent, HELLO_PERIOD, now) {
+            peer.last_hello_sent = Some(now);
+            actions.push(Action::Hello(peer.addr.clone(), hello.clone()));
+        }
+    }
+
+    // Subjective down
+    let mut events = Vec::new();
+    let master_sdown = m.link.is_down(m.down_after, now);
+    if master_sdown != m.sdown {
+        m.sdown = master_sdown;
+        let kind = if master_sdown { "+sdown" } else { "-sdown" };
+        events.push((kind, describe_master(m)));
+    }
+    let down_after = m.down_after;
+    let mut replica_events = Vec::new();
+    for replica in m.replicas.values_mut() {
+        let sdown = replica.link.is_down(down_after, now);
+        if sdown != replica.sdown {
+            replica.sdown = sdown;
+            let kind = if sdown { "+sdown" } else { "-sdown" };
+            replica_events.push((kind, replica.addr.clone()));
+        }
+    }
+    for (kind, addr) in replica_events {
+        events.push((kind, describe_replica(m, &addr)));
+    }
+
+    // Ask the other sentinels while we think the master is down. During a
+    // failover the question doubles as a vote request for ourselves.
+    if m.sdown {
+        let (epoch, req_runid) = match &m.failover {
+            Some(f) => (f.epoch, myid.clone()),
+            None => (current_epoch, "*".to_string()),
+        };
+        for peer in m.sentinels.values_mut() {
+            if !peer.ask_pending && due(peer.last_ask, ASK_PERIOD, now) {
+                peer.ask_pending = true;
+                peer.last_ask = Some(now);
+                actions.push(Action::AskMasterDown {
+                    runid: peer.runid.clone(),
+                    to: peer.addr.clone(),
+                    master: m.addr.clone(),
+                    epoch,
+                    req_runid: req_runid.clone(),
+                });
+            }
+        }
+    }
+
+    for (kind, message) in events {
+        st.event(kind, message);
+    }
+    check_objective_down(st, name, now);
+    start_failover_if_needed(st, name, now);
+    actions.extend(advance_failover(st, name, now));
+    actions.extend(fix_replica_configs(st, name, now));
+    actions
+}
+
+/// ODOWN once enough sentinels, including us, agree the master is down.
+fn check_objective_down(st: &mut SentinelState, name: &str, now: Instant) {
+    let Some(m) = st.masters.get_mut(name) else {
+        return;
+    };
+    let agreeing = 1 + m
+        .sentinels
+        .values()
+        .filter(|p| {
+            p.master_down
+                && p.master_down_reply
+                    .is_some_and(|t| now.duration_since(t) < ASK_PERIOD * 5)
+        })
+        .count();
+    let odown = m.sdown && agreeing >= m.quorum;
+    if odown != m.odown {
+        m.odown = odown;
+        if odown {
+            // Wait a random moment before trying, so peers don't all start at once
+            let desync = Duration::from_millis(rand::thread_rng().gen_range(0..MAX_DESYNC_MS));
+            let not_before = now + desync;
+            if m.failover_not_before.is_none_or(|t| t < not_before) {
+                m.failover_not_before = Some(not_before);
+            }
+            let message = format!("{} #quorum {agreeing}/{}", describe_master(m), m.quorum);
+            st.event("+odown", message);
+        } else {
+            let message = describe_master(m);
+            st.event("-odown", message);
+        }
+    }
+}
+
+fn start_failover_if_needed(st: &mut SentinelState, name: &str, now: Instant) {
+    let Some(m) = st.masters.get(name) else {
+        return;
+    };
+    if !m.odown || m.failover.is_some() || m.failover_not_before.is_some_and(|t| now < t) {
+        return;
+    }
+    st.current_epoch += 1;
+    let epoch = st.current_epoch;
+    let myid = st.myid.clone();
+    let m = st.masters.get_mut(name).unwrap();
+    m.failover = Some(Failover {
+        epoch,
+        stage: FailoverStage::WaitStart,
+        stage_since: now,
+        forced: false,
+        promoted: None,
+    });
+    m.failover_not_before = Some(now + m.failover_timeout * 2);
+    let description = describe_master(m);
+    st.event("+new-epoch", epoch.to_string());
+    st.event("+try-failover", description);
+    vote_leader(st, name, &myid, epoch);
+}
+
+/// Start a failover without agreement from other sentinels, for
+/// `SENTINEL FAILOVER`.
+pub fn force_failover(st: &mut SentinelState, name: &str) -> Result<(), String> {
+    let now = Instant::now();
+    let Some(m) = st.masters.get(name) else {
+        return Err("ERR No such master with that name".to_string());
+    };
+    if m.failover.is_some() {
+        return Err("INPROG Failover already in progress".to_string());
+    }
+    if select_replica(m, now).is_none() {
+        return Err("NOGOODSLAVE No suitable replica to promote".to_string());
+    }
+    st.current_epoch += 1;
+    let epoch = st.current_epoch;
+    let m = st.masters.get_mut(name).unwrap();
+    m.failover = Some(Failover {
+        epoch,
+        stage: FailoverStage::SelectReplica,
+        stage_since: now,
+        forced: true,
+        promoted: None,
+    });
+    m.failover_not_before = Some(now + m.failover_timeout * 2);
+    let description = describe_master(m);
+    st.event("+new-epoch", epoch.to_string());
+    st.event("+try-failover", description);
+    Ok(())
+}
+
+/// Vote for `runid` as the failover leader of `epoch`, unless we already
+/// voted in that epoch. Returns the vote we hold for the master.
+pub fn vote_leader(
+    st: &mut SentinelState,
+    name: &str,
+    runid: &str,
+    epoch: u64,
+) -> (Option<String>, u64) {
+    if epoch > st.current_epoch {
+        st.current_epoch = epoch;
+        st.event("+new-epoch", epoch.to_string());
+    }
+    let current_epoch = st.current_epoch;
+    let myid = st.myid.clone();
+    let Some(m) = st.masters.get_mut(name) else {
+        return (None, 0);
+    };
+    if m.leader_epoch < epoch && current_epoch <= epoch {
+        m.leader = Some(runid.to_string());
+        m.leader_epoch = current_epoch;
+        if runid != myid {
+            // Give the sentinel we voted for time to finish its failover
+            let desync = Duration::from_millis(rand::thread_rng().gen_range(0..MAX_DESYNC_MS));
+            m.failover_not_before = Some(Instant::now() + desync + m.failover_timeout * 2);
+        }
+        let message = format!("{} {}", runid, m.leader_epoch);
+        st.event("+vote-for-leader", message);
+    }
+    let m = &st.masters[name];
+    (m.leader.clone(), m.leader_epoch)
+}
+
+/// The sentinel with a majority of votes for `epoch`, if any.
+fn election_winner(m: &MonitoredMaster, epoch: u64) -> Option<String> {
+    let mut votes: HashMap<&str, usize> = HashMap::new();
+    if m.leader_epoch == epoch
+        && let Some(leader) = &m.leader
+    {
+        *votes.entry(leader).or_default() += 1;
+    }
+    for peer in m.sentinels.values() {
+        if peer.leader_epoch == epoch
+            && let Some(leader) = &peer.leader
+        {
+            *votes.entry(leader).or_default() += 1;
+        }
+    }
+    let voters = m.sentinels.len() + 1;
+    let needed = m.quorum.max(voters / 2 + 1);
+    votes
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .filter(|(_, count)| *count >= needed)
+        .map(|(leader, _)| leader.to_string())
+}
+
+/// Best replica to promote: lowest non-zero priority, then the most data,
+/// then the lowest address. Replicas that are down, haven't answered lately
+/// or already claim to be masters are skipped.
+fn select_replica(m: &MonitoredMaster, now: Instant) -> Option<Addr> {
+    let info_validity = if m.sdown {
+        INFO_PERIOD_FAST * 5
+    } else {
+        INFO_PERIOD * 5
+    };
+    m.replicas
+        .values()
+        .filter(|r| {
+            !r.sdown
+                && !r.role_master
+                && r.priority != 0
+                && now.duration_since(r.link.last_avail) < PING_PERIOD * 5
+                && r.link
+                    .last_info
+                    .is_some_and(|t| now.duration_since(t) < info_validity)
+        })
+        .min_by(|a, b| {
+            a.priority
+                .cmp(&b.priority)
+                .then(b.offset.cmp(&a.offset))
+                .then(a.addr.cmp(&b.addr))
+        })
+        .map(|r| r.addr.clone())
+}
+
+fn abort_failover(st: &mut SentinelState, name: &str, reason: &str) {
+    if let Some(m) = st.masters.get_mut(name) {
+        m.failover = None;
+        let message = describe_master(m);
+        st.event(reason, message);
+    }
+}
+
+fn set_stage(m: &mut MonitoredMaster, stage: FailoverStage, now: Instant) {
+    if let Some(f) = &mut m.failover {
+        f.stage = stage;
+        f.stage_since = now;
+    }
+}
+
+fn advance_failover(st: &mut SentinelState, name: &str, now: Instant) -> Vec<Action> {
+    let mut actions = Vec::new();
+    let myid = st.myid.clone();
+    let Some(m) = st.masters.get_mut(name) else {
+        return actions;
+    };
+    let Some(failover) = &m.failover else {
+        return actions;
+    };
+    let elapsed = now.duration_since(failover.stage_since);
+    match failover.stage {
+        FailoverStage::WaitStart => {
+            if !m.odown && !failover.forced {
+                abort_failover(st, name, "-failover-abort-master-back");
+                return actions;
+            }
+            match election_winner(m, failover.epoch) {
+                Some(leader) if leader == myid => {
+                    set_stage(m, FailoverStage::SelectReplica, now);
+                    let message = format!("{} epoch {}", describe_master(m), m.leader_epoch);
+                    st.event("+elected-leader", message);
+                }
+                _ if elapsed > m.failover_timeout.min(MAX_ELECTION_TIMEOUT) => {
+                    abort_failover(st, name, "-failover-abort-not-elected");
+                }
+                _ => {}
+            }
+        }
+        FailoverStage::SelectReplica => match select_replica(m, now) {
+            Some(addr) => {
+                if let Some(f) = &mut m.failover {
+                    f.promoted = Some(addr.clone());
+                }
+                set_stage(m, FailoverStage::WaitPromotion, now);
+                let message = describe_replica(m, &addr);
+                st.event("+selected-slave", message.clone());
+                st.event("+failover-state-send-slaveof-noone", message);
+                actions.push(Action::Replicaof(addr, None));
+            }
+            None => abort_failover(st, name, "-failover-abort-no-good-slave"),
+        },
+        FailoverStage::WaitPromotion => {
+            let promoted = failover.promoted.clone().unwrap_or_else(|| m.addr.clone());
+            if m.replicas.get(&promoted).is_some_and(|r| r.role_master) {
+                m.config_epoch = failover.epoch;
+                set_stage(m, FailoverStage::ReconfReplicas, now);
+                let message = describe_replica(m, &promoted);
+                st.event("+promoted-slave", message);
+            } else if elapsed > m.failover_timeout {
+                abort_failover(st, name, "-failover-abort-slave-timeout");
+            }
+        }
+        FailoverStage::ReconfReplicas => {
+            let promoted = failover.promoted.clone().unwrap_or_else(|| m.addr.clone());
+            for addr in m.replicas.keys().filter(|a| **a != promoted) {
+                actions.push(Action::Replicaof(addr.clone(), Some(promoted.clone())));
+            }
+            // A reachable old master is demoted right away; otherwise it is
+            // converted once it comes back
+            if !m.sdown {
+                actions.push(Action::Replicaof(m.addr.clone(), Some(promoted.clone())));
+            }
+            let message = describe_master(m);
+            st.event("+failover-end", message);
+            switch_master(st, name, promoted);
+        }
+    }
+    actions
+}
+
+/// Point the master at `new_addr`. Every other known instance, including
+/// the old master, becomes one of its replicas.
+pub fn switch_master(st: &mut SentinelState, name: &str, new_addr: Addr) {
+    let Some(m) = st.masters.get_mut(name) else {
+        return;
+    };
+    let old_addr = std::mem::replace(&mut m.addr, new_addr.clone());
+    let mut replicas: Vec<Addr> = m
+        .replicas
+        .keys()
+        .filter(|a| **a != new_addr)
+        .cloned()
+        .collect();
+    if old_addr != new_addr {
+        replicas.push(old_addr.clone());
+    }
+    m.replicas = replicas
+        .into_iter()
+        .map(|addr| (addr.clone(), ReplicaInstance::new(addr)))
+        .collect();
+    m.link = super::InstanceLink::new();
+    m.sdown = false;
+    m.odown = false;
+    m.failover = None;
+    for peer in m.sentinels.values_mut() {
+        peer.master_down = false;
+        peer.master_down_reply = None;
+    }
+    st.event(
+        "+switch-master",
+        format!(
+            "{name} {} {} {} {}",
+            old_addr.ip, old_addr.port, new_addr.ip, new_addr.port
+        ),
+    );
+}
+
+/// Reconfigure replicas that report the wrong role or master. Only done
+/// while the master is healthy and no failover is running, and only after
+/// the wrong config has persisted for a while.
+fn fix_replica_configs(st: &mut SentinelState, name: &str, now: Instant) -> Vec<Action> {
+    let mut actions = Vec::new();
+    let Some(m) = st.masters.get_mut(name) else {
+        return actions;
+    };
+    if m.sdown || m.failover.is_some() {
+        return actions;
+    }
+    let master = m.addr.clone();
+    let mut events = Vec::new();
+    for replica in m.replicas.values_mut() {
+        let wrong = replica.role_master || replica.master_addr.as_ref() != Some(&master);
+        if wrong
+            && !replica.sdown
+            && replica.link.last_info.is_some()
+            && now.duration_since(replica.role_since) >= RECONF_GRACE
+        {
+            replica.role_since = now;
+            let kind = if replica.role_master {
+                "+convert-to-slave"
+            } else {
+                "+fix-slave-config"
+            };
+            events.push((kind, replica.addr.clone()));
+            actions.push(Action::Replicaof(
+                replica.addr.clone(),
+                Some(master.clone()),
+            ));
+        }
+    }
+    let messages: Vec<_> = events
+        .into_iter()
+        .map(|(kind, addr)| (kind, describe_replica(m, &addr)))
+        .collect();
+    for (kind, message) in messages {
+        st.event(kind, message);
+    }
+    actions
+}
+
+/// Apply a hello message published by another sentinel.
+pub fn process_hello(st: &mut SentinelState, payload: &str) {
+    let parts: Vec<&str> = payload.split(',').collect();
+    if parts.len() != 8 {
+        return;
+    }
+    let (Ok(port), Ok(epoch), Ok(master_port), Ok(master_config_epoch)) = (
+        parts[1].parse::<u16>(),
+        parts[3].parse::<u64>(),
+        parts[6].parse::<u16>(),
+        parts[7].parse::<u64>(),
+    ) else {
+        return;
+    };
+    let (runid, name) = (parts[2], parts[4]);
+    if runid == st.myid || !st.masters.contains_key(name) {
+        return;
+    }
+    let addr = Addr::new(parts[0], port);
+    let master_addr = Addr::new(parts[5], master_port);
+    let now = Instant::now();
+
+    let m = st.masters.get_mut(name).unwrap();
+    // A sentinel restarted at the same address comes back with a new ID
+    m.sentinels
+        .retain(|id, peer| id == runid || peer.addr != addr);
+    let mut added = false;
+    let peer = m.sentinels.entry(runid.to_string()).or_insert_with(|| {
+        added = true;
+        PeerSentinel {
+            runid: runid.to_string(),
+            addr: addr.clone(),
+            last_hello_received: now,
+            last_hello_sent: None,
+            master_down: false,
+            master_down_reply: None,
+            ask_pending: false,
+            last_ask: None,
+            leader: None,
+            leader_epoch: 0,
+        }
+    });
+    peer.addr = addr.clone();
+    peer.last_hello_received = now;
+    if added {
+        let message = format!(
+            "sentinel {runid} {} {} @ {} {} {}",
+            addr.ip, addr.port, m.name, m.addr.ip, m.addr.port
+        );
+        st.event("+sentinel", message);
+    }
+
+    if epoch > st.current_epoch {
+        st.current_epoch = epoch;
+        st.event("+new-epoch", epoch.to_string());
+    }
+    let m = st.masters.get_mut(name).unwrap();
+    if master_config_epoch > m.config_epoch {
+        m.config_epoch = master_config_epoch;
+        if master_addr != m.addr {
+            let message = format!("sentinel {runid} {addr} @ {name}");
+            st.event("+config-update-from", message);
+            switch_master(st, name, master_addr);
+        }
+    }
+}
+
+/// Parse an INFO reply into its `field:value` lines.
+fn parse_info(text: &str) -> HashMap<&str, &str> {
+    text.lines()
+        .filter_map(|line| line.trim_end().split_once(':'))
+        .collect()
+}
+
+/// Fold an INFO reply into the state of the instance it came from.
+fn apply_info(st: &mut SentinelState, name: &str, target: &Target, addr: &Addr, text: &str) {
+    let now = Instant::now();
+    let info = parse_info(text);
+    let Some(m) = st.masters.get_mut(name) else {
+        return;
+    };
+    match target {
+        Target::Master => {
+            if m.addr != *addr {
+                return;
+            }
+            m.link.last_info = Some(now);
+            if info.get("role") != Some(&"master") {
+                return;
+            }
+            let mut discovered = Vec::new();
+            for (key, value) in &info {
+                if !key.starts_with("slave") || !key[5..].bytes().all(|b| b.is_ascii_digit()) {
+                    continue;
+                }
+                let fields: HashMap<&str, &str> =
+                    value.split(',').filter_map(|f| f.split_once('=')).collect();
+                if let (Some(ip), Some(Ok(port))) =
+                    (fields.get("ip"), fields.get("port").map(|p| p.parse()))
+                {
+                    let replica = Addr::new(*ip, port);
+                    if replica != m.addr && !m.replicas.contains_key(&replica) {
+                        discovered.push(replica);
+                    }
+                }
+            }
+            let mut messages = Vec::new();
+            for replica in discovered {
+                messages.push(describe_replica(m, &replica));
+                m.replicas
+                    .insert(replica.clone(), ReplicaInstance::new(replica));
+            }
+            for message in messages {
+                st.event("+slave", message);
+            }
+        }
+        Target::Replica(replica) => {
+            let Some(r) = m.replicas.get_mut(replica) else {
+                return;
+            };
+            r.link.last_info = Some(now);
+            let role_master = info.get("role") == Some(&"master");
+            let master_addr = match (info.get("master_host"), info.get("master_port")) {
+                (Some(host), Some(port)) if !role_master => {
+                    port.parse().ok().map(|port| Addr::new(*host, port))
+                }
+                _ => None,
+            };
+            if role_master != r.role_master || master_addr != r.master_addr {
+                r.role_since = now;
+            }
+            r.role_master = role_master;
+            r.master_addr = master_addr;
+            r.master_link_up = info.get("master_link_status") == Some(&"up");
+            if let Some(Ok(offset)) = info.get("slave_repl_offset").map(|o| o.parse()) {
+                r.offset = offset;
+            }
+            if let Some(Ok(priority)) = info.get("slave_priority").map(|p| p.parse()) {
+                r.priority = priority;
+            }
+        }
+    }
+}
+
+/// Pick the link bookkeeping for a target, if it still exists and is still
+/// at the address the request went to.
+fn target_link<'a>(
+    m: &'a mut MonitoredMaster,
+    target: &Target,
+    addr: &Addr,
+) -> Option<&'a mut super::InstanceLink> {
+    match target {
+        Target::Master => (m.addr == *addr).then_some(&mut m.link),
+        Target::Replica(replica) => m.replicas.get_mut(replica).map(|r| &mut r.link),
+    }
+}
+
+async fn run_action(state: SharedSentinel, name: String, action: Action) {
+    match action {
+        Action::Ping(target, addr) => {
+            let reply = query(&addr.to_string(), &["PING"], REQUEST_TIMEOUT).await;
+            // LOADING and MASTERDOWN still prove the instance is alive
+            let ok = match &reply {
+                Ok(RespValue::SimpleString(_)) => true,
+                Ok(RespValue::Error(e)) => e.starts_with("LOADING") || e.starts_with("MASTERDOWN"),
+                _ => false,
+            };
+            let mut st = state.lock().await;
+            if let Some(link) = st
+                .masters
+                .get_mut(&name)
+                .and_then(|m| target_link(m, &target, &addr))
+            {
+                link.ping_pending = false;
+                if ok {
+                    link.last_avail = Instant::now();
+                    link.act_ping = None;
+                }
+            }
+        }
+        Action::Info(target, addr) => {
+            let reply = query(&addr.to_string(), &["INFO", "replication"], REQUEST_TIMEOUT).await;
+            let mut st = state.lock().await;
+            if let Some(link) = st
+                .masters
+                .get_mut(&name)
+                .and_then(|m| target_link(m, &target, &addr))
+            {
+                link.info_pending = false;
+            }
+            if let Ok(RespValue::BulkString(Some(text))) = reply {
+                apply_info(
+                    &mut st,
+                    &name,
+                    &target,
+                    &addr,
+                    &String::from_utf8_lossy(&text),
+                );
+            }
+        }
+        Action::Hello(addr, message) => {
+            let _ = query(
+                &addr.to_string(),
+                &["PUBLISH", HELLO_CHANNEL, &message],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        }
+        Action::AskMasterDown {
+            runid,
+            to,
+            master,
+            epoch,
+            req_runid,
+        } => {
+            let reply = query(
+                &to.to_string(),
+                &[
+                    "SENTINEL",
+                    "is-master-down-by-addr",
+                    &master.ip,
+                    &master.port.to_string(),
+                    &epoch.to_string(),
+                    &req_runid,
+                ],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+            let mut st = state.lock().await;
+            let Some(peer) = st
+                .masters
+                .get_mut(&name)
+                .and_then(|m| m.sentinels.get_mut(&runid))
+            else {
+                return;
+            };
+            peer.ask_pending = false;
+            if let Ok(RespValue::Array(Some(items))) = reply
+                && let [
+                    RespValue::Integer(down),
+                    leader,
+                    RespValue::Integer(leader_epoch),
+                ] = items.as_slice()
+            {
+                peer.master_down = *down == 1;
+                peer.master_down_reply = Some(Instant::now());
+                if let Some(leader) = leader.to_string_lossy().filter(|l| l != "*") {
+                    peer.leader = Some(leader);
+                    peer.leader_epoch = *leader_epoch as u64;
+                }
+            }
+        }
+        Action::Replicaof(addr, master) => {
+            let port;
+            let args = match &master {
+                Some(master) => {
+                    port = master.port.to_string();
+                    ["REPLICAOF", master.ip.as_str(), port.as_str()]
+                }
+                None => ["REPLICAOF", "NO", "ONE"],
+            };
+            if let Err(e) = query(&addr.to_string(), &args, REQUEST_TIMEOUT).await {
+                tracing::warn!("REPLICAOF to {addr} failed: {e}");
+            }
+        }
+    }
+}
+
+/// Listen for hello messages on the current master, reconnecting whenever
+/// the master changes or the link drops.
+async fn subscribe_hellos(state: SharedSentinel, name: String) {
+    loop {
+        let Some(addr) = state
+            .lock()
+            .await
+            .masters
+            .get(&name)
+            .map(|m| m.addr.clone())
+        else {
+            return;
+        };
+        if let Ok(Ok(mut link)) =
+            tokio::time::timeout(REQUEST_TIMEOUT, Link::connect(&addr.to_string())).await
+            && link.send(&["SUBSCRIBE", HELLO_CHANNEL]).await.is_ok()
+        {
+            loop {
+                match tokio::time::timeout(PING_PERIOD, link.read_value()).await {
+                    Ok(Ok(RespValue::Array(Some(items)))) => {
+                        if let (Some(kind), Some(payload)) = (
+                            items.first().and_then(|v| v.to_string_lossy()),
+                            items.get(2).and_then(|v| v.to_string_lossy()),
+                        ) && kind == "message"
+                        {
+                            process_hello(&mut *state.lock().await, &payload);
+                        }
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(_)) => break,
+                    Err(_) => {
+                        let current = state
+                            .lock()
+                            .await
+                            .masters
+                            .get(&name)
+                            .map(|m| m.addr.clone());
+                        if current.as_ref() != Some(&addr) {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        tokio::time::sleep(PING_PERIOD).await;
+    }
+}
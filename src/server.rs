@@ -2,14 +2,16 @@ use crate::command;
 use crate::config::SharedConfig;
 use crate::connection::{ClientState, MonitorSender, new_monitor_sender};
 use crate::keywatcher::{KeyWatcher, SharedKeyWatcher};
+use crate::latency::{self, LatencyMonitor, SharedLatencyMonitor};
+use crate::metrics::{self, MetricsSources};
 use crate::persistence::aof::SharedAofWriter;
 use crate::pubsub::{PubSubReceiver, SharedPubSub};
-use crate::replication::{ReplicationRole, SharedReplicationState};
+use crate::replication::{FailoverState, ReplicationRole, SharedReplicationState};
 use crate::resp::{RespParser, RespValue};
 use crate::scripting::ScriptCache;
 use crate::slowlog::{SharedLastSaveTime, SharedSlowLog, SlowLog};
-use crate::store::SharedStore;
-use bytes::BytesMut;
+use crate::store::{Database, SharedStore};
+use bytes::{Buf, Bytes, BytesMut};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
@@ -27,11 +29,17 @@ pub async fn run_server(
     aof: SharedAofWriter,
     repl_state: SharedReplicationState,
 ) -> std::io::Result<()> {
-    let (bind, port) = {
+    let (bind, port, metrics_port) = {
         let cfg = config.read().await;
-        (cfg.bind.clone(), cfg.port)
+        (cfg.bind.clone(), cfg.port, cfg.metrics_port)
     };
 
+    {
+        let cfg = config.read().await;
+        store.lazyfree.configure(&cfg);
+        crate::types::encoding::configure(&cfg);
+    }
+
     let addr = format!("{bind}:{port}");
     let listener = TcpListener::bind(&addr).await?;
     info!("Cedis server listening on {addr}");
@@ -45,18 +53,39 @@ pub async fn run_server(
         let cfg = config.read().await;
         Arc::new(Mutex::new(SlowLog::new(cfg.slowlog_max_len)))
     };
+    let latency: SharedLatencyMonitor = Arc::new(std::sync::Mutex::new(LatencyMonitor::new()));
+
+    // Serve Prometheus metrics if configured
+    if metrics_port != 0 {
+        let metrics_addr = format!("{bind}:{metrics_port}");
+        let metrics_listener = TcpListener::bind(&metrics_addr).await?;
+        info!("Metrics endpoint listening on http://{metrics_addr}/metrics");
+        let sources = MetricsSources {
+            store: store.clone(),
+            config: config.clone(),
+            repl_state: repl_state.clone(),
+            last_save_time: last_save_time.clone(),
+            slowlog: slowlog.clone(),
+            latency: latency.clone(),
+        };
+        tokio::spawn(metrics::serve(metrics_listener, sources));
+    }
 
     // Spawn active expiration background task
     let store_clone = store.clone();
     let config_clone = config.clone();
+    let latency_clone = latency.clone();
     tokio::spawn(async move {
-        active_expiration_loop(store_clone, config_clone).await;
+        active_expiration_loop(store_clone, config_clone, latency_clone).await;
     });
 
     // Spawn AOF fsync background task
     let aof_clone = aof.clone();
+    let repl_clone = repl_state.clone();
+    let config_clone = config.clone();
+    let latency_clone = latency.clone();
     tokio::spawn(async move {
-        aof_fsync_loop(aof_clone).await;
+        aof_fsync_loop(aof_clone, repl_clone, config_clone, latency_clone).await;
     });
 
     // Spawn auto-save background task
@@ -64,48 +93,52 @@ pub async fn run_server(
     let config_clone = config.clone();
     let changes_clone = change_counter.clone();
     let last_save_clone = last_save_time.clone();
+    let latency_clone = latency.clone();
     tokio::spawn(async move {
-        auto_save_loop(store_clone, config_clone, changes_clone, last_save_clone).await;
+        auto_save_loop(
+            store_clone,
+            config_clone,
+            changes_clone,
+            last_save_clone,
+            latency_clone,
+        )
+        .await;
     });
 
     // Spawn memory eviction background task
     let store_clone = store.clone();
     let config_clone = config.clone();
+    let latency_clone = latency.clone();
     tokio::spawn(async move {
-        memory_eviction_loop(store_clone, config_clone).await;
+        memory_eviction_loop(store_clone, config_clone, latency_clone).await;
     });
 
     // If configured as replica, start sync loop
-    {
-        let cfg = config.read().await;
-        if let Some((ref host, port)) = cfg.replicaof {
-            let cancel = tokio_util::sync::CancellationToken::new();
-            {
-                let mut state = repl_state.write().await;
-                state.role = ReplicationRole::Replica;
-                state.master_host = Some(host.clone());
-                state.master_port = Some(port);
-                state.cancel = Some(cancel.clone());
-            }
-
-            let host = host.clone();
-            let store_c = store.clone();
-            let config_c = config.clone();
-            let repl_c = repl_state.clone();
-            let pubsub_c = pubsub.clone();
-            let kw_c = key_watcher.clone();
-            let sc_c = script_cache.clone();
-            let lst_c = last_save_time.clone();
-            let sl_c = slowlog.clone();
-
-            tokio::spawn(async move {
-                crate::replication::replica::replica_sync_loop(
-                    host, port, store_c, config_c, repl_c, pubsub_c, kw_c, sc_c, cancel, lst_c,
-                    sl_c,
-                )
-                .await;
-            });
+    let replicaof = config.read().await.replicaof.clone();
+    if let Some((host, port)) = replicaof {
+        let cancel = tokio_util::sync::CancellationToken::new();
+        {
+            let mut state = repl_state.write().await;
+            state.role = ReplicationRole::Replica;
+            state.master_host = Some(host.clone());
+            state.master_port = Some(port);
+            state.cancel = Some(cancel.clone());
         }
+        spawn_replica_sync(
+            host,
+            port,
+            cancel,
+            &store,
+            &config,
+            &aof,
+            &repl_state,
+            &pubsub,
+            &key_watcher,
+            &script_cache,
+            &last_save_time,
+            &slowlog,
+            &latency,
+        );
     }
 
     // Accept loop with graceful shutdown on ctrl-c
@@ -126,11 +159,15 @@ pub async fn run_server(
                 let repl_state = repl_state.clone();
                 let last_save_time = last_save_time.clone();
                 let slowlog = slowlog.clone();
+                let latency = latency.clone();
 
                 tokio::spawn(async move {
-                    if let Err(e) = handle_connection(stream, store, config, pubsub, aof, change_counter, key_watcher, script_cache, monitor_tx, repl_state, last_save_time, slowlog).await {
+                    store.stats.connected_clients.fetch_add(1, Ordering::Relaxed);
+                    store.stats.total_connections_received.fetch_add(1, Ordering::Relaxed);
+                    if let Err(e) = handle_connection(stream, store.clone(), config, pubsub, aof, change_counter, key_watcher, script_cache, monitor_tx, repl_state, last_save_time, slowlog, latency).await {
                         debug!("Connection error from {peer_addr}: {e}");
                     }
+                    store.stats.connected_clients.fetch_sub(1, Ordering::Relaxed);
                     debug!("Connection closed: {peer_addr}");
                 });
             }
@@ -145,6 +182,54 @@ pub async fn run_server(
     }
 }
 
+/// Spawn the task that syncs with a master and applies its stream.
+#[allow(clippy::too_many_arguments)]
+fn spawn_replica_sync(
+    host: String,
+    port: u16,
+    cancel: tokio_util::sync::CancellationToken,
+    store: &SharedStore,
+    config: &SharedConfig,
+    aof: &SharedAofWriter,
+    repl_state: &SharedReplicationState,
+    pubsub: &SharedPubSub,
+    key_watcher: &SharedKeyWatcher,
+    script_cache: &ScriptCache,
+    last_save_time: &SharedLastSaveTime,
+    slowlog: &SharedSlowLog,
+    latency: &SharedLatencyMonitor,
+) {
+    let store = store.clone();
+    let config = config.clone();
+    let aof = aof.clone();
+    let repl_state = repl_state.clone();
+    let pubsub = pubsub.clone();
+    let key_watcher = key_watcher.clone();
+    let script_cache = script_cache.clone();
+    let last_save_time = last_save_time.clone();
+    let slowlog = slowlog.clone();
+    let latency = latency.clone();
+
+    tokio::spawn(async move {
+        crate::replication::replica::replica_sync_loop(
+            host,
+            port,
+            store,
+            config,
+            aof,
+            repl_state,
+            pubsub,
+            key_watcher,
+            script_cache,
+            cancel,
+            last_save_time,
+            slowlog,
+            latency,
+        )
+        .await;
+    });
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn handle_connection(
     mut stream: TcpStream,
@@ -159,6 +244,7 @@ async fn handle_connection(
     repl_state: SharedReplicationState,
     last_save_time: SharedLastSaveTime,
     slowlog: SharedSlowLog,
+    latency: SharedLatencyMonitor,
 ) -> std::io::Result<()> {
     let mut client = ClientState::new();
     let mut buf = BytesMut::with_capacity(4096);
@@ -199,7 +285,15 @@ async fn handle_connection(
                             )
                     );
 
-                    if is_psync && let RespValue::Array(Some(ref items)) = value {
+                    // An unauthenticated PSYNC falls through to the NOAUTH check
+                    if is_psync
+                        && client.authenticated
+                        && let RespValue::Array(Some(ref items)) = value
+                    {
+                        let failover = items
+                            .get(3)
+                            .and_then(|a| a.to_string_lossy())
+                            .is_some_and(|s| s.eq_ignore_ascii_case("FAILOVER"));
                         let (replid, offset) = if items.len() >= 3 {
                             let rid = items[1].to_string_lossy().unwrap_or("?".to_string());
                             let off: i64 = items[2]
@@ -211,19 +305,16 @@ async fn handle_connection(
                             ("?".to_string(), -1i64)
                         };
 
-                        let backlog_size = {
-                            let cfg = config.read().await;
-                            cfg.repl_backlog_size
-                        };
-
                         // Hand off to PSYNC handler - this takes over the connection
                         crate::replication::master::handle_psync(
                             stream,
                             replid,
                             offset,
+                            failover,
+                            client.replica_listening_port,
                             &store,
+                            &config,
                             &repl_state,
-                            backlog_size,
                         )
                         .await;
                         return Ok(());
@@ -269,6 +360,7 @@ async fn handle_connection(
                         &repl_state,
                         &last_save_time,
                         &slowlog,
+                        &latency,
                     )
                     .await;
                     let cmd_duration = cmd_start.elapsed();
@@ -276,6 +368,19 @@ async fn handle_connection(
                     // Log to slowlog if above threshold
                     if let Some((cmd_name_str, args_strs)) = slowlog_info {
                         let cfg = config.read().await;
+                        {
+                            let mut lm = latency.lock().unwrap();
+                            let ms = cmd_duration.as_millis() as u64;
+                            let threshold = cfg.latency_monitor_threshold;
+                            if threshold > 0 && ms >= threshold {
+                                let event = if latency::is_fast_command(&cmd_name_str) {
+                                    latency::FAST_COMMAND
+                                } else {
+                                    latency::COMMAND
+                                };
+                                lm.add_sample(event, ms);
+                            }
+                        }
                         let threshold = cfg.slowlog_log_slower_than;
                         if threshold >= 0 && cmd_duration.as_micros() as i64 > threshold {
                             let timestamp = std::time::SystemTime::now()
@@ -293,8 +398,8 @@ async fn handle_connection(
                         }
                     }
 
-                    let serialized = response.serialize();
-                    stream.write_all(&serialized).await?;
+                    // Large bulk replies are written straight from the value
+                    write_chunks(&mut stream, response.to_chunks()).await?;
 
                     if is_monitor && client.in_monitor {
                         monitor_rx = Some(monitor_tx.subscribe());
@@ -380,7 +485,69 @@ async fn handle_connection(
     }
 }
 
+/// Write `chunks` in order, gathered into as few writes as the socket
+/// accepts so a reply split around a large value still goes out together.
+async fn write_chunks(stream: &mut TcpStream, mut chunks: Vec<Bytes>) -> std::io::Result<()> {
+    let mut first = 0;
+    while first < chunks.len() {
+        let slices: Vec<std::io::IoSlice> = chunks[first..]
+            .iter()
+            .map(|c| std::io::IoSlice::new(c))
+            .collect();
+        let mut written = stream.write_vectored(&slices).await?;
+        if written == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        while written > 0 {
+            let chunk = &mut chunks[first];
+            if written >= chunk.len() {
+                written -= chunk.len();
+                first += 1;
+            } else {
+                chunk.advance(written);
+                written = 0;
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Commands that are considered writes and should be logged to AOF.
+/// Commands a replica answers even when it must not serve stale data.
+fn is_stale_command(cmd: &str) -> bool {
+    matches!(
+        cmd,
+        "AUTH"
+            | "HELLO"
+            | "PING"
+            | "QUIT"
+            | "RESET"
+            | "INFO"
+            | "ROLE"
+            | "REPLICAOF"
+            | "SLAVEOF"
+            | "REPLCONF"
+            | "CONFIG"
+            | "COMMAND"
+            | "CLIENT"
+            | "DEBUG"
+            | "LATENCY"
+            | "SLOWLOG"
+            | "MONITOR"
+            | "SHUTDOWN"
+            | "SUBSCRIBE"
+            | "UNSUBSCRIBE"
+            | "PSUBSCRIBE"
+            | "PUNSUBSCRIBE"
+            | "PUBLISH"
+    )
+}
+
+/// Write commands that can wait for data before they write.
+fn is_blocking_command(cmd: &str) -> bool {
+    matches!(cmd, "BLPOP" | "BRPOP" | "BLMOVE" | "BLMPOP" | "XREADGROUP")
+}
+
 fn is_write_command(cmd: &str) -> bool {
     matches!(
         cmd,
@@ -445,6 +612,7 @@ fn is_write_command(cmd: &str) -> bool {
             | "SETBIT"
             | "PFADD"
             | "PFMERGE"
+            | "PFDEBUG"
             | "XADD"
             | "XTRIM"
             | "XDEL"
@@ -455,6 +623,7 @@ fn is_write_command(cmd: &str) -> bool {
             | "XREADGROUP"
             | "BITOP"
             | "GEOADD"
+            | "GEOSEARCHSTORE"
             | "COPY"
             | "FLUSHDB"
             | "FLUSHALL"
@@ -465,6 +634,75 @@ fn is_write_command(cmd: &str) -> bool {
     )
 }
 
+/// Whether a command, as invoked, modifies the keyspace.
+fn writes_keyspace(cmd_name: &str, args: &[RespValue]) -> bool {
+    is_write_command(cmd_name)
+        || ((cmd_name == "SORT" || cmd_name == "SORT_RO")
+            && args.iter().any(|a| {
+                a.to_string_lossy()
+                    .is_some_and(|s| s.eq_ignore_ascii_case("STORE"))
+            }))
+}
+
+/// Bump the versions of the keys a write modified, for WATCH.
+async fn touch_written_keys(
+    store: &SharedStore,
+    db_index: usize,
+    cmd_name: &str,
+    args: &[RespValue],
+) {
+    match cmd_name {
+        "FLUSHDB" | "FLUSHALL" | "SWAPDB" => {
+            let mut store_guard = store.lock_db(db_index).await;
+            store_guard.db(db_index).touch_all();
+        }
+        _ => {
+            let keys: Vec<String> = match cmd_name {
+                "RENAME" | "RENAMENX" => args
+                    .iter()
+                    .take(2)
+                    .filter_map(|a| a.to_string_lossy())
+                    .collect(),
+                "DEL" | "UNLINK" => args.iter().filter_map(|a| a.to_string_lossy()).collect(),
+                "MSET" | "MSETNX" => args
+                    .iter()
+                    .step_by(2)
+                    .filter_map(|a| a.to_string_lossy())
+                    .collect(),
+                // Only touch the STORE destination key (the key being written to).
+                // Don't touch the source key — SORT only reads from it.
+                "SORT" | "SORT_RO" => args
+                    .iter()
+                    .skip(1)
+                    .position(|a| {
+                        a.to_string_lossy()
+                            .is_some_and(|o| o.eq_ignore_ascii_case("STORE"))
+                    })
+                    .and_then(|i| args.get(i + 2))
+                    .and_then(|a| a.to_string_lossy())
+                    .into_iter()
+                    .collect(),
+                _ => args
+                    .first()
+                    .and_then(|a| a.to_string_lossy())
+                    .into_iter()
+                    .collect(),
+            };
+            let mut store_guard = store.lock_keys(db_index, &keys).await;
+            let mut db = store_guard.db(db_index);
+            for k in &keys {
+                // Only touch keys that were previously written to, so DEL
+                // on a non-existent key doesn't create a spurious version
+                // entry that breaks WATCH.
+                if matches!(cmd_name, "DEL" | "UNLINK") && db.key_version(k) == 0 {
+                    continue;
+                }
+                db.touch(k);
+            }
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn process_command(
     value: RespValue,
@@ -481,6 +719,7 @@ async fn process_command(
     repl_state: &SharedReplicationState,
     last_save_time: &SharedLastSaveTime,
     slowlog: &SharedSlowLog,
+    latency: &SharedLatencyMonitor,
 ) -> RespValue {
     let items = match value {
         RespValue::Array(Some(items)) if !items.is_empty() => items,
@@ -517,21 +756,79 @@ async fn process_command(
 
     // Check authentication
     if !client.authenticated && cmd_name != "AUTH" && cmd_name != "QUIT" && cmd_name != "HELLO" {
-        return RespValue::error("NOAUTH Authentication required.");
+        return command::reject_call(latency, &cmd_name, "NOAUTH Authentication required.");
+    }
+
+    // Writes and EXEC hold the write gate until they are propagated, so a
+    // FAILOVER pausing them waits for those in flight and none can run after
+    // the demotion. Blocking commands only wait out a pause, as they may
+    // block for long and must not hold the handover up.
+    let holds_gate = !client.is_replication_client
+        && if client.in_multi {
+            cmd_name == "EXEC"
+        } else {
+            is_write_command(&cmd_name)
+        };
+    let _write_gate = if holds_gate {
+        let gate = repl_state.read().await.write_gate.clone();
+        let guard = gate.read_owned().await;
+        (!is_blocking_command(&cmd_name)).then_some(guard)
+    } else {
+        None
+    };
+
+    let (is_replica, link_up) = {
+        let state = repl_state.read().await;
+        (
+            state.role == ReplicationRole::Replica,
+            state.master_link_status == "up",
+        )
+    };
+
+    // Without a link to the master our data may be stale
+    if is_replica
+        && !link_up
+        && !client.is_replication_client
+        && !is_stale_command(&cmd_name)
+        && !config.read().await.replica_serve_stale_data
+    {
+        return command::reject_call(
+            latency,
+            &cmd_name,
+            "MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.",
+        );
     }
 
     // Read-only enforcement for replicas
     if !client.is_replication_client {
-        let is_replica = {
-            let state = repl_state.read().await;
-            state.role == ReplicationRole::Replica
-        };
         let is_readonly = {
             let cfg = config.read().await;
             cfg.replica_read_only
         };
-        if is_replica && is_readonly && is_write_command(&cmd_name) {
-            return RespValue::error("READONLY You can't write against a read only replica.");
+        // SELECT is only in the write list so it reaches the AOF and replicas
+        if is_replica && is_readonly && is_write_command(&cmd_name) && cmd_name != "SELECT" {
+            return command::reject_call(
+                latency,
+                &cmd_name,
+                "READONLY You can't write against a read only replica.",
+            );
+        }
+        // A transaction queued while we were master, e.g. before a FAILOVER
+        if is_replica
+            && is_readonly
+            && cmd_name == "EXEC"
+            && client.in_multi
+            && client
+                .multi_queue
+                .iter()
+                .any(|(name, _)| is_write_command(name) && name != "SELECT")
+        {
+            crate::command::transaction::cmd_discard(client);
+            return command::reject_call(
+                latency,
+                &cmd_name,
+                "READONLY Transaction contains write commands but instance is now a read-only replica. EXEC aborted.",
+            );
         }
     }
 
@@ -541,9 +838,13 @@ async fn process_command(
             "SUBSCRIBE" | "UNSUBSCRIBE" | "PSUBSCRIBE" | "PUNSUBSCRIBE" | "PING" | "QUIT"
             | "RESET" => {}
             _ => {
-                return RespValue::error(format!(
-                    "ERR Can't execute '{cmd_name}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context"
-                ));
+                return command::reject_call(
+                    latency,
+                    &cmd_name,
+                    &format!(
+                        "ERR Can't execute '{cmd_name}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context"
+                    ),
+                );
             }
         }
     }
@@ -561,14 +862,36 @@ async fn process_command(
         ]);
     }
 
-    // Log write commands to AOF before executing (skip for replication clients)
-    let is_write = is_write_command(&cmd_name)
-        || ((cmd_name == "SORT" || cmd_name == "SORT_RO")
-            && args.iter().any(|a| {
-                a.to_string_lossy()
-                    .is_some_and(|s| s.eq_ignore_ascii_case("STORE"))
-            }));
+    let is_write = writes_keyspace(&cmd_name, args);
+    // Queued commands are accounted, logged and propagated when EXEC runs them
+    let queued = client.in_multi
+        && !matches!(
+            cmd_name.as_str(),
+            "EXEC" | "DISCARD" | "MULTI" | "WATCH" | "UNWATCH"
+        );
+    // Refuse writes when too few healthy replicas are attached
     if is_write && !client.is_replication_client {
+        let (min_replicas, max_lag) = {
+            let cfg = config.read().await;
+            (cfg.min_replicas_to_write, cfg.min_replicas_max_lag)
+        };
+        if min_replicas > 0 && max_lag > 0 {
+            let state = repl_state.read().await;
+            if state.role == ReplicationRole::Master && state.good_replicas(max_lag) < min_replicas
+            {
+                return command::reject_call(
+                    latency,
+                    &cmd_name,
+                    "NOREPLICAS Not enough good replicas to write.",
+                );
+            }
+        }
+    }
+
+    // Log write commands to AOF before executing (skip for replication clients).
+    // A replica's AOF mirrors the master stream, which carries its own SELECTs
+    if is_write && !queued && !client.is_replication_client && (!is_replica || cmd_name != "SELECT")
+    {
         let mut aof = aof.lock().await;
         if aof.is_active() {
             let _ = aof.log_command(&cmd_name, args);
@@ -576,6 +899,58 @@ async fn process_command(
         change_counter.fetch_add(1, Ordering::Relaxed);
     }
 
+    let started = std::time::Instant::now();
+    if cmd_name == "FAILOVER" && !client.in_multi {
+        let response = cmd_failover(
+            args,
+            store,
+            config,
+            aof,
+            repl_state,
+            pubsub,
+            key_watcher,
+            script_cache,
+            last_save_time,
+            slowlog,
+            latency,
+        )
+        .await;
+        command::record_call(latency, &cmd_name, started.elapsed(), &response);
+        return response;
+    }
+
+    // REPLICAOF starts or stops the sync task, which needs the server's handles
+    if matches!(cmd_name.as_str(), "REPLICAOF" | "SLAVEOF") && !client.in_multi {
+        let response = cmd_replicaof(
+            args,
+            store,
+            config,
+            aof,
+            repl_state,
+            pubsub,
+            key_watcher,
+            script_cache,
+            last_save_time,
+            slowlog,
+            latency,
+        )
+        .await;
+        command::record_call(latency, &cmd_name, started.elapsed(), &response);
+        return response;
+    }
+
+    // The writes of a transaction, logged and propagated once it has run
+    let exec_writes: Vec<(String, Vec<RespValue>)> = if cmd_name == "EXEC" && client.in_multi {
+        client
+            .multi_queue
+            .iter()
+            .filter(|(name, args)| writes_keyspace(name, args))
+            .cloned()
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let exec_db = client.db_index;
     let response = command::dispatch(
         &cmd_name,
         args,
@@ -589,88 +964,369 @@ async fn process_command(
         repl_state,
         last_save_time,
         slowlog,
+        latency,
     )
     .await;
+    match &response {
+        RespValue::Error(e) if queued => latency.lock().unwrap().record_error(e),
+        _ if queued => {}
+        _ => command::record_call(latency, &cmd_name, started.elapsed(), &response),
+    }
 
     // Touch modified keys for WATCH support
-    if is_write {
-        let mut store_guard = store.write().await;
-        let db = store_guard.db(client.db_index);
-        match cmd_name.as_str() {
-            "FLUSHDB" | "FLUSHALL" | "SWAPDB" => {
-                db.touch_all();
+    if is_write && !queued {
+        touch_written_keys(store, client.db_index, &cmd_name, args).await;
+    }
+    let exec_ran = matches!(response, RespValue::Array(Some(_)));
+    if exec_ran {
+        let mut db_index = exec_db;
+        for (name, args) in &exec_writes {
+            if name == "SELECT"
+                && let Some(index) = args.first().and_then(|a| a.to_string_lossy())
+            {
+                db_index = index.parse().unwrap_or(db_index);
+                continue;
             }
-            "RENAME" | "RENAMENX" => {
-                if let Some(k) = args.first().and_then(|a| a.to_string_lossy()) {
-                    db.touch(&k);
-                }
-                if let Some(k) = args.get(1).and_then(|a| a.to_string_lossy()) {
-                    db.touch(&k);
-                }
+            touch_written_keys(store, db_index, name, args).await;
+        }
+    }
+
+    // A transaction that ran is logged now, and reaches replicas as MULTI ... EXEC
+    let propagated: Vec<RespValue> = if client.is_replication_client {
+        Vec::new()
+    } else if is_write && !queued {
+        let mut cmd_parts = vec![items[0].clone()];
+        cmd_parts.extend_from_slice(args);
+        vec![RespValue::Array(Some(cmd_parts))]
+    } else if exec_ran && !exec_writes.is_empty() {
+        let command = |name: &str, args: &[RespValue]| {
+            let mut cmd_parts = vec![RespValue::bulk_string(name.as_bytes().to_vec())];
+            cmd_parts.extend_from_slice(args);
+            RespValue::Array(Some(cmd_parts))
+        };
+        let mut aof = aof.lock().await;
+        let mut commands = vec![command("MULTI", &[])];
+        for (name, args) in &exec_writes {
+            if aof.is_active() {
+                let _ = aof.log_command(name, args);
             }
-            "DEL" | "UNLINK" => {
-                for a in args {
-                    if let Some(k) = a.to_string_lossy() {
-                        // Only touch keys that were previously written to,
-                        // so DEL on a non-existent key doesn't create a
-                        // spurious version entry that breaks WATCH.
-                        if db.key_version(&k) > 0 {
-                            db.touch(&k);
-                        }
-                    }
-                }
+            change_counter.fetch_add(1, Ordering::Relaxed);
+            commands.push(command(name, args));
+        }
+        commands.push(command("EXEC", &[]));
+        commands
+    } else {
+        Vec::new()
+    };
+
+    // Replicate write commands to connected replicas (skip for replication clients).
+    // The offset advances even without replicas so WAITAOF can track local fsyncs.
+    if !propagated.is_empty() {
+        let mut state = repl_state.write().await;
+        if state.role == ReplicationRole::Master {
+            // Serialize the commands to RESP
+            let serialized: Vec<u8> = propagated.iter().flat_map(|c| c.serialize()).collect();
+
+            if !state.replicas.is_empty() {
+                state.ensure_backlog({
+                    let cfg = config.read().await;
+                    cfg.repl_backlog_size
+                });
             }
-            "MSET" | "MSETNX" => {
-                for i in (0..args.len()).step_by(2) {
-                    if let Some(k) = args[i].to_string_lossy() {
-                        db.touch(&k);
-                    }
-                }
+            state.feed_backlog(&serialized);
+            state.propagate_to_replicas(&serialized);
+            client.woff = state.master_repl_offset;
+        }
+        drop(state);
+
+        let fsynced = {
+            let mut aof = aof.lock().await;
+            aof.is_active().then(|| {
+                aof.advance_reploff(client.woff);
+                aof.fsynced_reploff()
+            })
+        };
+        // Only appendfsync always makes the write durable immediately
+        if let Some(fsynced) = fsynced
+            && fsynced >= client.woff
+        {
+            repl_state.write().await.record_aof_fsync(fsynced);
+        }
+    }
+
+    response
+}
+
+/// REPLICAOF host port | REPLICAOF NO ONE
+#[allow(clippy::too_many_arguments)]
+async fn cmd_replicaof(
+    args: &[RespValue],
+    store: &SharedStore,
+    config: &SharedConfig,
+    aof: &SharedAofWriter,
+    repl_state: &SharedReplicationState,
+    pubsub: &SharedPubSub,
+    key_watcher: &SharedKeyWatcher,
+    script_cache: &ScriptCache,
+    last_save_time: &SharedLastSaveTime,
+    slowlog: &SharedSlowLog,
+    latency: &SharedLatencyMonitor,
+) -> RespValue {
+    if args.len() != 2 {
+        return RespValue::error("ERR wrong number of arguments for 'replicaof' command");
+    }
+    let (Some(host), Some(port_str)) = (args[0].to_string_lossy(), args[1].to_string_lossy())
+    else {
+        return RespValue::error("ERR invalid host");
+    };
+
+    if host.eq_ignore_ascii_case("NO") && port_str.eq_ignore_ascii_case("ONE") {
+        let mut state = repl_state.write().await;
+        if state.role == ReplicationRole::Replica {
+            state.become_master();
+            info!("MASTER MODE enabled (replid2 {})", state.master_replid2);
+        }
+        drop(state);
+        config.write().await.replicaof = None;
+        return RespValue::ok();
+    }
+
+    let port: u16 = match port_str.parse() {
+        Ok(p) => p,
+        Err(_) => return RespValue::error("ERR Invalid master port"),
+    };
+
+    let cancel = tokio_util::sync::CancellationToken::new();
+    {
+        let mut state = repl_state.write().await;
+        if state.role == ReplicationRole::Replica
+            && state.master_host.as_deref() == Some(host.as_str())
+            && state.master_port == Some(port)
+        {
+            return RespValue::SimpleString("OK Already connected to specified master".to_string());
+        }
+        if let Some(old) = state.cancel.replace(cancel.clone()) {
+            old.cancel();
+        }
+        // Our dataset is about to follow the new master: drop our replicas so
+        // they reconnect and resync with the new history
+        state.replicas.clear();
+        state.role = ReplicationRole::Replica;
+        state.master_host = Some(host.clone());
+        state.master_port = Some(port);
+        state.master_link_status = "down".to_string();
+    }
+    config.write().await.replicaof = Some((host.clone(), port));
+    info!("REPLICAOF {host}:{port} enabled");
+
+    spawn_replica_sync(
+        host,
+        port,
+        cancel,
+        store,
+        config,
+        aof,
+        repl_state,
+        pubsub,
+        key_watcher,
+        script_cache,
+        last_save_time,
+        slowlog,
+        latency,
+    );
+    RespValue::ok()
+}
+
+/// FAILOVER [TO host port [FORCE]] [ABORT] [TIMEOUT ms]
+///
+/// Pauses writes, waits for a replica to acknowledge our whole stream, then
+/// demotes us to its replica with a `PSYNC ... FAILOVER` handshake that makes
+/// it promote itself. Replies right away; progress shows in INFO replication.
+#[allow(clippy::too_many_arguments)]
+async fn cmd_failover(
+    args: &[RespValue],
+    store: &SharedStore,
+    config: &SharedConfig,
+    aof: &SharedAofWriter,
+    repl_state: &SharedReplicationState,
+    pubsub: &SharedPubSub,
+    key_watcher: &SharedKeyWatcher,
+    script_cache: &ScriptCache,
+    last_save_time: &SharedLastSaveTime,
+    slowlog: &SharedSlowLog,
+    latency: &SharedLatencyMonitor,
+) -> RespValue {
+    let mut target: Option<(String, u16)> = None;
+    let mut force = false;
+    let mut abort = false;
+    let mut timeout_ms = 0u64;
+    let mut i = 0;
+    while i < args.len() {
+        let opt = args[i].to_string_lossy().unwrap_or_default().to_uppercase();
+        match opt.as_str() {
+            "TO" if i + 2 < args.len() && target.is_none() => {
+                let host = args[i + 1].to_string_lossy().unwrap_or_default();
+                let Some(port) = args[i + 2]
+                    .to_string_lossy()
+                    .and_then(|s| s.parse::<u16>().ok())
+                else {
+                    return RespValue::error("ERR Invalid port");
+                };
+                target = Some((host, port));
+                i += 3;
             }
-            "SORT" | "SORT_RO" => {
-                // Only touch the STORE destination key (the key being written to).
-                // Don't touch the source key — SORT only reads from it.
-                let mut i = 1;
-                while i < args.len() {
-                    if let Some(opt) = args[i].to_string_lossy()
-                        && opt.eq_ignore_ascii_case("STORE")
-                    {
-                        if let Some(dest) = args.get(i + 1).and_then(|a| a.to_string_lossy()) {
-                            db.touch(&dest);
-                        }
-                        break;
-                    }
-                    i += 1;
+            "TIMEOUT" if i + 1 < args.len() => {
+                match args[i + 1]
+                    .to_string_lossy()
+                    .and_then(|s| s.parse::<i64>().ok())
+                {
+                    Some(ms) if ms > 0 => timeout_ms = ms as u64,
+                    _ => return RespValue::error("ERR FAILOVER timeout must be greater than 0"),
                 }
+                i += 2;
             }
-            _ => {
-                if let Some(k) = args.first().and_then(|a| a.to_string_lossy()) {
-                    db.touch(&k);
+            "FORCE" => {
+                force = true;
+                i += 1;
+            }
+            "ABORT" => {
+                abort = true;
+                i += 1;
+            }
+            _ => return RespValue::error("ERR syntax error"),
+        }
+    }
+
+    let mut state = repl_state.write().await;
+    if abort {
+        if target.is_some() || force || timeout_ms > 0 {
+            return RespValue::error("ERR syntax error");
+        }
+        match state.failover_state {
+            FailoverState::NoFailover => {
+                return RespValue::error("ERR FAILOVER is not in progress.");
+            }
+            FailoverState::WaitingForSync => {
+                if let Some(token) = state.failover_abort.take() {
+                    token.cancel();
                 }
+                state.end_failover();
+            }
+            FailoverState::FailoverInProgress => {
+                state.abort_failover();
+                drop(state);
+                config.write().await.replicaof = None;
             }
         }
+        info!("FAILOVER aborted");
+        return RespValue::ok();
     }
 
-    // Replicate write commands to connected replicas (skip for replication clients)
-    if is_write && !client.is_replication_client {
-        let mut state = repl_state.write().await;
-        if state.role == ReplicationRole::Master && !state.replicas.is_empty() {
-            // Serialize the command to RESP
-            let mut cmd_parts = vec![items[0].clone()];
-            cmd_parts.extend_from_slice(args);
-            let resp_cmd = RespValue::Array(Some(cmd_parts));
-            let serialized = resp_cmd.serialize();
+    if state.role == ReplicationRole::Replica {
+        return RespValue::error("ERR FAILOVER is not valid when server is a replica.");
+    }
+    if state.failover_state != FailoverState::NoFailover {
+        return RespValue::error("ERR FAILOVER already in progress.");
+    }
+    if force && (target.is_none() || timeout_ms == 0) {
+        return RespValue::error(
+            "ERR FAILOVER with force option requires both a timeout and target HOST and IP.",
+        );
+    }
+    if state.connected_slaves() == 0 {
+        return RespValue::error("ERR FAILOVER requires connected replicas.");
+    }
+    if let Some((host, port)) = &target
+        && !state.replicas.iter().any(|r| {
+            r.state == crate::replication::ReplicaState::Online
+                && r.addr == *host
+                && r.port == *port
+        })
+    {
+        return RespValue::error("ERR FAILOVER target HOST and PORT is not a replica.");
+    }
 
-            state.ensure_backlog({
-                let cfg = config.read().await;
-                cfg.repl_backlog_size
-            });
-            state.feed_backlog(&serialized);
-            state.propagate_to_replicas(&serialized);
-        }
+    let abort_token = tokio_util::sync::CancellationToken::new();
+    state.failover_state = FailoverState::WaitingForSync;
+    state.failover_abort = Some(abort_token.clone());
+    let gate = state.write_gate.clone();
+    drop(state);
+
+    // Pause writes once those already running have been propagated
+    let pause = gate.write_owned().await;
+    let mut state = repl_state.write().await;
+    if abort_token.is_cancelled() {
+        return RespValue::ok();
     }
+    state.failover_pause = Some(pause);
+    state.request_acks();
+    drop(state);
+    info!("FAILOVER requested, waiting for a replica to catch up");
 
-    response
+    let store = store.clone();
+    let config = config.clone();
+    let aof = aof.clone();
+    let repl_state = repl_state.clone();
+    let pubsub = pubsub.clone();
+    let key_watcher = key_watcher.clone();
+    let script_cache = script_cache.clone();
+    let last_save_time = last_save_time.clone();
+    let slowlog = slowlog.clone();
+    let latency = latency.clone();
+    tokio::spawn(async move {
+        let target_ref = target.as_ref().map(|(host, port)| (host.as_str(), *port));
+        tokio::select! {
+            _ = crate::replication::wait_for_acks(&repl_state, timeout_ms, |s| {
+                s.failover_candidate(target_ref).is_some()
+            }) => {}
+            _ = abort_token.cancelled() => return,
+        }
+
+        let cancel = tokio_util::sync::CancellationToken::new();
+        let (host, port) = {
+            let mut state = repl_state.write().await;
+            if abort_token.is_cancelled() {
+                return;
+            }
+            let chosen = state
+                .failover_candidate(target_ref)
+                .or_else(|| target.clone().filter(|_| force));
+            let Some((host, port)) = chosen else {
+                tracing::warn!("FAILOVER timed out waiting for a replica to catch up");
+                state.end_failover();
+                return;
+            };
+            // Become a replica of the target; the sync task's PSYNC carries
+            // FAILOVER and ends the pause once the target has taken over
+            state.failover_state = FailoverState::FailoverInProgress;
+            state.failover_abort = None;
+            state.role = ReplicationRole::Replica;
+            state.master_host = Some(host.clone());
+            state.master_port = Some(port);
+            state.master_link_status = "down".to_string();
+            state.cancel = Some(cancel.clone());
+            (host, port)
+        };
+        config.write().await.replicaof = Some((host.clone(), port));
+        info!("FAILOVER to {host}:{port} in progress");
+
+        spawn_replica_sync(
+            host,
+            port,
+            cancel,
+            &store,
+            &config,
+            &aof,
+            &repl_state,
+            &pubsub,
+            &key_watcher,
+            &script_cache,
+            &last_save_time,
+            &slowlog,
+            &latency,
+        );
+    });
+    RespValue::ok()
 }
 
 async fn cleanup_client(pubsub: &SharedPubSub, client: &ClientState) {
@@ -679,7 +1335,11 @@ async fn cleanup_client(pubsub: &SharedPubSub, client: &ClientState) {
 }
 
 /// Background task that periodically expires keys.
-async fn active_expiration_loop(store: SharedStore, config: SharedConfig) {
+async fn active_expiration_loop(
+    store: SharedStore,
+    config: SharedConfig,
+    latency: SharedLatencyMonitor,
+) {
     loop {
         let hz = {
             let cfg = config.read().await;
@@ -689,24 +1349,43 @@ async fn active_expiration_loop(store: SharedStore, config: SharedConfig) {
 
         tokio::time::sleep(interval).await;
 
-        {
+        let (hz, effort) = {
             let cfg = config.read().await;
             if !cfg.active_expire_enabled {
                 continue;
             }
-        }
+            (cfg.hz, cfg.active_expire_effort)
+        };
 
-        let mut store = store.write().await;
-        store.active_expire_cycle();
+        let start = std::time::Instant::now();
+        store.active_expire_cycle(hz, effort).await;
+        latency::monitor(&latency, &config, latency::EXPIRE_CYCLE, start.elapsed()).await;
     }
 }
 
-/// Background task that flushes AOF every second (for everysec policy).
-async fn aof_fsync_loop(aof: SharedAofWriter) {
+/// Background task that fsyncs the AOF every second (for everysec policy)
+/// and publishes the durable replication offset for WAITAOF.
+async fn aof_fsync_loop(
+    aof: SharedAofWriter,
+    repl_state: SharedReplicationState,
+    config: SharedConfig,
+    latency: SharedLatencyMonitor,
+) {
     loop {
         tokio::time::sleep(Duration::from_secs(1)).await;
-        let mut aof = aof.lock().await;
-        let _ = aof.flush();
+        let start = std::time::Instant::now();
+        let fsynced = {
+            let mut aof = aof.lock().await;
+            if !aof.is_active() {
+                continue;
+            }
+            aof.fsync()
+        };
+        latency::monitor(&latency, &config, latency::AOF_FSYNC, start.elapsed()).await;
+        match fsynced {
+            Ok(offset) => repl_state.write().await.record_aof_fsync(offset),
+            Err(e) => tracing::warn!("AOF fsync failed: {e}"),
+        }
     }
 }
 
@@ -716,6 +1395,7 @@ async fn auto_save_loop(
     config: SharedConfig,
     changes: SharedChangeCounter,
     last_save_time: SharedLastSaveTime,
+    latency: SharedLatencyMonitor,
 ) {
     let mut last_save = std::time::Instant::now();
     loop {
@@ -736,9 +1416,12 @@ async fn auto_save_loop(
             .any(|(secs, min_changes)| elapsed >= *secs && current_changes >= *min_changes);
 
         if should_save && current_changes > 0 {
-            let store = store.read().await;
+            let mut store = store.lock_all().await;
             let path = format!("{dir}/{dbfilename}");
-            if let Err(e) = crate::persistence::rdb::save(&store, &path) {
+            let start = std::time::Instant::now();
+            let saved = crate::persistence::rdb::save(&mut store, &path);
+            latency::monitor(&latency, &config, latency::RDB_SAVE, start.elapsed()).await;
+            if let Err(e) = saved {
                 tracing::warn!("Auto-save failed: {e}");
             } else {
                 let now = std::time::SystemTime::now()
@@ -756,7 +1439,11 @@ async fn auto_save_loop(
 }
 
 /// Background task that evicts keys when memory usage exceeds maxmemory.
-async fn memory_eviction_loop(store: SharedStore, config: SharedConfig) {
+async fn memory_eviction_loop(
+    store: SharedStore,
+    config: SharedConfig,
+    latency: SharedLatencyMonitor,
+) {
     loop {
         tokio::time::sleep(Duration::from_millis(100)).await;
         let (maxmemory, policy) = {
@@ -766,39 +1453,28 @@ async fn memory_eviction_loop(store: SharedStore, config: SharedConfig) {
         if maxmemory == 0 {
             continue; // No limit set
         }
-        let mut store = store.write().await;
-        let used = store.estimated_memory();
-        if used <= maxmemory as usize {
+        if store.used_memory() <= maxmemory as usize {
             continue;
         }
-        // Evict keys until under limit or no more keys
+        let evict: fn(&mut Database<'_>) -> bool = match policy.as_str() {
+            "allkeys-random" => |db| db.evict_one_random(),
+            "volatile-random" => |db| db.evict_one_volatile_random(),
+            "volatile-ttl" => |db| db.evict_one_volatile_ttl(),
+            "allkeys-lru" => |db| db.evict_one_allkeys_lru(),
+            "volatile-lru" => |db| db.evict_one_volatile_lru(),
+            _ => continue, // noeviction - do nothing
+        };
+        let start = std::time::Instant::now();
+        // Evict keys until under limit or no more keys, one shard at a time
         for _ in 0..10 {
-            let evicted = match policy.as_str() {
-                "allkeys-random" => store.databases.iter_mut().any(|db| db.evict_one_random()),
-                "volatile-random" => store
-                    .databases
-                    .iter_mut()
-                    .any(|db| db.evict_one_volatile_random()),
-                "volatile-ttl" => store
-                    .databases
-                    .iter_mut()
-                    .any(|db| db.evict_one_volatile_ttl()),
-                "allkeys-lru" => store
-                    .databases
-                    .iter_mut()
-                    .any(|db| db.evict_one_allkeys_lru()),
-                "volatile-lru" => store
-                    .databases
-                    .iter_mut()
-                    .any(|db| db.evict_one_volatile_lru()),
-                _ => false, // noeviction - do nothing
-            };
-            if !evicted {
+            if !store.evict_one(evict).await {
                 break;
             }
-            if store.estimated_memory() <= maxmemory as usize {
+            store.stats.evicted_keys.fetch_add(1, Ordering::Relaxed);
+            if store.used_memory() <= maxmemory as usize {
                 break;
             }
         }
+        latency::monitor(&latency, &config, latency::EVICTION_CYCLE, start.elapsed()).await;
     }
 }
@@ -1,14 +1,26 @@
 use crate::types::RedisValue;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// An entry in the data store — wraps a value with metadata.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Entry {
     pub value: RedisValue,
     /// Expiry time as milliseconds since UNIX epoch. None = no expiry.
     pub expires_at: Option<u64>,
     /// Last access time in seconds since UNIX epoch (for LRU eviction / OBJECT IDLETIME).
-    pub last_access: u64,
+    /// Atomic so reads under a shard's read lock can refresh it.
+    last_access: AtomicU64,
+}
+
+impl Clone for Entry {
+    fn clone(&self) -> Self {
+        Entry {
+            value: self.value.clone(),
+            expires_at: self.expires_at,
+            last_access: AtomicU64::new(self.last_access()),
+        }
+    }
 }
 
 impl Entry {
@@ -16,7 +28,7 @@ impl Entry {
         Entry {
             value,
             expires_at: None,
-            last_access: now_seconds(),
+            last_access: AtomicU64::new(now_seconds()),
         }
     }
 
@@ -24,18 +36,23 @@ impl Entry {
         Entry {
             value,
             expires_at: Some(expires_at),
-            last_access: now_seconds(),
+            last_access: AtomicU64::new(now_seconds()),
         }
     }
 
     /// Update the last access time to now.
-    pub fn touch_access(&mut self) {
-        self.last_access = now_seconds();
+    pub fn touch_access(&self) {
+        self.last_access.store(now_seconds(), Ordering::Relaxed);
+    }
+
+    /// Last access time in seconds since UNIX epoch.
+    pub fn last_access(&self) -> u64 {
+        self.last_access.load(Ordering::Relaxed)
     }
 
     /// Return the idle time in seconds since last access.
     pub fn idle_seconds(&self) -> u64 {
-        now_seconds().saturating_sub(self.last_access)
+        now_seconds().saturating_sub(self.last_access())
     }
 
     /// Check if this entry has expired.
@@ -1,109 +1,357 @@
 pub mod entry;
 
 use crate::glob::glob_match;
-use crate::types::RedisValue;
+use crate::lazyfree::LazyFree;
+use crate::types::dict::Dict;
+use crate::types::memory;
 use entry::{Entry, now_millis};
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedMutexGuard, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
 
-/// A single Redis database (one of the 16 default databases).
+/// Hash partitions per database. Every shard has its own lock, so commands on
+/// keys in different shards run in parallel.
+pub const SHARDS_PER_DB: usize = 16;
+
+tokio::task_local! {
+    /// Shards locked up front by EXEC for all the commands it runs.
+    static HELD: Arc<Mutex<Vec<LockedShard>>>;
+}
+
+/// Shard of `key` in database `db`, as an index into the store's shard list.
+pub fn shard_id(db: usize, key: &str) -> usize {
+    // FNV-1a: cheap, and stable across runs
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    db * SHARDS_PER_DB + (hash % SHARDS_PER_DB as u64) as usize
+}
+
+/// Store-wide counters, shared by every shard.
+#[derive(Debug, Default)]
+pub struct StoreStats {
+    /// Tracks changes since last RDB save for INFO rdb_changes_since_last_save.
+    pub dirty: AtomicU64,
+    /// Total number of keys expired (lazy + active).
+    pub expired_keys: AtomicU64,
+    /// Number of keys expired by the active expiration background task.
+    pub expired_keys_active: AtomicU64,
+    /// Running average of the share of sampled volatile keys found expired
+    /// by the active expire cycle, stored as `f64` bits.
+    pub expired_stale_perc: AtomicU64,
+    /// Active expire cycles that stopped on their time budget.
+    pub expired_time_cap_reached_count: AtomicU64,
+    /// Microseconds spent in active expire cycles.
+    pub expire_cycle_time_used: AtomicU64,
+    /// Keys removed to stay under maxmemory.
+    pub evicted_keys: AtomicU64,
+    /// Clients currently connected, and all connections accepted so far.
+    pub connected_clients: AtomicU64,
+    pub total_connections_received: AtomicU64,
+}
+
+/// Figures a shard publishes whenever it is unlocked, so INFO, metrics and
+/// eviction can read them without taking any lock.
+#[derive(Debug, Default)]
+struct ShardGauges {
+    keys: AtomicUsize,
+    expires: AtomicUsize,
+    /// Bytes of the keyspace tables themselves.
+    overhead: AtomicUsize,
+    /// Estimated bytes of the keys and values. Only kept up to date when
+    /// the tracking allocator is not installed, as used memory comes
+    /// straight from the allocator otherwise.
+    dataset: AtomicUsize,
+}
+
+/// Entries of a shard measured to estimate its dataset size.
+const DATASET_SAMPLES: usize = 16;
+
+/// One hash partition of a database.
 #[derive(Debug)]
-pub struct Database {
-    data: HashMap<String, Entry>,
+pub struct Shard {
+    data: Dict<String, Entry>,
+    /// Keys of `data` that have a TTL, so active expiration only samples
+    /// volatile keys.
+    expires: Dict<String, ()>,
+    /// Where the next active expire pass resumes its scan of `expires`.
+    expires_cursor: u64,
     /// Monotonically increasing version counter for WATCH support.
     key_versions: HashMap<String, u64>,
     version_seq: u64,
-    /// Count of keys lazily expired since last drain.
-    pub lazy_expired_count: u64,
-}
-
-impl Default for Database {
-    fn default() -> Self {
-        Self::new()
-    }
+    stats: Arc<StoreStats>,
+    lazyfree: Arc<LazyFree>,
 }
 
-impl Database {
-    pub fn new() -> Self {
-        Database {
-            data: HashMap::new(),
+impl Shard {
+    fn new(stats: Arc<StoreStats>, lazyfree: Arc<LazyFree>) -> Self {
+        Shard {
+            data: Dict::new(),
+            expires: Dict::new(),
+            expires_cursor: 0,
             key_versions: HashMap::new(),
             version_seq: 0,
-            lazy_expired_count: 0,
+            stats,
+            lazyfree,
         }
     }
 
-    /// Bump the version of a key (called after writes for WATCH support).
-    pub fn touch(&mut self, key: &str) {
+    /// Store `entry` under `key`. A value it replaces is freed like a
+    /// server-side delete.
+    fn insert(&mut self, key: String, entry: Entry) {
+        if entry.expires_at.is_some() {
+            self.expires.insert(key.clone(), ());
+        } else {
+            self.expires.remove(&key);
+        }
+        if let Some(old) = self.data.insert(key, entry) {
+            self.free(old, self.lazyfree.lazy_server_del());
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.data.remove(key)?;
+        if entry.expires_at.is_some() {
+            self.expires.remove(key);
+        }
+        Some(entry)
+    }
+
+    /// Remove `key` and free its value, on the lazyfree thread if `lazy`.
+    fn delete(&mut self, key: &str, lazy: bool) -> bool {
+        match self.remove(key) {
+            Some(entry) => {
+                self.free(entry, lazy);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn free(&self, entry: Entry, lazy: bool) {
+        if lazy {
+            let effort = entry.value.free_effort();
+            self.lazyfree.free(entry, effort);
+        }
+    }
+
+    /// Remove every key, freeing them on the lazyfree thread if `lazy`. The
+    /// expires index and the WATCH versions go with them.
+    fn clear(&mut self, lazy: bool) {
+        if lazy {
+            let keys = self.data.len();
+            let tables = (
+                std::mem::take(&mut self.data),
+                std::mem::take(&mut self.expires),
+                std::mem::take(&mut self.key_versions),
+            );
+            self.lazyfree.free_keys(tables, keys);
+        } else {
+            self.data.clear();
+            self.expires.clear();
+            self.key_versions.clear();
+        }
+        self.expires_cursor = 0;
+        // Keys written from now on get versions past any a WATCH saw
+        self.version_seq += 1;
+    }
+
+    /// Remove `key` if its TTL has passed. Returns true if it was removed.
+    fn expire_if_needed(&mut self, key: &str) -> bool {
+        if self.data.get(key).is_some_and(|entry| entry.is_expired()) {
+            self.delete(key, self.lazyfree.lazy_expire());
+            self.stats.expired_keys.fetch_add(1, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn touch(&mut self, key: &str) {
         self.version_seq += 1;
         self.key_versions.insert(key.to_string(), self.version_seq);
     }
 
-    /// Bump the global version (for FLUSHDB/FLUSHALL).
-    pub fn touch_all(&mut self) {
+    fn touch_all(&mut self) {
         self.version_seq += 1;
         // Clear specific versions; any WATCH check will see version mismatch
         // since we increment version_seq past any stored version.
         self.key_versions.clear();
     }
 
-    /// Get the current version of a key (0 if never written).
-    pub fn key_version(&self, key: &str) -> u64 {
-        self.key_versions.get(key).copied().unwrap_or(0)
+    /// Check about `sample_size` volatile keys, carrying on the scan of the
+    /// expires index where the previous pass stopped, and remove the expired
+    /// ones. Returns (sampled, expired).
+    fn active_expire(&mut self, sample_size: usize) -> (usize, usize) {
+        if self.expires.is_empty() {
+            return (0, 0);
+        }
+        let now = now_millis();
+        let data = &self.data;
+        let mut sampled = 0;
+        let mut expired = Vec::new();
+        self.expires_cursor = self
+            .expires
+            .scan(self.expires_cursor, sample_size, |key, _| {
+                sampled += 1;
+                let entry = data.get(key).expect("expires index out of sync");
+                if entry.expires_at.is_some_and(|exp| now >= exp) {
+                    expired.push(key.clone());
+                }
+            });
+        let lazy = self.lazyfree.lazy_expire();
+        for key in &expired {
+            self.delete(key, lazy);
+        }
+        (sampled, expired.len())
+    }
+
+    /// Bytes of the keyspace tables, computed from their sizes alone. Key
+    /// names count towards the dataset, as in Redis.
+    fn overhead_memory(&self) -> usize {
+        memory::dict::<String, Entry>(self.data.capacity(), self.data.len())
+            + memory::dict::<String, ()>(self.expires.capacity(), self.expires.len())
+            + memory::hash_table::<String, u64>(self.key_versions.capacity())
+    }
+
+    fn publish(&self, gauges: &ShardGauges) {
+        gauges.keys.store(self.data.len(), Ordering::Relaxed);
+        gauges.expires.store(self.expires.len(), Ordering::Relaxed);
+        gauges
+            .overhead
+            .store(self.overhead_memory(), Ordering::Relaxed);
+        if crate::allocator::allocated().is_none() {
+            let dataset = memory::sampled(
+                self.data.iter(),
+                self.data.len(),
+                DATASET_SAMPLES,
+                |(key, entry)| key.capacity() + entry.value.memory_usage(DATASET_SAMPLES),
+            );
+            gauges.dataset.store(dataset, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A shard together with the write lock held on it. Its gauges are
+/// refreshed when the lock is released.
+#[derive(Debug)]
+struct LockedShard {
+    id: usize,
+    guard: OwnedRwLockWriteGuard<Shard>,
+    gauges: Arc<ShardGauges>,
+}
+
+impl Drop for LockedShard {
+    fn drop(&mut self) {
+        self.guard.publish(&self.gauges);
+    }
+}
+
+/// One Redis database (one of the 16 default databases), seen through the
+/// shards a [`KeyspaceGuard`] has locked. Using a key whose shard is not
+/// locked is a bug and panics.
+#[derive(Debug)]
+pub struct Database<'a> {
+    index: usize,
+    shards: &'a mut [LockedShard],
+}
+
+impl Database<'_> {
+    /// Index of this database.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    fn position(&self, key: &str) -> usize {
+        let id = shard_id(self.index, key);
+        self.shards
+            .binary_search_by_key(&id, |s| s.id)
+            .unwrap_or_else(|_| panic!("key '{key}' used without locking its shard"))
+    }
+
+    fn shard(&mut self, key: &str) -> &mut Shard {
+        let pos = self.position(key);
+        &mut self.shards[pos].guard
+    }
+
+    fn shard_ref(&self, key: &str) -> &Shard {
+        &self.shards[self.position(key)].guard
+    }
+
+    fn all_shards(&self) -> impl Iterator<Item = &Shard> {
+        self.shards.iter().map(|s| &*s.guard)
+    }
+
+    fn all_shards_mut(&mut self) -> impl Iterator<Item = &mut Shard> {
+        self.shards.iter_mut().map(|s| &mut *s.guard)
     }
 
-    /// Get the global version sequence (for touch_all detection).
-    pub fn global_version(&self) -> u64 {
-        self.version_seq
+    /// Bump the version of a key (called after writes for WATCH support).
+    pub fn touch(&mut self, key: &str) {
+        self.shard(key).touch(key);
+    }
+
+    /// Bump the version of every key (for FLUSHDB/FLUSHALL).
+    pub fn touch_all(&mut self) {
+        self.all_shards_mut().for_each(Shard::touch_all);
+    }
+
+    /// Get the current version of a key (0 if never written).
+    pub fn key_version(&self, key: &str) -> u64 {
+        self.shard_ref(key)
+            .key_versions
+            .get(key)
+            .copied()
+            .unwrap_or(0)
     }
 
     /// Get a value, performing lazy expiration and updating access time.
     pub fn get(&mut self, key: &str) -> Option<&Entry> {
-        // Lazy expiration
-        if self.is_expired(key) {
-            self.data.remove(key);
-            self.lazy_expired_count += 1;
-            return None;
-        }
-        // Update access time for LRU tracking
-        if let Some(entry) = self.data.get_mut(key) {
-            entry.touch_access();
-        }
-        self.data.get(key)
+        self.get_mut(key).map(|entry| &*entry)
     }
 
     /// Get a mutable value, performing lazy expiration and updating access time.
+    /// Change the TTL with [`set_expiry`](Self::set_expiry) or
+    /// [`persist`](Self::persist) rather than through the entry, so the
+    /// expires index stays in sync.
     pub fn get_mut(&mut self, key: &str) -> Option<&mut Entry> {
-        if self.is_expired(key) {
-            self.data.remove(key);
-            self.lazy_expired_count += 1;
+        let shard = self.shard(key);
+        if shard.expire_if_needed(key) {
             return None;
         }
-        if let Some(entry) = self.data.get_mut(key) {
-            entry.touch_access();
-        }
-        self.data.get_mut(key)
+        let entry = shard.data.get_mut(key)?;
+        entry.touch_access();
+        Some(entry)
     }
 
     /// Set a key-value pair.
     pub fn set(&mut self, key: String, entry: Entry) {
-        self.data.insert(key, entry);
+        self.shard(&key).insert(key, entry);
     }
 
-    /// Delete a key. Returns true if it existed.
+    /// Delete a key as a side effect of a command, freeing it lazily with
+    /// lazyfree-lazy-server-del. Returns true if it existed.
     pub fn del(&mut self, key: &str) -> bool {
-        self.data.remove(key).is_some()
+        let shard = self.shard(key);
+        let lazy = shard.lazyfree.lazy_server_del();
+        shard.delete(key, lazy)
+    }
+
+    /// Delete a key, freeing its value on the lazyfree thread if `lazy` and
+    /// the value is large. Returns true if it existed.
+    pub fn delete(&mut self, key: &str, lazy: bool) -> bool {
+        self.shard(key).delete(key, lazy)
     }
 
     /// Check if a key exists (with lazy expiration).
     pub fn exists(&mut self, key: &str) -> bool {
-        if self.is_expired(key) {
-            self.data.remove(key);
-            self.lazy_expired_count += 1;
-            return false;
-        }
-        self.data.contains_key(key)
+        let shard = self.shard(key);
+        !shard.expire_if_needed(key) && shard.data.contains_key(key)
     }
 
     /// Get the type of a key.
@@ -113,19 +361,23 @@ impl Database {
 
     /// Rename a key.
     pub fn rename(&mut self, old: &str, new: &str) -> bool {
-        if let Some(entry) = self.data.remove(old) {
-            self.data.insert(new.to_string(), entry);
+        if let Some(entry) = self.shard(old).remove(old) {
+            self.shard(new).insert(new.to_string(), entry);
             true
         } else {
             false
         }
     }
 
+    /// Iterate over every key and entry, expired ones included.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.all_shards().flat_map(|shard| shard.data.iter())
+    }
+
     /// Get all keys matching a pattern.
     pub fn keys(&self, pattern: &str) -> Vec<String> {
         let now = now_millis();
-        self.data
-            .iter()
+        self.iter()
             .filter(|(_, entry)| entry.expires_at.is_none_or(|exp| now < exp))
             .filter(|(key, _)| glob_match(pattern, key))
             .map(|(key, _)| key.clone())
@@ -134,89 +386,84 @@ impl Database {
 
     /// Scan with cursor-based iteration.
     /// Returns (next_cursor, keys).
-    pub fn scan(
-        &mut self,
-        cursor: usize,
-        pattern: Option<&str>,
-        count: usize,
-    ) -> (usize, Vec<String>) {
+    pub fn scan(&mut self, cursor: u64, pattern: Option<&str>, count: usize) -> (u64, Vec<String>) {
         self.scan_with_type(cursor, pattern, count, None)
     }
 
     /// Scan with cursor-based iteration and optional type filter.
     /// Lazily expires keys that match the pattern and are expired.
+    ///
+    /// The cursor packs the shard slot in its low bits and that shard's
+    /// [`Dict::scan`] cursor above them, so each call visits about `count`
+    /// entries and keys present for the whole iteration are always returned.
+    /// Needs every shard of the database locked.
     pub fn scan_with_type(
         &mut self,
-        cursor: usize,
+        cursor: u64,
         pattern: Option<&str>,
         count: usize,
         type_filter: Option<&str>,
-    ) -> (usize, Vec<String>) {
+    ) -> (u64, Vec<String>) {
+        const SLOTS: u64 = SHARDS_PER_DB as u64;
         let now = now_millis();
+        let mut slot = (cursor % SLOTS) as usize;
+        let mut table_cursor = cursor / SLOTS;
+        let mut results = Vec::new();
+        let mut visited = 0;
 
-        // Lazily delete expired keys that match the scan pattern
-        // Do this BEFORE building the cursor list for stability
-        {
-            let expired_matches: Vec<String> = self
-                .data
-                .iter()
-                .filter(|(key, entry)| {
-                    entry.expires_at.is_some_and(|exp| now >= exp)
-                        && pattern.is_none_or(|pat| glob_match(pat, key))
-                })
-                .map(|(key, _)| key.clone())
-                .collect();
-            for key in expired_matches {
-                self.data.remove(&key);
+        while slot < self.shards.len() {
+            debug_assert_eq!(self.shards[slot].id, self.index * SHARDS_PER_DB + slot);
+            let shard = &mut *self.shards[slot].guard;
+            let mut expired = Vec::new();
+            table_cursor =
+                shard
+                    .data
+                    .scan(table_cursor, count.saturating_sub(visited), |key, entry| {
+                        visited += 1;
+                        if !pattern.is_none_or(|p| glob_match(p, key)) {
+                            return;
+                        }
+                        if entry.expires_at.is_some_and(|exp| now >= exp) {
+                            expired.push(key.clone());
+                        } else if type_filter
+                            .is_none_or(|t| entry.value.type_name().eq_ignore_ascii_case(t))
+                        {
+                            results.push(key.clone());
+                        }
+                    });
+            for key in expired {
+                shard.expire_if_needed(&key);
             }
-        }
-
-        // Build sorted key list from non-expired keys for deterministic cursor
-        let mut all_keys: Vec<&String> = self
-            .data
-            .iter()
-            .filter(|(_, entry)| entry.expires_at.is_none_or(|exp| now < exp))
-            .map(|(key, _)| key)
-            .collect();
-        all_keys.sort();
-
-        let total = all_keys.len();
-        if total == 0 || cursor >= total {
-            return (0, vec![]);
-        }
-
-        let mut results = Vec::new();
-        let mut i = cursor;
-        let mut scanned = 0;
-
-        while i < total && scanned < count {
-            let key = all_keys[i];
-            let matches_pattern = pattern.is_none_or(|p| glob_match(p, key));
-            let matches_type = type_filter.is_none_or(|t| {
-                self.data
-                    .get(key)
-                    .is_some_and(|entry| entry.value.type_name().eq_ignore_ascii_case(t))
-            });
-            if matches_pattern && matches_type {
-                results.push(key.clone());
+            // A shard left part-way has hit either `count` or its bucket
+            // budget; resume there on the next call
+            if table_cursor != 0 {
+                break;
+            }
+            slot += 1;
+            if visited >= count {
+                break;
             }
-            i += 1;
-            scanned += 1;
         }
 
-        let next_cursor = if i >= total { 0 } else { i };
-        (next_cursor, results)
+        if slot >= self.shards.len() {
+            (0, results)
+        } else {
+            (table_cursor * SLOTS + slot as u64, results)
+        }
     }
 
     /// Get the expiry timestamp of a key, if any.
     pub fn get_expiry(&self, key: &str) -> Option<u64> {
-        self.data.get(key).and_then(|e| e.expires_at)
+        self.shard_ref(key).data.get(key).and_then(|e| e.expires_at)
     }
 
     /// Set expiry on a key. Returns true if the key exists.
     pub fn set_expiry(&mut self, key: &str, expires_at: u64) -> bool {
-        if let Some(entry) = self.data.get_mut(key) {
-            entry.expires_at = Some(expires_at);
+        let shard = self.shard(key);
+        if let Some(entry) = shard.data.get_mut(key) {
+            if entry.expires_at.replace(expires_at).is_none() {
+                shard.expires.insert(key.to_string(), ());
+            }
             true
         } else {
             false
@@ -225,10 +472,12 @@ impl Database {
 
     /// Remove expiry from a key. Returns true if the key had an expiry.
     pub fn persist(&mut self, key: &str) -> bool {
-        if let Some(entry) = self.data.get_mut(key)
+        let shard = self.shard(key);
+        if let Some(entry) = shard.data.get_mut(key)
             && entry.expires_at.is_some()
         {
             entry.expires_at = None;
+            shard.expires.remove(key);
             return true;
         }
         false
@@ -236,31 +485,12 @@ impl Database {
 
     /// Number of keys in the database (includes expired keys not yet removed).
     pub fn dbsize(&self) -> usize {
-        self.data.len()
+        self.all_shards().map(|shard| shard.data.len()).sum()
     }
 
-    /// Flush all data.
-    pub fn flush(&mut self) {
-        self.data.clear();
-    }
-
-    /// Run active expiration: sample random keys and remove expired ones.
-    /// Returns the number of keys removed.
-    pub fn active_expire(&mut self, sample_size: usize) -> usize {
-        let now = now_millis();
-        let expired_keys: Vec<String> = self
-            .data
-            .iter()
-            .filter(|(_, entry)| entry.expires_at.is_some_and(|exp| now >= exp))
-            .take(sample_size)
-            .map(|(key, _)| key.clone())
-            .collect();
-
-        let count = expired_keys.len();
-        for key in expired_keys {
-            self.data.remove(&key);
-        }
-        count
+    /// Flush all data, freeing it on the lazyfree thread if `lazy`.
+    pub fn flush(&mut self, lazy: bool) {
+        self.all_shards_mut().for_each(|shard| shard.clear(lazy));
     }
 
     /// Get a random key.
@@ -268,8 +498,7 @@ impl Database {
         use rand::seq::IteratorRandom;
         let mut rng = rand::thread_rng();
         let now = now_millis();
-        self.data
-            .iter()
+        self.iter()
             .filter(|(_, entry)| entry.expires_at.is_none_or(|exp| now < exp))
             .map(|(key, _)| key.clone())
             .choose(&mut rng)
@@ -277,7 +506,7 @@ impl Database {
 
     /// Get an entry without lazy expiration (for read-only inspection like MEMORY USAGE).
     pub fn get_entry(&self, key: &str) -> Option<&Entry> {
-        let entry = self.data.get(key)?;
+        let entry = self.shard_ref(key).data.get(key)?;
         if entry.is_expired() {
             None
         } else {
@@ -286,226 +515,842 @@ impl Database {
     }
 
     pub fn is_expired(&self, key: &str) -> bool {
-        self.data.get(key).is_some_and(|entry| entry.is_expired())
+        self.shard_ref(key)
+            .data
+            .get(key)
+            .is_some_and(|entry| entry.is_expired())
     }
 
     /// Check if a key exists and is not expired (i.e., logically alive).
     pub fn key_alive(&self, key: &str) -> bool {
-        self.data.get(key).is_some_and(|entry| !entry.is_expired())
-    }
-
-    /// Get keys with expiry info for persistence
-    pub fn iter(&self) -> impl Iterator<Item = (&String, &Entry)> {
-        self.data.iter()
+        self.shard_ref(key)
+            .data
+            .get(key)
+            .is_some_and(|entry| !entry.is_expired())
     }
 
     /// Number of keys with expiry set
     pub fn expires_count(&self) -> usize {
-        self.data
-            .values()
-            .filter(|e| e.expires_at.is_some())
-            .count()
-    }
-
-    /// Estimate memory usage of this database in bytes.
-    pub fn estimated_memory(&self) -> usize {
-        let mut total = 0usize;
-        for (key, entry) in &self.data {
-            // Key string bytes
-            total += key.len();
-            // Entry overhead (struct + Option<u64>)
-            total += 48;
-            // Value size estimate
-            total += match &entry.value {
-                RedisValue::String(s) => s.len(),
-                RedisValue::List(l) => {
-                    let element_bytes: usize = l.iter().map(|v| v.len()).sum();
-                    64 * l.len() + element_bytes
-                }
-                RedisValue::Hash(h) => {
-                    let field_bytes: usize = h.iter().map(|(k, v)| k.len() + v.len()).sum();
-                    96 * h.len() + field_bytes
-                }
-                RedisValue::Set(s) => {
-                    let member_bytes: usize = s.iter().map(|m| m.len()).sum();
-                    64 * s.len() + member_bytes
-                }
-                RedisValue::SortedSet(z) => {
-                    let member_bytes: usize = z.iter().map(|(m, _)| m.len()).sum();
-                    96 * z.len() + member_bytes
-                }
-                RedisValue::Stream(s) => {
-                    // Rough estimate: each entry has an ID (16 bytes) + fields
-                    128 * s.len()
-                }
-                RedisValue::HyperLogLog(_) => {
-                    // HyperLogLog uses a fixed 12KB register set
-                    12304
-                }
-                RedisValue::Geo(g) => g.estimated_memory(),
-            };
+        self.all_shards().map(|shard| shard.expires.len()).sum()
+    }
+
+    /// Bytes used by one key: its node in the keyspace table, the key name
+    /// and the value, measuring at most `samples` elements of an aggregate
+    /// (0 = all of them).
+    pub fn key_memory_usage(&self, key: &str, samples: usize) -> Option<usize> {
+        let (key, entry) = self.shard_ref(key).data.get_key_value(key)?;
+        Some(
+            memory::dict::<String, Entry>(0, 1)
+                + key.capacity()
+                + entry.value.memory_usage(samples),
+        )
+    }
+
+    /// Remove the key picked by `choose`, if any. Returns true if a key was evicted.
+    fn evict(&mut self, choose: impl FnOnce(&Self) -> Option<String>) -> bool {
+        match choose(self) {
+            Some(key) => {
+                let shard = self.shard(&key);
+                let lazy = shard.lazyfree.lazy_eviction();
+                shard.delete(&key, lazy)
+            }
+            None => false,
         }
-        total
     }
 
     /// Evict one random key. Returns true if a key was evicted.
     pub fn evict_one_random(&mut self) -> bool {
         use rand::seq::IteratorRandom;
-        let mut rng = rand::thread_rng();
-        if let Some(key) = self.data.keys().choose(&mut rng).cloned() {
-            self.data.remove(&key);
-            true
-        } else {
-            false
-        }
+        self.evict(|db| {
+            db.iter()
+                .map(|(k, _)| k.clone())
+                .choose(&mut rand::thread_rng())
+        })
     }
 
     /// Evict one random key that has an expiry set. Returns true if a key was evicted.
     pub fn evict_one_volatile_random(&mut self) -> bool {
         use rand::seq::IteratorRandom;
-        let mut rng = rand::thread_rng();
-        if let Some(key) = self
-            .data
-            .iter()
-            .filter(|(_, e)| e.expires_at.is_some())
-            .map(|(k, _)| k.clone())
-            .choose(&mut rng)
-        {
-            self.data.remove(&key);
-            true
-        } else {
-            false
-        }
+        self.evict(|db| {
+            db.iter()
+                .filter(|(_, e)| e.expires_at.is_some())
+                .map(|(k, _)| k.clone())
+                .choose(&mut rand::thread_rng())
+        })
     }
 
     /// Evict the key with the smallest TTL. Returns true if a key was evicted.
     pub fn evict_one_volatile_ttl(&mut self) -> bool {
-        let key = self
-            .data
-            .iter()
-            .filter_map(|(k, e)| e.expires_at.map(|exp| (k.clone(), exp)))
-            .min_by_key(|(_, exp)| *exp)
-            .map(|(k, _)| k);
-        if let Some(key) = key {
-            self.data.remove(&key);
-            true
-        } else {
-            false
-        }
+        self.evict(|db| {
+            db.iter()
+                .filter_map(|(k, e)| e.expires_at.map(|exp| (k, exp)))
+                .min_by_key(|(_, exp)| *exp)
+                .map(|(k, _)| k.clone())
+        })
     }
 
     /// Evict the least recently used key (sampled LRU). Returns true if a key was evicted.
     pub fn evict_one_allkeys_lru(&mut self) -> bool {
         use rand::seq::IteratorRandom;
-        let mut rng = rand::thread_rng();
         // Sample 5 random keys, evict the one with the oldest access time
-        let samples: Vec<(String, u64)> = self
-            .data
-            .iter()
-            .map(|(k, e)| (k.clone(), e.last_access))
-            .choose_multiple(&mut rng, 5);
-        if let Some((key, _)) = samples.into_iter().min_by_key(|(_, access)| *access) {
-            self.data.remove(&key);
-            true
-        } else {
-            false
-        }
+        self.evict(|db| {
+            db.iter()
+                .map(|(k, e)| (k, e.last_access()))
+                .choose_multiple(&mut rand::thread_rng(), 5)
+                .into_iter()
+                .min_by_key(|(_, access)| *access)
+                .map(|(k, _)| k.clone())
+        })
     }
 
     /// Evict the least recently used key that has an expiry (sampled volatile LRU).
     pub fn evict_one_volatile_lru(&mut self) -> bool {
         use rand::seq::IteratorRandom;
-        let mut rng = rand::thread_rng();
-        let samples: Vec<(String, u64)> = self
-            .data
-            .iter()
-            .filter(|(_, e)| e.expires_at.is_some())
-            .map(|(k, e)| (k.clone(), e.last_access))
-            .choose_multiple(&mut rng, 5);
-        if let Some((key, _)) = samples.into_iter().min_by_key(|(_, access)| *access) {
-            self.data.remove(&key);
-            true
-        } else {
-            false
+        self.evict(|db| {
+            db.iter()
+                .filter(|(_, e)| e.expires_at.is_some())
+                .map(|(k, e)| (k, e.last_access()))
+                .choose_multiple(&mut rand::thread_rng(), 5)
+                .into_iter()
+                .min_by_key(|(_, access)| *access)
+                .map(|(k, _)| k.clone())
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Locks {
+    Owned(Vec<LockedShard>),
+    /// The shards EXEC holds, borrowed by one of its commands.
+    Held(OwnedMutexGuard<Vec<LockedShard>>),
+}
+
+/// Write locks on a set of shards, sorted by shard id. Dropping the guard
+/// releases them.
+#[derive(Debug)]
+pub struct KeyspaceGuard {
+    locks: Locks,
+    num_databases: usize,
+    lazyfree: Arc<LazyFree>,
+}
+
+impl KeyspaceGuard {
+    /// The store's lazyfree thread and settings.
+    pub fn lazyfree(&self) -> &LazyFree {
+        &self.lazyfree
+    }
+
+    fn shards(&self) -> &[LockedShard] {
+        match &self.locks {
+            Locks::Owned(shards) => shards,
+            Locks::Held(shards) => shards,
         }
     }
+
+    fn shards_mut(&mut self) -> &mut [LockedShard] {
+        match &mut self.locks {
+            Locks::Owned(shards) => shards,
+            Locks::Held(shards) => shards,
+        }
+    }
+
+    pub fn num_databases(&self) -> usize {
+        self.num_databases
+    }
+
+    /// The locked part of database `index`.
+    pub fn db(&mut self, index: usize) -> Database<'_> {
+        let shards = self.shards_mut();
+        let start = shards.partition_point(|s| s.id < index * SHARDS_PER_DB);
+        let end = shards.partition_point(|s| s.id < (index + 1) * SHARDS_PER_DB);
+        Database {
+            index,
+            shards: &mut shards[start..end],
+        }
+    }
+
+    /// Every database with at least one locked shard, in index order.
+    pub fn databases(&mut self) -> impl Iterator<Item = Database<'_>> {
+        let mut rest = self.shards_mut();
+        std::iter::from_fn(move || {
+            let index = rest.first()?.id / SHARDS_PER_DB;
+            let len = rest.partition_point(|s| s.id / SHARDS_PER_DB == index);
+            let (shards, tail) = std::mem::take(&mut rest).split_at_mut(len);
+            rest = tail;
+            Some(Database { index, shards })
+        })
+    }
+
+    /// Whether the shard holding `key` in database `db` is locked.
+    pub fn holds(&self, db: usize, key: &str) -> bool {
+        let id = shard_id(db, key);
+        self.shards().binary_search_by_key(&id, |s| s.id).is_ok()
+    }
+
+    /// Whether every shard of database `db` is locked.
+    pub fn holds_db(&self, db: usize) -> bool {
+        let shards = self.shards();
+        let start = shards.partition_point(|s| s.id < db * SHARDS_PER_DB);
+        let end = shards.partition_point(|s| s.id < (db + 1) * SHARDS_PER_DB);
+        end - start == SHARDS_PER_DB
+    }
+
+    /// Flush every locked database, freeing the data on the lazyfree thread
+    /// if `lazy`.
+    pub fn flush_all(&mut self, lazy: bool) {
+        for shard in self.shards_mut() {
+            shard.guard.clear(lazy);
+        }
+    }
+
+    /// Exchange the contents of two databases, both of which must be fully
+    /// locked. Returns false if either index is out of range.
+    pub fn swap_db(&mut self, a: usize, b: usize) -> bool {
+        if a >= self.num_databases || b >= self.num_databases {
+            return false;
+        }
+        if a == b {
+            return true;
+        }
+        let (low, high) = (a.min(b), a.max(b));
+        let shards = self.shards_mut();
+        let split = shards.partition_point(|s| s.id < high * SHARDS_PER_DB);
+        let (head, tail) = shards.split_at_mut(split);
+        let low_start = head.partition_point(|s| s.id < low * SHARDS_PER_DB);
+        let low_shards = &mut head[low_start..low_start + SHARDS_PER_DB];
+        for (x, y) in low_shards.iter_mut().zip(tail.iter_mut()) {
+            std::mem::swap(&mut *x.guard, &mut *y.guard);
+        }
+        true
+    }
 }
 
-/// The complete data store — holds multiple databases.
+#[derive(Debug)]
+enum ReadLocks {
+    Shared(Vec<(usize, OwnedRwLockReadGuard<Shard>)>),
+    /// The shards EXEC holds, borrowed by one of its commands.
+    Held(OwnedMutexGuard<Vec<LockedShard>>),
+}
+
+/// Read locks on a set of shards, sorted by shard id, for commands that only
+/// look at their keys. Readers of a shard don't wait for each other.
+#[derive(Debug)]
+pub struct KeyspaceReadGuard {
+    locks: ReadLocks,
+}
+
+impl KeyspaceReadGuard {
+    fn shard(&self, db: usize, key: &str) -> &Shard {
+        let id = shard_id(db, key);
+        let unlocked = || panic!("key '{key}' used without locking its shard");
+        match &self.locks {
+            ReadLocks::Shared(shards) => {
+                let pos = shards
+                    .binary_search_by_key(&id, |(id, _)| *id)
+                    .unwrap_or_else(|_| unlocked());
+                &shards[pos].1
+            }
+            ReadLocks::Held(shards) => {
+                let pos = shards
+                    .binary_search_by_key(&id, |s| s.id)
+                    .unwrap_or_else(|_| unlocked());
+                &shards[pos].guard
+            }
+        }
+    }
+
+    /// The locked part of database `index`.
+    pub fn db(&self, index: usize) -> ReadDatabase<'_> {
+        ReadDatabase {
+            index,
+            keyspace: self,
+        }
+    }
+}
+
+/// Read-only view of the read-locked shards of one database.
+pub struct ReadDatabase<'a> {
+    index: usize,
+    keyspace: &'a KeyspaceReadGuard,
+}
+
+impl<'a> ReadDatabase<'a> {
+    fn live(&self, key: &str) -> Option<&'a Entry> {
+        self.keyspace
+            .shard(self.index, key)
+            .data
+            .get(key)
+            .filter(|entry| !entry.is_expired())
+    }
+
+    /// Get a value, updating its access time. An expired key reads as
+    /// missing; deleting it is left to the next writer of its shard or the
+    /// active expire cycle.
+    pub fn get(&self, key: &str) -> Option<&'a Entry> {
+        let entry = self.live(key)?;
+        entry.touch_access();
+        Some(entry)
+    }
+
+    /// Check if a key exists and has not expired.
+    pub fn exists(&self, key: &str) -> bool {
+        self.live(key).is_some()
+    }
+}
+
+/// The complete data store: every database, split into [`SHARDS_PER_DB`]
+/// independently locked shards. Locks are always taken in ascending shard
+/// order, so commands locking several shards cannot deadlock.
 #[derive(Debug)]
 pub struct DataStore {
-    pub databases: Vec<Database>,
-    /// Tracks changes since last RDB save for INFO rdb_changes_since_last_save.
-    pub dirty: u64,
-    /// Total number of keys expired (lazy + active).
-    pub expired_keys: u64,
-    /// Number of keys expired by the active expiration background task.
-    pub expired_keys_active: u64,
+    shards: Vec<Arc<RwLock<Shard>>>,
+    gauges: Vec<Arc<ShardGauges>>,
+    num_databases: usize,
+    /// Shard the next active expire cycle starts from.
+    expire_next_shard: AtomicUsize,
+    /// Shard the next eviction tries first.
+    evict_next_shard: AtomicUsize,
+    pub stats: Arc<StoreStats>,
+    pub lazyfree: Arc<LazyFree>,
 }
 
 impl DataStore {
     pub fn new(num_databases: usize) -> Self {
-        let mut databases = Vec::with_capacity(num_databases);
-        for _ in 0..num_databases {
-            databases.push(Database::new());
-        }
+        let stats = Arc::new(StoreStats::default());
+        let lazyfree = Arc::new(LazyFree::new());
+        let shards = (0..num_databases * SHARDS_PER_DB)
+            .map(|_| Arc::new(RwLock::new(Shard::new(stats.clone(), lazyfree.clone()))))
+            .collect();
+        let gauges = (0..num_databases * SHARDS_PER_DB)
+            .map(|_| Arc::default())
+            .collect();
         DataStore {
-            databases,
-            dirty: 0,
-            expired_keys: 0,
-            expired_keys_active: 0,
+            shards,
+            gauges,
+            num_databases,
+            expire_next_shard: AtomicUsize::new(0),
+            evict_next_shard: AtomicUsize::new(0),
+            stats,
+            lazyfree,
         }
     }
 
-    pub fn db(&mut self, index: usize) -> &mut Database {
-        &mut self.databases[index]
+    pub fn num_databases(&self) -> usize {
+        self.num_databases
+    }
+
+    fn db_gauges(&self, db: usize) -> &[Arc<ShardGauges>] {
+        &self.gauges[db * SHARDS_PER_DB..(db + 1) * SHARDS_PER_DB]
+    }
+
+    /// Keys, and keys with a TTL, in database `db` as of the last time each
+    /// of its shards was unlocked. Takes no lock.
+    pub fn db_key_counts(&self, db: usize) -> (usize, usize) {
+        self.db_gauges(db)
+            .iter()
+            .fold((0, 0), |(keys, expires), g| {
+                (
+                    keys + g.keys.load(Ordering::Relaxed),
+                    expires + g.expires.load(Ordering::Relaxed),
+                )
+            })
+    }
+
+    /// Bytes of database `db`'s keyspace tables. Takes no lock.
+    pub fn db_overhead(&self, db: usize) -> usize {
+        self.db_gauges(db)
+            .iter()
+            .map(|g| g.overhead.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Memory in use as reported by INFO and metrics: live heap bytes when
+    /// the tracking allocator is installed, else the shards' published
+    /// estimates. Takes no lock.
+    pub fn used_memory(&self) -> usize {
+        crate::allocator::allocated().unwrap_or_else(|| {
+            self.gauges
+                .iter()
+                .map(|g| g.overhead.load(Ordering::Relaxed) + g.dataset.load(Ordering::Relaxed))
+                .sum()
+        })
+    }
+
+    /// Lock the shards holding `keys` in database `db`.
+    pub async fn lock_keys<K: AsRef<str>>(
+        &self,
+        db: usize,
+        keys: impl IntoIterator<Item = K>,
+    ) -> KeyspaceGuard {
+        let ids = keys
+            .into_iter()
+            .map(|key| shard_id(db, key.as_ref()))
+            .collect();
+        self.lock_shards(ids).await
     }
 
-    pub fn flush_all(&mut self) {
-        for db in &mut self.databases {
-            db.flush();
+    /// Read-lock the shards holding `keys` in database `db`. Inside [`hold`]
+    /// the shards EXEC holds are borrowed instead.
+    pub async fn read_keys<K: AsRef<str>>(
+        &self,
+        db: usize,
+        keys: impl IntoIterator<Item = K>,
+    ) -> KeyspaceReadGuard {
+        let mut ids: Vec<_> = keys
+            .into_iter()
+            .map(|key| shard_id(db, key.as_ref()))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        if let Ok(held) = HELD.try_with(Arc::clone) {
+            return KeyspaceReadGuard {
+                locks: ReadLocks::Held(held.lock_owned().await),
+            };
+        }
+        let mut shards = Vec::with_capacity(ids.len());
+        for id in ids {
+            shards.push((id, self.shards[id].clone().read_owned().await));
+        }
+        KeyspaceReadGuard {
+            locks: ReadLocks::Shared(shards),
         }
     }
 
-    pub fn swap_db(&mut self, a: usize, b: usize) -> bool {
-        if a >= self.databases.len() || b >= self.databases.len() {
-            return false;
+    /// Lock every shard of database `db`.
+    pub async fn lock_db(&self, db: usize) -> KeyspaceGuard {
+        self.lock_dbs(&[db]).await
+    }
+
+    /// Lock every shard of the given databases.
+    pub async fn lock_dbs(&self, dbs: &[usize]) -> KeyspaceGuard {
+        let ids = dbs
+            .iter()
+            .filter(|&&db| db < self.num_databases)
+            .flat_map(|&db| db * SHARDS_PER_DB..(db + 1) * SHARDS_PER_DB)
+            .collect();
+        self.lock_shards(ids).await
+    }
+
+    /// Lock the whole keyspace.
+    pub async fn lock_all(&self) -> KeyspaceGuard {
+        self.lock_shards((0..self.shards.len()).collect()).await
+    }
+
+    /// Lock the whole keyspace without waiting, for stores nobody else uses
+    /// yet (loading, tools, tests).
+    pub fn try_lock_all(&self) -> Option<KeyspaceGuard> {
+        let shards = self
+            .shards
+            .iter()
+            .enumerate()
+            .map(|(id, shard)| {
+                let guard = shard.clone().try_write_owned().ok()?;
+                Some(LockedShard {
+                    id,
+                    guard,
+                    gauges: self.gauges[id].clone(),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(KeyspaceGuard {
+            locks: Locks::Owned(shards),
+            num_databases: self.num_databases,
+            lazyfree: self.lazyfree.clone(),
+        })
+    }
+
+    /// Lock the shards with the given ids, in ascending order. Inside
+    /// [`hold`] the shards are already held and are borrowed instead.
+    pub async fn lock_shards(&self, mut ids: Vec<usize>) -> KeyspaceGuard {
+        ids.sort_unstable();
+        ids.dedup();
+        if let Ok(held) = HELD.try_with(Arc::clone) {
+            let held = held.lock_owned().await;
+            assert!(
+                ids.iter()
+                    .all(|id| held.binary_search_by_key(id, |s| s.id).is_ok()),
+                "shard used by a transaction without being locked by it"
+            );
+            return KeyspaceGuard {
+                locks: Locks::Held(held),
+                num_databases: self.num_databases,
+                lazyfree: self.lazyfree.clone(),
+            };
+        }
+        let mut shards = Vec::with_capacity(ids.len());
+        for id in ids {
+            let guard = self.shards[id].clone().write_owned().await;
+            shards.push(LockedShard {
+                id,
+                guard,
+                gauges: self.gauges[id].clone(),
+            });
+        }
+        KeyspaceGuard {
+            locks: Locks::Owned(shards),
+            num_databases: self.num_databases,
+            lazyfree: self.lazyfree.clone(),
         }
-        self.databases.swap(a, b);
-        true
     }
 
-    /// Run active expiration across all databases.
-    pub fn active_expire_cycle(&mut self) -> usize {
-        let mut total = 0;
-        for db in &mut self.databases {
-            let n = db.active_expire(20);
-            total += n;
+    /// Run one adaptive active expire cycle, locking one shard at a time.
+    ///
+    /// Like Redis's slow cycle, each shard is sampled `keys_per_loop`
+    /// volatile keys at a time, again and again while more than a quarter of
+    /// a sample had expired. The cycle gives up once it has used its share
+    /// of the `1/hz` period, and the next one starts at the shard where it
+    /// stopped. `effort` (1-10, active-expire-effort) raises both the sample
+    /// size and the time budget. Returns the number of keys expired.
+    pub async fn active_expire_cycle(&self, hz: u64, effort: u64) -> usize {
+        let effort = effort.clamp(1, 10) - 1;
+        let keys_per_loop = 20 + 5 * effort as usize;
+        let time_perc = 25 + 2 * effort;
+        let budget = Duration::from_micros(1_000_000 * time_perc / hz.max(1) / 100);
+        let start = Instant::now();
+
+        let first = self.expire_next_shard.load(Ordering::Relaxed);
+        let (mut sampled, mut expired) = (0, 0);
+        let mut timed_out = false;
+        for i in 0..self.shards.len() {
+            let id = (first + i) % self.shards.len();
+            loop {
+                let (s, e) = {
+                    let mut shard = self.shards[id].write().await;
+                    let (s, e) = shard.active_expire(keys_per_loop);
+                    shard.publish(&self.gauges[id]);
+                    // Counted before the shard is unlocked, so nobody sees
+                    // the keys gone but not yet counted
+                    self.stats
+                        .expired_keys
+                        .fetch_add(e as u64, Ordering::Relaxed);
+                    self.stats
+                        .expired_keys_active
+                        .fetch_add(e as u64, Ordering::Relaxed);
+                    (s, e)
+                };
+                sampled += s;
+                expired += e;
+                if start.elapsed() > budget {
+                    timed_out = true;
+                    break;
+                }
+                if e * 4 <= s {
+                    break;
+                }
+            }
+            if timed_out {
+                self.expire_next_shard.store(id, Ordering::Relaxed);
+                break;
+            }
         }
-        self.expired_keys += total as u64;
-        self.expired_keys_active += total as u64;
-        // Also drain any lazy-expired counts from databases
-        for db in &mut self.databases {
-            let lazy = db.lazy_expired_count;
-            self.expired_keys += lazy;
-            db.lazy_expired_count = 0;
+
+        let stats = &self.stats;
+        stats
+            .expire_cycle_time_used
+            .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        if timed_out {
+            stats
+                .expired_time_cap_reached_count
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        // Only this loop writes the average, so load + store is enough
+        let current = if sampled > 0 {
+            expired as f64 / sampled as f64
+        } else {
+            0.0
+        };
+        let average = f64::from_bits(stats.expired_stale_perc.load(Ordering::Relaxed));
+        let average = current * 0.05 + average * 0.95;
+        stats
+            .expired_stale_perc
+            .store(average.to_bits(), Ordering::Relaxed);
+        expired
+    }
+
+    /// Evict one key picked by `evict`, locking one shard at a time starting
+    /// after the shard the last eviction came from. The shard is unlocked,
+    /// and its gauges published, before this returns, so
+    /// [`used_memory`](Self::used_memory) already reflects the eviction.
+    /// Returns false if no shard had a key to evict.
+    pub async fn evict_one(&self, evict: impl Fn(&mut Database<'_>) -> bool) -> bool {
+        let first = self.evict_next_shard.load(Ordering::Relaxed);
+        for i in 0..self.shards.len() {
+            let id = (first + i) % self.shards.len();
+            let mut keyspace = self.lock_shards(vec![id]).await;
+            if evict(&mut keyspace.db(id / SHARDS_PER_DB)) {
+                self.evict_next_shard.store(id + 1, Ordering::Relaxed);
+                return true;
+            }
         }
-        total
+        false
     }
 
-    /// Drain lazy expired counts from all databases into the store-level counter.
-    pub fn drain_lazy_expired(&mut self) {
-        for db in &mut self.databases {
-            let lazy = db.lazy_expired_count;
-            self.expired_keys += lazy;
-            db.lazy_expired_count = 0;
+    /// Replace the whole dataset with the contents of `loaded` (a freshly
+    /// loaded RDB). Databases `loaded` doesn't have are emptied.
+    pub async fn replace(&self, loaded: DataStore) {
+        let mut keyspace = self.lock_all().await;
+        let mut source = loaded
+            .try_lock_all()
+            .expect("a freshly loaded store is not shared");
+        let mut source = source.shards_mut().iter_mut();
+        for shard in keyspace.shards_mut() {
+            let shard = &mut shard.guard;
+            match source.next() {
+                Some(src) => {
+                    std::mem::swap(&mut shard.data, &mut src.guard.data);
+                    std::mem::swap(&mut shard.expires, &mut src.guard.expires);
+                    shard.expires_cursor = 0;
+                }
+                None => shard.clear(false),
+            }
+            shard.touch_all();
         }
     }
+}
+
+pub type SharedStore = Arc<DataStore>;
 
-    /// Estimate total memory usage across all databases.
-    pub fn estimated_memory(&self) -> usize {
-        self.databases.iter().map(|db| db.estimated_memory()).sum()
+/// Run `f` with the shards of `guard` held for its whole duration. Locks
+/// taken inside `f` borrow these shards rather than waiting on them, so EXEC
+/// can lock everything its queued commands touch once, up front.
+pub async fn hold<F: Future>(guard: KeyspaceGuard, f: F) -> F::Output {
+    match guard.locks {
+        Locks::Owned(shards) => HELD.scope(Arc::new(Mutex::new(shards)), f).await,
+        // Already inside a held scope
+        Locks::Held(held) => {
+            drop(held);
+            f.await
+        }
     }
 }
 
-pub type SharedStore = Arc<RwLock<DataStore>>;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RedisValue;
+    use crate::types::rstring::RedisString;
+
+    fn string(value: &str) -> Entry {
+        Entry::new(RedisValue::String(RedisString::new(
+            value.as_bytes().to_vec(),
+        )))
+    }
+
+    #[test]
+    fn test_keys_spread_across_shards() {
+        let store = DataStore::new(2);
+        let mut keyspace = store.try_lock_all().unwrap();
+        let mut db = keyspace.db(0);
+        for i in 0..100 {
+            db.set(format!("key:{i}"), string("v"));
+        }
+        assert_eq!(db.dbsize(), 100);
+        assert!(db.rename("key:1", "renamed"));
+        assert!(db.exists("renamed"));
+        assert!(!db.exists("key:1"));
+        assert_eq!(db.keys("key:*").len(), 99);
+
+        let used = keyspace
+            .shards()
+            .iter()
+            .filter(|s| !s.guard.data.is_empty());
+        assert!(used.count() > 1);
+
+        assert!(keyspace.swap_db(0, 1));
+        assert_eq!(keyspace.db(0).dbsize(), 0);
+        assert_eq!(keyspace.db(1).dbsize(), 100);
+        let indexes: Vec<_> = keyspace.databases().map(|db| db.index()).collect();
+        assert_eq!(indexes, [0, 1]);
+    }
+
+    #[test]
+    fn test_gauges_published_on_unlock() {
+        let store = DataStore::new(2);
+        {
+            let mut keyspace = store.try_lock_all().unwrap();
+            let mut db = keyspace.db(1);
+            for i in 0..50 {
+                db.set(format!("key:{i}"), string("v"));
+            }
+            db.set_expiry("key:0", now_millis() + 60_000);
+            // Nothing is published while the shards are locked
+            assert_eq!(store.db_key_counts(1), (0, 0));
+        }
+        assert_eq!(store.db_key_counts(0), (0, 0));
+        assert_eq!(store.db_key_counts(1), (50, 1));
+        assert!(store.db_overhead(1) > 0);
+        assert!(store.used_memory() > 0);
+
+        {
+            let mut keyspace = store.try_lock_all().unwrap();
+            assert!(keyspace.swap_db(0, 1));
+        }
+        assert_eq!(store.db_key_counts(0), (50, 1));
+        assert_eq!(store.db_key_counts(1), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_evict_one_publishes_gauges() {
+        let store = DataStore::new(2);
+        {
+            let mut keyspace = store.try_lock_all().unwrap();
+            let mut db = keyspace.db(1);
+            for i in 0..20 {
+                db.set(format!("key:{i}"), string("v"));
+            }
+            db.set_expiry("key:0", now_millis() + 60_000);
+        }
+        assert!(store.evict_one(|db| db.evict_one_volatile_ttl()).await);
+        assert!(!store.evict_one(|db| db.evict_one_volatile_ttl()).await);
+
+        let before = store.used_memory();
+        assert!(store.evict_one(|db| db.evict_one_random()).await);
+        assert_eq!(store.db_key_counts(1), (18, 0));
+        assert!(store.used_memory() < before);
+    }
+
+    #[tokio::test]
+    async fn test_readers_share_a_shard() {
+        let store = DataStore::new(1);
+        {
+            let mut keyspace = store.lock_keys(0, ["a"]).await;
+            let mut db = keyspace.db(0);
+            db.set("a".to_string(), string("v"));
+            db.set_expiry("a", now_millis() - 1);
+        }
+        let first = store.read_keys(0, ["a"]).await;
+        // A second reader doesn't wait for the first
+        let second = store.read_keys(0, ["a"]).await;
+        // Expired keys read as missing, and stay until a writer removes them
+        assert!(first.db(0).get("a").is_none());
+        assert!(!second.db(0).exists("a"));
+        drop((first, second));
+        assert_eq!(store.db_key_counts(0), (1, 1));
+        assert!(!store.lock_keys(0, ["a"]).await.db(0).exists("a"));
+        assert_eq!(store.db_key_counts(0), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_locks_only_touched_shards() {
+        let store = Arc::new(DataStore::new(1));
+        let a = "a";
+        let b = (0..)
+            .map(|i| format!("b{i}"))
+            .find(|k| shard_id(0, k) != shard_id(0, a))
+            .unwrap();
+
+        let mut guard = store.lock_keys(0, [a]).await;
+        assert!(guard.holds(0, a));
+        assert!(!guard.holds(0, &b));
+        // Another shard is free while `a` is locked
+        store
+            .lock_keys(0, [&b])
+            .await
+            .db(0)
+            .set(b.clone(), string("1"));
+        guard.db(0).set(a.to_string(), string("2"));
+
+        // The whole database waits for `a`
+        let waiter = tokio::spawn({
+            let store = store.clone();
+            async move { store.lock_db(0).await.db(0).dbsize() }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        drop(guard);
+        assert_eq!(waiter.await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_hold_lends_locked_shards() {
+        let store = Arc::new(DataStore::new(1));
+        let guard = store.lock_keys(0, ["x", "y"]).await;
+        hold(guard, async {
+            store
+                .lock_keys(0, ["x"])
+                .await
+                .db(0)
+                .set("x".into(), string("1"));
+            let mut both = store.lock_keys(0, ["y", "x"]).await;
+            assert!(both.db(0).exists("x"));
+        })
+        .await;
+        assert!(store.lock_db(0).await.db(0).exists("x"));
+    }
+
+    #[tokio::test]
+    async fn test_active_expire_samples_volatile_keys() {
+        let store = DataStore::new(1);
+        {
+            let mut keyspace = store.lock_db(0).await;
+            let mut db = keyspace.db(0);
+            for i in 0..1000 {
+                db.set(format!("plain:{i}"), string("v"));
+            }
+            for i in 0..200 {
+                let mut entry = string("v");
+                entry.expires_at = Some(1);
+                db.set(format!("gone:{i}"), entry);
+            }
+            assert!(db.set_expiry("plain:0", now_millis() + 60_000));
+            assert!(db.set_expiry("plain:1", now_millis() + 60_000));
+            assert!(db.persist("plain:1"));
+            db.set("plain:2".into(), string("v"));
+            assert_eq!(db.expires_count(), 201);
+        }
+
+        let mut expired = 0;
+        for _ in 0..10 {
+            expired += store.active_expire_cycle(10, 1).await;
+        }
+        assert_eq!(expired, 200);
+        let stale = f64::from_bits(store.stats.expired_stale_perc.load(Ordering::Relaxed));
+        assert!(stale > 0.0);
+
+        let mut keyspace = store.lock_db(0).await;
+        let db = keyspace.db(0);
+        assert_eq!(db.dbsize(), 1000);
+        assert_eq!(db.expires_count(), 1);
+    }
+
+    #[test]
+    fn test_lazy_delete_and_flush() {
+        use crate::types::set::RedisSet;
+
+        let big = || {
+            let mut set = RedisSet::new();
+            for i in 0..1000 {
+                set.add(i.to_string().into_bytes());
+            }
+            Entry::new(RedisValue::Set(set))
+        };
+        let store = DataStore::new(1);
+        let mut keyspace = store.try_lock_all().unwrap();
+        let mut db = keyspace.db(0);
+        db.set("small".into(), string("v"));
+        db.set("big".into(), big());
+        db.set("other".into(), big());
+        db.set_expiry("other", now_millis() + 60_000);
+        db.touch("other");
+        assert!(db.delete("small", true));
+        assert!(db.delete("big", true));
+        assert!(!db.delete("big", true));
+        db.flush(true);
+        assert_eq!(db.dbsize(), 0);
+        assert_eq!(db.expires_count(), 0);
+        assert!(
+            keyspace
+                .shards()
+                .iter()
+                .all(|s| s.guard.key_versions.is_empty())
+        );
+
+        // The big set, then the one key left at the flush
+        let start = std::time::Instant::now();
+        while store.lazyfree.lazyfreed_objects() < 2 {
+            assert!(start.elapsed().as_secs() < 5);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(store.lazyfree.lazyfreed_objects(), 2);
+    }
+}
@@ -0,0 +1,449 @@
+//! Chained hash table with Redis-style reverse-binary cursor scanning.
+//!
+//! `std::collections::HashMap` gives no stable position to resume an
+//! iteration from, so SCAN over it had to collect and sort every key per
+//! call. `Dict` keeps a power-of-two array of bucket chains instead: an
+//! entry always lives in bucket `hash & mask`, which lets [`Dict::scan`]
+//! walk the table in reverse-binary bucket order. That order visits every
+//! bucket of the current table exactly once per full iteration, and it
+//! survives the table growing or shrinking between calls, so an entry
+//! present for the whole iteration is always returned (possibly more than
+//! once after a shrink).
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+
+/// Smallest non-empty table.
+const MIN_BUCKETS: usize = 4;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    next: Link<K, V>,
+}
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+pub struct Dict<K, V> {
+    buckets: Vec<Link<K, V>>,
+    len: usize,
+    hasher: RandomState,
+}
+
+impl<K, V> Dict<K, V> {
+    pub fn new() -> Self {
+        Dict {
+            buckets: Vec::new(),
+            len: 0,
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of buckets in the table.
+    pub fn capacity(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets = Vec::new();
+        self.len = 0;
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            buckets: self.buckets.iter(),
+            node: None,
+            remaining: self.len,
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.buckets.iter_mut().flat_map(|bucket| {
+            let mut link = bucket.as_deref_mut();
+            std::iter::from_fn(move || {
+                let node = link.take()?;
+                link = node.next.as_deref_mut();
+                Some((&node.key, &mut node.value))
+            })
+        })
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// Visit the bucket `cursor` points at and return the cursor of the next
+    /// bucket, or 0 once the whole table has been visited.
+    ///
+    /// The cursor is incremented with its bits reversed, so the high bits of
+    /// the bucket index change fastest. A bucket's entries only ever move to
+    /// buckets sharing its low bits when the table doubles or halves, so a
+    /// cursor stays valid across resizes: no bucket still to be visited is
+    /// skipped, though a shrink can make some entries come up twice.
+    pub fn scan_bucket(&self, cursor: u64, mut f: impl FnMut(&K, &V)) -> u64 {
+        if self.buckets.is_empty() {
+            return 0;
+        }
+        let mask = (self.buckets.len() - 1) as u64;
+        let mut link = self.buckets[(cursor & mask) as usize].as_deref();
+        while let Some(node) = link {
+            f(&node.key, &node.value);
+            link = node.next.as_deref();
+        }
+        // Set the bits above the mask so the increment carries past them,
+        // then add one to the reversed cursor
+        let cursor = (cursor | !mask).reverse_bits().wrapping_add(1);
+        cursor.reverse_bits()
+    }
+
+    /// Visit buckets from `cursor` until at least `count` entries have been
+    /// seen, the table is exhausted, or `10 * count` buckets have been
+    /// visited (bounding the work on sparse tables). Returns the cursor to
+    /// resume from, 0 when done.
+    pub fn scan(&self, mut cursor: u64, count: usize, mut f: impl FnMut(&K, &V)) -> u64 {
+        let count = count.max(1);
+        let mut seen = 0;
+        let mut buckets = count.saturating_mul(10);
+        loop {
+            cursor = self.scan_bucket(cursor, |k, v| {
+                seen += 1;
+                f(k, v);
+            });
+            buckets -= 1;
+            if cursor == 0 || seen >= count || buckets == 0 {
+                return cursor;
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Dict<K, V> {
+    /// Keep only the entries for which `f` returns true.
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        for bucket in &mut self.buckets {
+            let mut chain = bucket.take();
+            let mut kept: Link<K, V> = None;
+            while let Some(mut node) = chain {
+                chain = node.next.take();
+                if f(&node.key, &mut node.value) {
+                    node.next = kept;
+                    kept = Some(node);
+                } else {
+                    self.len -= 1;
+                }
+            }
+            *bucket = kept;
+        }
+        self.shrink_if_sparse();
+    }
+
+    /// Halve the table while it is less than 1/8 full.
+    fn shrink_if_sparse(&mut self) {
+        let mut size = self.buckets.len();
+        while size > MIN_BUCKETS && self.len * 8 < size {
+            size /= 2;
+        }
+        if self.len == 0 {
+            self.buckets = Vec::new();
+        } else if size != self.buckets.len() {
+            self.rehash(size);
+        }
+    }
+
+    fn rehash(&mut self, size: usize) {
+        let old = std::mem::replace(&mut self.buckets, Vec::with_capacity(size));
+        self.buckets.resize_with(size, || None);
+        let mask = size - 1;
+        for mut chain in old {
+            while let Some(mut node) = chain {
+                chain = node.next.take();
+                let index = self.hasher.hash_one(&node.key) as usize & mask;
+                node.next = self.buckets[index].take();
+                self.buckets[index] = Some(node);
+            }
+        }
+    }
+
+    fn bucket_of<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.hasher.hash_one(key) as usize & (self.buckets.len() - 1)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_key_value(key).map(|(_, v)| v)
+    }
+
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        let mut link = self.buckets[self.bucket_of(key)].as_deref();
+        while let Some(node) = link {
+            if node.key.borrow() == key {
+                return Some((&node.key, &node.value));
+            }
+            link = node.next.as_deref();
+        }
+        None
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        let index = self.bucket_of(key);
+        let mut link = self.buckets[index].as_deref_mut();
+        while let Some(node) = link {
+            if node.key.borrow() == key {
+                return Some(&mut node.value);
+            }
+            link = node.next.as_deref_mut();
+        }
+        None
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_key_value(key).is_some()
+    }
+
+    /// Insert an entry, returning the previous value of `key` if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(slot) = self.get_mut(&key) {
+            return Some(std::mem::replace(slot, value));
+        }
+        // Keep the load factor at or below 1
+        if self.len >= self.buckets.len() {
+            self.rehash((self.buckets.len() * 2).max(MIN_BUCKETS));
+        }
+        let index = self.bucket_of(&key);
+        let next = self.buckets[index].take();
+        self.buckets[index] = Some(Box::new(Node { key, value, next }));
+        self.len += 1;
+        None
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        let index = self.bucket_of(key);
+        let mut link = &mut self.buckets[index];
+        loop {
+            match link {
+                None => return None,
+                Some(node) if node.key.borrow() == key => {
+                    let mut node = link.take().unwrap();
+                    *link = node.next.take();
+                    self.len -= 1;
+                    self.shrink_if_sparse();
+                    return Some((node.key, node.value));
+                }
+                Some(node) => link = &mut node.next,
+            }
+        }
+    }
+}
+
+impl<K, V> Default for Dict<K, V> {
+    fn default() -> Self {
+        Dict::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Clone for Dict<K, V> {
+    fn clone(&self) -> Self {
+        let mut dict = Dict::new();
+        dict.extend(self.iter().map(|(k, v)| (k.clone(), v.clone())));
+        dict
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Dict<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V> Drop for Dict<K, V> {
+    fn drop(&mut self) {
+        // Unlink chains iteratively rather than through recursive Box drops
+        for bucket in &mut self.buckets {
+            let mut chain = bucket.take();
+            while let Some(mut node) = chain {
+                chain = node.next.take();
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Extend<(K, V)> for Dict<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for Dict<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut dict = Dict::new();
+        dict.extend(iter);
+        dict
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a Dict<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    buckets: std::slice::Iter<'a, Link<K, V>>,
+    node: Option<&'a Node<K, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(node) = self.node {
+                self.node = node.next.as_deref();
+                self.remaining -= 1;
+                return Some((&node.key, &node.value));
+            }
+            self.node = self.buckets.next()?.as_deref();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn scan_all(dict: &Dict<u32, ()>, count: usize) -> Vec<u32> {
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        loop {
+            cursor = dict.scan(cursor, count, |k, _| seen.push(*k));
+            if cursor == 0 {
+                return seen;
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_get_remove() {
+        let mut dict = Dict::new();
+        for i in 0..1000u32 {
+            assert_eq!(dict.insert(i, i * 2), None);
+        }
+        assert_eq!(dict.insert(7, 0), Some(14));
+        assert_eq!(dict.len(), 1000);
+        assert_eq!(dict.get(&500), Some(&1000));
+        assert!(dict.capacity() >= 1000);
+        for i in 0..990u32 {
+            assert!(dict.remove(&i).is_some());
+        }
+        assert_eq!(dict.remove(&0), None);
+        assert_eq!(dict.len(), 10);
+        assert!(dict.capacity() <= 80, "{}", dict.capacity());
+        let mut rest: Vec<_> = dict.keys().copied().collect();
+        rest.sort_unstable();
+        assert_eq!(rest, (990..1000).collect::<Vec<_>>());
+        dict.retain(|k, _| k % 2 == 0);
+        assert_eq!(dict.len(), 5);
+        assert_eq!(dict.iter().len(), 5);
+    }
+
+    #[test]
+    fn test_scan_visits_everything_once() {
+        let dict: Dict<u32, ()> = (0..1000).map(|i| (i, ())).collect();
+        let mut seen = scan_all(&dict, 10);
+        seen.sort_unstable();
+        assert_eq!(seen, (0..1000).collect::<Vec<_>>());
+        assert_eq!(Dict::<u32, ()>::new().scan(0, 10, |_, _| panic!()), 0);
+    }
+
+    #[test]
+    fn test_scan_survives_resizing() {
+        let mut dict: Dict<u32, ()> = (0..100).map(|i| (i, ())).collect();
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        let mut step = 0u32;
+        loop {
+            cursor = dict.scan(cursor, 5, |k, _| {
+                seen.insert(*k);
+            });
+            // Grow the table for a while, then shrink it well below its
+            // starting size
+            step += 1;
+            if step < 10 {
+                dict.extend((0..200).map(|i| (1000 * step + i, ())));
+            } else {
+                dict.retain(|k, _| *k < 100);
+            }
+            if cursor == 0 {
+                break;
+            }
+        }
+        assert!((0..100).all(|i| seen.contains(&i)));
+    }
+}
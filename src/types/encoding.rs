@@ -0,0 +1,100 @@
+//! Size limits of the compact encodings.
+//!
+//! HyperLogLogs stay sparse up to `hll-sparse-max-bytes`.
+//! Lists are quicklists of listpack nodes sized by `list-max-listpack-size`.
+//! Hashes and sorted sets start out as a [`Listpack`](super::listpack::Listpack),
+//! sets as an [`IntSet`](super::intset::IntSet) or a listpack, and convert to
+//! their hash table based form once they outgrow these limits, never back.
+//! Values have no handle on the config, so like Redis's server globals the
+//! limits are process-wide: the server takes them from its [`Config`] at
+//! startup and on CONFIG SET, and servers sharing a process share them.
+
+use crate::config::Config;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+
+static LIST_MAX_LISTPACK_SIZE: AtomicI64 = AtomicI64::new(-2);
+static LIST_COMPRESS_DEPTH: AtomicUsize = AtomicUsize::new(0);
+static HASH_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(128);
+static HASH_MAX_LISTPACK_VALUE: AtomicUsize = AtomicUsize::new(64);
+static SET_MAX_INTSET_ENTRIES: AtomicUsize = AtomicUsize::new(512);
+static SET_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(128);
+static SET_MAX_LISTPACK_VALUE: AtomicUsize = AtomicUsize::new(64);
+static ZSET_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(128);
+static ZSET_MAX_LISTPACK_VALUE: AtomicUsize = AtomicUsize::new(64);
+static HLL_SPARSE_MAX_BYTES: AtomicUsize = AtomicUsize::new(3000);
+
+/// Take the `*-max-listpack-*`, `set-max-intset-entries`,
+/// `hll-sparse-max-bytes` and `list-compress-depth` options from `config`. Values already converted
+/// keep their encoding.
+pub fn configure(config: &Config) {
+    LIST_MAX_LISTPACK_SIZE.store(config.list_max_listpack_size, Ordering::Relaxed);
+    LIST_COMPRESS_DEPTH.store(
+        config.list_compress_depth.max(0) as usize,
+        Ordering::Relaxed,
+    );
+    let limits = [
+        (&HASH_MAX_LISTPACK_ENTRIES, config.hash_max_listpack_entries),
+        (&HASH_MAX_LISTPACK_VALUE, config.hash_max_listpack_value),
+        (&SET_MAX_INTSET_ENTRIES, config.set_max_intset_entries),
+        (&SET_MAX_LISTPACK_ENTRIES, config.set_max_listpack_entries),
+        (&SET_MAX_LISTPACK_VALUE, config.set_max_listpack_value),
+        (&ZSET_MAX_LISTPACK_ENTRIES, config.zset_max_listpack_entries),
+        (&ZSET_MAX_LISTPACK_VALUE, config.zset_max_listpack_value),
+        (&HLL_SPARSE_MAX_BYTES, config.hll_sparse_max_bytes),
+    ];
+    for (limit, value) in limits {
+        limit.store(value as usize, Ordering::Relaxed);
+    }
+}
+
+/// Size of a quicklist node: positive for a number of entries, -1 to -5
+/// for 4 KB to 64 KB of encoded entries.
+pub fn list_max_listpack_size() -> i64 {
+    LIST_MAX_LISTPACK_SIZE.load(Ordering::Relaxed)
+}
+
+/// Quicklist nodes at each end of a list kept uncompressed; 0 compresses
+/// none.
+pub fn list_compress_depth() -> usize {
+    LIST_COMPRESS_DEPTH.load(Ordering::Relaxed)
+}
+
+/// Most fields a listpack hash holds.
+pub fn hash_max_listpack_entries() -> usize {
+    HASH_MAX_LISTPACK_ENTRIES.load(Ordering::Relaxed)
+}
+
+/// Longest field or value a listpack hash holds.
+pub fn hash_max_listpack_value() -> usize {
+    HASH_MAX_LISTPACK_VALUE.load(Ordering::Relaxed)
+}
+
+/// Most members an intset holds.
+pub fn set_max_intset_entries() -> usize {
+    SET_MAX_INTSET_ENTRIES.load(Ordering::Relaxed)
+}
+
+/// Most members a listpack set holds.
+pub fn set_max_listpack_entries() -> usize {
+    SET_MAX_LISTPACK_ENTRIES.load(Ordering::Relaxed)
+}
+
+/// Longest member a listpack set holds.
+pub fn set_max_listpack_value() -> usize {
+    SET_MAX_LISTPACK_VALUE.load(Ordering::Relaxed)
+}
+
+/// Most members a listpack sorted set holds.
+pub fn zset_max_listpack_entries() -> usize {
+    ZSET_MAX_LISTPACK_ENTRIES.load(Ordering::Relaxed)
+}
+
+/// Longest member a listpack sorted set holds.
+pub fn zset_max_listpack_value() -> usize {
+    ZSET_MAX_LISTPACK_VALUE.load(Ordering::Relaxed)
+}
+
+/// Largest sparse HyperLogLog in bytes, header included.
+pub fn hll_sparse_max_bytes() -> usize {
+    HLL_SPARSE_MAX_BYTES.load(Ordering::Relaxed)
+}
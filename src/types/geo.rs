@@ -1,224 +1,339 @@
-use std::collections::HashMap;
+//! Geohash coding for geo keys.
+//!
+//! As in Redis, a geo key is a plain sorted set scored by a 52-bit geohash
+//! of each member's position: longitude and latitude are quantized to 26
+//! bits each over [-180, 180] and [-85.05112878, 85.05112878] and
+//! interleaved, latitude in the even bits. Every cell of a coarser grid is
+//! then one contiguous score range, so a search reads the cell around its
+//! center, sized to the search area, and that cell's eight neighbors, and
+//! keeps the members inside the area by exact distance.
 
-/// Earth's radius in meters.
+use super::sorted_set::RedisSortedSet;
+
+pub const LONG_MIN: f64 = -180.0;
+pub const LONG_MAX: f64 = 180.0;
+pub const LAT_MIN: f64 = -85.05112878;
+pub const LAT_MAX: f64 = 85.05112878;
+
+/// Bits per coordinate of a full-precision geohash.
+const STEP_MAX: u32 = 26;
+
+/// Earth's radius in meters, as Redis uses it.
 const EARTH_RADIUS_M: f64 = 6372797.560856;
 
-/// Mercator projection limits (used by Redis geohash).
+/// Half the Earth's circumference in the Mercator projection, in meters.
 const MERCATOR_MAX: f64 = 20037726.37;
-const MERCATOR_MIN: f64 = -20037726.37;
 
-/// Number of bits used for the geohash score (26 bits lat + 26 bits lon = 52 bits).
-const GEO_STEP: u32 = 26;
+/// Spread the bits of `v` out to the even bits of the result.
+fn spread(v: u32) -> u64 {
+    let mut x = u64::from(v);
+    x = (x | x << 16) & 0x0000_ffff_0000_ffff;
+    x = (x | x << 8) & 0x00ff_00ff_00ff_00ff;
+    x = (x | x << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | x << 2) & 0x3333_3333_3333_3333;
+    (x | x << 1) & 0x5555_5555_5555_5555
+}
 
-/// A geographic data set storing members with longitude/latitude coordinates.
-/// Under the hood, Redis stores geo data in a sorted set using geohash scores.
-#[derive(Debug, Clone, Default)]
-pub struct GeoSet {
-    /// member -> (longitude, latitude)
-    members: HashMap<Vec<u8>, (f64, f64)>,
+/// Gather the even bits of `x`, undoing [`spread`].
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | x >> 1) & 0x3333_3333_3333_3333;
+    x = (x | x >> 2) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | x >> 4) & 0x00ff_00ff_00ff_00ff;
+    x = (x | x >> 8) & 0x0000_ffff_0000_ffff;
+    (x | x >> 16) as u32
 }
 
-/// A search result entry with distance information.
-#[derive(Debug, Clone)]
-pub struct GeoResult {
-    pub member: Vec<u8>,
-    pub longitude: f64,
-    pub latitude: f64,
-    pub distance: f64,
+/// A cell of the grid that splits each coordinate range in `2^step`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    bits: u64,
+    step: u32,
+}
+
+/// Coordinate bounds of a cell.
+#[derive(Debug, Clone, Copy)]
+struct Area {
+    min_lon: f64,
+    max_lon: f64,
+    min_lat: f64,
+    max_lat: f64,
 }
 
-impl GeoSet {
-    pub fn new() -> Self {
-        GeoSet {
-            members: HashMap::new(),
+impl Cell {
+    /// The cell holding a position, with latitudes spanning `lat_range`.
+    fn encode(lon: f64, lat: f64, step: u32, lat_range: (f64, f64)) -> Cell {
+        let cells = (1u64 << step) as f64;
+        let lat_offset = (lat - lat_range.0) / (lat_range.1 - lat_range.0) * cells;
+        let lon_offset = (lon - LONG_MIN) / (LONG_MAX - LONG_MIN) * cells;
+        Cell {
+            bits: spread(lat_offset as u32) | spread(lon_offset as u32) << 1,
+            step,
         }
     }
 
-    pub fn len(&self) -> usize {
-        self.members.len()
-    }
-
-    pub fn is_empty(&self) -> bool {
-        self.members.is_empty()
-    }
-
-    /// Add or update a member with the given longitude and latitude.
-    /// Returns true if the member is new, false if updated.
-    pub fn add(&mut self, member: Vec<u8>, longitude: f64, latitude: f64) -> bool {
-        let is_new = !self.members.contains_key(&member);
-        self.members.insert(member, (longitude, latitude));
-        is_new
-    }
-
-    /// Get the position of a member.
-    pub fn pos(&self, member: &[u8]) -> Option<(f64, f64)> {
-        self.members.get(member).copied()
-    }
-
-    /// Calculate the distance in meters between two members.
-    pub fn dist(&self, member1: &[u8], member2: &[u8]) -> Option<f64> {
-        let &(lon1, lat1) = self.members.get(member1)?;
-        let &(lon2, lat2) = self.members.get(member2)?;
-        Some(haversine_distance(lat1, lon1, lat2, lon2))
-    }
-
-    /// Get all members.
-    pub fn all_members(&self) -> Vec<(&[u8], f64, f64)> {
-        self.members
-            .iter()
-            .map(|(m, &(lon, lat))| (m.as_slice(), lon, lat))
-            .collect()
-    }
-
-    /// Search for members within a given radius (in meters) of a center point.
-    /// Returns results sorted by distance ascending by default.
-    pub fn search_within_radius(
-        &self,
-        center_lon: f64,
-        center_lat: f64,
-        radius_m: f64,
-        ascending: bool,
-        count: Option<usize>,
-    ) -> Vec<GeoResult> {
-        let mut results: Vec<GeoResult> = self
-            .members
-            .iter()
-            .filter_map(|(member, &(lon, lat))| {
-                let d = haversine_distance(center_lat, center_lon, lat, lon);
-                if d <= radius_m {
-                    Some(GeoResult {
-                        member: member.clone(),
-                        longitude: lon,
-                        latitude: lat,
-                        distance: d,
-                    })
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        if ascending {
-            results.sort_by(|a, b| {
-                a.distance
-                    .partial_cmp(&b.distance)
-                    .unwrap_or(std::cmp::Ordering::Equal)
-            });
-        } else {
-            results.sort_by(|a, b| {
-                b.distance
-                    .partial_cmp(&a.distance)
-                    .unwrap_or(std::cmp::Ordering::Equal)
-            });
+    fn area(&self) -> Area {
+        let cells = (1u64 << self.step) as f64;
+        let lat = f64::from(squash(self.bits));
+        let lon = f64::from(squash(self.bits >> 1));
+        Area {
+            min_lon: LONG_MIN + lon / cells * (LONG_MAX - LONG_MIN),
+            max_lon: LONG_MIN + (lon + 1.0) / cells * (LONG_MAX - LONG_MIN),
+            min_lat: LAT_MIN + lat / cells * (LAT_MAX - LAT_MIN),
+            max_lat: LAT_MIN + (lat + 1.0) / cells * (LAT_MAX - LAT_MIN),
         }
+    }
 
-        if let Some(c) = count {
-            results.truncate(c);
+    /// The cell `dx` cells east and `dy` cells north, wrapping around the
+    /// edges of the grid.
+    fn moved(self, dx: i8, dy: i8) -> Cell {
+        const ODD: u64 = 0xaaaa_aaaa_aaaa_aaaa;
+        const EVEN: u64 = 0x5555_5555_5555_5555;
+        let unused = 64 - self.step * 2;
+        // Adding or subtracting one to the bits of one coordinate only: the
+        // other coordinate's bits are set or cleared so carries cross them
+        let shift = |bits: u64, own: u64, other: u64, d: i8| {
+            let filler = other >> unused;
+            let moved = match d.signum() {
+                1 => bits.wrapping_add(filler + 1),
+                -1 => (bits | filler).wrapping_sub(filler + 1),
+                _ => bits,
+            };
+            moved & (own >> unused)
+        };
+        Cell {
+            bits: shift(self.bits & ODD, ODD, EVEN, dx) | shift(self.bits & EVEN, EVEN, ODD, dy),
+            step: self.step,
         }
+    }
 
-        results
-    }
-
-    /// Search for members within a bounding box (width x height in meters) centered on a point.
-    pub fn search_within_box(
-        &self,
-        center_lon: f64,
-        center_lat: f64,
-        width_m: f64,
-        height_m: f64,
-        ascending: bool,
-        count: Option<usize>,
-    ) -> Vec<GeoResult> {
-        // Convert box dimensions to approximate lat/lon deltas
-        let half_width = width_m / 2.0;
-        let half_height = height_m / 2.0;
-
-        let mut results: Vec<GeoResult> = self
-            .members
-            .iter()
-            .filter_map(|(member, &(lon, lat))| {
-                let d = haversine_distance(center_lat, center_lon, lat, lon);
-                // Check if within the bounding box
-                let dx = haversine_distance(center_lat, center_lon, center_lat, lon);
-                let dy = haversine_distance(center_lat, center_lon, lat, center_lon);
-                if dx <= half_width && dy <= half_height {
-                    Some(GeoResult {
-                        member: member.clone(),
-                        longitude: lon,
-                        latitude: lat,
-                        distance: d,
-                    })
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        if ascending {
-            results.sort_by(|a, b| {
-                a.distance
-                    .partial_cmp(&b.distance)
-                    .unwrap_or(std::cmp::Ordering::Equal)
-            });
-        } else {
-            results.sort_by(|a, b| {
-                b.distance
-                    .partial_cmp(&a.distance)
-                    .unwrap_or(std::cmp::Ordering::Equal)
-            });
-        }
+    /// Scores of the members inside the cell, `min..max`.
+    fn scores(&self) -> (u64, u64) {
+        let shift = 2 * (STEP_MAX - self.step);
+        (self.bits << shift, (self.bits + 1) << shift)
+    }
+}
 
-        if let Some(c) = count {
-            results.truncate(c);
-        }
+/// Whether a position can be stored.
+pub fn is_valid(lon: f64, lat: f64) -> bool {
+    (LONG_MIN..=LONG_MAX).contains(&lon) && (LAT_MIN..=LAT_MAX).contains(&lat)
+}
+
+/// The 52-bit geohash score of a position.
+pub fn encode(lon: f64, lat: f64) -> u64 {
+    Cell::encode(lon, lat, STEP_MAX, (LAT_MIN, LAT_MAX)).bits
+}
 
-        results
+/// The position a score stands for: the center of its cell.
+pub fn decode(score: f64) -> (f64, f64) {
+    let area = Cell {
+        bits: score as u64,
+        step: STEP_MAX,
     }
+    .area();
+    let lon = ((area.min_lon + area.max_lon) / 2.0).clamp(LONG_MIN, LONG_MAX);
+    let lat = ((area.min_lat + area.max_lat) / 2.0).clamp(LAT_MIN, LAT_MAX);
+    (lon, lat)
+}
 
-    /// Estimate memory usage.
-    pub fn estimated_memory(&self) -> usize {
-        let member_bytes: usize = self.members.keys().map(|k| k.len() + 16).sum();
-        64 * self.members.len() + member_bytes
+/// The 11-character base32 geohash of a position, as GEOHASH reports it.
+/// Unlike scores, these span the standard latitudes, -90 to 90.
+pub fn hash_string(lon: f64, lat: f64) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+    let bits = Cell::encode(lon, lat, STEP_MAX, (-90.0, 90.0)).bits;
+    (0..11)
+        .map(|i| {
+            // 52 bits fill ten characters; the eleventh is padding
+            let index = if i == 10 {
+                0
+            } else {
+                (bits >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            ALPHABET[index as usize] as char
+        })
+        .collect()
+}
+
+/// Distance in meters between two latitudes on one meridian.
+fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_M * (lat2.to_radians() - lat1.to_radians()).abs()
+}
+
+/// Great-circle distance in meters between two positions (haversine).
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
     }
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
 }
 
-/// Haversine distance between two points in meters.
-fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
-    let lat1_rad = lat1.to_radians();
-    let lat2_rad = lat2.to_radians();
-    let dlat = (lat2 - lat1).to_radians();
-    let dlon = (lon2 - lon1).to_radians();
+/// The area a search covers around its center, in meters.
+#[derive(Debug, Clone, Copy)]
+pub enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+impl Shape {
+    /// Half the shape's east-west and north-south extent.
+    fn half_extent(&self) -> (f64, f64) {
+        match *self {
+            Shape::Radius(radius) => (radius, radius),
+            Shape::Box { width, height } => (width / 2.0, height / 2.0),
+        }
+    }
+
+    /// Distance from `center` to the point, if the point is inside.
+    fn distance_if_inside(&self, center: (f64, f64), lon: f64, lat: f64) -> Option<f64> {
+        let (center_lon, center_lat) = center;
+        if let Shape::Box { width, height } = *self
+            && (lat_distance(lat, center_lat) > height / 2.0
+                || distance(lon, lat, center_lon, lat) > width / 2.0)
+        {
+            return None;
+        }
+        let d = distance(center_lon, center_lat, lon, lat);
+        match *self {
+            Shape::Radius(radius) if d > radius => None,
+            _ => Some(d),
+        }
+    }
+}
 
-    let a =
-        (dlat / 2.0).sin().powi(2) + lat1_rad.cos() * lat2_rad.cos() * (dlon / 2.0).sin().powi(2);
-    let c = 2.0 * a.sqrt().asin();
-    EARTH_RADIUS_M * c
+/// Grid step whose cells are about as large as `reach` meters around `lat`.
+fn estimate_step(reach: f64, lat: f64) -> u32 {
+    if reach == 0.0 {
+        return STEP_MAX;
+    }
+    let mut step = 1i32;
+    let mut range = reach;
+    while range < MERCATOR_MAX {
+        range *= 2.0;
+        step += 1;
+    }
+    // Make sure the range fits in most cases
+    step -= 2;
+    // Cells get narrower towards the poles
+    if lat.abs() > 66.0 {
+        step -= 1;
+        if lat.abs() > 80.0 {
+            step -= 1;
+        }
+    }
+    step.clamp(1, STEP_MAX as i32) as u32
 }
 
-/// Encode longitude/latitude into a 52-bit geohash score (as used by Redis).
-/// The encoding interleaves latitude and longitude bits scaled to the Mercator projection range.
-pub fn geohash_encode(longitude: f64, latitude: f64) -> u64 {
-    // Scale longitude and latitude to Mercator ranges [0, 1]
-    let lon_offset = (longitude - MERCATOR_MIN) / (MERCATOR_MAX - MERCATOR_MIN);
-    let lat_offset = (latitude - MERCATOR_MIN) / (MERCATOR_MAX - MERCATOR_MIN);
+/// The cells covering `shape` around `center`: the cell holding the center
+/// and those of its neighbors that the shape reaches into.
+fn covering_cells(center: (f64, f64), shape: &Shape) -> Vec<Cell> {
+    let (lon, lat) = center;
+    let (half_width, half_height) = shape.half_extent();
+    let lat_delta = (half_height / EARTH_RADIUS_M).to_degrees();
+    // The bounding box is widest on its side nearer the pole
+    let widest_lat = if lat < 0.0 {
+        lat - lat_delta
+    } else {
+        lat + lat_delta
+    };
+    let lon_delta = (half_width / EARTH_RADIUS_M / widest_lat.to_radians().cos()).to_degrees();
+    let bounds = Area {
+        min_lon: lon - lon_delta,
+        max_lon: lon + lon_delta,
+        min_lat: lat - lat_delta,
+        max_lat: lat + lat_delta,
+    };
+
+    let reach = match *shape {
+        Shape::Radius(radius) => radius,
+        Shape::Box { .. } => half_width.hypot(half_height),
+    };
+    let mut step = estimate_step(reach, lat);
+    let mut cell = Cell::encode(lon, lat, step, (LAT_MIN, LAT_MAX));
+    // Near a cell edge the estimate can leave the bounding box poking out
+    // past a neighbor; one step coarser covers it
+    let short = cell.moved(0, 1).area().max_lat < bounds.max_lat
+        || cell.moved(0, -1).area().min_lat > bounds.min_lat
+        || cell.moved(1, 0).area().max_lon < bounds.max_lon
+        || cell.moved(-1, 0).area().min_lon > bounds.min_lon;
+    if step > 1 && short {
+        step -= 1;
+        cell = Cell::encode(lon, lat, step, (LAT_MIN, LAT_MAX));
+    }
 
-    // Quantize to GEO_STEP bits each
-    let max_val = (1u64 << GEO_STEP) - 1;
-    let lon_bits = (lon_offset * max_val as f64) as u64;
-    let lat_bits = (lat_offset * max_val as f64) as u64;
+    let area = cell.area();
+    let mut cells: Vec<Cell> = Vec::with_capacity(9);
+    for (dx, dy) in [
+        (0, 0),
+        (0, 1),
+        (0, -1),
+        (1, 0),
+        (-1, 0),
+        (1, 1),
+        (-1, 1),
+        (1, -1),
+        (-1, -1),
+    ] {
+        // Skip neighbors on a side the center cell already covers
+        let useless = (dy < 0 && area.min_lat < bounds.min_lat)
+            || (dy > 0 && area.max_lat > bounds.max_lat)
+            || (dx < 0 && area.min_lon < bounds.min_lon)
+            || (dx > 0 && area.max_lon > bounds.max_lon);
+        if step >= 2 && useless {
+            continue;
+        }
+        // On a coarse grid neighbors wrap around onto each other
+        let neighbor = cell.moved(dx, dy);
+        if !cells.contains(&neighbor) {
+            cells.push(neighbor);
+        }
+    }
+    cells
+}
 
-    // Interleave: longitude in even bits, latitude in odd bits
-    interleave(lon_bits, lat_bits)
+/// A member found by [`search`].
+#[derive(Debug, Clone)]
+pub struct GeoResult {
+    pub member: Vec<u8>,
+    pub score: f64,
+    pub longitude: f64,
+    pub latitude: f64,
+    /// Distance from the search center in meters.
+    pub distance: f64,
 }
 
-/// Interleave two 26-bit values into a 52-bit value.
-fn interleave(x: u64, y: u64) -> u64 {
-    let mut result = 0u64;
-    for i in 0..GEO_STEP {
-        result |= ((x >> i) & 1) << (2 * i);
-        result |= ((y >> i) & 1) << (2 * i + 1);
+/// Members of `zset` inside `shape` around `center`, in no particular
+/// order. Stops after `limit` members if given.
+pub fn search(
+    zset: &RedisSortedSet,
+    center: (f64, f64),
+    shape: &Shape,
+    limit: Option<usize>,
+) -> Vec<GeoResult> {
+    let mut results = Vec::new();
+    for cell in covering_cells(center, shape) {
+        let (min, max) = cell.scores();
+        // Scores are whole numbers, so the exclusive end is one below
+        for (member, score) in zset.range_by_score(min as f64, (max - 1) as f64) {
+            let (longitude, latitude) = decode(score);
+            if let Some(distance) = shape.distance_if_inside(center, longitude, latitude) {
+                results.push(GeoResult {
+                    member: member.to_vec(),
+                    score,
+                    longitude,
+                    latitude,
+                    distance,
+                });
+                if limit == Some(results.len()) {
+                    return results;
+                }
+            }
+        }
     }
-    result
+    results
 }
 
 /// Convert a unit string to a conversion factor from meters.
@@ -231,3 +346,70 @@ pub fn unit_to_meters(unit: &str) -> Option<f64> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_redis_coding() {
+        // Values Redis itself gives for these positions
+        let palermo = encode(13.361389, 38.115556);
+        assert_eq!(palermo, 3479099956230698);
+        let (lon, lat) = decode(palermo as f64);
+        assert_eq!(format!("{lon:.17}"), "13.36138933897018433");
+        assert_eq!(format!("{lat:.17}"), "38.11555639549629859");
+        assert_eq!(hash_string(lon, lat), "sqc8b49rny0");
+
+        let catania = decode(encode(15.087269, 37.502669) as f64);
+        assert_eq!(
+            format!("{:.4}", distance(lon, lat, catania.0, catania.1)),
+            "166274.1516"
+        );
+    }
+
+    #[test]
+    fn test_search_finds_what_a_scan_finds() {
+        let mut zset = RedisSortedSet::new();
+        // A grid of points every 0.1 degrees around Rome
+        for i in 0..40 {
+            for j in 0..40 {
+                let (lon, lat) = (10.5 + i as f64 * 0.1, 40.0 + j as f64 * 0.1);
+                zset.add(format!("{i}:{j}").into_bytes(), encode(lon, lat) as f64);
+            }
+        }
+        let center = (12.4964, 41.9028);
+        for shape in [
+            Shape::Radius(50_000.0),
+            Shape::Radius(3_000.0),
+            Shape::Box {
+                width: 120_000.0,
+                height: 40_000.0,
+            },
+        ] {
+            let mut found: Vec<Vec<u8>> = search(&zset, center, &shape, None)
+                .into_iter()
+                .map(|r| r.member)
+                .collect();
+            found.sort();
+            let mut expected: Vec<Vec<u8>> = zset
+                .iter()
+                .filter(|(_, score)| {
+                    let (lon, lat) = decode(*score);
+                    shape.distance_if_inside(center, lon, lat).is_some()
+                })
+                .map(|(member, _)| member.to_vec())
+                .collect();
+            expected.sort();
+            assert_eq!(found, expected);
+        }
+        assert_eq!(
+            search(&zset, center, &Shape::Radius(3_000.0), None).len(),
+            1
+        );
+        assert_eq!(
+            search(&zset, center, &Shape::Radius(50_000.0), Some(3)).len(),
+            3
+        );
+    }
+}
@@ -1,57 +1,153 @@
-use std::collections::HashMap;
+use super::dict::Dict;
+use super::encoding;
+use super::listpack::Listpack;
+use super::memory;
 
 /// Redis hash type.
-#[derive(Debug, Clone, Default)]
+///
+/// Small hashes keep their fields and values alternating in a listpack and
+/// move to a hash table once they have more than `hash-max-listpack-entries`
+/// fields or a field or value longer than `hash-max-listpack-value`.
+#[derive(Debug, Clone)]
 pub struct RedisHash {
-    data: HashMap<String, Vec<u8>>,
+    repr: Repr,
+}
+
+#[derive(Debug, Clone)]
+enum Repr {
+    Listpack(Listpack),
+    Table(Dict<String, Vec<u8>>),
+}
+
+impl Default for RedisHash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A listpack field. Fields are only ever inserted as `String`s.
+fn field_str(field: &[u8]) -> &str {
+    std::str::from_utf8(field).expect("listpack hash field is not UTF-8")
+}
+
+/// Offsets of `field` and of its value in a listpack hash.
+fn find(lp: &Listpack, field: &str) -> Option<(usize, usize)> {
+    let mut entries = lp.entries();
+    while let Some((offset, f)) = entries.next() {
+        let (value_offset, _) = entries.next()?;
+        if f == field.as_bytes() {
+            return Some((offset, value_offset));
+        }
+    }
+    None
 }
 
 impl RedisHash {
     pub fn new() -> Self {
         RedisHash {
-            data: HashMap::new(),
+            repr: Repr::Listpack(Listpack::new()),
         }
     }
 
     pub fn len(&self) -> usize {
-        self.data.len()
+        match &self.repr {
+            Repr::Listpack(lp) => lp.len() / 2,
+            Repr::Table(data) => data.len(),
+        }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.len() == 0
+    }
+
+    /// Name of the current encoding, for OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        match &self.repr {
+            Repr::Listpack(_) => "listpack",
+            Repr::Table(_) => "hashtable",
+        }
     }
 
-    pub fn get(&self, field: &str) -> Option<&Vec<u8>> {
-        self.data.get(field)
+    /// Allocations freed by dropping the hash.
+    pub fn free_effort(&self) -> usize {
+        match &self.repr {
+            Repr::Listpack(_) => 1,
+            Repr::Table(data) => data.len(),
+        }
+    }
+
+    pub fn get(&self, field: &str) -> Option<&[u8]> {
+        match &self.repr {
+            Repr::Listpack(lp) => find(lp, field).map(|(_, value)| lp.get(value).0),
+            Repr::Table(data) => data.get(field).map(Vec::as_slice),
+        }
     }
 
     /// Set a field. Returns true if the field is new (didn't exist before).
     pub fn set(&mut self, field: String, value: Vec<u8>) -> bool {
-        self.data.insert(field, value).is_none()
+        let max_value = encoding::hash_max_listpack_value();
+        if let Repr::Listpack(lp) = &mut self.repr {
+            if field.len() <= max_value && value.len() <= max_value {
+                if let Some((_, value_offset)) = find(lp, &field) {
+                    lp.replace(value_offset, &value);
+                    return false;
+                }
+                if lp.len() / 2 < encoding::hash_max_listpack_entries() {
+                    lp.push(field.as_bytes());
+                    lp.push(&value);
+                    return true;
+                }
+            }
+            self.convert();
+        }
+        match &mut self.repr {
+            Repr::Table(data) => data.insert(field, value).is_none(),
+            Repr::Listpack(_) => unreachable!(),
+        }
+    }
+
+    /// Move the fields into a hash table.
+    fn convert(&mut self) {
+        if let Repr::Listpack(_) = &self.repr {
+            let data = self
+                .iter()
+                .map(|(field, value)| (field.to_string(), value.to_vec()))
+                .collect();
+            self.repr = Repr::Table(data);
+        }
     }
 
     pub fn del(&mut self, field: &str) -> bool {
-        self.data.remove(field).is_some()
+        match &mut self.repr {
+            Repr::Listpack(lp) => match find(lp, field) {
+                Some((offset, _)) => {
+                    lp.remove(offset, 2);
+                    true
+                }
+                None => false,
+            },
+            Repr::Table(data) => data.remove(field).is_some(),
+        }
     }
 
     pub fn exists(&self, field: &str) -> bool {
-        self.data.contains_key(field)
+        self.get(field).is_some()
     }
 
-    pub fn keys(&self) -> Vec<&String> {
-        self.data.keys().collect()
+    pub fn keys(&self) -> Vec<&str> {
+        self.iter().map(|(field, _)| field).collect()
     }
 
-    pub fn values(&self) -> Vec<&Vec<u8>> {
-        self.data.values().collect()
+    pub fn values(&self) -> Vec<&[u8]> {
+        self.iter().map(|(_, value)| value).collect()
     }
 
-    pub fn entries(&self) -> Vec<(&String, &Vec<u8>)> {
-        self.data.iter().collect()
+    pub fn entries(&self) -> Vec<(&str, &[u8])> {
+        self.iter().collect()
     }
 
     pub fn incr_by(&mut self, field: &str, delta: i64) -> Result<i64, &'static str> {
-        let current = match self.data.get(field) {
+        let current = match self.get(field) {
             Some(v) => {
                 let s = std::str::from_utf8(v).map_err(|_| "hash value is not an integer")?;
                 s.parse::<i64>()
@@ -62,13 +158,12 @@ impl RedisHash {
         let new_val = current
             .checked_add(delta)
             .ok_or("increment or decrement would overflow")?;
-        self.data
-            .insert(field.to_string(), new_val.to_string().into_bytes());
+        self.set(field.to_string(), new_val.to_string().into_bytes());
         Ok(new_val)
     }
 
     pub fn incr_by_float(&mut self, field: &str, delta: f64) -> Result<f64, &'static str> {
-        let current = match self.data.get(field) {
+        let current = match self.get(field) {
             Some(v) => {
                 let s = std::str::from_utf8(v).map_err(|_| "hash value is not a valid float")?;
                 s.parse::<f64>()
@@ -80,30 +175,88 @@ impl RedisHash {
         if new_val.is_nan() || new_val.is_infinite() {
             return Err("value is NaN or Infinity");
         }
-        self.data
-            .insert(field.to_string(), format!("{new_val}").into_bytes());
+        self.set(field.to_string(), format!("{new_val}").into_bytes());
         Ok(new_val)
     }
 
     pub fn setnx(&mut self, field: String, value: Vec<u8>) -> bool {
-        use std::collections::hash_map::Entry;
-        match self.data.entry(field) {
-            Entry::Occupied(_) => false,
-            Entry::Vacant(e) => {
-                e.insert(value);
-                true
+        if self.exists(&field) {
+            return false;
+        }
+        self.set(field, value);
+        true
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&str, &[u8])> + '_> {
+        match &self.repr {
+            Repr::Listpack(lp) => {
+                let mut entries = lp.entries().map(|(_, entry)| entry);
+                Box::new(std::iter::from_fn(move || {
+                    Some((field_str(entries.next()?), entries.next()?))
+                }))
+            }
+            Repr::Table(data) => Box::new(data.iter().map(|(f, v)| (f.as_str(), v.as_slice()))),
+        }
+    }
+
+    /// Visit about `count` fields starting at `cursor`; returns the cursor to
+    /// resume from, 0 when done. See [`Dict::scan`]. A listpack hash is
+    /// visited whole in one call.
+    pub fn scan(&self, cursor: u64, count: usize, mut f: impl FnMut(&str, &[u8])) -> u64 {
+        match &self.repr {
+            Repr::Listpack(_) => {
+                self.iter().for_each(|(field, value)| f(field, value));
+                0
             }
+            Repr::Table(data) => data.scan(cursor, count, |field, value| f(field, value)),
         }
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<u8>)> {
-        self.data.iter()
+    /// Heap bytes used by the listpack, or by the hash table, its fields and
+    /// values.
+    pub fn memory_usage(&self, samples: usize) -> usize {
+        match &self.repr {
+            Repr::Listpack(lp) => lp.memory_usage(),
+            Repr::Table(data) => {
+                memory::dict::<String, Vec<u8>>(data.capacity(), data.len())
+                    + memory::sampled(data.iter(), data.len(), samples, |(f, v)| {
+                        f.capacity() + v.capacity()
+                    })
+            }
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listpack_converts_past_limits() {
+        let mut h = RedisHash::new();
+        assert!(h.set("a".to_string(), b"1".to_vec()));
+        assert!(!h.set("a".to_string(), b"2".to_vec()));
+        assert_eq!(h.incr_by("a", 5), Ok(7));
+        assert!(h.setnx("b".to_string(), b"x".to_vec()));
+        assert_eq!(h.get("a"), Some(&b"7"[..]));
+        assert!(h.del("b"));
+        assert_eq!(h.entries(), [("a", &b"7"[..])]);
+        assert_eq!(h.encoding(), "listpack");
 
-    /// Check if any field name or value exceeds the given byte length.
-    pub fn has_long_entry(&self, max_bytes: usize) -> bool {
-        self.data
-            .iter()
-            .any(|(k, v)| k.len() > max_bytes || v.len() > max_bytes)
+        h.set(
+            "long".to_string(),
+            vec![b'x'; encoding::hash_max_listpack_value() + 1],
+        );
+        assert_eq!(h.encoding(), "hashtable");
+        assert_eq!(h.get("a"), Some(&b"7"[..]));
+        assert_eq!(h.len(), 2);
+
+        let mut h = RedisHash::new();
+        for i in 0..=encoding::hash_max_listpack_entries() {
+            assert_eq!(h.encoding(), "listpack");
+            h.set(i.to_string(), b"v".to_vec());
+        }
+        assert_eq!(h.encoding(), "hashtable");
+        assert_eq!(h.len(), encoding::hash_max_listpack_entries() + 1);
     }
 }
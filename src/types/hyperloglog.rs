@@ -1,19 +1,66 @@
-// HyperLogLog probabilistic cardinality estimator.
-//
-// Uses 2^14 = 16384 registers, matching Redis's HLL implementation.
-// Each register stores the maximum number of leading zeros + 1 observed
-// for elements hashing to that register.
+//! HyperLogLog cardinality estimator in Redis's byte layout.
+//!
+//! As in Redis, an HLL is a plain string: a 16 byte header, `HYLL`, the
+//! encoding, three unused bytes and the cached cardinality (little endian,
+//! top bit set when stale), followed by 2^14 registers. Each register keeps
+//! the longest run of trailing zeros + 1 seen among the MurmurHash64A
+//! hashes that select it. The dense encoding packs registers in 6 bits
+//! each; the sparse one run-length codes them with the opcodes
+//!
+//! - `00xxxxxx`: 1 to 64 zero registers,
+//! - `01xxxxxx yyyyyyyy`: 1 to 16384 zero registers,
+//! - `1vvvvvxx`: 1 to 4 registers of value 1 to 32.
+//!
+//! New HLLs are sparse and turn dense, never back, when a register needs a
+//! value over 32 or the string would grow past `hll-sparse-max-bytes`.
 
-const HLL_P: usize = 14; // Number of bits used for register index
-const HLL_REGISTERS: usize = 1 << HLL_P; // 16384
-const HLL_P_MASK: u64 = (HLL_REGISTERS as u64) - 1;
+use super::encoding;
 
-/// Threshold below which we use linear counting for small cardinalities.
-const HLL_ALPHA: f64 = 0.7213 / (1.0 + 1.079 / HLL_REGISTERS as f64);
+const HLL_P: u32 = 14;
+/// Hash bits left after the register index.
+const HLL_Q: u32 = 64 - HLL_P;
+pub const HLL_REGISTERS: usize = 1 << HLL_P;
+const HLL_P_MASK: u64 = HLL_REGISTERS as u64 - 1;
+const HLL_BITS: usize = 6;
+const HLL_REGISTER_MAX: u8 = (1 << HLL_BITS) - 1;
+const HLL_ALPHA_INF: f64 = 0.721_347_520_444_481_7;
 
+const MAGIC: &[u8; 4] = b"HYLL";
+const HEADER_SIZE: usize = 16;
+const DENSE_SIZE: usize = HEADER_SIZE + (HLL_REGISTERS * HLL_BITS).div_ceil(8);
+/// Offset of the cached cardinality in the header.
+const CARD_OFFSET: usize = 8;
+
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+
+/// How the registers of an HLL are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Dense,
+    Sparse,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Dense => "dense",
+            Encoding::Sparse => "sparse",
+        }
+    }
+}
+
+/// An HLL whose sparse encoding doesn't add up to 2^14 registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Corrupted;
+
+/// An HLL string. Operations decode the bytes in place; the string can go
+/// back into the keyspace as is.
 #[derive(Debug, Clone)]
 pub struct HyperLogLog {
-    registers: Vec<u8>,
+    bytes: Vec<u8>,
 }
 
 impl Default for HyperLogLog {
@@ -23,104 +70,382 @@ impl Default for HyperLogLog {
 }
 
 impl HyperLogLog {
+    /// An empty HLL: sparse, one run of zero registers.
     pub fn new() -> Self {
-        HyperLogLog {
-            registers: vec![0; HLL_REGISTERS],
+        let mut bytes = header(Encoding::Sparse);
+        push_zeros(&mut bytes, HLL_REGISTERS);
+        HyperLogLog { bytes }
+    }
+
+    /// Take a string holding an HLL, or give it back if it doesn't look
+    /// like one. Sparse data is only checked when decoded.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, Vec<u8>> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return Err(bytes);
+        }
+        match bytes[4] {
+            0 if bytes.len() == DENSE_SIZE => Ok(HyperLogLog { bytes }),
+            1 => Ok(HyperLogLog { bytes }),
+            _ => Err(bytes),
         }
     }
 
-    /// Add an element to the HyperLogLog. Returns true if the internal
-    /// state changed (i.e., the cardinality estimate may have changed).
-    pub fn add(&mut self, element: &[u8]) -> bool {
-        let hash = fnv1a_hash(element);
+    /// Whether `bytes` is a string [`from_bytes`](Self::from_bytes) takes.
+    pub fn is_hll(bytes: &[u8]) -> bool {
+        bytes.len() >= HEADER_SIZE
+            && &bytes[..4] == MAGIC
+            && (bytes[4] == 1 || (bytes[4] == 0 && bytes.len() == DENSE_SIZE))
+    }
 
-        // Use the lower P bits to select the register
-        let index = (hash & HLL_P_MASK) as usize;
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
 
-        // Use the remaining bits to count leading zeros
-        let remaining = hash >> HLL_P;
-        // Count leading zeros in the remaining 50 bits (64 - 14 = 50)
-        // We add 1 because HLL uses "position of first 1-bit" which is leading_zeros + 1
-        let run = count_leading_zeros_50(remaining) + 1;
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
 
-        let old = self.registers[index];
-        if run > old {
-            self.registers[index] = run;
-            true
+    pub fn encoding(&self) -> Encoding {
+        if self.bytes[4] == 0 {
+            Encoding::Dense
         } else {
-            false
+            Encoding::Sparse
+        }
+    }
+
+    /// Add an element. Returns true if a register changed, and so maybe
+    /// the cardinality.
+    pub fn add(&mut self, element: &[u8]) -> Result<bool, Corrupted> {
+        self.add_all(std::iter::once(element))
+    }
+
+    /// Add several elements, decoding a sparse HLL only once. Returns true
+    /// if any register changed.
+    pub fn add_all<'a>(
+        &mut self,
+        elements: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<bool, Corrupted> {
+        let changed = match self.encoding() {
+            Encoding::Dense => {
+                let mut changed = false;
+                for element in elements {
+                    let (index, count) = pattern_len(element);
+                    if count > self.dense_get(index) {
+                        self.dense_set(index, count);
+                        changed = true;
+                    }
+                }
+                changed
+            }
+            Encoding::Sparse => {
+                let mut registers = self.registers()?;
+                let mut changed = false;
+                for element in elements {
+                    let (index, count) = pattern_len(element);
+                    if count > registers[index] {
+                        registers[index] = count;
+                        changed = true;
+                    }
+                }
+                if changed {
+                    *self = Self::from_registers(&registers, Encoding::Sparse);
+                }
+                changed
+            }
+        };
+        if changed {
+            self.invalidate_cache();
+        }
+        Ok(changed)
+    }
+
+    /// Estimated cardinality, from the cache if it is fresh. A stale cache
+    /// is left alone: PFCOUNT isn't replicated, so refreshing it would make
+    /// the stored value differ from the replicas' copy.
+    pub fn count(&self) -> Result<u64, Corrupted> {
+        match self.cached_count() {
+            Some(count) => Ok(count),
+            None => Ok(estimate(&self.registers()?)),
         }
     }
 
-    /// Estimate the cardinality using the HyperLogLog algorithm.
-    pub fn count(&self) -> u64 {
-        // Compute the harmonic mean of 2^(-register[i])
-        let mut sum = 0.0f64;
-        let mut zero_registers = 0u32;
+    fn cached_count(&self) -> Option<u64> {
+        let card: [u8; 8] = self.bytes[CARD_OFFSET..HEADER_SIZE].try_into().ok()?;
+        (card[7] & 0x80 == 0).then(|| u64::from_le_bytes(card))
+    }
 
-        for &val in &self.registers {
-            sum += 2.0f64.powi(-(val as i32));
-            if val == 0 {
-                zero_registers += 1;
+    fn invalidate_cache(&mut self) {
+        self.bytes[HEADER_SIZE - 1] |= 0x80;
+    }
+
+    /// Raise each register of `max` to this HLL's, for PFCOUNT and PFMERGE
+    /// over several keys.
+    pub fn merge_into(&self, max: &mut [u8]) -> Result<(), Corrupted> {
+        for (m, r) in max.iter_mut().zip(self.registers()?) {
+            *m = (*m).max(r);
+        }
+        Ok(())
+    }
+
+    /// All registers, one per byte.
+    pub fn registers(&self) -> Result<Vec<u8>, Corrupted> {
+        match self.encoding() {
+            Encoding::Dense => Ok((0..HLL_REGISTERS).map(|i| self.dense_get(i)).collect()),
+            Encoding::Sparse => {
+                let mut registers = vec![0u8; HLL_REGISTERS];
+                let mut index = 0;
+                for (value, len) in self.sparse_runs() {
+                    let run = registers.get_mut(index..index + len).ok_or(Corrupted)?;
+                    run.fill(value);
+                    index += len;
+                }
+                if index != HLL_REGISTERS {
+                    return Err(Corrupted);
+                }
+                Ok(registers)
             }
         }
+    }
 
-        let m = HLL_REGISTERS as f64;
-        let raw_estimate = HLL_ALPHA * m * m / sum;
+    /// An HLL holding `registers`, sparse if asked for and they fit. Its
+    /// cached cardinality is stale.
+    pub fn from_registers(registers: &[u8], encoding: Encoding) -> Self {
+        let mut hll = match encoding {
+            Encoding::Sparse => Self::sparse_from_registers(registers),
+            Encoding::Dense => None,
+        }
+        .unwrap_or_else(|| {
+            let mut hll = HyperLogLog {
+                bytes: header(Encoding::Dense),
+            };
+            hll.bytes.resize(DENSE_SIZE, 0);
+            for (index, &value) in registers.iter().enumerate() {
+                hll.dense_set(index, value.min(HLL_REGISTER_MAX));
+            }
+            hll
+        });
+        hll.invalidate_cache();
+        hll
+    }
 
-        // Apply corrections
-        if raw_estimate <= 2.5 * m {
-            // Small range correction: use linear counting if there are zero registers
-            if zero_registers > 0 {
-                // Linear counting
-                let lc = m * (m / zero_registers as f64).ln();
-                lc as u64
+    fn sparse_from_registers(registers: &[u8]) -> Option<Self> {
+        let mut bytes = header(Encoding::Sparse);
+        let mut i = 0;
+        while i < registers.len() {
+            let value = registers[i];
+            let run = registers[i..].iter().take_while(|&&r| r == value).count();
+            if value == 0 {
+                push_zeros(&mut bytes, run);
+            } else if value > SPARSE_VAL_MAX_VALUE {
+                return None;
             } else {
-                raw_estimate as u64
+                let mut left = run;
+                while left > 0 {
+                    let len = left.min(SPARSE_VAL_MAX_LEN);
+                    bytes.push(0x80 | (value - 1) << 2 | (len - 1) as u8);
+                    left -= len;
+                }
             }
-        } else if raw_estimate > (1u64 << 32) as f64 / 30.0 {
-            // Large range correction
-            let two_32 = (1u64 << 32) as f64;
-            let corrected = -two_32 * (1.0 - raw_estimate / two_32).ln();
-            corrected as u64
+            i += run;
+        }
+        if bytes.len() > encoding::hll_sparse_max_bytes() {
+            return None;
+        }
+        Some(HyperLogLog { bytes })
+    }
+
+    /// Convert to the dense encoding. Returns false if already dense.
+    pub fn to_dense(&mut self) -> Result<bool, Corrupted> {
+        if self.encoding() == Encoding::Dense {
+            return Ok(false);
+        }
+        let mut dense = Self::from_registers(&self.registers()?, Encoding::Dense);
+        dense.bytes[CARD_OFFSET..HEADER_SIZE]
+            .copy_from_slice(&self.bytes[CARD_OFFSET..HEADER_SIZE]);
+        *self = dense;
+        Ok(true)
+    }
+
+    /// The opcodes of a sparse HLL as `(value, run length)` pairs. A
+    /// truncated opcode ends the runs early.
+    pub fn sparse_runs(&self) -> impl Iterator<Item = (u8, usize)> + '_ {
+        let data = &self.bytes[HEADER_SIZE..];
+        let mut i = 0;
+        std::iter::from_fn(move || {
+            let op = *data.get(i)?;
+            i += 1;
+            Some(match op >> 6 {
+                0b00 => (0, (op & 0x3f) as usize + 1),
+                0b01 => {
+                    let low = *data.get(i)?;
+                    i += 1;
+                    (0, (((op & 0x3f) as usize) << 8 | low as usize) + 1)
+                }
+                _ => (((op >> 2) & 0x1f) + 1, (op & 0x03) as usize + 1),
+            })
+        })
+    }
+
+    /// The opcodes of a sparse HLL spelled out, `z:len` and `Z:len` for
+    /// zero runs and `v:value,len` for the rest, as PFDEBUG DECODE shows
+    /// them.
+    pub fn sparse_opcodes(&self) -> Vec<String> {
+        let data = &self.bytes[HEADER_SIZE..];
+        let mut ops = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let op = data[i];
+            ops.push(match op >> 6 {
+                0b00 => format!("z:{}", (op & 0x3f) as usize + 1),
+                0b01 => {
+                    let low = data.get(i + 1).copied().unwrap_or(0);
+                    i += 1;
+                    format!("Z:{}", (((op & 0x3f) as usize) << 8 | low as usize) + 1)
+                }
+                _ => format!("v:{},{}", ((op >> 2) & 0x1f) + 1, (op & 0x03) + 1),
+            });
+            i += 1;
+        }
+        ops
+    }
+
+    fn dense_get(&self, index: usize) -> u8 {
+        let registers = &self.bytes[HEADER_SIZE..];
+        let byte = index * HLL_BITS / 8;
+        let shift = index * HLL_BITS % 8;
+        let b0 = u16::from(registers[byte]);
+        let b1 = u16::from(registers.get(byte + 1).copied().unwrap_or(0));
+        ((b0 >> shift | b1 << (8 - shift)) as u8) & HLL_REGISTER_MAX
+    }
+
+    fn dense_set(&mut self, index: usize, value: u8) {
+        let registers = &mut self.bytes[HEADER_SIZE..];
+        let byte = index * HLL_BITS / 8;
+        let shift = index * HLL_BITS % 8;
+        let bits = u16::from(value) << shift;
+        let mask = u16::from(HLL_REGISTER_MAX) << shift;
+        registers[byte] = (registers[byte] & !(mask as u8)) | bits as u8;
+        if let Some(next) = registers.get_mut(byte + 1) {
+            *next = (*next & !((mask >> 8) as u8)) | (bits >> 8) as u8;
+        }
+    }
+}
+
+/// A header with a cached cardinality of 0.
+fn header(encoding: Encoding) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + 2);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(match encoding {
+        Encoding::Dense => 0,
+        Encoding::Sparse => 1,
+    });
+    bytes.resize(HEADER_SIZE, 0);
+    bytes
+}
+
+/// Append sparse opcodes for `run` zero registers.
+fn push_zeros(bytes: &mut Vec<u8>, mut run: usize) {
+    while run > 0 {
+        let len = run.min(SPARSE_XZERO_MAX_LEN);
+        if len > SPARSE_ZERO_MAX_LEN {
+            bytes.push(0x40 | ((len - 1) >> 8) as u8);
+            bytes.push(((len - 1) & 0xff) as u8);
         } else {
-            raw_estimate as u64
+            bytes.push((len - 1) as u8);
         }
+        run -= len;
     }
+}
 
-    /// Merge another HyperLogLog into this one by taking the max of each register.
-    pub fn merge(&mut self, other: &Self) {
-        for i in 0..HLL_REGISTERS {
-            if other.registers[i] > self.registers[i] {
-                self.registers[i] = other.registers[i];
-            }
+/// The register an element selects and the value it offers it: the
+/// number of trailing zeros + 1 in the rest of its hash.
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, 0xadc8_3b19);
+    let index = (hash & HLL_P_MASK) as usize;
+    // The sentinel bit caps the count at Q + 1
+    let rest = hash >> HLL_P | 1 << HLL_Q;
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+/// MurmurHash64A, the hash Redis gives HLL elements.
+fn murmurhash64a(data: &[u8], seed: u32) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = u64::from(seed) ^ (data.len() as u64).wrapping_mul(M);
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("8 byte chunk"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= u64::from(byte) << (8 * i);
         }
+        h = h.wrapping_mul(M);
     }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
 }
 
-/// FNV-1a 64-bit hash function.
-fn fnv1a_hash(data: &[u8]) -> u64 {
-    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
-    const FNV_PRIME: u64 = 0x00000100000001B3;
+/// Cardinality of a set of registers, by Ertl's improved estimator as
+/// Redis computes it.
+pub fn estimate(registers: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for &r in registers {
+        histogram[r as usize] += 1;
+    }
+    let m = HLL_REGISTERS as f64;
+    let q = HLL_Q as usize;
+    let mut z = m * tau((m - f64::from(histogram[q + 1])) / m);
+    for j in (1..=q).rev() {
+        z += f64::from(histogram[j]);
+        z *= 0.5;
+    }
+    z += m * sigma(f64::from(histogram[0]) / m);
+    (HLL_ALPHA_INF * m * m / z).round() as u64
+}
 
-    let mut hash = FNV_OFFSET_BASIS;
-    for &byte in data {
-        hash ^= byte as u64;
-        hash = hash.wrapping_mul(FNV_PRIME);
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
     }
-    hash
 }
 
-/// Count leading zeros in the lower 50 bits of a u64.
-/// We only consider 50 bits because 14 bits are used for the register index.
-fn count_leading_zeros_50(value: u64) -> u8 {
-    // We examine bits from position 49 down to 0
-    for i in 0..50 {
-        if (value >> (49 - i)) & 1 == 1 {
-            return i as u8;
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
         }
     }
-    50 // All 50 bits are zero
 }
 
 #[cfg(test)]
@@ -128,35 +453,49 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_new_hll_is_empty() {
+    fn test_new_hll_is_empty_and_sparse() {
         let hll = HyperLogLog::new();
-        assert_eq!(hll.count(), 0);
+        assert_eq!(hll.encoding(), Encoding::Sparse);
+        assert_eq!(hll.as_bytes(), b"HYLL\x01\0\0\0\0\0\0\0\0\0\0\0\x7f\xff");
+        assert_eq!(hll.count(), Ok(0));
     }
 
     #[test]
-    fn test_add_single_element() {
+    fn test_add_returns_false_for_duplicate() {
         let mut hll = HyperLogLog::new();
-        assert!(hll.add(b"hello"));
-        assert!(hll.count() > 0);
+        assert_eq!(hll.add(b"hello"), Ok(true));
+        assert_eq!(hll.add(b"hello"), Ok(false));
+        assert_eq!(hll.count(), Ok(1));
     }
 
     #[test]
-    fn test_add_returns_false_for_duplicate() {
+    fn test_cached_cardinality() {
         let mut hll = HyperLogLog::new();
-        hll.add(b"hello");
-        // Adding the same element again should not change state
-        assert!(!hll.add(b"hello"));
+        hll.add_all([&b"a"[..], b"b", b"c"]).unwrap();
+        // Adding marked the cache stale
+        assert_eq!(hll.as_bytes()[HEADER_SIZE - 1] & 0x80, 0x80);
+        // Counting doesn't refresh it
+        assert_eq!(hll.count(), Ok(3));
+        assert_eq!(hll.as_bytes()[HEADER_SIZE - 1] & 0x80, 0x80);
+
+        // A fresh cache, as Redis leaves it, is used as is
+        let mut bytes = hll.into_bytes();
+        bytes[CARD_OFFSET..HEADER_SIZE].copy_from_slice(&5u64.to_le_bytes());
+        assert!(HyperLogLog::is_hll(&bytes));
+        let hll = HyperLogLog::from_bytes(bytes).unwrap();
+        assert_eq!(hll.cached_count(), Some(5));
+        assert_eq!(hll.count(), Ok(5));
     }
 
     #[test]
-    fn test_cardinality_estimate() {
+    fn test_cardinality_estimate_and_promotion() {
         let mut hll = HyperLogLog::new();
         let n = 10000;
         for i in 0..n {
-            hll.add(format!("element-{i}").as_bytes());
+            hll.add(format!("element-{i}").as_bytes()).unwrap();
         }
-        let estimate = hll.count();
-        // HLL should be within ~2% for this many elements
+        assert_eq!(hll.encoding(), Encoding::Dense);
+        let estimate = hll.count().unwrap();
         let error = (estimate as f64 - n as f64).abs() / n as f64;
         assert!(
             error < 0.05,
@@ -165,40 +504,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_sparse_and_dense_agree() {
+        let mut sparse = HyperLogLog::new();
+        for i in 0..200 {
+            sparse.add(format!("x{i}").as_bytes()).unwrap();
+        }
+        assert_eq!(sparse.encoding(), Encoding::Sparse);
+        let mut dense = sparse.clone();
+        assert_eq!(dense.to_dense(), Ok(true));
+        assert_eq!(dense.as_bytes().len(), DENSE_SIZE);
+        assert_eq!(dense.registers(), sparse.registers());
+        assert_eq!(dense.count(), sparse.count());
+    }
+
     #[test]
     fn test_merge() {
         let mut hll1 = HyperLogLog::new();
         let mut hll2 = HyperLogLog::new();
-
         for i in 0..5000 {
-            hll1.add(format!("a-{i}").as_bytes());
+            hll1.add(format!("a-{i}").as_bytes()).unwrap();
+            hll2.add(format!("b-{i}").as_bytes()).unwrap();
         }
-        for i in 0..5000 {
-            hll2.add(format!("b-{i}").as_bytes());
-        }
-
-        let count1 = hll1.count();
-        let count2 = hll2.count();
-
-        hll1.merge(&hll2);
-        let merged_count = hll1.count();
-
-        // Merged count should be roughly count1 + count2 since they are disjoint
-        assert!(merged_count > count1);
-        assert!(merged_count > count2);
-    }
-
-    #[test]
-    fn test_fnv1a_hash_deterministic() {
-        let h1 = fnv1a_hash(b"test");
-        let h2 = fnv1a_hash(b"test");
-        assert_eq!(h1, h2);
+        let mut max = vec![0; HLL_REGISTERS];
+        hll1.merge_into(&mut max).unwrap();
+        hll2.merge_into(&mut max).unwrap();
+        let merged = HyperLogLog::from_registers(&max, Encoding::Dense).count();
+        assert!(merged.unwrap() > hll1.count().unwrap());
     }
 
     #[test]
-    fn test_fnv1a_hash_different_inputs() {
-        let h1 = fnv1a_hash(b"hello");
-        let h2 = fnv1a_hash(b"world");
-        assert_ne!(h1, h2);
+    fn test_rejects_other_strings() {
+        assert!(HyperLogLog::from_bytes(b"hello".to_vec()).is_err());
+        let mut corrupted = HyperLogLog::new().into_bytes();
+        corrupted.truncate(HEADER_SIZE + 1);
+        let hll = HyperLogLog::from_bytes(corrupted).unwrap();
+        assert_eq!(hll.registers(), Err(Corrupted));
     }
 }
@@ -0,0 +1,162 @@
+//! Intset: a sorted array of integers for sets whose members all are.
+//!
+//! Every value is stored with the same width, the smallest of 2, 4 or 8
+//! bytes that fits all of them, so a set of small numbers takes two bytes
+//! per member. Adding a value that doesn't fit widens the whole array.
+//! Lookups are binary searches.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntSet {
+    /// Bytes per value: 2, 4 or 8.
+    width: usize,
+    /// Little-endian values in ascending order.
+    data: Vec<u8>,
+}
+
+impl Default for IntSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IntSet {
+    pub fn new() -> Self {
+        IntSet {
+            width: 2,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len() / self.width
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Heap bytes held by the array.
+    pub fn memory_usage(&self) -> usize {
+        self.data.capacity()
+    }
+
+    /// Value at `index`.
+    pub fn get(&self, index: usize) -> i64 {
+        let bytes = &self.data[index * self.width..(index + 1) * self.width];
+        match self.width {
+            2 => i16::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            4 => i32::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            _ => i64::from_le_bytes(bytes.try_into().unwrap()),
+        }
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = i64> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    fn search(&self, value: i64) -> Result<usize, usize> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.get(mid).cmp(&value) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Ok(mid),
+            }
+        }
+        Err(low)
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        self.search(value).is_ok()
+    }
+
+    /// Add `value`. Returns true if it was not there yet.
+    pub fn insert(&mut self, value: i64) -> bool {
+        let width = width_of(value);
+        if width > self.width {
+            self.widen(width);
+        }
+        match self.search(value) {
+            Ok(_) => false,
+            Err(index) => {
+                let at = index * self.width;
+                let bytes = value.to_le_bytes();
+                self.data
+                    .splice(at..at, bytes[..self.width].iter().copied());
+                true
+            }
+        }
+    }
+
+    /// Remove `value`. Returns true if it was there.
+    pub fn remove(&mut self, value: i64) -> bool {
+        match self.search(value) {
+            Ok(index) => {
+                self.data
+                    .drain(index * self.width..(index + 1) * self.width);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn widen(&mut self, width: usize) {
+        let mut data = Vec::with_capacity(self.len() * width);
+        for value in self.iter() {
+            data.extend_from_slice(&value.to_le_bytes()[..width]);
+        }
+        self.data = data;
+        self.width = width;
+    }
+}
+
+fn width_of(value: i64) -> usize {
+    if i16::try_from(value).is_ok() {
+        2
+    } else if i32::try_from(value).is_ok() {
+        4
+    } else {
+        8
+    }
+}
+
+/// Parse a set member as an intset value: the canonical decimal form of an
+/// `i64`, so that it reads back as the same bytes.
+pub fn parse_member(member: &[u8]) -> Option<i64> {
+    let s = std::str::from_utf8(member).ok()?;
+    let value: i64 = s.parse().ok()?;
+    (value.to_string() == s).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sorted_and_widened() {
+        let mut set = IntSet::new();
+        for v in [5, -3, 100, 5] {
+            set.insert(v);
+        }
+        assert_eq!(set.iter().collect::<Vec<_>>(), [-3, 5, 100]);
+        assert_eq!(set.width, 2);
+
+        assert!(set.insert(i64::MIN));
+        assert_eq!(set.width, 8);
+        assert_eq!(set.iter().collect::<Vec<_>>(), [i64::MIN, -3, 5, 100]);
+        assert!(set.contains(100));
+        assert!(set.remove(5));
+        assert!(!set.remove(5));
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.iter().next_back(), Some(100));
+    }
+
+    #[test]
+    fn test_parse_member() {
+        assert_eq!(parse_member(b"-42"), Some(-42));
+        assert_eq!(parse_member(b"042"), None);
+        assert_eq!(parse_member(b"+1"), None);
+        assert_eq!(parse_member(b"1.0"), None);
+    }
+}
@@ -1,59 +1,266 @@
+use super::encoding;
+use super::listpack::Listpack;
+use super::lzf;
+use super::memory;
+use std::borrow::Cow;
 use std::collections::VecDeque;
 
-/// Redis list type — implemented using VecDeque for efficient push/pop from both ends.
+/// Nodes smaller than this aren't worth compressing.
+const MIN_COMPRESS_BYTES: usize = 48;
+/// Compressing a node has to save at least this many bytes to be kept.
+const MIN_COMPRESS_IMPROVE: usize = 8;
+/// Largest node when `list-max-listpack-size` counts entries.
+const SIZE_SAFETY_LIMIT: usize = 8192;
+
+/// Redis list type — a quicklist: a deque of listpack nodes, so elements
+/// don't each take their own allocation. Nodes hold as many entries as
+/// `list-max-listpack-size` allows. All but the `list-compress-depth`
+/// nodes at each end are kept LZF-compressed, and decompressed while they
+/// are read or changed. Every node holds at least one entry.
+///
+/// Pushing and popping at either end touches one node. Finding an index
+/// walks the node counts from the nearer end, then the entries of one node.
 #[derive(Debug, Clone, Default)]
 pub struct RedisList {
-    data: VecDeque<Vec<u8>>,
+    nodes: VecDeque<Node>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    count: usize,
+    data: NodeData,
+    /// Compression didn't pay off; not retried until the node changes.
+    incompressible: bool,
+}
+
+#[derive(Debug, Clone)]
+enum NodeData {
+    Packed(Listpack),
+    /// The listpack's `len` bytes, LZF-compressed.
+    Compressed {
+        lzf: Vec<u8>,
+        len: usize,
+    },
+}
+
+impl Node {
+    fn new(lp: Listpack) -> Node {
+        Node {
+            count: lp.len(),
+            data: NodeData::Packed(lp),
+            incompressible: false,
+        }
+    }
+
+    /// Size of the uncompressed entries.
+    fn bytes(&self) -> usize {
+        match &self.data {
+            NodeData::Packed(lp) => lp.len_bytes(),
+            NodeData::Compressed { len, .. } => *len,
+        }
+    }
+
+    /// Whether the node has room for an entry `entry_len` bytes long.
+    fn fits(&self, entry_len: usize) -> bool {
+        let size = self.bytes() + Listpack::encoded_len(entry_len);
+        match encoding::list_max_listpack_size() {
+            fill if fill > 0 => self.count < fill as usize && size <= SIZE_SAFETY_LIMIT,
+            fill => size <= max_node_bytes(fill),
+        }
+    }
+
+    /// The node's entries, decompressed into a copy if need be.
+    fn listpack(&self) -> Cow<'_, Listpack> {
+        match &self.data {
+            NodeData::Packed(lp) => Cow::Borrowed(lp),
+            NodeData::Compressed { lzf, len } => {
+                let buf = lzf::decompress(lzf, *len).expect("corrupt compressed list node");
+                Cow::Owned(Listpack::from_bytes(buf, self.count))
+            }
+        }
+    }
+
+    /// The node's entries for changing them, decompressing the node.
+    fn listpack_mut(&mut self) -> &mut Listpack {
+        self.decompress();
+        self.incompressible = false;
+        match &mut self.data {
+            NodeData::Packed(lp) => lp,
+            NodeData::Compressed { .. } => unreachable!(),
+        }
+    }
+
+    /// Copies of the node's entries if it is compressed, borrows otherwise.
+    fn entries(&self) -> Vec<Cow<'_, [u8]>> {
+        match &self.data {
+            NodeData::Packed(lp) => lp.iter().map(Cow::Borrowed).collect(),
+            NodeData::Compressed { .. } => self
+                .listpack()
+                .iter()
+                .map(|entry| Cow::Owned(entry.to_vec()))
+                .collect(),
+        }
+    }
+
+    /// Insert `value` as the entry at `index`.
+    fn insert(&mut self, index: usize, value: &[u8]) {
+        let lp = self.listpack_mut();
+        let offset = lp.offset_of(index);
+        lp.insert(offset, value);
+        self.count += 1;
+    }
+
+    /// Remove `count` entries starting at `index`.
+    fn remove(&mut self, index: usize, count: usize) {
+        let lp = self.listpack_mut();
+        let offset = lp.offset_of(index);
+        lp.remove(offset, count);
+        self.count -= count;
+    }
+
+    fn compress(&mut self) {
+        if self.incompressible {
+            return;
+        }
+        if let NodeData::Packed(lp) = &self.data {
+            if lp.len_bytes() >= MIN_COMPRESS_BYTES {
+                let compressed = lzf::compress(lp.as_bytes());
+                if compressed.len() + MIN_COMPRESS_IMPROVE <= lp.len_bytes() {
+                    self.data = NodeData::Compressed {
+                        len: lp.len_bytes(),
+                        lzf: compressed,
+                    };
+                    return;
+                }
+            }
+            self.incompressible = true;
+        }
+    }
+
+    fn decompress(&mut self) {
+        if let NodeData::Compressed { .. } = self.data {
+            self.data = NodeData::Packed(self.listpack().into_owned());
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        match &self.data {
+            NodeData::Packed(lp) => lp.memory_usage(),
+            NodeData::Compressed { lzf, .. } => lzf.capacity(),
+        }
+    }
+}
+
+/// Node size limit for a negative `list-max-listpack-size`.
+fn max_node_bytes(fill: i64) -> usize {
+    match fill {
+        -1 => 4096,
+        -3 => 16384,
+        -4 => 32768,
+        -5 => 65536,
+        _ => 8192,
+    }
 }
 
 impl RedisList {
     pub fn new() -> Self {
-        RedisList {
-            data: VecDeque::new(),
-        }
+        Self::default()
     }
 
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.len
     }
 
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.len == 0
+    }
+
+    /// Name of the current encoding, for OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        if self.nodes.len() <= 1 {
+            "listpack"
+        } else {
+            "quicklist"
+        }
+    }
+
+    /// Allocations freed by dropping the list.
+    pub fn free_effort(&self) -> usize {
+        self.nodes.len()
     }
 
     pub fn lpush(&mut self, value: Vec<u8>) {
-        self.data.push_front(value);
+        if !self.nodes.front().is_some_and(|n| n.fits(value.len())) {
+            self.nodes.push_front(Node::new(Listpack::new()));
+        }
+        self.nodes[0].insert(0, &value);
+        self.len += 1;
+        self.settle_ends();
     }
 
     pub fn rpush(&mut self, value: Vec<u8>) {
-        self.data.push_back(value);
+        if !self.nodes.back().is_some_and(|n| n.fits(value.len())) {
+            self.nodes.push_back(Node::new(Listpack::new()));
+        }
+        let node = self.nodes.back_mut().unwrap();
+        node.listpack_mut().push(&value);
+        node.count += 1;
+        self.len += 1;
+        self.settle_ends();
     }
 
     pub fn lpop(&mut self) -> Option<Vec<u8>> {
-        self.data.pop_front()
+        let node = self.nodes.front_mut()?;
+        let value = node.listpack_mut().get(0).0.to_vec();
+        node.remove(0, 1);
+        if node.count == 0 {
+            self.nodes.pop_front();
+        }
+        self.len -= 1;
+        self.settle_ends();
+        Some(value)
     }
 
     pub fn rpop(&mut self) -> Option<Vec<u8>> {
-        self.data.pop_back()
+        let node = self.nodes.back_mut()?;
+        let last = node.count - 1;
+        let lp = node.listpack_mut();
+        let value = lp.get(lp.offset_of(last)).0.to_vec();
+        node.remove(last, 1);
+        if node.count == 0 {
+            self.nodes.pop_back();
+        }
+        self.len -= 1;
+        self.settle_ends();
+        Some(value)
     }
 
-    pub fn lindex(&self, index: i64) -> Option<&Vec<u8>> {
+    pub fn lindex(&self, index: i64) -> Option<Vec<u8>> {
         let idx = self.resolve_index(index)?;
-        self.data.get(idx)
+        if idx >= self.len {
+            return None;
+        }
+        let (n, k) = self.locate(idx);
+        let lp = self.nodes[n].listpack();
+        Some(lp.get(lp.offset_of(k)).0.to_vec())
     }
 
     pub fn lset(&mut self, index: i64, value: Vec<u8>) -> bool {
         if let Some(idx) = self.resolve_index(index)
-            && idx < self.data.len()
+            && idx < self.len
         {
-            self.data[idx] = value;
+            let (n, k) = self.locate(idx);
+            let lp = self.nodes[n].listpack_mut();
+            lp.replace(lp.offset_of(k), &value);
+            self.settle(n);
             return true;
         }
         false
     }
 
-    pub fn lrange(&self, start: i64, stop: i64) -> Vec<&Vec<u8>> {
-        let len = self.data.len() as i64;
+    pub fn lrange(&self, start: i64, stop: i64) -> Vec<Vec<u8>> {
+        let len = self.len as i64;
         let start = if start < 0 {
             (len + start).max(0)
         } else {
@@ -61,51 +268,75 @@ impl RedisList {
         } as usize;
         let stop = if stop < 0 { (len + stop).max(0) } else { stop } as usize;
 
-        if start > stop || start >= self.data.len() {
+        if start > stop || start >= self.len {
             return vec![];
         }
 
-        let stop = stop.min(self.data.len() - 1);
-        (start..=stop).filter_map(|i| self.data.get(i)).collect()
+        let stop = stop.min(self.len - 1);
+        let (n, k) = self.locate(start);
+        let mut items = Vec::with_capacity(stop - start + 1);
+        let mut skip = k;
+        for node in self.nodes.range(n..) {
+            let lp = node.listpack();
+            for (_, entry) in lp.entries().skip(skip) {
+                items.push(entry.to_vec());
+                if items.len() == stop - start + 1 {
+                    return items;
+                }
+            }
+            skip = 0;
+        }
+        items
     }
 
     pub fn lrem(&mut self, count: i64, value: &[u8]) -> i64 {
-        let mut removed = 0i64;
-        if count > 0 {
-            let mut i = 0;
-            while i < self.data.len() && removed < count {
-                if self.data[i] == value {
-                    self.data.remove(i);
-                    removed += 1;
-                } else {
-                    i += 1;
-                }
+        let limit = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+        let order: Vec<usize> = if count < 0 {
+            (0..self.nodes.len()).rev().collect()
+        } else {
+            (0..self.nodes.len()).collect()
+        };
+        let mut removed = 0;
+        for n in order {
+            if removed == limit {
+                break;
             }
-        } else if count < 0 {
-            let target = -count;
-            let mut i = self.data.len();
-            while i > 0 && removed < target {
-                i -= 1;
-                if self.data[i] == value {
-                    self.data.remove(i);
-                    removed += 1;
-                }
+            let matches: Vec<usize> = self.nodes[n]
+                .listpack()
+                .entries()
+                .filter(|(_, entry)| *entry == value)
+                .map(|(offset, _)| offset)
+                .collect();
+            let take = matches.len().min(limit - removed);
+            if take == 0 {
+                continue;
             }
-        } else {
-            self.data.retain(|v| {
-                if v == value {
-                    removed += 1;
-                    false
-                } else {
-                    true
-                }
-            });
+            let chosen = if count < 0 {
+                &matches[matches.len() - take..]
+            } else {
+                &matches[..take]
+            };
+            let node = &mut self.nodes[n];
+            let lp = node.listpack_mut();
+            // Back to front, so the offsets before each one stay valid
+            for &offset in chosen.iter().rev() {
+                lp.remove(offset, 1);
+            }
+            node.count -= take;
+            removed += take;
         }
-        removed
+        self.nodes.retain(|node| node.count > 0);
+        self.len -= removed;
+        self.settle_all();
+        removed as i64
     }
 
     pub fn ltrim(&mut self, start: i64, stop: i64) {
-        let len = self.data.len() as i64;
+        let len = self.len as i64;
         let start = if start < 0 {
             (len + start).max(0)
         } else {
@@ -113,36 +344,120 @@ impl RedisList {
         } as usize;
         let stop = if stop < 0 { (len + stop).max(0) } else { stop } as usize;
 
-        if start > stop || start >= self.data.len() {
-            self.data.clear();
+        if start > stop || start >= self.len {
+            self.nodes.clear();
+            self.len = 0;
             return;
         }
 
-        let stop = stop.min(self.data.len() - 1);
-        let new_data: VecDeque<Vec<u8>> = self.data.drain(start..=stop).collect();
-        self.data = new_data;
+        let stop = stop.min(self.len - 1);
+        self.remove_front(start);
+        self.remove_back(self.len - (stop - start + 1));
+        self.settle_ends();
     }
 
-    pub fn linsert_before(&mut self, pivot: &[u8], value: Vec<u8>) -> Option<usize> {
-        if let Some(pos) = self.data.iter().position(|v| v.as_slice() == pivot) {
-            self.data.insert(pos, value);
-            Some(self.data.len())
-        } else {
-            None
+    fn remove_front(&mut self, mut count: usize) {
+        self.len -= count;
+        while count > 0 {
+            let node = self.nodes.front_mut().unwrap();
+            if node.count <= count {
+                count -= node.count;
+                self.nodes.pop_front();
+            } else {
+                node.remove(0, count);
+                count = 0;
+            }
         }
     }
 
+    fn remove_back(&mut self, mut count: usize) {
+        self.len -= count;
+        while count > 0 {
+            let node = self.nodes.back_mut().unwrap();
+            if node.count <= count {
+                count -= node.count;
+                self.nodes.pop_back();
+            } else {
+                node.remove(node.count - count, count);
+                count = 0;
+            }
+        }
+    }
+
+    pub fn linsert_before(&mut self, pivot: &[u8], value: Vec<u8>) -> Option<usize> {
+        let pos = self.position(pivot)?;
+        self.insert(pos, value);
+        Some(self.len)
+    }
+
     pub fn linsert_after(&mut self, pivot: &[u8], value: Vec<u8>) -> Option<usize> {
-        if let Some(pos) = self.data.iter().position(|v| v.as_slice() == pivot) {
-            self.data.insert(pos + 1, value);
-            Some(self.data.len())
+        let pos = self.position(pivot)?;
+        self.insert(pos + 1, value);
+        Some(self.len)
+    }
+
+    /// Index of the first element equal to `value`.
+    fn position(&self, value: &[u8]) -> Option<usize> {
+        let mut index = 0;
+        for node in &self.nodes {
+            if let Some(k) = node.listpack().iter().position(|entry| entry == value) {
+                return Some(index + k);
+            }
+            index += node.count;
+        }
+        None
+    }
+
+    /// Insert `value` so that it ends up at `index` (at most `len`).
+    fn insert(&mut self, index: usize, value: Vec<u8>) {
+        if index == 0 {
+            return self.lpush(value);
+        }
+        if index == self.len {
+            return self.rpush(value);
+        }
+        // Insert right after the element before it, which may be the last
+        // of its node
+        let (mut n, k) = self.locate(index - 1);
+        let k = k + 1;
+        let at_end = k == self.nodes[n].count;
+        if self.nodes[n].fits(value.len()) {
+            self.nodes[n].insert(k, &value);
+        } else if at_end
+            && self
+                .nodes
+                .get(n + 1)
+                .is_some_and(|next| next.fits(value.len()))
+        {
+            n += 1;
+            self.nodes[n].insert(0, &value);
         } else {
-            None
+            // Split the full node at the insertion point
+            if !at_end {
+                let lp = self.nodes[n].listpack_mut();
+                let offset = lp.offset_of(k);
+                let tail = lp.split_off(offset);
+                self.nodes[n].count = k;
+                self.nodes.insert(n + 1, Node::new(tail));
+            }
+            if self.nodes[n].fits(value.len()) {
+                self.nodes[n].insert(k, &value);
+            } else {
+                let mut lp = Listpack::new();
+                lp.push(&value);
+                n += 1;
+                self.nodes.insert(n, Node::new(lp));
+            }
         }
+        self.len += 1;
+        for i in n.saturating_sub(1)..n + 2 {
+            self.settle(i);
+        }
+        self.settle_ends();
     }
 
     pub fn lpos(&self, value: &[u8], rank: i64, count: Option<i64>, maxlen: usize) -> Vec<usize> {
-        let len = self.data.len();
+        let len = self.len;
         let limit = if maxlen == 0 { len } else { maxlen.min(len) };
 
         let mut results = Vec::new();
@@ -150,42 +465,28 @@ impl RedisList {
         // If count is 0, return ALL matches
         let unlimited = max_count == 0;
 
-        if rank >= 0 {
+        let (skip, forward) = if rank >= 0 {
             // Forward scan, skip (rank-1) matches
-            let skip = if rank == 0 { 0 } else { (rank - 1) as usize };
-            let mut skipped = 0;
-            for (scanned, (i, v)) in self.data.iter().enumerate().enumerate() {
-                if scanned >= limit {
-                    break;
-                }
-                if v.as_slice() == value {
-                    if skipped < skip {
-                        skipped += 1;
-                        continue;
-                    }
-                    results.push(i);
-                    if !unlimited && results.len() >= max_count as usize {
-                        break;
-                    }
-                }
-            }
+            (if rank == 0 { 0 } else { (rank - 1) as usize }, true)
         } else {
             // Reverse scan
-            let skip = ((-rank) - 1) as usize;
-            let mut skipped = 0;
-            for (scanned, i) in (0..len).rev().enumerate() {
-                if scanned >= limit {
-                    break;
+            (((-rank) - 1) as usize, false)
+        };
+        let elements: Box<dyn Iterator<Item = Cow<'_, [u8]>>> = if forward {
+            Box::new(self.iter())
+        } else {
+            Box::new(self.iter().rev())
+        };
+        let mut skipped = 0;
+        for (scanned, v) in elements.take(limit).enumerate() {
+            if v.as_ref() == value {
+                if skipped < skip {
+                    skipped += 1;
+                    continue;
                 }
-                if self.data[i].as_slice() == value {
-                    if skipped < skip {
-                        skipped += 1;
-                        continue;
-                    }
-                    results.push(i);
-                    if !unlimited && results.len() >= max_count as usize {
-                        break;
-                    }
+                results.push(if forward { scanned } else { len - 1 - scanned });
+                if !unlimited && results.len() >= max_count as usize {
+                    break;
                 }
             }
         }
@@ -193,12 +494,12 @@ impl RedisList {
         results
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &Vec<u8>> {
-        self.data.iter()
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Cow<'_, [u8]>> {
+        self.nodes.iter().flat_map(Node::entries)
     }
 
     fn resolve_index(&self, index: i64) -> Option<usize> {
-        let len = self.data.len() as i64;
+        let len = self.len as i64;
         if index < 0 {
             let idx = len + index;
             if idx < 0 { None } else { Some(idx as usize) }
@@ -206,4 +507,145 @@ impl RedisList {
             Some(index as usize)
         }
     }
+
+    /// Node holding element `index` (< len), and the element's index in it.
+    fn locate(&self, index: usize) -> (usize, usize) {
+        if index < self.len / 2 {
+            let mut index = index;
+            for (n, node) in self.nodes.iter().enumerate() {
+                if index < node.count {
+                    return (n, index);
+                }
+                index -= node.count;
+            }
+        } else {
+            let mut from_end = self.len - 1 - index;
+            for (n, node) in self.nodes.iter().enumerate().rev() {
+                if from_end < node.count {
+                    return (n, node.count - 1 - from_end);
+                }
+                from_end -= node.count;
+            }
+        }
+        unreachable!("list index out of range")
+    }
+
+    /// Compress node `n` if it is an interior node, decompress it if it
+    /// is within `list-compress-depth` of an end.
+    fn settle(&mut self, n: usize) {
+        self.settle_at(n, encoding::list_compress_depth());
+    }
+
+    fn settle_at(&mut self, n: usize, depth: usize) {
+        let count = self.nodes.len();
+        if let Some(node) = self.nodes.get_mut(n) {
+            if depth > 0 && n >= depth && n + depth < count {
+                node.compress();
+            } else {
+                node.decompress();
+            }
+        }
+    }
+
+    /// Settle the nodes whose place relative to the compress depth changes
+    /// when nodes come or go at the ends.
+    fn settle_ends(&mut self) {
+        let depth = encoding::list_compress_depth();
+        let count = self.nodes.len();
+        for n in (0..=depth).chain(count.saturating_sub(depth + 1)..count) {
+            self.settle(n);
+        }
+    }
+
+    fn settle_all(&mut self) {
+        for n in 0..self.nodes.len() {
+            self.settle(n);
+        }
+    }
+
+    /// Heap bytes used by the node deque and the nodes' buffers.
+    pub fn memory_usage(&self, samples: usize) -> usize {
+        self.nodes.capacity() * std::mem::size_of::<Node>()
+            + memory::sampled(
+                self.nodes.iter(),
+                self.nodes.len(),
+                samples,
+                Node::memory_usage,
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list_of(n: usize) -> RedisList {
+        let mut list = RedisList::new();
+        for i in 0..n {
+            list.rpush(format!("element-{i}").into_bytes());
+        }
+        list
+    }
+
+    fn element(i: usize) -> Vec<u8> {
+        format!("element-{i}").into_bytes()
+    }
+
+    #[test]
+    fn test_quicklist_nodes() {
+        let mut list = list_of(5000);
+        assert_eq!(list.len(), 5000);
+        assert!(list.nodes.len() >= 8);
+        assert_eq!(list.encoding(), "quicklist");
+        assert!(list.nodes.iter().all(|n| n.bytes() <= 8192));
+
+        assert_eq!(list.lindex(0), Some(element(0)));
+        assert_eq!(list.lindex(2500), Some(element(2500)));
+        assert_eq!(list.lindex(-1), Some(element(4999)));
+        assert_eq!(list.lindex(5000), None);
+        assert_eq!(
+            list.lrange(1998, 2001),
+            (1998..=2001).map(element).collect::<Vec<_>>()
+        );
+
+        list.lpush(b"head".to_vec());
+        assert_eq!(list.lpop(), Some(b"head".to_vec()));
+        assert_eq!(list.rpop(), Some(element(4999)));
+        assert!(list.lset(100, b"changed".to_vec()));
+        assert_eq!(list.lindex(100), Some(b"changed".to_vec()));
+
+        assert_eq!(
+            list.linsert_after(&element(3000), b"new".to_vec()),
+            Some(5000)
+        );
+        assert_eq!(list.lindex(3001), Some(b"new".to_vec()));
+        assert_eq!(list.lpos(b"new", 1, None, 0), [3001]);
+        assert_eq!(list.lpos(&element(0), -1, None, 0), [0]);
+
+        list.ltrim(1000, 2999);
+        assert_eq!(list.len(), 2000);
+        assert_eq!(list.lindex(0), Some(element(1000)));
+        assert_eq!(list.iter().next_back().as_deref(), Some(&element(2999)[..]));
+        assert_eq!(list.iter().count(), 2000);
+    }
+
+    #[test]
+    fn test_interior_nodes_compressed() {
+        let mut list = list_of(5000);
+        let before = list.memory_usage(0);
+        let count = list.nodes.len();
+        for n in 0..count {
+            list.settle_at(n, 1);
+        }
+
+        let compressed = |node: &Node| matches!(node.data, NodeData::Compressed { .. });
+        assert!(!compressed(&list.nodes[0]) && !compressed(&list.nodes[count - 1]));
+        assert!(list.nodes.range(1..count - 1).all(compressed));
+        assert!(list.memory_usage(0) < before * 3 / 4);
+
+        assert_eq!(list.lindex(2500), Some(element(2500)));
+        assert_eq!(list.lrem(0, &element(2500)), 1);
+        assert_eq!(list.lindex(2500), Some(element(2501)));
+        assert_eq!(list.iter().count(), 4999);
+    }
 }
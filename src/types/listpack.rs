@@ -0,0 +1,214 @@
+//! Listpack: a sequence of small byte strings packed into one buffer.
+//!
+//! Small hashes, sets and sorted sets keep their elements here instead of in
+//! a hash table, where every element costs a node, a bucket slot and its own
+//! heap allocations. An entry is its length as a LEB128 varint followed by
+//! its bytes. Lookups are linear scans, which is why collections are only
+//! kept in a listpack while they are small (see [`super::encoding`]).
+//!
+//! Positions are byte offsets of entries in the buffer, as returned by
+//! [`Listpack::entries`]; `len_bytes()` is the offset just past the end.
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Listpack {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl Listpack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Size of the encoded entries; the offset of a new entry appended at
+    /// the end.
+    pub fn len_bytes(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// The encoded entries.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// A listpack of the `len` entries encoded in `buf`, as returned by
+    /// [`Listpack::as_bytes`].
+    pub fn from_bytes(buf: Vec<u8>, len: usize) -> Self {
+        Listpack { buf, len }
+    }
+
+    /// Heap bytes held by the buffer.
+    pub fn memory_usage(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &[u8]> + '_ {
+        // Entries can only be walked forwards; reversing needs them collected
+        self.entries()
+            .map(|(_, entry)| entry)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Every entry with its offset, front to back.
+    pub fn entries(&self) -> Entries<'_> {
+        Entries {
+            buf: &self.buf,
+            offset: 0,
+        }
+    }
+
+    /// The entry at `offset` and the offset of the one after it.
+    pub fn get(&self, offset: usize) -> (&[u8], usize) {
+        let (len, header) = read_varint(&self.buf[offset..]);
+        let start = offset + header;
+        (&self.buf[start..start + len], start + len)
+    }
+
+    /// Bytes an entry `len` bytes long takes in a listpack.
+    pub fn encoded_len(len: usize) -> usize {
+        let mut header = 1;
+        while len >> (7 * header) != 0 {
+            header += 1;
+        }
+        header + len
+    }
+
+    pub fn push(&mut self, entry: &[u8]) {
+        write_varint(&mut self.buf, entry.len());
+        self.buf.extend_from_slice(entry);
+        self.len += 1;
+    }
+
+    /// Insert `entry` before the entry at `offset`.
+    pub fn insert(&mut self, offset: usize, entry: &[u8]) {
+        let mut encoded = Vec::with_capacity(entry.len() + 2);
+        write_varint(&mut encoded, entry.len());
+        encoded.extend_from_slice(entry);
+        self.buf.splice(offset..offset, encoded);
+        self.len += 1;
+    }
+
+    /// Replace the entry at `offset` with `entry`.
+    pub fn replace(&mut self, offset: usize, entry: &[u8]) {
+        let (_, end) = self.get(offset);
+        let mut encoded = Vec::with_capacity(entry.len() + 2);
+        write_varint(&mut encoded, entry.len());
+        encoded.extend_from_slice(entry);
+        self.buf.splice(offset..end, encoded);
+    }
+
+    /// Move the entries from `offset` on into a new listpack.
+    pub fn split_off(&mut self, offset: usize) -> Listpack {
+        let buf = self.buf.split_off(offset);
+        let tail = Listpack {
+            len: Entries {
+                buf: &buf,
+                offset: 0,
+            }
+            .count(),
+            buf,
+        };
+        self.len -= tail.len;
+        tail
+    }
+
+    /// Offset of the entry at `index`, or the end offset if there are only
+    /// `index` entries.
+    pub fn offset_of(&self, index: usize) -> usize {
+        self.entries()
+            .nth(index)
+            .map_or(self.buf.len(), |(offset, _)| offset)
+    }
+
+    /// Remove `count` consecutive entries starting at `offset`.
+    pub fn remove(&mut self, offset: usize, count: usize) {
+        let mut end = offset;
+        for _ in 0..count {
+            end = self.get(end).1;
+        }
+        self.buf.drain(offset..end);
+        self.len -= count;
+    }
+}
+
+/// Iterator over `(offset, entry)` pairs of a [`Listpack`].
+pub struct Entries<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = (usize, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.buf.len() {
+            return None;
+        }
+        let offset = self.offset;
+        let (len, header) = read_varint(&self.buf[offset..]);
+        let start = offset + header;
+        self.offset = start + len;
+        Some((offset, &self.buf[start..start + len]))
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// Decode a varint, returning its value and encoded size.
+fn read_varint(buf: &[u8]) -> (usize, usize) {
+    let mut n = 0;
+    for (i, &byte) in buf.iter().enumerate() {
+        n |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return (n, i + 1);
+        }
+    }
+    panic!("truncated listpack entry");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_entries() {
+        let mut lp = Listpack::new();
+        lp.push(b"a");
+        lp.push(&[b'x'; 200]);
+        lp.push(b"c");
+        assert_eq!(lp.len(), 3);
+        // 1 + 1, 2 + 200, 1 + 1
+        assert_eq!(lp.len_bytes(), 206);
+
+        let offsets: Vec<usize> = lp.entries().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, [0, 2, 204]);
+        lp.replace(2, b"b");
+        lp.insert(0, b"start");
+        lp.insert(lp.len_bytes(), b"end");
+        let entries: Vec<&[u8]> = lp.iter().collect();
+        assert_eq!(entries, [&b"start"[..], b"a", b"b", b"c", b"end"]);
+        assert_eq!(lp.iter().next_back(), Some(&b"end"[..]));
+
+        let (_, second) = lp.get(0);
+        lp.remove(second, 2);
+        let entries: Vec<&[u8]> = lp.iter().collect();
+        assert_eq!(entries, [&b"start"[..], b"c", b"end"]);
+        assert_eq!(lp.len(), 3);
+    }
+}
@@ -0,0 +1,141 @@
+//! LZF compression, the format Redis uses for compressed quicklist nodes.
+//!
+//! The output is a series of chunks, each starting with a control byte:
+//!
+//! - `000LLLLL`: a run of `L + 1` literal bytes follows.
+//! - `LLLOOOOO` with `LLL` > 0: copy `LLL + 2` bytes starting `O + 1` bytes
+//!   back in the output, where `O` continues into the next byte. `LLL` = 7
+//!   takes one more byte that is added to the length, before the offset
+//!   byte.
+//!
+//! The compressor finds back references through a hash table of the last
+//! position of every 3-byte sequence, like liblzf's fast mode.
+
+const HASH_LOG: u32 = 14;
+/// Longest literal run of one chunk.
+const MAX_LITERAL: usize = 1 << 5;
+/// Furthest back a reference reaches.
+const MAX_OFFSET: usize = 1 << 13;
+/// Longest back reference.
+const MAX_REF: usize = (1 << 8) + (1 << 3);
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+    (v.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn push_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for run in literals.chunks(MAX_LITERAL) {
+        out.push((run.len() - 1) as u8);
+        out.extend_from_slice(run);
+    }
+}
+
+/// Compress `input`. The result may be longer than the input when it has
+/// nothing to compress.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut table = vec![0u32; 1 << HASH_LOG];
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    let mut literal_start = 0;
+    let mut pos = 0;
+    while pos + 3 <= input.len() {
+        let slot = &mut table[hash(&input[pos..])];
+        // Positions are stored plus one, so 0 means empty
+        let candidate = std::mem::replace(slot, pos as u32 + 1) as usize;
+        if candidate > 0 {
+            let from = candidate - 1;
+            let offset = pos - from - 1;
+            if offset < MAX_OFFSET && input[from..from + 3] == input[pos..pos + 3] {
+                let max_len = (input.len() - pos).min(MAX_REF);
+                let mut len = 3;
+                while len < max_len && input[from + len] == input[pos + len] {
+                    len += 1;
+                }
+                push_literals(&mut out, &input[literal_start..pos]);
+                let stored = len - 2;
+                if stored < 7 {
+                    out.push((stored << 5) as u8 | (offset >> 8) as u8);
+                } else {
+                    out.push((7 << 5) | (offset >> 8) as u8);
+                    out.push((stored - 7) as u8);
+                }
+                out.push(offset as u8);
+                pos += len;
+                literal_start = pos;
+                continue;
+            }
+        }
+        pos += 1;
+    }
+    push_literals(&mut out, &input[literal_start..]);
+    out
+}
+
+/// Decompress `input` into its original `len` bytes. Returns `None` if it
+/// isn't valid LZF of that length.
+pub fn decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0;
+    while pos < input.len() {
+        let ctrl = input[pos] as usize;
+        pos += 1;
+        if ctrl < 32 {
+            let run = input.get(pos..pos + ctrl + 1)?;
+            out.extend_from_slice(run);
+            pos += run.len();
+        } else {
+            let mut copy = ctrl >> 5;
+            if copy == 7 {
+                copy += *input.get(pos)? as usize;
+                pos += 1;
+            }
+            copy += 2;
+            let offset = ((ctrl & 0x1f) << 8 | *input.get(pos)? as usize) + 1;
+            pos += 1;
+            let start = out.len().checked_sub(offset)?;
+            // The source may overlap what is being written
+            for i in start..start + copy {
+                out.push(out[i]);
+            }
+        }
+        if out.len() > len {
+            return None;
+        }
+    }
+    (out.len() == len).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let repetitive: Vec<u8> = b"queue-item-".repeat(500);
+        let compressed = compress(&repetitive);
+        assert!(compressed.len() < repetitive.len() / 10);
+        assert_eq!(
+            decompress(&compressed, repetitive.len()).as_deref(),
+            Some(&repetitive[..])
+        );
+
+        let noise: Vec<u8> = (0..5000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let compressed = compress(&noise);
+        assert_eq!(decompress(&compressed, noise.len()), Some(noise));
+
+        for input in [
+            &b""[..],
+            b"a",
+            b"ab",
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+        ] {
+            assert_eq!(
+                decompress(&compress(input), input.len()).as_deref(),
+                Some(input)
+            );
+        }
+        assert_eq!(decompress(&[0x20, 0], 3), None);
+    }
+}
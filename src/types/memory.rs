@@ -0,0 +1,79 @@
+//! Heap size of the value structures, for MEMORY USAGE and the dataset
+//! figures in INFO and MEMORY STATS.
+//!
+//! Sizes follow the containers' real layouts (capacities, hash table buckets,
+//! B-tree nodes) rather than per-type constants. Aggregates with more than
+//! `samples` elements are measured on the first `samples` elements and the
+//! average is scaled up, like Redis does; `samples == 0` walks everything.
+
+use std::mem::size_of;
+
+/// Heap bytes of a hashbrown table (`HashMap`/`HashSet`) with `capacity`
+/// usable slots holding `(K, V)` pairs.
+pub fn hash_table<K, V>(capacity: usize) -> usize {
+    if capacity == 0 {
+        return 0;
+    }
+    let buckets = if capacity < 8 {
+        (capacity + 1).next_power_of_two()
+    } else {
+        (capacity * 8 / 7).next_power_of_two()
+    };
+    // One control byte per bucket plus a trailing group for SIMD probing
+    buckets * size_of::<(K, V)>() + buckets + 16
+}
+
+/// Heap bytes of a [`Dict`](super::dict::Dict) with `buckets` buckets holding
+/// `len` `(K, V)` entries: one pointer per bucket and a boxed node per entry.
+pub fn dict<K, V>(buckets: usize, len: usize) -> usize {
+    buckets * size_of::<usize>() + len * (size_of::<(K, V)>() + size_of::<usize>())
+}
+
+/// Heap bytes of a `BTreeMap<K, V>` with `len` entries.
+pub fn btree<K, V>(len: usize) -> usize {
+    // Nodes hold up to 11 entries and are about two thirds full on average;
+    // internal nodes add 12 child pointers
+    const CAPACITY: usize = 11;
+    let node = 16 + CAPACITY * (size_of::<K>() + size_of::<V>());
+    let leaves = len.div_ceil(CAPACITY * 2 / 3);
+    let internal = leaves / (CAPACITY * 2 / 3);
+    leaves * node + internal * (node + (CAPACITY + 1) * size_of::<usize>())
+}
+
+/// Sum `f` over the elements of an aggregate with `len` elements, measuring
+/// at most `samples` of them (all when `samples` is 0) and extrapolating.
+pub fn sampled<T>(
+    iter: impl Iterator<Item = T>,
+    len: usize,
+    samples: usize,
+    f: impl Fn(T) -> usize,
+) -> usize {
+    if samples == 0 || len <= samples {
+        return iter.map(f).sum();
+    }
+    let total: usize = iter.take(samples).map(f).sum();
+    total * len / samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampled_extrapolates() {
+        let items = [10usize, 20, 30, 40];
+        assert_eq!(sampled(items.iter(), 4, 0, |n| *n), 100);
+        assert_eq!(sampled(items.iter(), 4, 8, |n| *n), 100);
+        assert_eq!(sampled(items.iter(), 4, 2, |n| *n), 60);
+    }
+
+    #[test]
+    fn test_container_sizes() {
+        assert_eq!(hash_table::<u64, u64>(0), 0);
+        // 3 usable slots live in 4 buckets
+        assert_eq!(hash_table::<u64, u64>(3), 4 * 16 + 4 + 16);
+        assert!(hash_table::<u64, u64>(100) >= 100 * 16);
+        assert_eq!(btree::<u64, ()>(0), 0);
+        assert!(btree::<u64, u64>(1000) > 1000 * 16);
+    }
+}
@@ -1,10 +1,17 @@
 pub mod bitmap;
+pub mod dict;
+pub mod encoding;
 pub mod geo;
 pub mod hash;
 pub mod hyperloglog;
+pub mod intset;
 pub mod list;
+pub mod listpack;
+pub mod lzf;
+pub mod memory;
 pub mod rstring;
 pub mod set;
+pub mod skiplist;
 pub mod sorted_set;
 pub mod stream;
 
@@ -17,8 +24,6 @@ pub enum RedisValue {
     Set(set::RedisSet),
     SortedSet(sorted_set::RedisSortedSet),
     Stream(stream::RedisStream),
-    HyperLogLog(hyperloglog::HyperLogLog),
-    Geo(geo::GeoSet),
 }
 
 impl RedisValue {
@@ -30,8 +35,32 @@ impl RedisValue {
             RedisValue::Set(_) => "set",
             RedisValue::SortedSet(_) => "zset",
             RedisValue::Stream(_) => "stream",
-            RedisValue::HyperLogLog(_) => "hyperloglog",
-            RedisValue::Geo(_) => "zset",
+        }
+    }
+
+    /// Allocations freed by dropping the value, roughly one per element of
+    /// an aggregate. Decides whether lazyfree drops it in the background.
+    pub fn free_effort(&self) -> usize {
+        match self {
+            RedisValue::List(l) => l.free_effort(),
+            RedisValue::Hash(h) => h.free_effort(),
+            RedisValue::Set(s) => s.free_effort(),
+            RedisValue::SortedSet(z) => z.free_effort(),
+            RedisValue::Stream(s) => s.len(),
+            RedisValue::String(_) => 1,
+        }
+    }
+
+    /// Heap bytes owned by the value, measuring at most `samples` elements
+    /// of an aggregate (0 = all of them).
+    pub fn memory_usage(&self, samples: usize) -> usize {
+        match self {
+            RedisValue::String(s) => s.memory_usage(),
+            RedisValue::List(l) => l.memory_usage(samples),
+            RedisValue::Hash(h) => h.memory_usage(samples),
+            RedisValue::Set(s) => s.memory_usage(samples),
+            RedisValue::SortedSet(z) => z.memory_usage(samples),
+            RedisValue::Stream(s) => s.memory_usage(samples),
         }
     }
 
@@ -118,32 +147,4 @@ impl RedisValue {
             _ => None,
         }
     }
-
-    pub fn as_hyperloglog(&self) -> Option<&hyperloglog::HyperLogLog> {
-        match self {
-            RedisValue::HyperLogLog(h) => Some(h),
-            _ => None,
-        }
-    }
-
-    pub fn as_hyperloglog_mut(&mut self) -> Option<&mut hyperloglog::HyperLogLog> {
-        match self {
-            RedisValue::HyperLogLog(h) => Some(h),
-            _ => None,
-        }
-    }
-
-    pub fn as_geo(&self) -> Option<&geo::GeoSet> {
-        match self {
-            RedisValue::Geo(g) => Some(g),
-            _ => None,
-        }
-    }
-
-    pub fn as_geo_mut(&mut self) -> Option<&mut geo::GeoSet> {
-        match self {
-            RedisValue::Geo(g) => Some(g),
-            _ => None,
-        }
-    }
 }
@@ -1,43 +1,57 @@
-/// Redis string type — binary-safe, stored as raw bytes.
-/// When the value is a valid integer, we also cache the integer form
-/// for efficient INCR/DECR operations.
+use bytes::Bytes;
+
+/// Redis string type — binary-safe, stored as shared [`Bytes`] so a value
+/// can go from a request into the keyspace and back out in replies without
+/// being copied. Mutations take the bytes back as a `Vec`, which only
+/// copies them if they are shared.
 #[derive(Debug, Clone)]
 pub struct RedisString {
-    data: Vec<u8>,
+    data: Bytes,
 }
 
 impl RedisString {
-    pub fn new(data: Vec<u8>) -> Self {
-        RedisString { data }
+    pub fn new(data: impl Into<Bytes>) -> Self {
+        RedisString { data: data.into() }
     }
 
     #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         RedisString {
-            data: s.as_bytes().to_vec(),
+            data: Bytes::copy_from_slice(s.as_bytes()),
         }
     }
 
     pub fn from_i64(n: i64) -> Self {
         RedisString {
-            data: n.to_string().into_bytes(),
+            data: n.to_string().into(),
         }
     }
 
     pub fn from_f64(n: f64) -> Self {
         // Use ryu or manual formatting to match Redis's float output
         let s = format_float(n);
-        RedisString {
-            data: s.into_bytes(),
-        }
+        RedisString { data: s.into() }
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         &self.data
     }
 
+    /// The value as shared bytes, without copying.
+    pub fn to_bytes(&self) -> Bytes {
+        self.data.clone()
+    }
+
     pub fn into_bytes(self) -> Vec<u8> {
-        self.data
+        self.data.into()
+    }
+
+    /// Run `f` on the value as a `Vec`.
+    fn modify<T>(&mut self, f: impl FnOnce(&mut Vec<u8>) -> T) -> T {
+        let mut data = Vec::from(std::mem::take(&mut self.data));
+        let result = f(&mut data);
+        self.data = data.into();
+        result
     }
 
     pub fn len(&self) -> usize {
@@ -63,14 +77,16 @@ impl RedisString {
     }
 
     /// Set the data.
-    pub fn set(&mut self, data: Vec<u8>) {
-        self.data = data;
+    pub fn set(&mut self, data: impl Into<Bytes>) {
+        self.data = data.into();
     }
 
     /// Append data and return new length.
     pub fn append(&mut self, data: &[u8]) -> usize {
-        self.data.extend_from_slice(data);
-        self.data.len()
+        self.modify(|v| {
+            v.extend_from_slice(data);
+            v.len()
+        })
     }
 
     /// Get a range of bytes (GETRANGE).
@@ -116,11 +132,13 @@ impl RedisString {
         if needed > Self::MAX_SIZE {
             return Err("string exceeds maximum allowed size (512MB)");
         }
-        if needed > self.data.len() {
-            self.data.resize(needed, 0);
-        }
-        self.data[offset..offset + data.len()].copy_from_slice(data);
-        Ok(self.data.len())
+        Ok(self.modify(|v| {
+            if needed > v.len() {
+                v.resize(needed, 0);
+            }
+            v[offset..needed].copy_from_slice(data);
+            v.len()
+        }))
     }
 
     /// Increment by i64, returning new value.
@@ -131,7 +149,7 @@ impl RedisString {
         let new_val = current
             .checked_add(delta)
             .ok_or("increment or decrement would overflow")?;
-        self.data = new_val.to_string().into_bytes();
+        self.data = new_val.to_string().into();
         Ok(new_val)
     }
 
@@ -142,9 +160,14 @@ impl RedisString {
         if new_val.is_nan() || new_val.is_infinite() {
             return Err("increment would produce NaN or Infinity");
         }
-        self.data = format_float(new_val).into_bytes();
+        self.data = format_float(new_val).into();
         Ok(new_val)
     }
+
+    /// Heap bytes used by the value.
+    pub fn memory_usage(&self) -> usize {
+        self.data.len()
+    }
 }
 
 /// Format a float like Redis does.
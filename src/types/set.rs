@@ -1,87 +1,223 @@
+use super::dict::Dict;
+use super::encoding;
+use super::intset::{self, IntSet};
+use super::listpack::Listpack;
+use super::memory;
+use std::borrow::Cow;
 use std::collections::HashSet;
 
 /// Redis set type.
-#[derive(Debug, Clone, Default)]
+///
+/// A set of integers lives in an intset while it has at most
+/// `set-max-intset-entries` members. Other small sets are a listpack of up
+/// to `set-max-listpack-entries` members no longer than
+/// `set-max-listpack-value`, and anything bigger a hash table.
+#[derive(Debug, Clone)]
 pub struct RedisSet {
-    data: HashSet<Vec<u8>>,
-    /// Once a non-integer member is added, the set can never go back to intset encoding.
-    was_non_intset: bool,
+    repr: Repr,
+}
+
+#[derive(Debug, Clone)]
+enum Repr {
+    IntSet(IntSet),
+    Listpack(Listpack),
+    Table(Dict<Vec<u8>, ()>),
+}
+
+impl Default for RedisSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn int_member<'a>(value: i64) -> Cow<'a, [u8]> {
+    Cow::Owned(value.to_string().into_bytes())
+}
+
+/// Offset of `member` in a listpack set.
+fn find(lp: &Listpack, member: &[u8]) -> Option<usize> {
+    lp.entries()
+        .find(|(_, m)| *m == member)
+        .map(|(offset, _)| offset)
 }
 
 impl RedisSet {
     pub fn new() -> Self {
         RedisSet {
-            data: HashSet::new(),
-            was_non_intset: false,
+            repr: Repr::IntSet(IntSet::new()),
         }
     }
 
     pub fn len(&self) -> usize {
-        self.data.len()
+        match &self.repr {
+            Repr::IntSet(set) => set.len(),
+            Repr::Listpack(lp) => lp.len(),
+            Repr::Table(data) => data.len(),
+        }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.len() == 0
+    }
+
+    /// Name of the current encoding, for OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        match &self.repr {
+            Repr::IntSet(_) => "intset",
+            Repr::Listpack(_) => "listpack",
+            Repr::Table(_) => "hashtable",
+        }
+    }
+
+    /// Allocations freed by dropping the set.
+    pub fn free_effort(&self) -> usize {
+        match &self.repr {
+            Repr::IntSet(_) | Repr::Listpack(_) => 1,
+            Repr::Table(data) => data.len(),
+        }
     }
 
     /// Add a member. Returns true if the member was new.
     pub fn add(&mut self, member: Vec<u8>) -> bool {
-        // Track if a non-integer was ever added (prevents downgrade to intset encoding)
-        if !self.was_non_intset {
-            let is_int = std::str::from_utf8(&member)
-                .ok()
-                .and_then(|s| s.parse::<i64>().ok())
-                .is_some();
-            if !is_int {
-                self.was_non_intset = true;
+        if let Repr::IntSet(set) = &mut self.repr {
+            match intset::parse_member(&member) {
+                Some(value) if set.contains(value) => return false,
+                Some(value) if set.len() < encoding::set_max_intset_entries() => {
+                    return set.insert(value);
+                }
+                _ => self.convert_intset(member.len()),
+            }
+        }
+        if let Repr::Listpack(lp) = &mut self.repr {
+            if find(lp, &member).is_some() {
+                return false;
+            }
+            if lp.len() < encoding::set_max_listpack_entries()
+                && member.len() <= encoding::set_max_listpack_value()
+            {
+                lp.push(&member);
+                return true;
+            }
+            self.convert_listpack();
+        }
+        match &mut self.repr {
+            Repr::Table(data) => data.insert(member, ()).is_none(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Leave the intset encoding to make room for a member `member_len`
+    /// bytes long: for a listpack if it holds the result, a hash table
+    /// otherwise.
+    fn convert_intset(&mut self, member_len: usize) {
+        let Repr::IntSet(set) = &self.repr else {
+            return;
+        };
+        let longest = [set.iter().next(), set.iter().next_back()]
+            .into_iter()
+            .flatten()
+            .map(|v| v.to_string().len())
+            .fold(member_len, usize::max);
+        self.repr = if set.len() < encoding::set_max_listpack_entries()
+            && longest <= encoding::set_max_listpack_value()
+        {
+            let mut lp = Listpack::new();
+            for value in set.iter() {
+                lp.push(value.to_string().as_bytes());
             }
+            Repr::Listpack(lp)
+        } else {
+            Repr::Table(
+                set.iter()
+                    .map(|v| (int_member(v).into_owned(), ()))
+                    .collect(),
+            )
+        };
+    }
+
+    fn convert_listpack(&mut self) {
+        if let Repr::Listpack(lp) = &self.repr {
+            self.repr = Repr::Table(lp.iter().map(|m| (m.to_vec(), ())).collect());
         }
-        self.data.insert(member)
     }
 
     pub fn remove(&mut self, member: &[u8]) -> bool {
-        self.data.remove(member)
+        match &mut self.repr {
+            Repr::IntSet(set) => intset::parse_member(member).is_some_and(|v| set.remove(v)),
+            Repr::Listpack(lp) => match find(lp, member) {
+                Some(offset) => {
+                    lp.remove(offset, 1);
+                    true
+                }
+                None => false,
+            },
+            Repr::Table(data) => data.remove(member).is_some(),
+        }
     }
 
     pub fn contains(&self, member: &[u8]) -> bool {
-        self.data.contains(member)
+        match &self.repr {
+            Repr::IntSet(set) => intset::parse_member(member).is_some_and(|v| set.contains(v)),
+            Repr::Listpack(lp) => find(lp, member).is_some(),
+            Repr::Table(data) => data.contains_key(member),
+        }
     }
 
-    pub fn members(&self) -> Vec<&Vec<u8>> {
-        self.data.iter().collect()
+    pub fn members(&self) -> Vec<Cow<'_, [u8]>> {
+        self.iter().collect()
     }
 
-    pub fn union(&self, other: &RedisSet) -> RedisSet {
-        RedisSet {
-            data: self.data.union(&other.data).cloned().collect(),
-            was_non_intset: self.was_non_intset || other.was_non_intset,
+    fn from_members<'a>(members: impl Iterator<Item = Cow<'a, [u8]>>) -> RedisSet {
+        let mut set = RedisSet::new();
+        for member in members {
+            set.add(member.into_owned());
         }
+        set
+    }
+
+    pub fn union(&self, other: &RedisSet) -> RedisSet {
+        Self::from_members(self.iter().chain(other.iter()))
     }
 
     pub fn intersect(&self, other: &RedisSet) -> RedisSet {
-        RedisSet {
-            data: self.data.intersection(&other.data).cloned().collect(),
-            was_non_intset: self.was_non_intset || other.was_non_intset,
-        }
+        Self::from_members(self.iter().filter(|m| other.contains(m)))
     }
 
     pub fn difference(&self, other: &RedisSet) -> RedisSet {
-        RedisSet {
-            data: self.data.difference(&other.data).cloned().collect(),
-            was_non_intset: self.was_non_intset || other.was_non_intset,
-        }
+        Self::from_members(self.iter().filter(|m| !other.contains(m)))
     }
 
+    /// Remove and return a random member.
     pub fn pop(&mut self) -> Option<Vec<u8>> {
-        let member = self.data.iter().next()?.clone();
-        self.data.remove(&member);
-        Some(member)
+        use rand::Rng;
+        if self.is_empty() {
+            return None;
+        }
+        let index = rand::thread_rng().gen_range(0..self.len());
+        match &mut self.repr {
+            Repr::IntSet(set) => {
+                let value = set.get(index);
+                set.remove(value);
+                Some(int_member(value).into_owned())
+            }
+            Repr::Listpack(lp) => {
+                let (offset, member) = lp.entries().nth(index)?;
+                let member = member.to_vec();
+                lp.remove(offset, 1);
+                Some(member)
+            }
+            Repr::Table(data) => {
+                let member = data.keys().next()?.clone();
+                data.remove(&member);
+                Some(member)
+            }
+        }
     }
 
-    pub fn random_member(&self) -> Option<&Vec<u8>> {
+    pub fn random_member(&self) -> Option<Cow<'_, [u8]>> {
         use rand::seq::IteratorRandom;
         let mut rng = rand::thread_rng();
-        self.data.iter().choose(&mut rng)
+        self.iter().choose(&mut rng)
     }
 
     pub fn random_members(&self, count: i64) -> Vec<Vec<u8>> {
@@ -90,17 +226,16 @@ impl RedisSet {
 
         if count > 0 {
             // Unique elements
-            let count = (count as usize).min(self.data.len());
-            self.data
-                .iter()
+            let count = (count as usize).min(self.len());
+            self.iter()
                 .choose_multiple(&mut rng, count)
                 .into_iter()
-                .cloned()
+                .map(Cow::into_owned)
                 .collect()
         } else {
             // May repeat
             let count = (-count) as usize;
-            let members: Vec<&Vec<u8>> = self.data.iter().collect();
+            let members = self.members();
             if members.is_empty() {
                 return vec![];
             }
@@ -108,46 +243,98 @@ impl RedisSet {
                 .map(|_| {
                     use rand::Rng;
                     let idx = rng.gen_range(0..members.len());
-                    members[idx].clone()
+                    members[idx].to_vec()
                 })
                 .collect()
         }
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &Vec<u8>> {
-        self.data.iter()
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Cow<'_, [u8]>> + '_> {
+        match &self.repr {
+            Repr::IntSet(set) => Box::new(set.iter().map(int_member)),
+            Repr::Listpack(lp) => Box::new(lp.entries().map(|(_, m)| Cow::Borrowed(m))),
+            Repr::Table(data) => Box::new(data.keys().map(|m| Cow::Borrowed(m.as_slice()))),
+        }
+    }
+
+    /// Visit about `count` members starting at `cursor`; returns the cursor
+    /// to resume from, 0 when done. See [`Dict::scan`]. An intset or
+    /// listpack set is visited whole in one call.
+    pub fn scan(&self, cursor: u64, count: usize, mut f: impl FnMut(&[u8])) -> u64 {
+        match &self.repr {
+            Repr::Table(data) => data.scan(cursor, count, |m, _| f(m)),
+            _ => {
+                self.iter().for_each(|m| f(&m));
+                0
+            }
+        }
     }
 
     pub fn from_set(data: HashSet<Vec<u8>>) -> Self {
-        let was_non_intset = data.iter().any(|m| {
-            std::str::from_utf8(m)
-                .ok()
-                .and_then(|s| s.parse::<i64>().ok())
-                .is_none()
-        });
-        RedisSet {
-            data,
-            was_non_intset,
+        Self::from_members(data.into_iter().map(Cow::Owned))
+    }
+
+    /// Heap bytes used by the intset or listpack, or by the hash table and
+    /// its members.
+    pub fn memory_usage(&self, samples: usize) -> usize {
+        match &self.repr {
+            Repr::IntSet(set) => set.memory_usage(),
+            Repr::Listpack(lp) => lp.memory_usage(),
+            Repr::Table(data) => {
+                memory::dict::<Vec<u8>, ()>(data.capacity(), data.len())
+                    + memory::sampled(data.keys(), data.len(), samples, |m| m.capacity())
+            }
         }
     }
+}
 
-    pub fn into_inner(self) -> HashSet<Vec<u8>> {
-        self.data
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(set: &RedisSet) -> Vec<Vec<u8>> {
+        let mut members: Vec<Vec<u8>> = set.iter().map(Cow::into_owned).collect();
+        members.sort();
+        members
     }
 
-    /// Check if any member exceeds the given byte length.
-    pub fn has_long_entry(&self, max_bytes: usize) -> bool {
-        self.data.iter().any(|m| m.len() > max_bytes)
+    #[test]
+    fn test_intset_to_listpack_to_hashtable() {
+        let mut set = RedisSet::new();
+        assert!(set.add(b"3".to_vec()));
+        assert!(set.add(b"-1".to_vec()));
+        assert!(!set.add(b"3".to_vec()));
+        assert_eq!(set.encoding(), "intset");
+        assert!(set.contains(b"-1"));
+        assert!(!set.contains(b"03"));
+
+        // "03" is not an integer as far as the intset goes
+        assert!(set.add(b"03".to_vec()));
+        assert_eq!(set.encoding(), "listpack");
+        assert_eq!(sorted(&set), [&b"-1"[..], b"03", b"3"]);
+        assert!(set.remove(b"3"));
+        assert_eq!(set.len(), 2);
+
+        set.add(vec![b'x'; encoding::set_max_listpack_value() + 1]);
+        assert_eq!(set.encoding(), "hashtable");
+        assert!(set.contains(b"03"));
+        assert_eq!(set.len(), 3);
     }
 
-    /// Check if all members are integers and the set was never promoted from intset.
-    pub fn is_all_integers(&self) -> bool {
-        !self.was_non_intset
-            && self.data.iter().all(|member| {
-                std::str::from_utf8(member)
-                    .ok()
-                    .and_then(|s| s.parse::<i64>().ok())
-                    .is_some()
-            })
+    #[test]
+    fn test_large_intset_skips_listpack() {
+        let mut set = RedisSet::new();
+        for i in 0..encoding::set_max_intset_entries() {
+            set.add(i.to_string().into_bytes());
+        }
+        assert_eq!(set.encoding(), "intset");
+        set.add(b"a".to_vec());
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(set.len(), encoding::set_max_intset_entries() + 1);
+
+        let mut popped = RedisSet::new();
+        popped.add(b"7".to_vec());
+        assert_eq!(popped.pop(), Some(b"7".to_vec()));
+        assert!(popped.is_empty());
     }
 }
@@ -0,0 +1,399 @@
+//! Skiplist ordered by (score, member) with span counts, as in Redis's zset.
+//!
+//! Each link records how many elements it skips, so the rank of an element
+//! is the sum of the spans followed to reach it, and the element at a rank
+//! is found by following links while the sum stays below it. Both, like
+//! insertion, removal and seeking to a score, take O(log n). Nodes live in
+//! an arena and link to each other by index; freed slots are reused.
+
+use super::memory;
+use std::cmp::Ordering;
+
+const MAX_LEVEL: usize = 32;
+/// Chance of a node reaching each level above the first.
+const LEVEL_P: f64 = 0.25;
+/// Index of the head node, which holds no element.
+const HEAD: usize = 0;
+/// The missing link.
+const NIL: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    forward: usize,
+    /// Elements between this node and `forward`, counting `forward`.
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    backward: usize,
+    levels: Vec<Level>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: usize,
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn random_level() -> usize {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    let mut level = 1;
+    while level < MAX_LEVEL && rng.r#gen::<f64>() < LEVEL_P {
+        level += 1;
+    }
+    level
+}
+
+impl SkipList {
+    pub fn new() -> Self {
+        let head = Node {
+            member: Vec::new(),
+            score: 0.0,
+            backward: NIL,
+            levels: vec![
+                Level {
+                    forward: NIL,
+                    span: 0,
+                };
+                MAX_LEVEL
+            ],
+        };
+        SkipList {
+            nodes: vec![head],
+            free: Vec::new(),
+            tail: NIL,
+            level: 1,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Order of node `x` relative to the element (`score`, `member`).
+    fn cmp(&self, x: usize, score: f64, member: &[u8]) -> Ordering {
+        let node = &self.nodes[x];
+        node.score
+            .total_cmp(&score)
+            .then_with(|| node.member.as_slice().cmp(member))
+    }
+
+    fn forward(&self, x: usize, level: usize) -> usize {
+        self.nodes[x].levels[level].forward
+    }
+
+    fn span(&self, x: usize, level: usize) -> usize {
+        self.nodes[x].levels[level].span
+    }
+
+    /// For each level, the last node before (`score`, `member`) and its
+    /// rank (1-based, 0 for the head).
+    fn predecessors(&self, score: f64, member: &[u8]) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || self.cmp(next, score, member) != Ordering::Less {
+                    break;
+                }
+                rank[i] += self.span(x, i);
+                x = next;
+            }
+            update[i] = x;
+        }
+        (update, rank)
+    }
+
+    /// Add an element. The member must not be in the list already.
+    pub fn insert(&mut self, score: f64, member: Vec<u8>) {
+        let (mut update, mut rank) = self.predecessors(score, &member);
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+        let node = Node {
+            member,
+            score,
+            backward: if update[0] == HEAD { NIL } else { update[0] },
+            levels: vec![
+                Level {
+                    forward: NIL,
+                    span: 0,
+                };
+                level
+            ],
+        };
+        let x = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        for i in 0..level {
+            let prev = self.nodes[update[i]].levels[i];
+            let skipped = rank[0] - rank[i];
+            self.nodes[x].levels[i] = Level {
+                forward: prev.forward,
+                span: prev.span - skipped,
+            };
+            self.nodes[update[i]].levels[i] = Level {
+                forward: x,
+                span: skipped + 1,
+            };
+        }
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].levels[i].span += 1;
+        }
+        match self.forward(x, 0) {
+            NIL => self.tail = x,
+            next => self.nodes[next].backward = x,
+        }
+        self.len += 1;
+    }
+
+    /// Remove an element. Returns false if it isn't there.
+    pub fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let (update, _) = self.predecessors(score, member);
+        let x = self.forward(update[0], 0);
+        if x == NIL || self.cmp(x, score, member) != Ordering::Equal {
+            return false;
+        }
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.forward(prev, i) == x {
+                let removed = self.nodes[x].levels[i];
+                let link = &mut self.nodes[prev].levels[i];
+                link.span += removed.span;
+                link.span -= 1;
+                link.forward = removed.forward;
+            } else {
+                self.nodes[prev].levels[i].span -= 1;
+            }
+        }
+        let backward = self.nodes[x].backward;
+        match self.forward(x, 0) {
+            NIL => self.tail = backward,
+            next => self.nodes[next].backward = backward,
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1) == NIL {
+            self.level -= 1;
+        }
+        let node = &mut self.nodes[x];
+        node.member = Vec::new();
+        node.levels = Vec::new();
+        self.free.push(x);
+        self.len -= 1;
+        true
+    }
+
+    /// 0-based rank of an element.
+    pub fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || self.cmp(next, score, member) == Ordering::Greater {
+                    break;
+                }
+                rank += self.span(x, i);
+                x = next;
+            }
+            if x != HEAD && self.cmp(x, score, member) == Ordering::Equal {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// Node at 0-based `rank`.
+    fn node_at(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || traversed + self.span(x, i) > target {
+                    break;
+                }
+                traversed += self.span(x, i);
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// Number of elements scoring below `score`, or up to it if
+    /// `inclusive`.
+    pub fn count_below(&self, score: f64, inclusive: bool) -> usize {
+        let mut count = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL {
+                    break;
+                }
+                let s = self.nodes[next].score;
+                if s > score || (s == score && !inclusive) {
+                    break;
+                }
+                count += self.span(x, i);
+                x = next;
+            }
+        }
+        count
+    }
+
+    /// Elements with 0-based ranks `start..=stop`, which must be in range.
+    pub fn range(&self, start: usize, stop: usize) -> Iter<'_> {
+        Iter {
+            list: self,
+            front: self.node_at(start).unwrap_or(NIL),
+            back: self.node_at(stop).unwrap_or(NIL),
+            remaining: stop + 1 - start,
+        }
+    }
+
+    /// Elements from 0-based rank `start` on.
+    pub fn range_from(&self, start: usize) -> Iter<'_> {
+        if start >= self.len {
+            return Iter {
+                list: self,
+                front: NIL,
+                back: NIL,
+                remaining: 0,
+            };
+        }
+        self.range(start, self.len - 1)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.range_from(0)
+    }
+
+    /// Heap bytes used by the nodes, measuring at most `samples` of them.
+    pub fn memory_usage(&self, samples: usize) -> usize {
+        self.nodes.capacity() * std::mem::size_of::<Node>()
+            + self.free.capacity() * std::mem::size_of::<usize>()
+            + memory::sampled(self.nodes.iter(), self.nodes.len(), samples, |n| {
+                n.member.capacity() + n.levels.capacity() * std::mem::size_of::<Level>()
+            })
+    }
+}
+
+/// Iterator over `(member, score)` pairs of a rank range, either way.
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    front: usize,
+    back: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let node = &self.list.nodes[self.front];
+        self.front = node.levels[0].forward;
+        Some((node.member.as_slice(), node.score))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let node = &self.list.nodes[self.back];
+        self.back = node.backward;
+        Some((node.member.as_slice(), node.score))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranks_follow_order() {
+        let mut list = SkipList::new();
+        // Insert out of order, with ties broken by member
+        for i in (0..1000).rev() {
+            list.insert((i / 2) as f64, format!("m{i:04}").into_bytes());
+        }
+        assert_eq!(list.len(), 1000);
+        for i in [0, 1, 499, 998, 999] {
+            let member = format!("m{i:04}").into_bytes();
+            assert_eq!(list.rank((i / 2) as f64, &member), Some(i));
+            assert_eq!(list.range(i, i).next(), Some((&member[..], (i / 2) as f64)));
+        }
+        assert_eq!(list.rank(0.0, b"missing"), None);
+        assert_eq!(list.count_below(10.0, false), 20);
+        assert_eq!(list.count_below(10.0, true), 22);
+
+        let window: Vec<f64> = list.range(10, 13).map(|(_, s)| s).collect();
+        assert_eq!(window, [5.0, 5.0, 6.0, 6.0]);
+        let back: Vec<f64> = list.range(10, 13).rev().map(|(_, s)| s).collect();
+        assert_eq!(back, [6.0, 6.0, 5.0, 5.0]);
+
+        for i in (0..1000).step_by(2) {
+            assert!(list.remove((i / 2) as f64, format!("m{i:04}").as_bytes()));
+        }
+        assert!(!list.remove(0.0, b"m0000"));
+        assert_eq!(list.len(), 500);
+        assert_eq!(list.rank(1.0, b"m0003"), Some(1));
+        assert_eq!(list.iter().next_back(), Some((&b"m0999"[..], 499.0)));
+
+        // Freed nodes are reused
+        let nodes = list.nodes.len();
+        list.insert(-1.0, b"first".to_vec());
+        assert_eq!(list.nodes.len(), nodes);
+        assert_eq!(list.rank(-1.0, b"first"), Some(0));
+        assert_eq!(list.iter().count(), 501);
+    }
+}
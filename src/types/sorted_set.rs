@@ -1,109 +1,190 @@
-use std::collections::{BTreeMap, HashMap};
+use super::dict::Dict;
+use super::encoding;
+use super::listpack::Listpack;
+use super::memory;
+use super::skiplist::SkipList;
 
-/// Redis sorted set — implemented using BTreeMap + HashMap.
-/// The BTreeMap provides ordered iteration by score,
-/// and the HashMap provides O(1) score lookup by member.
-#[derive(Debug, Clone, Default)]
+/// Redis sorted set.
+///
+/// Small sorted sets are a listpack of alternating members and scores (the
+/// 8 bytes of the `f64`), kept in score order, until they have more than
+/// `zset-max-listpack-entries` members or one longer than
+/// `zset-max-listpack-value`. Then they move to a skiplist + Dict: the
+/// skiplist provides ordered iteration and O(log n) rank and score lookups,
+/// and the Dict provides O(1) score lookup by member and ZSCAN cursors.
+#[derive(Debug, Clone)]
 pub struct RedisSortedSet {
-    /// member -> score
-    scores: HashMap<Vec<u8>, f64>,
-    /// (score, member) -> () — for ordered iteration
-    /// We use (OrderedFloat, member) as the key to get proper ordering.
-    tree: BTreeMap<SortedSetKey, ()>,
+    repr: Repr,
 }
 
-/// Key for the BTreeMap — sorts by score first, then by member lexicographically.
-#[derive(Debug, Clone, Eq, PartialEq)]
-struct SortedSetKey {
-    score_bits: u64, // IEEE 754 bits, transformed for ordering
-    member: Vec<u8>,
+#[derive(Debug, Clone)]
+enum Repr {
+    Listpack(Listpack),
+    Indexed {
+        /// member -> score
+        scores: Dict<Vec<u8>, f64>,
+        /// (score, member) in order, with ranks
+        list: SkipList,
+    },
 }
 
-impl SortedSetKey {
-    fn new(score: f64, member: Vec<u8>) -> Self {
-        SortedSetKey {
-            score_bits: f64_to_orderable(score),
-            member,
-        }
+impl Default for RedisSortedSet {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl Ord for SortedSetKey {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.score_bits
-            .cmp(&other.score_bits)
-            .then_with(|| self.member.cmp(&other.member))
-    }
+/// Iterator over the `(offset, member, score)` triples of a listpack
+/// sorted set.
+fn lp_members(lp: &Listpack) -> impl Iterator<Item = (usize, &[u8], f64)> {
+    let mut entries = lp.entries();
+    std::iter::from_fn(move || {
+        let (offset, member) = entries.next()?;
+        let (_, score) = entries.next()?;
+        Some((
+            offset,
+            member,
+            f64::from_le_bytes(score.try_into().unwrap()),
+        ))
+    })
 }
 
-impl PartialOrd for SortedSetKey {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
+/// Offset and score of `member` in a listpack sorted set.
+fn lp_find(lp: &Listpack, member: &[u8]) -> Option<(usize, f64)> {
+    lp_members(lp)
+        .find(|(_, m, _)| *m == member)
+        .map(|(offset, _, score)| (offset, score))
 }
 
-/// Transform f64 bits so that standard u64 ordering matches f64 ordering.
-fn f64_to_orderable(f: f64) -> u64 {
-    let bits = f.to_bits();
-    if bits >> 63 == 1 {
-        // Negative: flip all bits
-        !bits
-    } else {
-        // Positive: flip sign bit
-        bits ^ (1 << 63)
-    }
+/// Insert `member` at its place in score order.
+fn lp_insert(lp: &mut Listpack, member: &[u8], score: f64) {
+    let offset = lp_members(lp)
+        .find(|(_, m, s)| s.total_cmp(&score).then_with(|| (*m).cmp(member)).is_gt())
+        .map_or(lp.len_bytes(), |(offset, _, _)| offset);
+    lp.insert(offset, member);
+    let (_, score_offset) = lp.get(offset);
+    lp.insert(score_offset, &score.to_le_bytes());
 }
 
 impl RedisSortedSet {
     pub fn new() -> Self {
         RedisSortedSet {
-            scores: HashMap::new(),
-            tree: BTreeMap::new(),
+            repr: Repr::Listpack(Listpack::new()),
         }
     }
 
     pub fn len(&self) -> usize {
-        self.scores.len()
+        match &self.repr {
+            Repr::Listpack(lp) => lp.len() / 2,
+            Repr::Indexed { scores, .. } => scores.len(),
+        }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.scores.is_empty()
+        self.len() == 0
+    }
+
+    /// Name of the current encoding, for OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        match &self.repr {
+            Repr::Listpack(_) => "listpack",
+            Repr::Indexed { .. } => "skiplist",
+        }
+    }
+
+    /// Allocations freed by dropping the sorted set.
+    pub fn free_effort(&self) -> usize {
+        match &self.repr {
+            Repr::Listpack(_) => 1,
+            Repr::Indexed { scores, .. } => scores.len(),
+        }
     }
 
     /// Add or update a member. Returns true if the member was new.
     pub fn add(&mut self, member: Vec<u8>, score: f64) -> bool {
-        if let Some(&old_score) = self.scores.get(&member) {
-            // Remove old entry from tree
-            self.tree
-                .remove(&SortedSetKey::new(old_score, member.clone()));
-            self.scores.insert(member.clone(), score);
-            self.tree.insert(SortedSetKey::new(score, member), ());
-            false
-        } else {
-            self.scores.insert(member.clone(), score);
-            self.tree.insert(SortedSetKey::new(score, member), ());
-            true
+        if let Repr::Listpack(lp) = &mut self.repr {
+            if member.len() <= encoding::zset_max_listpack_value() {
+                match lp_find(lp, &member) {
+                    Some((_, old_score)) if old_score.to_bits() == score.to_bits() => {
+                        return false;
+                    }
+                    Some((offset, _)) => {
+                        lp.remove(offset, 2);
+                        lp_insert(lp, &member, score);
+                        return false;
+                    }
+                    None if lp.len() / 2 < encoding::zset_max_listpack_entries() => {
+                        lp_insert(lp, &member, score);
+                        return true;
+                    }
+                    None => {}
+                }
+            }
+            self.convert();
+        }
+        let Repr::Indexed { scores, list } = &mut self.repr else {
+            unreachable!()
+        };
+        match scores.insert(member.clone(), score) {
+            Some(old_score) if old_score.to_bits() == score.to_bits() => false,
+            Some(old_score) => {
+                list.remove(old_score, &member);
+                list.insert(score, member);
+                false
+            }
+            None => {
+                list.insert(score, member);
+                true
+            }
+        }
+    }
+
+    /// Move the members into the score table and ordered index.
+    fn convert(&mut self) {
+        if let Repr::Listpack(lp) = &self.repr {
+            let mut scores = Dict::new();
+            let mut list = SkipList::new();
+            for (_, member, score) in lp_members(lp) {
+                scores.insert(member.to_vec(), score);
+                list.insert(score, member.to_vec());
+            }
+            self.repr = Repr::Indexed { scores, list };
         }
     }
 
     pub fn remove(&mut self, member: &[u8]) -> bool {
-        if let Some(score) = self.scores.remove(member) {
-            self.tree.remove(&SortedSetKey::new(score, member.to_vec()));
-            true
-        } else {
-            false
+        match &mut self.repr {
+            Repr::Listpack(lp) => match lp_find(lp, member) {
+                Some((offset, _)) => {
+                    lp.remove(offset, 2);
+                    true
+                }
+                None => false,
+            },
+            Repr::Indexed { scores, list } => {
+                if let Some(score) = scores.remove(member) {
+                    list.remove(score, member);
+                    true
+                } else {
+                    false
+                }
+            }
         }
     }
 
     pub fn score(&self, member: &[u8]) -> Option<f64> {
-        self.scores.get(member).copied()
+        match &self.repr {
+            Repr::Listpack(lp) => lp_find(lp, member).map(|(_, score)| score),
+            Repr::Indexed { scores, .. } => scores.get(member).copied(),
+        }
     }
 
     /// Get the rank (0-based) of a member in ascending order.
     pub fn rank(&self, member: &[u8]) -> Option<usize> {
-        let score = self.scores.get(member)?;
-        let key = SortedSetKey::new(*score, member.to_vec());
-        Some(self.tree.range(..&key).count())
+        match &self.repr {
+            Repr::Listpack(lp) => lp_members(lp).position(|(_, m, _)| m == member),
+            Repr::Indexed { scores, list } => list.rank(*scores.get(member)?, member),
+        }
     }
 
     /// Get the rank (0-based) of a member in descending order.
@@ -112,50 +193,51 @@ impl RedisSortedSet {
         Some(self.len() - 1 - rank)
     }
 
-    /// Get members in ascending order by rank range.
-    pub fn range(&self, start: i64, stop: i64) -> Vec<(&[u8], f64)> {
+    /// Normalize a ZRANGE style rank range, with negative ranks counting
+    /// from the end, to in-range 0-based ranks. `None` if it is empty.
+    fn rank_range(&self, start: i64, stop: i64) -> Option<(usize, usize)> {
         let len = self.len() as i64;
-        if len == 0 {
-            return vec![];
-        }
         // Normalize without clamping to detect out-of-range
         let s = if start < 0 { len + start } else { start };
         let e = if stop < 0 { len + stop } else { stop };
-        if s > e || s >= len || e < 0 {
-            return vec![];
+        if len == 0 || s > e || s >= len || e < 0 {
+            return None;
         }
-        let start = s.max(0) as usize;
-        let stop = (e.min(len - 1)) as usize;
+        Some((s.max(0) as usize, e.min(len - 1) as usize))
+    }
 
-        self.tree
-            .keys()
-            .skip(start)
-            .take(stop - start + 1)
-            .map(|k| (k.member.as_slice(), *self.scores.get(&k.member).unwrap()))
-            .collect()
+    /// Get members in ascending order by rank range.
+    pub fn range(&self, start: i64, stop: i64) -> Vec<(&[u8], f64)> {
+        let Some((start, stop)) = self.rank_range(start, stop) else {
+            return vec![];
+        };
+        match &self.repr {
+            Repr::Listpack(lp) => lp_members(lp)
+                .skip(start)
+                .take(stop - start + 1)
+                .map(|(_, member, score)| (member, score))
+                .collect(),
+            Repr::Indexed { list, .. } => list.range(start, stop).collect(),
+        }
     }
 
     /// Get members in descending order by rank range.
     pub fn rev_range(&self, start: i64, stop: i64) -> Vec<(&[u8], f64)> {
-        let len = self.len() as i64;
-        if len == 0 {
+        let Some((start, stop)) = self.rank_range(start, stop) else {
             return vec![];
+        };
+        match &self.repr {
+            Repr::Listpack(_) => self
+                .iter()
+                .rev()
+                .skip(start)
+                .take(stop - start + 1)
+                .collect(),
+            Repr::Indexed { list, .. } => {
+                let last = list.len() - 1;
+                list.range(last - stop, last - start).rev().collect()
+            }
         }
-        let s = if start < 0 { len + start } else { start };
-        let e = if stop < 0 { len + stop } else { stop };
-        if s > e || s >= len || e < 0 {
-            return vec![];
-        }
-        let start = s.max(0) as usize;
-        let stop = (e.min(len - 1)) as usize;
-
-        self.tree
-            .keys()
-            .rev()
-            .skip(start)
-            .take(stop - start + 1)
-            .map(|k| (k.member.as_slice(), *self.scores.get(&k.member).unwrap()))
-            .collect()
     }
 
     /// Get members with scores in [min, max].
@@ -163,27 +245,33 @@ impl RedisSortedSet {
         if min > max {
             return vec![];
         }
-        let min_key = SortedSetKey::new(min, vec![]);
-        let max_key = SortedSetKey::new(max, vec![0xff; 128]);
-
-        self.tree
-            .range(min_key..=max_key)
-            .filter(|(k, _)| {
-                let score = *self.scores.get(&k.member).unwrap();
-                score >= min && score <= max
-            })
-            .map(|(k, _)| (k.member.as_slice(), *self.scores.get(&k.member).unwrap()))
-            .collect()
+        match &self.repr {
+            Repr::Listpack(lp) => lp_members(lp)
+                .skip_while(|(_, _, score)| *score < min)
+                .take_while(|(_, _, score)| *score <= max)
+                .map(|(_, member, score)| (member, score))
+                .collect(),
+            Repr::Indexed { list, .. } => list
+                .range_from(list.count_below(min, false))
+                .take_while(|(_, score)| *score <= max)
+                .collect(),
+        }
     }
 
     /// Count members with scores in [min, max].
     pub fn count(&self, min: f64, max: f64) -> usize {
-        self.range_by_score(min, max).len()
+        match &self.repr {
+            Repr::Listpack(_) => self.range_by_score(min, max).len(),
+            Repr::Indexed { .. } if min > max => 0,
+            Repr::Indexed { list, .. } => {
+                list.count_below(max, true) - list.count_below(min, false)
+            }
+        }
     }
 
     /// Increment a member's score. Returns the new score.
     pub fn incr_by(&mut self, member: Vec<u8>, delta: f64) -> f64 {
-        let current = self.scores.get(&member).copied().unwrap_or(0.0);
+        let current = self.score(&member).unwrap_or(0.0);
         let new_score = current + delta;
         self.add(member, new_score);
         new_score
@@ -191,34 +279,49 @@ impl RedisSortedSet {
 
     /// Pop the member with the minimum score.
     pub fn pop_min(&mut self) -> Option<(Vec<u8>, f64)> {
-        let key = self.tree.keys().next()?.clone();
-        let score = *self.scores.get(&key.member)?;
-        self.remove(&key.member.clone());
-        Some((key.member, score))
+        let (member, score) = self.iter().next()?;
+        let member = member.to_vec();
+        self.remove(&member);
+        Some((member, score))
     }
 
     /// Pop the member with the maximum score.
     pub fn pop_max(&mut self) -> Option<(Vec<u8>, f64)> {
-        let key = self.tree.keys().next_back()?.clone();
-        let score = *self.scores.get(&key.member)?;
-        self.remove(&key.member.clone());
-        Some((key.member, score))
+        let (member, score) = self.iter().next_back()?;
+        let member = member.to_vec();
+        self.remove(&member);
+        Some((member, score))
     }
 
     pub fn contains(&self, member: &[u8]) -> bool {
-        self.scores.contains_key(member)
+        self.score(member).is_some()
     }
 
-    /// Check if any member exceeds the given byte length.
-    pub fn has_long_entry(&self, max_bytes: usize) -> bool {
-        self.scores.keys().any(|m| m.len() > max_bytes)
+    /// Visit about `count` members in hash order starting at `cursor`;
+    /// returns the cursor to resume from, 0 when done. See [`Dict::scan`].
+    /// A listpack sorted set is visited whole in one call.
+    pub fn scan(&self, cursor: u64, count: usize, mut f: impl FnMut(&[u8], f64)) -> u64 {
+        match &self.repr {
+            Repr::Listpack(lp) => {
+                lp_members(lp).for_each(|(_, member, score)| f(member, score));
+                0
+            }
+            Repr::Indexed { scores, .. } => scores.scan(cursor, count, |m, &score| f(m, score)),
+        }
     }
 
     /// Iterator over all (member, score) pairs in score order.
-    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
-        self.tree
-            .keys()
-            .map(|k| (k.member.as_slice(), *self.scores.get(&k.member).unwrap()))
+    pub fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = (&[u8], f64)> + '_> {
+        match &self.repr {
+            Repr::Listpack(lp) => Box::new(
+                // Entries can only be walked forwards; reversing needs them collected
+                lp_members(lp)
+                    .map(|(_, member, score)| (member, score))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+            Repr::Indexed { list, .. } => Box::new(list.iter()),
+        }
     }
 
     /// Get range by lex (for members with equal scores).
@@ -229,27 +332,80 @@ impl RedisSortedSet {
         max: &[u8],
         max_inclusive: bool,
     ) -> Vec<(&[u8], f64)> {
-        self.tree
-            .keys()
-            .filter(|k| {
-                let m = k.member.as_slice();
+        self.iter()
+            .filter(|(m, _)| {
                 let above_min = if min.is_empty() {
                     true
                 } else if min_inclusive {
-                    m >= min
+                    *m >= min
                 } else {
-                    m > min
+                    *m > min
                 };
                 let below_max = if max.is_empty() {
                     true
                 } else if max_inclusive {
-                    m <= max
+                    *m <= max
                 } else {
-                    m < max
+                    *m < max
                 };
                 above_min && below_max
             })
-            .map(|k| (k.member.as_slice(), *self.scores.get(&k.member).unwrap()))
             .collect()
     }
+
+    /// Heap bytes used by the listpack, or by the score table, the skiplist
+    /// and the members, which both of them own a copy of.
+    pub fn memory_usage(&self, samples: usize) -> usize {
+        match &self.repr {
+            Repr::Listpack(lp) => lp.memory_usage(),
+            Repr::Indexed { scores, list } => {
+                memory::dict::<Vec<u8>, f64>(scores.capacity(), scores.len())
+                    + list.memory_usage(samples)
+                    + memory::sampled(scores.keys(), scores.len(), samples, |m| m.capacity())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listpack_keeps_score_order() {
+        let mut z = RedisSortedSet::new();
+        assert!(z.add(b"b".to_vec(), 2.0));
+        assert!(z.add(b"a".to_vec(), 2.0));
+        assert!(z.add(b"c".to_vec(), -1.5));
+        assert!(!z.add(b"c".to_vec(), 3.0));
+        assert_eq!(z.encoding(), "listpack");
+        assert_eq!(
+            z.range(0, -1),
+            [(&b"a"[..], 2.0), (&b"b"[..], 2.0), (&b"c"[..], 3.0)]
+        );
+        assert_eq!(z.rank(b"b"), Some(1));
+        assert_eq!(z.score(b"c"), Some(3.0));
+        assert_eq!(z.range_by_score(2.0, 2.5).len(), 2);
+        assert_eq!(z.pop_max(), Some((b"c".to_vec(), 3.0)));
+        assert_eq!(z.incr_by(b"a".to_vec(), 1.0), 3.0);
+        assert_eq!(z.rev_range(0, 0), [(&b"a"[..], 3.0)]);
+    }
+
+    #[test]
+    fn test_converts_past_limits() {
+        let mut z = RedisSortedSet::new();
+        for i in 0..encoding::zset_max_listpack_entries() {
+            z.add(i.to_string().into_bytes(), i as f64);
+        }
+        assert_eq!(z.encoding(), "listpack");
+        z.add(b"last".to_vec(), -1.0);
+        assert_eq!(z.encoding(), "skiplist");
+        assert_eq!(z.len(), encoding::zset_max_listpack_entries() + 1);
+        assert_eq!(z.rank(b"last"), Some(0));
+        assert_eq!(z.rank(b"1"), Some(2));
+
+        let mut z = RedisSortedSet::new();
+        z.add(vec![b'm'; encoding::zset_max_listpack_value() + 1], 1.0);
+        assert_eq!(z.encoding(), "skiplist");
+    }
 }
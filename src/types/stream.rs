@@ -1,3 +1,4 @@
+use super::memory;
 use std::collections::{BTreeMap, HashMap};
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -651,4 +652,41 @@ impl RedisStream {
             StreamEntryId::new(self.last_id.ms, self.last_id.seq + 1)
         }
     }
+
+    /// Heap bytes used by the entries, consumer groups and their PELs.
+    pub fn memory_usage(&self, samples: usize) -> usize {
+        let entries = memory::btree::<StreamEntryId, StreamEntry>(self.entries.len())
+            + memory::sampled(
+                self.entries.values(),
+                self.entries.len(),
+                samples,
+                |fields| {
+                    fields.capacity() * std::mem::size_of::<(Vec<u8>, Vec<u8>)>()
+                        + fields
+                            .iter()
+                            .map(|(f, v)| f.capacity() + v.capacity())
+                            .sum::<usize>()
+                },
+            );
+        let groups = memory::hash_table::<String, ConsumerGroup>(self.groups.capacity())
+            + memory::sampled(self.groups.values(), self.groups.len(), samples, |group| {
+                group.name.capacity()
+                    + memory::btree::<StreamEntryId, PendingEntry>(group.pel.len())
+                    + group
+                        .pel
+                        .values()
+                        .map(|pe| pe.consumer.capacity())
+                        .sum::<usize>()
+                    + memory::hash_table::<String, StreamConsumer>(group.consumers.capacity())
+                    + group
+                        .consumers
+                        .values()
+                        .map(|c| {
+                            c.name.capacity()
+                                + memory::btree::<StreamEntryId, PendingEntry>(c.pending.len())
+                        })
+                        .sum::<usize>()
+            });
+        entries + groups
+    }
 }
@@ -1,16 +1,20 @@
 use redis::Commands;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::{Mutex, RwLock};
 
 fn start_server(port: u16) -> tokio::task::JoinHandle<()> {
-    let config = cedis::config::Config {
+    start_server_with_config(cedis::config::Config {
         port,
         ..Default::default()
-    };
+    })
+}
+
+fn start_server_with_config(config: cedis::config::Config) -> tokio::task::JoinHandle<()> {
     let num_dbs = config.databases;
     let config = Arc::new(RwLock::new(config));
-    let store = Arc::new(RwLock::new(cedis::store::DataStore::new(num_dbs)));
+    let store = Arc::new(cedis::store::DataStore::new(num_dbs));
     let pubsub = Arc::new(RwLock::new(cedis::pubsub::PubSubRegistry::new()));
     let aof = Arc::new(Mutex::new(cedis::persistence::aof::AofWriter::new()));
     let repl_state = Arc::new(RwLock::new(cedis::replication::ReplicationState::new()));
@@ -1181,6 +1185,60 @@ async fn test_multi_discard() {
     .unwrap();
 }
 
+#[tokio::test]
+async fn test_exec_does_not_block() {
+    let port = 16516;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+        let mut other = get_client(port);
+        // Hanging here would mean the transaction kept q's shard locked
+        let limit = Some(std::time::Duration::from_secs(5));
+        conn.set_read_timeout(limit).unwrap();
+        other.set_read_timeout(limit).unwrap();
+
+        // Blocking commands inside EXEC reply as if they had timed out
+        let _: String = redis::cmd("MULTI").query(&mut conn).unwrap();
+        for cmd in [
+            redis::cmd("BLPOP").arg("q").arg(0).clone(),
+            redis::cmd("BRPOPLPUSH").arg("q").arg("dst").arg(0).clone(),
+            redis::cmd("BZPOPMIN").arg("z").arg(0).clone(),
+        ] {
+            let queued: String = cmd.query(&mut conn).unwrap();
+            assert_eq!(queued, "QUEUED");
+        }
+        let results: Vec<redis::Value> = redis::cmd("EXEC").query(&mut conn).unwrap();
+        assert_eq!(results, vec![redis::Value::Nil; 3]);
+
+        // The shard is free again for other clients
+        let len: i64 = other.rpush("q", "v").unwrap();
+        assert_eq!(len, 1);
+
+        // And a blocking pop with data to take still takes it
+        let _: String = redis::cmd("MULTI").query(&mut conn).unwrap();
+        let _: String = redis::cmd("BLPOP")
+            .arg("q")
+            .arg(0)
+            .query(&mut conn)
+            .unwrap();
+        let results: Vec<Vec<String>> = redis::cmd("EXEC").query(&mut conn).unwrap();
+        assert_eq!(results, vec![vec!["q".to_string(), "v".to_string()]]);
+
+        // WAIT replies with the current ack count instead of waiting for one
+        let _: String = redis::cmd("MULTI").query(&mut conn).unwrap();
+        let _: String = redis::cmd("SET").arg("x").arg(1).query(&mut conn).unwrap();
+        let _: String = redis::cmd("WAIT").arg(1).arg(0).query(&mut conn).unwrap();
+        let results: Vec<redis::Value> = redis::cmd("EXEC").query(&mut conn).unwrap();
+        assert_eq!(results, vec![redis::Value::Okay, redis::Value::Int(0)]);
+        let x: String = other.get("x").unwrap();
+        assert_eq!(x, "1");
+    })
+    .await
+    .unwrap();
+}
+
 #[tokio::test]
 async fn test_time_command() {
     let port = 16423;
@@ -1626,6 +1684,73 @@ async fn test_pfmerge() {
     .unwrap();
 }
 
+#[tokio::test]
+async fn test_hll_is_string() {
+    let port = 16515;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+
+        let _: i64 = redis::cmd("PFADD")
+            .arg("hll")
+            .arg("a")
+            .arg("b")
+            .arg("c")
+            .query(&mut conn)
+            .unwrap();
+        let key_type: String = redis::cmd("TYPE").arg("hll").query(&mut conn).unwrap();
+        assert_eq!(key_type, "string");
+
+        // The raw encoding survives a GET/SET round trip
+        let raw: Vec<u8> = redis::cmd("GET").arg("hll").query(&mut conn).unwrap();
+        assert_eq!(&raw[..5], b"HYLL\x01");
+        let _: String = redis::cmd("SET")
+            .arg("copy")
+            .arg(&raw)
+            .query(&mut conn)
+            .unwrap();
+        let count: i64 = redis::cmd("PFCOUNT").arg("copy").query(&mut conn).unwrap();
+        assert_eq!(count, 3);
+        // PFCOUNT isn't replicated, so it leaves the stored value alone
+        let after: Vec<u8> = redis::cmd("GET").arg("copy").query(&mut conn).unwrap();
+        assert_eq!(after, raw);
+
+        let encoding: String = redis::cmd("PFDEBUG")
+            .arg("ENCODING")
+            .arg("copy")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(encoding, "sparse");
+        let converted: i64 = redis::cmd("PFDEBUG")
+            .arg("TODENSE")
+            .arg("copy")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(converted, 1);
+        let raw: Vec<u8> = redis::cmd("GET").arg("copy").query(&mut conn).unwrap();
+        assert_eq!(raw.len(), 16 + 12288);
+        let count: i64 = redis::cmd("PFCOUNT").arg("copy").query(&mut conn).unwrap();
+        assert_eq!(count, 3);
+
+        // Plain strings are not HLLs
+        let _: String = redis::cmd("SET")
+            .arg("plain")
+            .arg("hello")
+            .query(&mut conn)
+            .unwrap();
+        let err = redis::cmd("PFADD")
+            .arg("plain")
+            .arg("x")
+            .query::<i64>(&mut conn)
+            .unwrap_err();
+        assert!(err.to_string().contains("not a valid HyperLogLog"));
+    })
+    .await
+    .unwrap();
+}
+
 // =========== Stream tests ===========
 
 #[tokio::test]
@@ -2073,6 +2198,65 @@ async fn test_geosearch() {
     .unwrap();
 }
 
+#[tokio::test]
+async fn test_geo_key_is_sorted_set() {
+    let port = 16514;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+
+        let _: i64 = redis::cmd("GEOADD")
+            .arg("places")
+            .arg("13.361389")
+            .arg("38.115556")
+            .arg("Palermo")
+            .arg("15.087269")
+            .arg("37.502669")
+            .arg("Catania")
+            .query(&mut conn)
+            .unwrap();
+
+        let key_type: String = redis::cmd("TYPE").arg("places").query(&mut conn).unwrap();
+        assert_eq!(key_type, "zset");
+
+        // Scores are the same 52-bit geohashes Redis uses
+        let score: String = redis::cmd("ZSCORE")
+            .arg("places")
+            .arg("Palermo")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(score, "3479099956230698");
+
+        let members: Vec<String> = redis::cmd("ZRANGE")
+            .arg("places")
+            .arg("0")
+            .arg("-1")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(members, vec!["Palermo", "Catania"]);
+
+        let hashes: Vec<String> = redis::cmd("GEOHASH")
+            .arg("places")
+            .arg("Palermo")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(hashes, vec!["sqc8b49rny0"]);
+
+        let removed: i64 = redis::cmd("ZREM")
+            .arg("places")
+            .arg("Catania")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(removed, 1);
+        let card: i64 = redis::cmd("ZCARD").arg("places").query(&mut conn).unwrap();
+        assert_eq!(card, 1);
+    })
+    .await
+    .unwrap();
+}
+
 // =========== COPY command test ===========
 
 #[tokio::test]
@@ -2365,7 +2549,7 @@ async fn test_auth_with_username() {
     };
     let num_dbs = config.databases;
     let config = Arc::new(RwLock::new(config));
-    let store = Arc::new(RwLock::new(cedis::store::DataStore::new(num_dbs)));
+    let store = Arc::new(cedis::store::DataStore::new(num_dbs));
     let pubsub = Arc::new(RwLock::new(cedis::pubsub::PubSubRegistry::new()));
     let aof = Arc::new(Mutex::new(cedis::persistence::aof::AofWriter::new()));
     let repl_state = Arc::new(RwLock::new(cedis::replication::ReplicationState::new()));
@@ -2563,3 +2747,1827 @@ async fn test_config_slowlog_params() {
     .await
     .unwrap();
 }
+
+// =========== WAIT / WAITAOF tests ===========
+
+#[tokio::test]
+async fn test_wait_for_replica_ack() {
+    let master_port = 16458;
+    let replica_port = 16459;
+    let _master = start_server(master_port);
+    let _replica = start_server_with_config(cedis::config::Config {
+        port: replica_port,
+        replicaof: Some(("127.0.0.1".to_string(), master_port)),
+        ..Default::default()
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut master = get_client(master_port);
+
+        // Wait for the replica to finish its initial sync
+        let start = Instant::now();
+        loop {
+            let info: String = redis::cmd("INFO")
+                .arg("replication")
+                .query(&mut master)
+                .unwrap();
+            if info.contains("connected_slaves:1") {
+                break;
+            }
+            assert!(start.elapsed().as_secs() < 5, "replica never connected");
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        let _: () = master.set("payment:1", "settled").unwrap();
+        let acked: i64 = redis::cmd("WAIT")
+            .arg(1)
+            .arg(5000)
+            .query(&mut master)
+            .unwrap();
+        assert_eq!(acked, 1);
+
+        // Once WAIT returns, the write is visible on the replica
+        let mut replica = get_client(replica_port);
+        let value: String = replica.get("payment:1").unwrap();
+        assert_eq!(value, "settled");
+
+        // Asking for more replicas than exist times out with the real count
+        let start = Instant::now();
+        let acked: i64 = redis::cmd("WAIT")
+            .arg(2)
+            .arg(200)
+            .query(&mut master)
+            .unwrap();
+        assert_eq!(acked, 1);
+        assert!(start.elapsed().as_millis() >= 200);
+
+        // WAIT is rejected on replicas
+        let result: redis::RedisResult<i64> = redis::cmd("WAIT").arg(0).arg(0).query(&mut replica);
+        assert!(result.is_err());
+
+        // The replica has no AOF, so it never acknowledges an fsync
+        let result: Vec<i64> = redis::cmd("WAITAOF")
+            .arg(0)
+            .arg(1)
+            .arg(100)
+            .query(&mut master)
+            .unwrap();
+        assert_eq!(result, vec![0, 0]);
+
+        // numlocal requires appendonly
+        let result: redis::RedisResult<Vec<i64>> = redis::cmd("WAITAOF")
+            .arg(1)
+            .arg(0)
+            .arg(0)
+            .query(&mut master);
+        assert!(result.is_err());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_wait_without_replicas() {
+    let port = 16517;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+        conn.set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let offset = |conn: &mut redis::Connection| {
+            let info: String = redis::cmd("INFO").arg("replication").query(conn).unwrap();
+            info.lines()
+                .find_map(|l| l.strip_prefix("master_repl_offset:"))
+                .unwrap()
+                .trim()
+                .to_string()
+        };
+
+        // With nobody to ask, WAIT answers at once and sends no GETACK
+        let _: () = conn.set("k", "v").unwrap();
+        let before = offset(&mut conn);
+        let acked: i64 = redis::cmd("WAIT").arg(1).arg(0).query(&mut conn).unwrap();
+        assert_eq!(acked, 0);
+        let result: Vec<i64> = redis::cmd("WAITAOF")
+            .arg(0)
+            .arg(1)
+            .arg(0)
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(result, vec![0, 0]);
+        assert_eq!(offset(&mut conn), before);
+
+        // Scripts can't wait for replicas
+        let result: redis::RedisResult<i64> = redis::cmd("EVAL")
+            .arg("return redis.call('WAIT', 1, 0)")
+            .arg(0)
+            .query(&mut conn);
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("not allowed from script"), "{err}");
+    })
+    .await
+    .unwrap();
+}
+
+// =========== min-replicas-to-write test ===========
+
+#[tokio::test]
+async fn test_min_replicas_to_write() {
+    let master_port = 16460;
+    let replica_port = 16461;
+    let _master = start_server_with_config(cedis::config::Config {
+        port: master_port,
+        min_replicas_to_write: 1,
+        min_replicas_max_lag: 10,
+        ..Default::default()
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut master = get_client(master_port);
+
+        // No replicas yet: writes are refused, reads still work
+        let result: redis::RedisResult<()> = master.set("k", "v");
+        let err = result.unwrap_err();
+        assert_eq!(err.code(), Some("NOREPLICAS"));
+        let value: Option<String> = master.get("k").unwrap();
+        assert_eq!(value, None);
+    })
+    .await
+    .unwrap();
+
+    let _replica = start_server_with_config(cedis::config::Config {
+        port: replica_port,
+        replicaof: Some(("127.0.0.1".to_string(), master_port)),
+        ..Default::default()
+    });
+
+    tokio::task::spawn_blocking(move || {
+        let mut master = get_client(master_port);
+        let start = Instant::now();
+        let info = loop {
+            let info: String = redis::cmd("INFO")
+                .arg("replication")
+                .query(&mut master)
+                .unwrap();
+            if info.contains("connected_slaves:1") {
+                break info;
+            }
+            assert!(start.elapsed().as_secs() < 5, "replica never connected");
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        };
+        assert!(info.contains("min_slaves_good_slaves:1"), "{info}");
+        let slave_line = info.lines().find(|l| l.starts_with("slave0:")).unwrap();
+        assert!(slave_line.contains(",lag=0"), "{slave_line}");
+
+        let _: () = master.set("k", "v").unwrap();
+
+        // Disabling the check at runtime
+        let _: () = redis::cmd("CONFIG")
+            .arg("SET")
+            .arg("min-replicas-to-write")
+            .arg("0")
+            .query(&mut master)
+            .unwrap();
+        let result: Vec<String> = redis::cmd("CONFIG")
+            .arg("GET")
+            .arg("min-replicas-*")
+            .query(&mut master)
+            .unwrap();
+        assert_eq!(
+            result,
+            vec!["min-replicas-to-write", "0", "min-replicas-max-lag", "10"]
+        );
+    })
+    .await
+    .unwrap();
+}
+
+// =========== Failover partial resync test ===========
+
+fn wait_for_info(conn: &mut redis::Connection, section: &str, needle: &str) -> String {
+    let start = Instant::now();
+    loop {
+        let info: String = redis::cmd("INFO").arg(section).query(conn).unwrap();
+        if info.contains(needle) {
+            return info;
+        }
+        assert!(
+            start.elapsed().as_secs() < 5,
+            "timed out waiting for {needle}: {info}"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+fn info_field(info: &str, field: &str) -> String {
+    info.lines()
+        .find_map(|l| l.strip_prefix(&format!("{field}:")))
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[tokio::test]
+async fn test_promoted_replica_serves_partial_resync() {
+    let a_port = 16462;
+    let b_port = 16463;
+    let c_port = 16464;
+    let _a = start_server(a_port);
+    for port in [b_port, c_port] {
+        start_server_with_config(cedis::config::Config {
+            port,
+            replicaof: Some(("127.0.0.1".to_string(), a_port)),
+            ..Default::default()
+        });
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut a = get_client(a_port);
+        let mut b = get_client(b_port);
+        let mut c = get_client(c_port);
+
+        let a_info = wait_for_info(&mut a, "replication", "connected_slaves:2");
+        let a_replid = info_field(&a_info, "master_replid");
+        let _: () = a.set("k", "v").unwrap();
+        let acked: i64 = redis::cmd("WAIT").arg(2).arg(5000).query(&mut a).unwrap();
+        assert_eq!(acked, 2);
+
+        // Promote B: it starts a new history and remembers A's as replid2
+        let _: () = redis::cmd("REPLICAOF")
+            .arg("NO")
+            .arg("ONE")
+            .query(&mut b)
+            .unwrap();
+        let b_info = wait_for_info(&mut b, "replication", "role:master");
+        assert_eq!(info_field(&b_info, "master_replid2"), a_replid);
+        assert_ne!(info_field(&b_info, "master_replid"), a_replid);
+
+        // Re-point C at B: it continues without a full resync
+        let _: () = redis::cmd("REPLICAOF")
+            .arg("127.0.0.1")
+            .arg(b_port)
+            .query(&mut c)
+            .unwrap();
+        wait_for_info(&mut b, "replication", "connected_slaves:1");
+        let stats = wait_for_info(&mut b, "stats", "sync_partial_ok:1");
+        assert!(stats.contains("sync_full:0"), "{stats}");
+
+        let _: () = b.set("k2", "v2").unwrap();
+        let acked: i64 = redis::cmd("WAIT").arg(1).arg(5000).query(&mut b).unwrap();
+        assert_eq!(acked, 1);
+        let values: Vec<String> = redis::cmd("MGET").arg("k").arg("k2").query(&mut c).unwrap();
+        assert_eq!(values, vec!["v", "v2"]);
+        let c_info = wait_for_info(&mut c, "replication", "role:slave");
+        assert_eq!(
+            info_field(&c_info, "master_replid"),
+            info_field(&b_info, "master_replid")
+        );
+
+        let reply: String = redis::cmd("REPLICAOF")
+            .arg("127.0.0.1")
+            .arg(b_port)
+            .query(&mut c)
+            .unwrap();
+        assert_eq!(reply, "OK Already connected to specified master");
+    })
+    .await
+    .unwrap();
+}
+
+// =========== Chained replication test ===========
+
+/// A cedis server running as a child process, killed on drop.
+struct ServerProcess(std::process::Child);
+
+impl ServerProcess {
+    fn spawn(args: &[&str]) -> Self {
+        let child = std::process::Command::new(env!("CARGO_BIN_EXE_cedis"))
+            .args(args)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        ServerProcess(child)
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn test_chained_replication_tree() {
+    let root = 16465;
+    let mids = [16466, 16467];
+    let leaves = [16468, 16469];
+    let dir = std::env::temp_dir().join(format!("cedis-chain-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_string_lossy().to_string();
+
+    let spawn = |port: u16, master: Option<u16>| {
+        let port = port.to_string();
+        let mut args = vec!["--port", port.as_str(), "--dir", dir.as_str()];
+        let master = master.map(|m| m.to_string());
+        if let Some(m) = &master {
+            args.extend(["--replicaof", "127.0.0.1", m.as_str()]);
+        }
+        ServerProcess::spawn(&args)
+    };
+    let _servers = [
+        spawn(root, None),
+        spawn(mids[0], Some(root)),
+        spawn(mids[1], Some(root)),
+        spawn(leaves[0], Some(mids[0])),
+        spawn(leaves[1], Some(mids[1])),
+    ];
+
+    let mut root_conn = get_client(root);
+    wait_for_info(&mut root_conn, "replication", "connected_slaves:2");
+    for mid in mids {
+        wait_for_info(&mut get_client(mid), "replication", "connected_slaves:1");
+    }
+
+    let _: () = root_conn.set("k", "v").unwrap();
+    let _: () = redis::cmd("SELECT").arg(3).query(&mut root_conn).unwrap();
+    let _: () = root_conn.set("k3", "in-db3").unwrap();
+    let acked: i64 = redis::cmd("WAIT")
+        .arg(2)
+        .arg(5000)
+        .query(&mut root_conn)
+        .unwrap();
+    assert_eq!(acked, 2);
+
+    let root_info: String = redis::cmd("INFO")
+        .arg("replication")
+        .query(&mut root_conn)
+        .unwrap();
+    let replid = info_field(&root_info, "master_replid");
+    let offset = info_field(&root_info, "master_repl_offset");
+
+    // Every node of the tree ends up with the same data, replid and offset
+    for port in leaves.into_iter().chain(mids) {
+        let mut conn = get_client(port);
+        let info = wait_for_info(
+            &mut conn,
+            "replication",
+            &format!("master_repl_offset:{offset}"),
+        );
+        assert_eq!(info_field(&info, "master_replid"), replid);
+
+        let value: String = conn.get("k").unwrap();
+        assert_eq!(value, "v");
+        let _: () = redis::cmd("SELECT").arg(3).query(&mut conn).unwrap();
+        let value: String = conn.get("k3").unwrap();
+        assert_eq!(value, "in-db3");
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_full_resync_disk_and_diskless() {
+    // (master repl-diskless-sync, replica repl-diskless-load)
+    let modes = [
+        (true, "swapdb"),
+        (false, "disabled"),
+        (true, "disabled"),
+        (false, "on-empty-db"),
+    ];
+    for (i, (diskless_sync, diskless_load)) in modes.into_iter().enumerate() {
+        let master_port = 16470 + 2 * i as u16;
+        let replica_port = master_port + 1;
+        start_server_with_config(cedis::config::Config {
+            port: master_port,
+            repl_diskless_sync: diskless_sync,
+            ..Default::default()
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        tokio::task::spawn_blocking(move || {
+            let mut master = get_client(master_port);
+            let mut pipe = redis::pipe();
+            for n in 0..20_000 {
+                pipe.cmd("SET").arg(format!("key:{n}")).arg(n).ignore();
+            }
+            pipe.cmd("RPUSH").arg("list").arg(&["a", "b", "c"]).ignore();
+            pipe.query::<()>(&mut master).unwrap();
+        })
+        .await
+        .unwrap();
+
+        start_server_with_config(cedis::config::Config {
+            port: replica_port,
+            replicaof: Some(("127.0.0.1".to_string(), master_port)),
+            repl_diskless_load: diskless_load.to_string(),
+            ..Default::default()
+        });
+
+        tokio::task::spawn_blocking(move || {
+            let mut master = get_client(master_port);
+            // Keep writing while the replica connects and loads the RDB
+            for _ in 0..300 {
+                let _: i64 = redis::cmd("INCR")
+                    .arg("counter")
+                    .query(&mut master)
+                    .unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            let acked: i64 = redis::cmd("WAIT")
+                .arg(1)
+                .arg(5000)
+                .query(&mut master)
+                .unwrap();
+            assert_eq!(
+                acked, 1,
+                "replica never caught up ({diskless_sync}, {diskless_load})"
+            );
+
+            let mut replica = get_client(replica_port);
+            wait_for_info(&mut replica, "replication", "master_link_status:up");
+            let size: i64 = redis::cmd("DBSIZE").query(&mut replica).unwrap();
+            assert_eq!(size, 20_002);
+            let counter: i64 = redis::cmd("GET")
+                .arg("counter")
+                .query(&mut replica)
+                .unwrap();
+            assert_eq!(counter, 300);
+            let list: Vec<String> = redis::cmd("LRANGE")
+                .arg("list")
+                .arg(0)
+                .arg(-1)
+                .query(&mut replica)
+                .unwrap();
+            assert_eq!(list, vec!["a", "b", "c"]);
+        })
+        .await
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_failover_to_replica() {
+    let a_port = 16478;
+    let b_port = 16479;
+    let c_port = 16480;
+    start_server(a_port);
+    for port in [b_port, c_port] {
+        start_server_with_config(cedis::config::Config {
+            port,
+            replicaof: Some(("127.0.0.1".to_string(), a_port)),
+            ..Default::default()
+        });
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut a = get_client(a_port);
+        let mut b = get_client(b_port);
+        let mut c = get_client(c_port);
+        wait_for_info(&mut a, "replication", "connected_slaves:2");
+
+        let err = redis::cmd("FAILOVER").query::<String>(&mut b).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("not valid when server is a replica")
+        );
+        let err = redis::cmd("FAILOVER")
+            .arg("ABORT")
+            .query::<String>(&mut a)
+            .unwrap_err();
+        assert!(err.to_string().contains("not in progress"));
+        let err = redis::cmd("FAILOVER")
+            .arg("TO")
+            .arg("127.0.0.1")
+            .arg(1)
+            .query::<String>(&mut a)
+            .unwrap_err();
+        assert!(err.to_string().contains("is not a replica"));
+
+        for n in 0..100 {
+            let _: () = redis::cmd("SET")
+                .arg(format!("k{n}"))
+                .arg(n)
+                .query(&mut a)
+                .unwrap();
+        }
+        // A transaction opened before the handover, executed after it
+        let mut tx = get_client(a_port);
+        let _: () = redis::cmd("MULTI").query(&mut tx).unwrap();
+        let _: () = redis::cmd("INCR").arg("counter").query(&mut tx).unwrap();
+        let _: () = redis::cmd("FAILOVER")
+            .arg("TO")
+            .arg("127.0.0.1")
+            .arg(b_port)
+            .arg("TIMEOUT")
+            .arg(5000)
+            .query(&mut a)
+            .unwrap();
+
+        // B takes over; A follows it without losing any write
+        wait_for_info(&mut b, "replication", "role:master");
+        let info = wait_for_info(&mut a, "replication", "master_link_status:up");
+        assert!(info.contains("role:slave"));
+        assert_eq!(info_field(&info, "master_port"), b_port.to_string());
+        assert_eq!(info_field(&info, "master_failover_state"), "no-failover");
+        let mut exists = redis::cmd("EXISTS");
+        for n in 0..100 {
+            exists.arg(format!("k{n}"));
+        }
+        let found: i64 = exists.query(&mut b).unwrap();
+        assert_eq!(found, 100);
+
+        let err = redis::cmd("SET")
+            .arg("x")
+            .arg(1)
+            .query::<()>(&mut a)
+            .unwrap_err();
+        assert_eq!(err.kind(), redis::ErrorKind::ReadOnly);
+
+        // The pause held EXEC back until A was a replica, so it is refused
+        let err = redis::cmd("EXEC").query::<Vec<i64>>(&mut tx).unwrap_err();
+        assert_eq!(err.kind(), redis::ErrorKind::ReadOnly);
+        let counter: Option<i64> = redis::cmd("GET").arg("counter").query(&mut a).unwrap();
+        let on_b: Option<i64> = redis::cmd("GET").arg("counter").query(&mut b).unwrap();
+        assert_eq!(counter, on_b);
+
+        // New writes on B reach A, and C through A
+        let _: () = redis::cmd("SET")
+            .arg("after")
+            .arg("yes")
+            .query(&mut b)
+            .unwrap();
+        let acked: i64 = redis::cmd("WAIT").arg(1).arg(5000).query(&mut b).unwrap();
+        assert_eq!(acked, 1);
+        let start = Instant::now();
+        loop {
+            let a_val: Option<String> = redis::cmd("GET").arg("after").query(&mut a).unwrap();
+            let c_val: Option<String> = redis::cmd("GET").arg("after").query(&mut c).unwrap();
+            if a_val.is_some() && c_val.is_some() {
+                break;
+            }
+            assert!(start.elapsed().as_secs() < 5, "write never reached A and C");
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_masterauth_role_and_stale_data() {
+    let master_port = 16481;
+    let replica_port = 16482;
+    let noauth_port = 16483;
+    let stale_port = 16484;
+    start_server_with_config(cedis::config::Config {
+        port: master_port,
+        requirepass: Some("secret".to_string()),
+        ..Default::default()
+    });
+    start_server_with_config(cedis::config::Config {
+        port: replica_port,
+        replicaof: Some(("127.0.0.1".to_string(), master_port)),
+        masterauth: Some("secret".to_string()),
+        ..Default::default()
+    });
+    start_server_with_config(cedis::config::Config {
+        port: noauth_port,
+        replicaof: Some(("127.0.0.1".to_string(), master_port)),
+        ..Default::default()
+    });
+    // Nothing listens on port 1, so this replica never gets a link
+    start_server_with_config(cedis::config::Config {
+        port: stale_port,
+        replicaof: Some(("127.0.0.1".to_string(), 1)),
+        replica_serve_stale_data: false,
+        ..Default::default()
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut master = get_client(master_port);
+        let _: () = redis::cmd("AUTH").arg("secret").query(&mut master).unwrap();
+        let mut replica = get_client(replica_port);
+        wait_for_info(&mut replica, "replication", "master_link_status:up");
+        let _: () = redis::cmd("SET")
+            .arg("k")
+            .arg("v")
+            .query(&mut master)
+            .unwrap();
+        let acked: i64 = redis::cmd("WAIT")
+            .arg(1)
+            .arg(5000)
+            .query(&mut master)
+            .unwrap();
+        assert_eq!(acked, 1);
+
+        // Only the replica with masterauth got in
+        let info: String = redis::cmd("INFO")
+            .arg("replication")
+            .query(&mut master)
+            .unwrap();
+        assert_eq!(info_field(&info, "connected_slaves"), "1");
+        let mut noauth = get_client(noauth_port);
+        let info: String = redis::cmd("INFO")
+            .arg("replication")
+            .query(&mut noauth)
+            .unwrap();
+        assert_eq!(info_field(&info, "master_link_status"), "down");
+
+        let role: redis::Value = redis::cmd("ROLE").query(&mut master).unwrap();
+        let redis::Value::Array(items) = role else {
+            panic!("unexpected ROLE reply: {role:?}");
+        };
+        assert_eq!(items[0], redis::Value::BulkString(b"master".to_vec()));
+        let redis::Value::Array(replicas) = &items[2] else {
+            panic!("unexpected replica list: {:?}", items[2]);
+        };
+        assert_eq!(replicas.len(), 1);
+        let replica_entry: Vec<String> = redis::from_redis_value(&replicas[0]).unwrap();
+        assert_eq!(replica_entry[0], "127.0.0.1");
+        assert_eq!(replica_entry[1], replica_port.to_string());
+
+        let (kind, host, port, link, _offset): (String, String, u16, String, i64) =
+            redis::cmd("ROLE").query(&mut replica).unwrap();
+        assert_eq!(
+            (kind.as_str(), host.as_str(), port, link.as_str()),
+            ("slave", "127.0.0.1", master_port, "connected")
+        );
+        let value: String = redis::cmd("GET").arg("k").query(&mut replica).unwrap();
+        assert_eq!(value, "v");
+
+        // replica-serve-stale-data no refuses queries while the link is down
+        let mut stale = get_client(stale_port);
+        let err = redis::cmd("GET")
+            .arg("k")
+            .query::<Option<String>>(&mut stale)
+            .unwrap_err();
+        assert_eq!(err.kind(), redis::ErrorKind::MasterDown);
+        let (kind, _, _, link, _): (String, String, u16, String, i64) =
+            redis::cmd("ROLE").query(&mut stale).unwrap();
+        assert_eq!((kind.as_str(), link.as_str()), ("slave", "connecting"));
+        let pong: String = redis::cmd("PING").query(&mut stale).unwrap();
+        assert_eq!(pong, "PONG");
+    })
+    .await
+    .unwrap();
+}
+
+/// Data servers for a sentinel test: a master and two replicas in their own
+/// directory, each replica started with the given priority.
+fn spawn_sentinel_cluster(
+    name: &str,
+    master: u16,
+    replicas: [(u16, u64); 2],
+) -> (ServerProcess, Vec<ServerProcess>) {
+    let dir = std::env::temp_dir().join(format!("cedis-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_string_lossy().to_string();
+    let master_port = master.to_string();
+    let master_proc = ServerProcess::spawn(&["--port", &master_port, "--dir", &dir]);
+    // Replicas back off when the master isn't up yet
+    get_client(master);
+    let replica_procs = replicas
+        .iter()
+        .map(|(port, priority)| {
+            ServerProcess::spawn(&[
+                "--port",
+                &port.to_string(),
+                "--dir",
+                &dir,
+                "--replicaof",
+                "127.0.0.1",
+                &master_port,
+                "--replica-priority",
+                &priority.to_string(),
+            ])
+        })
+        .collect();
+    (master_proc, replica_procs)
+}
+
+fn spawn_sentinel(port: u16, master: u16) -> ServerProcess {
+    ServerProcess::spawn(&[
+        "--sentinel",
+        "--port",
+        &port.to_string(),
+        "--sentinel",
+        "monitor",
+        "mymaster",
+        "127.0.0.1",
+        &master.to_string(),
+        "2",
+        "--sentinel",
+        "down-after-milliseconds",
+        "mymaster",
+        "1000",
+        "--sentinel",
+        "failover-timeout",
+        "mymaster",
+        "5000",
+    ])
+}
+
+fn sentinel_master_port(conn: &mut redis::Connection) -> u16 {
+    let addr: Vec<String> = redis::cmd("SENTINEL")
+        .arg("GET-MASTER-ADDR-BY-NAME")
+        .arg("mymaster")
+        .query(conn)
+        .unwrap();
+    addr[1].parse().unwrap()
+}
+
+/// Poll until `f` holds, for up to `secs` seconds.
+fn wait_until(secs: u64, what: &str, mut f: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !f() {
+        assert!(
+            start.elapsed().as_secs() < secs,
+            "timed out waiting for {what}"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
+
+/// Field/value pairs of every entry in a `SENTINEL REPLICAS`-style reply.
+fn sentinel_entries(conn: &mut redis::Connection, args: &[&str]) -> Vec<HashMap<String, String>> {
+    let entries: Vec<Vec<String>> = redis::cmd("SENTINEL").arg(args).query(conn).unwrap();
+    entries
+        .into_iter()
+        .map(|e| {
+            e.chunks(2)
+                .map(|kv| (kv[0].clone(), kv[1].clone()))
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_sentinel_automatic_failover() {
+    let master = 16490;
+    let (replica1, replica2) = (16491, 16492);
+    let sentinels = [16493, 16494, 16495];
+    // replica2 may never be promoted
+    let (master_proc, _replicas) =
+        spawn_sentinel_cluster("sentinel-auto", master, [(replica1, 100), (replica2, 0)]);
+    let mut m = get_client(master);
+    wait_for_info(&mut m, "replication", "connected_slaves:2");
+    let _sentinels: Vec<_> = sentinels
+        .iter()
+        .map(|&port| spawn_sentinel(port, master))
+        .collect();
+    let _: () = redis::cmd("SET").arg("k").arg("v").query(&mut m).unwrap();
+    let acked: i64 = redis::cmd("WAIT").arg(2).arg(5000).query(&mut m).unwrap();
+    assert_eq!(acked, 2);
+
+    // Sentinels find the replicas and each other
+    let mut s: Vec<_> = sentinels.iter().map(|&p| get_client(p)).collect();
+    for conn in s.iter_mut() {
+        wait_until(10, "discovery", || {
+            let replicas = sentinel_entries(conn, &["REPLICAS", "mymaster"]);
+            replicas.len() == 2
+                && replicas.iter().all(|r| r["master-host"] != "?")
+                && sentinel_entries(conn, &["SENTINELS", "mymaster"]).len() == 2
+        });
+    }
+    let ok: String = redis::cmd("SENTINEL")
+        .arg("CKQUORUM")
+        .arg("mymaster")
+        .query(&mut s[0])
+        .unwrap();
+    assert!(ok.starts_with("OK 3 usable Sentinels"));
+    let role: Vec<redis::Value> = redis::cmd("ROLE").query(&mut s[0]).unwrap();
+    assert_eq!(role[0], redis::Value::BulkString(b"sentinel".to_vec()));
+
+    drop(master_proc);
+
+    // Every sentinel agrees on the only eligible replica
+    for conn in s.iter_mut() {
+        wait_until(30, "switch-master", || {
+            sentinel_master_port(conn) == replica1
+        });
+    }
+    let mut r1 = get_client(replica1);
+    let mut r2 = get_client(replica2);
+    wait_for_info(&mut r1, "replication", "role:master");
+    wait_until(15, "replica2 to follow the new master", || {
+        let info: String = redis::cmd("INFO")
+            .arg("replication")
+            .query(&mut r2)
+            .unwrap();
+        info_field(&info, "master_port") == replica1.to_string()
+            && info_field(&info, "master_link_status") == "up"
+    });
+    let v: String = redis::cmd("GET").arg("k").query(&mut r2).unwrap();
+    assert_eq!(v, "v");
+
+    // The old master is now listed as a replica of the new one
+    let replicas = sentinel_entries(&mut s[0], &["REPLICAS", "mymaster"]);
+    assert!(replicas.iter().any(|r| r["port"] == master.to_string()));
+}
+
+#[test]
+fn test_sentinel_forced_failover() {
+    let master = 16496;
+    let (replica1, replica2) = (16497, 16498);
+    let sentinels = [16499, 16500, 16501];
+    // replica2 has the better (lower) priority
+    let (_master, _replicas) =
+        spawn_sentinel_cluster("sentinel-forced", master, [(replica1, 100), (replica2, 10)]);
+    let mut m = get_client(master);
+    wait_for_info(&mut m, "replication", "connected_slaves:2");
+    let _sentinels: Vec<_> = sentinels
+        .iter()
+        .map(|&port| spawn_sentinel(port, master))
+        .collect();
+    let mut s: Vec<_> = sentinels.iter().map(|&p| get_client(p)).collect();
+    for conn in s.iter_mut() {
+        wait_until(10, "discovery", || {
+            let replicas = sentinel_entries(conn, &["REPLICAS", "mymaster"]);
+            replicas.len() == 2
+                && replicas.iter().all(|r| r["master-host"] != "?")
+                && sentinel_entries(conn, &["SENTINELS", "mymaster"]).len() == 2
+        });
+    }
+    let masters = sentinel_entries(&mut s[0], &["MASTERS"]);
+    assert_eq!(masters[0]["name"], "mymaster");
+    assert_eq!(masters[0]["flags"], "master");
+    assert_eq!(masters[0]["num-other-sentinels"], "2");
+
+    let err = redis::cmd("SENTINEL")
+        .arg("FAILOVER")
+        .arg("nosuch")
+        .query::<String>(&mut s[0])
+        .unwrap_err();
+    assert!(err.to_string().contains("No such master"));
+    let _: () = redis::cmd("SENTINEL")
+        .arg("FAILOVER")
+        .arg("mymaster")
+        .query(&mut s[0])
+        .unwrap();
+
+    for conn in s.iter_mut() {
+        wait_until(20, "switch-master", || {
+            sentinel_master_port(conn) == replica2
+        });
+    }
+    let mut r2 = get_client(replica2);
+    wait_for_info(&mut r2, "replication", "role:master");
+    // The old master and the other replica both follow the promoted one
+    for port in [master, replica1] {
+        let mut conn = get_client(port);
+        wait_until(15, "reconfiguration", || {
+            let info: String = redis::cmd("INFO")
+                .arg("replication")
+                .query(&mut conn)
+                .unwrap();
+            info_field(&info, "master_port") == replica2.to_string()
+                && info_field(&info, "master_link_status") == "up"
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_latency_monitor() {
+    let port = 16502;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+
+        // Disabled by default
+        let _: () = redis::cmd("DEBUG")
+            .arg("SLEEP")
+            .arg(0.02)
+            .query(&mut conn)
+            .unwrap();
+        let latest: Vec<redis::Value> = redis::cmd("LATENCY")
+            .arg("LATEST")
+            .query(&mut conn)
+            .unwrap();
+        assert!(latest.is_empty());
+        let doctor: String = redis::cmd("LATENCY")
+            .arg("DOCTOR")
+            .query(&mut conn)
+            .unwrap();
+        assert!(doctor.contains("disabled"));
+
+        let _: () = redis::cmd("CONFIG")
+            .arg("SET")
+            .arg("latency-monitor-threshold")
+            .arg(10)
+            .query(&mut conn)
+            .unwrap();
+        let _: () = redis::cmd("DEBUG")
+            .arg("SLEEP")
+            .arg(0.05)
+            .query(&mut conn)
+            .unwrap();
+        let _: () = redis::cmd("SET")
+            .arg("k")
+            .arg("v")
+            .query(&mut conn)
+            .unwrap();
+
+        let latest: Vec<(String, i64, i64, i64)> = redis::cmd("LATENCY")
+            .arg("LATEST")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(latest.len(), 1);
+        let (event, _, last, max) = &latest[0];
+        assert_eq!(event, "command");
+        assert!(*last >= 50 && *max >= 50, "{latest:?}");
+
+        let history: Vec<(i64, i64)> = redis::cmd("LATENCY")
+            .arg("HISTORY")
+            .arg("command")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        let graph: String = redis::cmd("LATENCY")
+            .arg("GRAPH")
+            .arg("command")
+            .query(&mut conn)
+            .unwrap();
+        assert!(graph.starts_with("command - high"));
+        let err = redis::cmd("LATENCY")
+            .arg("GRAPH")
+            .arg("rdb-save")
+            .query::<String>(&mut conn)
+            .unwrap_err();
+        assert!(err.to_string().contains("No samples available"));
+        let doctor: String = redis::cmd("LATENCY")
+            .arg("DOCTOR")
+            .query(&mut conn)
+            .unwrap();
+        assert!(doctor.contains("1. command: 1 latency spikes"));
+
+        // Every command is counted in its histogram, fast or slow
+        let histogram: Vec<redis::Value> = redis::cmd("LATENCY")
+            .arg("HISTOGRAM")
+            .arg("set")
+            .arg("debug")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(histogram.len(), 4);
+        assert_eq!(histogram[0], redis::Value::BulkString(b"debug".to_vec()));
+        let redis::Value::Array(fields) = &histogram[3] else {
+            panic!("unexpected reply {histogram:?}");
+        };
+        assert_eq!(fields[1], redis::Value::Int(1));
+
+        let reset: i64 = redis::cmd("LATENCY").arg("RESET").query(&mut conn).unwrap();
+        assert_eq!(reset, 1);
+        let latest: Vec<redis::Value> = redis::cmd("LATENCY")
+            .arg("LATEST")
+            .query(&mut conn)
+            .unwrap();
+        assert!(latest.is_empty());
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_info_command_and_error_stats() {
+    let port = 16503;
+    let _server = start_server_with_config(cedis::config::Config {
+        port,
+        requirepass: Some("secret".to_string()),
+        ..Default::default()
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+        let err = redis::cmd("GET")
+            .arg("k")
+            .query::<()>(&mut conn)
+            .unwrap_err();
+        assert_eq!(err.code(), Some("NOAUTH"));
+        let _: () = redis::cmd("AUTH").arg("secret").query(&mut conn).unwrap();
+
+        let _: () = redis::cmd("SET")
+            .arg("k")
+            .arg("v")
+            .query(&mut conn)
+            .unwrap();
+        // Arity errors are refused before the command runs
+        assert!(redis::cmd("SET").arg("a").query::<()>(&mut conn).is_err());
+        assert!(redis::cmd("INCR").arg("k").query::<i64>(&mut conn).is_err());
+        assert!(
+            redis::cmd("LPUSH")
+                .arg("k")
+                .arg("x")
+                .query::<i64>(&mut conn)
+                .is_err()
+        );
+        assert!(redis::cmd("NOSUCHCMD").query::<()>(&mut conn).is_err());
+
+        let info: String = redis::cmd("INFO")
+            .arg("commandstats")
+            .query(&mut conn)
+            .unwrap();
+        assert!(info.starts_with("# Commandstats\r\n"));
+        let get = info_field(&info, "cmdstat_get");
+        assert!(get.starts_with("calls=0,usec=0,"), "{get}");
+        assert!(get.ends_with(",rejected_calls=1,failed_calls=0"), "{get}");
+        let set = info_field(&info, "cmdstat_set");
+        assert!(set.starts_with("calls=1,usec="), "{set}");
+        assert!(set.contains(",usec_per_call="), "{set}");
+        assert!(set.ends_with(",rejected_calls=1,failed_calls=0"), "{set}");
+        assert!(info_field(&info, "cmdstat_incr").ends_with("failed_calls=1"));
+        assert!(!info.contains("nosuchcmd"));
+
+        let info: String = redis::cmd("INFO")
+            .arg("errorstats")
+            .query(&mut conn)
+            .unwrap();
+        // The client library's own handshake commands are refused too
+        let noauth: u64 = info_field(&info, "errorstat_NOAUTH")
+            .strip_prefix("count=")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(noauth >= 1);
+        assert_eq!(info_field(&info, "errorstat_ERR"), "count=3");
+        assert_eq!(info_field(&info, "errorstat_WRONGTYPE"), "count=1");
+        let info: String = redis::cmd("INFO").arg("stats").query(&mut conn).unwrap();
+        assert_eq!(
+            info_field(&info, "total_error_replies"),
+            (noauth + 4).to_string()
+        );
+
+        let info: String = redis::cmd("INFO")
+            .arg("latencystats")
+            .query(&mut conn)
+            .unwrap();
+        let set = info_field(&info, "latency_percentiles_usec_set");
+        assert!(set.starts_with("p50=") && set.contains(",p99=") && set.contains(",p99.9="));
+        assert!(info_field(&info, "latency_percentiles_usec_get").is_empty());
+
+        // Only listed by default when asked for by name or with ALL
+        let info: String = redis::cmd("INFO").query(&mut conn).unwrap();
+        assert!(info.contains("# Errorstats"));
+        assert!(!info.contains("# Commandstats") && !info.contains("# Latencystats"));
+        let info: String = redis::cmd("INFO").arg("all").query(&mut conn).unwrap();
+        assert!(info.contains("# Commandstats") && info.contains("# Latencystats"));
+
+        let _: () = redis::cmd("CONFIG")
+            .arg("RESETSTAT")
+            .query(&mut conn)
+            .unwrap();
+        let info: String = redis::cmd("INFO")
+            .arg("errorstats")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(info, "# Errorstats\r\n\r\n");
+        let info: String = redis::cmd("INFO")
+            .arg("commandstats")
+            .query(&mut conn)
+            .unwrap();
+        assert!(info_field(&info, "cmdstat_set").is_empty());
+        // Only the INFO errorstats above; a call is accounted once it completes
+        assert!(info_field(&info, "cmdstat_info").starts_with("calls=1,"));
+    })
+    .await
+    .unwrap();
+}
+
+/// Plain HTTP GET, returning the status line and body.
+fn http_get(port: u16, path: &str) -> (String, String) {
+    use std::io::{Read, Write};
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+#[tokio::test]
+async fn test_prometheus_metrics_endpoint() {
+    let port = 16504;
+    let metrics_port = 16505;
+    let _server = start_server_with_config(cedis::config::Config {
+        port,
+        metrics_port,
+        ..Default::default()
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+        let _: () = conn.set("a", "1").unwrap();
+        let _: () = conn.set_ex("b", "2", 100).unwrap();
+        let _: Option<String> = conn.get("a").unwrap();
+        assert!(redis::cmd("INCRBY").arg("a").arg("x").query::<i64>(&mut conn).is_err());
+
+        let info: String = redis::cmd("INFO").arg("clients").query(&mut conn).unwrap();
+        assert_eq!(info_field(&info, "connected_clients"), "1");
+
+        let (status, body) = http_get(metrics_port, "/metrics");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let lines: Vec<&str> = body.lines().collect();
+        for expected in [
+            "# TYPE redis_commands_total counter",
+            "redis_instance_info{role=\"master\",redis_version=\"7.0.0\",cedis_version=\"0.1.0\"} 1",
+            "redis_db_keys{db=\"db0\"} 2",
+            "redis_db_keys_expiring{db=\"db0\"} 1",
+            "redis_db_keys{db=\"db1\"} 0",
+            "redis_connected_clients 1",
+            "redis_evicted_keys_total 0",
+            "redis_connected_slaves 0",
+            "redis_aof_enabled 0",
+            "redis_slowlog_length 0",
+            "redis_commands_total{cmd=\"set\"} 1",
+            "redis_commands_total{cmd=\"setex\"} 1",
+            "redis_commands_failed_calls_total{cmd=\"incrby\"} 1",
+            "redis_latency_percentiles_usec_count{cmd=\"get\"} 1",
+        ] {
+            assert!(lines.contains(&expected), "missing {expected:?} in:\n{body}");
+        }
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.starts_with("redis_latency_percentiles_usec{cmd=\"set\",quantile=\"0.99\"} ")),
+            "{body}"
+        );
+        for prefix in [
+            "redis_memory_used_bytes ",
+            "redis_master_repl_offset ",
+            "redis_errors_total{err=\"ERR\"} ",
+        ] {
+            assert!(lines.iter().any(|l| l.starts_with(prefix)), "{body}");
+        }
+
+        let (status, _) = http_get(metrics_port, "/nope");
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    })
+    .await
+    .unwrap();
+}
+
+fn info_int(conn: &mut redis::Connection, section: &str, field: &str) -> i64 {
+    let info: String = redis::cmd("INFO").arg(section).query(conn).unwrap();
+    info_field(&info, field).parse().unwrap()
+}
+
+#[test]
+fn test_memory_accounting() {
+    let port = 16506;
+    let dir = std::env::temp_dir().join(format!("cedis-memory-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let port_arg = port.to_string();
+    let _server = ServerProcess::spawn(&["--port", &port_arg, "--dir", dir.to_str().unwrap()]);
+    let mut conn = get_client(port);
+
+    // The server binary counts real heap usage
+    let before = info_int(&mut conn, "memory", "used_memory");
+    let _: () = conn.set("big", vec![b'x'; 4 << 20]).unwrap();
+    let after = info_int(&mut conn, "memory", "used_memory");
+    assert!(after - before >= 4 << 20, "{before} -> {after}");
+    let _: () = conn.del("big").unwrap();
+    let freed = info_int(&mut conn, "memory", "used_memory");
+    assert!(after - freed >= (4 << 20) * 9 / 10, "{after} -> {freed}");
+    assert!(info_int(&mut conn, "memory", "used_memory_peak") >= after);
+
+    // MEMORY USAGE walks the value, sampling aggregates
+    let _: () = conn.set("s", "x".repeat(1000)).unwrap();
+    let usage: i64 = redis::cmd("MEMORY")
+        .arg("USAGE")
+        .arg("s")
+        .query(&mut conn)
+        .unwrap();
+    assert!((1000..1200).contains(&usage), "{usage}");
+    for i in 0..200 {
+        let value = if i < 100 {
+            "y".repeat(10)
+        } else {
+            "y".repeat(1000)
+        };
+        let _: () = conn.hset("h", format!("field{i}"), value).unwrap();
+    }
+    let full: i64 = redis::cmd("MEMORY")
+        .arg("USAGE")
+        .arg("h")
+        .arg("SAMPLES")
+        .arg(0)
+        .query(&mut conn)
+        .unwrap();
+    assert!(full > 100 * 1000, "{full}");
+    let _: i64 = redis::cmd("MEMORY")
+        .arg("USAGE")
+        .arg("h")
+        .query(&mut conn)
+        .unwrap();
+    let _: () = conn
+        .geo_add("g", (13.361389, 38.115556, "Palermo"))
+        .unwrap();
+    let geo: i64 = redis::cmd("MEMORY")
+        .arg("USAGE")
+        .arg("g")
+        .query(&mut conn)
+        .unwrap();
+    assert!(geo > 0);
+    let missing: Option<i64> = redis::cmd("MEMORY")
+        .arg("USAGE")
+        .arg("nokey")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(missing, None);
+    let err = redis::cmd("MEMORY")
+        .arg("USAGE")
+        .arg("s")
+        .arg("SAMPLES")
+        .arg(-1)
+        .query::<i64>(&mut conn)
+        .unwrap_err();
+    assert!(err.to_string().contains("out of range"));
+
+    let stats: Vec<redis::Value> = redis::cmd("MEMORY").arg("STATS").query(&mut conn).unwrap();
+    let field = |name: &str| {
+        let i = stats
+            .iter()
+            .position(|v| *v == redis::Value::BulkString(name.as_bytes().to_vec()))
+            .unwrap_or_else(|| panic!("no {name} in {stats:?}"));
+        stats[i + 1].clone()
+    };
+    assert_eq!(field("keys.count"), redis::Value::Int(3));
+    let redis::Value::Int(dataset) = field("dataset.bytes") else {
+        panic!("{stats:?}");
+    };
+    assert!(dataset > full, "{dataset}");
+    assert!(matches!(field("db.0"), redis::Value::Array(_)));
+
+    // Enough data for the doctor to look at, well under the peak
+    let _: () = conn.set("ballast", vec![b'b'; 6 << 20]).unwrap();
+    let doctor: String = redis::cmd("MEMORY").arg("DOCTOR").query(&mut conn).unwrap();
+    // The 4MB value deleted above left a peak behind
+    assert!(doctor.contains("* Peak memory"), "{doctor}");
+
+    // Eviction sees the allocator's figure too
+    let used = info_int(&mut conn, "memory", "used_memory");
+    let _: () = redis::cmd("CONFIG")
+        .arg("SET")
+        .arg("maxmemory-policy")
+        .arg("allkeys-random")
+        .query(&mut conn)
+        .unwrap();
+    let _: () = redis::cmd("CONFIG")
+        .arg("SET")
+        .arg("maxmemory")
+        .arg(used + (1 << 20))
+        .query(&mut conn)
+        .unwrap();
+    // Well past the limit, whatever transient buffers `used` included
+    for i in 0..160 {
+        let _: () = conn.set(format!("fill{i}"), vec![b'z'; 100 << 10]).unwrap();
+    }
+    wait_until(5, "evictions", || {
+        info_int(&mut conn, "stats", "evicted_keys") > 0
+            && info_int(&mut conn, "memory", "used_memory") <= used + (1 << 20)
+    });
+}
+
+#[tokio::test]
+async fn test_sharded_keyspace_locking() {
+    let port = 16507;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        // Concurrent clients on the same key and on keys spread over shards
+        let handles: Vec<_> = (0..8)
+            .map(|c| {
+                std::thread::spawn(move || {
+                    let mut conn = get_client(port);
+                    for i in 0..200 {
+                        let _: i64 = conn.incr("shared_counter", 1).unwrap();
+                        let _: () = conn.set(format!("client{c}:{i}"), i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let mut conn = get_client(port);
+        let total: i64 = conn.get("shared_counter").unwrap();
+        assert_eq!(total, 8 * 200);
+        let size: i64 = redis::cmd("DBSIZE").query(&mut conn).unwrap();
+        assert_eq!(size, 8 * 200 + 1);
+
+        // MULTI/EXEC over keys in different shards, with a SELECT in between
+        let _: () = redis::cmd("MULTI").query(&mut conn).unwrap();
+        for cmd in [
+            redis::cmd("SET").arg("tx:a").arg("1").clone(),
+            redis::cmd("RENAME").arg("tx:a").arg("tx:b").clone(),
+            redis::cmd("SELECT").arg(1).clone(),
+            redis::cmd("SET").arg("tx:c").arg("3").clone(),
+            redis::cmd("SELECT").arg(0).clone(),
+        ] {
+            let queued: String = cmd.query(&mut conn).unwrap();
+            assert_eq!(queued, "QUEUED");
+        }
+        let _: redis::Value = redis::cmd("EXEC").query(&mut conn).unwrap();
+        let b: String = conn.get("tx:b").unwrap();
+        assert_eq!(b, "1");
+        let moved: i64 = redis::cmd("MOVE")
+            .arg("tx:b")
+            .arg(1)
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(moved, 1);
+        let _: () = redis::cmd("SELECT").arg(1).query(&mut conn).unwrap();
+        let keys: Vec<String> = redis::cmd("MGET")
+            .arg("tx:b")
+            .arg("tx:c")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(keys, ["1", "3"]);
+        let _: () = redis::cmd("SELECT").arg(0).query(&mut conn).unwrap();
+
+        // Scripts may use keys they don't declare
+        let v: String = redis::cmd("EVAL")
+            .arg("return redis.call('GET', 'shared_counter')")
+            .arg(1)
+            .arg("declared")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(v, "1600");
+        let v: String = redis::cmd("EVAL")
+            .arg("return redis.call('GET', 'shared_counter')")
+            .arg(0)
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(v, "1600");
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_scan_cursor_iteration() {
+    let port = 16508;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+        for i in 0..500 {
+            let _: () = conn.set(format!("k:{i}"), i).unwrap();
+        }
+        let _: () = conn.set("other", 1).unwrap();
+        let members: Vec<String> = (0..300).map(|i| format!("m{i}")).collect();
+        for m in &members {
+            let _: () = conn.hset("h", m, 1).unwrap();
+            let _: () = conn.sadd("s", m).unwrap();
+            let _: () = conn.zadd("z", m, 1).unwrap();
+        }
+
+        // SCAN returns every key once the cursor comes back to 0, a few at a
+        // time, even while new keys are being added
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = "0".to_string();
+        let mut calls = 0;
+        loop {
+            let (next, keys): (String, Vec<String>) = redis::cmd("SCAN")
+                .arg(&cursor)
+                .arg("MATCH")
+                .arg("k:*")
+                .arg("COUNT")
+                .arg(20)
+                .query(&mut conn)
+                .unwrap();
+            assert!(keys.len() <= 40, "SCAN returned {} keys", keys.len());
+            seen.extend(keys);
+            let _: () = conn.set(format!("new:{calls}"), calls).unwrap();
+            calls += 1;
+            if next == "0" {
+                break;
+            }
+            cursor = next;
+        }
+        assert!(calls > 5);
+        for i in 0..500 {
+            assert!(seen.contains(&format!("k:{i}")), "k:{i} missing");
+        }
+        assert!(seen.iter().all(|k| k.starts_with("k:")));
+
+        // HSCAN/SSCAN/ZSCAN page through their elements the same way
+        for (cmd, key, stride) in [("HSCAN", "h", 2), ("SSCAN", "s", 1), ("ZSCAN", "z", 2)] {
+            let mut seen = std::collections::HashSet::new();
+            let mut cursor = "0".to_string();
+            let mut calls = 0;
+            loop {
+                let (next, items): (String, Vec<String>) = redis::cmd(cmd)
+                    .arg(key)
+                    .arg(&cursor)
+                    .arg("COUNT")
+                    .arg(25)
+                    .query(&mut conn)
+                    .unwrap();
+                seen.extend(items.into_iter().step_by(stride));
+                calls += 1;
+                if next == "0" {
+                    break;
+                }
+                cursor = next;
+            }
+            assert!(calls > 5, "{cmd} took {calls} calls");
+            assert_eq!(seen.len(), members.len(), "{cmd}");
+        }
+
+        let (_, fields): (String, Vec<String>) = redis::cmd("HSCAN")
+            .arg("h")
+            .arg(0)
+            .arg("MATCH")
+            .arg("m1?")
+            .arg("COUNT")
+            .arg(1000)
+            .arg("NOVALUES")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(fields.len(), 10);
+
+        let err = redis::cmd("SCAN")
+            .arg("-1")
+            .query::<redis::Value>(&mut conn)
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid cursor"));
+        let err = redis::cmd("SSCAN")
+            .arg("s")
+            .arg(0)
+            .arg("COUNT")
+            .arg(0)
+            .query::<redis::Value>(&mut conn)
+            .unwrap_err();
+        assert!(err.to_string().contains("syntax error"));
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_active_expire_cycle() {
+    let port = 16509;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+        let _: () = redis::cmd("CONFIG")
+            .arg("SET")
+            .arg("active-expire-effort")
+            .arg(5)
+            .query(&mut conn)
+            .unwrap();
+        let effort: Vec<String> = redis::cmd("CONFIG")
+            .arg("GET")
+            .arg("active-expire-effort")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(effort, vec!["active-expire-effort", "5"]);
+        assert!(
+            redis::cmd("CONFIG")
+                .arg("SET")
+                .arg("active-expire-effort")
+                .arg(11)
+                .query::<()>(&mut conn)
+                .is_err()
+        );
+
+        for i in 0..2000 {
+            let _: () = conn.set(format!("persistent:{i}"), i).unwrap();
+        }
+        for i in 0..300 {
+            let _: () = conn.pset_ex(format!("volatile:{i}"), i, 50).unwrap();
+        }
+
+        // The background cycle removes the expired keys without them being
+        // touched
+        let start = Instant::now();
+        loop {
+            let size: i64 = redis::cmd("DBSIZE").query(&mut conn).unwrap();
+            if size == 2000 {
+                break;
+            }
+            assert!(start.elapsed().as_secs() < 5, "{size} keys left");
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        let info: String = redis::cmd("INFO").arg("stats").query(&mut conn).unwrap();
+        let field = |name: &str| -> f64 {
+            info.lines()
+                .find_map(|l| l.strip_prefix(&format!("{name}:")))
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap()
+        };
+        assert_eq!(field("expired_keys"), 300.0);
+        assert!(field("expired_stale_perc") > 0.0);
+        assert!(field("expire_cycle_cpu_milliseconds") >= 0.0);
+        let keyspace: String = redis::cmd("INFO").arg("keyspace").query(&mut conn).unwrap();
+        assert!(keyspace.contains("db0:keys=2000,expires=0"), "{keyspace}");
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_lazyfree() {
+    let port = 16510;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+        let members: Vec<i64> = (0..1000).collect();
+        let lazyfreed = |conn: &mut redis::Connection| -> i64 {
+            let info: String = redis::cmd("INFO").arg("memory").query(conn).unwrap();
+            assert!(info.contains("lazyfree_pending_objects:"));
+            info.lines()
+                .find_map(|l| l.strip_prefix("lazyfreed_objects:"))
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap()
+        };
+        let wait_for = |conn: &mut redis::Connection, n: i64| {
+            let start = Instant::now();
+            while lazyfreed(conn) < n {
+                assert!(start.elapsed().as_secs() < 5);
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+        };
+
+        // UNLINK frees large values in the background, small ones in place
+        let _: () = conn.sadd("big", &members).unwrap();
+        let _: () = conn.set("small", "v").unwrap();
+        let n: i64 = redis::cmd("UNLINK")
+            .arg("big")
+            .arg("small")
+            .arg("missing")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(n, 2);
+        wait_for(&mut conn, 1);
+        assert_eq!(lazyfreed(&mut conn), 1);
+
+        // DEL only does with lazyfree-lazy-user-del
+        let _: () = conn.sadd("big", &members).unwrap();
+        let _: () = conn.del("big").unwrap();
+        assert_eq!(lazyfreed(&mut conn), 1);
+        let _: () = redis::cmd("CONFIG")
+            .arg("SET")
+            .arg("lazyfree-lazy-user-del")
+            .arg("yes")
+            .query(&mut conn)
+            .unwrap();
+        let user_del: Vec<String> = redis::cmd("CONFIG")
+            .arg("GET")
+            .arg("lazyfree-lazy-user-del")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(user_del, vec!["lazyfree-lazy-user-del", "yes"]);
+        let _: () = conn.sadd("big", &members).unwrap();
+        let _: () = conn.del("big").unwrap();
+        wait_for(&mut conn, 2);
+
+        // Overwriting a value is a server-side delete
+        let _: () = redis::cmd("CONFIG")
+            .arg("SET")
+            .arg("lazyfree-lazy-server-del")
+            .arg("yes")
+            .query(&mut conn)
+            .unwrap();
+        let _: () = conn.sadd("set", &members).unwrap();
+        let _: () = conn.set("set", "replaced").unwrap();
+        wait_for(&mut conn, 3);
+
+        // FLUSHALL ASYNC counts every key it hands off
+        for i in 0..10 {
+            let _: () = conn.set(format!("k{i}"), i).unwrap();
+        }
+        let size: i64 = redis::cmd("DBSIZE").query(&mut conn).unwrap();
+        let _: () = redis::cmd("FLUSHALL")
+            .arg("ASYNC")
+            .query(&mut conn)
+            .unwrap();
+        let after: i64 = redis::cmd("DBSIZE").query(&mut conn).unwrap();
+        assert_eq!(after, 0);
+        wait_for(&mut conn, 3 + size);
+        let _: () = redis::cmd("FLUSHDB").arg("SYNC").query(&mut conn).unwrap();
+        assert!(
+            redis::cmd("FLUSHDB")
+                .arg("LATER")
+                .query::<()>(&mut conn)
+                .is_err()
+        );
+    })
+    .await
+    .unwrap();
+}
+
+// =========== Compact encodings test ===========
+
+#[tokio::test]
+async fn test_compact_encodings() {
+    let port = 16511;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+        let encoding = |conn: &mut redis::Connection, key: &str| -> String {
+            redis::cmd("OBJECT")
+                .arg("ENCODING")
+                .arg(key)
+                .query(conn)
+                .unwrap()
+        };
+        let usage = |conn: &mut redis::Connection, key: &str| -> i64 {
+            redis::cmd("MEMORY")
+                .arg("USAGE")
+                .arg(key)
+                .query(conn)
+                .unwrap()
+        };
+
+        // Hashes stay a listpack until a value is too long
+        let fields: Vec<(String, String)> = (0..100)
+            .map(|i| (format!("f{i}"), format!("v{i}")))
+            .collect();
+        let _: () = conn.hset_multiple("small", &fields).unwrap();
+        let _: () = conn.hset_multiple("big", &fields).unwrap();
+        assert_eq!(encoding(&mut conn, "small"), "listpack");
+        let _: () = conn.hset("big", "long", "x".repeat(65)).unwrap();
+        let _: () = conn.hdel("big", "long").unwrap();
+        assert_eq!(encoding(&mut conn, "big"), "hashtable");
+        let value: String = conn.hget("small", "f42").unwrap();
+        assert_eq!(value, "v42");
+        // The same 100 fields take several times less memory packed
+        assert!(usage(&mut conn, "big") > 3 * usage(&mut conn, "small"));
+
+        // Sets go from intset to listpack to hashtable
+        let _: () = conn.sadd("set", &[1, 2, 3]).unwrap();
+        assert_eq!(encoding(&mut conn, "set"), "intset");
+        let _: () = conn.sadd("set", "a").unwrap();
+        assert_eq!(encoding(&mut conn, "set"), "listpack");
+        let is_member: bool = conn.sismember("set", 2).unwrap();
+        assert!(is_member);
+        let more: Vec<i64> = (10..200).collect();
+        let _: () = conn.sadd("set", &more).unwrap();
+        assert_eq!(encoding(&mut conn, "set"), "hashtable");
+        let card: i64 = conn.scard("set").unwrap();
+        assert_eq!(card, 194);
+
+        // Sorted sets go from listpack to skiplist
+        for i in 0..128 {
+            let _: () = conn.zadd("zset", format!("m{i}"), -i).unwrap();
+        }
+        assert_eq!(encoding(&mut conn, "zset"), "listpack");
+        let first: Vec<(String, f64)> = conn.zrange_withscores("zset", 0, 0).unwrap();
+        assert_eq!(first, vec![("m127".to_string(), -127.0)]);
+        let _: () = conn.zadd("zset", "m128", 1).unwrap();
+        assert_eq!(encoding(&mut conn, "zset"), "skiplist");
+        let rank: i64 = conn.zrank("zset", "m0").unwrap();
+        assert_eq!(rank, 127);
+
+        let info: String = redis::cmd("DEBUG")
+            .arg("OBJECT")
+            .arg("small")
+            .query(&mut conn)
+            .unwrap();
+        assert!(info.contains("encoding:listpack"));
+    })
+    .await
+    .unwrap();
+}
+
+// =========== Quicklist test ===========
+
+#[tokio::test]
+async fn test_quicklist_compression() {
+    let port = 16512;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+        let items: Vec<String> = (0..20000).map(|i| format!("job:{i}")).collect();
+        let usage = |conn: &mut redis::Connection, key: &str| -> i64 {
+            redis::cmd("MEMORY")
+                .arg("USAGE")
+                .arg(key)
+                .query(conn)
+                .unwrap()
+        };
+
+        let _: () = conn.rpush("plain", &items).unwrap();
+        let _: () = redis::cmd("CONFIG")
+            .arg("SET")
+            .arg("list-compress-depth")
+            .arg("1")
+            .query(&mut conn)
+            .unwrap();
+        let _: () = conn.rpush("queue", &items).unwrap();
+        let encoding: String = redis::cmd("OBJECT")
+            .arg("ENCODING")
+            .arg("queue")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(encoding, "quicklist");
+        // Interior nodes are compressed
+        assert!(usage(&mut conn, "queue") < usage(&mut conn, "plain") * 3 / 4);
+
+        // Reads and edits reach into compressed nodes
+        let item: String = conn.lindex("queue", 10000).unwrap();
+        assert_eq!(item, "job:10000");
+        let range: Vec<String> = conn.lrange("queue", 12345, 12347).unwrap();
+        assert_eq!(range, ["job:12345", "job:12346", "job:12347"]);
+        let len: i64 = conn.linsert_before("queue", "job:15000", "urgent").unwrap();
+        assert_eq!(len, 20001);
+        let item: String = conn.lindex("queue", 15000).unwrap();
+        assert_eq!(item, "urgent");
+        let removed: i64 = conn.lrem("queue", 0, "job:5000").unwrap();
+        assert_eq!(removed, 1);
+        let _: () = conn.lset("queue", -2, "changed").unwrap();
+        let tail: Vec<String> = conn.lrange("queue", -3, -1).unwrap();
+        assert_eq!(tail, ["job:19997", "changed", "job:19999"]);
+        let first: String = conn.lpop("queue", None).unwrap();
+        assert_eq!(first, "job:0");
+        let _: () = conn.ltrim("queue", 9990, 10010).unwrap();
+        let len: i64 = conn.llen("queue").unwrap();
+        assert_eq!(len, 21);
+        let head: String = conn.lindex("queue", 0).unwrap();
+        assert_eq!(head, "job:9992");
+
+        let _: () = redis::cmd("CONFIG")
+            .arg("SET")
+            .arg("list-compress-depth")
+            .arg("0")
+            .query(&mut conn)
+            .unwrap();
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_sorted_set_ranks() {
+    let port = 16513;
+    let _server = start_server(port);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_client(port);
+        // Enough members for the skiplist encoding, two per score
+        let members: Vec<(i64, String)> = (0..2000).map(|i| (i / 2, format!("p{i:04}"))).collect();
+        let _: () = conn.zadd_multiple("board", &members).unwrap();
+        let encoding: String = redis::cmd("OBJECT")
+            .arg("ENCODING")
+            .arg("board")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(encoding, "skiplist");
+
+        let rank: i64 = conn.zrank("board", "p1234").unwrap();
+        assert_eq!(rank, 1234);
+        let rank: i64 = conn.zrevrank("board", "p1234").unwrap();
+        assert_eq!(rank, 765);
+        let page: Vec<String> = conn.zrange("board", 1000, 1002).unwrap();
+        assert_eq!(page, ["p1000", "p1001", "p1002"]);
+        let page: Vec<String> = conn.zrevrange("board", 0, 1).unwrap();
+        assert_eq!(page, ["p1999", "p1998"]);
+        let page: Vec<String> = conn.zrangebyscore("board", 700, 701).unwrap();
+        assert_eq!(page, ["p1400", "p1401", "p1402", "p1403"]);
+        let count: i64 = conn.zcount("board", "(10", 20).unwrap();
+        assert_eq!(count, 20);
+
+        // Moving a member changes the ranks behind it
+        let _: () = conn.zadd("board", "p1999", -1).unwrap();
+        let rank: i64 = conn.zrank("board", "p1999").unwrap();
+        assert_eq!(rank, 0);
+        let rank: i64 = conn.zrank("board", "p1234").unwrap();
+        assert_eq!(rank, 1235);
+        let removed: i64 = conn.zremrangebyrank("board", 0, 9).unwrap();
+        assert_eq!(removed, 10);
+        let first: Vec<String> = conn.zrange("board", 0, 0).unwrap();
+        assert_eq!(first, ["p0009"]);
+        let len: i64 = conn.zcard("board").unwrap();
+        assert_eq!(len, 1990);
+    })
+    .await
+    .unwrap();
+}